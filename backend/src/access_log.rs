@@ -0,0 +1,267 @@
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use engine::{DetectedProtocol, FlowKey};
+
+const ACCESS_LOG_QUEUE: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CloseReason {
+    Eof,
+    Timeout,
+    Error,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AccessLogEntry {
+    pub timestamp_ms: u64,
+    pub client: SocketAddr,
+    pub host: Option<String>,
+    pub protocol: &'static str,
+    pub bytes_up: u64,
+    pub bytes_down: u64,
+    pub duration_ms: u64,
+    pub bypass_applied: bool,
+    pub close_reason: CloseReason,
+}
+
+#[derive(Debug)]
+pub(crate) struct ConnectionRecord {
+    started: Instant,
+    pub client: SocketAddr,
+    pub host: Option<String>,
+    pub protocol: Option<DetectedProtocol>,
+    pub bytes_up: u64,
+    pub bytes_down: u64,
+    pub bypass_applied: bool,
+    pub close_reason: CloseReason,
+    pub flow: Option<FlowKey>,
+}
+
+impl ConnectionRecord {
+    pub fn new(client: SocketAddr) -> Self {
+        Self {
+            started: Instant::now(),
+            client,
+            host: None,
+            protocol: None,
+            bytes_up: 0,
+            bytes_down: 0,
+            bypass_applied: false,
+            close_reason: CloseReason::Eof,
+            flow: None,
+        }
+    }
+    
+    pub fn finish(self, result: &io::Result<()>) -> AccessLogEntry {
+        let close_reason = match result {
+            Ok(()) => self.close_reason,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => CloseReason::Timeout,
+            Err(_) => CloseReason::Error,
+        };
+        let protocol = match self.protocol {
+            Some(DetectedProtocol::TlsClientHello) => "tls",
+            Some(DetectedProtocol::HttpRequest) => "http",
+            Some(DetectedProtocol::Quic) => "quic",
+            Some(DetectedProtocol::Unknown) | None => "unknown",
+        };
+        
+        AccessLogEntry {
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            client: self.client,
+            host: self.host,
+            protocol,
+            bytes_up: self.bytes_up,
+            bytes_down: self.bytes_down,
+            duration_ms: self.started.elapsed().as_millis() as u64,
+            bypass_applied: self.bypass_applied,
+            close_reason,
+        }
+    }
+}
+
+pub struct AccessLog {
+    tx: mpsc::Sender<AccessLogEntry>,
+    dropped: AtomicU64,
+}
+
+impl AccessLog {
+    pub async fn open(path: PathBuf, max_bytes: u64) -> io::Result<Arc<Self>> {
+        let file = open_append(&path).await?;
+        let size = file.metadata().await?.len();
+        let (tx, rx) = mpsc::channel(ACCESS_LOG_QUEUE);
+        
+        tokio::spawn(write_entries(rx, path, file, size, max_bytes));
+        
+        Ok(Arc::new(Self {
+            tx,
+            dropped: AtomicU64::new(0),
+        }))
+    }
+    
+    pub fn record(&self, entry: AccessLogEntry) {
+        if self.tx.try_send(entry).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+async fn open_append(path: &Path) -> io::Result<File> {
+    tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+}
+
+fn rotated_path(path: &Path) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(".1");
+    PathBuf::from(rotated)
+}
+
+async fn rotate(path: &Path) -> io::Result<File> {
+    tokio::fs::rename(path, rotated_path(path)).await?;
+    open_append(path).await
+}
+
+async fn write_entries(
+    mut rx: mpsc::Receiver<AccessLogEntry>,
+    path: PathBuf,
+    file: File,
+    mut size: u64,
+    max_bytes: u64,
+) {
+    let mut writer = BufWriter::new(file);
+    
+    while let Some(entry) = rx.recv().await {
+        let mut line = match serde_json::to_vec(&entry) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("Failed to serialize access log entry: {}", e);
+                continue;
+            }
+        };
+        line.push(b'\n');
+        
+        if max_bytes > 0 && size > 0 && size + line.len() as u64 > max_bytes {
+            let _ = writer.flush().await;
+            match rotate(&path).await {
+                Ok(file) => {
+                    writer = BufWriter::new(file);
+                    size = 0;
+                }
+                Err(e) => warn!("Failed to rotate access log {}: {}", path.display(), e),
+            }
+        }
+        
+        if let Err(e) = writer.write_all(&line).await {
+            warn!("Failed to write access log {}: {}", path.display(), e);
+            continue;
+        }
+        size += line.len() as u64;
+        
+        if rx.is_empty() {
+            let _ = writer.flush().await;
+        }
+    }
+    
+    let _ = writer.flush().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+    
+    fn entry(host: &str) -> AccessLogEntry {
+        let mut record = ConnectionRecord::new("127.0.0.1:5000".parse().unwrap());
+        record.host = Some(host.to_string());
+        record.protocol = Some(DetectedProtocol::TlsClientHello);
+        record.bytes_up = 517;
+        record.bytes_down = 4096;
+        record.bypass_applied = true;
+        record.finish(&Ok(()))
+    }
+    
+    #[test]
+    fn test_close_reason_from_result() {
+        let record = ConnectionRecord::new("127.0.0.1:5000".parse().unwrap());
+        let timed_out = record.finish(&Err(io::Error::new(io::ErrorKind::TimedOut, "idle")));
+        assert_eq!(timed_out.close_reason, CloseReason::Timeout);
+        assert_eq!(timed_out.protocol, "unknown");
+        
+        let record = ConnectionRecord::new("127.0.0.1:5000".parse().unwrap());
+        let failed = record.finish(&Err(io::Error::new(io::ErrorKind::ConnectionReset, "reset")));
+        assert_eq!(failed.close_reason, CloseReason::Error);
+    }
+    
+    #[tokio::test]
+    async fn test_access_log_writes_jsonl() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("access.jsonl");
+        
+        let log = AccessLog::open(path.clone(), 0).await.unwrap();
+        log.record(entry("discord.com"));
+        log.record(entry("example.com"));
+        drop(log);
+        
+        let mut content = String::new();
+        for _ in 0..100 {
+            content = std::fs::read_to_string(&path).unwrap();
+            if content.lines().count() == 2 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        
+        let lines: Vec<serde_json::Value> = content.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["host"], "discord.com");
+        assert_eq!(lines[0]["protocol"], "tls");
+        assert_eq!(lines[0]["bytes_up"], 517);
+        assert_eq!(lines[0]["close_reason"], "eof");
+        assert_eq!(lines[1]["client"], "127.0.0.1:5000");
+    }
+    
+    #[tokio::test]
+    async fn test_access_log_rotates() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("access.jsonl");
+        
+        let line_len = serde_json::to_vec(&entry("discord.com")).unwrap().len() as u64 + 1;
+        let log = AccessLog::open(path.clone(), line_len * 2).await.unwrap();
+        for _ in 0..3 {
+            log.record(entry("discord.com"));
+        }
+        drop(log);
+        
+        let rotated = rotated_path(&path);
+        for _ in 0..100 {
+            if rotated.exists() && std::fs::read_to_string(&path).map(|c| c.lines().count() == 1).unwrap_or(false) {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(std::fs::read_to_string(&rotated).unwrap().lines().count(), 2);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use parking_lot::RwLock;
+
+use engine::BypassConfig;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StrategyState {
+    pub level: usize,
+    pub failures: u32,
+    pub confirmed: bool,
+}
+
+#[derive(Debug)]
+pub struct StrategyTable {
+    ladder: Vec<(String, BypassConfig)>,
+    hosts: RwLock<HashMap<String, StrategyState>>,
+}
+
+impl Default for StrategyTable {
+    fn default() -> Self {
+        Self::new(default_ladder())
+    }
+}
+
+impl StrategyTable {
+    pub fn new(ladder: Vec<(String, BypassConfig)>) -> Self {
+        assert!(!ladder.is_empty(), "strategy ladder must not be empty");
+        Self {
+            ladder,
+            hosts: RwLock::new(HashMap::new()),
+        }
+    }
+    
+    pub fn select(&self, host: &str) -> (usize, BypassConfig) {
+        let level = self.hosts.read()
+            .get(&host.to_ascii_lowercase())
+            .map(|state| state.level)
+            .unwrap_or(0);
+        (level, self.ladder[level].1.clone())
+    }
+    
+    pub fn strategy_name(&self, level: usize) -> &str {
+        self.ladder.get(level).map(|(name, _)| name.as_str()).unwrap_or("unknown")
+    }
+    
+    pub fn record_success(&self, host: &str, level: usize) {
+        let mut hosts = self.hosts.write();
+        let state = hosts.entry(host.to_ascii_lowercase()).or_default();
+        if state.level == level {
+            state.confirmed = true;
+        }
+    }
+    
+    pub fn record_failure(&self, host: &str, level: usize) -> Option<usize> {
+        let mut hosts = self.hosts.write();
+        let state = hosts.entry(host.to_ascii_lowercase()).or_default();
+        
+        if state.level != level {
+            return None;
+        }
+        
+        state.failures += 1;
+        state.confirmed = false;
+        
+        if level + 1 < self.ladder.len() {
+            state.level = level + 1;
+            Some(state.level)
+        } else {
+            None
+        }
+    }
+    
+    pub fn probe<F>(&self, host: &str, mut attempt: F) -> Option<usize>
+    where
+        F: FnMut(&BypassConfig) -> bool,
+    {
+        loop {
+            let (level, config) = self.select(host);
+            if attempt(&config) {
+                self.record_success(host, level);
+                return Some(level);
+            }
+            self.record_failure(host, level)?;
+        }
+    }
+    
+    pub fn snapshot(&self) -> Vec<(String, StrategyState)> {
+        let mut entries: Vec<_> = self.hosts.read()
+            .iter()
+            .map(|(host, state)| (host.clone(), *state))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        entries
+    }
+}
+
+pub fn default_ladder() -> Vec<(String, BypassConfig)> {
+    vec![
+        ("turk_telekom".to_string(), BypassConfig::turk_telekom()),
+        ("vodafone_tr".to_string(), BypassConfig::vodafone_tr()),
+        ("superonline".to_string(), BypassConfig::superonline()),
+        ("aggressive".to_string(), BypassConfig::aggressive()),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_escalates_until_success() {
+        let table = StrategyTable::default();
+        
+        let mut attempts = 0;
+        let level = table.probe("discord.com", |_| {
+            attempts += 1;
+            attempts == 3
+        });
+        
+        assert_eq!(level, Some(2));
+        assert_eq!(table.strategy_name(2), "superonline");
+        
+        let (level, config) = table.select("Discord.com");
+        assert_eq!(level, 2);
+        assert_eq!(config.max_segment_size, BypassConfig::superonline().max_segment_size);
+        
+        let snapshot = table.snapshot();
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot[0].1, StrategyState { level: 2, failures: 2, confirmed: true });
+    }
+    
+    #[test]
+    fn test_exhausted_ladder() {
+        let table = StrategyTable::default();
+        
+        assert_eq!(table.probe("blocked.example", |_| false), None);
+        assert_eq!(table.select("blocked.example").0, 3);
+        assert_eq!(table.select("other.example").0, 0);
+    }
+    
+    #[test]
+    fn test_stale_failure_ignored() {
+        let table = StrategyTable::default();
+        
+        assert_eq!(table.record_failure("example.com", 0), Some(1));
+        assert_eq!(table.record_failure("example.com", 0), None);
+        assert_eq!(table.select("example.com").0, 1);
+    }
+}
@@ -0,0 +1,180 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use bytes::BytesMut;
+use crossbeam_queue::ArrayQueue;
+
+pub const DEFAULT_POOL_CAPACITY: usize = 128;
+
+/// Relay buffers shared between connections. A buffer is checked out for the
+/// lifetime of a relay direction and handed back, cleared, when its guard is
+/// dropped. An empty pool falls back to allocating.
+#[derive(Debug)]
+pub struct BufferPool {
+    buffers: ArrayQueue<BytesMut>,
+    buffer_size: AtomicUsize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl BufferPool {
+    pub fn new(buffer_size: usize, capacity: usize) -> Self {
+        Self {
+            buffers: ArrayQueue::new(capacity.max(1)),
+            buffer_size: AtomicUsize::new(buffer_size),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+    
+    /// Buffers of the old size still in the pool are discarded as they are checked out.
+    pub fn set_buffer_size(&self, buffer_size: usize) {
+        self.buffer_size.store(buffer_size, Ordering::Relaxed);
+    }
+    
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size.load(Ordering::Relaxed)
+    }
+    
+    /// Returns a zeroed buffer of `buffer_size` bytes.
+    pub fn get(&self) -> PooledBuffer<'_> {
+        let size = self.buffer_size();
+        let buf = loop {
+            match self.buffers.pop() {
+                Some(buf) if buf.capacity() >= size => {
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                    break buf;
+                }
+                Some(_) => continue,
+                None => {
+                    self.misses.fetch_add(1, Ordering::Relaxed);
+                    break BytesMut::with_capacity(size);
+                }
+            }
+        };
+        
+        let mut buf = PooledBuffer { buf, pool: self };
+        buf.resize(size, 0);
+        buf
+    }
+    
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+    
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+    
+    pub fn available(&self) -> usize {
+        self.buffers.len()
+    }
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self::new(64 * 1024, DEFAULT_POOL_CAPACITY)
+    }
+}
+
+pub struct PooledBuffer<'a> {
+    buf: BytesMut,
+    pool: &'a BufferPool,
+}
+
+impl Deref for PooledBuffer<'_> {
+    type Target = BytesMut;
+    
+    fn deref(&self) -> &BytesMut {
+        &self.buf
+    }
+}
+
+impl DerefMut for PooledBuffer<'_> {
+    fn deref_mut(&mut self) -> &mut BytesMut {
+        &mut self.buf
+    }
+}
+
+impl Drop for PooledBuffer<'_> {
+    fn drop(&mut self) {
+        let mut buf = std::mem::take(&mut self.buf);
+        // Buffers that were split off, or sized for an older setting, are
+        // too small to hand out again.
+        if buf.capacity() < self.pool.buffer_size() {
+            return;
+        }
+        buf.clear();
+        let _ = self.pool.buffers.push(buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    
+    #[test]
+    fn test_buffers_are_reused() {
+        let pool = BufferPool::new(1024, 2);
+        
+        let first = pool.get();
+        let ptr = first.as_ptr();
+        assert_eq!(first.len(), 1024);
+        drop(first);
+        assert_eq!(pool.available(), 1);
+        
+        let mut second = pool.get();
+        assert_eq!(second.as_ptr(), ptr);
+        assert!(second.iter().all(|&b| b == 0));
+        second[0] = 0xFF;
+        drop(second);
+        
+        assert!(pool.get().iter().all(|&b| b == 0));
+        assert_eq!(pool.hits(), 2);
+        assert_eq!(pool.misses(), 1);
+    }
+    
+    #[test]
+    fn test_pool_overflow_and_resize() {
+        let pool = BufferPool::new(512, 2);
+        let buffers: Vec<_> = (0..4).map(|_| pool.get()).collect();
+        drop(buffers);
+        assert_eq!(pool.available(), 2);
+        assert_eq!(pool.misses(), 4);
+        
+        pool.set_buffer_size(2048);
+        assert_eq!(pool.get().len(), 2048);
+        assert_eq!(pool.available(), 1);
+        assert_eq!(pool.hits(), 0);
+        
+        let mut split = pool.get();
+        let _ = split.split_to(100);
+        drop(split);
+        assert_eq!(pool.available(), 0);
+    }
+    
+    #[test]
+    fn test_concurrent_checkout() {
+        let pool = Arc::new(BufferPool::new(256, 8));
+        let handles: Vec<_> = (0..8u8)
+            .map(|id| {
+                let pool = pool.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        let mut buf = pool.get();
+                        assert!(buf.iter().all(|&b| b == 0));
+                        buf.fill(id);
+                        assert!(buf.iter().all(|&b| b == id));
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        
+        assert_eq!(pool.hits() + pool.misses(), 8000);
+        assert!(pool.hits() > 0);
+    }
+}
@@ -0,0 +1,229 @@
+use std::io;
+
+use tokio::net::TcpStream;
+
+// The fake occupies real sequence space, so the kernel will retransmit it once the
+// low-TTL copy dies in transit. It is handed to the socket by reference to our own
+// page (vmsplice + splice) and the page is overwritten with the real bytes right
+// after the send, so every retransmission carries the real data.
+//
+// Returns false, having sent nothing, when the fake cannot be sent this way; the
+// caller then sends `real` itself. Once part of the fake is queued, the rest of
+// `real` is written here instead, so an error means the connection is broken.
+#[cfg(target_os = "linux")]
+pub async fn send_fake(stream: &TcpStream, fake: &[u8], real: &[u8], ttl: u8) -> io::Result<bool> {
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use tokio::io::Interest;
+    
+    if fake.is_empty() || fake.len() != real.len() {
+        return Ok(false);
+    }
+    
+    let Ok(page) = MappedPage::new(fake.len()) else {
+        return Ok(false);
+    };
+    page.write(fake);
+    
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Ok(false);
+    }
+    let (pipe_read, pipe_write) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+    
+    let queued = {
+        let iov = libc::iovec {
+            iov_base: page.ptr,
+            iov_len: fake.len(),
+        };
+        unsafe { libc::vmsplice(pipe_write.as_raw_fd(), &iov, 1, 0) }
+    };
+    if queued < 0 || queued as usize != fake.len() {
+        return Ok(false);
+    }
+    
+    let Some(ttl_guard) = TtlGuard::set(stream, ttl) else {
+        return Ok(false);
+    };
+    
+    let socket = stream.as_raw_fd();
+    let mut sent = 0;
+    let mut failure = None;
+    while sent < fake.len() {
+        let result = stream.async_io(Interest::WRITABLE, || {
+            let n = unsafe {
+                libc::splice(
+                    pipe_read.as_raw_fd(),
+                    std::ptr::null_mut(),
+                    socket,
+                    std::ptr::null_mut(),
+                    fake.len() - sent,
+                    libc::SPLICE_F_NONBLOCK,
+                )
+            };
+            if n < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(n as usize)
+            }
+        }).await;
+        
+        match result {
+            Ok(0) => {
+                failure = Some(io::Error::new(io::ErrorKind::WriteZero, "splice wrote no data"));
+                break;
+            }
+            Ok(n) => sent += n,
+            Err(e) => {
+                failure = Some(e);
+                break;
+            }
+        }
+    }
+    
+    drop(ttl_guard);
+    page.write(real);
+    
+    match failure {
+        None => Ok(true),
+        Some(_) if sent == 0 => Ok(false),
+        Some(_) => {
+            write_all(stream, &real[sent..]).await?;
+            Ok(true)
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+async fn write_all(stream: &TcpStream, mut data: &[u8]) -> io::Result<()> {
+    while !data.is_empty() {
+        stream.writable().await?;
+        match stream.try_write(data) {
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero)),
+            Ok(n) => data = &data[n..],
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+// Lowers a socket's TTL, putting the original back when dropped.
+pub struct TtlGuard<'a> {
+    stream: &'a TcpStream,
+    original: u32,
+}
+
+impl<'a> TtlGuard<'a> {
+    pub fn set(stream: &'a TcpStream, ttl: u8) -> Option<Self> {
+        let original = stream.ttl().ok()?;
+        stream.set_ttl(ttl as u32).ok()?;
+        Some(Self { stream, original })
+    }
+}
+
+impl Drop for TtlGuard<'_> {
+    fn drop(&mut self) {
+        let _ = self.stream.set_ttl(self.original);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn send_fake(_stream: &TcpStream, _fake: &[u8], _real: &[u8], _ttl: u8) -> io::Result<bool> {
+    Ok(false)
+}
+
+#[cfg(target_os = "linux")]
+struct MappedPage {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+#[cfg(target_os = "linux")]
+unsafe impl Send for MappedPage {}
+
+#[cfg(target_os = "linux")]
+impl MappedPage {
+    fn new(len: usize) -> io::Result<Self> {
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { ptr, len })
+    }
+    
+    fn write(&self, data: &[u8]) {
+        let len = data.len().min(self.len);
+        unsafe { std::ptr::copy_nonoverlapping(data.as_ptr(), self.ptr as *mut u8, len) };
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for MappedPage {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
+
+// Loopback never drops the low-TTL copy and the receive queue may still reference
+// the page when it is overwritten, so either payload can show up here.
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    
+    #[tokio::test]
+    async fn test_send_fake_restores_ttl() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).await.unwrap();
+            received
+        });
+        
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let original_ttl = stream.ttl().unwrap();
+        
+        assert!(send_fake(&stream, b"fake", b"real", 3).await.unwrap());
+        assert_eq!(stream.ttl().unwrap(), original_ttl);
+        
+        stream.write_all(b" tail").await.unwrap();
+        stream.shutdown().await.unwrap();
+        
+        let received = server.await.unwrap();
+        assert!(received == b"fake tail" || received == b"real tail");
+    }
+    
+    #[tokio::test]
+    async fn test_ttl_guard_restores_on_drop() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let original_ttl = stream.ttl().unwrap();
+        
+        let guard = TtlGuard::set(&stream, 3).unwrap();
+        assert_eq!(stream.ttl().unwrap(), 3);
+        drop(guard);
+        assert_eq!(stream.ttl().unwrap(), original_ttl);
+    }
+    
+    #[tokio::test]
+    async fn test_send_fake_length_mismatch() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        
+        assert!(!send_fake(&stream, b"fake", b"longer", 3).await.unwrap());
+    }
+}
@@ -0,0 +1,309 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use tokio::net::UdpSocket;
+use tokio::task::JoinSet;
+use tracing::{debug, info, warn};
+
+use engine::DohResolver;
+
+use crate::transparent::ProxyStats;
+
+pub const DEFAULT_DNS_LISTEN: &str = "127.0.0.1:5353";
+
+const DNS_HEADER_LEN: usize = 12;
+const MAX_UDP_RESPONSE: usize = 512;
+const MAX_QUERY_SIZE: usize = 4096;
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+const RCODE_NOERROR: u8 = 0;
+const RCODE_FORMERR: u8 = 1;
+const RCODE_SERVFAIL: u8 = 2;
+const RCODE_NOTIMP: u8 = 4;
+const RCODE_REFUSED: u8 = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DnsQuery {
+    id: u16,
+    recursion_desired: bool,
+    name: String,
+    qtype: u16,
+    qclass: u16,
+    question: Vec<u8>,
+}
+
+pub struct DnsProxy {
+    socket: Arc<UdpSocket>,
+    dns: Arc<DohResolver>,
+    stats: Arc<ProxyStats>,
+}
+
+impl DnsProxy {
+    pub async fn bind(addr: SocketAddr, dns: Arc<DohResolver>, stats: Arc<ProxyStats>) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr).await?;
+        Ok(Self {
+            socket: Arc::new(socket),
+            dns,
+            stats,
+        })
+    }
+    
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+    
+    pub async fn run(self) -> io::Result<()> {
+        info!(addr = %self.local_addr()?, "DNS listener started");
+        
+        let mut queries = JoinSet::new();
+        let mut buf = vec![0u8; MAX_QUERY_SIZE];
+        
+        loop {
+            let (n, peer) = match self.socket.recv_from(&mut buf).await {
+                Ok(received) => received,
+                Err(e) => {
+                    debug!("DNS listener receive error: {}", e);
+                    continue;
+                }
+            };
+            while queries.try_join_next().is_some() {}
+            
+            let packet = buf[..n].to_vec();
+            let socket = self.socket.clone();
+            let dns = self.dns.clone();
+            let stats = self.stats.clone();
+            queries.spawn(async move {
+                let Some(response) = answer_packet(&packet, &dns, &stats).await else {
+                    return;
+                };
+                if let Err(e) = socket.send_to(&response, peer).await {
+                    warn!("Failed to send DNS response to {}: {}", peer, e);
+                }
+            });
+        }
+    }
+}
+
+async fn answer_packet(packet: &[u8], dns: &DohResolver, stats: &ProxyStats) -> Option<Vec<u8>> {
+    if packet.len() < DNS_HEADER_LEN || packet[2] & 0x80 != 0 {
+        return None;
+    }
+    
+    let query = match parse_query(packet) {
+        Ok(query) => query,
+        Err(rcode) => return Some(error_response(packet, rcode)),
+    };
+    stats.dns_queries.fetch_add(1, Ordering::Relaxed);
+    
+    if query.qclass != CLASS_IN {
+        return Some(build_response(&query, RCODE_REFUSED, &[], 0));
+    }
+    // Only addresses are served; any other type gets an empty answer
+    // (NODATA), which resolvers take as "no such record" rather than an error.
+    if query.qtype != TYPE_A && query.qtype != TYPE_AAAA {
+        return Some(build_response(&query, RCODE_NOERROR, &[], 0));
+    }
+    
+    match dns.resolve_with_ttl(&query.name).await {
+        Ok((ips, ttl)) => {
+            let answers: Vec<IpAddr> = ips.into_iter()
+                .filter(|ip| ip.is_ipv4() == (query.qtype == TYPE_A))
+                .collect();
+            Some(build_response(&query, RCODE_NOERROR, &answers, ttl))
+        }
+        Err(e) => {
+            debug!("DNS listener failed to resolve {}: {}", query.name, e);
+            Some(build_response(&query, RCODE_SERVFAIL, &[], 0))
+        }
+    }
+}
+
+fn parse_query(packet: &[u8]) -> Result<DnsQuery, u8> {
+    let id = u16::from_be_bytes([packet[0], packet[1]]);
+    let opcode = (packet[2] >> 3) & 0x0F;
+    if opcode != 0 {
+        return Err(RCODE_NOTIMP);
+    }
+    if u16::from_be_bytes([packet[4], packet[5]]) != 1 {
+        return Err(RCODE_FORMERR);
+    }
+    
+    let mut labels = Vec::new();
+    let mut pos = DNS_HEADER_LEN;
+    loop {
+        let len = *packet.get(pos).ok_or(RCODE_FORMERR)? as usize;
+        pos += 1;
+        if len == 0 {
+            break;
+        }
+        if len & 0xC0 != 0 {
+            return Err(RCODE_FORMERR);
+        }
+        let label = packet.get(pos..pos + len).ok_or(RCODE_FORMERR)?;
+        labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+        pos += len;
+    }
+    if labels.is_empty() {
+        return Err(RCODE_FORMERR);
+    }
+    
+    let fixed = packet.get(pos..pos + 4).ok_or(RCODE_FORMERR)?;
+    Ok(DnsQuery {
+        id,
+        recursion_desired: packet[2] & 0x01 != 0,
+        name: labels.join("."),
+        qtype: u16::from_be_bytes([fixed[0], fixed[1]]),
+        qclass: u16::from_be_bytes([fixed[2], fixed[3]]),
+        question: packet[DNS_HEADER_LEN..pos + 4].to_vec(),
+    })
+}
+
+fn response_header(id: u16, recursion_desired: bool, rcode: u8) -> Vec<u8> {
+    let mut header = Vec::with_capacity(MAX_UDP_RESPONSE);
+    header.extend_from_slice(&id.to_be_bytes());
+    header.push(0x80 | u8::from(recursion_desired));
+    header.push(0x80 | rcode);
+    header.extend_from_slice(&[0; 8]);
+    header
+}
+
+fn error_response(packet: &[u8], rcode: u8) -> Vec<u8> {
+    response_header(u16::from_be_bytes([packet[0], packet[1]]), packet[2] & 0x01 != 0, rcode)
+}
+
+fn build_response(query: &DnsQuery, rcode: u8, answers: &[IpAddr], ttl: u32) -> Vec<u8> {
+    let mut response = response_header(query.id, query.recursion_desired, rcode);
+    response[5] = 1;
+    response.extend_from_slice(&query.question);
+    
+    let mut count: u16 = 0;
+    for ip in answers {
+        let (record_type, rdata) = match ip {
+            IpAddr::V4(v4) => (TYPE_A, v4.octets().to_vec()),
+            IpAddr::V6(v6) => (TYPE_AAAA, v6.octets().to_vec()),
+        };
+        if response.len() + 12 + rdata.len() > MAX_UDP_RESPONSE {
+            response[2] |= 0x02;
+            break;
+        }
+        
+        response.extend_from_slice(&[0xC0, DNS_HEADER_LEN as u8]);
+        response.extend_from_slice(&record_type.to_be_bytes());
+        response.extend_from_slice(&CLASS_IN.to_be_bytes());
+        response.extend_from_slice(&ttl.to_be_bytes());
+        response.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        response.extend_from_slice(&rdata);
+        count += 1;
+    }
+    response[6..8].copy_from_slice(&count.to_be_bytes());
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    
+    fn query_packet(id: u16, name: &str, qtype: u16) -> Vec<u8> {
+        let mut packet = Vec::new();
+        packet.extend_from_slice(&id.to_be_bytes());
+        packet.extend_from_slice(&[0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        for label in name.split('.') {
+            packet.push(label.len() as u8);
+            packet.extend_from_slice(label.as_bytes());
+        }
+        packet.push(0);
+        packet.extend_from_slice(&qtype.to_be_bytes());
+        packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+        packet
+    }
+    
+    async fn exchange(client: &UdpSocket, server: SocketAddr, packet: &[u8]) -> Vec<u8> {
+        client.send_to(packet, server).await.unwrap();
+        let mut buf = [0u8; MAX_UDP_RESPONSE];
+        let (n, _) = tokio::time::timeout(Duration::from_secs(5), client.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        buf[..n].to_vec()
+    }
+    
+    #[test]
+    fn test_parse_query() {
+        let packet = query_packet(0xBEEF, "Discord.com", TYPE_AAAA);
+        let query = parse_query(&packet).unwrap();
+        assert_eq!(query.id, 0xBEEF);
+        assert!(query.recursion_desired);
+        assert_eq!(query.name, "discord.com");
+        assert_eq!(query.qtype, TYPE_AAAA);
+        assert_eq!(query.question, packet[DNS_HEADER_LEN..]);
+        
+        let mut multiple = packet.clone();
+        multiple[5] = 2;
+        assert_eq!(parse_query(&multiple), Err(RCODE_FORMERR));
+        assert_eq!(parse_query(&packet[..packet.len() - 2]), Err(RCODE_FORMERR));
+        
+        let mut status = packet.clone();
+        status[2] |= 2 << 3;
+        assert_eq!(parse_query(&status), Err(RCODE_NOTIMP));
+    }
+    
+    #[test]
+    fn test_build_response_truncates() {
+        let query = parse_query(&query_packet(1, "many.example", TYPE_A)).unwrap();
+        let answers: Vec<IpAddr> = (0..64).map(|i| IpAddr::from([10, 0, 0, i])).collect();
+        
+        let response = build_response(&query, RCODE_NOERROR, &answers, 60);
+        assert!(response.len() <= MAX_UDP_RESPONSE);
+        assert_eq!(response[2] & 0x02, 0x02);
+        let count = u16::from_be_bytes([response[6], response[7]]) as usize;
+        assert_eq!(response.len(), DNS_HEADER_LEN + query.question.len() + count * 16);
+    }
+    
+    #[tokio::test]
+    async fn test_dns_proxy_answers_from_resolver() {
+        let dns = Arc::new(DohResolver::with_providers(Vec::new()));
+        dns.add_override("pinned.example", vec!["203.0.113.9".parse().unwrap(), "2001:db8::9".parse().unwrap()]);
+        let stats = ProxyStats::new();
+        
+        let proxy = DnsProxy::bind("127.0.0.1:0".parse().unwrap(), dns, stats.clone()).await.unwrap();
+        let server = proxy.local_addr().unwrap();
+        let handle = tokio::spawn(proxy.run());
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        
+        let query = query_packet(0x1234, "pinned.example", TYPE_A);
+        let response = exchange(&client, server, &query).await;
+        assert_eq!(&response[..2], &[0x12, 0x34]);
+        assert_eq!(response[3] & 0x0F, RCODE_NOERROR);
+        assert_eq!(u16::from_be_bytes([response[6], response[7]]), 1);
+        let answer = &response[query.len()..];
+        assert_eq!(&answer[2..4], &TYPE_A.to_be_bytes());
+        assert!(u32::from_be_bytes([answer[6], answer[7], answer[8], answer[9]]) > 0);
+        assert_eq!(&answer[12..], &[203, 0, 113, 9]);
+        
+        let query = query_packet(0x1235, "pinned.example", TYPE_AAAA);
+        let response = exchange(&client, server, &query).await;
+        assert_eq!(u16::from_be_bytes([response[6], response[7]]), 1);
+        assert_eq!(response[response.len() - 16..], "2001:db8::9".parse::<std::net::Ipv6Addr>().unwrap().octets());
+        
+        let query = query_packet(0x1236, "unresolvable.invalid", TYPE_A);
+        let response = exchange(&client, server, &query).await;
+        assert_eq!(response[3] & 0x0F, RCODE_SERVFAIL);
+        assert_eq!(u16::from_be_bytes([response[6], response[7]]), 0);
+        
+        // HTTPS records are not served, but the name may still resolve.
+        let query = query_packet(0x1237, "pinned.example", 65);
+        let response = exchange(&client, server, &query).await;
+        assert_eq!(response[3] & 0x0F, RCODE_NOERROR);
+        assert_eq!(u16::from_be_bytes([response[6], response[7]]), 0);
+        assert_eq!(response.len(), query.len());
+        
+        assert_eq!(stats.dns_queries.load(Ordering::Relaxed), 4);
+        handle.abort();
+    }
+}
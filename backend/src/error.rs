@@ -49,3 +49,12 @@ pub enum BackendError {
     #[error("Permission denied: {0}")]
     PermissionDenied(String),
 }
+
+impl From<BackendError> for std::io::Error {
+    fn from(err: BackendError) -> Self {
+        match err {
+            BackendError::Io(e) => e,
+            other => std::io::Error::other(other),
+        }
+    }
+}
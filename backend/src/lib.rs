@@ -1,11 +1,23 @@
+pub mod access_log;
+pub mod adaptive;
+pub mod buffer_pool;
+pub mod desync;
+pub mod dns_proxy;
 pub mod error;
 pub mod proxy;
 pub mod traits;
 pub mod transparent;
 pub mod tun;
 
+#[cfg(test)]
+mod test_util;
+
+pub use access_log::{AccessLog, AccessLogEntry, CloseReason};
+pub use adaptive::{StrategyState, StrategyTable};
+pub use buffer_pool::{BufferPool, PooledBuffer};
+pub use dns_proxy::DnsProxy;
 pub use error::{BackendError, Result};
-pub use traits::{Backend, BackendConfig, BackendHandle, BackendSettings, Packet, PacketDirection, ProxySettings, TunSettings, ProxyType};
+pub use traits::{Backend, BackendConfig, BackendHandle, BackendSettings, ConnectionCounts, Packet, PacketDirection, ProxySettings, TunSettings, ProxyType};
 pub use tun::TunBackend;
-pub use proxy::ProxyBackend;
-pub use transparent::{BypassProxy, ProxyConfig, ProxyStats};
+pub use proxy::{ConnectionTracker, ProxyBackend, MAX_DRAIN_GRACE};
+pub use transparent::{BypassBackend, BypassProxy, ProxyConfig, ProxyStats, ProxyStatsSnapshot};
@@ -1,19 +1,30 @@
-use std::net::SocketAddr;
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::task::Poll;
+use std::time::Duration;
 
 use async_trait::async_trait;
 use bytes::BytesMut;
 use parking_lot::Mutex;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::mpsc;
-use tracing::{debug, error, info, warn};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::WriteHalf;
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+use tokio::sync::{mpsc, Notify};
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tracing::{debug, error, info, trace, warn};
 
-use engine::{FlowKey, Pipeline, Stats};
+use engine::{BypassConfig, BypassEngine, DohResolver, FlowKey, PacketMeta, Pipeline, Stats, StatsHistory};
+use engine::flow::{EmitKind, EmittedPacket};
 use engine::config::Protocol;
 
+use crate::buffer_pool::{BufferPool, DEFAULT_POOL_CAPACITY};
+use crate::desync;
 use crate::error::{BackendError, Result};
+use crate::transparent::read_client_hello;
 use crate::traits::{Backend, BackendConfig, BackendHandle, BackendSettings, ProxySettings, ProxyType};
 
 pub struct ProxyBackend {
@@ -21,7 +32,7 @@ pub struct ProxyBackend {
     shutdown_tx: Option<mpsc::Sender<()>>,    
     config: Option<ProxySettings>,    
     task_handle: Mutex<Option<tokio::task::JoinHandle<()>>>,    
-    active_connections: Arc<AtomicU64>,
+    connections: Arc<ConnectionTracker>,
 }
 
 impl ProxyBackend {
@@ -31,19 +42,28 @@ impl ProxyBackend {
             shutdown_tx: None,
             config: None,
             task_handle: Mutex::new(None),
-            active_connections: Arc::new(AtomicU64::new(0)),
+            connections: Arc::new(ConnectionTracker::default()),
         }
     }
 
+    pub fn listen_addr(&self) -> Option<SocketAddr> {
+        self.listen_addrs().first().copied()
+    }
+
+    pub fn listen_addrs(&self) -> Vec<SocketAddr> {
+        self.config.as_ref().map(|c| c.listen_addrs.clone()).unwrap_or_default()
+    }
+
+    pub fn active_connections(&self) -> u64 {
+        self.connections.active()
+    }
+
     async fn handle_socks5(
         mut client: TcpStream,
         client_addr: SocketAddr,
-        pipeline: Arc<Pipeline>,
-        stats: Arc<Stats>,
-        active_conns: Arc<AtomicU64>,
+        ctx: ConnectionContext,
+        _guard: ConnectionGuard,
     ) {
-        let _guard = ConnectionGuard::new(active_conns);
-        
         debug!(client = %client_addr, "New SOCKS5 connection");
         
         let mut buf = [0u8; 2];
@@ -54,8 +74,18 @@ impl ProxyBackend {
         let version = buf[0];
         let nmethods = buf[1] as usize;
         
+        if version == 0x04 && ctx.allow_socks4 {
+            Self::handle_socks4(client, client_addr, ctx, buf[1]).await;
+            return;
+        }
+        
+        let ConnectionContext { pipeline, stats, dns, bypass, idle_timeout, bind_addr, clamp_mss, fake_ttl, buffers, .. } = ctx;
+        
         if version != 0x05 {
-            warn!(version, "inv SOCKS version");
+            stats.record_invalid_packet();
+            if pipeline.log_limiter().allow() {
+                warn!(version, "inv SOCKS version");
+            }
             return;
         }
         
@@ -82,12 +112,12 @@ impl ProxyBackend {
         let atyp = request[3];
         
         if cmd != 0x01 {
-            let response = [0x05, 0x07, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
-            let _ = client.write_all(&response).await;
+            let _ = client.write_all(&socks5_reply(0x07, client.local_addr().ok())).await;
             return;
         }
         
-        let (dst_addr, dst_port) = match atyp {
+        let mut hostname = None;
+        let targets = match atyp {
             0x01 => {
                 let mut addr = [0u8; 4];
                 if client.read_exact(&mut addr).await.is_err() {
@@ -99,7 +129,7 @@ impl ProxyBackend {
                 }
                 let port = u16::from_be_bytes(port_buf);
                 let ip = std::net::Ipv4Addr::new(addr[0], addr[1], addr[2], addr[3]);
-                (std::net::IpAddr::V4(ip), port)
+                vec![SocketAddr::new(std::net::IpAddr::V4(ip), port)]
             }
             0x03 => {
                 let mut len = [0u8; 1];
@@ -118,22 +148,22 @@ impl ProxyBackend {
                 
                 let domain_str = match String::from_utf8(domain) {
                     Ok(s) => s,
-                    Err(_) => return,
+                    Err(_) => {
+                        stats.record_invalid_packet();
+                        return;
+                    }
                 };
                 
-                let resolved = match tokio::net::lookup_host(format!("{}:{}", domain_str, port)).await {
-                    Ok(mut addrs) => match addrs.next() {
-                        Some(addr) => addr,
-                        None => return,
-                    },
-                    Err(_) => {
-                        let response = [0x05, 0x04, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
-                        let _ = client.write_all(&response).await;
+                let resolved = match Self::resolve_domain(&dns, &stats, &domain_str, port).await {
+                    Some(addrs) => addrs,
+                    None => {
+                        let _ = client.write_all(&socks5_reply(0x04, client.local_addr().ok())).await;
                         return;
                     }
                 };
                 
-                (resolved.ip(), port)
+                hostname = Some(domain_str);
+                resolved
             }
             0x04 => {
                 let mut addr = [0u8; 16];
@@ -146,80 +176,379 @@ impl ProxyBackend {
                 }
                 let port = u16::from_be_bytes(port_buf);
                 let ip = std::net::Ipv6Addr::from(addr);
-                (std::net::IpAddr::V6(ip), port)
+                vec![SocketAddr::new(std::net::IpAddr::V6(ip), port)]
             }
             _ => {
-                let response = [0x05, 0x08, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
-                let _ = client.write_all(&response).await;
+                stats.record_invalid_packet();
+                let _ = client.write_all(&socks5_reply(0x08, client.local_addr().ok())).await;
+                return;
+            }
+        };
+        
+        debug!(dst = ?targets, "SOCKS5 CONNECT request");
+        
+        let (mut remote, dst) = match Self::connect_targets(&targets, bind_addr, clamp_mss.filter(|_| !bypass.is_paused()), &stats).await {
+            Ok(connected) => connected,
+            Err(e) => {
+                if pipeline.log_limiter().allow() {
+                    warn!(error = %e, dst = ?targets, "Failed to connect");
+                }
+                let _ = client.write_all(&socks5_reply(0x05, client.local_addr().ok())).await;
+                return;
+            }
+        };
+        
+        if client.write_all(&socks5_reply(0x00, remote.local_addr().ok())).await.is_err() {
+            return;
+        }
+        
+        let flow_key = FlowKey::new(
+            client_addr.ip(),
+            dst.ip(),
+            client_addr.port(),
+            dst.port(),
+            Protocol::Tcp,
+        );
+        
+        let _ = remote.set_nodelay(true);
+        if !Self::send_first_payload(&mut client, &mut remote, flow_key, &mut hostname, &stats, &bypass, Vec::new()).await {
+            return;
+        }
+        
+        Self::relay_streams(client, remote, flow_key, hostname, pipeline, idle_timeout, fake_ttl, &buffers).await;
+    }
+
+    async fn handle_socks4(mut client: TcpStream, client_addr: SocketAddr, ctx: ConnectionContext, cmd: u8) {
+        let ConnectionContext { pipeline, stats, dns, bypass, idle_timeout, bind_addr, clamp_mss, fake_ttl, buffers, .. } = ctx;
+        
+        debug!(client = %client_addr, "New SOCKS4 connection");
+        
+        let mut request = [0u8; 6];
+        if client.read_exact(&mut request).await.is_err() {
+            return;
+        }
+        let dst_port = u16::from_be_bytes([request[0], request[1]]);
+        let ip = std::net::Ipv4Addr::new(request[2], request[3], request[4], request[5]);
+        
+        if read_null_terminated(&mut client).await.is_none() {
+            return;
+        }
+        
+        if cmd != 0x01 {
+            let _ = client.write_all(&socks4_reply(SOCKS4_REJECTED, None)).await;
+            return;
+        }
+        
+        let mut hostname = None;
+        let octets = ip.octets();
+        let targets = if octets[..3] == [0, 0, 0] && octets[3] != 0 {
+            let domain = match read_null_terminated(&mut client).await.map(String::from_utf8) {
+                Some(Ok(domain)) => domain,
+                _ => return,
+            };
+            
+            match Self::resolve_domain(&dns, &stats, &domain, dst_port).await {
+                Some(addrs) => {
+                    hostname = Some(domain);
+                    addrs
+                }
+                None => {
+                    let _ = client.write_all(&socks4_reply(SOCKS4_REJECTED, None)).await;
+                    return;
+                }
+            }
+        } else {
+            vec![SocketAddr::new(std::net::IpAddr::V4(ip), dst_port)]
+        };
+        
+        debug!(dst = ?targets, "SOCKS4 CONNECT request");
+        
+        let (mut remote, dst) = match Self::connect_targets(&targets, bind_addr, clamp_mss.filter(|_| !bypass.is_paused()), &stats).await {
+            Ok(connected) => connected,
+            Err(e) => {
+                if pipeline.log_limiter().allow() {
+                    warn!(error = %e, dst = ?targets, "Failed to connect");
+                }
+                let _ = client.write_all(&socks4_reply(SOCKS4_REJECTED, None)).await;
+                return;
+            }
+        };
+        
+        if client.write_all(&socks4_reply(SOCKS4_GRANTED, remote.local_addr().ok())).await.is_err() {
+            return;
+        }
+        
+        let flow_key = FlowKey::new(
+            client_addr.ip(),
+            dst.ip(),
+            client_addr.port(),
+            dst.port(),
+            Protocol::Tcp,
+        );
+        
+        let _ = remote.set_nodelay(true);
+        if !Self::send_first_payload(&mut client, &mut remote, flow_key, &mut hostname, &stats, &bypass, Vec::new()).await {
+            return;
+        }
+        
+        Self::relay_streams(client, remote, flow_key, hostname, pipeline, idle_timeout, fake_ttl, &buffers).await;
+    }
+
+    async fn resolve_domain(dns: &DohResolver, stats: &Stats, domain: &str, port: u16) -> Option<Vec<SocketAddr>> {
+        let host_port = format!("{}:{}", domain, port);
+        match dns.resolve_host_port_all(&host_port).await {
+            Ok(addrs) => {
+                stats.record_socks_doh_resolved();
+                Some(addrs)
+            }
+            Err(e) => {
+                debug!(error = %e, domain = %domain, "DoH resolution failed, falling back to system resolver");
+                lookup_all(&host_port).await
+            }
+        }
+    }
+    
+    async fn connect_targets(
+        targets: &[SocketAddr],
+        bind_addr: Option<IpAddr>,
+        clamp_mss: Option<u16>,
+        stats: &Stats,
+    ) -> Result<(TcpStream, SocketAddr)> {
+        let (stream, addr, fallback) = connect_racing(targets, bind_addr, clamp_mss).await?;
+        if fallback {
+            stats.record_connect_fallback();
+        }
+        Ok((stream, addr))
+    }
+
+    async fn handle_http_connect(
+        mut client: TcpStream,
+        client_addr: SocketAddr,
+        ctx: ConnectionContext,
+        _guard: ConnectionGuard,
+    ) {
+        let ConnectionContext { pipeline, stats, dns, bypass, idle_timeout, bind_addr, clamp_mss, fake_ttl, buffers, .. } = ctx;
+        
+        debug!(client = %client_addr, "New HTTP CONNECT connection");
+        
+        let mut buf = Vec::with_capacity(1024);
+        let mut chunk = [0u8; 1024];
+        let header_end = loop {
+            if let Some(pos) = find_header_end(&buf) {
+                break pos;
+            }
+            if buf.len() >= MAX_CONNECT_HEADER_SIZE {
+                stats.record_invalid_packet();
+                let _ = client.write_all(b"HTTP/1.1 431 Request Header Fields Too Large\r\n\r\n").await;
+                return;
+            }
+            match client.read(&mut chunk).await {
+                Ok(0) | Err(_) => return,
+                Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            }
+        };
+        
+        let request = String::from_utf8_lossy(&buf[..header_end]).to_string();
+        let pipelined = buf[header_end..].to_vec();
+        
+        let mut parts = request.lines().next().unwrap_or("").split_whitespace();
+        let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+        
+        if method != "CONNECT" {
+            let _ = client.write_all(b"HTTP/1.1 405 Method Not Allowed\r\nAllow: CONNECT\r\n\r\n").await;
+            return;
+        }
+        
+        let resolved = match dns.resolve_host_port_all(target).await {
+            Ok(addrs) => Some(addrs),
+            Err(_) => lookup_all(target).await,
+        };
+        
+        let targets = match resolved {
+            Some(addrs) => addrs,
+            None => {
+                if pipeline.log_limiter().allow() {
+                    warn!(target, "Failed to resolve CONNECT target");
+                }
+                let _ = client.write_all(b"HTTP/1.1 502 Bad Gateway\r\n\r\n").await;
                 return;
             }
         };
         
-        debug!(dst = %dst_addr, port = dst_port, "SOCKS5 CONNECT request");
+        debug!(dst = ?targets, "HTTP CONNECT request");
         
-        let remote = match TcpStream::connect((dst_addr, dst_port)).await {
-            Ok(stream) => stream,
+        let (mut remote, dst) = match Self::connect_targets(&targets, bind_addr, clamp_mss.filter(|_| !bypass.is_paused()), &stats).await {
+            Ok(connected) => connected,
             Err(e) => {
-                warn!(error = %e, dst = %dst_addr, port = dst_port, "Failed to connect");
-                let response = [0x05, 0x05, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
-                let _ = client.write_all(&response).await;
+                if pipeline.log_limiter().allow() {
+                    warn!(error = %e, dst = ?targets, "Failed to connect");
+                }
+                let _ = client.write_all(b"HTTP/1.1 502 Bad Gateway\r\n\r\n").await;
                 return;
             }
         };
         
-        let response = [0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
-        if client.write_all(&response).await.is_err() {
+        if client.write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n").await.is_err() {
             return;
         }
         
         let flow_key = FlowKey::new(
             client_addr.ip(),
-            dst_addr,
+            dst.ip(),
             client_addr.port(),
-            dst_port,
+            dst.port(),
             Protocol::Tcp,
         );
         
-        Self::relay_streams(client, remote, flow_key, pipeline, stats).await;
+        let mut hostname = None;
+        let _ = remote.set_nodelay(true);
+        if !Self::send_first_payload(&mut client, &mut remote, flow_key, &mut hostname, &stats, &bypass, pipelined).await {
+            return;
+        }
+        
+        Self::relay_streams(client, remote, flow_key, hostname, pipeline, idle_timeout, fake_ttl, &buffers).await;
+    }
+
+    async fn reject_connection(mut client: TcpStream, proxy_type: ProxyType) {
+        match proxy_type {
+            ProxyType::Socks5 => {
+                let mut greeting = [0u8; 2];
+                if client.read_exact(&mut greeting).await.is_err() || greeting[0] != 0x05 {
+                    return;
+                }
+                let mut methods = vec![0u8; greeting[1] as usize];
+                if client.read_exact(&mut methods).await.is_err() {
+                    return;
+                }
+                if !methods.contains(&0x00) {
+                    let _ = client.write_all(&[0x05, 0xFF]).await;
+                    return;
+                }
+                if client.write_all(&[0x05, 0x00]).await.is_err() {
+                    return;
+                }
+                
+                let mut request = [0u8; 262];
+                if client.read(&mut request).await.is_err() {
+                    return;
+                }
+                let _ = client.write_all(&socks5_reply(0x01, client.local_addr().ok())).await;
+            }
+            ProxyType::HttpConnect => {
+                let _ = client.write_all(b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n").await;
+            }
+        }
+        let _ = client.shutdown().await;
+    }
+
+    async fn send_first_payload(
+        client: &mut TcpStream,
+        remote: &mut TcpStream,
+        flow_key: FlowKey,
+        hostname: &mut Option<String>,
+        stats: &Stats,
+        bypass: &BypassEngine,
+        mut initial: Vec<u8>,
+    ) -> bool {
+        if initial.is_empty() {
+            if tokio::time::timeout(FIRST_PAYLOAD_TIMEOUT, client.readable()).await.is_err() {
+                return true;
+            }
+            initial = match read_client_hello(client, 4096).await {
+                Ok(data) if !data.is_empty() => data,
+                _ => return false,
+            };
+        }
+        
+        let result = bypass.process_outgoing(&initial);
+        
+        debug!(
+            flow = ?flow_key,
+            protocol = ?result.protocol,
+            host = result.hostname.as_deref().unwrap_or("-"),
+            fragments = result.fragments.len(),
+            "First payload"
+        );
+        
+        if hostname.is_none() {
+            *hostname = result.hostname.clone();
+        }
+        
+        stats.record_packet_in(initial.len());
+        if result.modified {
+            stats.record_transform();
+            stats.record_fragments(result.fragments.len() as u32);
+        }
+        
+        for (i, fragment) in result.fragments.iter().enumerate() {
+            if remote.write_all(fragment).await.is_err() {
+                return false;
+            }
+            stats.record_packet_out(fragment.len());
+            
+            if i + 1 < result.fragments.len() {
+                if let Some(delay) = result.inter_fragment_delay {
+                    tokio::time::sleep(delay).await;
+                }
+            }
+        }
+        if result.fragments.len() > 1 {
+            stats.record_fragment_overhead_writes(result.fragments.len() as u64 - 1);
+        }
+        
+        true
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn relay_streams(
         mut client: TcpStream,
         mut remote: TcpStream,
         flow_key: FlowKey,
+        hostname: Option<String>,
         pipeline: Arc<Pipeline>,
-        stats: Arc<Stats>,
+        idle_timeout: Duration,
+        fake_ttl: u8,
+        buffers: &BufferPool,
     ) {
         let (mut client_read, mut client_write) = client.split();
         let (mut remote_read, mut remote_write) = remote.split();
         
-        let _flow_key_rev = flow_key.reverse();
-        let _pipeline_clone = pipeline.clone();
-        let stats_clone = stats.clone();
+        let flow_key_rev = flow_key.reverse();
+        let pipeline_clone = pipeline.clone();
+        let hostname_rev = hostname.clone();
+        let last_activity = Mutex::new(Instant::now());
+        let last_activity = &last_activity;
         
         let outbound = async move {
-            let mut buf = BytesMut::with_capacity(4096);
-            buf.resize(4096, 0);
+            let mut buf = buffers.get();
             
             loop {
-                let n = match client_read.read(&mut buf).await {
-                    Ok(0) => break,
-                    Ok(n) => n,
-                    Err(_) => break,
+                let n = match read_until_idle(&mut client_read, &mut buf, last_activity, idle_timeout).await {
+                    Ok(n) if n > 0 => n,
+                    _ => break,
                 };
                 
                 let data = BytesMut::from(&buf[..n]);
                 
-                match pipeline.process(flow_key, data) {
+                let meta = PacketMeta::outbound().with_hostname(hostname.clone()).in_stream();
+                match pipeline.process_with_meta(flow_key, data, meta) {
+                    Ok(output) if output.dropped => {
+                        trace!(flow = ?flow_key, bytes = n, "Pipeline dropped client data");
+                    }
                     Ok(output) => {
-                        for packet in output.all_packets() {
-                            if remote_write.write_all(&packet).await.is_err() {
-                                return;
-                            }
+                        let packets = output.emitted();
+                        let writes = packets.iter().filter(|p| p.kind == EmitKind::Real).count();
+                        if write_emitted(&mut remote_write, packets, fake_ttl).await.is_err() {
+                            return;
+                        }
+                        if writes > 1 {
+                            pipeline.stats().record_fragment_overhead_writes(writes as u64 - 1);
                         }
                     }
                     Err(e) => {
-                        warn!(error = %e, "Pipeline processing error");
+                        if pipeline.log_limiter().allow() {
+                            warn!(error = %e, "Pipeline processing error");
+                        }
                         break;
                     }
                 }
@@ -227,22 +556,38 @@ impl ProxyBackend {
         };
         
         let inbound = async move {
-            let mut buf = BytesMut::with_capacity(4096);
-            buf.resize(4096, 0);
+            let mut buf = buffers.get();
             
             loop {
-                let n = match remote_read.read(&mut buf).await {
-                    Ok(0) => break,
-                    Ok(n) => n,
-                    Err(_) => break,
+                let n = match read_until_idle(&mut remote_read, &mut buf, last_activity, idle_timeout).await {
+                    Ok(n) if n > 0 => n,
+                    _ => break,
                 };
                 
-                if client_write.write_all(&buf[..n]).await.is_err() {
-                    break;
-                }
+                let data = BytesMut::from(&buf[..n]);
                 
-                stats_clone.record_packet_in(n);
-                stats_clone.record_packet_out(n);
+                let meta = PacketMeta::inbound().with_hostname(hostname_rev.clone()).in_stream();
+                match pipeline_clone.process_with_meta(flow_key_rev, data, meta) {
+                    Ok(output) if output.dropped => {
+                        trace!(flow = ?flow_key_rev, bytes = n, "Pipeline dropped server data");
+                    }
+                    Ok(output) => {
+                        if let Some(delay) = output.delay {
+                            tokio::time::sleep(delay).await;
+                        }
+                        for packet in output.all_packets() {
+                            if client_write.write_all(&packet).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        if pipeline_clone.log_limiter().allow() {
+                            warn!(error = %e, "Pipeline processing error");
+                        }
+                        break;
+                    }
+                }
             }
         };
         
@@ -255,105 +600,553 @@ impl ProxyBackend {
     }
 }
 
-struct ConnectionGuard {
-    counter: Arc<AtomicU64>,
-}
+const MAX_CONNECT_HEADER_SIZE: usize = 8192;
+const RELAY_BUFFER_SIZE: usize = 4096;
+const FIRST_PAYLOAD_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(500);
+const REJECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+static FAKE_UNSUPPORTED_LOGGED: AtomicBool = AtomicBool::new(false);
+const CONNECTION_ATTEMPT_DELAY: std::time::Duration = std::time::Duration::from_millis(250);
+const MAX_SOCKS4_FIELD_LEN: usize = 255;
+const SOCKS4_GRANTED: u8 = 0x5A;
+const SOCKS4_REJECTED: u8 = 0x5B;
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Longest grace [`ConnectionTracker::drain`] waits for open connections.
+pub const MAX_DRAIN_GRACE: Duration = Duration::from_secs(600);
 
-impl ConnectionGuard {
-    fn new(counter: Arc<AtomicU64>) -> Self {
-        counter.fetch_add(1, Ordering::Relaxed);
-        Self { counter }
+/// A fake is sent in place of the start of the next real packet, whose bytes
+/// replace it on retransmission; fakes that cannot be sent that way are dropped.
+async fn write_emitted(
+    remote: &mut WriteHalf<'_>,
+    packets: Vec<EmittedPacket>,
+    fake_ttl: u8,
+) -> io::Result<()> {
+    let mut fakes: Vec<EmittedPacket> = Vec::new();
+    for packet in packets {
+        if let Some(delay) = packet.delay_before {
+            tokio::time::sleep(delay).await;
+        }
+        if packet.kind == EmitKind::Fake {
+            fakes.push(packet);
+            continue;
+        }
+        
+        let data = &packet.data;
+        let mut offset = 0;
+        for fake in fakes.drain(..) {
+            let len = fake.data.len().min(data.len() - offset);
+            if len == 0 {
+                break;
+            }
+            let ttl = fake.ttl_override.unwrap_or(fake_ttl);
+            if desync::send_fake(remote.as_ref(), &fake.data[..len], &data[offset..offset + len], ttl).await? {
+                offset += len;
+            } else if !FAKE_UNSUPPORTED_LOGGED.swap(true, Ordering::Relaxed) {
+                warn!("Cannot send fake packets with a custom TTL on this socket, skipping them");
+            }
+        }
+        
+        match packet.ttl_override {
+            Some(ttl) => write_with_ttl(remote, &data[offset..], ttl).await?,
+            None => remote.write_all(&data[offset..]).await?,
+        }
     }
+    Ok(())
 }
 
-impl Drop for ConnectionGuard {
-    fn drop(&mut self) {
-        self.counter.fetch_sub(1, Ordering::Relaxed);
+async fn write_with_ttl(remote: &WriteHalf<'_>, data: &[u8], ttl: u8) -> io::Result<()> {
+    // Written through the shared socket so the guard can hold it; the TTL
+    // comes back however the write ends, error and cancellation included.
+    let socket = remote.as_ref();
+    let _ttl = desync::TtlGuard::set(socket, ttl);
+    let mut written = 0;
+    while written < data.len() {
+        socket.writable().await?;
+        match socket.try_write(&data[written..]) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => written += n,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(e) => return Err(e),
+        }
     }
+    Ok(())
 }
 
-impl Default for ProxyBackend {
-    fn default() -> Self {
-        Self::new()
-    }
+#[derive(Clone)]
+struct ConnectionContext {
+    pipeline: Arc<Pipeline>,
+    stats: Arc<Stats>,
+    dns: Arc<DohResolver>,
+    bypass: Arc<BypassEngine>,
+    idle_timeout: Duration,
+    allow_socks4: bool,
+    bind_addr: Option<IpAddr>,
+    clamp_mss: Option<u16>,
+    fake_ttl: u8,
+    buffers: Arc<BufferPool>,
 }
 
-#[async_trait]
-impl Backend for ProxyBackend {
-    fn name(&self) -> &'static str {
-        "proxy"
+pub(crate) async fn bind_listeners(addrs: &[SocketAddr]) -> io::Result<Vec<TcpListener>> {
+    if addrs.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "No listen address configured"));
+    }
+    
+    let mut listeners = Vec::with_capacity(addrs.len());
+    for addr in addrs {
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", addr, e)))?;
+        listeners.push(listener);
     }
+    Ok(listeners)
+}
 
-    async fn start(&mut self, config: BackendConfig) -> Result<BackendHandle> {
-        if self.running.load(Ordering::SeqCst) {
-            return Err(BackendError::AlreadyRunning);
+pub(crate) async fn connect_outbound(
+    dst: SocketAddr,
+    bind_addr: Option<IpAddr>,
+    clamp_mss: Option<u16>,
+) -> Result<TcpStream> {
+    if bind_addr.is_none() && clamp_mss.is_none() {
+        return Ok(TcpStream::connect(dst).await?);
+    }
+    
+    if let Some(bind_addr) = bind_addr {
+        if bind_addr.is_ipv4() != dst.is_ipv4() {
+            return Err(BackendError::NetworkConfig(format!(
+                "bind address {} cannot reach {} destination {}",
+                bind_addr,
+                if dst.is_ipv4() { "IPv4" } else { "IPv6" },
+                dst
+            )));
+        }
+    }
+    
+    let socket = if dst.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
+    if let Some(mss) = clamp_mss {
+        static MSS_UNSUPPORTED_LOGGED: AtomicBool = AtomicBool::new(false);
+        if let Err(e) = set_mss(&socket, mss) {
+            if !MSS_UNSUPPORTED_LOGGED.swap(true, Ordering::Relaxed) {
+                warn!(error = %e, mss, "Cannot clamp TCP_MAXSEG on this platform, connecting without it");
+            }
         }
+    }
+    if let Some(bind_addr) = bind_addr {
+        socket.bind(SocketAddr::new(bind_addr, 0)).map_err(|e| {
+            BackendError::NetworkConfig(format!("failed to bind outbound socket to {}: {}", bind_addr, e))
+        })?;
+    }
+    Ok(socket.connect(dst).await?)
+}
 
-        let proxy_settings = match config.backend_settings {
-            BackendSettings::Proxy(settings) => settings,
-            _ => return Err(BackendError::NotSupported(
-                "ProxyBackend requires ProxySettings".to_string()
-            )),
-        };
+#[cfg(unix)]
+fn set_mss(socket: &TcpSocket, mss: u16) -> io::Result<()> {
+    socket2::SockRef::from(socket).set_tcp_mss(mss as u32)
+}
 
-        info!(
-            addr = %proxy_settings.listen_addr,
-            proxy_type = ?proxy_settings.proxy_type,
-            "Starting proxy backend"
-        );
+#[cfg(not(unix))]
+fn set_mss(_socket: &TcpSocket, _mss: u16) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "TCP_MAXSEG is not supported"))
+}
 
-        let listener = TcpListener::bind(proxy_settings.listen_addr)
-            .await
-            .map_err(|e| BackendError::BindFailed(e.to_string()))?;
+pub(crate) async fn lookup_all(host_port: &str) -> Option<Vec<SocketAddr>> {
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host(host_port).await.ok()?.collect();
+    if addrs.is_empty() {
+        None
+    } else {
+        Some(addrs)
+    }
+}
 
-        let stats = Arc::new(Stats::new());
-        let pipeline = Arc::new(
-            Pipeline::new(config.engine_config, stats.clone())
-                .map_err(|e| BackendError::Engine(e))?
-        );
+fn interleave_families(addrs: &[SocketAddr]) -> Vec<SocketAddr> {
+    let first_is_v6 = addrs.first().is_some_and(|addr| addr.is_ipv6());
+    let (preferred, other): (Vec<SocketAddr>, Vec<SocketAddr>) = addrs
+        .iter()
+        .partition(|addr| addr.is_ipv6() == first_is_v6);
+    let mut preferred = preferred.into_iter();
+    let mut other = other.into_iter();
+    let mut ordered = Vec::with_capacity(addrs.len());
+    loop {
+        match (preferred.next(), other.next()) {
+            (None, None) => break,
+            (first, second) => {
+                ordered.extend(first);
+                ordered.extend(second);
+            }
+        }
+    }
+    ordered
+}
 
-        let (shutdown_tx, mut shutdown_rx) = mpsc::channel::<()>(1);
+pub(crate) async fn connect_racing(
+    addrs: &[SocketAddr],
+    bind_addr: Option<IpAddr>,
+    clamp_mss: Option<u16>,
+) -> Result<(TcpStream, SocketAddr, bool)> {
+    let mut pending = interleave_families(addrs).into_iter().enumerate();
+    let mut attempts = JoinSet::new();
+    let mut last_error = None;
+    
+    loop {
+        if attempts.is_empty() {
+            match pending.next() {
+                Some(next) => spawn_attempt(&mut attempts, next, bind_addr, clamp_mss),
+                None => break,
+            }
+        }
+        
+        tokio::select! {
+            Some(joined) = attempts.join_next() => match joined {
+                Ok((index, addr, Ok(stream))) => return Ok((stream, addr, index > 0)),
+                Ok((_, addr, Err(e))) => {
+                    debug!(error = %e, dst = %addr, "Connection attempt failed");
+                    last_error = Some(e);
+                    if let Some(next) = pending.next() {
+                        spawn_attempt(&mut attempts, next, bind_addr, clamp_mss);
+                    }
+                }
+                Err(e) => last_error = Some(BackendError::Connection(e.to_string())),
+            },
+            _ = tokio::time::sleep(CONNECTION_ATTEMPT_DELAY), if pending.len() > 0 => {
+                if let Some(next) = pending.next() {
+                    spawn_attempt(&mut attempts, next, bind_addr, clamp_mss);
+                }
+            }
+        }
+    }
+    
+    Err(last_error.unwrap_or_else(|| BackendError::Connection("no addresses to connect to".to_string())))
+}
 
-        self.config = Some(proxy_settings.clone());
-        self.shutdown_tx = Some(shutdown_tx.clone());
-        self.running.store(true, Ordering::SeqCst);
+fn spawn_attempt(
+    attempts: &mut JoinSet<(usize, SocketAddr, Result<TcpStream>)>,
+    (index, addr): (usize, SocketAddr),
+    bind_addr: Option<IpAddr>,
+    clamp_mss: Option<u16>,
+) {
+    attempts.spawn(async move { (index, addr, connect_outbound(addr, bind_addr, clamp_mss).await) });
+}
 
-        let running = self.running.clone();
-        let pipeline_clone = pipeline.clone();
-        let stats_clone = stats.clone();
-        let max_connections = proxy_settings.max_connections;
-        let active_connections = self.active_connections.clone();
-        let proxy_type = proxy_settings.proxy_type;
+/// Accepts from whichever listener is ready, starting at `next` and moving it
+/// past the one that answered so a busy listener cannot starve the others.
+pub(crate) async fn accept_any(listeners: &[TcpListener], next: &mut usize) -> io::Result<(TcpStream, SocketAddr)> {
+    std::future::poll_fn(|cx| {
+        for offset in 0..listeners.len() {
+            let index = (*next + offset) % listeners.len();
+            if let Poll::Ready(result) = listeners[index].poll_accept(cx) {
+                *next = index + 1;
+                return Poll::Ready(result);
+            }
+        }
+        Poll::Pending
+    }).await
+}
+
+pub(crate) async fn read_until_idle<R>(
+    reader: &mut R,
+    buf: &mut [u8],
+    last_activity: &Mutex<Instant>,
+    idle_timeout: Duration,
+) -> io::Result<usize>
+where
+    R: AsyncRead + Unpin,
+{
+    loop {
+        let deadline = *last_activity.lock() + idle_timeout;
+        match tokio::time::timeout_at(deadline, reader.read(buf)).await {
+            Ok(Ok(0)) => return Ok(0),
+            Ok(Ok(n)) => {
+                *last_activity.lock() = Instant::now();
+                return Ok(n);
+            }
+            Ok(Err(e)) => return Err(e),
+            Err(_) => {
+                if last_activity.lock().elapsed() >= idle_timeout {
+                    debug!(idle_secs = idle_timeout.as_secs(), "Idle timeout, closing connection");
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "Idle timeout"));
+                }
+            }
+        }
+    }
+}
+
+fn socks5_reply(status: u8, bound: Option<SocketAddr>) -> Vec<u8> {
+    let bound = bound.unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], 0)));
+    let mut reply = vec![0x05, status, 0x00];
+    match bound {
+        SocketAddr::V4(addr) => {
+            reply.push(0x01);
+            reply.extend_from_slice(&addr.ip().octets());
+        }
+        SocketAddr::V6(addr) => {
+            reply.push(0x04);
+            reply.extend_from_slice(&addr.ip().octets());
+        }
+    }
+    reply.extend_from_slice(&bound.port().to_be_bytes());
+    reply
+}
+
+fn socks4_reply(status: u8, bound: Option<SocketAddr>) -> [u8; 8] {
+    let mut reply = [0x00, status, 0, 0, 0, 0, 0, 0];
+    if let Some(SocketAddr::V4(addr)) = bound {
+        reply[2..4].copy_from_slice(&addr.port().to_be_bytes());
+        reply[4..].copy_from_slice(&addr.ip().octets());
+    }
+    reply
+}
+
+async fn read_null_terminated(client: &mut TcpStream) -> Option<Vec<u8>> {
+    let mut value = Vec::new();
+    loop {
+        let byte = client.read_u8().await.ok()?;
+        if byte == 0 {
+            return Some(value);
+        }
+        if value.len() >= MAX_SOCKS4_FIELD_LEN {
+            return None;
+        }
+        value.push(byte);
+    }
+}
+
+fn find_header_end(data: &[u8]) -> Option<usize> {
+    data.windows(4).position(|w| w == b"\r\n\r\n").map(|p| p + 4)
+}
+
+#[derive(Debug, Default)]
+pub struct ConnectionTracker {
+    total: AtomicU64,
+    per_ip: Mutex<HashMap<IpAddr, u64>>,
+    draining: AtomicBool,
+    drain_started: Notify,
+}
+
+impl ConnectionTracker {
+    pub fn active(&self) -> u64 {
+        self.total.load(Ordering::Relaxed)
+    }
+    
+    pub fn active_for(&self, ip: IpAddr) -> u64 {
+        self.per_ip.lock().get(&ip).copied().unwrap_or(0)
+    }
+    
+    pub fn per_ip(&self) -> HashMap<IpAddr, u64> {
+        self.per_ip.lock().clone()
+    }
+    
+    /// Stops the backend accepting, then waits up to `grace`, capped at
+    /// [`MAX_DRAIN_GRACE`], for open connections to finish.
+    pub async fn drain(&self, grace: Duration) {
+        self.draining.store(true, Ordering::SeqCst);
+        self.drain_started.notify_waiters();
+        let deadline = Instant::now() + grace.min(MAX_DRAIN_GRACE);
+        while self.active() > 0 && Instant::now() < deadline {
+            tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+        }
+    }
+    
+    async fn draining(&self) {
+        let started = self.drain_started.notified();
+        if !self.draining.load(Ordering::SeqCst) {
+            started.await;
+        }
+    }
+    
+    fn try_acquire(self: &Arc<Self>, ip: IpAddr, max_total: usize, max_per_ip: usize) -> Option<ConnectionGuard> {
+        let mut per_ip = self.per_ip.lock();
+        
+        if self.total.load(Ordering::Relaxed) >= max_total as u64 {
+            return None;
+        }
+        
+        let count = per_ip.entry(ip).or_insert(0);
+        if max_per_ip > 0 && *count >= max_per_ip as u64 {
+            return None;
+        }
+        
+        *count += 1;
+        self.total.fetch_add(1, Ordering::Relaxed);
+        
+        Some(ConnectionGuard {
+            tracker: self.clone(),
+            ip,
+        })
+    }
+}
+
+pub(crate) struct ConnectionGuard {
+    tracker: Arc<ConnectionTracker>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut per_ip = self.tracker.per_ip.lock();
+        if let Some(count) = per_ip.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                per_ip.remove(&self.ip);
+            }
+        }
+        self.tracker.total.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Default for ProxyBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Backend for ProxyBackend {
+    fn name(&self) -> &'static str {
+        "proxy"
+    }
+
+    async fn start(&mut self, config: BackendConfig) -> Result<BackendHandle> {
+        if self.running.load(Ordering::SeqCst) {
+            return Err(BackendError::AlreadyRunning);
+        }
+
+        let proxy_settings = match config.backend_settings {
+            BackendSettings::Proxy(settings) => settings,
+            _ => return Err(BackendError::NotSupported(
+                "ProxyBackend requires ProxySettings".to_string()
+            )),
+        };
+
+        info!(
+            addrs = ?proxy_settings.listen_addrs,
+            proxy_type = ?proxy_settings.proxy_type,
+            "Starting proxy backend"
+        );
+
+        let mut listeners = bind_listeners(&proxy_settings.listen_addrs)
+            .await
+            .map_err(|e| BackendError::BindFailed(e.to_string()))?;
+        let mut proxy_settings = proxy_settings;
+        proxy_settings.listen_addrs = listeners
+            .iter()
+            .filter_map(|listener| listener.local_addr().ok())
+            .collect();
+
+        let cleanup_every = std::time::Duration::from_secs(config.engine_config.limits.cleanup_interval_secs);
+        let mut dns = DohResolver::from_config(&config.engine_config.dns);
+        let fragment_doh = config.engine_config.dns.fragment_doh;
+        if let Some(ref path) = config.engine_config.dns.hosts_file {
+            let count = dns.load_hosts_file(path).map_err(|e| {
+                io::Error::new(e.kind(), format!("failed to load hosts file {}: {}", path.display(), e))
+            })?;
+            info!(path = %path.display(), entries = count, "Loaded hosts file");
+        }
+        let stats = Arc::new(Stats::new());
+        let pipeline = Arc::new(
+            Pipeline::new(config.engine_config, stats.clone())
+                .map_err(BackendError::Engine)?
+        );
+        if fragment_doh {
+            // Same fragmenting as from_config, but idle while paused.
+            let engine = BypassEngine::new(BypassConfig::default()).with_pause_flag(pipeline.pause_flag());
+            dns = dns.with_bypass_engine(Arc::new(engine));
+        }
+
+        let (shutdown_tx, mut shutdown_rx) = mpsc::channel::<()>(1);
+
+        self.config = Some(proxy_settings.clone());
+        self.shutdown_tx = Some(shutdown_tx.clone());
+        self.running.store(true, Ordering::SeqCst);
+        self.connections.draining.store(false, Ordering::SeqCst);
+
+        let history = Arc::new(StatsHistory::default());
+        let running = self.running.clone();
+        let pipeline_clone = pipeline.clone();
+        let stats_clone = stats.clone();
+        let history_clone = history.clone();
+        let max_connections = proxy_settings.max_connections;
+        let max_connections_per_ip = proxy_settings.max_connections_per_ip;
+        let connections_tracker = self.connections.clone();
+        let proxy_type = proxy_settings.proxy_type;
+        let dns = Arc::new(dns.with_bind_addr(proxy_settings.bind_addr));
+        let ctx = ConnectionContext {
+            pipeline: pipeline.clone(),
+            stats: stats.clone(),
+            dns: dns.clone(),
+            bypass: Arc::new(BypassEngine::new(proxy_settings.bypass.clone()).with_pause_flag(pipeline.pause_flag())),
+            idle_timeout: Duration::from_secs(proxy_settings.timeout_secs),
+            allow_socks4: proxy_settings.allow_socks4,
+            bind_addr: proxy_settings.bind_addr,
+            clamp_mss: proxy_settings.bypass.clamp_mss,
+            fake_ttl: proxy_settings.bypass.fake_packet_ttl,
+            buffers: Arc::new(BufferPool::new(RELAY_BUFFER_SIZE, DEFAULT_POOL_CAPACITY)),
+        };
+        let drain_timeout = std::time::Duration::from_secs(proxy_settings.drain_timeout_secs);
 
         let handle = tokio::spawn(async move {
             info!("Proxy backend accepting connections");
             
+            let mut connections = JoinSet::new();
+            let mut next_listener = 0;
+            let mut cleanup_interval = tokio::time::interval(cleanup_every);
+            let mut schedule_interval = tokio::time::interval(engine::pipeline::SCHEDULE_REFRESH_INTERVAL);
+            let mut history_interval = tokio::time::interval(engine::stats::HISTORY_SAMPLE_INTERVAL);
+            
             loop {
                 tokio::select! {
                     _ = shutdown_rx.recv() => {
                         info!("Proxy backend received shutdown signal");
                         break;
                     }
-                    result = listener.accept() => {
+                    _ = cleanup_interval.tick() => {
+                        let evicted = pipeline_clone.cleanup();
+                        if evicted > 0 {
+                            debug!(evicted, "Cleaned up expired flows");
+                        }
+                    }
+                    _ = schedule_interval.tick() => {
+                        pipeline_clone.refresh_schedules();
+                    }
+                    _ = history_interval.tick() => {
+                        history_clone.sample(&stats_clone);
+                    }
+                    _ = connections_tracker.draining(), if !listeners.is_empty() => {
+                        info!("Draining, no longer accepting connections");
+                        listeners.clear();
+                    }
+                    result = accept_any(&listeners, &mut next_listener), if !listeners.is_empty() => {
                         match result {
                             Ok((stream, addr)) => {
-                                if active_connections.load(Ordering::Relaxed) >= max_connections as u64 {
-                                    warn!(addr = %addr, "Connection limit reached, rejecting");
-                                    continue;
-                                }
+                                while connections.try_join_next().is_some() {}
+                                
+                                let guard = match connections_tracker.try_acquire(addr.ip(), max_connections, max_connections_per_ip) {
+                                    Some(guard) => guard,
+                                    None => {
+                                        if pipeline_clone.log_limiter().allow() {
+                                            warn!(
+                                                addr = %addr,
+                                                active = connections_tracker.active(),
+                                                from_ip = connections_tracker.active_for(addr.ip()),
+                                                "Connection limit reached, rejecting"
+                                            );
+                                        }
+                                        tokio::spawn(async move {
+                                            let _ = tokio::time::timeout(
+                                                REJECT_TIMEOUT,
+                                                Self::reject_connection(stream, proxy_type),
+                                            ).await;
+                                        });
+                                        continue;
+                                    }
+                                };
                                 
-                                let pipeline = pipeline_clone.clone();
-                                let stats = stats_clone.clone();
-                                let active = active_connections.clone();
+                                let ctx = ctx.clone();
                                 
                                 match proxy_type {
                                     ProxyType::Socks5 => {
-                                        tokio::spawn(Self::handle_socks5(
-                                            stream, addr, pipeline, stats, active
-                                        ));
+                                        connections.spawn(Self::handle_socks5(stream, addr, ctx, guard));
                                     }
                                     ProxyType::HttpConnect => {
-                                        warn!("--");
+                                        connections.spawn(Self::handle_http_connect(stream, addr, ctx, guard));
                                     }
                                 }
                             }
@@ -364,6 +1157,20 @@ impl Backend for ProxyBackend {
                     }
                 }
             }
+            
+            drop(listeners);
+            
+            if !connections.is_empty() {
+                info!(connections = connections.len(), "Draining active connections");
+                let drained = tokio::time::timeout(drain_timeout, async {
+                    while connections.join_next().await.is_some() {}
+                }).await;
+                
+                if drained.is_err() {
+                    warn!(connections = connections.len(), "Drain timeout reached, aborting connections");
+                    connections.shutdown().await;
+                }
+            }
 
             running.store(false, Ordering::SeqCst);
             info!("Proxy backend stopped");
@@ -374,7 +1181,12 @@ impl Backend for ProxyBackend {
         Ok(BackendHandle {
             shutdown_tx,
             stats,
+            history,
             pipeline,
+            connections: Some(self.connections.clone()),
+            listen_addrs: proxy_settings.listen_addrs,
+            dns: Some(dns),
+            proxy: None,
         })
     }
 
@@ -391,10 +1203,7 @@ impl Backend for ProxyBackend {
 
         let handle = self.task_handle.lock().take();
         if let Some(handle) = handle {
-            let _ = tokio::time::timeout(
-                std::time::Duration::from_secs(5),
-                handle,
-            ).await;
+            let _ = handle.await;
         }
 
         self.running.store(false, Ordering::SeqCst);
@@ -416,6 +1225,7 @@ impl Backend for ProxyBackend {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::spawn_echo_server;
     use engine::Config;
 
     #[test]
@@ -437,27 +1247,884 @@ mod tests {
             engine_config: Config::default(),
             max_queue_size: 100,
             backend_settings: BackendSettings::Proxy(ProxySettings {
-                listen_addr: "127.0.0.1:0".parse().unwrap(),
+                listen_addrs: vec!["127.0.0.1:0".parse().unwrap()],
                 ..Default::default()
             }),
         };
         
+        let _handle = backend.start(config).await.unwrap();
+        assert!(backend.is_running());
+        
+        backend.stop().await.unwrap();
+        assert!(!backend.is_running());
+    }
+
+    async fn socks5_connect(proxy: SocketAddr, target: SocketAddr) -> (TcpStream, Vec<u8>) {
+        let mut client = TcpStream::connect(proxy).await.unwrap();
+        client.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+        let mut method = [0u8; 2];
+        client.read_exact(&mut method).await.unwrap();
+        assert_eq!(method, [0x05, 0x00]);
+        
+        let ip = match target.ip() {
+            std::net::IpAddr::V4(ip) => ip.octets(),
+            _ => unreachable!(),
+        };
+        let mut request = vec![0x05, 0x01, 0x00, 0x01];
+        request.extend_from_slice(&ip);
+        request.extend_from_slice(&target.port().to_be_bytes());
+        client.write_all(&request).await.unwrap();
+        
+        let mut reply = vec![0u8; 10];
+        client.read_exact(&mut reply).await.unwrap();
+        (client, reply)
+    }
+
+    #[tokio::test]
+    async fn test_socks5_reply_carries_bound_address() {
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target_addr = target.local_addr().unwrap();
+        let accepted = tokio::spawn(async move {
+            let (_stream, peer) = target.accept().await.unwrap();
+            peer
+        });
+        
+        let mut backend = ProxyBackend::new();
+        let config = BackendConfig {
+            engine_config: Config::default(),
+            max_queue_size: 100,
+            backend_settings: BackendSettings::Proxy(ProxySettings {
+                listen_addrs: vec!["127.0.0.1:0".parse().unwrap()],
+                ..Default::default()
+            }),
+        };
+        backend.start(config).await.unwrap();
+        
+        let (client, reply) = socks5_connect(backend.listen_addr().unwrap(), target_addr).await;
+        assert_eq!(&reply[..4], &[0x05, 0x00, 0x00, 0x01]);
+        
+        let bound_ip = std::net::Ipv4Addr::new(reply[4], reply[5], reply[6], reply[7]);
+        let bound_port = u16::from_be_bytes([reply[8], reply[9]]);
+        assert_eq!(bound_ip, std::net::Ipv4Addr::LOCALHOST);
+        assert_ne!(bound_port, 0);
+        assert_eq!(SocketAddr::from((bound_ip, bound_port)), accepted.await.unwrap());
+        
+        drop(client);
+        backend.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_malformed_socks5_requests_counted() {
+        let mut backend = ProxyBackend::new();
+        let config = BackendConfig {
+            engine_config: Config::default(),
+            max_queue_size: 100,
+            backend_settings: BackendSettings::Proxy(ProxySettings {
+                listen_addrs: vec!["127.0.0.1:0".parse().unwrap()],
+                ..Default::default()
+            }),
+        };
+        let handle = backend.start(config).await.unwrap();
+        let proxy_addr = backend.listen_addr().unwrap();
+        
+        let mut client = TcpStream::connect(proxy_addr).await.unwrap();
+        client.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+        let mut reply = [0u8; 2];
+        client.read_exact(&mut reply).await.unwrap();
+        client.write_all(&[0x05, 0x01, 0x00, 0x09]).await.unwrap();
+        let mut reply = [0u8; 10];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply[1], 0x08);
+        
+        let mut client = TcpStream::connect(proxy_addr).await.unwrap();
+        client.write_all(&[0x06, 0x01, 0x00]).await.unwrap();
+        let mut response = Vec::new();
+        let _ = client.read_to_end(&mut response).await;
+        
+        assert_eq!(handle.stats().snapshot().invalid_packets, 2);
+        backend.stop().await.unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_socks5_connection_limit_reply() {
+        let mut backend = ProxyBackend::new();
+        let config = BackendConfig {
+            engine_config: Config::default(),
+            max_queue_size: 100,
+            backend_settings: BackendSettings::Proxy(ProxySettings {
+                listen_addrs: vec!["127.0.0.1:0".parse().unwrap()],
+                max_connections: 1,
+                drain_timeout_secs: 0,
+                ..Default::default()
+            }),
+        };
+        backend.start(config).await.unwrap();
+        let proxy_addr = backend.listen_addr().unwrap();
+        
+        let mut first = TcpStream::connect(proxy_addr).await.unwrap();
+        first.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+        let mut method = [0u8; 2];
+        first.read_exact(&mut method).await.unwrap();
+        assert_eq!(backend.active_connections(), 1);
+        
+        let (_second, reply) = socks5_connect(proxy_addr, "127.0.0.1:9".parse().unwrap()).await;
+        assert_eq!(&reply[..4], &[0x05, 0x01, 0x00, 0x01]);
+        assert_eq!(u16::from_be_bytes([reply[8], reply[9]]), proxy_addr.port());
+        
+        backend.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_listens_on_every_address() {
+        // Hosts without IPv6 loopback listen on two IPv4 ports instead.
+        let ipv6 = std::net::TcpListener::bind("[::1]:0").is_ok();
+        let second: SocketAddr = if ipv6 { "[::1]:0" } else { "127.0.0.1:0" }.parse().unwrap();
+        let mut backend = ProxyBackend::new();
+        let config = BackendConfig {
+            engine_config: Config::default(),
+            max_queue_size: 100,
+            backend_settings: BackendSettings::Proxy(ProxySettings {
+                listen_addrs: vec!["127.0.0.1:0".parse().unwrap(), second],
+                ..Default::default()
+            }),
+        };
         let handle = backend.start(config).await.unwrap();
+        
+        let addrs = handle.listen_addrs().to_vec();
+        assert_eq!(addrs, backend.listen_addrs());
+        assert_eq!(addrs.len(), 2);
+        assert!(addrs[0].is_ipv4());
+        assert_eq!(addrs[1].is_ipv6(), ipv6);
+        
+        for addr in addrs {
+            assert_ne!(addr.port(), 0);
+            let (mut client, reply) = socks5_connect(addr, spawn_echo_server().await).await;
+            assert_eq!(reply[1], 0x00);
+            client.write_all(b"ping").await.unwrap();
+            let mut echoed = [0u8; 4];
+            tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut echoed)).await.unwrap().unwrap();
+            assert_eq!(&echoed, b"ping");
+        }
+        
+        backend.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_outbound_bind_addr() {
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target_addr = target.local_addr().unwrap();
+        let accepted = tokio::spawn(async move {
+            let (_stream, peer) = target.accept().await.unwrap();
+            peer
+        });
+        
+        let mut backend = ProxyBackend::new();
+        let config = BackendConfig {
+            engine_config: Config::default(),
+            max_queue_size: 100,
+            backend_settings: BackendSettings::Proxy(ProxySettings {
+                listen_addrs: vec!["127.0.0.1:0".parse().unwrap()],
+                bind_addr: Some("127.0.0.2".parse().unwrap()),
+                ..Default::default()
+            }),
+        };
+        backend.start(config).await.unwrap();
+        
+        let (client, reply) = socks5_connect(backend.listen_addr().unwrap(), target_addr).await;
+        assert_eq!(reply[1], 0x00);
+        assert_eq!(&reply[4..8], &[127, 0, 0, 2]);
+        
+        let peer = tokio::time::timeout(Duration::from_secs(5), accepted).await.unwrap().unwrap();
+        assert_eq!(peer.ip(), "127.0.0.2".parse::<IpAddr>().unwrap());
+        
+        drop(client);
+        backend.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_outbound_bind_family_mismatch() {
+        let dst: SocketAddr = "127.0.0.1:9".parse().unwrap();
+        let err = connect_outbound(dst, Some("::1".parse().unwrap()), None).await.unwrap_err();
+        assert!(matches!(err, BackendError::NetworkConfig(_)));
+        assert!(err.to_string().contains("IPv4 destination"));
+    }
+
+    #[tokio::test]
+    async fn test_bind_listeners_requires_address() {
+        assert!(bind_listeners(&[]).await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn test_accept_any_rotates_listeners() {
+        let listeners = bind_listeners(&["127.0.0.1:0".parse().unwrap(), "127.0.0.1:0".parse().unwrap()]).await.unwrap();
+        let addrs: Vec<SocketAddr> = listeners.iter().map(|l| l.local_addr().unwrap()).collect();
+        let mut clients = Vec::new();
+        for addr in &addrs {
+            for _ in 0..2 {
+                clients.push(TcpStream::connect(addr).await.unwrap());
+            }
+        }
+        
+        let mut next = 0;
+        let mut accepted = Vec::new();
+        for _ in 0..4 {
+            let (stream, _) = accept_any(&listeners, &mut next).await.unwrap();
+            accepted.push(stream.local_addr().unwrap());
+        }
+        assert_eq!(accepted, [addrs[0], addrs[1], addrs[0], addrs[1]]);
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[tokio::test]
+    async fn test_set_mss_round_trips() {
+        let socket = TcpSocket::new_v4().unwrap();
+        set_mss(&socket, 536).unwrap();
+        assert_eq!(socket2::SockRef::from(&socket).tcp_mss().unwrap(), 536);
+    }
+
+    #[tokio::test]
+    async fn test_connect_outbound_with_clamped_mss() {
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target_addr = target.local_addr().unwrap();
+        
+        let stream = connect_outbound(target_addr, None, Some(88)).await.unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), target_addr);
+    }
+
+    #[test]
+    fn test_interleave_families_starts_with_first_family() {
+        let addrs: Vec<SocketAddr> = vec![
+            "192.0.2.1:443".parse().unwrap(),
+            "192.0.2.2:443".parse().unwrap(),
+            "[2001:db8::1]:443".parse().unwrap(),
+        ];
+        let ordered = interleave_families(&addrs);
+        assert_eq!(ordered, vec![addrs[0], addrs[2], addrs[1]]);
+        
+        let v6_first = vec![addrs[2], addrs[0], addrs[1]];
+        assert_eq!(interleave_families(&v6_first), v6_first);
+    }
+
+    #[tokio::test]
+    async fn test_connect_racing_falls_back() {
+        let dead = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let dead_addr = dead.local_addr().unwrap();
+        drop(dead);
+        
+        let live = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let live_addr = live.local_addr().unwrap();
+        
+        let (_stream, addr, fallback) = connect_racing(&[dead_addr, live_addr], None, None).await.unwrap();
+        assert_eq!(addr, live_addr);
+        assert!(fallback);
+        
+        let (_stream, addr, fallback) = connect_racing(&[live_addr], None, None).await.unwrap();
+        assert_eq!(addr, live_addr);
+        assert!(!fallback);
+        
+        assert!(connect_racing(&[dead_addr], None, None).await.is_err());
+        assert!(connect_racing(&[], None, None).await.is_err());
+    }
+
+    async fn start_socks4_backend(allow_socks4: bool) -> ProxyBackend {
+        let mut backend = ProxyBackend::new();
+        let config = BackendConfig {
+            engine_config: Config::default(),
+            max_queue_size: 100,
+            backend_settings: BackendSettings::Proxy(ProxySettings {
+                listen_addrs: vec!["127.0.0.1:0".parse().unwrap()],
+                allow_socks4,
+                ..Default::default()
+            }),
+        };
+        backend.start(config).await.unwrap();
+        backend
+    }
+
+    async fn assert_socks4_echo(backend: &ProxyBackend, request: &[u8]) {
+        let mut client = TcpStream::connect(backend.listen_addr().unwrap()).await.unwrap();
+        client.write_all(request).await.unwrap();
+        
+        let mut reply = [0u8; 8];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply[0], 0x00);
+        assert_eq!(reply[1], SOCKS4_GRANTED);
+        assert_ne!(u16::from_be_bytes([reply[2], reply[3]]), 0);
+        assert_eq!(&reply[4..], &[127, 0, 0, 1]);
+        
+        client.write_all(b"legacy client").await.unwrap();
+        let mut echoed = [0u8; 13];
+        tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut echoed)).await.unwrap().unwrap();
+        assert_eq!(&echoed, b"legacy client");
+    }
+
+    #[tokio::test]
+    async fn test_socks4_ip_connect() {
+        let echo_addr = spawn_echo_server().await;
+        let mut backend = start_socks4_backend(true).await;
+        
+        let mut request = vec![0x04, 0x01];
+        request.extend_from_slice(&echo_addr.port().to_be_bytes());
+        request.extend_from_slice(&[127, 0, 0, 1]);
+        request.extend_from_slice(b"user\0");
+        assert_socks4_echo(&backend, &request).await;
+        
+        backend.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_socks4a_hostname_connect() {
+        let echo_addr = spawn_echo_server().await;
+        let mut backend = start_socks4_backend(true).await;
+        
+        let mut request = vec![0x04, 0x01];
+        request.extend_from_slice(&echo_addr.port().to_be_bytes());
+        request.extend_from_slice(&[0, 0, 0, 1]);
+        request.push(0x00);
+        request.extend_from_slice(b"127.0.0.1\0");
+        assert_socks4_echo(&backend, &request).await;
+        
+        backend.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_socks4_disabled_by_default() {
+        let echo_addr = spawn_echo_server().await;
+        let mut backend = start_socks4_backend(false).await;
+        
+        let mut client = TcpStream::connect(backend.listen_addr().unwrap()).await.unwrap();
+        let mut request = vec![0x04, 0x01];
+        request.extend_from_slice(&echo_addr.port().to_be_bytes());
+        request.extend_from_slice(&[127, 0, 0, 1, 0]);
+        client.write_all(&request).await.unwrap();
+        
+        let mut response = Vec::new();
+        let _ = client.read_to_end(&mut response).await;
+        assert!(response.is_empty());
+        
+        backend.stop().await.unwrap();
+    }
+
+    fn http_connect_config() -> BackendConfig {
+        BackendConfig {
+            engine_config: Config::default(),
+            max_queue_size: 100,
+            backend_settings: BackendSettings::Proxy(ProxySettings {
+                listen_addrs: vec!["127.0.0.1:0".parse().unwrap()],
+                proxy_type: ProxyType::HttpConnect,
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_http_connect_start_stop() {
+        let mut backend = ProxyBackend::new();
+        
+        let _handle = backend.start(http_connect_config()).await.unwrap();
         assert!(backend.is_running());
+        assert_ne!(backend.listen_addr().unwrap().port(), 0);
         
         backend.stop().await.unwrap();
         assert!(!backend.is_running());
     }
 
+    #[tokio::test]
+    async fn test_http_connect_echo() {
+        let echo_addr = spawn_echo_server().await;
+        
+        let mut backend = ProxyBackend::new();
+        let _handle = backend.start(http_connect_config()).await.unwrap();
+        let proxy_addr = backend.listen_addr().unwrap();
+        
+        let mut client = TcpStream::connect(proxy_addr).await.unwrap();
+        let request = format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n\r\n\x16\x03\x01pipelined", echo_addr, echo_addr);
+        client.write_all(request.as_bytes()).await.unwrap();
+        
+        let expected = b"HTTP/1.1 200 Connection Established\r\n\r\n\x16\x03\x01pipelined";
+        let mut response = vec![0u8; expected.len()];
+        tokio::time::timeout(std::time::Duration::from_secs(5), client.read_exact(&mut response)).await.unwrap().unwrap();
+        assert_eq!(&response[..], &expected[..]);
+        
+        client.write_all(b"more data").await.unwrap();
+        let mut echoed = [0u8; 9];
+        client.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(&echoed, b"more data");
+        
+        drop(client);
+        backend.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_http_connect_rejects_other_methods() {
+        let mut backend = ProxyBackend::new();
+        let _handle = backend.start(http_connect_config()).await.unwrap();
+        
+        let mut client = TcpStream::connect(backend.listen_addr().unwrap()).await.unwrap();
+        client.write_all(b"GET http://example.com/ HTTP/1.1\r\nHost: example.com\r\n\r\n").await.unwrap();
+        
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).await.unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 405"));
+        
+        backend.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_socks5_fragments_client_hello() {
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target_addr = target.local_addr().unwrap();
+        let hello = crate::transparent::tests::sample_client_hello();
+        let expected = hello.clone();
+        let receiver = tokio::spawn(async move {
+            let (mut stream, _) = target.accept().await.unwrap();
+            let mut buf = [0u8; 1024];
+            let n = stream.read(&mut buf).await.unwrap();
+            let first_segment = n;
+            let mut received = buf[..n].to_vec();
+            while received.len() < expected.len() {
+                let n = stream.read(&mut buf).await.unwrap();
+                assert!(n > 0);
+                received.extend_from_slice(&buf[..n]);
+            }
+            (first_segment, received)
+        });
+        
+        let mut backend = ProxyBackend::new();
+        let config = BackendConfig {
+            engine_config: Config::default(),
+            max_queue_size: 100,
+            backend_settings: BackendSettings::Proxy(ProxySettings {
+                listen_addrs: vec!["127.0.0.1:0".parse().unwrap()],
+                bypass: engine::BypassConfig {
+                    fragment_delay_us: 50_000,
+                    ..Default::default()
+                },
+                ..Default::default()
+            }),
+        };
+        let handle = backend.start(config).await.unwrap();
+        
+        let (mut client, reply) = socks5_connect(backend.listen_addr().unwrap(), target_addr).await;
+        assert_eq!(reply[1], 0x00);
+        
+        client.write_all(&hello).await.unwrap();
+        
+        let (first_segment, received) = tokio::time::timeout(std::time::Duration::from_secs(5), receiver)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(received, hello);
+        assert!(first_segment < hello.len());
+        assert!(handle.stats.snapshot().fragments_generated >= 2);
+        
+        backend.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_write_emitted_sends_fakes_in_place_of_real_bytes() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).await.unwrap();
+            received
+        });
+        
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let (_, mut write) = stream.split();
+        let packets = vec![
+            EmittedPacket::new(BytesMut::from(&b"FAKE"[..]), EmitKind::Fake),
+            BytesMut::from(&b"real data"[..]).into(),
+            EmittedPacket::new(BytesMut::from(&b"!"[..]), EmitKind::Real).with_ttl(5),
+            EmittedPacket::new(BytesMut::from(&b"trailing fake"[..]), EmitKind::Fake),
+        ];
+        let original_ttl = write.as_ref().ttl().unwrap();
+        write_emitted(&mut write, packets, 3).await.unwrap();
+        assert_eq!(write.as_ref().ttl().unwrap(), original_ttl);
+        write.shutdown().await.unwrap();
+        
+        // Loopback never drops the low-TTL copy, so the first bytes may be
+        // either payload; everything else must be the real data.
+        let received = server.await.unwrap();
+        assert_eq!(received.len(), b"real data!".len());
+        assert!(&received[..4] == b"FAKE" || &received[..4] == b"real");
+        assert_eq!(&received[4..], b" data!");
+    }
+    
+    #[tokio::test]
+    async fn test_write_with_ttl_restores_ttl_on_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let _peer = listener.accept().await.unwrap();
+        let (_, mut write) = stream.split();
+        let original_ttl = write.as_ref().ttl().unwrap();
+        
+        write.shutdown().await.unwrap();
+        assert!(write_with_ttl(&write, b"too late", 5).await.is_err());
+        assert_eq!(write.as_ref().ttl().unwrap(), original_ttl);
+    }
+    
+    #[tokio::test]
+    async fn test_socks5_honors_jitter_delay() {
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target_addr = target.local_addr().unwrap();
+        let receiver = tokio::spawn(async move {
+            let (mut stream, _) = target.accept().await.unwrap();
+            let mut buf = [0u8; 64];
+            let n = stream.read(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], b"hello");
+            stream.write_all(b"ready").await.unwrap();
+            let n = stream.read(&mut buf).await.unwrap();
+            (std::time::Instant::now(), buf[..n].to_vec())
+        });
+        
+        let mut engine_config = Config::default();
+        engine_config.global.enable_jitter = true;
+        engine_config.transforms.jitter.min_ms = 100;
+        engine_config.transforms.jitter.max_ms = 150;
+        engine_config.rules.push(engine::config::Rule {
+            name: "jitter-only".to_string(),
+            enabled: true,
+            priority: 10,
+            match_criteria: engine::config::MatchCriteria {
+                dst_ports: Some(vec![target_addr.port()]),
+                ..Default::default()
+            },
+            action: Default::default(),
+            transforms: vec![engine::config::TransformType::Jitter],
+            overrides: Default::default(),
+        });
+        
+        let mut backend = ProxyBackend::new();
+        let config = BackendConfig {
+            engine_config,
+            max_queue_size: 100,
+            backend_settings: BackendSettings::Proxy(ProxySettings {
+                listen_addrs: vec!["127.0.0.1:0".parse().unwrap()],
+                ..Default::default()
+            }),
+        };
+        let handle = backend.start(config).await.unwrap();
+        
+        let (mut client, reply) = socks5_connect(backend.listen_addr().unwrap(), target_addr).await;
+        assert_eq!(reply[1], 0x00);
+        
+        client.write_all(b"hello").await.unwrap();
+        let mut ready = [0u8; 5];
+        client.read_exact(&mut ready).await.unwrap();
+        
+        let sent_at = std::time::Instant::now();
+        client.write_all(b"ping").await.unwrap();
+        
+        let (received_at, data) = tokio::time::timeout(std::time::Duration::from_secs(5), receiver)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(data, b"ping");
+        assert!(received_at.duration_since(sent_at) >= std::time::Duration::from_millis(100));
+        assert!(handle.stats.snapshot().total_jitter_ms >= 100);
+        
+        backend.stop().await.unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_socks5_spaces_fragments_with_jitter() {
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target_addr = target.local_addr().unwrap();
+        let receiver = tokio::spawn(async move {
+            let (mut stream, _) = target.accept().await.unwrap();
+            let mut buf = [0u8; 64];
+            let n = stream.read(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], b"hello");
+            stream.write_all(b"ready").await.unwrap();
+            
+            let mut arrivals = Vec::new();
+            let mut received = Vec::new();
+            while received.len() < 12 {
+                let n = stream.read(&mut buf).await.unwrap();
+                assert!(n > 0);
+                arrivals.push(std::time::Instant::now());
+                received.extend_from_slice(&buf[..n]);
+            }
+            (arrivals, received)
+        });
+        
+        let mut engine_config = Config::default();
+        engine_config.global.enable_jitter = true;
+        engine_config.transforms.fragment.min_size = 4;
+        engine_config.transforms.fragment.max_size = 4;
+        engine_config.transforms.jitter.min_ms = 40;
+        engine_config.transforms.jitter.max_ms = 60;
+        engine_config.rules.push(engine::config::Rule {
+            name: "paced-fragments".to_string(),
+            enabled: true,
+            priority: 10,
+            match_criteria: engine::config::MatchCriteria {
+                dst_ports: Some(vec![target_addr.port()]),
+                ..Default::default()
+            },
+            action: Default::default(),
+            transforms: vec![engine::config::TransformType::Fragment, engine::config::TransformType::Jitter],
+            overrides: Default::default(),
+        });
+        
+        let mut backend = ProxyBackend::new();
+        let config = BackendConfig {
+            engine_config,
+            max_queue_size: 100,
+            backend_settings: BackendSettings::Proxy(ProxySettings {
+                listen_addrs: vec!["127.0.0.1:0".parse().unwrap()],
+                ..Default::default()
+            }),
+        };
+        backend.start(config).await.unwrap();
+        
+        let (mut client, reply) = socks5_connect(backend.listen_addr().unwrap(), target_addr).await;
+        assert_eq!(reply[1], 0x00);
+        
+        // The first payload goes through the bypass engine, not the pipeline.
+        client.write_all(b"hello").await.unwrap();
+        let mut ready = [0u8; 5];
+        client.read_exact(&mut ready).await.unwrap();
+        client.write_all(b"0123456789ab").await.unwrap();
+        
+        let (arrivals, received) = tokio::time::timeout(std::time::Duration::from_secs(5), receiver)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(received, b"0123456789ab");
+        // Three fragments, each after the first held back by at least min_ms.
+        let spread = arrivals.last().unwrap().duration_since(arrivals[0]);
+        assert!(spread >= std::time::Duration::from_millis(80), "{:?}", spread);
+        
+        backend.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_drain_stops_accepting() {
+        // Caps rather than overflows the deadline.
+        ConnectionTracker::default().drain(Duration::MAX).await;
+        
+        let target_addr = spawn_echo_server().await;
+        let mut backend = ProxyBackend::new();
+        let config = BackendConfig {
+            engine_config: Config::default(),
+            max_queue_size: 100,
+            backend_settings: BackendSettings::Proxy(ProxySettings {
+                listen_addrs: vec!["127.0.0.1:0".parse().unwrap()],
+                drain_timeout_secs: 0,
+                ..Default::default()
+            }),
+        };
+        let handle = backend.start(config).await.unwrap();
+        let addr = backend.listen_addr().unwrap();
+        let (mut client, reply) = socks5_connect(addr, target_addr).await;
+        assert_eq!(reply[1], 0x00);
+        
+        let connections = handle.connections.clone().unwrap();
+        let started = std::time::Instant::now();
+        connections.drain(Duration::from_millis(200)).await;
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert_eq!(connections.active(), 1);
+        assert!(TcpStream::connect(addr).await.is_err());
+        
+        client.write_all(b"still open").await.unwrap();
+        let mut buf = [0u8; 10];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"still open");
+        
+        backend.stop().await.unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_stop_aborts_connections_after_drain_timeout() {
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target_addr = target.local_addr().unwrap();
+        let _server = tokio::spawn(async move {
+            let (stream, _) = target.accept().await.unwrap();
+            tokio::time::sleep(std::time::Duration::from_secs(30)).await;
+            drop(stream);
+        });
+        
+        let mut backend = ProxyBackend::new();
+        let config = BackendConfig {
+            engine_config: Config::default(),
+            max_queue_size: 100,
+            backend_settings: BackendSettings::Proxy(ProxySettings {
+                listen_addrs: vec!["127.0.0.1:0".parse().unwrap()],
+                drain_timeout_secs: 1,
+                ..Default::default()
+            }),
+        };
+        backend.start(config).await.unwrap();
+        
+        let (mut client, reply) = socks5_connect(backend.listen_addr().unwrap(), target_addr).await;
+        assert_eq!(reply[1], 0x00);
+        client.write_all(b"in flight").await.unwrap();
+        
+        assert_eq!(backend.active_connections(), 1);
+        
+        let started = std::time::Instant::now();
+        backend.stop().await.unwrap();
+        let elapsed = started.elapsed();
+        
+        assert!(elapsed >= std::time::Duration::from_millis(900));
+        assert!(elapsed < std::time::Duration::from_secs(3));
+        assert_eq!(backend.active_connections(), 0);
+        
+        let mut buf = [0u8; 16];
+        let n = tokio::time::timeout(std::time::Duration::from_secs(1), client.read(&mut buf))
+            .await
+            .unwrap()
+            .unwrap_or(0);
+        assert_eq!(n, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_periodic_flow_cleanup() {
+        let mut engine_config = Config::default();
+        engine_config.limits.flow_timeout_secs = 60;
+        engine_config.limits.cleanup_interval_secs = 10;
+        
+        let mut backend = ProxyBackend::new();
+        let config = BackendConfig {
+            engine_config,
+            max_queue_size: 100,
+            backend_settings: BackendSettings::Proxy(ProxySettings {
+                listen_addrs: vec!["127.0.0.1:0".parse().unwrap()],
+                ..Default::default()
+            }),
+        };
+        let handle = backend.start(config).await.unwrap();
+        
+        for port in 1000..1003 {
+            let key = FlowKey::new(
+                "127.0.0.1".parse().unwrap(),
+                "10.0.0.1".parse().unwrap(),
+                port,
+                443,
+                Protocol::Tcp,
+            );
+            handle.pipeline.process(key, BytesMut::from(&b"data"[..])).unwrap();
+        }
+        assert_eq!(handle.pipeline.flow_cache().len(), 3);
+        
+        tokio::time::sleep(std::time::Duration::from_secs(75)).await;
+        
+        assert_eq!(handle.pipeline.flow_cache().len(), 0);
+        assert_eq!(handle.stats.snapshot().flows_evicted, 3);
+        assert_eq!(handle.stats.snapshot().active_flows, 0);
+        
+        backend.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_idle_connection_closed_after_timeout() {
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target_addr = target.local_addr().unwrap();
+        let peer = tokio::spawn(async move {
+            let (mut stream, _) = target.accept().await.unwrap();
+            let mut buf = [0u8; 64];
+            let n = stream.read(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], b"ping");
+            let mut rest = Vec::new();
+            stream.read_to_end(&mut rest).await.unwrap();
+        });
+        
+        let mut backend = ProxyBackend::new();
+        let config = BackendConfig {
+            engine_config: Config::default(),
+            max_queue_size: 100,
+            backend_settings: BackendSettings::Proxy(ProxySettings {
+                listen_addrs: vec!["127.0.0.1:0".parse().unwrap()],
+                timeout_secs: 1,
+                ..Default::default()
+            }),
+        };
+        backend.start(config).await.unwrap();
+        
+        let (mut client, reply) = socks5_connect(backend.listen_addr().unwrap(), target_addr).await;
+        assert_eq!(reply[1], 0x00);
+        client.write_all(b"ping").await.unwrap();
+        
+        let started = std::time::Instant::now();
+        let mut buf = [0u8; 16];
+        let n = tokio::time::timeout(Duration::from_secs(5), client.read(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(n, 0);
+        assert!(started.elapsed() >= Duration::from_millis(900));
+        
+        tokio::time::timeout(Duration::from_secs(1), peer).await.unwrap().unwrap();
+        for _ in 0..50 {
+            if backend.active_connections() == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(backend.active_connections(), 0);
+        
+        backend.stop().await.unwrap();
+    }
+
     #[test]
     fn test_connection_guard() {
-        let counter = Arc::new(AtomicU64::new(0));
+        let tracker = Arc::new(ConnectionTracker::default());
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
         
         {
-            let _guard = ConnectionGuard::new(counter.clone());
-            assert_eq!(counter.load(Ordering::Relaxed), 1);
+            let _first = tracker.try_acquire(ip, 10, 2).unwrap();
+            let _second = tracker.try_acquire(ip, 10, 2).unwrap();
+            assert_eq!(tracker.active(), 2);
+            assert_eq!(tracker.active_for(ip), 2);
+            assert!(tracker.try_acquire(ip, 10, 2).is_none());
+            
+            let other: IpAddr = "10.0.0.1".parse().unwrap();
+            assert!(tracker.try_acquire(other, 2, 0).is_none());
+            assert!(tracker.try_acquire(other, 10, 0).is_some());
         }
         
-        assert_eq!(counter.load(Ordering::Relaxed), 0);
+        assert_eq!(tracker.active(), 0);
+        assert!(tracker.per_ip().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_per_ip_connection_limit() {
+        let mut backend = ProxyBackend::new();
+        let config = BackendConfig {
+            engine_config: Config::default(),
+            max_queue_size: 100,
+            backend_settings: BackendSettings::Proxy(ProxySettings {
+                listen_addrs: vec!["127.0.0.1:0".parse().unwrap()],
+                max_connections_per_ip: 3,
+                drain_timeout_secs: 0,
+                ..Default::default()
+            }),
+        };
+        let handle = backend.start(config).await.unwrap();
+        let proxy_addr = backend.listen_addr().unwrap();
+        
+        let mut held = Vec::new();
+        for _ in 0..3 {
+            let mut client = TcpStream::connect(proxy_addr).await.unwrap();
+            client.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+            let mut method = [0u8; 2];
+            client.read_exact(&mut method).await.unwrap();
+            assert_eq!(method, [0x05, 0x00]);
+            held.push(client);
+        }
+        
+        let counts = handle.connection_counts();
+        assert_eq!(counts.total, 3);
+        assert_eq!(counts.per_ip.get(&"127.0.0.1".parse::<IpAddr>().unwrap()), Some(&3));
+        
+        let (_rejected, reply) = socks5_connect(proxy_addr, "127.0.0.1:9".parse().unwrap()).await;
+        assert_eq!(reply[1], 0x01);
+        
+        drop(held);
+        backend.stop().await.unwrap();
     }
 }
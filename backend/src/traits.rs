@@ -1,13 +1,19 @@
-use std::net::SocketAddr;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 
 use async_trait::async_trait;
 use bytes::BytesMut;
 use tokio::sync::mpsc;
 
-use engine::{Config, FlowKey, Pipeline, Stats};
+use engine::{BypassConfig, Config, DohResolver, FlowKey, Pipeline, Stats, StatsHistory};
+use engine::stats::{HostStatsEntry, StatsSnapshot};
+use engine::config::{BackendKind, Rule};
 
 use crate::error::Result;
+use crate::proxy::{ConnectionTracker, ProxyBackend};
+use crate::transparent::{BypassBackend, BypassProxy, ProxyConfig};
+use crate::tun::TunBackend;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PacketDirection {
@@ -69,6 +75,55 @@ impl Default for BackendConfig {
 pub enum BackendSettings {
     Tun(TunSettings),
     Proxy(ProxySettings),
+    Bypass(Box<ProxyConfig>),
+}
+
+impl BackendSettings {
+    pub fn from_config(config: &engine::config::BackendSection) -> Self {
+        let proxy = |proxy_type| {
+            BackendSettings::Proxy(ProxySettings {
+                listen_addrs: config.listen.clone(),
+                proxy_type,
+                max_connections: config.max_connections,
+                timeout_secs: config.timeout_secs,
+                bypass: config.bypass.clone(),
+                ..Default::default()
+            })
+        };
+        
+        match config.kind {
+            BackendKind::Socks5 => proxy(ProxyType::Socks5),
+            BackendKind::HttpConnect => proxy(ProxyType::HttpConnect),
+            BackendKind::BypassHttp => BackendSettings::Bypass(Box::new(ProxyConfig {
+                listen_addrs: config.listen.clone(),
+                bypass: config.bypass.clone(),
+                idle_timeout: std::time::Duration::from_secs(config.timeout_secs),
+                ..Default::default()
+            })),
+            BackendKind::Tun => BackendSettings::Tun(TunSettings {
+                device_name: config.tun.device_name.clone(),
+                mtu: config.tun.mtu,
+                address: config.tun.address.clone(),
+                netmask: config.tun.netmask.clone(),
+            }),
+        }
+    }
+    
+    pub fn backend(&self) -> Box<dyn Backend> {
+        match self {
+            BackendSettings::Tun(_) => Box::new(TunBackend::new()),
+            BackendSettings::Proxy(_) => Box::new(ProxyBackend::new()),
+            BackendSettings::Bypass(_) => Box::new(BypassBackend::new()),
+        }
+    }
+    
+    pub fn is_supported(&self) -> bool {
+        match self {
+            BackendSettings::Tun(_) => TunBackend::is_supported(),
+            BackendSettings::Proxy(_) => ProxyBackend::is_supported(),
+            BackendSettings::Bypass(_) => BypassBackend::is_supported(),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -92,19 +147,29 @@ impl Default for TunSettings {
 
 #[derive(Debug, Clone)]
 pub struct ProxySettings {
-    pub listen_addr: SocketAddr,    
+    pub listen_addrs: Vec<SocketAddr>,
     pub proxy_type: ProxyType,    
     pub max_connections: usize,    
+    pub max_connections_per_ip: usize,
     pub timeout_secs: u64,
+    pub drain_timeout_secs: u64,
+    pub allow_socks4: bool,
+    pub bind_addr: Option<IpAddr>,
+    pub bypass: BypassConfig,
 }
 
 impl Default for ProxySettings {
     fn default() -> Self {
         Self {
-            listen_addr: "127.0.0.1:1080".parse().unwrap(),
+            listen_addrs: vec!["127.0.0.1:1080".parse().unwrap()],
             proxy_type: ProxyType::Socks5,
             max_connections: 1000,
+            max_connections_per_ip: 0,
             timeout_secs: 300,
+            drain_timeout_secs: 5,
+            allow_socks4: false,
+            bind_addr: None,
+            bypass: BypassConfig::default(),
         }
     }
 }
@@ -118,7 +183,18 @@ pub enum ProxyType {
 pub struct BackendHandle {
     pub shutdown_tx: mpsc::Sender<()>,
     pub stats: Arc<Stats>,
+    pub history: Arc<StatsHistory>,
     pub pipeline: Arc<Pipeline>,
+    pub connections: Option<Arc<ConnectionTracker>>,
+    pub listen_addrs: Vec<SocketAddr>,
+    pub dns: Option<Arc<DohResolver>>,
+    pub proxy: Option<Arc<BypassProxy>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionCounts {
+    pub total: u64,
+    pub per_ip: HashMap<IpAddr, u64>,
 }
 
 impl BackendHandle {
@@ -130,11 +206,60 @@ impl BackendHandle {
     }
 
     pub fn stats(&self) -> &Arc<Stats> {
+        if let Some(ref proxy) = self.proxy {
+            proxy.stats().export_to(&self.stats);
+        }
         &self.stats
     }
+    
+    /// A snapshot with the 1- and 5-minute rates filled in from the history.
+    pub fn stats_snapshot(&self) -> StatsSnapshot {
+        let mut snapshot = self.stats().snapshot();
+        self.history.fill_rates(&mut snapshot);
+        snapshot
+    }
+    
+    pub fn reset_stats(&self) {
+        self.stats.reset();
+        self.history.clear();
+    }
+
+    pub fn top_hosts(&self, n: usize) -> Vec<HostStatsEntry> {
+        match self.proxy {
+            Some(ref proxy) => proxy.stats().hosts.top(n),
+            None => self.stats.hosts.top(n),
+        }
+    }
+    
+    pub fn connection_counts(&self) -> ConnectionCounts {
+        match self.connections {
+            Some(ref tracker) => ConnectionCounts {
+                total: tracker.active(),
+                per_ip: tracker.per_ip(),
+            },
+            None => ConnectionCounts::default(),
+        }
+    }
+
+    pub fn listen_addrs(&self) -> &[SocketAddr] {
+        &self.listen_addrs
+    }
+
+    pub fn dns(&self) -> Option<&Arc<DohResolver>> {
+        self.dns.as_ref()
+    }
 
     pub fn reload_config(&self, config: Config) -> Result<()> {
+        let backend = config.backend.clone();
         self.pipeline.reload_config(config)?;
+        if let Some(ref proxy) = self.proxy {
+            proxy.reload(backend.bypass, std::time::Duration::from_secs(backend.timeout_secs));
+        }
+        Ok(())
+    }
+
+    pub fn update_rules(&self, rules: Vec<Rule>) -> Result<()> {
+        self.pipeline.update_rules(rules)?;
         Ok(())
     }
 }
@@ -177,4 +302,37 @@ mod tests {
         assert_eq!(proxy.proxy_type, ProxyType::Socks5);
         assert_eq!(proxy.max_connections, 1000);
     }
+    
+    #[test]
+    fn test_settings_from_config() {
+        let mut config = engine::config::BackendSection {
+            kind: BackendKind::HttpConnect,
+            max_connections: 50,
+            timeout_secs: 60,
+            bypass: BypassConfig::superonline(),
+            ..Default::default()
+        };
+        match BackendSettings::from_config(&config) {
+            BackendSettings::Proxy(settings) => {
+                assert_eq!(settings.proxy_type, ProxyType::HttpConnect);
+                assert_eq!(settings.listen_addrs, config.listen);
+                assert_eq!((settings.max_connections, settings.timeout_secs), (50, 60));
+                assert_eq!(settings.bypass.max_segment_size, 15);
+            }
+            other => panic!("unexpected settings {:?}", other),
+        }
+        
+        config.kind = BackendKind::BypassHttp;
+        let BackendSettings::Bypass(proxy_config) = BackendSettings::from_config(&config) else {
+            panic!("bypass_http did not map to a bypass proxy");
+        };
+        assert_eq!(proxy_config.idle_timeout, std::time::Duration::from_secs(60));
+        assert_eq!(proxy_config.bypass.max_segment_size, 15);
+        
+        config.kind = BackendKind::Tun;
+        config.tun.mtu = 1400;
+        let settings = BackendSettings::from_config(&config);
+        assert!(matches!(settings, BackendSettings::Tun(ref tun) if tun.mtu == 1400));
+        assert_eq!(settings.backend().name(), "tun");
+    }
 }
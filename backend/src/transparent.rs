@@ -1,18 +1,46 @@
+use std::collections::{HashMap, HashSet};
 use std::io::{self, ErrorKind};
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
 
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::mpsc;
+use async_trait::async_trait;
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinSet;
 use tokio::time::sleep;
 use tracing::{debug, error, info, warn};
 
-use engine::{BypassConfig, BypassEngine, DetectedProtocol, DohResolver};
+use engine::{host_matches, BypassConfig, BypassEngine, DetectedProtocol, DohProvider, DohResolver, FamilyPreference, FlowKey, HostStats, IncomingVerdict, LogRateLimiter, PacketMeta, Pipeline, Stats, StatsHistory};
+use engine::config::Protocol;
+use engine::tls::TLS_HANDSHAKE;
 
-#[derive(Debug, Default)]
+use crate::access_log::{AccessLog, CloseReason, ConnectionRecord};
+use crate::buffer_pool::BufferPool;
+use crate::adaptive::StrategyTable;
+use crate::desync;
+use crate::dns_proxy::DnsProxy;
+use crate::error::BackendError;
+use crate::traits::{Backend, BackendConfig, BackendHandle, BackendSettings};
+use crate::proxy::{accept_any, bind_listeners, connect_outbound, connect_racing, lookup_all, read_until_idle};
+
+const TLS_RECORD_HEADER_LEN: usize = 5;
+const MAX_CLIENT_HELLO_SIZE: usize = TLS_RECORD_HEADER_LEN + 16 * 1024;
+const CLIENT_HELLO_READ_TIMEOUT: Duration = Duration::from_millis(500);
+const MAX_REQUEST_HEAD_SIZE: usize = 16 * 1024;
+const REQUEST_HEAD_TIMEOUT: Duration = Duration::from_secs(10);
+const HTTP_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_FINGERPRINTS: usize = 4096;
+const PROXY_AUTH_REQUIRED: &[u8] = b"HTTP/1.1 407 Proxy Authentication Required\r\nProxy-Authenticate: Basic realm=\"turkeydpi\"\r\nContent-Length: 0\r\n\r\n";
+
+static FAKE_UNSUPPORTED_LOGGED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Default)]
 pub struct ProxyStats {
     pub connections_total: AtomicU64,
     pub connections_active: AtomicU64,
@@ -21,8 +49,88 @@ pub struct ProxyStats {
     pub tls_connections: AtomicU64,
     pub http_connections: AtomicU64,
     pub bypass_applied: AtomicU64,
+    pub fakes_sent: AtomicU64,
+    pub bypass_skipped: AtomicU64,
+    pub blocked_detected: AtomicU64,
+    pub bypass_success: AtomicU64,
+    pub direct_connections: AtomicU64,
     pub dns_queries: AtomicU64,
     pub errors: AtomicU64,
+    pub dns_failures: AtomicU64,
+    pub connect_timeouts: AtomicU64,
+    pub connect_refused: AtomicU64,
+    pub relay_errors: AtomicU64,
+    pub tls_blocked_suspected: AtomicU64,
+    pub auth_failures: AtomicU64,
+    pub connect_fallbacks: AtomicU64,
+    pub strategies: StrategyTable,
+    pub hosts: HostStats,
+    pub(crate) fingerprints: Mutex<HashSet<String>>,
+    pub(crate) dns: OnceLock<Arc<DohResolver>>,
+    pub(crate) log_limiter: LogRateLimiter,
+    pub(crate) buffers: BufferPool,
+    pub(crate) paused: Arc<AtomicBool>,
+    pub(crate) pipeline: OnceLock<Arc<Pipeline>>,
+    exported: Mutex<ProxyStatsSnapshot>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProxyStatsSnapshot {
+    pub connections_total: u64,
+    pub connections_active: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub tls_connections: u64,
+    pub http_connections: u64,
+    pub bypass_applied: u64,
+    pub fakes_sent: u64,
+    pub bypass_skipped: u64,
+    pub blocked_detected: u64,
+    pub bypass_success: u64,
+    pub direct_connections: u64,
+    pub dns_queries: u64,
+    pub errors: u64,
+    pub dns_failures: u64,
+    pub connect_timeouts: u64,
+    pub connect_refused: u64,
+    pub relay_errors: u64,
+    pub tls_blocked_suspected: u64,
+    pub auth_failures: u64,
+    pub connect_fallbacks: u64,
+    pub buffer_pool_hits: u64,
+    pub buffer_pool_misses: u64,
+    pub distinct_fingerprints: u64,
+}
+
+impl ProxyStatsSnapshot {
+    pub fn delta(&self, previous: &Self) -> Self {
+        Self {
+            connections_total: self.connections_total.saturating_sub(previous.connections_total),
+            connections_active: self.connections_active,
+            bytes_sent: self.bytes_sent.saturating_sub(previous.bytes_sent),
+            bytes_received: self.bytes_received.saturating_sub(previous.bytes_received),
+            tls_connections: self.tls_connections.saturating_sub(previous.tls_connections),
+            http_connections: self.http_connections.saturating_sub(previous.http_connections),
+            bypass_applied: self.bypass_applied.saturating_sub(previous.bypass_applied),
+            fakes_sent: self.fakes_sent.saturating_sub(previous.fakes_sent),
+            bypass_skipped: self.bypass_skipped.saturating_sub(previous.bypass_skipped),
+            blocked_detected: self.blocked_detected.saturating_sub(previous.blocked_detected),
+            bypass_success: self.bypass_success.saturating_sub(previous.bypass_success),
+            direct_connections: self.direct_connections.saturating_sub(previous.direct_connections),
+            dns_queries: self.dns_queries.saturating_sub(previous.dns_queries),
+            errors: self.errors.saturating_sub(previous.errors),
+            dns_failures: self.dns_failures.saturating_sub(previous.dns_failures),
+            connect_timeouts: self.connect_timeouts.saturating_sub(previous.connect_timeouts),
+            connect_refused: self.connect_refused.saturating_sub(previous.connect_refused),
+            relay_errors: self.relay_errors.saturating_sub(previous.relay_errors),
+            tls_blocked_suspected: self.tls_blocked_suspected.saturating_sub(previous.tls_blocked_suspected),
+            auth_failures: self.auth_failures.saturating_sub(previous.auth_failures),
+            connect_fallbacks: self.connect_fallbacks.saturating_sub(previous.connect_fallbacks),
+            buffer_pool_hits: self.buffer_pool_hits.saturating_sub(previous.buffer_pool_hits),
+            buffer_pool_misses: self.buffer_pool_misses.saturating_sub(previous.buffer_pool_misses),
+            distinct_fingerprints: self.distinct_fingerprints,
+        }
+    }
 }
 
 impl ProxyStats {
@@ -30,117 +138,487 @@ impl ProxyStats {
         Arc::new(Self::default())
     }
     
+    pub fn snapshot(&self) -> ProxyStatsSnapshot {
+        ProxyStatsSnapshot {
+            connections_total: self.connections_total.load(Ordering::Relaxed),
+            connections_active: self.connections_active.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            tls_connections: self.tls_connections.load(Ordering::Relaxed),
+            http_connections: self.http_connections.load(Ordering::Relaxed),
+            bypass_applied: self.bypass_applied.load(Ordering::Relaxed),
+            fakes_sent: self.fakes_sent.load(Ordering::Relaxed),
+            bypass_skipped: self.bypass_skipped.load(Ordering::Relaxed),
+            blocked_detected: self.blocked_detected.load(Ordering::Relaxed),
+            bypass_success: self.bypass_success.load(Ordering::Relaxed),
+            direct_connections: self.direct_connections.load(Ordering::Relaxed),
+            dns_queries: self.dns_queries.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            dns_failures: self.dns_failures.load(Ordering::Relaxed),
+            connect_timeouts: self.connect_timeouts.load(Ordering::Relaxed),
+            connect_refused: self.connect_refused.load(Ordering::Relaxed),
+            relay_errors: self.relay_errors.load(Ordering::Relaxed),
+            tls_blocked_suspected: self.tls_blocked_suspected.load(Ordering::Relaxed),
+            auth_failures: self.auth_failures.load(Ordering::Relaxed),
+            connect_fallbacks: self.connect_fallbacks.load(Ordering::Relaxed),
+            buffer_pool_hits: self.buffers.hits(),
+            buffer_pool_misses: self.buffers.misses(),
+            distinct_fingerprints: self.fingerprints.lock().len() as u64,
+        }
+    }
+    
+    pub fn export_to(&self, stats: &Stats) {
+        let mut exported = self.exported.lock();
+        let current = self.snapshot();
+        let delta = current.delta(&exported);
+        *exported = current;
+        drop(exported);
+        
+        stats.bytes_out.fetch_add(delta.bytes_sent, Ordering::Relaxed);
+        stats.bytes_in.fetch_add(delta.bytes_received, Ordering::Relaxed);
+        stats.packets_transformed.fetch_add(delta.bypass_applied, Ordering::Relaxed);
+        stats.decoys_sent.fetch_add(delta.fakes_sent, Ordering::Relaxed);
+        stats.connect_fallbacks.fetch_add(delta.connect_fallbacks, Ordering::Relaxed);
+        stats.dns_queries.fetch_add(delta.dns_queries, Ordering::Relaxed);
+        stats.connection_errors.fetch_add(delta.errors, Ordering::Relaxed);
+        stats.dns_failures.fetch_add(delta.dns_failures, Ordering::Relaxed);
+        stats.connect_timeouts.fetch_add(delta.connect_timeouts, Ordering::Relaxed);
+        stats.connect_refused.fetch_add(delta.connect_refused, Ordering::Relaxed);
+        stats.relay_errors.fetch_add(delta.relay_errors, Ordering::Relaxed);
+        stats.tls_blocked_suspected.fetch_add(delta.tls_blocked_suspected, Ordering::Relaxed);
+    }
+    
+    pub(crate) fn record_fingerprint(&self, ja3: String) {
+        // Past the cap, distinct_fingerprints stops counting new ones.
+        let mut fingerprints = self.fingerprints.lock();
+        if fingerprints.len() < MAX_FINGERPRINTS {
+            fingerprints.insert(ja3);
+        }
+    }
+    
+    fn record_connect_error(&self, err: &io::Error) {
+        match err.kind() {
+            ErrorKind::TimedOut => self.connect_timeouts.fetch_add(1, Ordering::Relaxed),
+            ErrorKind::ConnectionRefused => self.connect_refused.fetch_add(1, Ordering::Relaxed),
+            _ => 0,
+        };
+    }
+    
+    fn dns_cache_line(&self) -> String {
+        let cache = self.dns.get().map(|dns| dns.cache_stats()).unwrap_or_default();
+        format!("DNS cache: {} hits, {} misses, {} negative hits, {:.1} ms avg lookup",
+                cache.hits, cache.misses, cache.negative_hits, cache.average_lookup_ms())
+    }
+    
+    fn dns_transport_line(&self) -> String {
+        let transports = self.dns.get().map(|dns| dns.transport_stats()).unwrap_or_default();
+        format!("DNS transports: DoH {} ok / {} failed, DoT {} ok / {} failed",
+                transports.doh_ok, transports.doh_failed, transports.dot_ok, transports.dot_failed)
+    }
+    
+    pub fn summary_lines(&self) -> Vec<String> {
+        let snapshot = self.snapshot();
+        vec![
+            format!("Connections: {} total, {} active", snapshot.connections_total, snapshot.connections_active),
+            format!("TLS/HTTPS: {} ({} distinct JA3 fingerprints)", snapshot.tls_connections, snapshot.distinct_fingerprints),
+            format!("HTTP: {}", snapshot.http_connections),
+            format!("Bypass applied: {}", snapshot.bypass_applied),
+            format!("Fake packets: {}", snapshot.fakes_sent),
+            format!("Bypass skipped (excluded): {}", snapshot.bypass_skipped),
+            format!("Direct connections: {}", snapshot.direct_connections),
+            format!("Bypass succeeded: {}, blocks detected: {} ({} suspected on TLS)",
+                    snapshot.bypass_success, snapshot.blocked_detected, snapshot.tls_blocked_suspected),
+            format!("DoH DNS queries: {}", snapshot.dns_queries),
+            self.dns_cache_line(),
+            self.dns_transport_line(),
+            format!("Connect fallbacks: {}", snapshot.connect_fallbacks),
+            format!("Buffer pool: {} reused, {} allocated", snapshot.buffer_pool_hits, snapshot.buffer_pool_misses),
+            format!("Data: {} KB sent, {} KB received", snapshot.bytes_sent / 1024, snapshot.bytes_received / 1024),
+            format!("Errors: {} (DNS {}, connect timeouts {}, connect refused {}, relay {})",
+                    snapshot.errors, snapshot.dns_failures, snapshot.connect_timeouts,
+                    snapshot.connect_refused, snapshot.relay_errors),
+            format!("Auth failures: {}", snapshot.auth_failures),
+        ]
+    }
+    
+    fn learned_strategy_lines(&self) -> Vec<String> {
+        self.strategies.snapshot()
+            .into_iter()
+            .map(|(host, state)| format!("{} -> {} ({} failures{})",
+                                         host,
+                                         self.strategies.strategy_name(state.level),
+                                         state.failures,
+                                         if state.confirmed { ", confirmed" } else { "" }))
+            .collect()
+    }
+    
+    fn top_host_lines(&self) -> Vec<String> {
+        self.hosts.top(10)
+            .into_iter()
+            .map(|entry| format!("{:<40} {:>6} {:>10} {:>10} {:>6} {:>6}",
+                                 entry.host,
+                                 entry.counters.connections,
+                                 entry.counters.bytes_up / 1024,
+                                 entry.counters.bytes_down / 1024,
+                                 entry.counters.bypass_applied,
+                                 entry.counters.failures))
+            .collect()
+    }
+    
     pub fn print_summary(&self) {
         println!("\n📊 Statistics:");
-        println!("   Connections: {} total, {} active", 
-                 self.connections_total.load(Ordering::Relaxed),
-                 self.connections_active.load(Ordering::Relaxed));
-        println!("   TLS/HTTPS: {}", self.tls_connections.load(Ordering::Relaxed));
-        println!("   HTTP: {}", self.http_connections.load(Ordering::Relaxed));
-        println!("   Bypass applied: {}", self.bypass_applied.load(Ordering::Relaxed));
-        println!("   DoH DNS queries: {}", self.dns_queries.load(Ordering::Relaxed));
-        println!("   Data: {} KB sent, {} KB received",
-                 self.bytes_sent.load(Ordering::Relaxed) / 1024,
-                 self.bytes_received.load(Ordering::Relaxed) / 1024);
-        println!("   Errors: {}", self.errors.load(Ordering::Relaxed));
+        for line in self.summary_lines() {
+            println!("   {}", line);
+        }
+        
+        let hosts = self.top_host_lines();
+        if !hosts.is_empty() {
+            println!("   Top hosts:");
+            println!("      {:<40} {:>6} {:>10} {:>10} {:>6} {:>6}", "HOST", "CONNS", "KB UP", "KB DOWN", "BYPASS", "FAILED");
+            for line in hosts {
+                println!("      {}", line);
+            }
+        }
+        
+        let learned = self.learned_strategy_lines();
+        if !learned.is_empty() {
+            println!("   Learned strategies:");
+            for line in learned {
+                println!("      {}", line);
+            }
+        }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct ProxyConfig {
-    pub listen_addr: SocketAddr,    
+    pub listen_addrs: Vec<SocketAddr>,
     pub bypass: BypassConfig,    
     pub connect_timeout: Duration,    
     pub buffer_size: usize,    
     pub verbose: bool,
+    pub adaptive: bool,
+    pub adaptive_window: Duration,
+    pub auth: Option<(String, String)>,
+    pub direct_hosts: Vec<String>,
+    pub only_hosts: Vec<String>,
+    pub upstream_proxy: Option<SocketAddr>,
+    pub upstream_auth: Option<(String, String)>,
+    pub idle_timeout: Duration,
+    pub bind_addr: Option<IpAddr>,
+    pub access_log: Option<PathBuf>,
+    pub access_log_max_bytes: u64,
+    pub drain_timeout: Duration,
+    pub install_signal_handler: bool,
+    pub stats_interval: Option<Duration>,
+    pub print_stats_summary: bool,
+    pub doh_providers: Vec<DohProvider>,
+    pub family_preference: FamilyPreference,
+    pub hosts_file: Option<PathBuf>,
+    pub dns_listen: Option<SocketAddr>,
+    pub fragment_doh: bool,
+    pub log_rate_limit: u32,
 }
 
 impl Default for ProxyConfig {
     fn default() -> Self {
         Self {
-            listen_addr: "127.0.0.1:8844".parse().unwrap(),
+            listen_addrs: vec!["127.0.0.1:8844".parse().unwrap()],
             bypass: BypassConfig::default(),
             connect_timeout: Duration::from_secs(30),
             buffer_size: 65536,
             verbose: false,
+            adaptive: false,
+            adaptive_window: Duration::from_secs(3),
+            auth: None,
+            direct_hosts: Vec::new(),
+            only_hosts: Vec::new(),
+            upstream_proxy: None,
+            upstream_auth: None,
+            idle_timeout: Duration::from_secs(300),
+            bind_addr: None,
+            access_log: None,
+            access_log_max_bytes: 10 * 1024 * 1024,
+            drain_timeout: Duration::from_secs(5),
+            install_signal_handler: true,
+            stats_interval: None,
+            print_stats_summary: false,
+            doh_providers: DohProvider::defaults(),
+            family_preference: FamilyPreference::default(),
+            hosts_file: None,
+            dns_listen: None,
+            fragment_doh: false,
+            log_rate_limit: 100,
+        }
+    }
+}
+
+impl ProxyConfig {
+    pub fn bypass_host(&self, host: &str) -> bool {
+        if host_listed(&self.direct_hosts, host) {
+            return false;
+        }
+        self.only_hosts.is_empty() || host_listed(&self.only_hosts, host)
+    }
+}
+
+fn host_listed(list: &[String], host: &str) -> bool {
+    list.iter().any(|entry| {
+        if entry.starts_with('.') || entry.starts_with("*.") {
+            host_matches(entry, host)
+        } else {
+            host_matches(&format!(".{}", entry), host)
+        }
+    })
+}
+
+struct ResponseCheck {
+    engine: BypassEngine,
+    host: String,
+    bypassed: bool,
+    tls: bool,
+    reset_deadline: Instant,
+}
+
+impl ResponseCheck {
+    fn new(engine: BypassEngine, host: String, bypassed: bool, tls: bool, reset_window: Duration) -> Self {
+        Self {
+            engine,
+            host,
+            bypassed,
+            tls,
+            reset_deadline: Instant::now() + reset_window,
+        }
+    }
+    
+    fn record(&self, stats: &ProxyStats, data: &[u8]) -> IncomingVerdict {
+        if data.is_empty() && Instant::now() > self.reset_deadline {
+            return IncomingVerdict::Unknown;
         }
+        
+        let verdict = self.engine.inspect_incoming(data);
+        if verdict.is_blocked() {
+            stats.blocked_detected.fetch_add(1, Ordering::Relaxed);
+            if self.tls {
+                stats.tls_blocked_suspected.fetch_add(1, Ordering::Relaxed);
+            }
+            if stats.log_limiter.allow() {
+                warn!("🚫 {} looks blocked ({:?})", self.host, verdict);
+            }
+        } else if verdict == IncomingVerdict::Allowed && self.bypassed {
+            stats.bypass_success.fetch_add(1, Ordering::Relaxed);
+        }
+        verdict
     }
 }
 
 pub struct BypassProxy {
-    config: ProxyConfig,
+    config: RwLock<ProxyConfig>,
     stats: Arc<ProxyStats>,
     dns: Arc<DohResolver>,
     running: Arc<AtomicBool>,
-    shutdown_tx: Option<mpsc::Sender<()>>,
+    shutdown_tx: Mutex<Option<mpsc::Sender<()>>>,
+    finished: watch::Sender<bool>,
+    local_addrs: Mutex<Vec<SocketAddr>>,
+}
+
+struct ActiveConnection(Arc<ProxyStats>);
+
+impl Drop for ActiveConnection {
+    fn drop(&mut self) {
+        self.0.connections_active.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 impl BypassProxy {
     pub fn new(config: ProxyConfig) -> Self {
+        let stats = ProxyStats::new();
+        let mut dns = DohResolver::with_providers(config.doh_providers.clone())
+            .with_bind_addr(config.bind_addr)
+            .with_family_preference(config.family_preference);
+        if config.fragment_doh {
+            let engine = BypassEngine::new(config.bypass.clone()).with_pause_flag(stats.paused.clone());
+            dns = dns.with_bypass_engine(Arc::new(engine));
+        }
+        let dns = Arc::new(dns);
+        let _ = stats.dns.set(dns.clone());
+        stats.log_limiter.set_rate(config.log_rate_limit);
+        stats.buffers.set_buffer_size(config.buffer_size);
+        
         Self {
-            config,
-            stats: ProxyStats::new(),
-            dns: Arc::new(DohResolver::new()),
+            dns,
+            config: RwLock::new(config),
+            stats,
             running: Arc::new(AtomicBool::new(false)),
-            shutdown_tx: None,
+            shutdown_tx: Mutex::new(None),
+            finished: watch::channel(true).0,
+            local_addrs: Mutex::new(Vec::new()),
         }
     }
     
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.local_addrs.lock().clone()
+    }
+    
     pub fn stats(&self) -> Arc<ProxyStats> {
         self.stats.clone()
     }
     
+    /// Records connections as flows of `pipeline` and refuses those matching
+    /// one of its `Drop` rules.
+    pub fn with_pipeline(self, pipeline: Arc<Pipeline>) -> Self {
+        let _ = self.stats.pipeline.set(pipeline);
+        self
+    }
+    
+    pub fn reload(&self, bypass: BypassConfig, idle_timeout: Duration) {
+        let mut config = self.config.write();
+        config.bypass = bypass;
+        config.idle_timeout = idle_timeout;
+    }
+    
+    pub fn pause_flag(&self) -> Arc<AtomicBool> {
+        self.stats.paused.clone()
+    }
+    
     pub fn is_running(&self) -> bool {
         self.running.load(Ordering::SeqCst)
     }
     
-    pub async fn run(&mut self) -> io::Result<()> {
-        let listener = TcpListener::bind(self.config.listen_addr).await?;
-        let local_addr = listener.local_addr()?;
+    pub async fn run(&self) -> io::Result<()> {
+        let config = self.config.read().clone();
+        if !config.direct_hosts.is_empty() && !config.only_hosts.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "direct_hosts and only_hosts are mutually exclusive",
+            ));
+        }
+        
+        if config.doh_providers.is_empty() {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "at least one DoH provider is required"));
+        }
+        for provider in &config.doh_providers {
+            provider.validate().map_err(|e| io::Error::new(ErrorKind::InvalidInput, e.to_string()))?;
+        }
+        
+        if let Some(ref path) = config.hosts_file {
+            let count = self.dns.load_hosts_file(path).map_err(|e| {
+                io::Error::new(e.kind(), format!("failed to load hosts file {}: {}", path.display(), e))
+            })?;
+            info!(path = %path.display(), entries = count, "Loaded hosts file");
+        }
+        
+        let listeners = bind_listeners(&config.listen_addrs).await?;
+        let local_addrs = listeners
+            .iter()
+            .map(|listener| listener.local_addr())
+            .collect::<io::Result<Vec<_>>>()?;
+        let dns_proxy = match config.dns_listen {
+            Some(addr) => Some(DnsProxy::bind(addr, self.dns.clone(), self.stats.clone()).await?),
+            None => None,
+        };
         
         println!("╔══════════════════════════════════════════════════════════════╗");
         println!("║            TurkeyDPI -  Bypass Proxy Started                 ║");
         println!("╠══════════════════════════════════════════════════════════════╣");
-        println!("║  Listening on: {:<46} ║", format!("http://{}", local_addr));
-        println!("║  SNI Fragmentation: {:<41} ║", if self.config.bypass.fragment_sni { "ENABLED ✓" } else { "disabled" });
-        println!("║  HTTP Host Fragmentation: {:<35} ║", if self.config.bypass.fragment_http_host { "ENABLED ✓" } else { "disabled" });
+        for local_addr in &local_addrs {
+            println!("║  Listening on: {:<46} ║", format!("http://{}", local_addr));
+        }
+        println!("║  SNI Fragmentation: {:<41} ║", if config.bypass.fragment_sni { "ENABLED ✓" } else { "disabled" });
+        println!("║  HTTP Host Fragmentation: {:<35} ║", if config.bypass.fragment_http_host { "ENABLED ✓" } else { "disabled" });
         println!("║  DNS-over-HTTPS: {:<44} ║", "ENABLED ✓ (bypasses DNS blocking)");
+        if !config.direct_hosts.is_empty() {
+            println!("║  Direct (no bypass): {:<40} ║", format!("{} hosts", config.direct_hosts.len()));
+        }
+        if !config.only_hosts.is_empty() {
+            println!("║  Bypass only for: {:<43} ║", format!("{} hosts", config.only_hosts.len()));
+        }
+        if let Some(ref path) = config.access_log {
+            println!("║  Access log: {:<48} ║", path.display().to_string());
+        }
+        if let Some(ref path) = config.hosts_file {
+            println!("║  Hosts file: {:<48} ║", path.display().to_string());
+        }
+        if let Some(ref dns_proxy) = dns_proxy {
+            println!("║  DNS listener: {:<46} ║", format!("udp://{}", dns_proxy.local_addr()?));
+        }
         println!("╠══════════════════════════════════════════════════════════════╣");
-        println!("║  Configure your browser HTTP proxy to: {:<21} ║", local_addr);
+        println!("║  Configure your browser HTTP proxy to: {:<21} ║", local_addrs[0]);
+        println!("║  Or use the PAC file: {:<38} ║", format!("http://{}/proxy.pac", local_addrs[0]));
         println!("║  Press Ctrl+C to stop                                        ║");
         println!("╚══════════════════════════════════════════════════════════════╝");
         println!();
         
+        let access_log = match config.access_log {
+            Some(ref path) => Some(AccessLog::open(path.clone(), config.access_log_max_bytes).await?),
+            None => None,
+        };
+        
         let (shutdown_tx, mut shutdown_rx) = mpsc::channel::<()>(1);
-        self.shutdown_tx = Some(shutdown_tx);
+        *self.shutdown_tx.lock() = Some(shutdown_tx);
+        *self.local_addrs.lock() = local_addrs;
+        self.finished.send_replace(false);
         self.running.store(true, Ordering::SeqCst);
         
-        let config = self.config.clone();
         let stats = self.stats.clone();
         let dns = self.dns.clone();
         let running = self.running.clone();
+        let mut connections = JoinSet::new();
+        let mut next_listener = 0;
+        let reporter = config.stats_interval.map(|interval| {
+            tokio::spawn(report_stats(stats.clone(), interval, config.print_stats_summary))
+        });
+        let dns_listener = dns_proxy.map(|dns_proxy| tokio::spawn(dns_proxy.run()));
+        
+        let ctrl_c = async {
+            if config.install_signal_handler {
+                let _ = tokio::signal::ctrl_c().await;
+            } else {
+                std::future::pending::<()>().await;
+            }
+        };
+        tokio::pin!(ctrl_c);
         
         loop {
             tokio::select! {
-                result = listener.accept() => {
+                result = accept_any(&listeners, &mut next_listener) => {
                     match result {
                         Ok((stream, peer_addr)) => {
-                            let config = config.clone();
+                            while connections.try_join_next().is_some() {}
+                            
+                            let config = self.config.read().clone();
                             let stats = stats.clone();
                             let dns = dns.clone();
                             
                             stats.connections_total.fetch_add(1, Ordering::Relaxed);
                             stats.connections_active.fetch_add(1, Ordering::Relaxed);
+                            let active = ActiveConnection(stats.clone());
                             
                             let verbose = config.verbose;
-                            tokio::spawn(async move {
-                                if let Err(e) = handle_client(stream, peer_addr, config, stats.clone(), dns).await {
-                                    if verbose {
+                            let access_log = access_log.clone();
+                            connections.spawn(async move {
+                                let _active = active;
+                                let mut record = ConnectionRecord::new(peer_addr);
+                                let result = handle_client(stream, &mut record, config, stats.clone(), dns).await;
+                                if let Err(ref e) = result {
+                                    if verbose && stats.log_limiter.allow() {
                                         debug!("Connection error: {}", e);
                                     }
                                     stats.errors.fetch_add(1, Ordering::Relaxed);
                                 }
-                                stats.connections_active.fetch_sub(1, Ordering::Relaxed);
+                                close_flow(&stats, &record);
+                                if let Some(ref host) = record.host {
+                                    stats.hosts.record_connection(
+                                        host,
+                                        record.bytes_up,
+                                        record.bytes_down,
+                                        record.bypass_applied,
+                                        result.is_err(),
+                                    );
+                                }
+                                if let Some(ref access_log) = access_log {
+                                    access_log.record(record.finish(&result));
+                                }
                             });
                         }
                         Err(e) => {
@@ -152,48 +630,260 @@ impl BypassProxy {
                     info!("Shutdown signal received");
                     break;
                 }
-                _ = tokio::signal::ctrl_c() => {
+                _ = &mut ctrl_c => {
                     println!("\nShutting down...");
                     break;
                 }
             }
         }
         
+        drop(listeners);
+        if let Some(reporter) = reporter {
+            reporter.abort();
+        }
+        if let Some(dns_listener) = dns_listener {
+            dns_listener.abort();
+        }
+        
+        if !connections.is_empty() {
+            info!("Draining {} active connections", connections.len());
+            let drained = tokio::time::timeout(config.drain_timeout, async {
+                while connections.join_next().await.is_some() {}
+            }).await;
+            
+            if drained.is_err() {
+                warn!("Drain timeout reached, aborting {} connections", connections.len());
+                connections.shutdown().await;
+            }
+        }
+        
         running.store(false, Ordering::SeqCst);
+        self.shutdown_tx.lock().take();
+        self.finished.send_replace(true);
         self.stats.print_summary();
+        if let Some(dropped) = access_log.map(|log| log.dropped()).filter(|&dropped| dropped > 0) {
+            println!("   Access log entries dropped: {}", dropped);
+        }
         Ok(())
     }
     
-    pub async fn stop(&mut self) {
-        if let Some(tx) = self.shutdown_tx.take() {
+    pub async fn stop(&self) {
+        let mut finished = self.finished.subscribe();
+        let tx = self.shutdown_tx.lock().clone();
+        if let Some(tx) = tx {
             let _ = tx.send(()).await;
         }
+        let _ = finished.wait_for(|finished| *finished).await;
+    }
+    
+    /// Resolves once `run` has bound its listeners.
+    async fn started(&self) {
+        let mut finished = self.finished.subscribe();
+        let _ = finished.wait_for(|finished| !*finished).await;
+    }
+}
+
+/// Runs a [`BypassProxy`] behind the [`Backend`] interface. The handle's
+/// pipeline tracks the proxied connections as flows and decides which of them
+/// rules drop; the bypass itself stays with the proxy.
+#[derive(Default)]
+pub struct BypassBackend {
+    proxy: Option<Arc<BypassProxy>>,
+}
+
+impl BypassBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    pub fn proxy_stats(&self) -> Option<Arc<ProxyStats>> {
+        self.proxy.as_ref().map(|proxy| proxy.stats())
+    }
+}
+
+#[async_trait]
+impl Backend for BypassBackend {
+    fn name(&self) -> &'static str {
+        "bypass"
+    }
+    
+    async fn start(&mut self, config: BackendConfig) -> crate::error::Result<BackendHandle> {
+        if self.is_running() {
+            return Err(BackendError::AlreadyRunning);
+        }
+        
+        let mut proxy_config = match config.backend_settings {
+            BackendSettings::Bypass(proxy_config) => *proxy_config,
+            _ => return Err(BackendError::NotSupported(
+                "BypassBackend requires a ProxyConfig".to_string()
+            )),
+        };
+        // Signals and periodic reports are left to whoever owns the backend.
+        proxy_config.install_signal_handler = false;
+        proxy_config.stats_interval = None;
+        proxy_config.log_rate_limit = config.engine_config.limits.log_rate_limit;
+        
+        let proxy = BypassProxy::new(proxy_config);
+        let cleanup_every = Duration::from_secs(config.engine_config.limits.cleanup_interval_secs);
+        let stats = Arc::new(Stats::new());
+        let pipeline = Arc::new(
+            Pipeline::new(config.engine_config, stats.clone())
+                .map_err(BackendError::Engine)?
+                .with_pause_flag(proxy.pause_flag())
+        );
+        let proxy = Arc::new(proxy.with_pipeline(pipeline.clone()));
+        
+        let mut task = tokio::spawn({
+            let proxy = proxy.clone();
+            async move { proxy.run().await }
+        });
+        tokio::select! {
+            result = &mut task => {
+                result.map_err(io::Error::from)??;
+                return Err(BackendError::NotRunning);
+            }
+            _ = proxy.started() => {}
+        }
+        
+        let (shutdown_tx, mut shutdown_rx) = mpsc::channel::<()>(1);
+        let history = Arc::new(StatsHistory::default());
+        tokio::spawn({
+            let proxy = proxy.clone();
+            let pipeline = pipeline.clone();
+            let stats = stats.clone();
+            let history = history.clone();
+            async move {
+                let mut cleanup_interval = tokio::time::interval(cleanup_every);
+                let mut schedule_interval = tokio::time::interval(engine::pipeline::SCHEDULE_REFRESH_INTERVAL);
+                let mut history_interval = tokio::time::interval(engine::stats::HISTORY_SAMPLE_INTERVAL);
+                loop {
+                    tokio::select! {
+                        _ = shutdown_rx.recv() => {
+                            proxy.stop().await;
+                            break;
+                        }
+                        _ = &mut task => break,
+                        _ = cleanup_interval.tick() => {
+                            pipeline.cleanup();
+                        }
+                        _ = schedule_interval.tick() => {
+                            pipeline.refresh_schedules();
+                        }
+                        _ = history_interval.tick() => {
+                            proxy.stats().export_to(&stats);
+                            history.sample(&stats);
+                        }
+                    }
+                }
+            }
+        });
+        
+        let handle = BackendHandle {
+            shutdown_tx,
+            stats,
+            history,
+            pipeline,
+            connections: None,
+            listen_addrs: proxy.local_addrs(),
+            dns: Some(proxy.dns.clone()),
+            proxy: Some(proxy.clone()),
+        };
+        self.proxy = Some(proxy);
+        Ok(handle)
+    }
+    
+    async fn stop(&mut self) -> crate::error::Result<()> {
+        match self.proxy.take() {
+            Some(proxy) if proxy.is_running() => {
+                proxy.stop().await;
+                Ok(())
+            }
+            _ => Err(BackendError::NotRunning),
+        }
+    }
+    
+    fn is_running(&self) -> bool {
+        self.proxy.as_ref().is_some_and(|proxy| proxy.is_running())
+    }
+    
+    fn is_supported() -> bool {
+        true
+    }
+}
+
+async fn report_stats(stats: Arc<ProxyStats>, every: Duration, print_summary: bool) {
+    let mut interval = tokio::time::interval(every);
+    interval.tick().await;
+    let mut previous = stats.snapshot();
+    
+    loop {
+        interval.tick().await;
+        let current = stats.snapshot();
+        let delta = current.delta(&previous);
+        previous = current;
+        
+        info!(
+            connections = delta.connections_total,
+            active = delta.connections_active,
+            bytes_sent = delta.bytes_sent,
+            bytes_received = delta.bytes_received,
+            bypass_applied = delta.bypass_applied,
+            dns_queries = delta.dns_queries,
+            errors = delta.errors,
+            "Proxy stats"
+        );
+        if print_summary {
+            stats.print_summary();
+        }
     }
 }
 
 async fn handle_client(
     mut client: TcpStream,
-    peer_addr: SocketAddr,
+    record: &mut ConnectionRecord,
     config: ProxyConfig,
     stats: Arc<ProxyStats>,
     dns: Arc<DohResolver>,
 ) -> io::Result<()> {
-    let mut buf = vec![0u8; 4096];
-    let n = client.read(&mut buf).await?;
-    if n == 0 {
-        return Ok(());
-    }
+    let (buf, header_end) = match tokio::time::timeout(REQUEST_HEAD_TIMEOUT, read_request_head(&mut client)).await {
+        Ok(Ok(Some(head))) => head,
+        Ok(Ok(None)) => return Ok(()),
+        Ok(Err(e)) if e.kind() == ErrorKind::InvalidData => {
+            client.write_all(b"HTTP/1.1 431 Request Header Fields Too Large\r\n\r\n").await?;
+            return Err(e);
+        }
+        Ok(Err(e)) => return Err(e),
+        Err(_) => {
+            client.write_all(b"HTTP/1.1 408 Request Timeout\r\n\r\n").await?;
+            return Err(io::Error::new(ErrorKind::TimedOut, "Request header timeout"));
+        }
+    };
     
-    let request = String::from_utf8_lossy(&buf[..n]);
+    let request = String::from_utf8_lossy(&buf[..header_end]);
     
+    if let Some(path) = local_request_path(&request, client.local_addr().ok(), &config.listen_addrs) {
+        return serve_local(&mut client, &request, path, &config, &stats).await;
+    }
+    
+    if let Some((ref user, ref pass)) = config.auth {
+        if !is_authorized(&request, "proxy-authorization", user, pass) {
+            stats.errors.fetch_add(1, Ordering::Relaxed);
+            stats.auth_failures.fetch_add(1, Ordering::Relaxed);
+            if stats.log_limiter.allow() {
+                warn!("Proxy authentication failed for {}", record.client);
+            }
+            client.write_all(PROXY_AUTH_REQUIRED).await?;
+            return Ok(());
+        }
+    }
     
     if request.starts_with("CONNECT ") {
-        return handle_connect(client, peer_addr, &request, &buf[..n], config, stats, dns).await;
+        return handle_connect(client, record, &request, &buf[header_end..], config, stats, dns).await;
     }
     
     
-    if let Some(target) = extract_http_target(&request) {
-        return handle_http_forward(client, peer_addr, &request, &buf[..n], target, config, stats, dns).await;
+    if extract_http_target(&request).is_some() {
+        return handle_http_forward(client, record, &buf, config, stats, dns).await;
     }
     
     
@@ -203,9 +893,9 @@ async fn handle_client(
 
 async fn handle_connect(
     mut client: TcpStream,
-    peer_addr: SocketAddr,
+    record: &mut ConnectionRecord,
     request: &str,
-    _raw_request: &[u8],
+    surplus: &[u8],
     config: ProxyConfig,
     stats: Arc<ProxyStats>,
     dns: Arc<DohResolver>,
@@ -213,41 +903,29 @@ async fn handle_connect(
     let target = extract_connect_target(request)?;
     
     if config.verbose {
-        debug!("{} -> CONNECT {}", peer_addr, target);
+        debug!("{} -> CONNECT {}", record.client, target);
     }
+    record.host = Some(target_host(&target).to_string());
     
-    let resolved_addr = match dns.resolve_host_port(&target).await {
-        Ok(addr) => {
-            stats.dns_queries.fetch_add(1, Ordering::Relaxed);
-            if config.verbose {
-                debug!("DoH resolved {} -> {}", target, addr);
-            }
-            addr
-        }
+    if let Some(parent) = config.upstream_proxy {
+        return match connect_via_parent(&mut client, parent, &target, &config).await? {
+            Some(remote) => tunnel_connect(client, remote, target, surplus, config, stats, record).await,
+            None => Err(io::Error::new(ErrorKind::ConnectionRefused, "Upstream proxy refused CONNECT")),
+        };
+    }
+    
+    let targets = match resolve_target(&target, &config, &stats, &dns).await {
+        Ok(addrs) => addrs,
         Err(e) => {
-            warn!("DoH resolution failed for {}: {}", target, e);
-            match tokio::net::lookup_host(&target).await {
-                Ok(mut addrs) => {
-                    if let Some(addr) = addrs.next() {
-                        addr
-                    } else {
-                        let msg = format!("HTTP/1.1 502 Bad Gateway\r\n\r\nDNS resolution failed: {}\r\n", e);
-                        client.write_all(msg.as_bytes()).await?;
-                        return Err(io::Error::new(ErrorKind::NotFound, "DNS resolution failed"));
-                    }
-                }
-                Err(_) => {
-                    let msg = format!("HTTP/1.1 502 Bad Gateway\r\n\r\nDNS resolution failed: {}\r\n", e);
-                    client.write_all(msg.as_bytes()).await?;
-                    return Err(io::Error::new(ErrorKind::NotFound, "DNS resolution failed"));
-                }
-            }
+            let msg = format!("HTTP/1.1 502 Bad Gateway\r\n\r\nDNS resolution failed: {}\r\n", e);
+            client.write_all(msg.as_bytes()).await?;
+            return Err(io::Error::new(ErrorKind::NotFound, "DNS resolution failed"));
         }
     };
     
-    let mut remote = match tokio::time::timeout(
+    let remote = match tokio::time::timeout(
         config.connect_timeout,
-        TcpStream::connect(resolved_addr)
+        connect_targets(&targets, &config, &stats)
     ).await {
         Ok(Ok(stream)) => stream,
         Ok(Err(e)) => {
@@ -256,25 +934,152 @@ async fn handle_connect(
             return Err(e);
         }
         Err(_) => {
+            stats.connect_timeouts.fetch_add(1, Ordering::Relaxed);
             client.write_all(b"HTTP/1.1 504 Gateway Timeout\r\n\r\n").await?;
             return Err(io::Error::new(ErrorKind::TimedOut, "Connection timeout"));
         }
     };
     
+    tunnel_connect(client, remote, target, surplus, config, stats, record).await
+}
+
+fn admit_connection(stats: &ProxyStats, record: &mut ConnectionRecord, remote: &TcpStream, host: &str, len: usize) -> bool {
+    let (Some(pipeline), Ok(dst)) = (stats.pipeline.get(), remote.peer_addr()) else {
+        return true;
+    };
+    let key = FlowKey::new(record.client.ip(), dst.ip(), record.client.port(), dst.port(), Protocol::Tcp);
+    record.flow = Some(key);
+    let meta = PacketMeta::outbound().with_hostname(Some(host.to_string())).in_stream();
+    pipeline.admit_connection(key, meta, len)
+}
+
+fn close_flow(stats: &ProxyStats, record: &ConnectionRecord) {
+    let (Some(pipeline), Some(key)) = (stats.pipeline.get(), record.flow) else {
+        return;
+    };
+    let mut flow = pipeline.flow_cache().get_or_create(key);
+    flow.update((record.bytes_up + record.bytes_down).saturating_sub(flow.byte_count) as usize);
+    pipeline.flow_cache().update(flow);
+}
+
+async fn resolve_target(
+    target: &str,
+    config: &ProxyConfig,
+    stats: &ProxyStats,
+    dns: &DohResolver,
+) -> io::Result<Vec<SocketAddr>> {
+    match dns.resolve_host_port_all(target).await {
+        Ok(addrs) => {
+            stats.dns_queries.fetch_add(1, Ordering::Relaxed);
+            if config.verbose {
+                debug!("DoH resolved {} -> {:?}", target, addrs);
+            }
+            Ok(addrs)
+        }
+        Err(e) => {
+            if stats.log_limiter.allow() {
+                warn!("DoH resolution failed for {}: {}", target, e);
+            }
+            let addrs = lookup_all(target).await.ok_or(e);
+            if addrs.is_err() {
+                stats.dns_failures.fetch_add(1, Ordering::Relaxed);
+            }
+            addrs
+        }
+    }
+}
+
+async fn connect_targets(
+    targets: &[SocketAddr],
+    config: &ProxyConfig,
+    stats: &ProxyStats,
+) -> io::Result<TcpStream> {
+    let clamp_mss = config.bypass.clamp_mss.filter(|_| !stats.paused.load(Ordering::Relaxed));
+    let (stream, addr, fallback) = connect_racing(targets, config.bind_addr, clamp_mss)
+        .await
+        .map_err(|e| {
+            let e = io::Error::from(e);
+            stats.record_connect_error(&e);
+            e
+        })?;
+    if fallback {
+        stats.connect_fallbacks.fetch_add(1, Ordering::Relaxed);
+        if config.verbose {
+            debug!("Connected to {} after falling back from first address", addr);
+        }
+    }
+    Ok(stream)
+}
+
+async fn tunnel_connect(
+    mut client: TcpStream,
+    mut remote: TcpStream,
+    target: String,
+    surplus: &[u8],
+    config: ProxyConfig,
+    stats: Arc<ProxyStats>,
+    record: &mut ConnectionRecord,
+) -> io::Result<()> {
     client.write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n").await?;
     
     let _ = client.set_nodelay(true);
     let _ = remote.set_nodelay(true);
     
-    let mut initial_buf = vec![0u8; config.buffer_size];
-    let initial_len = match client.read(&mut initial_buf).await {
-        Ok(0) => return Ok(()),
-        Ok(n) => n,
-        Err(e) => return Err(e),
-    };
+    let initial_data = read_client_hello(&mut surplus.chain(&mut client), config.buffer_size).await?;
+    if initial_data.is_empty() {
+        return Ok(());
+    }
+    
+    record.bytes_up += initial_data.len() as u64;
+    
+    let hello = engine::parse_client_hello(&initial_data);
+    if let Some(ref info) = hello {
+        let ja3 = info.ja3();
+        debug!("🔒 {} JA3: {}", info.sni_hostname.as_deref().unwrap_or(&target), ja3);
+        stats.record_fingerprint(ja3);
+    }
+    record.protocol = Some(if hello.is_some() { DetectedProtocol::TlsClientHello } else { DetectedProtocol::Unknown });
+    let sni = hello.and_then(|info| info.sni_hostname);
+    let host = sni.clone().unwrap_or_else(|| target_host(&target).to_string());
+    record.host = Some(host.clone());
+    if !admit_connection(&stats, record, &remote, &host, initial_data.len()) {
+        if config.verbose {
+            debug!("{} dropped by rule", host);
+        }
+        return Ok(());
+    }
+    if !config.bypass_host(&host) {
+        stats.direct_connections.fetch_add(1, Ordering::Relaxed);
+        if config.verbose {
+            debug!("➡️  {} [direct]", host);
+        }
+        remote.write_all(&initial_data).await?;
+        stats.bytes_sent.fetch_add(initial_data.len() as u64, Ordering::Relaxed);
+        relay_bidirectional(client, remote, stats, config.idle_timeout, None, true, record).await;
+        return Ok(());
+    }
     
-    let engine = BypassEngine::new(config.bypass.clone());
-    let result = engine.process_outgoing(&initial_buf[..initial_len]);
+    // A paused proxy sends data as is, which says nothing about how well a
+    // strategy works.
+    let paused = stats.paused.load(Ordering::Relaxed);
+    let adaptive_host = if config.adaptive && !paused { sni } else { None };
+    
+    let (engine, adaptive_level) = match adaptive_host {
+        Some(ref host) => {
+            let (level, mut bypass) = stats.strategies.select(host);
+            bypass.exclude_hosts = config.bypass.exclude_hosts.clone();
+            bypass.host_overrides = config.bypass.host_overrides.clone();
+            (BypassEngine::new(bypass), Some(level))
+        }
+        None => (BypassEngine::new(config.bypass.clone()), None),
+    };
+    let engine = engine.with_pause_flag(stats.paused.clone());
+    let result = engine.process_outgoing(&initial_data);
+    record.protocol = Some(result.protocol);
+    record.bypass_applied = result.modified;
+    if let Some(ref hostname) = result.hostname {
+        record.host = Some(hostname.clone());
+    }
     
     match result.protocol {
         DetectedProtocol::TlsClientHello => {
@@ -285,6 +1090,9 @@ async fn handle_connect(
                 } else if config.verbose {
                     debug!("🔒 {} [passthrough]", host);
                 }
+                if config.verbose && !result.alpn.is_empty() {
+                    debug!("🔒 {} ALPN: {}", host, result.alpn.join(", "));
+                }
             }
         }
         DetectedProtocol::HttpRequest => {
@@ -297,7 +1105,7 @@ async fn handle_connect(
                 }
             }
         }
-        DetectedProtocol::Unknown => {
+        DetectedProtocol::Quic | DetectedProtocol::Unknown => {
             if config.verbose {
                 debug!("❓ Unknown protocol to {}", target);
             }
@@ -308,10 +1116,55 @@ async fn handle_connect(
         stats.bypass_applied.fetch_add(1, Ordering::Relaxed);
     }
     
-    for (i, fragment) in result.fragments.iter().enumerate() {
+    if result.excluded {
+        stats.bypass_skipped.fetch_add(1, Ordering::Relaxed);
+        if config.verbose {
+            debug!("{} excluded from bypass", result.hostname.as_deref().unwrap_or(&target));
+        }
+    }
+    
+    let mut first_fragment = 0;
+    if let (Some(fake), Some(head)) = (&result.fake_packet, result.fragments.first()) {
+        if fake.len() < head.len() {
+            if config.verbose {
+                debug!("Fake ClientHello for {} is shorter than the first fragment, not sending it", target);
+            }
+        } else if desync::send_fake(&remote, &fake[..head.len()], head, config.bypass.fake_packet_ttl).await? {
+            stats.fakes_sent.fetch_add(1, Ordering::Relaxed);
+            stats.bytes_sent.fetch_add(head.len() as u64, Ordering::Relaxed);
+            first_fragment = 1;
+            if config.verbose {
+                debug!("Fake ClientHello sent to {} ({} bytes, TTL {})", target, head.len(), config.bypass.fake_packet_ttl);
+            }
+            if let Some(delay) = result.inter_fragment_delay {
+                sleep(delay).await;
+            }
+        } else if !FAKE_UNSUPPORTED_LOGGED.swap(true, Ordering::Relaxed) {
+            warn!("Cannot send fake packets with a custom TTL on this socket, skipping them");
+        }
+    }
+    
+    let original_ttl = remote.ttl().ok();
+    
+    for (i, fragment) in result.fragments.iter().enumerate().skip(first_fragment) {
+        let deferred = result.is_deferred(i)
+            && original_ttl.is_some()
+            && remote.set_ttl(config.bypass.fake_packet_ttl as u32).is_ok();
+        
         remote.write_all(fragment).await?;
         stats.bytes_sent.fetch_add(fragment.len() as u64, Ordering::Relaxed);
         
+        if deferred {
+            if let Some(ttl) = original_ttl {
+                remote.set_ttl(ttl)?;
+            }
+            if config.verbose {
+                debug!("Fragment {} sent with TTL {} for out-of-order delivery", i, config.bypass.fake_packet_ttl);
+            }
+        } else if result.is_deferred(i) && config.verbose {
+            debug!("Cannot set TTL on {}, sending fragment {} in order", target, i);
+        }
+        
         if i < result.fragments.len() - 1 {
             if let Some(delay) = result.inter_fragment_delay {
                 sleep(delay).await;
@@ -320,18 +1173,171 @@ async fn handle_connect(
     }
     remote.flush().await?;
     
-    relay_bidirectional(client, remote, stats, config.buffer_size).await;
+    let check = ResponseCheck::new(
+        engine,
+        result.hostname.clone().unwrap_or_else(|| target.clone()),
+        result.modified,
+        result.protocol == DetectedProtocol::TlsClientHello,
+        config.adaptive_window,
+    );
+    
+    if let (Some(host), Some(level)) = (&adaptive_host, adaptive_level) {
+        let mut buf = stats.buffers.get();
+        let first = tokio::time::timeout(config.adaptive_window, remote.read(&mut buf)).await;
+        let verdict = match first {
+            Ok(Ok(n)) => Some((check.record(&stats, &buf[..n]), n)),
+            Ok(Err(_)) => Some((check.record(&stats, &[]), 0)),
+            Err(_) => None,
+        };
+        
+        match verdict {
+            Some((verdict, n)) if !verdict.is_blocked() && n > 0 => {
+                stats.strategies.record_success(host, level);
+                client.write_all(&buf[..n]).await?;
+                stats.bytes_received.fetch_add(n as u64, Ordering::Relaxed);
+                record.bytes_down += n as u64;
+            }
+            Some(_) => {
+                let next = stats.strategies.record_failure(host, level);
+                if config.verbose {
+                    match next {
+                        Some(next) => debug!("{} failed with {}, escalating to {}",
+                                             host,
+                                             stats.strategies.strategy_name(level),
+                                             stats.strategies.strategy_name(next)),
+                        None => debug!("{} failed with {}", host, stats.strategies.strategy_name(level)),
+                    }
+                }
+                return Ok(());
+            }
+            None => {}
+        }
+        drop(buf);
+        
+        relay_bidirectional(client, remote, stats, config.idle_timeout, None, true, record).await;
+        return Ok(());
+    }
+    
+    relay_bidirectional(client, remote, stats, config.idle_timeout, Some(check), false, record).await;
     
     Ok(())
 }
 
-fn extract_connect_target(request: &str) -> io::Result<String> {
-    let first_line = request.lines().next().ok_or_else(|| {
-        io::Error::new(ErrorKind::InvalidInput, "Empty request")
-    })?;
+async fn connect_via_parent(
+    client: &mut TcpStream,
+    parent: SocketAddr,
+    target: &str,
+    config: &ProxyConfig,
+) -> io::Result<Option<TcpStream>> {
+    let mut remote = match tokio::time::timeout(config.connect_timeout, connect_outbound(parent, config.bind_addr, None)).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(e)) => {
+            let msg = format!("HTTP/1.1 502 Bad Gateway\r\n\r\nUpstream proxy: {}\r\n", e);
+            client.write_all(msg.as_bytes()).await?;
+            return Ok(None);
+        }
+        Err(_) => {
+            client.write_all(b"HTTP/1.1 504 Gateway Timeout\r\n\r\n").await?;
+            return Ok(None);
+        }
+    };
     
-    let parts: Vec<&str> = first_line.split_whitespace().collect();
-    if parts.len() < 2 {
+    let mut request = format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n", target, target);
+    if let Some((ref user, ref pass)) = config.upstream_auth {
+        request.push_str(&format!("Proxy-Authorization: {}\r\n", basic_credentials(user, pass)));
+    }
+    request.push_str("\r\n");
+    remote.write_all(request.as_bytes()).await?;
+    
+    let mut parent_conn = BufferedConn::new(remote, Vec::new(), config.connect_timeout);
+    let head = match parent_conn.read_head().await {
+        Ok(Some(head)) => head,
+        Ok(None) | Err(_) => {
+            client.write_all(b"HTTP/1.1 502 Bad Gateway\r\n\r\n").await?;
+            return Ok(None);
+        }
+    };
+    
+    let response = String::from_utf8_lossy(&head).to_string();
+    let status = response_status(&response);
+    if matches!(status, Some(status) if (200..300).contains(&status)) {
+        return Ok(Some(parent_conn.stream));
+    }
+    
+    client.write_all(&head).await?;
+    let _ = parent_conn.copy_body(response_body_framing(&response, status, false), client).await;
+    Ok(None)
+}
+
+async fn read_request_head(client: &mut TcpStream) -> io::Result<Option<(Vec<u8>, usize)>> {
+    let mut buf = Vec::with_capacity(4096);
+    let mut chunk = [0u8; 4096];
+    
+    loop {
+        let n = client.read(&mut chunk).await?;
+        if n == 0 {
+            if buf.is_empty() {
+                return Ok(None);
+            }
+            let len = buf.len();
+            return Ok(Some((buf, len)));
+        }
+        
+        let search_from = buf.len().saturating_sub(3);
+        buf.extend_from_slice(&chunk[..n]);
+        
+        if let Some(pos) = buf[search_from..].windows(4).position(|w| w == b"\r\n\r\n") {
+            let header_end = search_from + pos + 4;
+            return Ok(Some((buf, header_end)));
+        }
+        
+        if buf.len() > MAX_REQUEST_HEAD_SIZE {
+            return Err(io::Error::new(ErrorKind::InvalidData, "Request headers too large"));
+        }
+    }
+}
+
+pub(crate) async fn read_client_hello<R>(reader: &mut R, buffer_size: usize) -> io::Result<Vec<u8>>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut buf = vec![0u8; buffer_size.max(TLS_RECORD_HEADER_LEN)];
+    let n = reader.read(&mut buf).await?;
+    let mut data = buf[..n].to_vec();
+    
+    if data.first() != Some(&TLS_HANDSHAKE) {
+        return Ok(data);
+    }
+    
+    loop {
+        let wanted = if data.len() >= TLS_RECORD_HEADER_LEN {
+            TLS_RECORD_HEADER_LEN + u16::from_be_bytes([data[3], data[4]]) as usize
+        } else {
+            TLS_RECORD_HEADER_LEN
+        };
+        
+        if data.len() >= wanted.min(MAX_CLIENT_HELLO_SIZE) {
+            break;
+        }
+        
+        let to_read = (wanted.min(MAX_CLIENT_HELLO_SIZE) - data.len()).min(buf.len());
+        match tokio::time::timeout(CLIENT_HELLO_READ_TIMEOUT, reader.read(&mut buf[..to_read])).await {
+            Ok(Ok(0)) | Err(_) => break,
+            Ok(Ok(n)) => data.extend_from_slice(&buf[..n]),
+            Ok(Err(e)) => return Err(e),
+        }
+    }
+    
+    Ok(data)
+}
+
+fn extract_connect_target(request: &str) -> io::Result<String> {
+    let first_line = request.lines().next().ok_or_else(|| {
+        io::Error::new(ErrorKind::InvalidInput, "Empty request")
+    })?;
+    
+    let parts: Vec<&str> = first_line.split_whitespace().collect();
+    if parts.len() < 2 {
         return Err(io::Error::new(ErrorKind::InvalidInput, "Invalid CONNECT"));
     }
     
@@ -348,49 +1354,267 @@ async fn relay_bidirectional(
     client: TcpStream,
     remote: TcpStream,
     stats: Arc<ProxyStats>,
-    buffer_size: usize,
+    idle_timeout: Duration,
+    mut check: Option<ResponseCheck>,
+    use_fast_path: bool,
+    record: &mut ConnectionRecord,
 ) {
+    if use_fast_path && check.is_none() {
+        relay_fast(client, remote, &stats, idle_timeout, record).await;
+        return;
+    }
+    
     let (mut client_read, mut client_write) = client.into_split();
     let (mut remote_read, mut remote_write) = remote.into_split();
     
     let stats_up = stats.clone();
     let stats_down = stats.clone();
+    let last_activity = Mutex::new(tokio::time::Instant::now());
+    let last_activity = &last_activity;
     
     let client_to_remote = async move {
-        let mut buf = vec![0u8; buffer_size];
-        loop {
-            match client_read.read(&mut buf).await {
-                Ok(0) => break,
-                Ok(n) => {
-                    if remote_write.write_all(&buf[..n]).await.is_err() {
-                        break;
-                    }
-                    stats_up.bytes_sent.fetch_add(n as u64, Ordering::Relaxed);
-                }
-                Err(_) => break,
+        let mut buf = stats_up.buffers.get();
+        let mut sent = 0u64;
+        let reason = loop {
+            let n = match read_until_idle(&mut client_read, &mut buf, last_activity, idle_timeout).await {
+                Ok(0) => break CloseReason::Eof,
+                Ok(n) => n,
+                Err(e) => break close_reason(&e),
+            };
+            if remote_write.write_all(&buf[..n]).await.is_err() {
+                break CloseReason::Error;
             }
-        }
+            sent += n as u64;
+            stats_up.bytes_sent.fetch_add(n as u64, Ordering::Relaxed);
+        };
         let _ = remote_write.shutdown().await;
+        (sent, reason)
     };
     
     let remote_to_client = async move {
-        let mut buf = vec![0u8; buffer_size];
+        let mut buf = stats_down.buffers.get();
+        let mut received = 0u64;
+        let reason = loop {
+            let read = read_until_idle(&mut remote_read, &mut buf, last_activity, idle_timeout).await;
+            if let Some(check) = check.take() {
+                check.record(&stats_down, &buf[..*read.as_ref().unwrap_or(&0)]);
+            }
+            
+            let n = match read {
+                Ok(0) => break CloseReason::Eof,
+                Ok(n) => n,
+                Err(e) => break close_reason(&e),
+            };
+            if client_write.write_all(&buf[..n]).await.is_err() {
+                break CloseReason::Error;
+            }
+            received += n as u64;
+            stats_down.bytes_received.fetch_add(n as u64, Ordering::Relaxed);
+        };
+        let _ = client_write.shutdown().await;
+        (received, reason)
+    };
+    
+    let ((sent, up), (received, down)) = tokio::join!(client_to_remote, remote_to_client);
+    record.bytes_up += sent;
+    record.bytes_down += received;
+    record.close_reason = match (up, down) {
+        (CloseReason::Timeout, _) | (_, CloseReason::Timeout) => CloseReason::Timeout,
+        (CloseReason::Error, _) | (_, CloseReason::Error) => CloseReason::Error,
+        _ => CloseReason::Eof,
+    };
+    if record.close_reason == CloseReason::Error {
+        stats.relay_errors.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Relays without the per-read bookkeeping of the manual loop once nothing
+/// needs to look at the bytes. Each direction copies through a buffer from
+/// the pool; byte counters advance as data is written and a single timer
+/// closes the connection once neither side has read anything for
+/// `idle_timeout`.
+async fn relay_fast(
+    mut client: TcpStream,
+    mut remote: TcpStream,
+    stats: &ProxyStats,
+    idle_timeout: Duration,
+    record: &mut ConnectionRecord,
+) {
+    let last_activity = Mutex::new(tokio::time::Instant::now());
+    let (mut client_read, mut client_write) = client.split();
+    let (mut remote_read, mut remote_write) = remote.split();
+    let (mut sent, mut received) = (0u64, 0u64);
+    
+    let idle = async {
         loop {
-            match remote_read.read(&mut buf).await {
-                Ok(0) => break,
-                Ok(n) => {
-                    if client_write.write_all(&buf[..n]).await.is_err() {
-                        break;
-                    }
-                    stats_down.bytes_received.fetch_add(n as u64, Ordering::Relaxed);
+            let deadline = *last_activity.lock() + idle_timeout;
+            tokio::time::sleep_until(deadline).await;
+            if last_activity.lock().elapsed() >= idle_timeout {
+                debug!(idle_secs = idle_timeout.as_secs(), "Idle timeout, closing connection");
+                break;
+            }
+        }
+    };
+    
+    let result = tokio::select! {
+        result = async {
+            tokio::try_join!(
+                copy_pooled(&mut client_read, &mut remote_write, &stats.buffers, &last_activity, &stats.bytes_sent, &mut sent),
+                copy_pooled(&mut remote_read, &mut client_write, &stats.buffers, &last_activity, &stats.bytes_received, &mut received),
+            )
+        } => Some(result),
+        _ = idle => None,
+    };
+    
+    record.bytes_up += sent;
+    record.bytes_down += received;
+    record.close_reason = match result {
+        Some(Ok(_)) => CloseReason::Eof,
+        Some(Err(e)) => close_reason(&e),
+        None => CloseReason::Timeout,
+    };
+    if record.close_reason == CloseReason::Error {
+        stats.relay_errors.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+async fn copy_pooled<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    buffers: &BufferPool,
+    last_activity: &Mutex<tokio::time::Instant>,
+    counter: &AtomicU64,
+    written: &mut u64,
+) -> io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = buffers.get();
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            return writer.shutdown().await;
+        }
+        *last_activity.lock() = tokio::time::Instant::now();
+        writer.write_all(&buf[..n]).await?;
+        *written += n as u64;
+        counter.fetch_add(n as u64, Ordering::Relaxed);
+    }
+}
+
+fn close_reason(err: &io::Error) -> CloseReason {
+    if err.kind() == ErrorKind::TimedOut {
+        CloseReason::Timeout
+    } else {
+        CloseReason::Error
+    }
+}
+
+fn local_request_path<'a>(request: &'a str, local_addr: Option<SocketAddr>, listen_addrs: &[SocketAddr]) -> Option<&'a str> {
+    let mut parts = request.lines().next()?.split_whitespace();
+    let (method, target) = (parts.next()?, parts.next()?);
+    if method != "GET" || !target.starts_with('/') {
+        return None;
+    }
+    
+    let local_addr = local_addr?;
+    let host_header = find_header(request, "host")?;
+    let (host, port) = match host_header.rsplit_once(':') {
+        Some((host, port)) if !host.ends_with(':') => (host, port.parse::<u16>().ok()?),
+        _ => (host_header, 80),
+    };
+    if port != local_addr.port() {
+        return None;
+    }
+    
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let is_local = host.eq_ignore_ascii_case("localhost")
+        || host.parse::<IpAddr>().is_ok_and(|ip| ip == local_addr.ip() || listen_addrs.iter().any(|addr| addr.ip() == ip));
+    if !is_local {
+        return None;
+    }
+    
+    Some(target.split('?').next().unwrap_or(target))
+}
+
+async fn serve_local(
+    client: &mut TcpStream,
+    request: &str,
+    path: &str,
+    config: &ProxyConfig,
+    stats: &ProxyStats,
+) -> io::Result<()> {
+    let (status, content_type, body) = match path {
+        "/proxy.pac" => {
+            let proxy = find_header(request, "host").unwrap_or_default();
+            ("200 OK", "application/x-ns-proxy-autoconfig", pac_file(proxy, &config.only_hosts))
+        }
+        "/" => {
+            if let Some((ref user, ref pass)) = config.auth {
+                if !is_authorized(request, "authorization", user, pass) {
+                    stats.auth_failures.fetch_add(1, Ordering::Relaxed);
+                    client.write_all(b"HTTP/1.1 401 Unauthorized\r\nWWW-Authenticate: Basic realm=\"turkeydpi\"\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await?;
+                    return Ok(());
                 }
-                Err(_) => break,
             }
+            ("200 OK", "text/html; charset=utf-8", status_page(stats))
         }
-        let _ = client_write.shutdown().await;
+        _ => ("404 Not Found", "text/plain", "Not found\n".to_string()),
     };
     
-    tokio::join!(client_to_remote, remote_to_client);
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    client.write_all(response.as_bytes()).await?;
+    let _ = client.shutdown().await;
+    Ok(())
+}
+
+fn pac_file(proxy: &str, only_hosts: &[String]) -> String {
+    if only_hosts.is_empty() {
+        return format!("function FindProxyForURL(url, host) {{\n    return \"PROXY {}\";\n}}\n", proxy);
+    }
+    
+    let conditions: Vec<String> = only_hosts
+        .iter()
+        .map(|entry| {
+            let domain = entry.trim_start_matches("*.").trim_start_matches('.').replace('"', "");
+            format!("host == \"{}\" || dnsDomainIs(host, \".{}\")", domain, domain)
+        })
+        .collect();
+    format!(
+        "function FindProxyForURL(url, host) {{\n    if ({}) {{\n        return \"PROXY {}\";\n    }}\n    return \"DIRECT\";\n}}\n",
+        conditions.join(" ||\n        "),
+        proxy
+    )
+}
+
+fn status_page(stats: &ProxyStats) -> String {
+    let mut html = String::from("<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>TurkeyDPI</title></head><body>\n<h1>TurkeyDPI Bypass Proxy</h1>\n<ul>\n");
+    for line in stats.summary_lines() {
+        html.push_str(&format!("<li>{}</li>\n", html_escape(&line)));
+    }
+    html.push_str("</ul>\n");
+    
+    let learned = stats.learned_strategy_lines();
+    if !learned.is_empty() {
+        html.push_str("<h2>Learned strategies</h2>\n<ul>\n");
+        for line in learned {
+            html.push_str(&format!("<li>{}</li>\n", html_escape(&line)));
+        }
+        html.push_str("</ul>\n");
+    }
+    html.push_str("<p><a href=\"/proxy.pac\">proxy.pac</a></p>\n</body></html>\n");
+    html
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
 }
 
 fn extract_http_target(request: &str) -> Option<String> {
@@ -410,8 +1634,7 @@ fn extract_http_target(request: &str) -> Option<String> {
     }
     
     
-    if url.starts_with("http://") {
-        let without_scheme = &url[7..];
+    if let Some(without_scheme) = url.strip_prefix("http://") {
         let host_end = without_scheme.find('/').unwrap_or(without_scheme.len());
         let host_port = &without_scheme[..host_end];
         
@@ -438,123 +1661,378 @@ fn extract_http_target(request: &str) -> Option<String> {
     None
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn handle_http_forward(
-    mut client: TcpStream,
-    peer_addr: SocketAddr,
-    request: &str,
+    client: TcpStream,
+    record: &mut ConnectionRecord,
     raw_request: &[u8],
-    target: String,
     config: ProxyConfig,
     stats: Arc<ProxyStats>,
     dns: Arc<DohResolver>,
 ) -> io::Result<()> {
-    if config.verbose {
-        debug!("{} -> HTTP {}", peer_addr, target);
-    }
-    
-    
-    let resolved_addr = match dns.resolve_host_port(&target).await {
-        Ok(addr) => {
-            stats.dns_queries.fetch_add(1, Ordering::Relaxed);
-            addr
+    let mut client = BufferedConn::new(client, raw_request.to_vec(), HTTP_IDLE_TIMEOUT);
+    let mut upstreams: HashMap<String, (BufferedConn, Option<ResponseCheck>)> = HashMap::new();
+    record.protocol = Some(DetectedProtocol::HttpRequest);
+    
+    loop {
+        let head = match client.read_head().await {
+            Ok(Some(head)) => head,
+            Ok(None) => break,
+            Err(e) => {
+                record.close_reason = close_reason(&e);
+                break;
+            }
+        };
+        let request = String::from_utf8_lossy(&head).to_string();
+        
+        if let Some((ref user, ref pass)) = config.auth {
+            if !is_authorized(&request, "proxy-authorization", user, pass) {
+                stats.errors.fetch_add(1, Ordering::Relaxed);
+                stats.auth_failures.fetch_add(1, Ordering::Relaxed);
+                if stats.log_limiter.allow() {
+                    warn!("Proxy authentication failed for {}", record.client);
+                }
+                client.stream.write_all(PROXY_AUTH_REQUIRED).await?;
+                break;
+            }
         }
-        Err(_) => {
-            match tokio::net::lookup_host(&target).await {
-                Ok(mut addrs) => {
-                    if let Some(addr) = addrs.next() {
-                        addr
-                    } else {
-                        client.write_all(b"HTTP/1.1 502 Bad Gateway\r\n\r\n").await?;
-                        return Err(io::Error::new(ErrorKind::NotFound, "DNS resolution failed"));
-                    }
+        
+        let target = match extract_http_target(&request) {
+            Some(target) => target,
+            None => {
+                client.stream.write_all(b"HTTP/1.1 400 Bad Request\r\n\r\nUnsupported request\r\n").await?;
+                break;
+            }
+        };
+        
+        if config.verbose {
+            debug!("{} -> HTTP {}", record.client, target);
+        }
+        
+        if !upstreams.contains_key(&target) {
+            let remote = match connect_http_upstream(&mut client.stream, &target, &config, &stats, &dns).await? {
+                Some(remote) => remote,
+                None => break,
+            };
+            
+            let host = extract_host_header(&request).unwrap_or_else(|| target.clone());
+            if !admit_connection(&stats, record, &remote, target_host(&host), head.len()) {
+                if config.verbose {
+                    debug!("{} dropped by rule", host);
+                }
+                break;
+            }
+            stats.http_connections.fetch_add(1, Ordering::Relaxed);
+            if record.host.is_none() {
+                record.host = Some(target_host(&host).to_string());
+            }
+            
+            let check = if config.bypass_host(target_host(&target)) {
+                info!("🌐 {} [HTTP forwarded]", host);
+                Some(ResponseCheck::new(BypassEngine::new(config.bypass.clone()), host, false, false, config.adaptive_window))
+            } else {
+                stats.direct_connections.fetch_add(1, Ordering::Relaxed);
+                if config.verbose {
+                    debug!("➡️  {} [direct]", host);
+                }
+                None
+            };
+            upstreams.insert(target.clone(), (BufferedConn::new(remote, Vec::new(), HTTP_IDLE_TIMEOUT), check));
+        }
+        let (upstream, check) = upstreams.get_mut(&target).expect("upstream inserted above");
+        
+        let client_keep_alive = wants_keep_alive(&request);
+        let forwarded = match (config.upstream_proxy, &config.upstream_auth) {
+            (Some(_), Some((user, pass))) => {
+                let auth = format!("Proxy-Authorization: {}", basic_credentials(user, pass));
+                insert_header(&strip_proxy_headers(&head), &auth)
+            }
+            (Some(_), None) => strip_proxy_headers(&head),
+            (None, _) => strip_proxy_headers(&rewrite_http_request(&request, &head)),
+        };
+        
+        if upstream.stream.write_all(&forwarded).await.is_err() {
+            client.stream.write_all(b"HTTP/1.1 502 Bad Gateway\r\n\r\n").await?;
+            break;
+        }
+        stats.bytes_sent.fetch_add(forwarded.len() as u64, Ordering::Relaxed);
+        
+        let sent = client.copy_body(request_body_framing(&request), &mut upstream.stream).await?;
+        stats.bytes_sent.fetch_add(sent, Ordering::Relaxed);
+        record.bytes_up += forwarded.len() as u64 + sent;
+        
+        let head_only = request.starts_with("HEAD ");
+        let (framing, upstream_keep_alive) = loop {
+            let response_head = match upstream.read_head().await? {
+                Some(response_head) => response_head,
+                None => {
+                    client.stream.write_all(b"HTTP/1.1 502 Bad Gateway\r\n\r\n").await?;
+                    return Ok(());
                 }
-                Err(e) => {
-                    client.write_all(b"HTTP/1.1 502 Bad Gateway\r\n\r\n").await?;
-                    return Err(io::Error::new(ErrorKind::NotFound, e.to_string()));
+            };
+            
+            if let Some(check) = check.take() {
+                let mut first = response_head.clone();
+                first.extend_from_slice(&upstream.buf);
+                check.record(&stats, &first);
+            }
+            
+            client.stream.write_all(&response_head).await?;
+            stats.bytes_received.fetch_add(response_head.len() as u64, Ordering::Relaxed);
+            record.bytes_down += response_head.len() as u64;
+            
+            let response = String::from_utf8_lossy(&response_head).to_string();
+            match response_status(&response) {
+                Some(101) => {
+                    let (_, (upstream, _)) = upstreams.remove_entry(&target).expect("upstream exists");
+                    return relay_upgraded(client, upstream, stats, record).await;
                 }
+                Some(status) if (100..200).contains(&status) => continue,
+                status => break (response_body_framing(&response, status, head_only), wants_keep_alive(&response)),
             }
+        };
+        
+        let received = upstream.copy_body(framing, &mut client.stream).await?;
+        stats.bytes_received.fetch_add(received, Ordering::Relaxed);
+        record.bytes_down += received;
+        
+        if framing == BodyFraming::UntilClose {
+            break;
         }
-    };
+        if !upstream_keep_alive {
+            upstreams.remove(&target);
+        }
+        if !client_keep_alive {
+            break;
+        }
+    }
     
+    let _ = client.stream.shutdown().await;
+    Ok(())
+}
+
+async fn connect_http_upstream(
+    client: &mut TcpStream,
+    target: &str,
+    config: &ProxyConfig,
+    stats: &ProxyStats,
+    dns: &DohResolver,
+) -> io::Result<Option<TcpStream>> {
+    let targets = match config.upstream_proxy {
+        Some(parent) => vec![parent],
+        None => match resolve_target(target, config, stats, dns).await {
+            Ok(addrs) => addrs,
+            Err(_) => {
+                client.write_all(b"HTTP/1.1 502 Bad Gateway\r\n\r\n").await?;
+                return Ok(None);
+            }
+        },
+    };
     
-    let mut remote = match tokio::time::timeout(
-        config.connect_timeout,
-        TcpStream::connect(resolved_addr)
-    ).await {
-        Ok(Ok(stream)) => stream,
+    match tokio::time::timeout(config.connect_timeout, connect_targets(&targets, config, stats)).await {
+        Ok(Ok(stream)) => Ok(Some(stream)),
         Ok(Err(e)) => {
             let msg = format!("HTTP/1.1 502 Bad Gateway\r\n\r\n{}\r\n", e);
             client.write_all(msg.as_bytes()).await?;
-            return Err(e);
+            Ok(None)
         }
         Err(_) => {
+            stats.connect_timeouts.fetch_add(1, Ordering::Relaxed);
             client.write_all(b"HTTP/1.1 504 Gateway Timeout\r\n\r\n").await?;
-            return Err(io::Error::new(ErrorKind::TimedOut, "Connection timeout"));
+            Ok(None)
         }
-    };
-    
-    
-    let rewritten_request = rewrite_http_request(request, raw_request);
-    
-    
-    if let Some(host) = extract_host_header(request) {
-        info!("🌐 {} [HTTP forwarded]", host);
+    }
+}
+
+async fn relay_upgraded(
+    mut client: BufferedConn,
+    mut upstream: BufferedConn,
+    stats: Arc<ProxyStats>,
+    record: &mut ConnectionRecord,
+) -> io::Result<()> {
+    if !upstream.buf.is_empty() {
+        client.stream.write_all(&upstream.buf).await?;
+    }
+    if !client.buf.is_empty() {
+        upstream.stream.write_all(&client.buf).await?;
     }
     
-    stats.http_connections.fetch_add(1, Ordering::Relaxed);
-    
-    
-    remote.write_all(&rewritten_request).await?;
-    stats.bytes_sent.fetch_add(rewritten_request.len() as u64, Ordering::Relaxed);
-    
-    
-    let (mut client_read, mut client_write) = client.into_split();
-    let (mut remote_read, mut remote_write) = remote.into_split();
+    let (sent, received) = tokio::io::copy_bidirectional(&mut client.stream, &mut upstream.stream).await?;
+    stats.bytes_sent.fetch_add(sent, Ordering::Relaxed);
+    stats.bytes_received.fetch_add(received, Ordering::Relaxed);
+    record.bytes_up += sent;
+    record.bytes_down += received;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BodyFraming {
+    Empty,
+    Length(u64),
+    Chunked,
+    UntilClose,
+}
+
+struct BufferedConn {
+    stream: TcpStream,
+    buf: Vec<u8>,
+    idle_timeout: Duration,
+}
+
+impl BufferedConn {
+    fn new(stream: TcpStream, buf: Vec<u8>, idle_timeout: Duration) -> Self {
+        Self { stream, buf, idle_timeout }
+    }
     
-    let stats_clone = stats.clone();
-    let buffer_size = config.buffer_size;
-    let idle_timeout = std::time::Duration::from_secs(30);
+    async fn fill(&mut self) -> io::Result<usize> {
+        let mut chunk = [0u8; 8192];
+        let n = tokio::time::timeout(self.idle_timeout, self.stream.read(&mut chunk))
+            .await
+            .map_err(|_| io::Error::new(ErrorKind::TimedOut, "HTTP idle timeout"))??;
+        self.buf.extend_from_slice(&chunk[..n]);
+        Ok(n)
+    }
     
-    let client_to_remote = async {
-        let mut buf = vec![0u8; buffer_size];
+    async fn read_head(&mut self) -> io::Result<Option<Vec<u8>>> {
         loop {
-            match tokio::time::timeout(idle_timeout, client_read.read(&mut buf)).await {
-                Ok(Ok(0)) => break,
-                Ok(Ok(n)) => {
-                    if remote_write.write_all(&buf[..n]).await.is_err() {
-                        break;
-                    }
-                    stats_clone.bytes_sent.fetch_add(n as u64, Ordering::Relaxed);
-                }
-                Ok(Err(_)) | Err(_) => break,
+            if let Some(pos) = self.buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                return Ok(Some(self.buf.drain(..pos + 4).collect()));
+            }
+            if self.buf.len() > MAX_REQUEST_HEAD_SIZE {
+                return Err(io::Error::new(ErrorKind::InvalidData, "HTTP headers too large"));
+            }
+            if self.fill().await? == 0 {
+                return Ok(None);
             }
         }
-    };
+    }
     
-    let stats_clone2 = stats.clone();
-    let remote_to_client = async {
-        let mut buf = vec![0u8; buffer_size];
+    async fn read_line(&mut self) -> io::Result<Vec<u8>> {
         loop {
-            match tokio::time::timeout(idle_timeout, remote_read.read(&mut buf)).await {
-                Ok(Ok(0)) => break,
-                Ok(Ok(n)) => {
-                    if client_write.write_all(&buf[..n]).await.is_err() {
-                        break;
-                    }
-                    stats_clone2.bytes_received.fetch_add(n as u64, Ordering::Relaxed);
-                }
-                Ok(Err(_)) | Err(_) => break,
+            if let Some(pos) = self.buf.windows(2).position(|w| w == b"\r\n") {
+                return Ok(self.buf.drain(..pos + 2).collect());
+            }
+            if self.buf.len() > MAX_REQUEST_HEAD_SIZE {
+                return Err(io::Error::new(ErrorKind::InvalidData, "HTTP chunk line too large"));
+            }
+            if self.fill().await? == 0 {
+                return Err(io::Error::new(ErrorKind::UnexpectedEof, "Truncated chunked body"));
             }
         }
-    };
-    
+    }
     
-    tokio::select! {
-        _ = client_to_remote => {},
-        _ = remote_to_client => {},
+    async fn copy_exact(&mut self, mut len: u64, out: &mut TcpStream) -> io::Result<u64> {
+        let total = len;
+        while len > 0 {
+            if self.buf.is_empty() && self.fill().await? == 0 {
+                return Err(io::Error::new(ErrorKind::UnexpectedEof, "Truncated HTTP body"));
+            }
+            let take = (self.buf.len() as u64).min(len) as usize;
+            out.write_all(&self.buf[..take]).await?;
+            self.buf.drain(..take);
+            len -= take as u64;
+        }
+        Ok(total)
     }
     
-    Ok(())
+    async fn copy_body(&mut self, framing: BodyFraming, out: &mut TcpStream) -> io::Result<u64> {
+        match framing {
+            BodyFraming::Empty => Ok(0),
+            BodyFraming::Length(len) => self.copy_exact(len, out).await,
+            BodyFraming::Chunked => {
+                let mut copied = 0;
+                loop {
+                    let line = self.read_line().await?;
+                    out.write_all(&line).await?;
+                    copied += line.len() as u64;
+                    
+                    let size = String::from_utf8_lossy(&line);
+                    let size = size.trim().split(';').next().unwrap_or_default();
+                    let size = u64::from_str_radix(size.trim(), 16)
+                        .map_err(|_| io::Error::new(ErrorKind::InvalidData, "Invalid chunk size"))?;
+                    
+                    if size == 0 {
+                        loop {
+                            let trailer = self.read_line().await?;
+                            out.write_all(&trailer).await?;
+                            copied += trailer.len() as u64;
+                            if trailer == b"\r\n" {
+                                return Ok(copied);
+                            }
+                        }
+                    }
+                    
+                    copied += self.copy_exact(size + 2, out).await?;
+                }
+            }
+            BodyFraming::UntilClose => {
+                let mut copied = self.buf.len() as u64;
+                out.write_all(&self.buf).await?;
+                self.buf.clear();
+                while self.fill().await? > 0 {
+                    out.write_all(&self.buf).await?;
+                    copied += self.buf.len() as u64;
+                    self.buf.clear();
+                }
+                Ok(copied)
+            }
+        }
+    }
+}
+
+fn find_header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines()
+        .skip(1)
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .find(|(header, _)| header.trim().eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim())
+}
+
+fn is_chunked(head: &str) -> bool {
+    find_header(head, "transfer-encoding")
+        .map(|value| value.to_ascii_lowercase().contains("chunked"))
+        .unwrap_or(false)
+}
+
+fn request_body_framing(head: &str) -> BodyFraming {
+    if is_chunked(head) {
+        return BodyFraming::Chunked;
+    }
+    match find_header(head, "content-length").and_then(|value| value.parse().ok()) {
+        Some(0) | None => BodyFraming::Empty,
+        Some(len) => BodyFraming::Length(len),
+    }
+}
+
+fn response_body_framing(head: &str, status: Option<u16>, head_only: bool) -> BodyFraming {
+    if head_only || matches!(status, Some(204) | Some(304)) {
+        return BodyFraming::Empty;
+    }
+    if is_chunked(head) {
+        return BodyFraming::Chunked;
+    }
+    match find_header(head, "content-length").and_then(|value| value.parse().ok()) {
+        Some(0) => BodyFraming::Empty,
+        Some(len) => BodyFraming::Length(len),
+        None => BodyFraming::UntilClose,
+    }
+}
+
+fn response_status(head: &str) -> Option<u16> {
+    head.lines().next()?.split_whitespace().nth(1)?.parse().ok()
+}
+
+fn wants_keep_alive(head: &str) -> bool {
+    let connection = find_header(head, "proxy-connection")
+        .or_else(|| find_header(head, "connection"))
+        .map(|value| value.to_ascii_lowercase());
+    
+    match connection {
+        Some(value) if value.contains("close") => false,
+        Some(value) if value.contains("keep-alive") => true,
+        _ => !head.lines().next().unwrap_or_default().contains("HTTP/1.0"),
+    }
 }
 
 fn rewrite_http_request(request: &str, raw: &[u8]) -> Vec<u8> {
@@ -573,8 +2051,7 @@ fn rewrite_http_request(request: &str, raw: &[u8]) -> Vec<u8> {
     let version = parts[2];
     
     
-    let path = if url.starts_with("http://") {
-        let without_scheme = &url[7..];
+    let path = if let Some(without_scheme) = url.strip_prefix("http://") {
         if let Some(slash_pos) = without_scheme.find('/') {
             &without_scheme[slash_pos..]
         } else {
@@ -596,6 +2073,78 @@ fn rewrite_http_request(request: &str, raw: &[u8]) -> Vec<u8> {
     result
 }
 
+fn is_authorized(request: &str, header: &str, user: &str, pass: &str) -> bool {
+    let expected = base64_encode(format!("{}:{}", user, pass).as_bytes());
+    
+    request.lines()
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .filter(|(name, _)| name.trim().eq_ignore_ascii_case(header))
+        .any(|(_, value)| {
+            let mut parts = value.split_whitespace();
+            matches!(
+                (parts.next(), parts.next()),
+                (Some(scheme), Some(token)) if scheme.eq_ignore_ascii_case("basic") && token == expected
+            )
+        })
+}
+
+fn basic_credentials(user: &str, pass: &str) -> String {
+    format!("Basic {}", base64_encode(format!("{}:{}", user, pass).as_bytes()))
+}
+
+fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        
+        out.push(ALPHABET[(n >> 18) as usize & 0x3f] as char);
+        out.push(ALPHABET[(n >> 12) as usize & 0x3f] as char);
+        out.push(if chunk.len() > 1 { ALPHABET[(n >> 6) as usize & 0x3f] as char } else { '=' });
+        out.push(if chunk.len() > 2 { ALPHABET[n as usize & 0x3f] as char } else { '=' });
+    }
+    out
+}
+
+fn strip_proxy_headers(raw: &[u8]) -> Vec<u8> {
+    let header_end = match raw.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(pos) => pos + 2,
+        None => return raw.to_vec(),
+    };
+    
+    let mut result = Vec::with_capacity(raw.len());
+    for line in raw[..header_end].split_inclusive(|&b| b == b'\n') {
+        let name = line.split(|&b| b == b':').next().unwrap_or_default();
+        if !name.eq_ignore_ascii_case(b"proxy-authorization") && !name.eq_ignore_ascii_case(b"proxy-connection") {
+            result.extend_from_slice(line);
+        }
+    }
+    result.extend_from_slice(&raw[header_end..]);
+    result
+}
+
+fn insert_header(raw: &[u8], header: &str) -> Vec<u8> {
+    let header_end = match raw.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(pos) => pos + 2,
+        None => return raw.to_vec(),
+    };
+    
+    let mut result = Vec::with_capacity(raw.len() + header.len() + 2);
+    result.extend_from_slice(&raw[..header_end]);
+    result.extend_from_slice(header.as_bytes());
+    result.extend_from_slice(b"\r\n");
+    result.extend_from_slice(&raw[header_end..]);
+    result
+}
+
+fn target_host(target: &str) -> &str {
+    let host = target.rsplit_once(':').map(|(host, _)| host).unwrap_or(target);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
 fn extract_host_header(request: &str) -> Option<String> {
     for line in request.lines() {
         if line.to_lowercase().starts_with("host:") {
@@ -606,8 +2155,46 @@ fn extract_host_header(request: &str) -> Option<String> {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use crate::test_util::spawn_echo_server;
+    use tokio::net::TcpListener;
+    
+    #[test]
+    fn test_stats_snapshot_delta() {
+        let stats = ProxyStats::new();
+        stats.connections_total.fetch_add(3, Ordering::Relaxed);
+        stats.connections_active.fetch_add(2, Ordering::Relaxed);
+        stats.bytes_sent.fetch_add(1000, Ordering::Relaxed);
+        let first = stats.snapshot();
+        assert_eq!(first.connections_total, 3);
+        
+        stats.connections_total.fetch_add(2, Ordering::Relaxed);
+        stats.connections_active.fetch_sub(1, Ordering::Relaxed);
+        stats.bytes_sent.fetch_add(500, Ordering::Relaxed);
+        stats.errors.fetch_add(1, Ordering::Relaxed);
+        stats.record_fingerprint("a".to_string());
+        stats.record_fingerprint("b".to_string());
+        stats.record_fingerprint("a".to_string());
+        let delta = stats.snapshot().delta(&first);
+        
+        assert_eq!(delta.connections_total, 2);
+        assert_eq!(delta.connections_active, 1);
+        assert_eq!(delta.bytes_sent, 500);
+        assert_eq!(delta.errors, 1);
+        assert_eq!(delta.dns_queries, 0);
+        assert_eq!(delta.distinct_fingerprints, 2);
+        
+        let json = serde_json::to_value(stats.snapshot()).unwrap();
+        assert_eq!(json["connections_total"], 5);
+        
+        for i in 0..MAX_FINGERPRINTS {
+            stats.record_fingerprint(i.to_string());
+        }
+        assert_eq!(stats.snapshot().distinct_fingerprints, MAX_FINGERPRINTS as u64);
+        stats.record_fingerprint("new".to_string());
+        assert!(!stats.fingerprints.lock().contains("new"));
+    }
     
     #[test]
     fn test_extract_connect_target() {
@@ -618,10 +2205,1230 @@ mod tests {
         assert_eq!(extract_connect_target(req2).unwrap(), "example.com:443");
     }
     
+    pub(crate) fn sample_client_hello() -> Vec<u8> {
+        vec![
+            0x16, 0x03, 0x01, 0x00, 0x4a,
+            0x01, 0x00, 0x00, 0x46,
+            0x03, 0x03,
+            0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07,
+            0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f,
+            0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17,
+            0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e, 0x1f,
+            0x00,
+            0x00, 0x02, 0x13, 0x01,
+            0x01, 0x00,
+            0x00, 0x1b,
+            0x00, 0x00, 0x00, 0x10,
+            0x00, 0x0e, 0x00, 0x00, 0x0b,
+            0x64, 0x69, 0x73, 0x63, 0x6f, 0x72, 0x64, 0x2e, 0x63, 0x6f, 0x6d,
+            0x00, 0x15, 0x00, 0x03, 0x00, 0x00, 0x00,
+        ]
+    }
+    
+    #[tokio::test]
+    async fn test_read_client_hello_across_writes() {
+        let hello = sample_client_hello();
+        let (mut writer, mut reader) = tokio::io::duplex(4096);
+        
+        let chunks = vec![hello[..3].to_vec(), hello[3..40].to_vec(), hello[40..].to_vec()];
+        tokio::spawn(async move {
+            for chunk in chunks {
+                writer.write_all(&chunk).await.unwrap();
+                sleep(Duration::from_millis(20)).await;
+            }
+            sleep(Duration::from_secs(2)).await;
+        });
+        
+        let data = read_client_hello(&mut reader, 4096).await.unwrap();
+        assert_eq!(data, hello);
+        
+        let engine = BypassEngine::new(BypassConfig {
+            tls_split_pos: 0,
+            ..Default::default()
+        });
+        let result = engine.process_outgoing(&data);
+        assert!(result.modified);
+        assert_eq!(result.hostname.as_deref(), Some("discord.com"));
+        
+        let info = engine::parse_client_hello(&data).unwrap();
+        let sni_start = info.sni_offset.unwrap();
+        let sni_end = sni_start + info.sni_length.unwrap();
+        
+        let mut boundary = 0;
+        let mut split_in_sni = false;
+        for fragment in &result.fragments[..result.fragments.len() - 1] {
+            boundary += fragment.len();
+            if boundary > sni_start && boundary < sni_end {
+                split_in_sni = true;
+            }
+        }
+        assert!(split_in_sni);
+        
+        let reassembled: Vec<u8> = result.fragments.iter().flat_map(|f| f.iter().copied()).collect();
+        assert_eq!(reassembled, hello);
+    }
+    
+    #[tokio::test]
+    async fn test_read_client_hello_incomplete_record() {
+        let hello = sample_client_hello();
+        let (mut writer, mut reader) = tokio::io::duplex(4096);
+        
+        let partial = hello[..30].to_vec();
+        tokio::spawn(async move {
+            writer.write_all(&partial).await.unwrap();
+        });
+        
+        let data = read_client_hello(&mut reader, 4096).await.unwrap();
+        assert_eq!(data, &hello[..30]);
+    }
+    
+    #[tokio::test]
+    async fn test_read_client_hello_non_tls() {
+        let (mut writer, mut reader) = tokio::io::duplex(4096);
+        writer.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        
+        let data = read_client_hello(&mut reader, 4096).await.unwrap();
+        assert_eq!(data, b"GET / HTTP/1.1\r\n\r\n");
+    }
+    
+    #[tokio::test]
+    async fn test_connect_out_of_order_echo() {
+        let echo_addr = spawn_echo_server().await;
+        
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+        let config = ProxyConfig {
+            bypass: BypassConfig {
+                send_out_of_order: true,
+                fragment_delay_us: 1000,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        tokio::spawn(async move {
+            let (stream, peer_addr) = proxy.accept().await.unwrap();
+            let _ = handle_client(stream, &mut ConnectionRecord::new(peer_addr), config, ProxyStats::new(), Arc::new(DohResolver::new())).await;
+        });
+        
+        let mut client = TcpStream::connect(proxy_addr).await.unwrap();
+        client.write_all(format!("CONNECT {} HTTP/1.1\r\n\r\n", echo_addr).as_bytes()).await.unwrap();
+        
+        let mut response = [0u8; 39];
+        client.read_exact(&mut response).await.unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 200"));
+        
+        let hello = sample_client_hello();
+        client.write_all(&hello).await.unwrap();
+        
+        let mut echoed = vec![0u8; hello.len()];
+        tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut echoed)).await.unwrap().unwrap();
+        assert_eq!(echoed, hello);
+    }
+    
+    #[tokio::test]
+    async fn test_relay_buffers_are_pooled() {
+        let echo_addr = spawn_echo_server().await;
+        
+        let stats = ProxyStats::new();
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+        let proxy_stats = stats.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, peer_addr) = proxy.accept().await.unwrap();
+                let stats = proxy_stats.clone();
+                tokio::spawn(async move {
+                    let _ = handle_client(stream, &mut ConnectionRecord::new(peer_addr), ProxyConfig::default(), stats, Arc::new(DohResolver::new())).await;
+                });
+            }
+        });
+        
+        for batch in 0..10u8 {
+            let connections = (0..8u8).map(|i| async move {
+                let mut client = TcpStream::connect(proxy_addr).await.unwrap();
+                client.write_all(format!("CONNECT {} HTTP/1.1\r\n\r\n", echo_addr).as_bytes()).await.unwrap();
+                let mut response = [0u8; 39];
+                client.read_exact(&mut response).await.unwrap();
+                assert!(response.starts_with(b"HTTP/1.1 200"));
+                
+                let mut payload = format!("payload {} {}:", batch, i).into_bytes();
+                payload.extend((0..20_000u32).map(|j| (j as u8).wrapping_mul(batch + 1).wrapping_add(i)));
+                client.write_all(&payload).await.unwrap();
+                
+                let mut echoed = vec![0u8; payload.len()];
+                tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut echoed)).await.unwrap().unwrap();
+                assert!(echoed == payload, "connection {} {} was corrupted", batch, i);
+            });
+            spawn_and_join(connections).await;
+        }
+        
+        let snapshot = stats.snapshot();
+        assert!(snapshot.buffer_pool_hits > 0, "{:?}", snapshot);
+        assert!(snapshot.buffer_pool_misses < 160, "{:?}", snapshot);
+    }
+    
+    async fn spawn_and_join<F: std::future::Future<Output = ()> + Send + 'static>(futures: impl Iterator<Item = F>) {
+        let mut set = tokio::task::JoinSet::new();
+        for future in futures {
+            set.spawn(future);
+        }
+        while let Some(result) = set.join_next().await {
+            result.unwrap();
+        }
+    }
+    
+    async fn tcp_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (connected, accepted) = tokio::join!(TcpStream::connect(listener.local_addr().unwrap()), listener.accept());
+        (connected.unwrap(), accepted.unwrap().0)
+    }
+    
+    /// Pushes `upload` through the relay and answers with `download`, returning the
+    /// relay's record once both sides have closed.
+    async fn relay_transfer(use_fast_path: bool, stats: Arc<ProxyStats>, upload: Vec<u8>, download: Vec<u8>) -> ConnectionRecord {
+        let (mut client, client_side) = tcp_pair().await;
+        let (remote_side, mut remote) = tcp_pair().await;
+        let relay = tokio::spawn(async move {
+            let mut record = ConnectionRecord::new(client_side.peer_addr().unwrap());
+            relay_bidirectional(client_side, remote_side, stats, Duration::from_secs(5), None, use_fast_path, &mut record).await;
+            record
+        });
+        
+        let expected_up = upload.clone();
+        let server = tokio::spawn(async move {
+            let mut received = Vec::new();
+            remote.read_to_end(&mut received).await.unwrap();
+            assert!(received == expected_up);
+            remote.write_all(&download).await.unwrap();
+            remote.shutdown().await.unwrap();
+        });
+        
+        client.write_all(&upload).await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).await.unwrap();
+        server.await.unwrap();
+        
+        let record = relay.await.unwrap();
+        assert_eq!(received.len(), record.bytes_down as usize);
+        record
+    }
+    
+    #[tokio::test]
+    async fn test_relay_fast_path_counts_bytes() {
+        let upload: Vec<u8> = (0..3_000_000u32).map(|i| (i % 251) as u8).collect();
+        let download: Vec<u8> = (0..1_500_000u32).map(|i| (i % 241) as u8).collect();
+        
+        for use_fast_path in [true, false] {
+            let stats = ProxyStats::new();
+            let record = relay_transfer(use_fast_path, stats.clone(), upload.clone(), download.clone()).await;
+            assert_eq!(record.bytes_up, upload.len() as u64, "fast path: {}", use_fast_path);
+            assert_eq!(record.bytes_down, download.len() as u64, "fast path: {}", use_fast_path);
+            assert_eq!(record.close_reason, CloseReason::Eof);
+            
+            let snapshot = stats.snapshot();
+            assert_eq!(snapshot.bytes_sent, upload.len() as u64);
+            assert_eq!(snapshot.bytes_received, download.len() as u64);
+            assert_eq!(snapshot.buffer_pool_misses, 2, "fast path: {}", use_fast_path);
+            assert_eq!(stats.buffers.available(), 2, "fast path: {}", use_fast_path);
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_relay_fast_path_idle_timeout() {
+        let (_client, client_side) = tcp_pair().await;
+        let (remote_side, _remote) = tcp_pair().await;
+        let mut record = ConnectionRecord::new(client_side.peer_addr().unwrap());
+        let relay = relay_bidirectional(client_side, remote_side, ProxyStats::new(), Duration::from_millis(50), None, true, &mut record);
+        tokio::time::timeout(Duration::from_secs(5), relay).await.unwrap();
+        assert_eq!(record.close_reason, CloseReason::Timeout);
+    }
+    
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "throughput benchmark; run with --ignored"]
+    async fn bench_relay_throughput() {
+        let payload = vec![0xA5u8; 256 * 1024 * 1024];
+        let mut elapsed = Vec::new();
+        for use_fast_path in [false, true] {
+            let start = Instant::now();
+            relay_transfer(use_fast_path, ProxyStats::new(), payload.clone(), Vec::new()).await;
+            elapsed.push(start.elapsed());
+        }
+        assert!(elapsed[1] <= elapsed[0], "fast path took {:?}, manual loop {:?}", elapsed[1], elapsed[0]);
+    }
+    
+    fn auth_config() -> ProxyConfig {
+        ProxyConfig {
+            auth: Some(("user".to_string(), "secret".to_string())),
+            ..Default::default()
+        }
+    }
+    
+    async fn auth_proxy(stats: Arc<ProxyStats>) -> SocketAddr {
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+        let config = auth_config();
+        tokio::spawn(async move {
+            loop {
+                let (stream, peer_addr) = proxy.accept().await.unwrap();
+                let config = config.clone();
+                let stats = stats.clone();
+                tokio::spawn(async move {
+                    let _ = handle_client(stream, &mut ConnectionRecord::new(peer_addr), config, stats, Arc::new(DohResolver::new())).await;
+                });
+            }
+        });
+        proxy_addr
+    }
+    
+    async fn connect_response(proxy_addr: SocketAddr, request: String) -> String {
+        let mut client = TcpStream::connect(proxy_addr).await.unwrap();
+        client.write_all(request.as_bytes()).await.unwrap();
+        
+        let mut response = vec![0u8; 512];
+        let n = tokio::time::timeout(Duration::from_secs(5), client.read(&mut response)).await.unwrap().unwrap();
+        String::from_utf8_lossy(&response[..n]).to_string()
+    }
+    
+    #[tokio::test]
+    async fn test_proxy_auth() {
+        let echo = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let echo_addr = echo.local_addr().unwrap();
+        tokio::spawn(async move {
+            let _ = echo.accept().await.unwrap();
+        });
+        
+        let stats = ProxyStats::new();
+        let proxy_addr = auth_proxy(stats.clone()).await;
+        
+        let missing = connect_response(proxy_addr, format!("CONNECT {} HTTP/1.1\r\n\r\n", echo_addr)).await;
+        assert!(missing.starts_with("HTTP/1.1 407"));
+        assert!(missing.contains("Proxy-Authenticate: Basic realm=\"turkeydpi\""));
+        
+        let wrong = connect_response(
+            proxy_addr,
+            format!("CONNECT {} HTTP/1.1\r\nProxy-Authorization: Basic {}\r\n\r\n", echo_addr, base64_encode(b"user:wrong")),
+        ).await;
+        assert!(wrong.starts_with("HTTP/1.1 407"));
+        
+        let plain = connect_response(proxy_addr, "GET http://example.com/ HTTP/1.1\r\nHost: example.com\r\n\r\n".to_string()).await;
+        assert!(plain.starts_with("HTTP/1.1 407"));
+        
+        let correct = connect_response(
+            proxy_addr,
+            format!("CONNECT {} HTTP/1.1\r\nproxy-authorization: basic dXNlcjpzZWNyZXQ=\r\n\r\n", echo_addr),
+        ).await;
+        assert!(correct.starts_with("HTTP/1.1 200"));
+        
+        assert_eq!(stats.auth_failures.load(Ordering::Relaxed), 3);
+        assert_eq!(stats.errors.load(Ordering::Relaxed), 3);
+    }
+    
+    async fn spawn_proxy(config: ProxyConfig) -> SocketAddr {
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, peer_addr) = proxy.accept().await.unwrap();
+            let _ = handle_client(stream, &mut ConnectionRecord::new(peer_addr), config, ProxyStats::new(), Arc::new(DohResolver::new())).await;
+        });
+        proxy_addr
+    }
+    
+    #[tokio::test]
+    async fn test_connect_split_headers_keep_surplus() {
+        let echo_addr = spawn_echo_server().await;
+        
+        let proxy_addr = spawn_proxy(ProxyConfig::default()).await;
+        let mut client = TcpStream::connect(proxy_addr).await.unwrap();
+        client.set_nodelay(true).unwrap();
+        
+        let hello = sample_client_hello();
+        let head = format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n\r\n", echo_addr, echo_addr);
+        let split = head.len() - 1;
+        
+        client.write_all(&head.as_bytes()[..split]).await.unwrap();
+        sleep(Duration::from_millis(50)).await;
+        
+        let mut second = head.as_bytes()[split..].to_vec();
+        second.extend_from_slice(&hello[..3]);
+        client.write_all(&second).await.unwrap();
+        sleep(Duration::from_millis(50)).await;
+        client.write_all(&hello[3..]).await.unwrap();
+        
+        let mut response = [0u8; 39];
+        client.read_exact(&mut response).await.unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 200"));
+        
+        let mut echoed = vec![0u8; hello.len()];
+        tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut echoed)).await.unwrap().unwrap();
+        assert_eq!(echoed, hello);
+    }
+    
+    #[tokio::test]
+    async fn test_http_forward_split_request_line() {
+        let server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let received = tokio::spawn(async move {
+            let (mut stream, _) = server.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !request.ends_with(b"body") {
+                let n = stream.read(&mut buf).await.unwrap();
+                assert!(n > 0);
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok").await.unwrap();
+            request
+        });
+        
+        let proxy_addr = spawn_proxy(ProxyConfig {
+            bypass: BypassConfig {
+                fragment_http_host: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        }).await;
+        let mut client = TcpStream::connect(proxy_addr).await.unwrap();
+        client.set_nodelay(true).unwrap();
+        
+        client.write_all(format!("POST http://{}/submit HT", server_addr).as_bytes()).await.unwrap();
+        sleep(Duration::from_millis(50)).await;
+        client.write_all(format!("TP/1.1\r\nHost: {}\r\nContent-Length: 4\r\n\r\nbody", server_addr).as_bytes()).await.unwrap();
+        
+        let request = tokio::time::timeout(Duration::from_secs(5), received).await.unwrap().unwrap();
+        let request = String::from_utf8(request).unwrap();
+        assert!(request.starts_with("POST /submit HTTP/1.1\r\n"));
+        assert!(request.ends_with("\r\n\r\nbody"));
+        
+        let mut response = vec![0u8; 40];
+        tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut response)).await.unwrap().unwrap();
+        assert!(response.ends_with(b"ok"));
+    }
+    
+    #[tokio::test]
+    async fn test_http_forward_admits_hostname() {
+        use engine::config::{MatchCriteria, Rule, RuleAction};
+        
+        let (origin, accepted) = spawn_http_origin("alpha", false).await;
+        let pipeline = Arc::new(Pipeline::new(engine::Config::default(), Arc::new(Stats::new())).unwrap());
+        
+        let fetch = |pipeline: Arc<Pipeline>| async move {
+            let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let proxy_addr = proxy.local_addr().unwrap();
+            tokio::spawn(async move {
+                let (stream, peer_addr) = proxy.accept().await.unwrap();
+                let stats = ProxyStats::new();
+                let _ = stats.pipeline.set(pipeline);
+                let _ = handle_client(stream, &mut ConnectionRecord::new(peer_addr), ProxyConfig::default(), stats, Arc::new(DohResolver::new())).await;
+            });
+            let mut client = TcpStream::connect(proxy_addr).await.unwrap();
+            client.write_all(format!("GET http://{}/one HTTP/1.1\r\nHost: origin.test\r\nConnection: close\r\n\r\n", origin).as_bytes()).await.unwrap();
+            let mut response = Vec::new();
+            tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut response)).await.unwrap().unwrap();
+            String::from_utf8(response).unwrap()
+        };
+        
+        assert!(fetch(pipeline.clone()).await.ends_with("alpha /one "));
+        let flows = pipeline.flow_cache().iter_snapshot();
+        assert_eq!(flows.len(), 1);
+        assert_eq!(flows[0].hostname.as_deref(), Some("origin.test"));
+        
+        pipeline.update_rules(vec![Rule {
+            name: "drop-origin".to_string(),
+            enabled: true,
+            priority: 10,
+            match_criteria: MatchCriteria {
+                domains: Some(vec!["origin.test".to_string()]),
+                require_hostname: true,
+                ..Default::default()
+            },
+            action: RuleAction::Drop,
+            transforms: Vec::new(),
+            overrides: HashMap::new(),
+        }]).unwrap();
+        assert!(fetch(pipeline.clone()).await.is_empty());
+        assert_eq!(pipeline.rule_stats()[0].hits, 1);
+        assert_eq!(accepted.load(Ordering::Relaxed), 2);
+    }
+    
+    async fn spawn_http_origin(name: &'static str, chunked: bool) -> (SocketAddr, Arc<AtomicU64>) {
+        let server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        let accepted = Arc::new(AtomicU64::new(0));
+        let accepted_clone = accepted.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = server.accept().await.unwrap();
+                accepted_clone.fetch_add(1, Ordering::Relaxed);
+                tokio::spawn(async move {
+                    let mut conn = BufferedConn::new(stream, Vec::new(), Duration::from_secs(5));
+                    while let Ok(Some(head)) = conn.read_head().await {
+                        let request = String::from_utf8_lossy(&head).to_string();
+                        let mut body = Vec::new();
+                        let len = find_header(&request, "content-length").and_then(|v| v.parse().ok()).unwrap_or(0);
+                        while conn.buf.len() < len {
+                            conn.fill().await.unwrap();
+                        }
+                        body.extend(conn.buf.drain(..len));
+                        
+                        let path = request.split_whitespace().nth(1).unwrap().to_string();
+                        let payload = format!("{} {} {}", name, path, String::from_utf8_lossy(&body));
+                        let response = if chunked {
+                            format!(
+                                "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+                                payload.len(),
+                                payload
+                            )
+                        } else {
+                            format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", payload.len(), payload)
+                        };
+                        conn.stream.write_all(response.as_bytes()).await.unwrap();
+                    }
+                });
+            }
+        });
+        (addr, accepted)
+    }
+    
+    #[tokio::test]
+    async fn test_http_keep_alive_across_hosts() {
+        let (origin_a, accepted_a) = spawn_http_origin("alpha", false).await;
+        let (origin_b, accepted_b) = spawn_http_origin("beta", true).await;
+        
+        let proxy_addr = spawn_proxy(ProxyConfig {
+            bypass: BypassConfig {
+                fragment_http_host: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        }).await;
+        let stream = TcpStream::connect(proxy_addr).await.unwrap();
+        let mut client = BufferedConn::new(stream, Vec::new(), Duration::from_secs(5));
+        
+        let requests = [
+            (origin_a, "POST", "/one", "hello"),
+            (origin_b, "GET", "/two", ""),
+            (origin_a, "GET", "/three", ""),
+        ];
+        let mut pipelined = Vec::new();
+        for (origin, method, path, body) in requests {
+            pipelined.extend_from_slice(format!(
+                "{} http://{}{} HTTP/1.1\r\nHost: {}\r\nProxy-Connection: keep-alive\r\nContent-Length: {}\r\n\r\n{}",
+                method, origin, path, origin, body.len(), body
+            ).as_bytes());
+        }
+        client.stream.write_all(&pipelined).await.unwrap();
+        
+        let mut bodies = Vec::new();
+        for _ in 0..requests.len() {
+            let head = client.read_head().await.unwrap().unwrap();
+            let head = String::from_utf8_lossy(&head).to_string();
+            assert_eq!(response_status(&head), Some(200));
+            
+            let mut body = Vec::new();
+            match response_body_framing(&head, Some(200), false) {
+                BodyFraming::Length(len) => {
+                    while (client.buf.len() as u64) < len {
+                        client.fill().await.unwrap();
+                    }
+                    body.extend(client.buf.drain(..len as usize));
+                }
+                BodyFraming::Chunked => {
+                    let size_line = client.read_line().await.unwrap();
+                    let size = usize::from_str_radix(String::from_utf8_lossy(&size_line).trim(), 16).unwrap();
+                    while client.buf.len() < size + 2 {
+                        client.fill().await.unwrap();
+                    }
+                    body.extend(client.buf.drain(..size));
+                    client.buf.drain(..2);
+                    assert_eq!(client.read_line().await.unwrap(), b"0\r\n");
+                    assert_eq!(client.read_line().await.unwrap(), b"\r\n");
+                }
+                other => panic!("unexpected framing {:?}", other),
+            }
+            bodies.push(String::from_utf8(body).unwrap());
+        }
+        assert_eq!(bodies, vec!["alpha /one hello", "beta /two ", "alpha /three "]);
+        assert_eq!(accepted_a.load(Ordering::Relaxed), 1);
+        assert_eq!(accepted_b.load(Ordering::Relaxed), 1);
+    }
+    
+    fn chained_config(parent: SocketAddr, credentials: bool) -> ProxyConfig {
+        ProxyConfig {
+            bypass: BypassConfig {
+                fragment_http_host: false,
+                ..Default::default()
+            },
+            upstream_proxy: Some(parent),
+            upstream_auth: credentials.then(|| ("user".to_string(), "secret".to_string())),
+            ..Default::default()
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_upstream_proxy_connect_chain() {
+        let echo_addr = spawn_echo_server().await;
+        
+        let parent_stats = ProxyStats::new();
+        let parent = auth_proxy(parent_stats.clone()).await;
+        let proxy_addr = spawn_proxy(chained_config(parent, true)).await;
+        
+        let mut client = TcpStream::connect(proxy_addr).await.unwrap();
+        client.write_all(format!("CONNECT {} HTTP/1.1\r\n\r\n", echo_addr).as_bytes()).await.unwrap();
+        let mut response = [0u8; 39];
+        client.read_exact(&mut response).await.unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 200"));
+        
+        let hello = sample_client_hello();
+        client.write_all(&hello).await.unwrap();
+        let mut echoed = vec![0u8; hello.len()];
+        tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut echoed)).await.unwrap().unwrap();
+        assert_eq!(echoed, hello);
+        assert_eq!(parent_stats.auth_failures.load(Ordering::Relaxed), 0);
+    }
+    
+    #[tokio::test]
+    async fn test_upstream_proxy_error_propagated() {
+        let parent_stats = ProxyStats::new();
+        let parent = auth_proxy(parent_stats.clone()).await;
+        let proxy_addr = spawn_proxy(chained_config(parent, false)).await;
+        
+        let response = connect_response(proxy_addr, "CONNECT example.com:443 HTTP/1.1\r\n\r\n".to_string()).await;
+        assert!(response.starts_with("HTTP/1.1 407"));
+        assert!(response.contains("Proxy-Authenticate: Basic realm=\"turkeydpi\""));
+        assert_eq!(parent_stats.auth_failures.load(Ordering::Relaxed), 1);
+    }
+    
+    #[tokio::test]
+    async fn test_upstream_proxy_http_forward() {
+        let (origin, accepted) = spawn_http_origin("alpha", false).await;
+        let parent = auth_proxy(ProxyStats::new()).await;
+        let proxy_addr = spawn_proxy(chained_config(parent, true)).await;
+        
+        let stream = TcpStream::connect(proxy_addr).await.unwrap();
+        let mut client = BufferedConn::new(stream, Vec::new(), Duration::from_secs(5));
+        client.stream.write_all(format!(
+            "GET http://{}/chained HTTP/1.1\r\nHost: {}\r\nProxy-Connection: close\r\n\r\n",
+            origin, origin
+        ).as_bytes()).await.unwrap();
+        
+        let head = client.read_head().await.unwrap().unwrap();
+        let head = String::from_utf8_lossy(&head).to_string();
+        assert_eq!(response_status(&head), Some(200));
+        let body = b"alpha /chained ";
+        while client.buf.len() < body.len() {
+            client.fill().await.unwrap();
+        }
+        assert_eq!(&client.buf[..body.len()], body);
+        assert_eq!(accepted.load(Ordering::Relaxed), 1);
+    }
+    
+    async fn spawn_idle_tunnel(idle_timeout: Duration, trickle: Option<Duration>) -> (TcpStream, Arc<ProxyStats>) {
+        let remote = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let remote_addr = remote.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = remote.accept().await.unwrap();
+            let mut buf = [0u8; 64];
+            let _ = stream.read(&mut buf).await;
+            match trickle {
+                Some(interval) => {
+                    for _ in 0..8 {
+                        sleep(interval).await;
+                        if stream.write_all(b".").await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                None => {
+                    let _ = stream.read(&mut buf).await;
+                }
+            }
+        });
+        
+        let proxy = Arc::new(BypassProxy::new(ProxyConfig {
+            listen_addrs: vec!["127.0.0.1:0".parse().unwrap()],
+            idle_timeout,
+            install_signal_handler: false,
+            ..Default::default()
+        }));
+        tokio::spawn({
+            let proxy = proxy.clone();
+            async move { proxy.run().await }
+        });
+        proxy.started().await;
+        let proxy_addr = proxy.local_addrs()[0];
+        let stats = proxy.stats();
+        
+        let mut client = TcpStream::connect(proxy_addr).await.unwrap();
+        client.write_all(format!("CONNECT {} HTTP/1.1\r\n\r\n", remote_addr).as_bytes()).await.unwrap();
+        let mut response = [0u8; 39];
+        client.read_exact(&mut response).await.unwrap();
+        client.write_all(b"ping").await.unwrap();
+        (client, stats)
+    }
+    
+    #[tokio::test]
+    async fn test_tunnel_idle_timeout_silent_peer() {
+        let (mut client, stats) = spawn_idle_tunnel(Duration::from_millis(200), None).await;
+        assert_eq!(stats.connections_active.load(Ordering::Relaxed), 1);
+        
+        let mut buf = [0u8; 16];
+        let n = tokio::time::timeout(Duration::from_secs(5), client.read(&mut buf)).await.unwrap().unwrap();
+        assert_eq!(n, 0);
+        
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while stats.connections_active.load(Ordering::Relaxed) != 0 {
+                sleep(Duration::from_millis(10)).await;
+            }
+        }).await.unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_tunnel_activity_resets_idle_timer() {
+        let (mut client, _stats) = spawn_idle_tunnel(Duration::from_millis(300), Some(Duration::from_millis(100))).await;
+        
+        let mut received = vec![0u8; 8];
+        tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut received)).await.unwrap().unwrap();
+        assert_eq!(received, b"........");
+    }
+    
+    #[tokio::test]
+    async fn test_connection_record_for_tunnel() {
+        let echo_addr = spawn_echo_server().await;
+        
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (stream, peer_addr) = proxy.accept().await.unwrap();
+            let mut record = ConnectionRecord::new(peer_addr);
+            let result = handle_client(stream, &mut record, ProxyConfig::default(), ProxyStats::new(), Arc::new(DohResolver::new())).await;
+            record.finish(&result)
+        });
+        
+        let mut client = TcpStream::connect(proxy_addr).await.unwrap();
+        client.write_all(format!("CONNECT {} HTTP/1.1\r\n\r\n", echo_addr).as_bytes()).await.unwrap();
+        let mut response = [0u8; 39];
+        client.read_exact(&mut response).await.unwrap();
+        
+        let hello = sample_client_hello();
+        client.write_all(&hello).await.unwrap();
+        let mut echoed = vec![0u8; hello.len()];
+        client.read_exact(&mut echoed).await.unwrap();
+        client.shutdown().await.unwrap();
+        drop(client);
+        
+        let entry = tokio::time::timeout(Duration::from_secs(5), server).await.unwrap().unwrap();
+        assert_eq!(entry.host.as_deref(), Some("discord.com"));
+        assert_eq!(entry.protocol, "tls");
+        assert_eq!(entry.bytes_up, hello.len() as u64);
+        assert_eq!(entry.bytes_down, hello.len() as u64);
+        assert!(entry.bypass_applied);
+        assert_eq!(entry.close_reason, CloseReason::Eof);
+    }
+    
+    async fn connect_failure(stats: Arc<ProxyStats>, target: &str) -> String {
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+        // No upstream providers, so lookups fail without leaving the host.
+        let dns = Arc::new(DohResolver::with_providers(Vec::new()));
+        let server = tokio::spawn(async move {
+            let (stream, peer_addr) = proxy.accept().await.unwrap();
+            handle_client(stream, &mut ConnectionRecord::new(peer_addr), ProxyConfig::default(), stats, dns).await
+        });
+        
+        let mut client = TcpStream::connect(proxy_addr).await.unwrap();
+        client.write_all(format!("CONNECT {} HTTP/1.1\r\n\r\n", target).as_bytes()).await.unwrap();
+        let mut response = Vec::new();
+        tokio::time::timeout(Duration::from_secs(10), client.read_to_end(&mut response)).await.unwrap().unwrap();
+        assert!(server.await.unwrap().is_err());
+        String::from_utf8(response).unwrap()
+    }
+    
+    #[tokio::test]
+    async fn test_connect_failures_are_categorized() {
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let closed_addr = closed.local_addr().unwrap();
+        drop(closed);
+        let stats = ProxyStats::new();
+        
+        let response = connect_failure(stats.clone(), &closed_addr.to_string()).await;
+        assert!(response.starts_with("HTTP/1.1 502"), "{}", response);
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.connect_refused, 1);
+        assert_eq!(snapshot.dns_failures, 0);
+        assert_eq!(snapshot.connect_timeouts, 0);
+        
+        // The out-of-range port also keeps the system resolver fallback offline.
+        let response = connect_failure(stats.clone(), "unresolvable.invalid:70000").await;
+        assert!(response.contains("DNS resolution failed"), "{}", response);
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.dns_failures, 1);
+        assert_eq!(snapshot.connect_refused, 1);
+        assert_eq!(snapshot.relay_errors, 0);
+        assert_eq!(snapshot.tls_blocked_suspected, 0);
+        
+        stats.dns_queries.fetch_add(2, Ordering::Relaxed);
+        let engine_stats = Stats::new();
+        stats.export_to(&engine_stats);
+        stats.export_to(&engine_stats);
+        let exported = engine_stats.snapshot();
+        assert_eq!(exported.connection_errors, snapshot.errors);
+        assert_eq!(exported.dns_queries, snapshot.dns_queries + 2);
+        assert_eq!(exported.socks_doh_resolved, 0);
+        assert_eq!(exported.dns_failures, 1);
+        assert_eq!(exported.connect_refused, 1);
+        assert_eq!(exported.connect_timeouts, 0);
+    }
+    
+    #[tokio::test]
+    async fn test_paused_connect_skips_mss_clamp() {
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let targets = [target.local_addr().unwrap()];
+        let mut config = ProxyConfig::default();
+        config.bypass.clamp_mss = Some(536);
+        let stats = ProxyStats::new();
+        
+        let clamped = connect_targets(&targets, &config, &stats).await.unwrap();
+        assert!(socket2::SockRef::from(&clamped).tcp_mss().unwrap() <= 536);
+        
+        stats.paused.store(true, Ordering::Relaxed);
+        let unclamped = connect_targets(&targets, &config, &stats).await.unwrap();
+        assert!(socket2::SockRef::from(&unclamped).tcp_mss().unwrap() > 536);
+    }
+    
+    async fn local_response(config: ProxyConfig, stats: Arc<ProxyStats>, path: &str, hostname: Option<&str>) -> String {
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, peer_addr) = proxy.accept().await.unwrap();
+            let _ = handle_client(stream, &mut ConnectionRecord::new(peer_addr), config, stats, Arc::new(DohResolver::new())).await;
+        });
+        
+        let host = match hostname {
+            Some(hostname) => format!("{}:{}", hostname, proxy_addr.port()),
+            None => proxy_addr.to_string(),
+        };
+        let mut client = TcpStream::connect(proxy_addr).await.unwrap();
+        client.write_all(format!("GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", path, host).as_bytes()).await.unwrap();
+        
+        let mut response = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut response)).await.unwrap().unwrap();
+        String::from_utf8(response).unwrap()
+    }
+    
+    #[tokio::test]
+    async fn test_serves_pac_file() {
+        let response = local_response(ProxyConfig::default(), ProxyStats::new(), "/proxy.pac", None).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("Content-Type: application/x-ns-proxy-autoconfig"));
+        assert!(response.contains("return \"PROXY 127.0.0.1:"));
+        assert!(!response.contains("DIRECT"));
+        
+        let config = ProxyConfig {
+            only_hosts: vec!["discord.com".to_string()],
+            ..Default::default()
+        };
+        let response = local_response(config, ProxyStats::new(), "/proxy.pac", None).await;
+        assert!(response.contains("dnsDomainIs(host, \".discord.com\")"));
+        assert!(response.contains("return \"DIRECT\";"));
+    }
+    
+    #[tokio::test]
+    async fn test_serves_status_page() {
+        let stats = ProxyStats::new();
+        stats.connections_total.store(7, Ordering::Relaxed);
+        stats.bypass_applied.store(3, Ordering::Relaxed);
+        
+        let response = local_response(ProxyConfig::default(), stats, "/", Some("localhost")).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("text/html"));
+        assert!(response.contains("<li>Connections: 7 total, 0 active</li>"));
+        assert!(response.contains("<li>Bypass applied: 3</li>"));
+        
+        let response = local_response(auth_config(), ProxyStats::new(), "/", None).await;
+        assert!(response.starts_with("HTTP/1.1 401"));
+    }
+    
+    #[test]
+    fn test_local_request_path() {
+        let local: SocketAddr = "192.168.1.2:8844".parse().unwrap();
+        let listen = vec!["0.0.0.0:8844".parse().unwrap()];
+        let request = |line: &str, host: &str| format!("{}\r\nHost: {}\r\n\r\n", line, host);
+        
+        assert_eq!(local_request_path(&request("GET /proxy.pac HTTP/1.1", "192.168.1.2:8844"), Some(local), &listen), Some("/proxy.pac"));
+        assert_eq!(local_request_path(&request("GET /?x=1 HTTP/1.1", "localhost:8844"), Some(local), &listen), Some("/"));
+        assert_eq!(local_request_path(&request("GET / HTTP/1.1", "example.com"), Some(local), &listen), None);
+        assert_eq!(local_request_path(&request("GET / HTTP/1.1", "192.168.1.2:80"), Some(local), &listen), None);
+        assert_eq!(local_request_path(&request("GET http://192.168.1.2:8844/ HTTP/1.1", "192.168.1.2:8844"), Some(local), &listen), None);
+        assert_eq!(local_request_path(&request("POST / HTTP/1.1", "192.168.1.2:8844"), Some(local), &listen), None);
+    }
+    
+    #[tokio::test]
+    async fn test_stop_waits_for_drain() {
+        let remote = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let remote_addr = remote.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = remote.accept().await.unwrap();
+            let mut buf = [0u8; 64];
+            while let Ok(n) = stream.read(&mut buf).await {
+                if n == 0 {
+                    break;
+                }
+            }
+        });
+        
+        let proxy = Arc::new(BypassProxy::new(ProxyConfig {
+            listen_addrs: vec!["127.0.0.1:0".parse().unwrap()],
+            drain_timeout: Duration::from_millis(300),
+            install_signal_handler: false,
+            ..Default::default()
+        }));
+        let runner = tokio::spawn({
+            let proxy = proxy.clone();
+            async move { proxy.run().await }
+        });
+        
+        while !proxy.is_running() {
+            sleep(Duration::from_millis(5)).await;
+        }
+        let proxy_addr = proxy.local_addrs()[0];
+        assert_ne!(proxy_addr.port(), 0);
+        
+        let mut client = TcpStream::connect(proxy_addr).await.unwrap();
+        client.write_all(format!("CONNECT {} HTTP/1.1\r\n\r\n", remote_addr).as_bytes()).await.unwrap();
+        let mut response = [0u8; 39];
+        client.read_exact(&mut response).await.unwrap();
+        client.write_all(b"ping").await.unwrap();
+        while proxy.stats().connections_active.load(Ordering::Relaxed) == 0 {
+            sleep(Duration::from_millis(5)).await;
+        }
+        
+        let started = std::time::Instant::now();
+        tokio::time::timeout(Duration::from_secs(3), proxy.stop()).await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(300));
+        assert!(!proxy.is_running());
+        assert_eq!(proxy.stats().connections_active.load(Ordering::Relaxed), 0);
+        runner.await.unwrap().unwrap();
+        
+        let mut buf = [0u8; 16];
+        let n = tokio::time::timeout(Duration::from_secs(1), client.read(&mut buf)).await.unwrap().unwrap_or(0);
+        assert_eq!(n, 0);
+        assert!(TcpStream::connect(proxy_addr).await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn test_bypass_backend_lifecycle() {
+        let backend_config = |proxy_config| BackendConfig {
+            backend_settings: BackendSettings::Bypass(Box::new(proxy_config)),
+            ..Default::default()
+        };
+        
+        let mut backend = BypassBackend::new();
+        let mut config = backend_config(ProxyConfig {
+            listen_addrs: vec!["127.0.0.1:0".parse().unwrap()],
+            drain_timeout: Duration::from_millis(50),
+            ..Default::default()
+        });
+        config.engine_config.limits.log_rate_limit = 1;
+        let handle = backend.start(config).await.unwrap();
+        assert!(backend.is_running());
+        let log_limiter = &backend.proxy.as_ref().unwrap().stats().log_limiter;
+        assert!(log_limiter.allow());
+        assert!(!log_limiter.allow());
+        let addr = handle.listen_addrs()[0];
+        assert_ne!(addr.port(), 0);
+        
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(format!("GET /proxy.pac HTTP/1.1\r\nHost: {}\r\n\r\n", addr).as_bytes()).await.unwrap();
+        let mut response = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut response)).await.unwrap().unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 200 OK"));
+        
+        let paused = backend.proxy.as_ref().unwrap().pause_flag();
+        handle.pipeline.pause();
+        assert!(paused.load(Ordering::Relaxed));
+        handle.pipeline.resume();
+        assert!(!paused.load(Ordering::Relaxed));
+        
+        handle.shutdown().await.unwrap();
+        tokio::time::timeout(Duration::from_secs(3), async {
+            while backend.is_running() {
+                sleep(Duration::from_millis(5)).await;
+            }
+        }).await.unwrap();
+        assert!(TcpStream::connect(addr).await.is_err());
+        
+        let result = BypassBackend::new().start(backend_config(ProxyConfig {
+            listen_addrs: vec!["127.0.0.1:0".parse().unwrap()],
+            direct_hosts: vec!["a.example".to_string()],
+            only_hosts: vec!["b.example".to_string()],
+            ..Default::default()
+        })).await;
+        let Err(err) = result else { panic!("conflicting host lists accepted") };
+        assert!(err.to_string().contains("mutually exclusive"), "{}", err);
+    }
+    
+    #[tokio::test]
+    async fn test_bypass_backend_reports_to_handle() {
+        use engine::config::{MatchCriteria, Rule, RuleAction};
+        
+        let echo_addr = spawn_echo_server().await;
+        
+        let mut backend = BypassBackend::new();
+        let handle = backend.start(BackendConfig {
+            backend_settings: BackendSettings::Bypass(Box::new(ProxyConfig {
+                listen_addrs: vec!["127.0.0.1:0".parse().unwrap()],
+                drain_timeout: Duration::from_millis(50),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }).await.unwrap();
+        let proxy = backend.proxy.clone().unwrap();
+        let proxy_addr = handle.listen_addrs()[0];
+        
+        let relay = |payload: &'static [u8]| {
+            let stats = proxy.stats();
+            async move {
+                let mut client = TcpStream::connect(proxy_addr).await.unwrap();
+                client.write_all(format!("CONNECT {} HTTP/1.1\r\n\r\n", echo_addr).as_bytes()).await.unwrap();
+                let mut response = [0u8; 39];
+                client.read_exact(&mut response).await.unwrap();
+                client.write_all(payload).await.unwrap();
+                let mut echoed = vec![0u8; payload.len()];
+                let echoed = match client.read_exact(&mut echoed).await {
+                    Ok(_) => echoed,
+                    Err(_) => Vec::new(),
+                };
+                let _ = client.shutdown().await;
+                let mut rest = Vec::new();
+                let _ = tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut rest)).await.unwrap();
+                tokio::time::timeout(Duration::from_secs(5), async {
+                    while stats.connections_active.load(Ordering::Relaxed) > 0 {
+                        sleep(Duration::from_millis(5)).await;
+                    }
+                }).await.unwrap();
+                echoed
+            }
+        };
+        
+        assert_eq!(relay(b"hello").await, b"hello");
+        let snapshot = handle.stats_snapshot();
+        assert_eq!((snapshot.bytes_out, snapshot.bytes_in, snapshot.flows_created), (5, 5, 1));
+        let flows = handle.pipeline.flow_cache().iter_snapshot();
+        assert_eq!(flows.len(), 1);
+        assert_eq!(flows[0].hostname.as_deref(), Some("127.0.0.1"));
+        assert_eq!(flows[0].byte_count, 10);
+        let hosts = handle.top_hosts(10);
+        assert_eq!(hosts.len(), 1);
+        assert_eq!((hosts[0].host.as_str(), hosts[0].counters.bytes_up), ("127.0.0.1", 5));
+        
+        handle.update_rules(vec![Rule {
+            name: "drop-echo".to_string(),
+            enabled: true,
+            priority: 10,
+            match_criteria: MatchCriteria {
+                dst_ports: Some(vec![echo_addr.port()]),
+                ..Default::default()
+            },
+            action: RuleAction::Drop,
+            transforms: Vec::new(),
+            overrides: HashMap::new(),
+        }]).unwrap();
+        assert!(relay(b"hello").await.is_empty());
+        assert_eq!(handle.pipeline.rule_stats()[0].hits, 1);
+        assert_eq!(handle.stats_snapshot().packets_dropped, 1);
+        
+        handle.reset_stats();
+        assert_eq!(handle.stats_snapshot().bytes_out, 0);
+        
+        let mut config = engine::Config::default();
+        config.backend.bypass.fake_packet_ttl = 9;
+        config.backend.timeout_secs = 7;
+        handle.reload_config(config).unwrap();
+        let reloaded = proxy.config.read().clone();
+        assert_eq!(reloaded.bypass.fake_packet_ttl, 9);
+        assert_eq!(reloaded.idle_timeout, Duration::from_secs(7));
+        
+        handle.shutdown().await.unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_host_stats_per_sni() {
+        let echo_addr = spawn_echo_server().await;
+        
+        let proxy = Arc::new(BypassProxy::new(ProxyConfig {
+            listen_addrs: vec!["127.0.0.1:0".parse().unwrap()],
+            install_signal_handler: false,
+            ..Default::default()
+        }));
+        let runner = tokio::spawn({
+            let proxy = proxy.clone();
+            async move { proxy.run().await }
+        });
+        while !proxy.is_running() {
+            sleep(Duration::from_millis(5)).await;
+        }
+        let proxy_addr = proxy.local_addrs()[0];
+        
+        let mut hello_len = HashMap::new();
+        for sni in ["discord.com", "www.youtube.com", "discord.com", "Discord.com"] {
+            let hello = engine::tls::build_client_hello(sni, &[7; 32]);
+            *hello_len.entry(sni.to_ascii_lowercase()).or_insert(0) += hello.len() as u64;
+            
+            let mut client = TcpStream::connect(proxy_addr).await.unwrap();
+            client.write_all(format!("CONNECT {} HTTP/1.1\r\n\r\n", echo_addr).as_bytes()).await.unwrap();
+            let mut response = [0u8; 39];
+            client.read_exact(&mut response).await.unwrap();
+            client.write_all(&hello).await.unwrap();
+            let mut echoed = vec![0u8; hello.len()];
+            client.read_exact(&mut echoed).await.unwrap();
+            client.shutdown().await.unwrap();
+            let mut rest = Vec::new();
+            tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut rest)).await.unwrap().unwrap();
+        }
+        
+        tokio::time::timeout(Duration::from_secs(5), proxy.stop()).await.unwrap();
+        runner.await.unwrap().unwrap();
+        
+        let hosts = &proxy.stats().hosts;
+        assert_eq!(hosts.len(), 2);
+        let discord = hosts.get("discord.com").unwrap();
+        assert_eq!(discord.connections, 3);
+        assert_eq!(discord.bypass_applied, 3);
+        assert_eq!(discord.failures, 0);
+        assert_eq!(discord.bytes_up, hello_len["discord.com"]);
+        assert_eq!(discord.bytes_down, hello_len["discord.com"]);
+        
+        let youtube = hosts.get("www.youtube.com").unwrap();
+        assert_eq!((youtube.connections, youtube.bytes_up), (1, hello_len["www.youtube.com"]));
+        assert_eq!(hosts.top(1)[0].host, "discord.com");
+    }
+    
+    #[test]
+    fn test_insert_header() {
+        let raw = b"GET / HTTP/1.1\r\nHost: a\r\n\r\nbody";
+        assert_eq!(
+            insert_header(raw, "Proxy-Authorization: Basic dXNlcjpzZWNyZXQ="),
+            b"GET / HTTP/1.1\r\nHost: a\r\nProxy-Authorization: Basic dXNlcjpzZWNyZXQ=\r\n\r\nbody".to_vec()
+        );
+    }
+    
+    #[test]
+    fn test_http_framing() {
+        assert_eq!(request_body_framing("POST / HTTP/1.1\r\nContent-Length: 5\r\n"), BodyFraming::Length(5));
+        assert_eq!(request_body_framing("GET / HTTP/1.1\r\nHost: a\r\n"), BodyFraming::Empty);
+        assert_eq!(request_body_framing("POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n"), BodyFraming::Chunked);
+        
+        assert_eq!(response_body_framing("HTTP/1.1 200 OK\r\n", Some(200), false), BodyFraming::UntilClose);
+        assert_eq!(response_body_framing("HTTP/1.1 200 OK\r\nContent-Length: 10\r\n", Some(200), true), BodyFraming::Empty);
+        assert_eq!(response_body_framing("HTTP/1.1 304 Not Modified\r\n", Some(304), false), BodyFraming::Empty);
+        
+        assert!(wants_keep_alive("GET / HTTP/1.1\r\nHost: a\r\n"));
+        assert!(!wants_keep_alive("GET / HTTP/1.1\r\nConnection: close\r\n"));
+        assert!(!wants_keep_alive("GET / HTTP/1.0\r\n"));
+        assert!(wants_keep_alive("GET / HTTP/1.0\r\nProxy-Connection: Keep-Alive\r\n"));
+    }
+    
+    #[tokio::test]
+    async fn test_request_head_too_large() {
+        let proxy_addr = spawn_proxy(ProxyConfig::default()).await;
+        let mut client = TcpStream::connect(proxy_addr).await.unwrap();
+        
+        let mut request = b"GET http://example.com/ HTTP/1.1\r\n".to_vec();
+        request.extend(std::iter::repeat_n(b'a', MAX_REQUEST_HEAD_SIZE + 1));
+        let _ = client.write_all(&request).await;
+        
+        let mut response = [0u8; 12];
+        client.read_exact(&mut response).await.unwrap();
+        assert_eq!(&response, b"HTTP/1.1 431");
+    }
+    
+    #[test]
+    fn test_bypass_host_lists() {
+        let direct = ProxyConfig {
+            direct_hosts: vec!["intranet.local".to_string(), "*.corp.example".to_string()],
+            ..Default::default()
+        };
+        assert!(!direct.bypass_host("intranet.local"));
+        assert!(!direct.bypass_host("wiki.intranet.local"));
+        assert!(!direct.bypass_host("git.corp.example"));
+        assert!(direct.bypass_host("discord.com"));
+        assert!(direct.bypass_host("notintranet.local"));
+        
+        let only = ProxyConfig {
+            only_hosts: vec!["discord.com".to_string(), "discord.gg".to_string()],
+            ..Default::default()
+        };
+        assert!(only.bypass_host("discord.com"));
+        assert!(only.bypass_host("gateway.discord.gg"));
+        assert!(!only.bypass_host("example.com"));
+        
+        assert_eq!(target_host("example.com:443"), "example.com");
+        assert_eq!(target_host("[::1]:8080"), "::1");
+    }
+    
+    #[tokio::test]
+    async fn test_direct_host_skips_bypass() {
+        let echo = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let echo_addr = echo.local_addr().unwrap();
+        let first_read = tokio::spawn(async move {
+            let (mut stream, _) = echo.accept().await.unwrap();
+            let mut buf = vec![0u8; 4096];
+            let n = stream.read(&mut buf).await.unwrap();
+            buf.truncate(n);
+            buf
+        });
+        
+        let stats = ProxyStats::new();
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+        let config = ProxyConfig {
+            bypass: BypassConfig {
+                fragment_delay_us: 50_000,
+                ..Default::default()
+            },
+            direct_hosts: vec!["discord.com".to_string()],
+            ..Default::default()
+        };
+        let stats_clone = stats.clone();
+        tokio::spawn(async move {
+            let (stream, peer_addr) = proxy.accept().await.unwrap();
+            let _ = handle_client(stream, &mut ConnectionRecord::new(peer_addr), config, stats_clone, Arc::new(DohResolver::new())).await;
+        });
+        
+        let mut client = TcpStream::connect(proxy_addr).await.unwrap();
+        client.write_all(format!("CONNECT {} HTTP/1.1\r\n\r\n", echo_addr).as_bytes()).await.unwrap();
+        let mut response = [0u8; 39];
+        client.read_exact(&mut response).await.unwrap();
+        
+        let hello = sample_client_hello();
+        client.write_all(&hello).await.unwrap();
+        
+        let received = tokio::time::timeout(Duration::from_secs(5), first_read).await.unwrap().unwrap();
+        assert_eq!(received, hello);
+        assert_eq!(stats.direct_connections.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.bypass_applied.load(Ordering::Relaxed), 0);
+    }
+    
+    #[test]
+    fn test_base64_encode() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"user:secret"), "dXNlcjpzZWNyZXQ=");
+    }
+    
+    #[test]
+    fn test_strip_proxy_headers() {
+        let raw = b"GET / HTTP/1.1\r\nHost: example.com\r\nProxy-Authorization: Basic dXNlcjpzZWNyZXQ=\r\nProxy-Connection: keep-alive\r\n\r\nbody";
+        assert_eq!(strip_proxy_headers(raw), b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\nbody");
+    }
+    
     #[test]
     fn test_default_config() {
         let config = ProxyConfig::default();
-        assert_eq!(config.listen_addr.port(), 8844);
+        assert_eq!(config.listen_addrs[0].port(), 8844);
         assert!(config.bypass.fragment_sni);
         assert!(config.bypass.fragment_http_host);
     }
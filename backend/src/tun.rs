@@ -7,7 +7,7 @@ use parking_lot::Mutex;
 use tokio::sync::mpsc;
 use tracing::{debug, info};
 
-use engine::{FlowKey, Pipeline, Stats};
+use engine::{FlowKey, Pipeline, Stats, StatsHistory};
 use engine::config::Protocol;
 
 use crate::error::{BackendError, Result};
@@ -30,6 +30,7 @@ impl TunBackend {
         }
     }
 
+    #[allow(dead_code)]
     fn parse_ipv4_flow_key(data: &[u8]) -> Option<FlowKey> {
         if data.len() < 20 {
             return None;
@@ -118,7 +119,7 @@ impl Backend for TunBackend {
         let stats = Arc::new(Stats::new());
         let pipeline = Arc::new(
             Pipeline::new(config.engine_config, stats.clone())
-                .map_err(|e| BackendError::Engine(e))?
+                .map_err(BackendError::Engine)?
         );
 
         let (shutdown_tx, mut shutdown_rx) = mpsc::channel::<()>(1);
@@ -127,15 +128,19 @@ impl Backend for TunBackend {
         self.shutdown_tx = Some(shutdown_tx.clone());
         self.running.store(true, Ordering::SeqCst);
 
+        let history = Arc::new(StatsHistory::default());
         let running = self.running.clone();
         let pipeline_clone = pipeline.clone();
-        let _stats_clone = stats.clone();
+        let history_clone = history.clone();
+        let stats_clone = stats.clone();
 
         let handle = tokio::spawn(async move {
             info!("TUN backend task started");
             let mut cleanup_interval = tokio::time::interval(
                 std::time::Duration::from_secs(30)
             );
+            let mut schedule_interval = tokio::time::interval(engine::pipeline::SCHEDULE_REFRESH_INTERVAL);
+            let mut history_interval = tokio::time::interval(engine::stats::HISTORY_SAMPLE_INTERVAL);
             
             loop {
                 tokio::select! {
@@ -149,6 +154,12 @@ impl Backend for TunBackend {
                             debug!(evicted, "Cleaned up expired flows");
                         }
                     }
+                    _ = schedule_interval.tick() => {
+                        pipeline_clone.refresh_schedules();
+                    }
+                    _ = history_interval.tick() => {
+                        history_clone.sample(&stats_clone);
+                    }
                 }
             }
 
@@ -161,7 +172,12 @@ impl Backend for TunBackend {
         Ok(BackendHandle {
             shutdown_tx,
             stats,
+            history,
             pipeline,
+            connections: None,
+            listen_addrs: Vec::new(),
+            dns: None,
+            proxy: None,
         })
     }
 
@@ -301,7 +317,7 @@ mod tests {
             backend_settings: BackendSettings::Tun(TunSettings::default()),
         };
         
-        let handle = backend.start(config).await.unwrap();
+        let _handle = backend.start(config).await.unwrap();
         assert!(backend.is_running());
         
         tokio::time::sleep(std::time::Duration::from_millis(10)).await;
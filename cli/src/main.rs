@@ -1,13 +1,17 @@
+use std::net::SocketAddr;
 use std::path::PathBuf;
 
 use anyhow::{Context, Result};
-use clap::{Parser, Subcommand, ValueEnum};
-use tracing::{info, Level};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use tracing::{info, warn, Level};
 use tracing_subscriber::{fmt, EnvFilter};
 
-use backend::{Backend, BypassProxy, ProxyConfig};
+use backend::{BypassProxy, ProxyConfig};
+use backend::dns_proxy::DEFAULT_DNS_LISTEN;
 use control::{ControlClient, ControlServer, ServerConfig};
-use engine::{BypassConfig, Config};
+use control::server::{env_filter_reloader, LogReloader};
+use engine::{BypassConfig, Config, ConfigPreset, DohProvider, FamilyPreference};
+use engine::config::Protocol;
 
 #[derive(Parser)]
 #[command(name = "turkeydpi")]
@@ -24,45 +28,179 @@ struct Cli {
 
     #[arg(long, default_value = "/tmp/turkeydpi.sock")]
     socket: PathBuf,
+    
+    /// Keep traffic counters in FILE across daemon restarts
+    #[arg(long, value_name = "FILE")]
+    stats_file: Option<PathBuf>,
 
     #[command(subcommand)]
     command: Commands,
 }
 
-#[derive(Subcommand)]
-enum Commands {
-    Bypass {
-        #[arg(short, long, default_value = "127.0.0.1:8844")]
-        listen: String,
+#[derive(Args)]
+struct BypassArgs {
+    #[arg(short, long, default_value = "127.0.0.1:8844")]
+    listen: String,
 
-        #[arg(short, long, default_value = "aggressive")]
-        preset: IspPreset,
+    #[arg(short, long, default_value = "aggressive")]
+    preset: IspPreset,
 
-        #[arg(short, long)]
-        verbose: bool,
-    },
+    #[arg(long, value_delimiter = ',')]
+    exclude: Vec<String>,
+
+    #[arg(long)]
+    adaptive: bool,
+
+    #[arg(long, value_parser = parse_auth)]
+    auth: Option<(String, String)>,
+
+    #[arg(long, value_delimiter = ',', conflicts_with = "only")]
+    direct: Vec<String>,
+
+    #[arg(long, value_delimiter = ',')]
+    only: Vec<String>,
+
+    #[arg(long, value_name = "FILE")]
+    access_log: Option<PathBuf>,
+
+    #[arg(long, value_name = "MB", default_value = "10")]
+    access_log_max_mb: u64,
+
+    #[arg(long, value_name = "SECS")]
+    stats_interval: Option<u64>,
+
+    #[arg(long = "doh", value_name = "URL")]
+    doh: Vec<DohProvider>,
+
+    #[arg(long, value_name = "PREFERENCE", default_value = "v4-first")]
+    ip_family: FamilyPreference,
+
+    #[arg(long, value_name = "FILE")]
+    hosts: Option<PathBuf>,
+
+    #[arg(long, value_name = "ADDR", num_args = 0..=1, default_missing_value = DEFAULT_DNS_LISTEN)]
+    dns_listen: Option<SocketAddr>,
+
+    #[arg(long)]
+    fragment_doh: bool,
+
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    Bypass(Box<BypassArgs>),
 
     Run {
+        /// Start the backend from the config's [backend] section
         #[arg(long)]
         proxy: bool,
 
-        #[arg(long, default_value = "127.0.0.1:1080")]
-        listen: String,
+        /// Override the backend's listen addresses, comma-separated
+        #[arg(long)]
+        listen: Option<String>,
+
+        /// Reload the --config file automatically when it changes
+        #[arg(long)]
+        watch: bool,
     },
 
-    Start,
+    /// Start the engine, with the config's backend unless --backend is given
+    Start {
+        #[arg(long, value_name = "KIND")]
+        backend: Option<StartBackend>,
+        
+        /// Addresses for the proxy to listen on, comma-separated
+        #[arg(long, value_name = "ADDR", requires = "backend")]
+        listen: Option<String>,
+    },
     Stop,
+    /// Let traffic through untouched, keeping the backend and its connections up
+    Pause,
+    /// Apply the bypass again after a pause
+    Resume,
+    /// Stop the backend and make the daemon exit
+    Shutdown {
+        /// Seconds, at most 600, to let open connections finish before the backend stops
+        #[arg(long, value_name = "SECS")]
+        grace: Option<u64>,
+    },
     Status,
+    /// Show the engine's most recent state transitions
+    StateHistory,
     Health,
     Stats,
     ResetStats,
+    Rules,
+    Hosts {
+        /// Number of hosts to show, busiest first
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+    },
+    Flows {
+        /// Number of flows to show, busiest first
+        #[arg(long)]
+        limit: Option<usize>,
+        
+        /// Only flows whose hostname contains this, or whose address starts with it
+        #[arg(long, value_name = "HOST_OR_IP")]
+        filter: Option<String>,
+    },
+    /// Show the daemon's log filter, or change it without a restart
+    LogLevel {
+        /// A level, or filter directives such as "engine=trace,backend=info"
+        level: Option<String>,
+        
+        /// Change only this target's level
+        #[arg(long, requires = "level")]
+        target: Option<String>,
+    },
+    /// Print engine notifications as they happen
+    Watch {
+        /// Only these kinds: state_changed, config_reloaded, error, stats_update
+        #[arg(long, value_delimiter = ',')]
+        kinds: Vec<String>,
+    },
+    Rule {
+        #[command(subcommand)]
+        action: RuleAction,
+    },
+    Explain {
+        #[arg(value_name = "ADDR:PORT")]
+        target: SocketAddr,
+
+        #[arg(long)]
+        host: Option<String>,
+
+        #[arg(long, value_enum, default_value = "tcp")]
+        protocol: ExplainProtocol,
+    },
+    Dns {
+        #[command(subcommand)]
+        action: DnsAction,
+    },
     Validate {
         #[arg(value_name = "FILE")]
         config: PathBuf,
     },
+    /// Reload the daemon's config, by default from the file it last loaded
     Reload {
         #[arg(value_name = "FILE")]
-        config: PathBuf,
+        config: Option<PathBuf>,
+        
+        /// Read the file here and send its contents, for a daemon that cannot see it
+        #[arg(long)]
+        push: bool,
+    },
+    /// Write the daemon's running config to disk, by default over the file it last loaded
+    Save {
+        #[arg(value_name = "PATH")]
+        path: Option<PathBuf>,
+        
+        /// toml or json; by default json for .json files and toml otherwise
+        #[arg(long)]
+        format: Option<String>,
     },
     GenConfig {
         #[arg(long, default_value = "toml")]
@@ -70,13 +208,77 @@ enum Commands {
 
         #[arg(short, long)]
         output: Option<PathBuf>,
+
+        /// Emit a complete ISP- or use-case-tuned config (see --list-presets)
+        #[arg(long, value_name = "NAME")]
+        preset: Option<ConfigPreset>,
+
+        #[arg(long, conflicts_with = "preset")]
+        list_presets: bool,
+    },
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum ExplainProtocol {
+    Tcp,
+    Udp,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum StartBackend {
+    Socks5,
+    #[value(alias = "http-connect")]
+    Http,
+    #[value(alias = "bypass-http")]
+    Bypass,
+    Tun,
+}
+
+impl StartBackend {
+    fn to_spec(&self, listen: Vec<std::net::SocketAddr>) -> Result<control::BackendSpec> {
+        Ok(match self {
+            StartBackend::Socks5 => control::BackendSpec::Socks5 { listen, max_connections: None },
+            StartBackend::Http => control::BackendSpec::HttpConnect { listen, max_connections: None },
+            StartBackend::Bypass => control::BackendSpec::BypassHttp { listen },
+            StartBackend::Tun if !listen.is_empty() => anyhow::bail!("--listen does not apply to the TUN backend"),
+            StartBackend::Tun => control::BackendSpec::Tun {
+                device_name: None,
+                mtu: None,
+                address: None,
+                netmask: None,
+            },
+        })
+    }
+}
+
+#[derive(Subcommand)]
+enum RuleAction {
+    Add {
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+    },
+    Rm {
+        name: String,
+    },
+    Enable {
+        name: String,
     },
+    Disable {
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum DnsAction {
+    Flush,
+    Dump,
 }
 
-fn setup_logging(level: &str, json: bool) -> Result<()> {
+fn setup_logging(level: &str, json: bool) -> Result<(String, LogReloader)> {
     let level = level.parse::<Level>().unwrap_or(Level::INFO);
     let filter = EnvFilter::from_default_env()
         .add_directive(level.into());
+    let directives = filter.to_string();
 
     let subscriber = fmt::Subscriber::builder()
         .with_env_filter(filter)
@@ -85,86 +287,178 @@ fn setup_logging(level: &str, json: bool) -> Result<()> {
         .with_file(true)
         .with_line_number(true);
 
-    if json {
-        subscriber.json().init();
+    let reloader = if json {
+        let subscriber = subscriber.json().with_filter_reloading();
+        let handle = subscriber.reload_handle();
+        subscriber.init();
+        env_filter_reloader(handle)
     } else {
+        let subscriber = subscriber.with_filter_reloading();
+        let handle = subscriber.reload_handle();
         subscriber.init();
-    }
+        env_filter_reloader(handle)
+    };
 
-    Ok(())
+    Ok((directives, reloader))
 }
 
-async fn run_daemon(cli: &Cli, proxy: bool, listen: &str) -> Result<()> {
+async fn run_daemon(
+    cli: &Cli,
+    proxy: bool,
+    listen: Option<&str>,
+    watch: bool,
+    logging: Option<(String, LogReloader)>,
+) -> Result<()> {
     info!(
         version = env!("CARGO_PKG_VERSION"),
         "Starting TurkeyDPI engine"
     );
 
-    let config = if let Some(ref path) = cli.config {
-        Config::load_from_file(path)
-            .with_context(|| format!("Failed to load config from {}", path.display()))?
-    } else {
-        Config::default()
-    };
+    let config = Config::load_with_env(cli.config.as_deref())
+        .with_context(|| match cli.config {
+            Some(ref path) => format!("Failed to load config from {}", path.display()),
+            None => "Failed to load config".to_string(),
+        })?;
+    for warning in config.warnings() {
+        warn!("{}", warning);
+    }
 
     info!("Configuration loaded successfully");
 
     let server_config = ServerConfig {
         socket_path: cli.socket.clone(),
+        stats_file: cli.stats_file.clone(),
         ..Default::default()
     };
 
     let mut server = ControlServer::new(server_config, config.clone());
+    if let Some(ref path) = cli.config {
+        server.set_config_path(std::fs::canonicalize(path).unwrap_or_else(|_| path.clone()));
+    }
+    if let Some((filter, reloader)) = logging {
+        server.set_log_reloader(filter, reloader);
+    }
     server.start().await?;
 
     info!(socket = %cli.socket.display(), "Control server started");
 
     if proxy {
-        info!(listen = %listen, "Starting proxy backend");
-        
-        let listen_addr: std::net::SocketAddr = listen.parse()
-            .with_context(|| format!("Invalid listen address: {}", listen))?;
+        let mut backend_config = config.backend.clone();
+        if let Some(listen) = listen {
+            backend_config.listen = parse_listen_addrs(listen)?;
+        }
+        let backend_settings = backend::BackendSettings::from_config(&backend_config);
+        if !backend_settings.is_supported() {
+            anyhow::bail!("The {} backend is not supported on this platform", backend_config.kind);
+        }
+        info!(kind = %backend_config.kind, listen = ?backend_config.listen, "Starting backend");
 
         let backend_config = backend::BackendConfig {
             engine_config: config,
             max_queue_size: 1000,
-            backend_settings: backend::BackendSettings::Proxy(backend::ProxySettings {
-                listen_addr,
-                ..Default::default()
-            }),
+            backend_settings,
         };
 
-        let mut backend = backend::ProxyBackend::new();
+        let mut backend = backend_config.backend_settings.backend();
         let handle = backend.start(backend_config).await?;
 
-        info!(addr = %listen_addr, "Proxy backend started");
+        info!(addrs = ?handle.listen_addrs(), "Backend started");
 
-        tokio::signal::ctrl_c().await?;
-        info!("Received shutdown signal");
+        server.set_external_pipeline(handle.pipeline.clone());
+        let watcher = watch_config(cli, &server, watch, Some(handle.pipeline.clone()));
+        let persister = cli.stats_file.clone().map(|path| persist_stats(path, &handle));
 
-        
+        // The server doesn't own this backend, so the grace is ours to honour.
+        let grace = wait_for_shutdown(&server).await?;
+        if let (Some(grace), Some(connections)) = (grace, handle.connections.as_ref()) {
+            connections.drain(grace).await;
+        }
+
+        if let Some(watcher) = watcher {
+            watcher.abort();
+        }
+        if let Some(persister) = persister {
+            persister.abort();
+        }
+        if let Some(ref path) = cli.stats_file {
+            if let Err(e) = handle.stats().save_to(path) {
+                warn!(path = %path.display(), error = %e, "Failed to save stats");
+            }
+        }
         handle.shutdown().await?;
         backend.stop().await?;
     } else {
-        info!("Running in control-only mode (use --proxy to start proxy backend)");
+        info!("Running in control-only mode (use --proxy to start the configured backend)");
         
-        tokio::signal::ctrl_c().await?;
-        info!("Received shutdown signal");
+        let watcher = watch_config(cli, &server, watch, None);
+
+        wait_for_shutdown(&server).await?;
+
+        if let Some(watcher) = watcher {
+            watcher.abort();
+        }
     }
 
-    server.stop().await?;
+    if server.is_running() {
+        server.stop().await?;
+    }
     info!("Shutdown complete");
 
     Ok(())
 }
 
-async fn send_command<F, T>(socket: &PathBuf, action: F) -> Result<T>
-where
-    F: FnOnce(&mut ControlClient) -> std::pin::Pin<Box<dyn std::future::Future<Output = control::Result<T>> + Send + '_>>,
-{
-    let mut client = ControlClient::new(socket);
-    action(&mut client).await
-        .with_context(|| format!("Failed to connect to {}", socket.display()))
+/// Waits for Ctrl-C or for a client to send Shutdown, returning the grace
+/// the client asked for.
+async fn wait_for_shutdown(server: &ControlServer) -> Result<Option<std::time::Duration>> {
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => {
+            result?;
+            info!("Received shutdown signal");
+            Ok(None)
+        }
+        grace = server.shutdown_signal() => {
+            info!("Shutdown requested by a control client");
+            Ok(Some(grace))
+        }
+    }
+}
+
+fn watch_config(
+    cli: &Cli,
+    server: &ControlServer,
+    watch: bool,
+    pipeline: Option<std::sync::Arc<engine::Pipeline>>,
+) -> Option<tokio::task::JoinHandle<()>> {
+    if !watch {
+        return None;
+    }
+    match cli.config {
+        Some(ref path) => Some(server.watch_config(path.clone(), control::DEFAULT_WATCH_INTERVAL, pipeline)),
+        None => {
+            warn!("--watch has no effect without --config");
+            None
+        }
+    }
+}
+
+fn persist_stats(path: PathBuf, handle: &backend::BackendHandle) -> tokio::task::JoinHandle<()> {
+    match handle.stats().load_from(&path) {
+        Ok(()) => handle.history.clear(),
+        Err(engine::EngineError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => warn!(path = %path.display(), error = %e, "Ignoring saved stats"),
+    }
+    
+    let stats = handle.stats().clone();
+    tokio::spawn(async move {
+        let period = control::server::STATS_PERSIST_INTERVAL;
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        loop {
+            interval.tick().await;
+            if let Err(e) = stats.save_to(&path) {
+                warn!(path = %path.display(), error = %e, "Failed to save stats");
+            }
+        }
+    })
 }
 
 #[derive(Debug, Clone, ValueEnum)]
@@ -190,18 +484,77 @@ impl IspPreset {
     }
 }
 
-async fn run_bypass(listen: &str, preset: &IspPreset, verbose: bool) -> Result<()> {
-    let listen_addr = listen.parse()
-        .with_context(|| format!("Invalid listen address: {}", listen))?;
+fn parse_listen_addrs(listen: &str) -> Result<Vec<std::net::SocketAddr>> {
+    listen
+        .split(',')
+        .map(str::trim)
+        .filter(|addr| !addr.is_empty())
+        .map(|addr| addr.parse().with_context(|| format!("Invalid listen address: {}", addr)))
+        .collect()
+}
+
+fn parse_auth(value: &str) -> std::result::Result<(String, String), String> {
+    match value.split_once(':') {
+        Some((user, pass)) if !user.is_empty() => Ok((user.to_string(), pass.to_string())),
+        _ => Err("expected user:pass".to_string()),
+    }
+}
+
+fn expand_host_list(values: &[String]) -> Result<Vec<String>> {
+    let mut hosts = Vec::new();
+    for value in values {
+        match value.strip_prefix('@') {
+            Some(path) => {
+                let content = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read host list: {}", path))?;
+                hosts.extend(
+                    content
+                        .lines()
+                        .map(str::trim)
+                        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                        .map(String::from),
+                );
+            }
+            None => {
+                let host = value.trim();
+                if !host.is_empty() {
+                    hosts.push(host.to_string());
+                }
+            }
+        }
+    }
+    Ok(hosts)
+}
+
+async fn run_bypass(args: &BypassArgs, json_logs: bool) -> Result<()> {
+    let listen_addrs = parse_listen_addrs(&args.listen)?;
+    
+    let mut bypass = args.preset.to_bypass_config();
+    bypass.exclude_hosts = args.exclude.clone();
     
-    let config = ProxyConfig {
-        listen_addr,
-        bypass: preset.to_bypass_config(),
-        verbose,
+    let mut config = ProxyConfig {
+        listen_addrs,
+        bypass,
+        verbose: args.verbose,
+        adaptive: args.adaptive,
+        auth: args.auth.clone(),
+        direct_hosts: expand_host_list(&args.direct)?,
+        only_hosts: expand_host_list(&args.only)?,
+        access_log: args.access_log.clone(),
+        access_log_max_bytes: args.access_log_max_mb * 1024 * 1024,
+        stats_interval: args.stats_interval.filter(|&secs| secs > 0).map(std::time::Duration::from_secs),
+        print_stats_summary: !json_logs,
+        family_preference: args.ip_family,
+        hosts_file: args.hosts.clone(),
+        dns_listen: args.dns_listen,
+        fragment_doh: args.fragment_doh,
         ..Default::default()
     };
+    if !args.doh.is_empty() {
+        config.doh_providers = args.doh.clone();
+    }
     
-    let mut proxy = BypassProxy::new(config);
+    let proxy = BypassProxy::new(config);
     proxy.run().await?;
     
     Ok(())
@@ -211,27 +564,34 @@ async fn run_bypass(listen: &str, preset: &IspPreset, verbose: bool) -> Result<(
 async fn main() -> Result<()> {
     let cli = Cli::parse();
 
-    if !matches!(cli.command, Commands::GenConfig { .. } | Commands::Bypass { .. }) {
-        setup_logging(&cli.log_level, cli.json_logs)?;
-    }
+    let logging = if !matches!(cli.command, Commands::GenConfig { .. } | Commands::Bypass(_)) {
+        Some(setup_logging(&cli.log_level, cli.json_logs)?)
+    } else {
+        None
+    };
 
     match &cli.command {
-        Commands::Bypass { listen, preset, verbose } => {
-            if *verbose {
+        Commands::Bypass(args) => {
+            if args.verbose {
                 setup_logging("debug", cli.json_logs)?;
             } else {
                 setup_logging("info", cli.json_logs)?;
             }
-            run_bypass(listen, preset, *verbose).await?;
+            run_bypass(args, cli.json_logs).await?;
         }
 
-        Commands::Run { proxy, listen } => {
-            run_daemon(&cli, *proxy, listen).await?;
+        Commands::Run { proxy, listen, watch } => {
+            run_daemon(&cli, *proxy, listen.as_deref(), *watch, logging).await?;
         }
 
-        Commands::Start => {
+        Commands::Start { backend, listen } => {
+            let listen = match listen {
+                Some(listen) => parse_listen_addrs(listen)?,
+                None => Vec::new(),
+            };
+            let spec = backend.as_ref().map(|backend| backend.to_spec(listen)).transpose()?;
             let mut client = ControlClient::new(&cli.socket);
-            client.start().await?;
+            client.start(spec).await?;
             println!("Engine started");
         }
 
@@ -240,6 +600,24 @@ async fn main() -> Result<()> {
             client.stop().await?;
             println!("Engine stopped");
         }
+        
+        Commands::Pause => {
+            let mut client = ControlClient::new(&cli.socket);
+            client.pause().await?;
+            println!("Engine paused; traffic passes through unmodified");
+        }
+        
+        Commands::Resume => {
+            let mut client = ControlClient::new(&cli.socket);
+            client.resume().await?;
+            println!("Engine resumed");
+        }
+        
+        Commands::Shutdown { grace } => {
+            let mut client = ControlClient::new(&cli.socket);
+            client.shutdown(*grace).await?;
+            println!("Daemon shutting down");
+        }
 
         Commands::Status => {
             let mut client = ControlClient::new(&cli.socket);
@@ -249,6 +627,7 @@ async fn main() -> Result<()> {
             println!("  State: {:?}", status.state);
             println!("  Running: {}", status.running);
             println!("  Active flows: {}", status.active_flows);
+            println!("  Flow memory: {}", format_bytes(status.flow_memory_bytes));
             println!("  Packets processed: {}", status.packets_processed);
             println!("  Bytes processed: {}", format_bytes(status.bytes_processed));
             println!("  Errors: {}", status.error_count);
@@ -258,6 +637,9 @@ async fn main() -> Result<()> {
             if let Some(ref path) = status.config_path {
                 println!("  Config: {}", path);
             }
+            if !status.listen_addrs.is_empty() {
+                println!("  Listening on: {}", status.listen_addrs.join(", "));
+            }
         }
 
         Commands::Health => {
@@ -273,6 +655,28 @@ async fn main() -> Result<()> {
                 println!("  Backend: {}", backend);
             }
             println!("  OS: {} ({})", health.system.os, health.system.arch);
+            println!("  Control clients: {}", health.connected_clients);
+        }
+        
+        Commands::StateHistory => {
+            let mut client = ControlClient::new(&cli.socket);
+            let history = match client.send(control::Command::GetStateHistory).await?.data {
+                control::ResponseData::StateHistory(history) => history,
+                control::ResponseData::Error { message } => anyhow::bail!(message),
+                _ => anyhow::bail!("Unexpected response"),
+            };
+            
+            if history.is_empty() {
+                println!("No state changes yet");
+            }
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64;
+            for transition in history {
+                let ago = now.saturating_sub(transition.timestamp) / 1000;
+                println!("  {:>6}s ago  {:?} -> {:?}", ago, transition.from, transition.to);
+            }
         }
 
         Commands::Stats => {
@@ -281,6 +685,7 @@ async fn main() -> Result<()> {
             
             if let control::ResponseData::Stats(stats) = response.data {
                 println!("Statistics:");
+                println!("  Uptime:           {}s", stats.uptime_secs);
                 println!("  Packets in:       {}", stats.packets_in);
                 println!("  Packets out:      {}", stats.packets_out);
                 println!("  Bytes in:         {}", format_bytes(stats.bytes_in));
@@ -289,12 +694,41 @@ async fn main() -> Result<()> {
                 println!("  Packets matched:  {}", stats.packets_matched);
                 println!("  Transformed:      {}", stats.packets_transformed);
                 println!("  Transform errors: {}", stats.transform_errors);
+                println!("  Rule errors:      {}", stats.rule_compile_errors);
+                println!("  Invalid packets:  {}", stats.invalid_packets);
                 println!("  Active flows:     {}", stats.active_flows);
                 println!("  Flows created:    {}", stats.flows_created);
                 println!("  Flows evicted:    {}", stats.flows_evicted);
+                println!("  Memory evictions: {}", stats.memory_evictions);
                 println!("  Fragments gen:    {}", stats.fragments_generated);
                 println!("  Total jitter:     {}ms", stats.total_jitter_ms);
                 println!("  Decoys sent:      {}", stats.decoys_sent);
+                println!(
+                    "  Overhead:         {:.1}% ({} padding, {} decoys, {} extra writes)",
+                    stats.overhead_ratio() * 100.0,
+                    format_bytes(stats.padding_bytes_added),
+                    format_bytes(stats.decoy_bytes_sent),
+                    stats.fragment_overhead_writes,
+                );
+                println!("  SOCKS via DoH:    {}", stats.socks_doh_resolved);
+                println!("  DNS queries:      {}", stats.dns_queries);
+                println!("  Connect fallback: {}", stats.connect_fallbacks);
+                println!(
+                    "  Conn errors:      {} (DNS {}, connect timeouts {}, connect refused {}, relay {})",
+                    stats.connection_errors,
+                    stats.dns_failures,
+                    stats.connect_timeouts,
+                    stats.connect_refused,
+                    stats.relay_errors,
+                );
+                println!("  TLS blocks:       {}", stats.tls_blocked_suspected);
+                println!("  Avg in/out:       {} / {}", format_rate(stats.pps_in, stats.bps_in), format_rate(stats.pps_out, stats.bps_out));
+                if let Some(rates) = stats.rate_1m {
+                    println!("  1 min in/out:     {} / {}", format_rate(rates.pps_in, rates.bps_in), format_rate(rates.pps_out, rates.bps_out));
+                }
+                if let Some(rates) = stats.rate_5m {
+                    println!("  5 min in/out:     {} / {}", format_rate(rates.pps_in, rates.bps_in), format_rate(rates.pps_out, rates.bps_out));
+                }
             }
         }
 
@@ -304,10 +738,251 @@ async fn main() -> Result<()> {
             println!("Statistics reset");
         }
 
+        Commands::Rules => {
+            let mut client = ControlClient::new(&cli.socket);
+            let response = client.send(control::Command::GetRuleStats).await?;
+            match response.data {
+                control::ResponseData::RuleStats(rules) => {
+                    let now_ms = std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .map(|d| d.as_millis() as u64)
+                        .unwrap_or(0);
+                    println!("{:<32} {:>10} {:>14}  LAST HIT", "RULE", "HITS", "BYTES");
+                    for rule in rules {
+                        let last_hit = match rule.last_hit {
+                            Some(ms) => format!("{}s ago", now_ms.saturating_sub(ms) / 1000),
+                            None => "never".to_string(),
+                        };
+                        println!("{:<32} {:>10} {:>14}  {}", rule.name, rule.hits, rule.bytes, last_hit);
+                    }
+                }
+                control::ResponseData::Error { message } => anyhow::bail!(message),
+                _ => anyhow::bail!("Unexpected response"),
+            }
+        }
+
+        Commands::Hosts { top } => {
+            let mut client = ControlClient::new(&cli.socket);
+            let response = client.send(control::Command::GetHostStats).await?;
+            match response.data {
+                control::ResponseData::HostStats(hosts) => {
+                    println!("{:<40} {:>8} {:>12} {:>12} {:>8} {:>8}", "HOST", "CONNS", "UP", "DOWN", "BYPASS", "FAILED");
+                    for entry in hosts.iter().take(*top) {
+                        let counters = &entry.counters;
+                        println!("{:<40} {:>8} {:>12} {:>12} {:>8} {:>8}",
+                                 entry.host,
+                                 counters.connections,
+                                 format_bytes(counters.bytes_up),
+                                 format_bytes(counters.bytes_down),
+                                 counters.bypass_applied,
+                                 counters.failures);
+                    }
+                    if hosts.len() > *top {
+                        println!("({} more)", hosts.len() - top);
+                    }
+                }
+                control::ResponseData::Error { message } => anyhow::bail!(message),
+                _ => anyhow::bail!("Unexpected response"),
+            }
+        }
+        
+        Commands::Flows { limit, filter } => {
+            let mut client = ControlClient::new(&cli.socket);
+            let command = control::Command::GetFlows { limit: *limit, filter: filter.clone() };
+            match client.send(command).await?.data {
+                control::ResponseData::Flows(flows) => {
+                    println!("{:<5} {:<47} {:<32} {:<16} {:>8} {:>10} {:>7} {:>7}",
+                             "PROTO", "SOURCE -> DESTINATION", "HOST", "RULE", "PACKETS", "BYTES", "AGE", "IDLE");
+                    for flow in &flows {
+                        let endpoints = format!(
+                            "{} -> {}",
+                            SocketAddr::new(flow.src_ip, flow.src_port),
+                            SocketAddr::new(flow.dst_ip, flow.dst_port),
+                        );
+                        println!("{:<5} {:<47} {:<32} {:<16} {:>8} {:>10} {:>6}s {:>6}s",
+                                 format!("{:?}", flow.protocol).to_lowercase(),
+                                 endpoints,
+                                 flow.hostname.as_deref().unwrap_or("-"),
+                                 flow.matched_rule.as_deref().unwrap_or("-"),
+                                 flow.packets,
+                                 format_bytes(flow.bytes),
+                                 flow.age_secs,
+                                 flow.idle_secs);
+                    }
+                    if flows.is_empty() {
+                        println!("(no flows)");
+                    }
+                }
+                control::ResponseData::Error { message } => anyhow::bail!(message),
+                _ => anyhow::bail!("Unexpected response"),
+            }
+        }
+        
+        Commands::LogLevel { level, target } => {
+            let mut client = ControlClient::new(&cli.socket);
+            let command = match level {
+                Some(level) => control::Command::SetLogLevel { level: level.clone(), target: target.clone() },
+                None => control::Command::GetLogLevel,
+            };
+            match client.send(command).await?.data {
+                control::ResponseData::LogLevel { filter } => println!("Log filter: {}", filter),
+                control::ResponseData::Error { message } => anyhow::bail!(message),
+                _ => anyhow::bail!("Unexpected response"),
+            }
+        }
+        
+        Commands::Watch { kinds } => {
+            let mut client = ControlClient::new(&cli.socket);
+            let mut notifications = client.subscribe(kinds.clone()).await?;
+            
+            while let Some(notification) = notifications.next().await {
+                match notification?.kind {
+                    control::NotificationKind::StateChanged { old, new } => {
+                        println!("state:  {:?} -> {:?}", old, new);
+                    }
+                    control::NotificationKind::ConfigReloaded => println!("config: reloaded"),
+                    control::NotificationKind::Error { message } => println!("error:  {}", message),
+                    control::NotificationKind::StatsUpdate(stats) => {
+                        let rates = stats.rate_1m.unwrap_or(engine::Rates {
+                            pps_in: stats.pps_in,
+                            pps_out: stats.pps_out,
+                            bps_in: stats.bps_in,
+                            bps_out: stats.bps_out,
+                        });
+                        println!(
+                            "stats:  in {} / out {}, {} active flows",
+                            format_rate(rates.pps_in, rates.bps_in),
+                            format_rate(rates.pps_out, rates.bps_out),
+                            stats.active_flows,
+                        );
+                    }
+                }
+            }
+        }
+        
+        Commands::Rule { action } => {
+            let (command, done) = match action {
+                RuleAction::Add { file } => {
+                    let rule = engine::config::Rule::load_from_file(file)
+                        .with_context(|| format!("Failed to load rule from {}", file.display()))?;
+                    let done = format!("Rule '{}' added", rule.name);
+                    (control::Command::AddRule(rule), done)
+                }
+                RuleAction::Rm { name } => (
+                    control::Command::RemoveRule { name: name.clone() },
+                    format!("Rule '{}' removed", name),
+                ),
+                RuleAction::Enable { name } => (
+                    control::Command::SetRuleEnabled { name: name.clone(), enabled: true },
+                    format!("Rule '{}' enabled", name),
+                ),
+                RuleAction::Disable { name } => (
+                    control::Command::SetRuleEnabled { name: name.clone(), enabled: false },
+                    format!("Rule '{}' disabled", name),
+                ),
+            };
+            
+            let mut client = ControlClient::new(&cli.socket);
+            let response = client.send(command).await?;
+            match response.data {
+                control::ResponseData::Ok => println!("{}", done),
+                control::ResponseData::Error { message } => anyhow::bail!(message),
+                _ => anyhow::bail!("Unexpected response"),
+            }
+        }
+
+        Commands::Explain { target, host, protocol } => {
+            let protocol = match protocol {
+                ExplainProtocol::Tcp => Protocol::Tcp,
+                ExplainProtocol::Udp => Protocol::Udp,
+            };
+            let mut client = ControlClient::new(&cli.socket);
+            let response = client.send(control::Command::Explain {
+                dst: target.ip(),
+                port: target.port(),
+                protocol,
+                hostname: host.clone(),
+            }).await?;
+            match response.data {
+                control::ResponseData::Explanation(explanation) => {
+                    if !explanation.pipeline_enabled {
+                        println!("Pipeline is disabled; traffic passes through untouched");
+                        return Ok(());
+                    }
+                    match explanation.matched_rule {
+                        Some(ref rule) => println!("Matched rule: {}", rule),
+                        None => {
+                            println!("No rule matches; traffic passes through untouched");
+                            return Ok(());
+                        }
+                    }
+                    for plan in &explanation.transforms {
+                        println!("  {:?}: {}", plan.transform, plan.params);
+                    }
+                    for skipped in &explanation.skipped {
+                        println!("  {:?}: disabled globally", skipped);
+                    }
+                    if explanation.dropped {
+                        println!("Sample packet would be dropped");
+                    } else {
+                        println!("Sample packet emitted as sizes {:?}", explanation.output_sizes);
+                    }
+                    if let Some(delay) = explanation.delay_ms {
+                        println!("Delay: {}ms", delay);
+                    }
+                }
+                control::ResponseData::Error { message } => anyhow::bail!(message),
+                _ => anyhow::bail!("Unexpected response"),
+            }
+        }
+
+        Commands::Dns { action } => {
+            let mut client = ControlClient::new(&cli.socket);
+            match action {
+                DnsAction::Flush => {
+                    let response = client.send(control::Command::FlushDnsCache).await?;
+                    match response.data {
+                        control::ResponseData::DnsCacheFlushed { entries } => {
+                            println!("DNS cache flushed ({} entries)", entries);
+                        }
+                        control::ResponseData::Error { message } => anyhow::bail!(message),
+                        _ => anyhow::bail!("Unexpected response"),
+                    }
+                }
+                DnsAction::Dump => {
+                    let response = client.send(control::Command::GetDnsCache).await?;
+                    match response.data {
+                        control::ResponseData::DnsCache(entries) => {
+                            println!("DNS cache ({} entries):", entries.len());
+                            for entry in entries {
+                                let addresses: Vec<String> = entry.addresses.iter().map(|ip| ip.to_string()).collect();
+                                println!("  {:<40} {:>6}s  {}", entry.host, entry.ttl_secs, addresses.join(", "));
+                            }
+                        }
+                        control::ResponseData::Error { message } => anyhow::bail!(message),
+                        _ => anyhow::bail!("Unexpected response"),
+                    }
+                }
+            }
+        }
+
         Commands::Validate { config } => {
-            match Config::load_from_file(config) {
-                Ok(_) => {
+            match Config::load_with_env_report(Some(config)) {
+                Ok((loaded, applied)) => {
                     println!("✓ Configuration is valid: {}", config.display());
+                    match loaded.backend.kind {
+                        engine::config::BackendKind::Tun => println!("  backend: tun ({})", loaded.backend.tun.address),
+                        kind => {
+                            let listen: Vec<String> = loaded.backend.listen.iter().map(ToString::to_string).collect();
+                            println!("  backend: {} on {}", kind, listen.join(", "));
+                        }
+                    }
+                    for name in applied {
+                        println!("  environment override: {}", name);
+                    }
+                    for warning in loaded.warnings() {
+                        println!("  warning: {}", warning);
+                    }
                 }
                 Err(e) => {
                     eprintln!("✗ Configuration error: {}", e);
@@ -316,21 +991,58 @@ async fn main() -> Result<()> {
             }
         }
 
-        Commands::Reload { config } => {
-            let new_config = Config::load_from_file(config)
-                .with_context(|| format!("Failed to load config from {}", config.display()))?;
-            
+        Commands::Reload { config, push } => {
             let mut client = ControlClient::new(&cli.socket);
-            client.send(control::Command::Reload(new_config)).await?;
-            println!("Configuration reloaded");
+            let path = match config {
+                Some(path) if !*push => std::fs::canonicalize(path)
+                    .with_context(|| format!("Failed to resolve {}", path.display()))?,
+                Some(path) => path.clone(),
+                None => match client.status().await?.config_path {
+                    Some(path) => PathBuf::from(path),
+                    None => anyhow::bail!("The daemon has no config file to re-read; pass one"),
+                },
+            };
+            
+            let command = if *push {
+                let new_config = Config::load_from_file(&path)
+                    .with_context(|| format!("Failed to load config from {}", path.display()))?;
+                control::Command::Reload(new_config)
+            } else {
+                control::Command::ReloadFromPath { path: path.display().to_string() }
+            };
+            if let control::ResponseData::Error { message } = client.send(command).await?.data {
+                anyhow::bail!(message);
+            }
+            println!("Configuration reloaded from {}", path.display());
         }
 
-        Commands::GenConfig { format, output } => {
-            let config = create_example_config();
+        Commands::Save { path, format } => {
+            let mut client = ControlClient::new(&cli.socket);
+            let path = path.as_deref().map(std::path::absolute).transpose().context("Failed to resolve the path")?;
+            let command = control::Command::SaveConfig {
+                path: path.map(|p| p.display().to_string()),
+                format: format.clone(),
+            };
+            match client.send(command).await?.data {
+                control::ResponseData::Saved { path } => println!("Configuration saved to {}", path),
+                control::ResponseData::Error { message } => anyhow::bail!(message),
+                _ => anyhow::bail!("Unexpected response"),
+            }
+        }
+        
+        Commands::GenConfig { format, output, preset, list_presets } => {
+            if *list_presets {
+                for preset in ConfigPreset::ALL {
+                    println!("{:<14} {}", preset.name(), preset.description());
+                }
+                return Ok(());
+            }
             
-            let content = match format.as_str() {
-                "json" => serde_json::to_string_pretty(&config)?,
-                "toml" | _ => toml::to_string_pretty(&config)?,
+            let content = match (format.as_str(), preset) {
+                ("json", Some(preset)) => serde_json::to_string_pretty(&Config::preset(*preset))?,
+                ("json", None) => serde_json::to_string_pretty(&create_example_config())?,
+                (_, Some(preset)) => preset.to_commented_toml()?,
+                (_, None) => toml::to_string_pretty(&create_example_config())?,
             };
 
             if let Some(path) = output {
@@ -345,6 +1057,10 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+fn format_rate(pps: f64, bps: f64) -> String {
+    format!("{:.1} pkt/s, {}/s", pps, format_bytes(bps as u64))
+}
+
 fn format_bytes(bytes: u64) -> String {
     const KB: u64 = 1024;
     const MB: u64 = KB * 1024;
@@ -385,11 +1101,9 @@ fn create_example_config() -> Config {
                     protocols: Some(vec![Protocol::Tcp]),
                     ..Default::default()
                 },
-                transforms: vec![
-                    TransformType::Fragment,
-                    TransformType::Padding,
-                ],
-                overrides: HashMap::new(),
+                action: RuleAction::Transform,
+                transforms: vec![TransformType::Fragment],
+                overrides: HashMap::from([("fragment.split_at_sni".to_string(), serde_json::Value::Bool(true))]),
             },
             Rule {
                 name: "dns-protection".to_string(),
@@ -400,6 +1114,7 @@ fn create_example_config() -> Config {
                     protocols: Some(vec![Protocol::Udp]),
                     ..Default::default()
                 },
+                action: RuleAction::Transform,
                 transforms: vec![
                     TransformType::Padding,
                 ],
@@ -412,6 +1127,7 @@ fn create_example_config() -> Config {
             max_memory_mb: 128,
             max_jitter_ms: 500,
             flow_timeout_secs: 120,
+            cleanup_interval_secs: 30,
             log_rate_limit: 100,
         },
         transforms: TransformParams {
@@ -420,19 +1136,25 @@ fn create_example_config() -> Config {
                 max_size: 40,
                 split_at_offset: None,
                 randomize: true,
+                split_at_sni: false,
+                seed: None,
             },
             resegment: ResegmentParams {
                 segment_size: 16,
                 max_segments: 8,
+                tail_behavior: TailBehavior::Strict,
             },
             padding: PaddingParams {
                 min_bytes: 0,
                 max_bytes: 64,
                 fill_byte: None,
+                seed: None,
+                mode: PaddingMode::Append,
             },
             jitter: JitterParams {
                 min_ms: 0,
                 max_ms: 50,
+                seed: None,
             },
             header: HeaderParams {
                 normalize_ttl: false,
@@ -445,7 +1167,40 @@ fn create_example_config() -> Config {
                 send_after: false,
                 ttl: 1,
                 probability: 0.0,
+                corruption: DecoyCorruption::LowTtl,
+                count: 1,
+                seed: None,
+            },
+            reorder: ReorderParams {
+                strategy: ReorderStrategy::Swap,
+                seed: None,
+            },
+            fake: FakeParams {
+                payload: FakePayload::Http {
+                    host: "example.com".to_string(),
+                },
+                repeat: 1,
             },
         },
+        dns: DnsConfig {
+            providers: DohProvider::defaults(),
+            min_ttl_secs: 10,
+            max_ttl_secs: 3600,
+            negative_ttl_secs: 30,
+            max_cache_entries: 4096,
+            family_preference: FamilyPreference::V4First,
+            provider_stagger_ms: 300,
+            lookup_timeout_ms: 8000,
+            hosts_file: None,
+            fragment_doh: false,
+        },
+        backend: BackendSection {
+            kind: BackendKind::Socks5,
+            listen: vec!["127.0.0.1:1080".parse().unwrap()],
+            max_connections: 1000,
+            timeout_secs: 300,
+            tun: TunConfig::default(),
+            bypass: BypassConfig::turk_telekom(),
+        },
     }
 }
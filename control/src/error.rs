@@ -1,5 +1,7 @@
 use thiserror::Error;
 
+use crate::messages::EngineState;
+
 pub type Result<T> = std::result::Result<T, ControlError>;
 
 #[derive(Debug, Error)]
@@ -39,4 +41,7 @@ pub enum ControlError {
 
     #[error("Internal error: {0}")]
     Internal(String),
+    
+    #[error("Engine cannot go from {from:?} to {to:?}")]
+    InvalidTransition { from: EngineState, to: EngineState },
 }
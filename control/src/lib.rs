@@ -1,7 +1,9 @@
 pub mod error;
 pub mod messages;
 pub mod server;
+pub mod watch;
 
 pub use error::{ControlError, Result};
-pub use messages::{Request, Response, ResponseData, Command, Status};
-pub use server::{ControlServer, ControlClient, ServerConfig};
+pub use messages::{Request, Response, ResponseData, BackendSpec, Command, DnsCacheEntry, FlowInfo, Notification, NotificationKind, StateTransition, Status};
+pub use server::{ControlServer, ControlClient, NotificationStream, ServerConfig};
+pub use watch::DEFAULT_WATCH_INTERVAL;
@@ -1,7 +1,14 @@
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+
 use serde::{Deserialize, Serialize};
 
-use engine::Config;
-use engine::stats::StatsSnapshot;
+use backend::BackendSettings;
+use engine::{Config, EngineError, Explanation, FlowSummary, RuleStats};
+use engine::config::{BackendKind, BackendSection, Protocol, Rule};
+use engine::stats::{HostStatsEntry, StatsSnapshot};
+
+use crate::error::{ControlError, Result};
 
 pub const API_VERSION: &str = "1.0.0";
 
@@ -22,15 +29,164 @@ impl Request {
 #[serde(rename_all = "snake_case")]
 pub enum Command {
     Health,    
-    Start,    
+    Start {
+        #[serde(default)]
+        backend: Option<BackendSpec>,
+    },
     Stop,    
+    Pause,
+    Resume,
+    Shutdown {
+        #[serde(default)]
+        grace_secs: Option<u64>,
+    },
     GetConfig,    
     SetConfig(Config),    
     Reload(Config),    
+    /// Has the daemon load and apply the config file at `path`, which
+    /// becomes the remembered config path.
+    ReloadFromPath {
+        path: String,
+    },
+    SaveConfig {
+        #[serde(default)]
+        path: Option<String>,
+        #[serde(default)]
+        format: Option<String>,
+    },
     GetStats,    
     ResetStats,
     GetStatus,    
     Ping,
+    FlushDnsCache,
+    GetDnsCache,
+    GetRuleStats,
+    GetHostStats,
+    /// The busiest tracked flows, optionally only those whose hostname
+    /// contains `filter` or whose address starts with it.
+    GetFlows {
+        #[serde(default)]
+        limit: Option<usize>,
+        #[serde(default)]
+        filter: Option<String>,
+    },
+    AddRule(Rule),
+    RemoveRule {
+        name: String,
+    },
+    SetRuleEnabled {
+        name: String,
+        enabled: bool,
+    },
+    Explain {
+        dst: IpAddr,
+        port: u16,
+        protocol: Protocol,
+        #[serde(default)]
+        hostname: Option<String>,
+    },
+    SetLogLevel {
+        level: String,
+        #[serde(default)]
+        target: Option<String>,
+    },
+    GetLogLevel,
+    /// The most recent engine state transitions, oldest first.
+    GetStateHistory,
+    /// Turns the connection into a stream of [`Notification`]s, one JSON
+    /// object per line, of the given kinds (all of them if empty).
+    Subscribe {
+        #[serde(default)]
+        kinds: Vec<String>,
+    },
+}
+
+/// A serializable mirror of [`BackendSettings`]. Fields that are left out
+/// keep the backend's defaults.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BackendSpec {
+    Socks5 {
+        #[serde(default)]
+        listen: Vec<SocketAddr>,
+        #[serde(default)]
+        max_connections: Option<usize>,
+    },
+    HttpConnect {
+        #[serde(default)]
+        listen: Vec<SocketAddr>,
+        #[serde(default)]
+        max_connections: Option<usize>,
+    },
+    BypassHttp {
+        #[serde(default)]
+        listen: Vec<SocketAddr>,
+    },
+    Tun {
+        #[serde(default)]
+        device_name: Option<String>,
+        #[serde(default)]
+        mtu: Option<u16>,
+        #[serde(default)]
+        address: Option<String>,
+        #[serde(default)]
+        netmask: Option<String>,
+    },
+}
+
+impl BackendSpec {
+    pub fn kind(&self) -> BackendKind {
+        match self {
+            BackendSpec::Socks5 { .. } => BackendKind::Socks5,
+            BackendSpec::HttpConnect { .. } => BackendKind::HttpConnect,
+            BackendSpec::BypassHttp { .. } => BackendKind::BypassHttp,
+            BackendSpec::Tun { .. } => BackendKind::Tun,
+        }
+    }
+    
+    /// The config's `[backend]` section with whatever the spec sets on top,
+    /// validated the same way a config file is.
+    pub fn into_settings(self, base: &BackendSection) -> Result<BackendSettings> {
+        let mut section = base.clone();
+        section.kind = self.kind();
+        match self {
+            BackendSpec::Socks5 { listen, max_connections } | BackendSpec::HttpConnect { listen, max_connections } => {
+                if !listen.is_empty() {
+                    section.listen = listen;
+                }
+                if let Some(max_connections) = max_connections {
+                    section.max_connections = max_connections;
+                }
+            }
+            BackendSpec::BypassHttp { listen } => {
+                if !listen.is_empty() {
+                    section.listen = listen;
+                }
+            }
+            BackendSpec::Tun { device_name, mtu, address, netmask } => {
+                if device_name.is_some() {
+                    section.tun.device_name = device_name;
+                }
+                if let Some(mtu) = mtu {
+                    section.tun.mtu = mtu;
+                }
+                if let Some(address) = address {
+                    section.tun.address = address;
+                }
+                if let Some(netmask) = netmask {
+                    section.tun.netmask = netmask;
+                }
+            }
+        }
+
+        section.validate().map_err(|e| match e {
+            EngineError::ConfigValidation { field, message } => {
+                ControlError::InvalidRequest(format!("{}: {}", field, message))
+            }
+            other => ControlError::Engine(other),
+        })?;
+        Ok(BackendSettings::from_config(&section))
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -70,11 +226,69 @@ pub enum ResponseData {
     Ok,
     Error { message: String },
     Health(HealthInfo),    
-    Config(Config),    
+    Config(Box<Config>),    
     Stats(StatsSnapshot),    
     Status(Status),    
     Pong { timestamp: u64 },    
     Validation { valid: bool, errors: Vec<String> },
+    DnsCacheFlushed { entries: usize },
+    DnsCache(Vec<DnsCacheEntry>),
+    RuleStats(Vec<RuleStats>),
+    HostStats(Vec<HostStatsEntry>),
+    Flows(Vec<FlowInfo>),
+    LogLevel { filter: String },
+    Explanation(Explanation),
+    StateHistory(Vec<StateTransition>),
+    Saved { path: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsCacheEntry {
+    pub host: String,
+    pub addresses: Vec<IpAddr>,
+    pub ttl_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FlowInfo {
+    pub src_ip: IpAddr,
+    pub src_port: u16,
+    pub dst_ip: IpAddr,
+    pub dst_port: u16,
+    pub protocol: Protocol,
+    pub hostname: Option<String>,
+    pub matched_rule: Option<String>,
+    pub packets: u64,
+    pub bytes: u64,
+    pub age_secs: u64,
+    pub idle_secs: u64,
+}
+
+impl FlowInfo {
+    pub fn matches(&self, filter: &str) -> bool {
+        let filter = filter.to_ascii_lowercase();
+        self.hostname.as_deref().is_some_and(|host| host.to_ascii_lowercase().contains(&filter))
+            || self.src_ip.to_string().starts_with(&filter)
+            || self.dst_ip.to_string().starts_with(&filter)
+    }
+}
+
+impl From<FlowSummary> for FlowInfo {
+    fn from(flow: FlowSummary) -> Self {
+        Self {
+            src_ip: flow.key.src_ip,
+            src_port: flow.key.src_port,
+            dst_ip: flow.key.dst_ip,
+            dst_port: flow.key.dst_port,
+            protocol: flow.key.protocol,
+            hostname: flow.hostname,
+            matched_rule: flow.matched_rule,
+            packets: flow.packet_count,
+            bytes: flow.byte_count,
+            age_secs: flow.age_secs,
+            idle_secs: flow.idle_secs,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -85,6 +299,9 @@ pub struct HealthInfo {
     pub uptime_secs: u64,    
     pub backend: Option<String>,    
     pub system: SystemInfo,
+    /// Open control connections, this one included.
+    #[serde(default)]
+    pub connected_clients: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -114,6 +331,14 @@ pub struct Status {
     pub error_count: u64,    
     pub last_error: Option<String>,    
     pub config_path: Option<String>,
+    #[serde(default)]
+    pub active_connections: u64,
+    #[serde(default)]
+    pub connections_per_ip: BTreeMap<String, u64>,
+    #[serde(default)]
+    pub listen_addrs: Vec<String>,
+    #[serde(default)]
+    pub flow_memory_bytes: u64,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -122,10 +347,38 @@ pub enum EngineState {
     Stopped,
     Starting,    
     Running,    
+    Paused,
     Stopping,    
     Error,
 }
 
+impl EngineState {
+    pub fn can_transition_to(self, new: EngineState) -> bool {
+        matches!(
+            (self, new),
+            (_, EngineState::Error)
+                | (EngineState::Stopped | EngineState::Error, EngineState::Starting)
+                | (EngineState::Starting, EngineState::Running)
+                | (EngineState::Running, EngineState::Paused)
+                | (EngineState::Paused, EngineState::Running)
+                | (EngineState::Running | EngineState::Paused, EngineState::Stopping)
+                | (EngineState::Stopping, EngineState::Stopped)
+        )
+    }
+    
+    pub fn is_running(self) -> bool {
+        matches!(self, EngineState::Running | EngineState::Paused)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateTransition {
+    pub from: EngineState,
+    pub to: EngineState,
+    /// Milliseconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Notification {
     #[serde(flatten)]
@@ -140,12 +393,26 @@ pub enum NotificationKind {
     StateChanged { old: EngineState, new: EngineState },    
     ConfigReloaded,    
     Error { message: String },
-    StatsUpdate(StatsSnapshot),
+    StatsUpdate(Box<StatsSnapshot>),
+}
+
+impl NotificationKind {
+    pub const NAMES: [&'static str; 4] = ["state_changed", "config_reloaded", "error", "stats_update"];
+    
+    pub fn name(&self) -> &'static str {
+        match self {
+            NotificationKind::StateChanged { .. } => "state_changed",
+            NotificationKind::ConfigReloaded => "config_reloaded",
+            NotificationKind::Error { .. } => "error",
+            NotificationKind::StatsUpdate(_) => "stats_update",
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use backend::{ProxyConfig, ProxyType};
 
     #[test]
     fn test_request_serialization() {
@@ -180,12 +447,35 @@ mod tests {
     fn test_command_variants() {
         let commands = vec![
             Command::Health,
-            Command::Start,
+            Command::Start { backend: None },
+            Command::Start { backend: Some(BackendSpec::Tun { device_name: None, mtu: Some(1400), address: None, netmask: None }) },
             Command::Stop,
+            Command::Pause,
+            Command::Resume,
+            Command::Shutdown { grace_secs: Some(5) },
             Command::GetConfig,
             Command::GetStats,
             Command::GetStatus,
             Command::Ping,
+            Command::FlushDnsCache,
+            Command::GetDnsCache,
+            Command::GetRuleStats,
+            Command::GetHostStats,
+            Command::GetFlows { limit: Some(10), filter: Some("discord".to_string()) },
+            Command::RemoveRule { name: "https".to_string() },
+            Command::SetRuleEnabled { name: "https".to_string(), enabled: false },
+            Command::Explain {
+                dst: "104.16.0.1".parse().unwrap(),
+                port: 443,
+                protocol: Protocol::Tcp,
+                hostname: Some("discord.com".to_string()),
+            },
+            Command::Subscribe { kinds: vec!["state_changed".to_string()] },
+            Command::SetLogLevel { level: "trace".to_string(), target: Some("engine".to_string()) },
+            Command::GetLogLevel,
+            Command::GetStateHistory,
+            Command::ReloadFromPath { path: "/etc/turkeydpi/config.toml".to_string() },
+            Command::SaveConfig { path: None, format: Some("json".to_string()) },
         ];
         
         for cmd in commands {
@@ -193,6 +483,98 @@ mod tests {
             let _: Command = serde_json::from_str(&json).unwrap();
         }
     }
+    
+    #[test]
+    fn test_start_backend_spec() {
+        let request: Request = serde_json::from_str(r#"{"id":1,"command":{"type":"start","data":{}}}"#).unwrap();
+        assert!(matches!(request.command, Command::Start { backend: None }));
+        
+        let json = r#"{"type":"start","data":{"backend":{"kind":"http_connect","listen":["127.0.0.1:8080"]}}}"#;
+        let Command::Start { backend: Some(spec) } = serde_json::from_str(json).unwrap() else {
+            panic!("expected a backend spec");
+        };
+        assert_eq!(spec.kind(), BackendKind::HttpConnect);
+        let mut base = BackendSection {
+            max_connections: 64,
+            timeout_secs: 30,
+            ..Default::default()
+        };
+        base.bypass.clamp_mss = Some(536);
+        match spec.into_settings(&base).unwrap() {
+            BackendSettings::Proxy(settings) => {
+                assert_eq!(settings.proxy_type, ProxyType::HttpConnect);
+                assert_eq!(settings.listen_addrs, vec!["127.0.0.1:8080".parse().unwrap()]);
+                assert_eq!(settings.max_connections, 64);
+                assert_eq!(settings.timeout_secs, 30);
+                assert_eq!(settings.bypass.clamp_mss, Some(536));
+            }
+            other => panic!("unexpected settings {:?}", other),
+        }
+        
+        let BackendSettings::Bypass(config) = BackendSpec::BypassHttp { listen: Vec::new() }.into_settings(&base).unwrap() else {
+            panic!("bypass_http is not a bypass proxy");
+        };
+        assert_eq!(config.listen_addrs, base.listen);
+        assert_ne!(config.listen_addrs, ProxyConfig::default().listen_addrs);
+        assert_eq!(config.bypass.clamp_mss, Some(536));
+        
+        let tun = |mtu, address: &str| BackendSpec::Tun {
+            device_name: None,
+            mtu,
+            address: Some(address.to_string()),
+            netmask: None,
+        };
+        let BackendSettings::Tun(settings) = tun(Some(1400), "10.1.0.1").into_settings(&base).unwrap() else {
+            panic!("tun spec is not a tun backend");
+        };
+        assert_eq!((settings.mtu, settings.address.as_str()), (1400, "10.1.0.1"));
+        
+        let invalid = [
+            tun(Some(100), "10.1.0.1"),
+            tun(None, "10.1.0"),
+            BackendSpec::Socks5 { listen: Vec::new(), max_connections: Some(0) },
+        ];
+        for spec in invalid {
+            assert!(matches!(spec.clone().into_settings(&base), Err(ControlError::InvalidRequest(_))), "{:?}", spec);
+        }
+    }
+    
+    #[test]
+    fn test_engine_state_transitions() {
+        use EngineState::*;
+        
+        let legal = [
+            (Stopped, Starting),
+            (Starting, Running),
+            (Running, Stopping),
+            (Running, Paused),
+            (Paused, Running),
+            (Paused, Stopping),
+            (Stopping, Stopped),
+            (Error, Starting),
+            (Starting, Error),
+            (Running, Error),
+        ];
+        for (from, to) in legal {
+            assert!(from.can_transition_to(to), "{:?} -> {:?}", from, to);
+        }
+        
+        let illegal = [
+            (Stopped, Running),
+            (Stopped, Stopping),
+            (Starting, Stopping),
+            (Running, Starting),
+            (Running, Stopped),
+            (Stopped, Paused),
+            (Starting, Paused),
+            (Paused, Starting),
+            (Stopping, Starting),
+            (Error, Running),
+        ];
+        for (from, to) in illegal {
+            assert!(!from.can_transition_to(to), "{:?} -> {:?}", from, to);
+        }
+    }
 
     #[test]
     fn test_health_info() {
@@ -203,6 +585,7 @@ mod tests {
             uptime_secs: 3600,
             backend: Some("proxy".to_string()),
             system: SystemInfo::default(),
+            connected_clients: 2,
         };
         
         let json = serde_json::to_string(&health).unwrap();
@@ -210,8 +593,55 @@ mod tests {
         
         assert!(parsed.running);
         assert_eq!(parsed.uptime_secs, 3600);
+        assert_eq!(parsed.connected_clients, 2);
     }
 
+    #[test]
+    fn test_stats_response_rates() {
+        let stats = StatsSnapshot {
+            packets_in: 600,
+            uptime_secs: 60,
+            pps_in: 10.0,
+            rate_1m: Some(engine::Rates {
+                pps_in: 12.5,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        
+        let json = serde_json::to_string(&Response::success(7, ResponseData::Stats(stats))).unwrap();
+        let parsed: Response = serde_json::from_str(&json).unwrap();
+        match parsed.data {
+            ResponseData::Stats(stats) => {
+                assert_eq!(stats.uptime_secs, 60);
+                assert_eq!(stats.pps_in, 10.0);
+                assert_eq!(stats.rate_1m.unwrap().pps_in, 12.5);
+                assert!(stats.rate_5m.is_none());
+            }
+            other => panic!("unexpected response: {:?}", other),
+        }
+    }
+    
+    #[test]
+    fn test_notification_serialization() {
+        let notification = Notification {
+            kind: NotificationKind::StateChanged { old: EngineState::Stopped, new: EngineState::Starting },
+            timestamp: 1,
+        };
+        let json = serde_json::to_string(&notification).unwrap();
+        assert_eq!(
+            json,
+            r#"{"notification":"state_changed","data":{"old":"stopped","new":"starting"},"timestamp":1}"#
+        );
+        
+        let parsed: Notification = serde_json::from_str(r#"{"notification":"config_reloaded","timestamp":2}"#).unwrap();
+        assert!(matches!(parsed.kind, NotificationKind::ConfigReloaded));
+        assert!(NotificationKind::NAMES.contains(&parsed.kind.name()));
+        
+        let subscribe: Command = serde_json::from_str(r#"{"type":"subscribe","data":{}}"#).unwrap();
+        assert!(matches!(subscribe, Command::Subscribe { kinds } if kinds.is_empty()));
+    }
+    
     #[test]
     fn test_status() {
         let status = Status {
@@ -223,6 +653,10 @@ mod tests {
             error_count: 0,
             last_error: None,
             config_path: Some("/etc/turkeydpi/config.toml".to_string()),
+            active_connections: 3,
+            connections_per_ip: BTreeMap::from([("127.0.0.1".to_string(), 3)]),
+            listen_addrs: vec!["127.0.0.1:1080".to_string(), "[::1]:1080".to_string()],
+            flow_memory_bytes: 4096,
         };
         
         let json = serde_json::to_string(&status).unwrap();
@@ -230,5 +664,8 @@ mod tests {
         
         assert_eq!(parsed.state, EngineState::Running);
         assert_eq!(parsed.active_flows, 100);
+        assert_eq!(parsed.active_connections, 3);
+        assert_eq!(parsed.connections_per_ip.get("127.0.0.1"), Some(&3));
+        assert_eq!(parsed.listen_addrs.len(), 2);
     }
 }
@@ -1,30 +1,66 @@
+use std::collections::VecDeque;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use parking_lot::RwLock;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use parking_lot::{Mutex, RwLock};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
 use tokio::net::{UnixListener, UnixStream};
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, mpsc, Notify};
+use tokio::task::JoinHandle;
 use tracing::{debug, error, info, trace, warn};
+use tracing_subscriber::{reload, EnvFilter};
 
-use engine::{Config, Stats};
-use backend::{Backend, BackendHandle, BackendConfig, BackendSettings, ProxySettings};
-use backend::proxy::ProxyBackend;
+use engine::{Config, ConfigFormat, FlowKey, PacketMeta, Pipeline, Stats};
+use backend::{BackendHandle, BackendConfig, BackendSettings, MAX_DRAIN_GRACE};
 
 use crate::error::{ControlError, Result};
+use crate::watch;
 use crate::messages::{
-    Command, EngineState, HealthInfo,
-    Request, Response, ResponseData, Status, SystemInfo, API_VERSION,
+    BackendSpec, Command, DnsCacheEntry, EngineState, FlowInfo, HealthInfo, Notification, NotificationKind,
+    Request, Response, ResponseData, StateTransition, Status, SystemInfo, API_VERSION,
 };
 
+// Typical size of a browser ClientHello.
+const EXPLAIN_SAMPLE_LEN: usize = 517;
+
+pub const STATS_PERSIST_INTERVAL: Duration = Duration::from_secs(300);
+
+const STATE_HISTORY_LEN: usize = 32;
+
+pub type LogReloader = Arc<dyn Fn(&str) -> std::result::Result<(), String> + Send + Sync>;
+
+pub fn env_filter_reloader<S: 'static>(handle: reload::Handle<EnvFilter, S>) -> LogReloader {
+    Arc::new(move |directives: &str| {
+        let filter = EnvFilter::try_new(directives).map_err(|e| e.to_string())?;
+        handle.reload(filter).map_err(|e| e.to_string())
+    })
+}
+
+struct LogControl {
+    filter: String,
+    reload: LogReloader,
+}
+
+// Notifications a slow subscriber may fall behind by before it misses some.
+const NOTIFICATION_BUFFER: usize = 64;
+
+pub const DEFAULT_MAX_REQUEST_BYTES: usize = 1024 * 1024;
+
 #[derive(Debug, Clone)]
 pub struct ServerConfig {
     pub socket_path: PathBuf,    
     pub max_clients: usize,    
+    /// Limit on waiting for a request line, and on handling it.
     pub timeout_secs: u64,    
+    /// Longest request line accepted, newline included.
+    pub max_request_bytes: usize,
     pub enable_notifications: bool,
+    /// How often subscribers get a stats update while the engine runs.
+    pub stats_update_secs: u64,
+    pub stats_file: Option<PathBuf>,
 }
 
 impl Default for ServerConfig {
@@ -33,7 +69,10 @@ impl Default for ServerConfig {
             socket_path: PathBuf::from("/tmp/turkeydpi.sock"),
             max_clients: 10,
             timeout_secs: 30,
+            max_request_bytes: DEFAULT_MAX_REQUEST_BYTES,
             enable_notifications: true,
+            stats_update_secs: 5,
+            stats_file: None,
         }
     }
 }
@@ -41,41 +80,171 @@ impl Default for ServerConfig {
 struct ServerState {
     config: RwLock<Config>,    
     backend_handle: RwLock<Option<BackendHandle>>,    
+    /// The pipeline of a backend started beside the server, which Pause and
+    /// Resume toggle when the server has no backend of its own.
+    external_pipeline: RwLock<Option<Arc<Pipeline>>>,
     engine_state: RwLock<EngineState>,    
+    state_history: Mutex<VecDeque<StateTransition>>,
     start_time: Instant,    
     backend_type: RwLock<Option<String>>,    
     last_error: RwLock<Option<String>>,    
     config_path: RwLock<Option<PathBuf>>,
+    stats_file: Option<PathBuf>,
+    notifications: Option<RwLock<broadcast::Sender<Notification>>>,
+    logging: RwLock<Option<LogControl>>,
+    request_timeout: Duration,
+    max_request_bytes: usize,
+    connected_clients: AtomicUsize,
+    shutdown_requested: Notify,
+    /// Holds the requested grace once the accept loop has exited after a
+    /// Shutdown command.
+    shut_down: tokio::sync::watch::Sender<Option<Duration>>,
+    shutdown_grace: Mutex<Duration>,
 }
 
 impl ServerState {
-    fn new(config: Config) -> Self {
+    fn new(config: Config, server_config: &ServerConfig) -> Self {
+        let notifications = server_config
+            .enable_notifications
+            .then(|| RwLock::new(broadcast::channel(NOTIFICATION_BUFFER).0));
         Self {
             config: RwLock::new(config),
             backend_handle: RwLock::new(None),
+            external_pipeline: RwLock::new(None),
             engine_state: RwLock::new(EngineState::Stopped),
+            state_history: Mutex::new(VecDeque::with_capacity(STATE_HISTORY_LEN)),
             start_time: Instant::now(),
             backend_type: RwLock::new(None),
             last_error: RwLock::new(None),
             config_path: RwLock::new(None),
+            stats_file: server_config.stats_file.clone(),
+            notifications,
+            logging: RwLock::new(None),
+            request_timeout: Duration::from_secs(server_config.timeout_secs),
+            max_request_bytes: server_config.max_request_bytes.max(1),
+            connected_clients: AtomicUsize::new(0),
+            shutdown_requested: Notify::new(),
+            shut_down: tokio::sync::watch::channel(None).0,
+            shutdown_grace: Mutex::new(Duration::ZERO),
+        }
+    }
+    
+    fn notify(&self, kind: NotificationKind) {
+        let Some(ref tx) = self.notifications else {
+            return;
+        };
+        // Fails only when nobody is subscribed.
+        let _ = tx.read().send(Notification { kind, timestamp: now_millis() });
+    }
+    
+    fn has_subscribers(&self) -> bool {
+        self.notifications.as_ref().is_some_and(|tx| tx.read().receiver_count() > 0)
+    }
+    
+    // Replacing the sender ends each subscriber's stream once it has
+    // forwarded what was already sent.
+    fn close_subscribers(&self) {
+        if let Some(ref tx) = self.notifications {
+            *tx.write() = broadcast::channel(NOTIFICATION_BUFFER).0;
+        }
+    }
+    
+    /// Moves the engine to `new` if the state machine allows it and returns
+    /// the state it left. Every change is recorded and announced.
+    fn transition(&self, new: EngineState) -> Result<EngineState> {
+        let mut engine_state = self.engine_state.write();
+        let old = *engine_state;
+        if !old.can_transition_to(new) {
+            return Err(ControlError::InvalidTransition { from: old, to: new });
+        }
+        if old == new {
+            return Ok(old);
+        }
+        *engine_state = new;
+        
+        let mut history = self.state_history.lock();
+        if history.len() == STATE_HISTORY_LEN {
+            history.pop_front();
+        }
+        history.push_back(StateTransition { from: old, to: new, timestamp: now_millis() });
+        drop(history);
+        
+        self.notify(NotificationKind::StateChanged { old, new });
+        Ok(old)
+    }
+    
+    fn persist_stats(&self) {
+        let (Some(path), Some(handle)) = (&self.stats_file, &*self.backend_handle.read()) else {
+            return;
+        };
+        match handle.stats().save_to(path) {
+            Ok(()) => debug!(path = %path.display(), "Saved stats"),
+            Err(e) => warn!(path = %path.display(), error = %e, "Failed to save stats"),
+        }
+    }
+    
+    fn restore_stats(&self, handle: &BackendHandle) {
+        let Some(ref path) = self.stats_file else {
+            return;
+        };
+        match handle.stats().load_from(path) {
+            Ok(()) => {
+                // Samples taken before the baseline was added would show it as traffic.
+                handle.history.clear();
+                info!(path = %path.display(), epoch = handle.stats().epoch(), "Restored stats");
+            }
+            Err(engine::EngineError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                debug!(path = %path.display(), "No saved stats yet");
+            }
+            Err(e) => warn!(path = %path.display(), error = %e, "Ignoring saved stats"),
+        }
+    }
+    
+    fn remove_persisted_stats(&self) {
+        let Some(ref path) = self.stats_file else {
+            return;
+        };
+        if let Err(e) = std::fs::remove_file(path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!(path = %path.display(), error = %e, "Failed to remove saved stats");
+            }
         }
     }
 }
 
+/// Counts a control connection for as long as its handler runs.
+struct ClientGuard(Arc<ServerState>);
+
+impl ClientGuard {
+    fn new(state: Arc<ServerState>) -> Self {
+        state.connected_clients.fetch_add(1, Ordering::SeqCst);
+        Self(state)
+    }
+}
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        self.0.connected_clients.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
 pub struct ControlServer {
     server_config: ServerConfig,    
     running: Arc<AtomicBool>,    
     state: Arc<ServerState>,
     shutdown_tx: Option<mpsc::Sender<()>>,
+    accept_task: Option<JoinHandle<()>>,
 }
 
 impl ControlServer {
     pub fn new(server_config: ServerConfig, engine_config: Config) -> Self {
+        let state = ServerState::new(engine_config, &server_config);
         Self {
             server_config,
             running: Arc::new(AtomicBool::new(false)),
-            state: Arc::new(ServerState::new(engine_config)),
+            state: Arc::new(state),
             shutdown_tx: None,
+            accept_task: None,
         }
     }
 
@@ -105,10 +274,21 @@ impl ControlServer {
 
         let running = self.running.clone();
         let state = self.state.clone();
+        let socket_path = socket_path.clone();
+        state.shut_down.send_replace(None);
         let max_clients = self.server_config.max_clients;
-
-        tokio::spawn(async move {
-            let mut active_clients = 0usize;
+        let stats_update_period = Duration::from_secs(self.server_config.stats_update_secs.max(1));
+
+        self.accept_task = Some(tokio::spawn(async move {
+            let mut persist_interval = tokio::time::interval_at(
+                tokio::time::Instant::now() + STATS_PERSIST_INTERVAL,
+                STATS_PERSIST_INTERVAL,
+            );
+            let mut stats_update_interval = tokio::time::interval_at(
+                tokio::time::Instant::now() + stats_update_period,
+                stats_update_period,
+            );
+            let mut shut_down = false;
             
             loop {
                 tokio::select! {
@@ -116,18 +296,37 @@ impl ControlServer {
                         info!("Control server received shutdown signal");
                         break;
                     }
+                    _ = state.shutdown_requested.notified() => {
+                        let _ = std::fs::remove_file(&socket_path);
+                        state.close_subscribers();
+                        shut_down = true;
+                        break;
+                    }
+                    _ = persist_interval.tick() => {
+                        state.persist_stats();
+                    }
+                    _ = stats_update_interval.tick() => {
+                        if !state.has_subscribers() {
+                            continue;
+                        }
+                        let snapshot = state.backend_handle.read().as_ref().map(|handle| handle.stats_snapshot());
+                        if let Some(snapshot) = snapshot {
+                            state.notify(NotificationKind::StatsUpdate(Box::new(snapshot)));
+                        }
+                    }
                     result = listener.accept() => {
                         match result {
                             Ok((stream, _addr)) => {
-                                if active_clients >= max_clients {
+                                if state.connected_clients.load(Ordering::SeqCst) >= max_clients {
                                     warn!("Max clients reached, rejecting connection");
                                     continue;
                                 }
                                 
-                                active_clients += 1;
+                                let guard = ClientGuard::new(state.clone());
                                 let state = state.clone();
                                 
                                 tokio::spawn(async move {
+                                    let _guard = guard;
                                     if let Err(e) = Self::handle_client(stream, state).await {
                                         debug!(error = %e, "Client handler error");
                                     }
@@ -143,7 +342,10 @@ impl ControlServer {
 
             running.store(false, Ordering::SeqCst);
             info!("Control server stopped");
-        });
+            if shut_down {
+                state.shut_down.send_replace(Some(*state.shutdown_grace.lock()));
+            }
+        }));
 
         Ok(())
     }
@@ -154,10 +356,13 @@ impl ControlServer {
         }
 
         info!("Stopping control server");
+        
+        self.state.persist_stats();
 
         if let Some(tx) = self.shutdown_tx.take() {
             let _ = tx.send(()).await;
         }
+        self.state.close_subscribers();
 
         let _ = std::fs::remove_file(&self.server_config.socket_path);
 
@@ -168,6 +373,23 @@ impl ControlServer {
     pub fn is_running(&self) -> bool {
         self.running.load(Ordering::SeqCst)
     }
+    
+    /// Resolves with the requested grace once a client's Shutdown command
+    /// has stopped the backend and closed the control socket, at which point
+    /// the daemon should exit.
+    pub fn shutdown_signal(&self) -> impl std::future::Future<Output = Duration> + Send + 'static {
+        let mut shut_down = self.state.shut_down.subscribe();
+        async move {
+            match shut_down.wait_for(|grace| grace.is_some()).await {
+                Ok(grace) => grace.unwrap_or_default(),
+                Err(_) => Duration::ZERO,
+            }
+        }
+    }
+    
+    pub fn is_finished(&self) -> bool {
+        self.accept_task.as_ref().is_none_or(|task| task.is_finished())
+    }
 
     async fn handle_client(stream: UnixStream, state: Arc<ServerState>) -> Result<()> {
         let (reader, mut writer) = stream.into_split();
@@ -177,10 +399,22 @@ impl ControlServer {
         loop {
             line.clear();
             
-            let bytes_read = reader.read_line(&mut line).await?;
+            let limit = state.max_request_bytes as u64;
+            let mut bounded = (&mut reader).take(limit);
+            let bytes_read = match tokio::time::timeout(state.request_timeout, bounded.read_line(&mut line)).await {
+                Ok(read) => read?,
+                Err(_) => {
+                    debug!("Client sent no complete request in time, disconnecting");
+                    return Self::reject(&mut writer, ControlError::Timeout.to_string()).await;
+                }
+            };
             if bytes_read == 0 {
                 break;
             }
+            if bytes_read as u64 == limit && !line.ends_with('\n') {
+                warn!(limit, "Oversized control request, disconnecting client");
+                return Self::reject(&mut writer, format!("Request exceeds {} bytes", limit)).await;
+            }
 
             let line = line.trim();
             if line.is_empty() {
@@ -189,93 +423,220 @@ impl ControlServer {
 
             trace!(request = %line, "Received request");
 
-            let response = match serde_json::from_str::<Request>(line) {
-                Ok(request) => Self::handle_request(&request, &state).await,
-                Err(e) => Response::error(0, format!("Invalid JSON: {}", e)),
+            let (response, subscription, shutdown) = match serde_json::from_str::<Request>(line) {
+                Ok(request) => {
+                    let handled = tokio::time::timeout(state.request_timeout, Self::handle_request(&request, &state));
+                    let response = match handled.await {
+                        Ok(response) => response,
+                        Err(_) => Self::timed_out(&request, &state),
+                    };
+                    // Subscribed before the client hears back, so it misses
+                    // nothing it triggers afterwards.
+                    let subscription = match (&request.command, &state.notifications) {
+                        (Command::Subscribe { kinds }, Some(tx)) if response.success => {
+                            Some((tx.read().subscribe(), kinds.clone()))
+                        }
+                        _ => None,
+                    };
+                    let shutdown = match request.command {
+                        Command::Shutdown { grace_secs } if response.success => {
+                            Some(Duration::from_secs(grace_secs.unwrap_or(0)).min(MAX_DRAIN_GRACE))
+                        }
+                        _ => None,
+                    };
+                    (response, subscription, shutdown)
+                }
+                Err(e) => (Response::error(0, format!("Invalid JSON: {}", e)), None, None),
             };
 
             let response_json = serde_json::to_string(&response)?;
             writer.write_all(response_json.as_bytes()).await?;
             writer.write_all(b"\n").await?;
             writer.flush().await?;
+            
+            if let Some((rx, kinds)) = subscription {
+                return Self::stream_notifications(rx, &kinds, reader, writer).await;
+            }
+            // Only now that the client has its answer.
+            if let Some(grace) = shutdown {
+                Self::shut_down(&state, grace).await;
+                return Ok(());
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Sends a final error response before the connection is dropped.
+    async fn reject(writer: &mut tokio::net::unix::OwnedWriteHalf, message: String) -> Result<()> {
+        let response_json = serde_json::to_string(&Response::error(0, message))?;
+        writer.write_all(response_json.as_bytes()).await?;
+        writer.write_all(b"\n").await?;
+        writer.flush().await?;
+        Ok(())
+    }
+    
+    /// A command abandoned mid-transition leaves the engine in an error
+    /// state rather than stuck starting or stopping.
+    fn timed_out(request: &Request, state: &ServerState) -> Response {
+        let message = ControlError::Timeout.to_string();
+        warn!(id = request.id, "Control command timed out");
+        let engine_state = *state.engine_state.read();
+        if matches!(engine_state, EngineState::Starting | EngineState::Stopping) {
+            *state.last_error.write() = Some(message.clone());
+            let _ = state.transition(EngineState::Error);
+        }
+        Response::error(request.id, message)
+    }
+    
+    /// Forwards notifications to a subscribed client until it disconnects.
+    async fn stream_notifications(
+        mut rx: broadcast::Receiver<Notification>,
+        kinds: &[String],
+        mut reader: BufReader<tokio::net::unix::OwnedReadHalf>,
+        mut writer: tokio::net::unix::OwnedWriteHalf,
+    ) -> Result<()> {
+        let mut discard = String::new();
+        loop {
+            let notification = tokio::select! {
+                received = rx.recv() => match received {
+                    Ok(notification) => notification,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        debug!(missed, "Subscriber fell behind, notifications dropped");
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                read = reader.read_line(&mut discard) => {
+                    if read? == 0 {
+                        break;
+                    }
+                    discard.clear();
+                    continue;
+                }
+            };
+            if !kinds.is_empty() && !kinds.iter().any(|kind| kind == notification.kind.name()) {
+                continue;
+            }
+            
+            let json = serde_json::to_string(&notification)?;
+            writer.write_all(json.as_bytes()).await?;
+            writer.write_all(b"\n").await?;
+            writer.flush().await?;
         }
 
         Ok(())
     }
 
+    fn edit_rules(
+        id: u64,
+        state: &ServerState,
+        edit: impl FnOnce(&mut Config) -> std::result::Result<(), String>,
+    ) -> Response {
+        let mut config = state.config.write();
+        let mut new_config = config.clone();
+        
+        if let Err(message) = edit(&mut new_config) {
+            return Response::error(id, message);
+        }
+        if let Err(e) = new_config.validate() {
+            return Response::error(id, e.to_string());
+        }
+        
+        if let Some(ref handle) = *state.backend_handle.read() {
+            if let Err(e) = handle.update_rules(new_config.rules.clone()) {
+                return Response::error(id, e.to_string());
+            }
+        }
+        
+        *config = new_config;
+        Response::ok(id)
+    }
+
     async fn handle_request(request: &Request, state: &ServerState) -> Response {
         let id = request.id;
 
         match &request.command {
             Command::Health => {
                 let health = HealthInfo {
-                    running: *state.engine_state.read() == EngineState::Running,
+                    running: state.engine_state.read().is_running(),
                     version: env!("CARGO_PKG_VERSION").to_string(),
                     api_version: API_VERSION.to_string(),
                     uptime_secs: state.start_time.elapsed().as_secs(),
                     backend: state.backend_type.read().clone(),
                     system: SystemInfo::default(),
+                    connected_clients: state.connected_clients.load(Ordering::SeqCst) as u64,
                 };
                 Response::success(id, ResponseData::Health(health))
             }
 
-            Command::Start => {
-                let current_state = *state.engine_state.read();
-                if current_state == EngineState::Running {
-                    return Response::error(id, "Engine already running".to_string());
+            Command::Start { backend } => {
+                let config = state.config.read().clone();
+                let (kind, backend_settings) = match backend.clone() {
+                    Some(spec) => {
+                        let kind = spec.kind();
+                        match spec.into_settings(&config.backend) {
+                            Ok(settings) => (kind, settings),
+                            Err(e) => return Response::error(id, e.to_string()),
+                        }
+                    }
+                    None => (config.backend.kind, BackendSettings::from_config(&config.backend)),
+                };
+                if !backend_settings.is_supported() {
+                    return Response::error(id, format!("{} backend is not supported on this platform", kind));
                 }
 
-                *state.engine_state.write() = EngineState::Starting;
+                if let Err(e) = state.transition(EngineState::Starting) {
+                    return Response::error(id, e.to_string());
+                }
 
-                let config = state.config.read().clone();
                 let backend_config = BackendConfig {
                     engine_config: config,
                     max_queue_size: 1000,
-                    backend_settings: BackendSettings::Proxy(
-                        ProxySettings::default()
-                    ),
+                    backend_settings,
                 };
 
-                let mut backend = ProxyBackend::new();
+                let mut backend = backend_config.backend_settings.backend();
                 match backend.start(backend_config).await {
                     Ok(handle) => {
+                        state.restore_stats(&handle);
                         *state.backend_handle.write() = Some(handle);
-                        *state.backend_type.write() = Some("proxy".to_string());
-                        *state.engine_state.write() = EngineState::Running;
+                        *state.backend_type.write() = Some(kind.name().to_string());
                         *state.last_error.write() = None;
+                        if let Err(e) = state.transition(EngineState::Running) {
+                            warn!(error = %e, "Backend started outside of Starting");
+                        }
                         Response::ok(id)
                     }
                     Err(e) => {
-                        *state.engine_state.write() = EngineState::Error;
                         *state.last_error.write() = Some(e.to_string());
+                        let _ = state.transition(EngineState::Error);
+                        state.notify(NotificationKind::Error { message: e.to_string() });
                         Response::error(id, e.to_string())
                     }
                 }
             }
 
-            Command::Stop => {
-                let current_state = *state.engine_state.read();
-                if current_state != EngineState::Running {
-                    return Response::error(id, "Engine not running".to_string());
-                }
-
-                *state.engine_state.write() = EngineState::Stopping;
+            Command::Stop => match Self::stop_backend(state).await {
+                Ok(()) => Response::ok(id),
+                Err(e) => Response::error(id, e.to_string()),
+            },
 
-                let handle = state.backend_handle.write().take();
-                if let Some(handle) = handle {
-                    if let Err(e) = handle.shutdown().await {
-                        warn!(error = %e, "Error during shutdown");
-                    }
+            Command::Shutdown { .. } => {
+                let engine_state = *state.engine_state.read();
+                if matches!(engine_state, EngineState::Starting | EngineState::Stopping) {
+                    return Response::error(id, format!("Cannot shut down while the engine is {:?}", engine_state));
                 }
-
-                *state.backend_type.write() = None;
-                *state.engine_state.write() = EngineState::Stopped;
                 Response::ok(id)
             }
+            
+            Command::Pause => Self::set_paused(id, state, true),
+            
+            Command::Resume => Self::set_paused(id, state, false),
 
             Command::GetConfig => {
                 let config = state.config.read().clone();
-                Response::success(id, ResponseData::Config(config))
+                Response::success(id, ResponseData::Config(Box::new(config)))
             }
 
             Command::SetConfig(new_config) => {
@@ -295,25 +656,76 @@ impl ControlServer {
                 }
             }
 
-            Command::Reload(new_config) => {
-                if let Err(e) = new_config.validate() {
-                    return Response::error(id, e.to_string());
+            Command::Reload(new_config) => Self::reload(id, state, new_config.clone()),
+            
+            Command::ReloadFromPath { path } => {
+                let path = PathBuf::from(path);
+                let config = match Config::load_from_file(&path) {
+                    Ok(config) => config,
+                    Err(e) => {
+                        let message = describe_config_error(&e.into());
+                        return Response::error(id, format!("Failed to load {}: {}", path.display(), message));
+                    }
+                };
+                let response = Self::reload(id, state, config);
+                if response.success {
+                    info!(path = %path.display(), "Reloaded configuration");
+                    *state.config_path.write() = Some(path);
+                }
+                response
+            }
+            
+            Command::SaveConfig { path, format } => {
+                let Some(path) = path.as_ref().map(PathBuf::from).or_else(|| state.config_path.read().clone()) else {
+                    return Response::error(id, "No path given and the daemon was not started from a config file".to_string());
+                };
+                let format = match format {
+                    Some(format) => match format.parse::<ConfigFormat>() {
+                        Ok(format) => format,
+                        Err(e) => return Response::error(id, e.to_string()),
+                    },
+                    None => ConfigFormat::of_path(&path),
+                };
+                let config = state.config.read().clone();
+                match config.save_to_file(&path, format) {
+                    Ok(()) => {
+                        info!(path = %path.display(), "Saved configuration");
+                        Response::success(id, ResponseData::Saved { path: path.display().to_string() })
+                    }
+                    Err(e) => Response::error(id, format!("Failed to save {}: {}", path.display(), e)),
                 }
+            }
 
-                *state.config.write() = new_config.clone();
+            Command::AddRule(rule) => {
+                Self::edit_rules(id, state, |config| {
+                    config.upsert_rule(rule.clone());
+                    Ok(())
+                })
+            }
 
-                if let Some(ref handle) = *state.backend_handle.read() {
-                    if let Err(e) = handle.reload_config(new_config.clone()) {
-                        return Response::error(id, e.to_string());
+            Command::RemoveRule { name } => {
+                Self::edit_rules(id, state, |config| {
+                    if config.remove_rule(name) {
+                        Ok(())
+                    } else {
+                        Err(format!("Rule '{}' not found", name))
                     }
-                }
+                })
+            }
 
-                Response::ok(id)
+            Command::SetRuleEnabled { name, enabled } => {
+                Self::edit_rules(id, state, |config| {
+                    if config.set_rule_enabled(name, *enabled) {
+                        Ok(())
+                    } else {
+                        Err(format!("Rule '{}' not found", name))
+                    }
+                })
             }
 
             Command::GetStats => {
                 let stats = if let Some(ref handle) = *state.backend_handle.read() {
-                    handle.stats().snapshot()
+                    handle.stats_snapshot()
                 } else {
                     Stats::new().snapshot()
                 };
@@ -322,8 +734,9 @@ impl ControlServer {
 
             Command::ResetStats => {
                 if let Some(ref handle) = *state.backend_handle.read() {
-                    handle.stats().reset();
+                    handle.reset_stats();
                 }
+                state.remove_persisted_stats();
                 Response::ok(id)
             }
 
@@ -335,9 +748,21 @@ impl ControlServer {
                 } else {
                     (0, 0, 0, 0)
                 };
+                let connections = backend_handle
+                    .as_ref()
+                    .map(|handle| handle.connection_counts())
+                    .unwrap_or_default();
+                let listen_addrs = backend_handle
+                    .as_ref()
+                    .map(|handle| handle.listen_addrs().iter().map(|addr| addr.to_string()).collect())
+                    .unwrap_or_default();
+                let flow_memory_bytes = backend_handle
+                    .as_ref()
+                    .map(|handle| handle.pipeline.flow_cache().memory_used() as u64)
+                    .unwrap_or(0);
 
                 let status = Status {
-                    running: *state.engine_state.read() == EngineState::Running,
+                    running: state.engine_state.read().is_running(),
                     state: *state.engine_state.read(),
                     active_flows,
                     packets_processed: packets,
@@ -345,18 +770,240 @@ impl ControlServer {
                     error_count: errors,
                     last_error: state.last_error.read().clone(),
                     config_path: state.config_path.read().as_ref().map(|p| p.display().to_string()),
+                    active_connections: connections.total,
+                    connections_per_ip: connections.per_ip
+                        .into_iter()
+                        .map(|(ip, count)| (ip.to_string(), count))
+                        .collect(),
+                    listen_addrs,
+                    flow_memory_bytes,
                 };
                 Response::success(id, ResponseData::Status(status))
             }
 
+            Command::FlushDnsCache => {
+                let dns = state.backend_handle.read().as_ref().and_then(|handle| handle.dns().cloned());
+                match dns {
+                    Some(dns) => Response::success(id, ResponseData::DnsCacheFlushed { entries: dns.flush() }),
+                    None => Response::error(id, "DNS resolver not available".to_string()),
+                }
+            }
+
+            Command::GetDnsCache => {
+                let dns = state.backend_handle.read().as_ref().and_then(|handle| handle.dns().cloned());
+                match dns {
+                    Some(dns) => {
+                        let entries = dns.dump()
+                            .into_iter()
+                            .map(|(host, addresses, remaining)| DnsCacheEntry {
+                                host,
+                                addresses,
+                                ttl_secs: remaining.as_secs(),
+                            })
+                            .collect();
+                        Response::success(id, ResponseData::DnsCache(entries))
+                    }
+                    None => Response::error(id, "DNS resolver not available".to_string()),
+                }
+            }
+
+            Command::GetRuleStats => {
+                let rules = match *state.backend_handle.read() {
+                    Some(ref handle) => handle.pipeline.rule_stats(),
+                    None => Vec::new(),
+                };
+                Response::success(id, ResponseData::RuleStats(rules))
+            }
+
+            Command::GetHostStats => {
+                let hosts = match *state.backend_handle.read() {
+                    Some(ref handle) => handle.top_hosts(usize::MAX),
+                    None => Vec::new(),
+                };
+                Response::success(id, ResponseData::HostStats(hosts))
+            }
+            
+            Command::GetFlows { limit, filter } => {
+                let flows = match *state.backend_handle.read() {
+                    Some(ref handle) => handle.pipeline.flow_cache().iter_snapshot(),
+                    None => Vec::new(),
+                };
+                let mut flows: Vec<FlowInfo> = flows
+                    .into_iter()
+                    .map(FlowInfo::from)
+                    .filter(|flow| filter.as_deref().is_none_or(|filter| flow.matches(filter)))
+                    .collect();
+                flows.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(b.packets.cmp(&a.packets)));
+                flows.truncate(limit.unwrap_or(usize::MAX));
+                Response::success(id, ResponseData::Flows(flows))
+            }
+            
+            Command::Explain { dst, port, protocol, hostname } => {
+                let src = if dst.is_ipv4() {
+                    IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+                } else {
+                    IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+                };
+                let key = FlowKey::new(src, *dst, 0, *port, *protocol);
+                let meta = PacketMeta::outbound().with_hostname(hostname.clone());
+                
+                let pipeline = state.backend_handle.read().as_ref().map(|handle| handle.pipeline.clone());
+                let pipeline = match pipeline {
+                    Some(pipeline) => pipeline,
+                    None => match Pipeline::new(state.config.read().clone(), Arc::new(Stats::new())) {
+                        Ok(pipeline) => Arc::new(pipeline),
+                        Err(e) => return Response::error(id, e.to_string()),
+                    },
+                };
+                Response::success(id, ResponseData::Explanation(pipeline.explain_with_meta(key, &meta, EXPLAIN_SAMPLE_LEN)))
+            }
+
+            Command::SetLogLevel { level, target } => {
+                let mut logging = state.logging.write();
+                let Some(ref mut logging) = *logging else {
+                    return Response::error(id, "Log level cannot be changed at runtime".to_string());
+                };
+                let filter = match target {
+                    Some(target) => with_target_level(&logging.filter, target, level),
+                    None => level.clone(),
+                };
+                match (logging.reload)(&filter) {
+                    Ok(()) => {
+                        info!(filter = %filter, "Log filter changed");
+                        logging.filter = filter.clone();
+                        Response::success(id, ResponseData::LogLevel { filter })
+                    }
+                    Err(e) => Response::error(id, format!("Invalid log filter '{}': {}", filter, e)),
+                }
+            }
+            
+            Command::GetLogLevel => match *state.logging.read() {
+                Some(ref logging) => Response::success(id, ResponseData::LogLevel { filter: logging.filter.clone() }),
+                None => Response::error(id, "Log level cannot be changed at runtime".to_string()),
+            },
+            
+            Command::GetStateHistory => {
+                let history = state.state_history.lock().iter().copied().collect();
+                Response::success(id, ResponseData::StateHistory(history))
+            }
+            
+            Command::Subscribe { kinds } => {
+                if state.notifications.is_none() {
+                    return Response::error(id, "Notifications are disabled".to_string());
+                }
+                match kinds.iter().find(|kind| !NotificationKind::NAMES.contains(&kind.as_str())) {
+                    Some(kind) => Response::error(
+                        id,
+                        format!("Unknown notification kind '{}' (expected one of: {})", kind, NotificationKind::NAMES.join(", ")),
+                    ),
+                    None => Response::ok(id),
+                }
+            }
+            
             Command::Ping => {
-                let timestamp = std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_millis() as u64;
-                Response::success(id, ResponseData::Pong { timestamp })
+                Response::success(id, ResponseData::Pong { timestamp: now_millis() })
+            }
+        }
+    }
+    
+    async fn stop_backend(state: &ServerState) -> Result<()> {
+        state.transition(EngineState::Stopping)?;
+        state.persist_stats();
+        
+        let handle = state.backend_handle.write().take();
+        if let Some(handle) = handle {
+            if let Err(e) = handle.shutdown().await {
+                warn!(error = %e, "Error during shutdown");
+                state.notify(NotificationKind::Error { message: e.to_string() });
+            }
+        }
+        
+        *state.backend_type.write() = None;
+        if let Err(e) = state.transition(EngineState::Stopped) {
+            warn!(error = %e, "Backend stopped outside of Stopping");
+        }
+        Ok(())
+    }
+    
+    async fn shut_down(state: &ServerState, grace: Duration) {
+        info!(grace_secs = grace.as_secs(), "Shutting down on request");
+        *state.shutdown_grace.lock() = grace;
+        if state.engine_state.read().is_running() {
+            let connections = state.backend_handle.read().as_ref().and_then(|handle| handle.connections.clone());
+            if let Some(connections) = connections {
+                connections.drain(grace).await;
+            }
+            if let Err(e) = Self::stop_backend(state).await {
+                warn!(error = %e, "Failed to stop the backend");
+            }
+        }
+        state.shutdown_requested.notify_one();
+    }
+    
+    fn set_paused(id: u64, state: &ServerState, paused: bool) -> Response {
+        let pipeline = match *state.backend_handle.read() {
+            Some(ref handle) => {
+                let target = if paused { EngineState::Paused } else { EngineState::Running };
+                if let Err(e) = state.transition(target) {
+                    return Response::error(id, e.to_string());
+                }
+                handle.pipeline.clone()
             }
+            None => match *state.external_pipeline.read() {
+                Some(ref pipeline) => pipeline.clone(),
+                None => return Response::error(id, ControlError::NotRunning.to_string()),
+            },
+        };
+        
+        if paused {
+            pipeline.pause();
+        } else {
+            pipeline.resume();
+        }
+        info!(paused, "Pipeline {}", if paused { "paused" } else { "resumed" });
+        Response::ok(id)
+    }
+
+    fn reload(id: u64, state: &ServerState, config: Config) -> Response {
+        let engine_state = *state.engine_state.read();
+        if matches!(engine_state, EngineState::Starting | EngineState::Stopping) {
+            return Response::error(id, format!("Cannot reload while the engine is {:?}", engine_state));
+        }
+        match Self::apply_config(state, config) {
+            Ok(()) => Response::ok(id),
+            Err(e) => Response::error(id, describe_config_error(&e)),
+        }
+    }
+    
+    fn apply_config(state: &ServerState, config: Config) -> Result<()> {
+        config.validate()?;
+        for warning in config.warnings() {
+            warn!("{}", warning);
+        }
+        
+        if let Some(ref handle) = *state.backend_handle.read() {
+            handle.reload_config(config.clone())?;
         }
+        *state.config.write() = config;
+        state.notify(NotificationKind::ConfigReloaded);
+        Ok(())
+    }
+
+    /// Reloads `path` whenever it changes, applying it to the server state and
+    /// then to `pipeline`, a backend pipeline the server did not start itself.
+    /// A config the server rejects never reaches `pipeline`.
+    pub fn watch_config(&self, path: PathBuf, interval: Duration, pipeline: Option<Arc<Pipeline>>) -> JoinHandle<()> {
+        let state = self.state.clone();
+        *state.config_path.write() = Some(path.clone());
+        let current = state.config.read().clone();
+        
+        watch::watch_config(path, interval, current, move |config| {
+            Self::apply_config(&state, config.clone())?;
+            if let Some(ref pipeline) = pipeline {
+                pipeline.reload_config(config)?;
+            }
+            Ok(())
+        })
     }
 
     pub fn load_config(&self, path: impl AsRef<Path>) -> Result<()> {
@@ -370,11 +1017,58 @@ impl ControlServer {
         Ok(())
     }
 
+    /// Remembers `path` as the file the running config came from, which a
+    /// reload without a path re-reads.
+    pub fn set_config_path(&self, path: impl Into<PathBuf>) {
+        *self.state.config_path.write() = Some(path.into());
+    }
+    
+    /// Lets Pause and Resume reach `pipeline`, a backend pipeline the server
+    /// did not start itself.
+    pub fn set_external_pipeline(&self, pipeline: Arc<Pipeline>) {
+        *self.state.external_pipeline.write() = Some(pipeline);
+    }
+    
+    pub fn set_log_reloader(&self, filter: impl Into<String>, reload: LogReloader) {
+        *self.state.logging.write() = Some(LogControl {
+            filter: filter.into(),
+            reload,
+        });
+    }
+    
     pub fn socket_path(&self) -> &Path {
         &self.server_config.socket_path
     }
 }
 
+fn with_target_level(filter: &str, target: &str, level: &str) -> String {
+    let directive = format!("{}={}", target, level);
+    let mut directives: Vec<&str> = filter
+        .split(',')
+        .map(str::trim)
+        .filter(|d| !d.is_empty() && d.split_once('=').is_none_or(|(t, _)| t != target))
+        .collect();
+    directives.push(&directive);
+    directives.join(",")
+}
+
+/// Like `to_string`, but names the field a validation failure is about.
+fn describe_config_error(e: &ControlError) -> String {
+    match e {
+        ControlError::Engine(engine::EngineError::ConfigValidation { field, message }) => {
+            format!("Invalid config: {}: {}", field, message)
+        }
+        other => other.to_string(),
+    }
+}
+
+fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
 pub struct ControlClient {
     socket_path: PathBuf,
     next_id: u64,
@@ -420,8 +1114,8 @@ impl ControlClient {
         }
     }
 
-    pub async fn start(&mut self) -> Result<()> {
-        let response = self.send(Command::Start).await?;
+    pub async fn start(&mut self, backend: Option<BackendSpec>) -> Result<()> {
+        let response = self.send(Command::Start { backend }).await?;
         if response.success {
             Ok(())
         } else if let ResponseData::Error { message } = response.data {
@@ -441,6 +1135,39 @@ impl ControlClient {
             Err(ControlError::Internal("Unknown error".to_string()))
         }
     }
+    
+    pub async fn pause(&mut self) -> Result<()> {
+        let response = self.send(Command::Pause).await?;
+        if response.success {
+            Ok(())
+        } else if let ResponseData::Error { message } = response.data {
+            Err(ControlError::Internal(message))
+        } else {
+            Err(ControlError::Internal("Unknown error".to_string()))
+        }
+    }
+    
+    pub async fn resume(&mut self) -> Result<()> {
+        let response = self.send(Command::Resume).await?;
+        if response.success {
+            Ok(())
+        } else if let ResponseData::Error { message } = response.data {
+            Err(ControlError::Internal(message))
+        } else {
+            Err(ControlError::Internal("Unknown error".to_string()))
+        }
+    }
+    
+    pub async fn shutdown(&mut self, grace_secs: Option<u64>) -> Result<()> {
+        let response = self.send(Command::Shutdown { grace_secs }).await?;
+        if response.success {
+            Ok(())
+        } else if let ResponseData::Error { message } = response.data {
+            Err(ControlError::Internal(message))
+        } else {
+            Err(ControlError::Internal("Unknown error".to_string()))
+        }
+    }
 
     pub async fn status(&mut self) -> Result<Status> {
         let response = self.send(Command::GetStatus).await?;
@@ -450,24 +1177,76 @@ impl ControlClient {
             _ => Err(ControlError::InvalidRequest("Unexpected response".to_string())),
         }
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tempfile::tempdir;
-
-    #[tokio::test]
-    async fn test_server_start_stop() {
-        let temp_dir = tempdir().unwrap();
-        let socket_path = temp_dir.path().join("test.sock");
+    
+    /// Opens a connection of its own that receives notifications of `kinds`,
+    /// or of every kind if it is empty.
+    pub async fn subscribe(&mut self, kinds: Vec<String>) -> Result<NotificationStream> {
+        let stream = UnixStream::connect(&self.socket_path)
+            .await
+            .map_err(|e| ControlError::Connection(e.to_string()))?;
         
-        let server_config = ServerConfig {
-            socket_path: socket_path.clone(),
-            ..Default::default()
-        };
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
         
-        let mut server = ControlServer::new(server_config, Config::default());
+        let request = Request::new(self.next_id, Command::Subscribe { kinds });
+        self.next_id += 1;
+        
+        let request_json = serde_json::to_string(&request)?;
+        writer.write_all(request_json.as_bytes()).await?;
+        writer.write_all(b"\n").await?;
+        writer.flush().await?;
+        
+        let mut line = String::new();
+        reader.read_line(&mut line).await?;
+        let response: Response = serde_json::from_str(&line)?;
+        match response.data {
+            ResponseData::Ok => Ok(NotificationStream {
+                reader,
+                _writer: writer,
+                line,
+            }),
+            ResponseData::Error { message } => Err(ControlError::Internal(message)),
+            _ => Err(ControlError::InvalidRequest("Unexpected response".to_string())),
+        }
+    }
+}
+
+pub struct NotificationStream {
+    reader: BufReader<tokio::net::unix::OwnedReadHalf>,
+    // Dropping the write half would look like a disconnect to the server.
+    _writer: tokio::net::unix::OwnedWriteHalf,
+    line: String,
+}
+
+impl NotificationStream {
+    /// The next notification, or `None` once the server closes the stream.
+    pub async fn next(&mut self) -> Option<Result<Notification>> {
+        self.line.clear();
+        match self.reader.read_line(&mut self.line).await {
+            Ok(0) => None,
+            Ok(_) => Some(serde_json::from_str(&self.line).map_err(Into::into)),
+            Err(e) => Some(Err(e.into())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[tokio::test]
+    async fn test_server_start_stop() {
+        let temp_dir = tempdir().unwrap();
+        let socket_path = temp_dir.path().join("test.sock");
+        
+        let server_config = ServerConfig {
+            socket_path: socket_path.clone(),
+            ..Default::default()
+        };
+        
+        let mut server = ControlServer::new(server_config, Config::default());
         
         server.start().await.unwrap();
         assert!(server.is_running());
@@ -505,6 +1284,41 @@ mod tests {
         server.stop().await.unwrap();
     }
 
+    #[tokio::test]
+    async fn test_closed_clients_free_their_slot() {
+        let temp_dir = tempdir().unwrap();
+        let socket_path = temp_dir.path().join("test.sock");
+        
+        let server_config = ServerConfig {
+            socket_path: socket_path.clone(),
+            max_clients: 2,
+            ..Default::default()
+        };
+        let max_clients = server_config.max_clients;
+        let mut server = ControlServer::new(server_config, Config::default());
+        server.start().await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        
+        let mut client = ControlClient::new(&socket_path);
+        for _ in 0..max_clients + 5 {
+            let health = tokio::time::timeout(std::time::Duration::from_secs(5), client.health())
+                .await
+                .expect("health request hung")
+                .unwrap();
+            assert_eq!(health.connected_clients, 1);
+            
+            // The handler notices the disconnect a moment after the reply.
+            let released = async {
+                while server.state.connected_clients.load(Ordering::SeqCst) > 0 {
+                    tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+                }
+            };
+            tokio::time::timeout(std::time::Duration::from_secs(5), released).await.expect("client slot leaked");
+        }
+        
+        server.stop().await.unwrap();
+    }
+    
     #[tokio::test]
     async fn test_ping_pong() {
         let temp_dir = tempdir().unwrap();
@@ -531,4 +1345,606 @@ mod tests {
         
         server.stop().await.unwrap();
     }
+
+    #[tokio::test]
+    async fn test_dns_cache_commands_require_backend() {
+        let temp_dir = tempdir().unwrap();
+        let socket_path = temp_dir.path().join("test.sock");
+        
+        let server_config = ServerConfig {
+            socket_path: socket_path.clone(),
+            ..Default::default()
+        };
+        
+        let mut server = ControlServer::new(server_config, Config::default());
+        server.start().await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        
+        let mut client = ControlClient::new(&socket_path);
+        for command in [Command::FlushDnsCache, Command::GetDnsCache] {
+            let response = client.send(command).await.unwrap();
+            assert!(!response.success);
+        }
+        
+        server.stop().await.unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_reset_stats_removes_saved_baseline() {
+        let temp_dir = tempdir().unwrap();
+        let socket_path = temp_dir.path().join("test.sock");
+        let stats_file = temp_dir.path().join("stats.json");
+        
+        let stats = Stats::new();
+        stats.record_packet_in(100);
+        stats.save_to(&stats_file).unwrap();
+        
+        let server_config = ServerConfig {
+            socket_path: socket_path.clone(),
+            stats_file: Some(stats_file.clone()),
+            ..Default::default()
+        };
+        let mut server = ControlServer::new(server_config, Config::default());
+        server.start().await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        
+        let mut client = ControlClient::new(&socket_path);
+        assert!(client.send(Command::ResetStats).await.unwrap().success);
+        assert!(!stats_file.exists());
+        assert!(client.send(Command::ResetStats).await.unwrap().success);
+        
+        server.stop().await.unwrap();
+        assert!(!stats_file.exists());
+    }
+    
+    #[tokio::test]
+    async fn test_subscribe_receives_state_changes() {
+        let temp_dir = tempdir().unwrap();
+        let socket_path = temp_dir.path().join("test.sock");
+        
+        let server_config = ServerConfig {
+            socket_path: socket_path.clone(),
+            ..Default::default()
+        };
+        let mut server = ControlServer::new(server_config, Config::default());
+        server.start().await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        
+        let mut subscriber = ControlClient::new(&socket_path);
+        assert!(subscriber.subscribe(vec!["bogus".to_string()]).await.is_err());
+        let mut notifications = subscriber.subscribe(vec!["state_changed".to_string()]).await.unwrap();
+        
+        let mut client = ControlClient::new(&socket_path);
+        let started = client.send(Command::Start { backend: None }).await.unwrap().success;
+        
+        let mut states = Vec::new();
+        for _ in 0..2 {
+            let notification = tokio::time::timeout(std::time::Duration::from_secs(5), notifications.next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            match notification.kind {
+                NotificationKind::StateChanged { old, new } => states.push((old, new)),
+                other => panic!("unexpected notification: {:?}", other),
+            }
+        }
+        let outcome = if started { EngineState::Running } else { EngineState::Error };
+        assert_eq!(states, vec![
+            (EngineState::Stopped, EngineState::Starting),
+            (EngineState::Starting, outcome),
+        ]);
+        
+        if started {
+            assert!(client.send(Command::Stop).await.unwrap().success);
+        }
+        server.stop().await.unwrap();
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while notifications.next().await.is_some() {}
+        }).await.unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_start_with_backend_spec() {
+        let temp_dir = tempdir().unwrap();
+        let socket_path = temp_dir.path().join("test.sock");
+        
+        let server_config = ServerConfig {
+            socket_path: socket_path.clone(),
+            ..Default::default()
+        };
+        let listen = vec!["127.0.0.1:0".parse().unwrap()];
+        let mut config = Config::default();
+        config.backend.kind = engine::config::BackendKind::BypassHttp;
+        config.backend.listen = listen.clone();
+        let mut server = ControlServer::new(server_config, config);
+        server.start().await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        
+        let mut client = ControlClient::new(&socket_path);
+        let invalid = BackendSpec::HttpConnect { listen: Vec::new(), max_connections: Some(0) };
+        let err = client.start(Some(invalid)).await.unwrap_err();
+        assert!(err.to_string().contains("max_connections"), "{}", err);
+        assert_eq!(*server.state.engine_state.read(), EngineState::Stopped);
+        
+        let specs = [
+            None,
+            Some(BackendSpec::Socks5 { listen: listen.clone(), max_connections: Some(10) }),
+            Some(BackendSpec::HttpConnect { listen, max_connections: None }),
+        ];
+        for (spec, kind) in specs.into_iter().zip(["bypass_http", "socks5", "http_connect"]) {
+            client.start(spec).await.unwrap();
+            let health = match client.send(Command::Health).await.unwrap().data {
+                ResponseData::Health(health) => health,
+                other => panic!("unexpected response: {:?}", other),
+            };
+            assert_eq!(health.backend.as_deref(), Some(kind));
+            let addr = server.state.backend_handle.read().as_ref().unwrap().listen_addrs()[0];
+            assert!(tokio::net::TcpStream::connect(addr).await.is_ok());
+            client.stop().await.unwrap();
+        }
+        
+        server.stop().await.unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_pause_resume() {
+        let temp_dir = tempdir().unwrap();
+        let socket_path = temp_dir.path().join("test.sock");
+        
+        let server_config = ServerConfig {
+            socket_path: socket_path.clone(),
+            ..Default::default()
+        };
+        let mut config = Config::default();
+        config.backend.listen = vec!["127.0.0.1:0".parse().unwrap()];
+        let mut server = ControlServer::new(server_config, config);
+        server.start().await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        
+        let mut client = ControlClient::new(&socket_path);
+        assert!(client.pause().await.is_err());
+        client.start(None).await.unwrap();
+        let pipeline = server.state.backend_handle.read().as_ref().unwrap().pipeline.clone();
+        
+        client.pause().await.unwrap();
+        assert!(pipeline.is_paused());
+        let status = client.status().await.unwrap();
+        assert_eq!(status.state, EngineState::Paused);
+        assert!(status.running);
+        assert!(client.pause().await.is_err());
+        
+        client.resume().await.unwrap();
+        assert!(!pipeline.is_paused());
+        assert_eq!(client.status().await.unwrap().state, EngineState::Running);
+        
+        client.pause().await.unwrap();
+        client.stop().await.unwrap();
+        assert_eq!(*server.state.engine_state.read(), EngineState::Stopped);
+        assert!(client.resume().await.is_err());
+        
+        server.stop().await.unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_pause_reaches_external_pipeline() {
+        let temp_dir = tempdir().unwrap();
+        let socket_path = temp_dir.path().join("test.sock");
+        
+        let server_config = ServerConfig {
+            socket_path: socket_path.clone(),
+            ..Default::default()
+        };
+        let mut config = Config::default();
+        config.backend.listen = vec!["127.0.0.1:0".parse().unwrap()];
+        let mut server = ControlServer::new(server_config, config.clone());
+        server.start().await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        
+        // Started beside the server, the way `run --proxy` does.
+        let backend_config = BackendConfig {
+            backend_settings: BackendSettings::from_config(&config.backend),
+            engine_config: config,
+            max_queue_size: 1000,
+        };
+        let mut backend = backend_config.backend_settings.backend();
+        let handle = backend.start(backend_config).await.unwrap();
+        
+        let mut client = ControlClient::new(&socket_path);
+        let error = client.pause().await.unwrap_err();
+        assert!(error.to_string().contains(&ControlError::NotRunning.to_string()), "{}", error);
+        assert!(!handle.pipeline.is_paused());
+        
+        server.set_external_pipeline(handle.pipeline.clone());
+        client.pause().await.unwrap();
+        assert!(handle.pipeline.is_paused());
+        client.resume().await.unwrap();
+        assert!(!handle.pipeline.is_paused());
+        
+        handle.shutdown().await.unwrap();
+        backend.stop().await.unwrap();
+        server.stop().await.unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_state_transitions_and_history() {
+        let temp_dir = tempdir().unwrap();
+        let socket_path = temp_dir.path().join("test.sock");
+        
+        let server_config = ServerConfig {
+            socket_path: socket_path.clone(),
+            ..Default::default()
+        };
+        let mut server = ControlServer::new(server_config, Config::default());
+        server.start().await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let state = server.state.clone();
+        
+        assert!(matches!(
+            state.transition(EngineState::Running),
+            Err(ControlError::InvalidTransition { from: EngineState::Stopped, to: EngineState::Running })
+        ));
+        assert!(state.state_history.lock().is_empty());
+        
+        assert_eq!(state.transition(EngineState::Starting).unwrap(), EngineState::Stopped);
+        let mut client = ControlClient::new(&socket_path);
+        let stop = client.send(Command::Stop).await.unwrap();
+        assert!(!stop.success);
+        assert_eq!(*state.engine_state.read(), EngineState::Starting);
+        
+        let cycle = [EngineState::Running, EngineState::Stopping, EngineState::Stopped, EngineState::Starting];
+        for new in cycle.iter().cycle().take(40) {
+            state.transition(*new).unwrap();
+        }
+        state.transition(EngineState::Error).unwrap();
+        state.transition(EngineState::Error).unwrap();
+        
+        let history = match client.send(Command::GetStateHistory).await.unwrap().data {
+            ResponseData::StateHistory(history) => history,
+            other => panic!("unexpected response: {:?}", other),
+        };
+        assert_eq!(history.len(), STATE_HISTORY_LEN);
+        assert_eq!(history.last().map(|t| (t.from, t.to)), Some((EngineState::Starting, EngineState::Error)));
+        for pair in history.windows(2) {
+            assert_eq!(pair[0].to, pair[1].from);
+            assert!(pair[0].timestamp <= pair[1].timestamp);
+        }
+        
+        server.stop().await.unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_reload_from_path() {
+        let temp_dir = tempdir().unwrap();
+        let socket_path = temp_dir.path().join("test.sock");
+        let config_path = temp_dir.path().join("config.toml");
+        
+        let server_config = ServerConfig {
+            socket_path: socket_path.clone(),
+            ..Default::default()
+        };
+        let mut server = ControlServer::new(server_config, Config::default());
+        server.start().await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        
+        let mut client = ControlClient::new(&socket_path);
+        let mut reload = async |path: &Path| {
+            client.send(Command::ReloadFromPath { path: path.display().to_string() }).await.unwrap()
+        };
+        
+        std::fs::write(&config_path, "[limits]\nmax_flows = 1234\n").unwrap();
+        assert!(reload(&config_path).await.success);
+        assert_eq!(server.state.config.read().limits.max_flows, 1234);
+        
+        let invalid_path = temp_dir.path().join("invalid.toml");
+        std::fs::write(&invalid_path, "[limits]\nmax_flows = 0\n").unwrap();
+        let response = reload(&invalid_path).await;
+        match response.data {
+            ResponseData::Error { message } => assert!(message.contains("limits.max_flows"), "{}", message),
+            other => panic!("unexpected response: {:?}", other),
+        }
+        assert!(!reload(&temp_dir.path().join("missing.toml")).await.success);
+        assert_eq!(server.state.config.read().limits.max_flows, 1234);
+        
+        let status = ControlClient::new(&socket_path).status().await.unwrap();
+        assert_eq!(status.config_path, Some(config_path.display().to_string()));
+        
+        server.stop().await.unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_save_config() {
+        let temp_dir = tempdir().unwrap();
+        let socket_path = temp_dir.path().join("test.sock");
+        let config_path = temp_dir.path().join("config.toml");
+        
+        let server_config = ServerConfig {
+            socket_path: socket_path.clone(),
+            ..Default::default()
+        };
+        let mut server = ControlServer::new(server_config, Config::default());
+        server.start().await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        
+        let mut client = ControlClient::new(&socket_path);
+        let save = |path: Option<&Path>, format: Option<&str>| Command::SaveConfig {
+            path: path.map(|p| p.display().to_string()),
+            format: format.map(str::to_string),
+        };
+        assert!(!client.send(save(None, None)).await.unwrap().success);
+        
+        let mut pushed = Config::default();
+        pushed.limits.max_flows = 4321;
+        assert!(client.send(Command::Reload(pushed)).await.unwrap().success);
+        
+        let json_path = temp_dir.path().join("saved.json");
+        let response = client.send(save(Some(&json_path), None)).await.unwrap();
+        assert!(matches!(response.data, ResponseData::Saved { ref path } if *path == json_path.display().to_string()));
+        assert_eq!(Config::load_from_file(&json_path).unwrap().limits.max_flows, 4321);
+        
+        assert!(!client.send(save(Some(&json_path), Some("yaml"))).await.unwrap().success);
+        assert!(!client.send(save(Some(&json_path), Some("toml"))).await.unwrap().success);
+        assert_eq!(Config::load_from_file(&json_path).unwrap().limits.max_flows, 4321);
+        
+        server.set_config_path(&config_path);
+        assert!(client.send(save(None, None)).await.unwrap().success);
+        let saved = std::fs::read_to_string(&config_path).unwrap();
+        assert!(saved.contains("max_flows = 4321"), "{}", saved);
+        
+        server.stop().await.unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_get_flows() {
+        let temp_dir = tempdir().unwrap();
+        let socket_path = temp_dir.path().join("test.sock");
+        
+        let server_config = ServerConfig {
+            socket_path: socket_path.clone(),
+            ..Default::default()
+        };
+        let mut server = ControlServer::new(server_config, Config::default());
+        server.start().await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        
+        let stats = Arc::new(Stats::new());
+        let pipeline = Arc::new(Pipeline::new(Config::default(), stats.clone()).unwrap());
+        let flow = |port: u16| FlowKey::new(
+            "10.0.0.2".parse().unwrap(),
+            "104.16.0.1".parse().unwrap(),
+            port,
+            443,
+            engine::config::Protocol::Tcp,
+        );
+        for (port, hostname, packets) in [(40001, "discord.com", 3), (40002, "example.com", 1), (40003, "cdn.discordapp.com", 2)] {
+            for _ in 0..packets {
+                let meta = PacketMeta::outbound().with_hostname(Some(hostname.to_string()));
+                pipeline.process_with_meta(flow(port), bytes::BytesMut::from(&[0u8; 100][..]), meta).unwrap();
+            }
+        }
+        *server.state.backend_handle.write() = Some(BackendHandle {
+            shutdown_tx: mpsc::channel(1).0,
+            stats,
+            history: Default::default(),
+            pipeline,
+            connections: None,
+            listen_addrs: Vec::new(),
+            dns: None,
+            proxy: None,
+        });
+        
+        let mut client = ControlClient::new(&socket_path);
+        let mut get_flows = async |limit, filter: Option<&str>| {
+            let command = Command::GetFlows { limit, filter: filter.map(str::to_string) };
+            match client.send(command).await.unwrap().data {
+                ResponseData::Flows(flows) => flows,
+                other => panic!("unexpected response: {:?}", other),
+            }
+        };
+        
+        let flows = get_flows(None, None).await;
+        let ports: Vec<u16> = flows.iter().map(|flow| flow.src_port).collect();
+        assert_eq!(ports, vec![40001, 40003, 40002]);
+        assert_eq!(flows[0].hostname.as_deref(), Some("discord.com"));
+        assert_eq!((flows[0].packets, flows[0].bytes), (3, 300));
+        assert_eq!(flows[0].dst_ip, "104.16.0.1".parse::<IpAddr>().unwrap());
+        
+        assert_eq!(get_flows(Some(1), None).await.len(), 1);
+        assert_eq!(get_flows(None, Some("DISCORD")).await.len(), 2);
+        assert_eq!(get_flows(None, Some("104.16.")).await.len(), 3);
+        assert!(get_flows(None, Some("192.168.")).await.is_empty());
+        
+        server.stop().await.unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_log_level_commands() {
+        let temp_dir = tempdir().unwrap();
+        let socket_path = temp_dir.path().join("test.sock");
+        
+        let server_config = ServerConfig {
+            socket_path: socket_path.clone(),
+            ..Default::default()
+        };
+        let mut server = ControlServer::new(server_config, Config::default());
+        server.start().await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        
+        let mut client = ControlClient::new(&socket_path);
+        let set = |level: &str, target: Option<&str>| Command::SetLogLevel {
+            level: level.to_string(),
+            target: target.map(str::to_string),
+        };
+        assert!(!client.send(set("debug", None)).await.unwrap().success);
+        assert!(!client.send(Command::GetLogLevel).await.unwrap().success);
+        
+        let (layer, handle) = reload::Layer::new(EnvFilter::new("info"));
+        let _subscriber = tracing::subscriber::set_default(tracing_subscriber::registry().with(layer));
+        server.set_log_reloader("info", env_filter_reloader(handle));
+        assert!(!tracing::enabled!(tracing::Level::DEBUG));
+        
+        let log_level = |response: Response| match response.data {
+            ResponseData::LogLevel { filter } => filter,
+            other => panic!("unexpected response: {:?}", other),
+        };
+        assert_eq!(log_level(client.send(set("debug", None)).await.unwrap()), "debug");
+        assert!(tracing::enabled!(tracing::Level::DEBUG));
+        assert_eq!(log_level(client.send(set("trace", Some("engine"))).await.unwrap()), "debug,engine=trace");
+        assert!(tracing::enabled!(target: "engine", tracing::Level::TRACE));
+        assert_eq!(log_level(client.send(set("info", Some("engine"))).await.unwrap()), "debug,engine=info");
+        assert!(!tracing::enabled!(target: "engine", tracing::Level::DEBUG));
+        
+        let response = client.send(set("engine=loud", None)).await.unwrap();
+        assert!(!response.success);
+        assert_eq!(log_level(client.send(Command::GetLogLevel).await.unwrap()), "debug,engine=info");
+        assert!(!tracing::enabled!(target: "engine", tracing::Level::DEBUG));
+        assert!(tracing::enabled!(tracing::Level::DEBUG));
+        
+        server.stop().await.unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_stalled_and_oversized_requests_disconnected() {
+        let temp_dir = tempdir().unwrap();
+        let socket_path = temp_dir.path().join("test.sock");
+        
+        let server_config = ServerConfig {
+            socket_path: socket_path.clone(),
+            timeout_secs: 1,
+            max_request_bytes: 256,
+            ..Default::default()
+        };
+        let mut server = ControlServer::new(server_config, Config::default());
+        server.start().await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        
+        let rejected = |payload: &'static [u8]| {
+            let socket_path = socket_path.clone();
+            async move {
+                let mut stream = UnixStream::connect(&socket_path).await.unwrap();
+                stream.write_all(payload).await.unwrap();
+                let mut reply = String::new();
+                let read = tokio::time::timeout(
+                    std::time::Duration::from_secs(3),
+                    tokio::io::AsyncReadExt::read_to_string(&mut stream, &mut reply),
+                );
+                read.await.expect("connection left open").unwrap();
+                let response: Response = serde_json::from_str(reply.trim()).unwrap();
+                assert!(!response.success);
+                match response.data {
+                    ResponseData::Error { message } => message,
+                    other => panic!("unexpected response: {:?}", other),
+                }
+            }
+        };
+        
+        let started = Instant::now();
+        assert_eq!(rejected(b"garbage without a newline").await, "Request timeout");
+        assert!(started.elapsed() >= std::time::Duration::from_secs(1));
+        assert!(rejected(&[b'x'; 300]).await.contains("256 bytes"));
+        
+        let mut client = ControlClient::new(&socket_path);
+        assert!(client.send(Command::Ping).await.unwrap().success);
+        
+        server.stop().await.unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_rule_commands() {
+        let temp_dir = tempdir().unwrap();
+        let socket_path = temp_dir.path().join("test.sock");
+        
+        let config = ServerConfig {
+            socket_path: socket_path.clone(),
+            ..Default::default()
+        };
+        let mut server = ControlServer::new(config, Config::default());
+        server.start().await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        
+        let mut rule = engine::config::Rule {
+            name: "https".to_string(),
+            enabled: true,
+            priority: 10,
+            match_criteria: engine::config::MatchCriteria {
+                dst_ports: Some(vec![443]),
+                ..Default::default()
+            },
+            action: Default::default(),
+            transforms: vec![engine::config::TransformType::Fragment],
+            overrides: Default::default(),
+        };
+        
+        let mut client = ControlClient::new(&socket_path);
+        assert!(client.send(Command::AddRule(rule.clone())).await.unwrap().success);
+        
+        rule.name = "invalid".to_string();
+        rule.transforms.clear();
+        assert!(!client.send(Command::AddRule(rule)).await.unwrap().success);
+        
+        let response = client.send(Command::SetRuleEnabled { name: "https".to_string(), enabled: false }).await.unwrap();
+        assert!(response.success);
+        let response = client.send(Command::RemoveRule { name: "missing".to_string() }).await.unwrap();
+        assert!(!response.success);
+        
+        match client.send(Command::GetConfig).await.unwrap().data {
+            ResponseData::Config(config) => {
+                assert_eq!(config.rules.len(), 1);
+                assert_eq!(config.rules[0].name, "https");
+                assert!(!config.rules[0].enabled);
+            }
+            other => panic!("unexpected response: {:?}", other),
+        }
+        
+        assert!(client.send(Command::RemoveRule { name: "https".to_string() }).await.unwrap().success);
+        
+        server.stop().await.unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_explain_without_backend() {
+        let temp_dir = tempdir().unwrap();
+        let socket_path = temp_dir.path().join("test.sock");
+        
+        let mut engine_config = Config::default();
+        engine_config.rules.push(engine::config::Rule {
+            name: "https".to_string(),
+            enabled: true,
+            priority: 10,
+            match_criteria: engine::config::MatchCriteria {
+                dst_ports: Some(vec![443]),
+                ..Default::default()
+            },
+            action: Default::default(),
+            transforms: vec![engine::config::TransformType::Fragment],
+            overrides: Default::default(),
+        });
+        
+        let config = ServerConfig {
+            socket_path: socket_path.clone(),
+            ..Default::default()
+        };
+        let mut server = ControlServer::new(config, engine_config);
+        server.start().await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        
+        let mut client = ControlClient::new(&socket_path);
+        let response = client.send(Command::Explain {
+            dst: "104.16.0.1".parse().unwrap(),
+            port: 443,
+            protocol: engine::config::Protocol::Tcp,
+            hostname: Some("discord.com".to_string()),
+        }).await.unwrap();
+        
+        match response.data {
+            ResponseData::Explanation(explanation) => {
+                assert_eq!(explanation.matched_rule.as_deref(), Some("https"));
+                assert_eq!(explanation.hostname.as_deref(), Some("discord.com"));
+                assert!(explanation.output_sizes.len() > 1);
+            }
+            other => panic!("unexpected response: {:?}", other),
+        }
+        
+        server.stop().await.unwrap();
+    }
 }
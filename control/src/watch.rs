@@ -0,0 +1,215 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use serde_json::Value;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, error, info, warn};
+
+use engine::Config;
+
+use crate::error::Result;
+
+pub const DEFAULT_WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+fn file_stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Polls `path` every `interval` and hands each changed, valid config to
+/// `apply`. Configs that fail to load or apply are logged and the previous
+/// one stays in effect.
+pub fn watch_config<F>(path: PathBuf, interval: Duration, mut current: Config, mut apply: F) -> JoinHandle<()>
+where
+    F: FnMut(Config) -> Result<()> + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut last_stamp = file_stamp(&path);
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        
+        info!(path = %path.display(), "Watching config file for changes");
+        
+        loop {
+            ticker.tick().await;
+            
+            let stamp = file_stamp(&path);
+            if stamp == last_stamp {
+                continue;
+            }
+            last_stamp = stamp;
+            if stamp.is_none() {
+                warn!(path = %path.display(), "Watched config file is missing; keeping the current config");
+                continue;
+            }
+            
+            let config = match Config::load_with_env(Some(&path)) {
+                Ok(config) => config,
+                Err(e) => {
+                    error!(path = %path.display(), error = %e, "Rejected changed config; keeping the current config");
+                    continue;
+                }
+            };
+            
+            let changes = describe_changes(&current, &config);
+            if changes.is_empty() {
+                debug!(path = %path.display(), "Config file touched without changes");
+                continue;
+            }
+            
+            match apply(config.clone()) {
+                Ok(()) => {
+                    info!(path = %path.display(), changes = %changes.join(", "), "Reloaded config");
+                    current = config;
+                }
+                Err(e) => {
+                    error!(path = %path.display(), error = %e, "Failed to apply changed config; keeping the current config");
+                }
+            }
+        }
+    })
+}
+
+pub fn describe_changes(old: &Config, new: &Config) -> Vec<String> {
+    let mut changes = Vec::new();
+    
+    for rule in &new.rules {
+        match old.rules.iter().find(|r| r.name == rule.name) {
+            None => changes.push(format!("rule added: {}", rule.name)),
+            Some(previous) if to_value(previous) != to_value(rule) => {
+                changes.push(format!("rule changed: {}", rule.name));
+            }
+            Some(_) => {}
+        }
+    }
+    for rule in &old.rules {
+        if !new.rules.iter().any(|r| r.name == rule.name) {
+            changes.push(format!("rule removed: {}", rule.name));
+        }
+    }
+    
+    let (old_limits, new_limits) = (to_value(&old.limits), to_value(&new.limits));
+    if let (Value::Object(old_limits), Value::Object(new_limits)) = (&old_limits, &new_limits) {
+        for (field, value) in new_limits {
+            let previous = old_limits.get(field).unwrap_or(&Value::Null);
+            if previous != value {
+                changes.push(format!("limits.{}: {} -> {}", field, previous, value));
+            }
+        }
+    }
+    
+    for (section, old_value, new_value) in [
+        ("global", to_value(&old.global), to_value(&new.global)),
+        ("dns", to_value(&old.dns), to_value(&new.dns)),
+        ("transforms", to_value(&old.transforms), to_value(&new.transforms)),
+    ] {
+        if old_value != new_value {
+            changes.push(format!("{} changed", section));
+        }
+    }
+    
+    changes
+}
+
+fn to_value<T: serde::Serialize>(value: &T) -> Value {
+    serde_json::to_value(value).unwrap_or(Value::Null)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::sync::Arc;
+    
+    use bytes::BytesMut;
+    use engine::{FlowKey, Pipeline, Stats};
+    use engine::config::Protocol;
+    use tempfile::tempdir;
+    
+    const BASE: &str = r#"
+[[rules]]
+name = "https"
+transforms = ["padding"]
+
+[rules.match_criteria]
+dst_ports = [443]
+"#;
+
+    const WITH_HTTP: &str = r#"
+[[rules]]
+name = "http"
+transforms = ["padding"]
+
+[rules.match_criteria]
+dst_ports = [80]
+"#;
+
+    fn http_key() -> FlowKey {
+        FlowKey::new(
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            IpAddr::V4(Ipv4Addr::new(93, 184, 216, 34)),
+            40000,
+            80,
+            Protocol::Tcp,
+        )
+    }
+    
+    async fn wait_for(mut condition: impl FnMut() -> bool) -> bool {
+        for _ in 0..200 {
+            if condition() {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        false
+    }
+    
+    #[test]
+    fn test_describe_changes() {
+        let old = Config::from_toml(BASE).unwrap();
+        let mut new = Config::from_toml(&format!("{}{}", BASE, WITH_HTTP)).unwrap();
+        new.limits.max_flows = 50_000;
+        new.rules[0].priority = 5;
+        
+        let changes = describe_changes(&old, &new);
+        assert!(changes.contains(&"rule added: http".to_string()), "{:?}", changes);
+        assert!(changes.contains(&"rule changed: https".to_string()), "{:?}", changes);
+        assert!(changes.iter().any(|c| c.starts_with("limits.max_flows:") && c.ends_with("-> 50000")), "{:?}", changes);
+        
+        let changes = describe_changes(&new, &old);
+        assert!(changes.contains(&"rule removed: http".to_string()), "{:?}", changes);
+        assert!(describe_changes(&old, &old).is_empty());
+    }
+    
+    #[tokio::test]
+    async fn test_watch_config_reloads_pipeline() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, BASE).unwrap();
+        
+        let config = Config::load_from_file(&path).unwrap();
+        let pipeline = Arc::new(Pipeline::new(config.clone(), Arc::new(Stats::new())).unwrap());
+        let target = pipeline.clone();
+        let watcher = watch_config(path.clone(), Duration::from_millis(10), config, move |config| {
+            target.reload_config(config)?;
+            Ok(())
+        });
+        
+        let matched = |pipeline: &Pipeline| {
+            pipeline.process(http_key(), BytesMut::from(&b"GET /"[..])).unwrap().matched_rule
+        };
+        assert_eq!(matched(&pipeline), None);
+        
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        std::fs::write(&path, "[[rules]]\nname = \"broken\"\n").unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(matched(&pipeline), None);
+        assert_eq!(pipeline.config().rules.len(), 1);
+        
+        std::fs::write(&path, format!("{}{}", BASE, WITH_HTTP)).unwrap();
+        assert!(wait_for(|| matched(&pipeline).as_deref() == Some("http")).await);
+        
+        watcher.abort();
+    }
+}
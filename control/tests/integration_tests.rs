@@ -0,0 +1,43 @@
+use std::time::Duration;
+
+use control::{ControlClient, ControlServer, ServerConfig};
+use engine::Config;
+use tempfile::tempdir;
+
+#[tokio::test]
+async fn test_shutdown_command() {
+    let temp_dir = tempdir().unwrap();
+    let socket_path = temp_dir.path().join("test.sock");
+    
+    let server_config = ServerConfig {
+        socket_path: socket_path.clone(),
+        ..Default::default()
+    };
+    let mut config = Config::default();
+    config.backend.listen = vec!["127.0.0.1:0".parse().unwrap()];
+    let mut server = ControlServer::new(server_config, config);
+    server.start().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    
+    let mut client = ControlClient::new(&socket_path);
+    client.start(None).await.unwrap();
+    let backend_addr = client.status().await.unwrap().listen_addrs[0].clone();
+    
+    let shutdown = server.shutdown_signal();
+    client.shutdown(Some(u64::MAX)).await.unwrap();
+    let grace = tokio::time::timeout(Duration::from_secs(5), shutdown).await.expect("server did not shut down");
+    assert_eq!(grace, backend::MAX_DRAIN_GRACE);
+    
+    assert!(server.is_finished());
+    assert!(!server.is_running());
+    assert!(!socket_path.exists());
+    assert!(ControlClient::new(&socket_path).health().await.is_err());
+    tokio::time::timeout(Duration::from_secs(3), async {
+        while tokio::net::TcpStream::connect(&backend_addr).await.is_ok() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }).await.expect("backend still accepting connections");
+    
+    // Already resolved for anyone who asks late.
+    tokio::time::timeout(Duration::from_secs(1), server.shutdown_signal()).await.unwrap();
+}
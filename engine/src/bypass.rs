@@ -1,18 +1,52 @@
 use bytes::{Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 
-use crate::tls::{parse_client_hello, is_client_hello, is_http_request, find_http_host};
+use crate::error::{EngineError, Result};
+use crate::quic::parse_quic_initial;
+use crate::tls::{TLS_ALERT, TLS_APPLICATION_DATA, TLS_CHANGE_CIPHER_SPEC, TLS_HANDSHAKE};
+use crate::tls::{build_client_hello_padded, parse_client_hello, is_client_hello, is_http_request, find_http_host, find_http_host_header, fragment_at_offsets, split_into_records, ClientHelloInfo};
 
-#[derive(Debug, Clone)]
+/// Where the TLS ClientHello is cut before it is sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SplitStrategy {
+    /// Cut at a fixed byte offset from the start of the record.
+    FixedOffset(usize),
+    /// Cut right before the first byte of the SNI hostname.
+    SniStart,
+    /// Cut in the middle of the SNI hostname.
+    SniMiddle,
+    /// Cut before the SNI hostname and again in its middle (three fragments).
+    Auto,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct BypassConfig {
     pub fragment_sni: bool,
     
+    /// Fixed split offset for the ClientHello; `0` means auto (see [`SplitStrategy::Auto`]).
+    /// Ignored when `split_strategy` or `split_positions` is set.
     pub tls_split_pos: usize,
     
+    pub split_strategy: Option<SplitStrategy>,
+    
+    /// Absolute ClientHello offsets to cut at; takes precedence over `split_strategy`.
+    pub split_positions: Vec<usize>,
+    
     pub fragment_http_host: bool,
     
     pub http_split_pos: usize,
     
+    pub split_host_value: bool,
+    
+    pub mangle_host_case: bool,
+    
+    pub host_extra_space: bool,
+    
     pub send_fake_packets: bool,
     
     pub fake_packet_ttl: u8,
@@ -21,86 +55,187 @@ pub struct BypassConfig {
     
     pub use_tcp_segmentation: bool,
     
+    /// Deliver the head fragment after the tail (`send_order` on the result).
+    pub send_out_of_order: bool,
+    
+    /// Re-frame the ClientHello as several TLS records; `record_size: 0` cuts at the split points.
+    pub record_split: bool,
+    
+    pub record_size: usize,
+    
+    pub block_quic: bool,
+    
+    pub host_overrides: Vec<HostOverride>,
+    
+    pub exclude_hosts: Vec<String>,
+    
     pub min_segment_size: usize,
     
     pub max_segment_size: usize,
+    
+    /// Clamp `TCP_MAXSEG` on outbound sockets so the kernel splits the ClientHello itself.
+    pub clamp_mss: Option<u16>,
 }
 
 impl Default for BypassConfig {
     fn default() -> Self {
         Self {
             fragment_sni: true,
-            tls_split_pos: 3,  
+            tls_split_pos: 3,
+            split_strategy: None,
+            split_positions: Vec::new(),
             fragment_http_host: true,
             http_split_pos: 2, 
+            split_host_value: false,
+            mangle_host_case: false,
+            host_extra_space: false,
             send_fake_packets: false,
             fake_packet_ttl: 1,
             fragment_delay_us: 0,
             use_tcp_segmentation: true,
+            send_out_of_order: false,
+            record_split: false,
+            record_size: 0,
+            block_quic: false,
+            host_overrides: Vec::new(),
+            exclude_hosts: Vec::new(),
             min_segment_size: 1,
             max_segment_size: 40,
+            clamp_mss: None,
         }
     }
 }
 
 impl BypassConfig {
+    pub fn tls_split_strategy(&self) -> SplitStrategy {
+        match self.split_strategy {
+            Some(strategy) => strategy,
+            None if self.tls_split_pos == 0 => SplitStrategy::Auto,
+            None => SplitStrategy::FixedOffset(self.tls_split_pos),
+        }
+    }
+    
+    pub fn validate(&self) -> Result<()> {
+        if self.max_segment_size < self.min_segment_size {
+            return Err(EngineError::validation("max_segment_size", "must be >= min_segment_size"));
+        }
+        
+        if self.fake_packet_ttl == 0 {
+            return Err(EngineError::validation("fake_packet_ttl", "must be >= 1"));
+        }
+        
+        for (i, host_override) in self.host_overrides.iter().enumerate() {
+            host_override.config.validate().map_err(|e| e.in_section(&format!("host_overrides[{}]", i)))?;
+        }
+        
+        Ok(())
+    }
+    
     pub fn turk_telekom() -> Self {
         Self {
             fragment_sni: true,
-            tls_split_pos: 2,
+            tls_split_pos: 0,
+            split_strategy: Some(SplitStrategy::Auto),
+            split_positions: Vec::new(),
             fragment_http_host: true,
             http_split_pos: 2,
+            split_host_value: false,
+            mangle_host_case: false,
+            host_extra_space: false,
             send_fake_packets: false,
             fake_packet_ttl: 1,
             fragment_delay_us: 0,
             use_tcp_segmentation: true,
+            send_out_of_order: false,
+            record_split: false,
+            record_size: 0,
+            block_quic: false,
+            host_overrides: Vec::new(),
+            exclude_hosts: Vec::new(),
             min_segment_size: 1,
             max_segment_size: 20,
+            clamp_mss: None,
         }
     }
     
     pub fn vodafone_tr() -> Self {
         Self {
             fragment_sni: true,
-            tls_split_pos: 3,
+            tls_split_pos: 0,
+            split_strategy: Some(SplitStrategy::Auto),
+            split_positions: Vec::new(),
             fragment_http_host: true,
             http_split_pos: 3,
+            split_host_value: false,
+            mangle_host_case: false,
+            host_extra_space: false,
             send_fake_packets: false,
             fake_packet_ttl: 1,
             fragment_delay_us: 100,
             use_tcp_segmentation: true,
+            send_out_of_order: false,
+            record_split: false,
+            record_size: 0,
+            block_quic: false,
+            host_overrides: Vec::new(),
+            exclude_hosts: Vec::new(),
             min_segment_size: 1,
             max_segment_size: 30,
+            clamp_mss: None,
         }
     }
     
     pub fn superonline() -> Self {
         Self {
             fragment_sni: true,
-            tls_split_pos: 1,
+            tls_split_pos: 0,
+            split_strategy: Some(SplitStrategy::Auto),
+            split_positions: Vec::new(),
             fragment_http_host: true,
             http_split_pos: 1,
+            split_host_value: false,
+            mangle_host_case: false,
+            host_extra_space: false,
             send_fake_packets: false,
             fake_packet_ttl: 1,
             fragment_delay_us: 0,
             use_tcp_segmentation: true,
+            send_out_of_order: false,
+            record_split: false,
+            record_size: 0,
+            block_quic: false,
+            host_overrides: Vec::new(),
+            exclude_hosts: Vec::new(),
             min_segment_size: 1,
             max_segment_size: 15,
+            clamp_mss: None,
         }
     }
     
     pub fn aggressive() -> Self {
         Self {
             fragment_sni: true,
-            tls_split_pos: 0,  
+            tls_split_pos: 0,
+            split_strategy: Some(SplitStrategy::Auto),
+            split_positions: Vec::new(),
             fragment_http_host: true,
             http_split_pos: 1,
+            split_host_value: false,
+            mangle_host_case: false,
+            host_extra_space: false,
             send_fake_packets: false,
             fake_packet_ttl: 3,
             fragment_delay_us: 10000,
             use_tcp_segmentation: true,
+            send_out_of_order: false,
+            record_split: false,
+            record_size: 0,
+            block_quic: false,
+            host_overrides: Vec::new(),
+            exclude_hosts: Vec::new(),
             min_segment_size: 1,
             max_segment_size: 5,
+            clamp_mss: Some(88),
         }
     }
 }
@@ -113,6 +248,11 @@ pub struct BypassResult {
     pub modified: bool,
     pub protocol: DetectedProtocol,    
     pub hostname: Option<String>,
+    pub alpn: Vec<String>,
+    pub send_order: Vec<usize>,
+    pub quic_version: Option<u32>,
+    pub drop: bool,
+    pub excluded: bool,
 }
 
 impl Default for BypassResult {
@@ -124,27 +264,175 @@ impl Default for BypassResult {
             modified: false,
             protocol: DetectedProtocol::Unknown,
             hostname: None,
+            alpn: Vec::new(),
+            send_order: Vec::new(),
+            quic_version: None,
+            drop: false,
+            excluded: false,
         }
     }
 }
 
+impl BypassResult {
+    pub fn is_deferred(&self, index: usize) -> bool {
+        let rank = |i: usize| self.send_order.iter().position(|&o| o == i);
+        match rank(index) {
+            Some(r) => (index + 1..self.fragments.len()).any(|j| rank(j).is_some_and(|rj| rj < r)),
+            None => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IncomingVerdict {
+    Allowed,
+    TlsAlert(u8),
+    Reset,
+    BlockPage,
+    Unknown,
+}
+
+impl IncomingVerdict {
+    pub fn is_blocked(&self) -> bool {
+        matches!(self, IncomingVerdict::TlsAlert(_) | IncomingVerdict::Reset | IncomingVerdict::BlockPage)
+    }
+}
+
+// SNI of the decoy ClientHello sent ahead of the real one.
+pub const FAKE_SNI: &str = "www.w3.org";
+
+pub const BLOCK_PAGE_SIGNATURES: &[&str] = &[
+    "bilgi.btk.gov.tr",
+    "internet.btk.gov.tr",
+    "guvenlinet.org.tr",
+    "195.175.254.2",
+    "erişim engellenmiştir",
+];
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DetectedProtocol {
     TlsClientHello,
     HttpRequest,
+    Quic,
     Unknown,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HostOverride {
+    pub pattern: String,
+    pub config: BypassConfig,
+}
+
+impl HostOverride {
+    pub fn new(pattern: impl Into<String>, config: BypassConfig) -> Self {
+        Self {
+            pattern: pattern.into(),
+            config,
+        }
+    }
+    
+    pub fn matches(&self, host: &str) -> bool {
+        host_matches(&self.pattern, host)
+    }
+}
+
+pub fn host_matches(pattern: &str, host: &str) -> bool {
+    let host = host.trim_end_matches('.');
+    let domain = pattern.strip_prefix("*.").or_else(|| pattern.strip_prefix('.'));
+    match domain {
+        Some(domain) => {
+            host.eq_ignore_ascii_case(domain)
+                || (host.len() > domain.len()
+                    && host.as_bytes()[host.len() - domain.len() - 1] == b'.'
+                    && host[host.len() - domain.len()..].eq_ignore_ascii_case(domain))
+        }
+        None => host.eq_ignore_ascii_case(pattern),
+    }
+}
+
+#[derive(Debug)]
 pub struct BypassEngine {
     config: BypassConfig,
+    overrides: Vec<(HostOverride, BypassEngine)>,
+    paused: Option<Arc<AtomicBool>>,
 }
 
 impl BypassEngine {
     pub fn new(config: BypassConfig) -> Self {
-        Self { config }
+        let overrides = config.host_overrides.iter()
+            .map(|o| {
+                let mut override_config = o.config.clone();
+                override_config.host_overrides.clear();
+                (o.clone(), BypassEngine::new(override_config))
+            })
+            .collect();
+        Self { config, overrides, paused: None }
     }
-
+    
+    pub fn with_pause_flag(mut self, paused: Arc<AtomicBool>) -> Self {
+        self.paused = Some(paused);
+        self
+    }
+    
+    pub fn is_paused(&self) -> bool {
+        self.paused.as_ref().is_some_and(|paused| paused.load(Ordering::Relaxed))
+    }
+    
+    pub fn inspect_incoming(&self, data: &[u8]) -> IncomingVerdict {
+        if data.is_empty() {
+            return IncomingVerdict::Reset;
+        }
+        
+        match data[0] {
+            TLS_ALERT => return IncomingVerdict::TlsAlert(data.get(6).copied().unwrap_or(0)),
+            TLS_HANDSHAKE | TLS_CHANGE_CIPHER_SPEC | TLS_APPLICATION_DATA => return IncomingVerdict::Allowed,
+            _ => {}
+        }
+        
+        if !data.starts_with(b"HTTP/") {
+            return IncomingVerdict::Unknown;
+        }
+        
+        let text = String::from_utf8_lossy(data).to_lowercase();
+        let status = text.split_whitespace().nth(1).and_then(|s| s.parse::<u16>().ok());
+        let header_end = text.find("\r\n\r\n").or_else(|| text.find("\n\n")).unwrap_or(text.len());
+        let (headers, body) = text.split_at(header_end);
+        
+        let blocked = match status {
+            Some(301 | 302 | 303 | 307 | 308) => headers.lines()
+                .filter_map(|line| line.strip_prefix("location:"))
+                .any(contains_block_signature),
+            Some(200) => contains_block_signature(body),
+            _ => false,
+        };
+        
+        if blocked {
+            IncomingVerdict::BlockPage
+        } else {
+            IncomingVerdict::Allowed
+        }
+    }
+    
     pub fn process_outgoing(&self, data: &[u8]) -> BypassResult {
+        if self.is_paused() {
+            return BypassResult {
+                fragments: vec![Bytes::copy_from_slice(data)],
+                ..Default::default()
+            };
+        }
+        
+        if !self.overrides.is_empty() {
+            if let Some(host) = detect_hostname(data) {
+                if let Some((_, engine)) = self.overrides.iter().find(|(o, _)| o.matches(&host)) {
+                    return engine.process(data);
+                }
+            }
+        }
+        
+        self.process(data)
+    }
+
+    fn process(&self, data: &[u8]) -> BypassResult {
         let mut result = BypassResult::default();
         
         
@@ -154,11 +442,24 @@ impl BypassEngine {
         } else if is_http_request(data) {
             result.protocol = DetectedProtocol::HttpRequest;
             self.process_http_request(data, &mut result);
+        } else if let Some(info) = parse_quic_initial(data) {
+            result.protocol = DetectedProtocol::Quic;
+            result.quic_version = Some(info.version);
+            if self.config.block_quic {
+                result.drop = true;
+                result.modified = true;
+            } else {
+                result.fragments.push(Bytes::copy_from_slice(data));
+            }
         } else {
             
             result.fragments.push(Bytes::copy_from_slice(data));
         }
         
+        if self.config.send_out_of_order && result.fragments.len() > 1 {
+            result.send_order = (1..result.fragments.len()).chain(std::iter::once(0)).collect();
+        }
+        
         result
     }
     
@@ -171,45 +472,27 @@ impl BypassEngine {
         
         if let Some(info) = parse_client_hello(data) {
             result.hostname = info.sni_hostname.clone();
+            result.alpn = info.alpn.clone();
             
+            if result.hostname.as_deref().is_some_and(|h| self.is_excluded(h)) {
+                result.excluded = true;
+                result.fragments.push(Bytes::copy_from_slice(data));
+                return;
+            }
             
             
-            let split_pos = if self.config.tls_split_pos > 0 {
-                
-                self.config.tls_split_pos.min(data.len() - 1)
-            } else if let (Some(sni_off), Some(sni_len)) = (info.sni_offset, info.sni_length) {
-                
-                
-                if sni_len > 2 {
-                    sni_off + (sni_len / 2)
-                } else {
-                    sni_off
-                }.min(data.len() - 1)
+            
+            let split_points = self.tls_split_points(&info);
+            let record_split = if self.config.record_split {
+                split_into_records(data, &self.record_sizes(data.len(), &split_points))
             } else {
-                
-                5.min(data.len() - 1)
+                None
             };
+            let fragments = record_split
+                .unwrap_or_else(|| fragment_at_offsets(BytesMut::from(data), &split_points));
             
-            
-            if split_pos > 0 && split_pos < data.len() {
-                
-                let segment_size = self.config.max_segment_size.max(1);
-                
-                if segment_size < split_pos {
-                    
-                    let mut pos = 0;
-                    while pos < split_pos {
-                        let end = (pos + segment_size).min(split_pos);
-                        result.fragments.push(Bytes::copy_from_slice(&data[pos..end]));
-                        pos = end;
-                    }
-                    
-                    result.fragments.push(Bytes::copy_from_slice(&data[split_pos..]));
-                } else {
-                    
-                    result.fragments.push(Bytes::copy_from_slice(&data[..split_pos]));
-                    result.fragments.push(Bytes::copy_from_slice(&data[split_pos..]));
-                }
+            if fragments.len() > 1 {
+                result.fragments.extend(fragments.into_iter().map(BytesMut::freeze));
                 result.modified = true;
                 
                 if self.config.fragment_delay_us > 0 {
@@ -229,33 +512,89 @@ impl BypassEngine {
         }
     }
     
+    fn is_excluded(&self, host: &str) -> bool {
+        self.config.exclude_hosts.iter().any(|pattern| host_matches(pattern, host))
+    }
+    
+    fn tls_split_points(&self, info: &ClientHelloInfo) -> Vec<usize> {
+        if !self.config.split_positions.is_empty() {
+            return self.config.split_positions.clone();
+        }
+        
+        let sni = match (info.sni_offset, info.sni_length) {
+            (Some(offset), Some(length)) => Some((offset, length)),
+            _ => None,
+        };
+        
+        match (self.config.tls_split_strategy(), sni) {
+            (SplitStrategy::FixedOffset(offset), _) => vec![offset],
+            (SplitStrategy::SniStart, Some((offset, _))) => vec![offset],
+            (SplitStrategy::SniMiddle, Some((offset, length))) => vec![offset + length / 2],
+            (SplitStrategy::Auto, Some((offset, length))) => vec![offset, offset + length / 2],
+            (_, None) => vec![5],
+        }
+    }
+    
+    fn record_sizes(&self, len: usize, split_points: &[usize]) -> Vec<usize> {
+        let body_len = len.saturating_sub(5);
+        
+        if self.config.record_size > 0 {
+            return vec![self.config.record_size; body_len.div_ceil(self.config.record_size)];
+        }
+        
+        let mut points: Vec<usize> = split_points.iter()
+            .filter(|&&p| p > 5 && p < len)
+            .map(|&p| p - 5)
+            .collect();
+        points.sort();
+        points.dedup();
+        
+        let mut prev = 0;
+        points.into_iter()
+            .map(|p| {
+                let size = p - prev;
+                prev = p;
+                size
+            })
+            .collect()
+    }
+    
     fn process_http_request(&self, data: &[u8], result: &mut BypassResult) {
+        if let Some(hostname) = extract_http_hostname(data).filter(|h| self.is_excluded(h)) {
+            result.hostname = Some(hostname);
+            result.excluded = true;
+            result.fragments.push(Bytes::copy_from_slice(data));
+            return;
+        }
+        
+        let mangled = self.mangle_host_header(data);
+        if mangled.is_some() {
+            result.modified = true;
+        }
+        let data = mangled.as_deref().unwrap_or(data);
+        
         if !self.config.fragment_http_host {
+            result.hostname = extract_http_hostname(data);
             result.fragments.push(Bytes::copy_from_slice(data));
             return;
         }
         
         
-        if let Some((host_offset, host_len)) = find_http_host(data) {
-            result.hostname = std::str::from_utf8(&data[host_offset..host_offset + host_len])
-                .ok()
-                .map(|s| s.to_string());
+        if let Some(hostname) = extract_http_hostname(data) {
+            result.hostname = Some(hostname);
             
+            let split_points = find_http_host_header(data)
+                .zip(find_http_host(data))
+                .map(|(name, value)| self.http_split_points(name, value))
+                .unwrap_or_default();
+            let fragments = fragment_at_offsets(BytesMut::from(data), &split_points);
             
-            if let Some(host_header_pos) = find_host_header_start(data) {
-                
-                let split_pos = (host_header_pos + self.config.http_split_pos).min(data.len() - 1);
+            if fragments.len() > 1 {
+                result.fragments.extend(fragments.into_iter().map(BytesMut::freeze));
+                result.modified = true;
                 
-                if split_pos > 0 && split_pos < data.len() {
-                    result.fragments.push(Bytes::copy_from_slice(&data[..split_pos]));
-                    result.fragments.push(Bytes::copy_from_slice(&data[split_pos..]));
-                    result.modified = true;
-                    
-                    if self.config.fragment_delay_us > 0 {
-                        result.inter_fragment_delay = Some(Duration::from_micros(self.config.fragment_delay_us));
-                    }
-                } else {
-                    result.fragments.push(Bytes::copy_from_slice(data));
+                if self.config.fragment_delay_us > 0 {
+                    result.inter_fragment_delay = Some(Duration::from_micros(self.config.fragment_delay_us));
                 }
             } else {
                 result.fragments.push(Bytes::copy_from_slice(data));
@@ -264,34 +603,104 @@ impl BypassEngine {
             result.fragments.push(Bytes::copy_from_slice(data));
         }
     }
-
-    fn generate_fake_tls_packet(&self, original: &[u8]) -> Bytes {
+    
+    fn http_split_points(&self, host_header: usize, (value, value_len): (usize, usize)) -> Vec<usize> {
+        let host_end = value + value_len;
+        let mut points = vec![(host_header + self.config.http_split_pos).min(host_end.saturating_sub(1))];
+        if self.config.split_host_value && value_len > 1 {
+            points.push(value + value_len / 2);
+        }
+        points.sort();
+        points.dedup();
         
-        let mut fake = BytesMut::with_capacity(original.len());
+        let max = self.config.max_segment_size;
+        if max == 0 {
+            return points;
+        }
+        let min = self.config.min_segment_size.max(1);
         
+        let mut boundaries = vec![0];
+        boundaries.extend(points.iter().copied());
+        boundaries.push(host_end);
+        for pair in boundaries.windows(2) {
+            let (start, end) = (pair[0], pair[1]);
+            let mut cut = start + max;
+            while cut < end && end - cut >= min {
+                points.push(cut);
+                cut += max;
+            }
+        }
         
-        fake.extend_from_slice(original);
+        points.sort();
+        points
+    }
+    
+    fn mangle_host_header(&self, data: &[u8]) -> Option<Vec<u8>> {
+        if !self.config.mangle_host_case && !self.config.host_extra_space {
+            return None;
+        }
         
+        let name_start = find_http_host_header(data)?;
+        let colon = name_start + 4;
+        if data.get(colon) != Some(&b':') {
+            return None;
+        }
         
-        if let Some(info) = parse_client_hello(original) {
-            if let (Some(offset), Some(len)) = (info.sni_offset, info.sni_length) {
-                if offset + len <= fake.len() {
-                    
-                    for i in 0..len {
-                        fake[offset + i] = b'x';
-                    }
-                }
-            }
+        let mut mangled = Vec::with_capacity(data.len() + 1);
+        mangled.extend_from_slice(&data[..name_start]);
+        
+        if self.config.mangle_host_case {
+            mangled.extend_from_slice(&mixed_case_host(rand::random()));
+        } else {
+            mangled.extend_from_slice(&data[name_start..colon]);
         }
         
-        fake.freeze()
+        mangled.push(b':');
+        if self.config.host_extra_space {
+            mangled.push(b' ');
+        }
+        mangled.extend_from_slice(&data[colon + 1..]);
+        
+        Some(mangled)
+    }
+    
+    fn generate_fake_tls_packet(&self, original: &[u8]) -> Bytes {
+        // A decoy at least as long as the original, so any prefix of it can
+        // stand in for the same prefix of the real ClientHello.
+        Bytes::from(build_client_hello_padded(FAKE_SNI, &rand::random(), original.len()))
     }
 }
 
-fn find_host_header_start(data: &[u8]) -> Option<usize> {
-    let text = std::str::from_utf8(data).ok()?;
-    let lower = text.to_lowercase();
-    lower.find("\nhost:").map(|p| p + 1) 
+fn contains_block_signature(text: &str) -> bool {
+    BLOCK_PAGE_SIGNATURES.iter().any(|sig| text.contains(sig))
+}
+
+fn detect_hostname(data: &[u8]) -> Option<String> {
+    if is_client_hello(data) {
+        parse_client_hello(data)?.sni_hostname
+    } else if is_http_request(data) {
+        extract_http_hostname(data)
+    } else {
+        None
+    }
+}
+
+fn extract_http_hostname(data: &[u8]) -> Option<String> {
+    let (offset, len) = find_http_host(data)?;
+    std::str::from_utf8(&data[offset..offset + len])
+        .ok()
+        .map(|s| s.to_string())
+}
+
+fn mixed_case_host(seed: u64) -> [u8; 4] {
+    let mask = 2 + (seed % 13) as u8;
+    let mut name = *b"host";
+    for (i, c) in name.iter_mut().enumerate() {
+        if mask & (1 << i) != 0 {
+            c.make_ascii_uppercase();
+        }
+    }
+    name
 }
 
 #[cfg(test)]
@@ -300,8 +709,8 @@ mod tests {
     
     fn sample_tls_client_hello() -> Vec<u8> {
         vec![
-            0x16, 0x03, 0x01, 0x00, 0x5a,
-            0x01, 0x00, 0x00, 0x56,
+            0x16, 0x03, 0x01, 0x00, 0x4a,
+            0x01, 0x00, 0x00, 0x46,
             0x03, 0x03,
             0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07,
             0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f,
@@ -310,7 +719,7 @@ mod tests {
             0x00,
             0x00, 0x02, 0x13, 0x01,
             0x01, 0x00,
-            0x00, 0x17,
+            0x00, 0x1b,
             0x00, 0x00, 0x00, 0x10,
             0x00, 0x0e, 0x00, 0x00, 0x0b,
             0x64, 0x69, 0x73, 0x63, 0x6f, 0x72, 0x64, 0x2e, 0x63, 0x6f, 0x6d,
@@ -336,6 +745,11 @@ mod tests {
             reassembled.extend_from_slice(frag);
         }
         assert_eq!(reassembled, data);
+        
+        
+        for pair in result.fragments.windows(2) {
+            assert_eq!(pair[1].as_ptr(), pair[0].as_ptr().wrapping_add(pair[0].len()));
+        }
     }
     
     #[test]
@@ -358,6 +772,70 @@ mod tests {
         assert_eq!(&reassembled[..], &data[..]);
     }
     
+    #[test]
+    fn test_bypass_http_many_fragments() {
+        let engine = BypassEngine::new(BypassConfig {
+            http_split_pos: 2,
+            split_host_value: true,
+            max_segment_size: 8,
+            ..Default::default()
+        });
+        let data = b"GET /some/long/path HTTP/1.1\r\nUser-Agent: test\r\nHost: discord.com\r\nAccept: */*\r\n\r\n";
+        
+        let result = engine.process_outgoing(data);
+        assert!(result.modified);
+        assert_eq!(result.hostname.as_deref(), Some("discord.com"));
+        assert!(result.fragments.len() >= 3, "{}", result.fragments.len());
+        assert_eq!(result.fragments.concat(), data);
+        
+        let (value, len) = find_http_host(data).unwrap();
+        let host_end = value + len;
+        let mut offset = 0;
+        let mut cuts = Vec::new();
+        for fragment in &result.fragments {
+            if offset + fragment.len() <= host_end {
+                assert!(fragment.len() <= 8, "{:?}", fragment);
+            }
+            offset += fragment.len();
+            cuts.push(offset);
+        }
+        let name = find_http_host_header(data).unwrap();
+        assert!(cuts.contains(&(name + 2)));
+        
+        // One cut lands inside the hostname itself.
+        assert!(cuts.iter().any(|&cut| cut > value && cut < host_end));
+        let straddling = BypassEngine::new(BypassConfig {
+            split_host_value: true,
+            max_segment_size: 0,
+            ..Default::default()
+        }).process_outgoing(data);
+        assert_eq!(straddling.fragments.len(), 3);
+        assert_eq!(&straddling.fragments[1][..], b"st: disco");
+        assert!(straddling.fragments[2].starts_with(b"rd.com\r\n"));
+    }
+    
+    #[test]
+    fn test_http_min_segment_size() {
+        let engine = BypassEngine::new(BypassConfig {
+            http_split_pos: 0,
+            min_segment_size: 5,
+            max_segment_size: 10,
+            ..Default::default()
+        });
+        let data = b"GET / HTTP/1.1\r\nHost: a.example\r\n\r\n";
+        
+        let result = engine.process_outgoing(data);
+        assert_eq!(result.fragments.concat(), data);
+        let (value, len) = find_http_host(data).unwrap();
+        let mut offset = 0;
+        for fragment in &result.fragments {
+            offset += fragment.len();
+            if offset <= value + len {
+                assert!(fragment.len() >= 5 && fragment.len() <= 10, "{:?}", result.fragments);
+            }
+        }
+    }
+    
     #[test]
     fn test_isp_presets() {
         let data = sample_tls_client_hello();
@@ -382,6 +860,427 @@ mod tests {
             assert_eq!(reassembled, data);
         }
     }
+        
+    #[test]
+    fn test_clamp_mss_presets() {
+        assert_eq!(BypassConfig::aggressive().clamp_mss, Some(88));
+        for config in [
+            BypassConfig::default(),
+            BypassConfig::turk_telekom(),
+            BypassConfig::vodafone_tr(),
+            BypassConfig::superonline(),
+        ] {
+            assert_eq!(config.clamp_mss, None);
+        }
+    }
+    
+    #[test]
+    fn test_config_validation_and_serde() {
+        for config in [
+            BypassConfig::default(),
+            BypassConfig::turk_telekom(),
+            BypassConfig::vodafone_tr(),
+            BypassConfig::superonline(),
+            BypassConfig::aggressive(),
+        ] {
+            config.validate().unwrap();
+        }
+        
+        let config = BypassConfig {
+            split_strategy: Some(SplitStrategy::FixedOffset(7)),
+            host_overrides: vec![HostOverride::new("*.discord.com", BypassConfig::superonline())],
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&config).unwrap();
+        let parsed: BypassConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.split_strategy, Some(SplitStrategy::FixedOffset(7)));
+        assert_eq!(parsed.host_overrides[0].pattern, "*.discord.com");
+        assert_eq!(parsed.host_overrides[0].config.max_segment_size, 15);
+        
+        let partial: BypassConfig = toml::from_str("split_strategy = \"sni_middle\"\nmax_segment_size = 25").unwrap();
+        assert_eq!(partial.split_strategy, Some(SplitStrategy::SniMiddle));
+        assert_eq!(partial.max_segment_size, 25);
+        assert_eq!(partial.http_split_pos, BypassConfig::default().http_split_pos);
+        
+        let inverted = BypassConfig {
+            min_segment_size: 50,
+            max_segment_size: 10,
+            ..Default::default()
+        };
+        assert!(matches!(
+            inverted.validate(),
+            Err(EngineError::ConfigValidation { ref field, .. }) if field == "max_segment_size"
+        ));
+        
+        let nested = BypassConfig {
+            host_overrides: vec![HostOverride::new("example.com", BypassConfig { fake_packet_ttl: 0, ..Default::default() })],
+            ..Default::default()
+        };
+        assert!(matches!(
+            nested.validate(),
+            Err(EngineError::ConfigValidation { ref field, .. }) if field == "host_overrides[0].fake_packet_ttl"
+        ));
+    }
+    
+    #[test]
+    fn test_split_strategies_reassemble() {
+        let data = sample_tls_client_hello();
+        let info = parse_client_hello(&data).unwrap();
+        let sni_start = info.sni_offset.unwrap();
+        let sni_middle = sni_start + info.sni_length.unwrap() / 2;
+        
+        for strategy in [
+            SplitStrategy::FixedOffset(3),
+            SplitStrategy::SniStart,
+            SplitStrategy::SniMiddle,
+            SplitStrategy::Auto,
+        ] {
+            let engine = BypassEngine::new(BypassConfig {
+                split_strategy: Some(strategy),
+                ..Default::default()
+            });
+            let result = engine.process_outgoing(&data);
+            
+            assert!(result.modified);
+            
+            let boundaries: Vec<usize> = result.fragments[..result.fragments.len() - 1]
+                .iter()
+                .scan(0, |pos, frag| {
+                    *pos += frag.len();
+                    Some(*pos)
+                })
+                .collect();
+            let expected = match strategy {
+                SplitStrategy::FixedOffset(offset) => vec![offset],
+                SplitStrategy::SniStart => vec![sni_start],
+                SplitStrategy::SniMiddle => vec![sni_middle],
+                SplitStrategy::Auto => vec![sni_start, sni_middle],
+            };
+            assert_eq!(boundaries, expected);
+            
+            let mut reassembled = Vec::new();
+            for frag in &result.fragments {
+                reassembled.extend_from_slice(frag);
+            }
+            assert_eq!(reassembled, data);
+        }
+    }
+    
+    #[test]
+    fn test_split_positions() {
+        let data = sample_tls_client_hello();
+        let engine = BypassEngine::new(BypassConfig {
+            split_positions: vec![40, 10, 10, 0, 25, data.len() + 5],
+            ..Default::default()
+        });
+        let result = engine.process_outgoing(&data);
+        
+        let lengths: Vec<usize> = result.fragments.iter().map(|f| f.len()).collect();
+        assert_eq!(lengths, vec![10, 15, 15, data.len() - 40]);
+        
+        let mut reassembled = Vec::new();
+        for frag in &result.fragments {
+            reassembled.extend_from_slice(frag);
+        }
+        assert_eq!(reassembled, data);
+    }
+    
+    #[test]
+    fn test_presets_cut_around_sni() {
+        let data = sample_tls_client_hello();
+        let info = parse_client_hello(&data).unwrap();
+        let sni_start = info.sni_offset.unwrap();
+        
+        for config in [
+            BypassConfig::turk_telekom(),
+            BypassConfig::vodafone_tr(),
+            BypassConfig::superonline(),
+            BypassConfig::aggressive(),
+        ] {
+            let result = BypassEngine::new(config).process_outgoing(&data);
+            assert_eq!(result.fragments.len(), 3);
+            assert_eq!(result.fragments[0].len(), sni_start);
+        }
+    }
+    
+    #[test]
+    fn test_split_pos_zero_is_auto() {
+        let config = BypassConfig {
+            tls_split_pos: 0,
+            ..Default::default()
+        };
+        assert_eq!(config.tls_split_strategy(), SplitStrategy::Auto);
+        
+        let config = BypassConfig {
+            tls_split_pos: 7,
+            ..Default::default()
+        };
+        assert_eq!(config.tls_split_strategy(), SplitStrategy::FixedOffset(7));
+        assert_eq!(BypassConfig::aggressive().tls_split_strategy(), SplitStrategy::Auto);
+    }
+    
+    #[test]
+    fn test_out_of_order_send_order() {
+        let data = sample_tls_client_hello();
+        let engine = BypassEngine::new(BypassConfig {
+            send_out_of_order: true,
+            split_strategy: Some(SplitStrategy::Auto),
+            ..Default::default()
+        });
+        let result = engine.process_outgoing(&data);
+        
+        assert_eq!(result.send_order, vec![1, 2, 0]);
+        assert!(result.is_deferred(0));
+        assert!(!result.is_deferred(1));
+        assert!(!result.is_deferred(2));
+        
+        let result = BypassEngine::new(BypassConfig::default()).process_outgoing(&data);
+        assert!(result.send_order.is_empty());
+        assert!(!result.is_deferred(0));
+    }
+    
+    #[test]
+    fn test_record_split() {
+        let data = sample_tls_client_hello();
+        let info = parse_client_hello(&data).unwrap();
+        let sni_middle = info.sni_offset.unwrap() + info.sni_length.unwrap() / 2;
+        
+        for (record_size, expected) in [
+            (0, vec![sni_middle - 5, data.len() - sni_middle]),
+            (30, vec![30, 30, data.len() - 65]),
+        ] {
+            let engine = BypassEngine::new(BypassConfig {
+                record_split: true,
+                record_size,
+                split_strategy: Some(SplitStrategy::SniMiddle),
+                ..Default::default()
+            });
+            let result = engine.process_outgoing(&data);
+            assert!(result.modified);
+            
+            let mut lengths = Vec::new();
+            let mut payload = Vec::new();
+            for record in &result.fragments {
+                assert_eq!(&record[..3], &data[..3]);
+                let len = u16::from_be_bytes([record[3], record[4]]) as usize;
+                assert_eq!(record.len(), 5 + len);
+                lengths.push(len);
+                payload.extend_from_slice(&record[5..]);
+            }
+            assert_eq!(lengths, expected);
+            assert_eq!(payload, &data[5..]);
+        }
+    }
+    
+    #[test]
+    fn test_http_host_mangling() {
+        let engine = BypassEngine::new(BypassConfig {
+            mangle_host_case: true,
+            host_extra_space: true,
+            ..Default::default()
+        });
+        
+        for data in [
+            &b"GET / HTTP/1.1\r\nHost: discord.com\r\nConnection: close\r\n\r\n"[..],
+            &b"GET / HTTP/1.1\nHost: discord.com\nConnection: close\n\n"[..],
+        ] {
+            let result = engine.process_outgoing(data);
+            assert!(result.modified);
+            assert_eq!(result.hostname.as_deref(), Some("discord.com"));
+            
+            let mut mangled = Vec::new();
+            for frag in &result.fragments {
+                mangled.extend_from_slice(frag);
+            }
+            assert_eq!(mangled.len(), data.len() + 1);
+            
+            let name_start = find_http_host_header(data).unwrap();
+            let name = &mangled[name_start..name_start + 4];
+            assert!(name.eq_ignore_ascii_case(b"host"));
+            assert_ne!(name, b"Host");
+            assert_eq!(&mangled[name_start + 4..name_start + 7], b":  ");
+            assert_eq!(&mangled[..name_start], &data[..name_start]);
+            assert_eq!(&mangled[name_start + 7..], &data[name_start + 6..]);
+            
+            let (offset, len) = find_http_host(&mangled).unwrap();
+            assert_eq!(&mangled[offset..offset + len], b"discord.com");
+        }
+    }
+    
+    #[test]
+    fn test_mixed_case_host() {
+        for seed in 0..32 {
+            let name = mixed_case_host(seed);
+            assert!(name.eq_ignore_ascii_case(b"host"));
+            assert!(&name != b"host" && &name != b"Host" && &name != b"HOST");
+        }
+    }
+    
+    #[test]
+    fn test_quic_initial_detection() {
+        let mut data = vec![
+            0xc0, 0x00, 0x00, 0x00, 0x01, 0x08, 0x83, 0x94,
+            0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08, 0x00, 0x00,
+            0x44, 0x9e,
+        ];
+        data.resize(1200, 0);
+        
+        let result = BypassEngine::new(BypassConfig::default()).process_outgoing(&data);
+        assert_eq!(result.protocol, DetectedProtocol::Quic);
+        assert_eq!(result.quic_version, Some(1));
+        assert!(!result.drop);
+        assert_eq!(result.fragments.len(), 1);
+        
+        let engine = BypassEngine::new(BypassConfig {
+            block_quic: true,
+            ..Default::default()
+        });
+        let result = engine.process_outgoing(&data);
+        assert_eq!(result.protocol, DetectedProtocol::Quic);
+        assert!(result.drop);
+        assert!(result.fragments.is_empty());
+    }
+    
+    #[test]
+    fn test_host_override_matching() {
+        let exact = HostOverride::new("discord.com", BypassConfig::default());
+        assert!(exact.matches("discord.com"));
+        assert!(exact.matches("Discord.COM."));
+        assert!(!exact.matches("cdn.discord.com"));
+        
+        let wildcard = HostOverride::new("*.windowsupdate.com", BypassConfig::default());
+        assert!(wildcard.matches("windowsupdate.com"));
+        assert!(wildcard.matches("fe2.update.windowsupdate.com"));
+        assert!(!wildcard.matches("notwindowsupdate.com"));
+        assert!(!wildcard.matches("windowsupdate.com.evil.net"));
+    }
+    
+    #[test]
+    fn test_host_overrides() {
+        let data = sample_tls_client_hello();
+        
+        let excluded = BypassEngine::new(BypassConfig {
+            host_overrides: vec![HostOverride::new("*.discord.com", BypassConfig {
+                fragment_sni: false,
+                ..Default::default()
+            })],
+            ..Default::default()
+        });
+        let result = excluded.process_outgoing(&data);
+        assert!(!result.modified);
+        assert_eq!(result.fragments.len(), 1);
+        assert_eq!(&result.fragments[0][..], &data[..]);
+        
+        let aggressive = BypassEngine::new(BypassConfig {
+            host_overrides: vec![
+                HostOverride::new("example.com", BypassConfig {
+                    fragment_sni: false,
+                    fragment_http_host: false,
+                    ..Default::default()
+                }),
+                HostOverride::new("discord.com", BypassConfig {
+                    split_positions: vec![1, 2, 3, 4],
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        });
+        let result = aggressive.process_outgoing(&data);
+        assert!(result.modified);
+        assert_eq!(result.fragments.len(), 5);
+        
+        let default_result = BypassEngine::new(BypassConfig::default()).process_outgoing(&data);
+        assert_eq!(default_result.fragments.len(), 2);
+        
+        let http = b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n";
+        let result = aggressive.process_outgoing(http);
+        assert_eq!(result.fragments.len(), 1);
+    }
+    
+    #[test]
+    fn test_fake_differs_from_real_head() {
+        let data = sample_tls_client_hello();
+        let strategies = [SplitStrategy::Auto, SplitStrategy::SniStart, SplitStrategy::FixedOffset(20)];
+        for strategy in strategies {
+            let engine = BypassEngine::new(BypassConfig {
+                send_fake_packets: true,
+                split_strategy: Some(strategy),
+                ..Default::default()
+            });
+            let result = engine.process_outgoing(&data);
+            let fake = result.fake_packet.expect("fake generated");
+            let head = &result.fragments[0];
+            assert!(fake.len() >= data.len(), "{:?}", strategy);
+            assert_ne!(&fake[..head.len()], &head[..], "{:?}", strategy);
+            assert_eq!(parse_client_hello(&fake).unwrap().sni_hostname.as_deref(), Some(FAKE_SNI));
+        }
+    }
+    
+    #[test]
+    fn test_exclude_hosts() {
+        let engine = BypassEngine::new(BypassConfig {
+            exclude_hosts: vec![".gov.tr".to_string(), "discord.com".to_string()],
+            send_fake_packets: true,
+            mangle_host_case: true,
+            ..Default::default()
+        });
+        
+        let data = sample_tls_client_hello();
+        let result = engine.process_outgoing(&data);
+        assert!(result.excluded);
+        assert!(!result.modified);
+        assert!(result.fake_packet.is_none());
+        assert_eq!(result.fragments.len(), 1);
+        assert_eq!(&result.fragments[0][..], &data[..]);
+        
+        let http = b"GET / HTTP/1.1\r\nHost: www.turkiye.gov.tr\r\n\r\n";
+        let result = engine.process_outgoing(http);
+        assert!(result.excluded);
+        assert!(!result.modified);
+        assert_eq!(result.hostname.as_deref(), Some("www.turkiye.gov.tr"));
+        assert_eq!(&result.fragments[0][..], &http[..]);
+        
+        let http = b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n";
+        let result = engine.process_outgoing(http);
+        assert!(!result.excluded);
+        assert!(result.modified);
+    }
+    
+    #[test]
+    fn test_inspect_incoming_block_pages() {
+        let engine = BypassEngine::new(BypassConfig::default());
+        
+        let page = include_str!("../tests/fixtures/btk_block_page.html");
+        let response = format!("HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n\r\n{}", page);
+        assert_eq!(engine.inspect_incoming(response.as_bytes()), IncomingVerdict::BlockPage);
+        
+        let redirect = include_str!("../tests/fixtures/btk_redirect.txt");
+        assert_eq!(engine.inspect_incoming(redirect.as_bytes()), IncomingVerdict::BlockPage);
+        assert!(engine.inspect_incoming(redirect.as_bytes()).is_blocked());
+        
+        let normal = include_str!("../tests/fixtures/normal_page.html");
+        let response = format!("HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n\r\n{}", normal);
+        assert_eq!(engine.inspect_incoming(response.as_bytes()), IncomingVerdict::Allowed);
+        
+        let moved = b"HTTP/1.1 301 Moved Permanently\r\nLocation: https://discord.com/\r\n\r\n";
+        assert_eq!(engine.inspect_incoming(moved), IncomingVerdict::Allowed);
+    }
+    
+    #[test]
+    fn test_inspect_incoming_tls() {
+        let engine = BypassEngine::new(BypassConfig::default());
+        
+        let server_hello = [0x16, 0x03, 0x03, 0x00, 0x04, 0x02, 0x00, 0x00, 0x00];
+        assert_eq!(engine.inspect_incoming(&server_hello), IncomingVerdict::Allowed);
+        
+        let alert = [0x15, 0x03, 0x03, 0x00, 0x02, 0x02, 0x28];
+        assert_eq!(engine.inspect_incoming(&alert), IncomingVerdict::TlsAlert(0x28));
+        assert!(engine.inspect_incoming(&alert).is_blocked());
+        
+        assert_eq!(engine.inspect_incoming(&[]), IncomingVerdict::Reset);
+        assert_eq!(engine.inspect_incoming(b"\x00\x01garbage"), IncomingVerdict::Unknown);
+        assert!(!engine.inspect_incoming(b"\x00\x01garbage").is_blocked());
+    }
     
     #[test]
     fn test_unknown_protocol_passthrough() {
@@ -395,4 +1294,21 @@ mod tests {
         assert_eq!(result.fragments.len(), 1);
         assert_eq!(&result.fragments[0][..], &data[..]);
     }
+    
+    #[test]
+    fn test_paused_passthrough() {
+        let paused = Arc::new(AtomicBool::new(true));
+        let engine = BypassEngine::new(BypassConfig::default()).with_pause_flag(paused.clone());
+        let data = sample_tls_client_hello();
+        
+        let result = engine.process_outgoing(&data);
+        assert!(!result.modified);
+        assert_eq!(result.fragments.len(), 1);
+        assert_eq!(&result.fragments[0][..], &data[..]);
+        
+        paused.store(false, Ordering::Relaxed);
+        let result = engine.process_outgoing(&data);
+        assert!(result.modified);
+        assert!(result.fragments.len() >= 2);
+    }
 }
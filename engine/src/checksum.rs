@@ -0,0 +1,134 @@
+//! Internet checksum (RFC 1071) helpers for packets rewritten in place.
+
+/// Adds `data` to a running ones-complement sum. An odd trailing byte is
+/// padded with zero, so only the last chunk of a message may have odd length.
+pub fn sum(data: &[u8], initial: u32) -> u32 {
+    let mut sum = initial as u64;
+    let mut chunks = data.chunks_exact(2);
+    for word in &mut chunks {
+        sum += u16::from_be_bytes([word[0], word[1]]) as u64;
+    }
+    if let [last] = chunks.remainder() {
+        sum += (*last as u64) << 8;
+    }
+    while sum > 0xFFFF_FFFF {
+        sum = (sum & 0xFFFF_FFFF) + (sum >> 32);
+    }
+    sum as u32
+}
+
+pub fn finish(mut sum: u32) -> u16 {
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+pub fn checksum(data: &[u8]) -> u16 {
+    finish(sum(data, 0))
+}
+
+pub fn update_u16(checksum: u16, old: u16, new: u16) -> u16 {
+    let sum = (!checksum as u32) + (!old as u32) + new as u32;
+    finish(sum)
+}
+
+/// Writes `new` at the even offset `offset` of `packet` and patches the
+/// checksum stored at `checksum_offset` to match.
+pub fn replace_u16(packet: &mut [u8], offset: usize, new: u16, checksum_offset: usize) {
+    let old = u16::from_be_bytes([packet[offset], packet[offset + 1]]);
+    packet[offset..offset + 2].copy_from_slice(&new.to_be_bytes());
+    let checksum = u16::from_be_bytes([packet[checksum_offset], packet[checksum_offset + 1]]);
+    let checksum = update_u16(checksum, old, new);
+    packet[checksum_offset..checksum_offset + 2].copy_from_slice(&checksum.to_be_bytes());
+}
+
+pub fn ipv4_header_len(packet: &[u8]) -> Option<usize> {
+    if packet.len() < 20 || packet[0] >> 4 != 4 {
+        return None;
+    }
+    let ihl = (packet[0] & 0x0F) as usize * 4;
+    (ihl >= 20 && packet.len() >= ihl).then_some(ihl)
+}
+
+pub fn ipv4_header_checksum(header: &[u8]) -> u16 {
+    finish(sum(&header[12..], sum(&header[..10], 0)))
+}
+
+pub fn set_ipv4_header_checksum(packet: &mut [u8]) -> bool {
+    let Some(ihl) = ipv4_header_len(packet) else {
+        return false;
+    };
+    let checksum = ipv4_header_checksum(&packet[..ihl]);
+    packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+    true
+}
+
+/// TCP checksum over an IPv4 pseudo-header and `segment`, whose own
+/// checksum field is taken as zero.
+pub fn tcp_ipv4_checksum(src: [u8; 4], dst: [u8; 4], segment: &[u8]) -> u16 {
+    let mut total = sum(&dst, sum(&src, 0)) + 6 + segment.len() as u32;
+    total = sum(&segment[..16.min(segment.len())], total);
+    if segment.len() > 18 {
+        total = sum(&segment[18..], total);
+    }
+    finish(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn reference(data: &[u8]) -> u16 {
+        let mut total: u64 = 0;
+        for i in (0..data.len()).step_by(2) {
+            let hi = data[i] as u64;
+            let lo = data.get(i + 1).copied().unwrap_or(0) as u64;
+            total += hi * 256 + lo;
+        }
+        match total % 0xFFFF {
+            0 if total != 0 => 0,
+            folded => !(folded as u16),
+        }
+    }
+    
+    #[test]
+    fn test_rfc1071_example() {
+        let data = [0x00, 0x01, 0xF2, 0x03, 0xF4, 0xF5, 0xF6, 0xF7];
+        assert_eq!(checksum(&data), !0xDDF2);
+        assert_eq!(checksum(&data[..7]), reference(&data[..7]));
+    }
+    
+    #[test]
+    fn test_known_ipv4_header() {
+        let mut header = [
+            0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00,
+            0xC0, 0xA8, 0x00, 0x01, 0xC0, 0xA8, 0x00, 0xC7,
+        ];
+        assert!(set_ipv4_header_checksum(&mut header));
+        assert_eq!(&header[10..12], &[0xB8, 0x61]);
+        assert_eq!(checksum(&header), 0);
+    }
+    
+    #[test]
+    fn test_incremental_matches_full() {
+        let mut data: Vec<u8> = (0..64u32).map(|i| (i * 37 % 256) as u8).collect();
+        data[10] = 0;
+        data[11] = 0;
+        let full = checksum(&data);
+        data[10..12].copy_from_slice(&full.to_be_bytes());
+        
+        for (offset, value) in [(0, 0xFFFF), (4, 0x0000), (8, 0x8001), (40, 0x1234)] {
+            replace_u16(&mut data, offset, value, 10);
+            assert_eq!(checksum(&data), 0, "offset {}", offset);
+        }
+    }
+    
+    #[test]
+    fn test_odd_lengths() {
+        for len in 0..40 {
+            let data: Vec<u8> = (0..len as u8).map(|b| b.wrapping_mul(91).wrapping_add(7)).collect();
+            assert_eq!(checksum(&data), reference(&data), "len {}", len);
+        }
+    }
+}
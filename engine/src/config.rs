@@ -1,14 +1,17 @@
 use std::collections::HashMap;
-use std::net::IpAddr;
-use std::path::Path;
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 use ipnet::IpNet;
 use serde::{Deserialize, Serialize};
 
+use crate::bypass::BypassConfig;
 use crate::error::{EngineError, Result};
+use crate::flow::FlowDirection;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(default)]
 pub struct Config {
     pub global: GlobalConfig,
@@ -18,32 +21,128 @@ pub struct Config {
     pub limits: Limits,
     
     pub transforms: TransformParams,
+    
+    pub dns: DnsConfig,
+    
+    pub backend: BackendSection,
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            global: GlobalConfig::default(),
-            rules: Vec::new(),
-            limits: Limits::default(),
-            transforms: TransformParams::default(),
-        }
-    }
-}
+pub const ENV_PREFIX: &str = "TURKEYDPI_";
 
 impl Config {
     pub fn load_from_file(path: impl AsRef<Path>) -> Result<Self> {
-        let path = path.as_ref();
+        let config = Self::parse_file(path.as_ref())?;
+        config.validate()?;
+        Ok(config)
+    }
+    
+    fn parse_file(path: &Path) -> Result<Self> {
         let content = std::fs::read_to_string(path)?;
+        Self::parse_as(&content, ConfigFormat::of_path(path))
+    }
         
-        let config: Config = if path.extension().map_or(false, |e| e == "toml") {
-            toml::from_str(&content)?
-        } else {
-            serde_json::from_str(&content)?
-        };
+    fn parse_as(content: &str, format: ConfigFormat) -> Result<Self> {
+        match format {
+            ConfigFormat::Toml => Ok(toml::from_str(content)?),
+            ConfigFormat::Json => Ok(serde_json::from_str(content)?),
+        }
+    }
+    
+    pub fn to_string_as(&self, format: ConfigFormat) -> Result<String> {
+        match format {
+            ConfigFormat::Toml => toml::to_string_pretty(self).map_err(|e| EngineError::Config(e.to_string())),
+            ConfigFormat::Json => Ok(serde_json::to_string_pretty(self)?),
+        }
+    }
+    
+    /// Writes the config to `path` through a temporary file. The output is
+    /// read back in `format` first, and `path` is left untouched unless it
+    /// yields this same config.
+    pub fn save_to_file(&self, path: impl AsRef<Path>, format: ConfigFormat) -> Result<()> {
+        let path = path.as_ref();
+        let loaded_as = ConfigFormat::of_path(path);
+        if loaded_as != format {
+            return Err(EngineError::Config(format!(
+                "{} would be loaded as {}, not {}",
+                path.display(), loaded_as, format,
+            )));
+        }
+        let content = self.to_string_as(format)?;
+        
+        let parsed = Self::parse_as(&content, format)
+            .and_then(|parsed| parsed.validate().map(|()| parsed))
+            .map_err(|e| EngineError::Config(format!("saved config would not load back: {}", e)))?;
+        if serde_json::to_value(&parsed)? != serde_json::to_value(self)? {
+            return Err(EngineError::Config("saved config would not load back unchanged".to_string()));
+        }
         
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let mut tmp = tempfile::NamedTempFile::new_in(dir)?;
+        tmp.write_all(content.as_bytes())?;
+        tmp.as_file().sync_all()?;
+        tmp.persist(path).map_err(|e| e.error)?;
+        Ok(())
+    }
+    
+    /// Loads `path` (or the defaults) and applies `TURKEYDPI_SECTION__FIELD`
+    /// environment overrides on top.
+    pub fn load_with_env(path: Option<&Path>) -> Result<Self> {
+        Self::load_with_env_report(path).map(|(config, _)| config)
+    }
+    
+    /// Like [`Config::load_with_env`], also returning the names of the
+    /// environment variables that were applied.
+    pub fn load_with_env_report(path: Option<&Path>) -> Result<(Self, Vec<String>)> {
+        let mut config = match path {
+            Some(path) => Self::parse_file(path)?,
+            None => Self::default(),
+        };
+        let applied = config.apply_env_overrides(std::env::vars())?;
         config.validate()?;
-        Ok(config)
+        Ok((config, applied))
+    }
+    
+    pub fn apply_env_overrides(&mut self, vars: impl IntoIterator<Item = (String, String)>) -> Result<Vec<String>> {
+        let mut overrides: Vec<(String, String)> = vars
+            .into_iter()
+            .filter(|(name, _)| name.strip_prefix(ENV_PREFIX).is_some_and(|path| path.contains("__")))
+            .collect();
+        if overrides.is_empty() {
+            return Ok(Vec::new());
+        }
+        overrides.sort();
+        
+        let mut tree = serde_json::to_value(&*self)?;
+        for (name, raw) in &overrides {
+            let path = &name[ENV_PREFIX.len()..];
+            let unknown = || EngineError::validation(name, format!("unknown config path in environment variable `{}`", name));
+            let current = env_slot(&mut tree, path).ok_or_else(unknown)?;
+            
+            let mut error = None;
+            for candidate in env_value_candidates(current, raw) {
+                let mut attempt = tree.clone();
+                *env_slot(&mut attempt, path).ok_or_else(unknown)? = candidate;
+                match serde_json::from_value::<Self>(attempt.clone()) {
+                    Ok(_) => {
+                        tree = attempt;
+                        error = None;
+                        break;
+                    }
+                    Err(e) => {
+                        error.get_or_insert(e);
+                    }
+                }
+            }
+            if let Some(e) = error {
+                return Err(EngineError::validation(name, format!("invalid value for `{}`: {}", name, e)));
+            }
+        }
+        
+        *self = serde_json::from_value(tree)?;
+        Ok(overrides.into_iter().map(|(name, _)| name).collect())
     }
     
     pub fn from_json(json: &str) -> Result<Self> {
@@ -58,6 +157,29 @@ impl Config {
         Ok(config)
     }
     
+    pub fn upsert_rule(&mut self, rule: Rule) {
+        match self.rules.iter_mut().find(|r| r.name == rule.name) {
+            Some(existing) => *existing = rule,
+            None => self.rules.push(rule),
+        }
+    }
+    
+    pub fn remove_rule(&mut self, name: &str) -> bool {
+        let before = self.rules.len();
+        self.rules.retain(|r| r.name != name);
+        self.rules.len() != before
+    }
+    
+    pub fn set_rule_enabled(&mut self, name: &str, enabled: bool) -> bool {
+        match self.rules.iter_mut().find(|r| r.name == name) {
+            Some(rule) => {
+                rule.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+    
     pub fn validate(&self) -> Result<()> {
         
         if self.limits.max_flows == 0 {
@@ -72,48 +194,81 @@ impl Config {
             return Err(EngineError::validation("limits.max_memory_mb", "must be > 0"));
         }
         
+        if self.limits.cleanup_interval_secs == 0 {
+            return Err(EngineError::validation("limits.cleanup_interval_secs", "must be > 0"));
+        }
         
-        if self.transforms.fragment.min_size == 0 {
-            return Err(EngineError::validation(
-                "transforms.fragment.min_size",
-                "must be > 0",
-            ));
+        
+        self.transforms.validate(&self.limits)?;
+        
+        
+        for (i, rule) in self.rules.iter().enumerate() {
+            rule.validate()
+                .and_then(|_| self.transforms.with_overrides(&rule.overrides)?.validate(&self.limits))
+                .map_err(|e| {
+                    EngineError::validation(format!("rules[{}]", i), e.to_string())
+                })?;
         }
         
-        if self.transforms.fragment.max_size < self.transforms.fragment.min_size {
-            return Err(EngineError::validation(
-                "transforms.fragment.max_size",
-                "must be >= min_size",
-            ));
+        if self.dns.max_ttl_secs < self.dns.min_ttl_secs {
+            return Err(EngineError::validation("dns.max_ttl_secs", "must be >= min_ttl_secs"));
         }
         
-        if self.transforms.jitter.max_ms > self.limits.max_jitter_ms {
-            return Err(EngineError::validation(
-                "transforms.jitter.max_ms",
-                format!(
-                    "exceeds safety limit of {}ms",
-                    self.limits.max_jitter_ms
-                ),
-            ));
+        if self.dns.max_cache_entries == 0 {
+            return Err(EngineError::validation("dns.max_cache_entries", "must be > 0"));
         }
         
-        if self.transforms.padding.max_bytes > 1500 {
-            return Err(EngineError::validation(
-                "transforms.padding.max_bytes",
-                "exceeds MTU (1500 bytes)",
-            ));
+        if self.dns.lookup_timeout_ms == 0 {
+            return Err(EngineError::validation("dns.lookup_timeout_ms", "must be > 0"));
         }
         
+        if self.dns.providers.is_empty() {
+            return Err(EngineError::validation("dns.providers", "must not be empty"));
+        }
         
-        for (i, rule) in self.rules.iter().enumerate() {
-            rule.validate().map_err(|e| {
-                EngineError::validation(format!("rules[{}]", i), e.to_string())
+        for (i, provider) in self.dns.providers.iter().enumerate() {
+            provider.validate().map_err(|e| {
+                EngineError::validation(format!("dns.providers[{}]", i), e.to_string())
             })?;
         }
         
+        self.backend.validate().map_err(|e| e.in_section("backend"))?;
+        
         Ok(())
     }
     
+    /// Settings that are valid but likely to misbehave. `validate` does not
+    /// report these; callers that load a config log them.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        
+        for rule in self.rules.iter().filter(|r| r.enabled && r.transforms.contains(&TransformType::Padding)) {
+            let may_match_tcp = rule.match_criteria.protocols.as_ref().is_none_or(|p| p.contains(&Protocol::Tcp));
+            let mode = self.transforms.with_overrides(&rule.overrides).map(|p| p.padding.mode);
+            if may_match_tcp && matches!(mode, Ok(PaddingMode::Append)) {
+                warnings.push(format!(
+                    "rule `{}` appends padding to TCP traffic; proxied streams skip it, use padding.mode = \"tls_record\" instead",
+                    rule.name
+                ));
+            }
+        }
+        
+        for rule in self.rules.iter().filter(|r| r.enabled && r.transforms.contains(&TransformType::Resegment)) {
+            let Ok(params) = self.transforms.with_overrides(&rule.overrides).map(|p| p.resegment) else {
+                continue;
+            };
+            let covered = params.segment_size.saturating_mul(params.max_segments);
+            if params.tail_behavior != TailBehavior::ContinueSegmenting && covered < 1500 {
+                warnings.push(format!(
+                    "rule `{}` cuts at most {} segments of {} bytes, leaving the rest of a 1500-byte packet in one large segment; raise resegment.max_segments or set resegment.tail_behavior = \"continue_segmenting\"",
+                    rule.name, params.max_segments, params.segment_size
+                ));
+            }
+        }
+        
+        warnings
+    }
+    
     pub fn merge(&mut self, other: Config) {
         if !other.rules.is_empty() {
             self.rules = other.rules;
@@ -121,6 +276,49 @@ impl Config {
         self.global = other.global;
         self.limits = other.limits;
         self.transforms = other.transforms;
+        self.dns = other.dns;
+        self.backend = other.backend;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfigFormat {
+    #[default]
+    Toml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// The format [`Config::load_from_file`] reads `path` as: TOML for
+    /// `.toml` files, JSON otherwise.
+    pub fn of_path(path: &Path) -> Self {
+        if path.extension().is_some_and(|e| e == "toml") {
+            ConfigFormat::Toml
+        } else {
+            ConfigFormat::Json
+        }
+    }
+}
+
+impl std::fmt::Display for ConfigFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ConfigFormat::Toml => "toml",
+            ConfigFormat::Json => "json",
+        })
+    }
+}
+
+impl std::str::FromStr for ConfigFormat {
+    type Err = EngineError;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "toml" => Ok(ConfigFormat::Toml),
+            "json" => Ok(ConfigFormat::Json),
+            _ => Err(EngineError::Config(format!("unknown config format {} (expected toml or json)", s))),
+        }
     }
 }
 
@@ -168,6 +366,10 @@ pub struct Rule {
     
     pub match_criteria: MatchCriteria,
     
+    #[serde(default)]
+    pub action: RuleAction,
+    
+    #[serde(default)]
     pub transforms: Vec<TransformType>,
     
     #[serde(default)]
@@ -178,17 +380,94 @@ fn default_true() -> bool {
     true
 }
 
+fn env_slot<'a>(tree: &'a mut serde_json::Value, path: &str) -> Option<&'a mut serde_json::Value> {
+    path.split("__").try_fold(tree, |node, segment| {
+        let segment = segment.to_ascii_lowercase();
+        match node {
+            serde_json::Value::Object(map) => map.get_mut(&segment),
+            serde_json::Value::Array(items) => items.get_mut(segment.parse::<usize>().ok()?),
+            _ => None,
+        }
+    })
+}
+
+fn coerce_env_value(current: &serde_json::Value, raw: &str) -> serde_json::Value {
+    use serde_json::Value;
+    
+    let raw = raw.trim();
+    match current {
+        Value::String(_) => Value::String(raw.to_string()),
+        Value::Array(items) => {
+            let sample = items.first().unwrap_or(&Value::Null);
+            raw.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| coerce_env_value(sample, item))
+                .collect()
+        }
+        _ => serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string())),
+    }
+}
+
+fn env_value_candidates(current: &serde_json::Value, raw: &str) -> Vec<serde_json::Value> {
+    // An unset optional field gives no hint whether it holds a scalar or a list.
+    if current.is_null() {
+        vec![
+            coerce_env_value(current, raw),
+            coerce_env_value(&serde_json::Value::Array(Vec::new()), raw),
+        ]
+    } else {
+        vec![coerce_env_value(current, raw)]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleAction {
+    /// Run the rule's transforms over matching packets.
+    #[default]
+    Transform,
+    /// Drop every matching packet.
+    Drop,
+    /// Drop packets of a flow beyond `pps` packets per second; the rest go
+    /// through the rule's transforms.
+    RateLimit { pps: u32 },
+}
+
 impl Rule {
+    pub fn load_from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)?;
+        
+        let rule: Rule = match ConfigFormat::of_path(path) {
+            ConfigFormat::Toml => toml::from_str(&content)?,
+            ConfigFormat::Json => serde_json::from_str(&content)?,
+        };
+        
+        rule.validate()?;
+        Ok(rule)
+    }
+    
     pub fn validate(&self) -> Result<()> {
         if self.name.is_empty() {
             return Err(EngineError::validation("name", "cannot be empty"));
         }
         
-        if self.transforms.is_empty() {
-            return Err(EngineError::validation("transforms", "must specify at least one transform"));
+        match self.action {
+            RuleAction::Transform if self.transforms.is_empty() => {
+                return Err(EngineError::validation("transforms", "must specify at least one transform"));
+            }
+            RuleAction::Drop if !self.transforms.is_empty() => {
+                return Err(EngineError::validation("transforms", "drop rules cannot have transforms"));
+            }
+            RuleAction::RateLimit { pps: 0 } => {
+                return Err(EngineError::validation("action.rate_limit.pps", "rate_limit pps must be greater than 0"));
+            }
+            _ => {}
         }
         
         self.match_criteria.validate()?;
+        TransformParams::default().with_overrides(&self.overrides)?;
         
         Ok(())
     }
@@ -207,9 +486,103 @@ pub struct MatchCriteria {
     
     pub protocols: Option<Vec<Protocol>>,
     
+    /// Exact hostnames or `*.suffix` patterns matched against the flow's SNI/Host.
     pub domains: Option<Vec<String>>,
     
+    /// File with additional `domains` patterns, one per line (`#` starts a comment).
+    /// Read whenever the rule is compiled, so a reload picks up edits.
+    pub domains_file: Option<PathBuf>,
+    
+    /// Whether a flow whose hostname is not known yet fails `domains`. When false,
+    /// the domain check is deferred until a hostname has been recorded for the flow.
+    pub require_hostname: bool,
+    
     pub process: Option<String>,
+    
+    /// Local-time window outside of which the rule does not match.
+    pub active_hours: Option<TimeWindow>,
+    
+    /// Unset matches traffic in both directions.
+    pub direction: Option<FlowDirection>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Weekday {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+impl Weekday {
+    fn from_chrono(day: chrono::Weekday) -> Self {
+        match day {
+            chrono::Weekday::Mon => Weekday::Mon,
+            chrono::Weekday::Tue => Weekday::Tue,
+            chrono::Weekday::Wed => Weekday::Wed,
+            chrono::Weekday::Thu => Weekday::Thu,
+            chrono::Weekday::Fri => Weekday::Fri,
+            chrono::Weekday::Sat => Weekday::Sat,
+            chrono::Weekday::Sun => Weekday::Sun,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeWindow {
+    /// Start of the window as `HH:MM`, inclusive.
+    pub start: String,
+    
+    /// End of the window as `HH:MM`, exclusive. An end before the start wraps past
+    /// midnight; an end equal to the start covers the whole day.
+    pub end: String,
+    
+    /// Days on which the window starts; empty means every day.
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+}
+
+impl TimeWindow {
+    pub fn validate(&self) -> Result<()> {
+        parse_time(&self.start)
+            .ok_or_else(|| EngineError::validation("active_hours.start", format!("invalid time (expected HH:MM): {}", self.start)))?;
+        parse_time(&self.end)
+            .ok_or_else(|| EngineError::validation("active_hours.end", format!("invalid time (expected HH:MM): {}", self.end)))?;
+        Ok(())
+    }
+    
+    pub fn is_active_at(&self, now: &chrono::NaiveDateTime) -> bool {
+        use chrono::{Datelike, Timelike};
+        
+        let (Some(start), Some(end)) = (parse_time(&self.start), parse_time(&self.end)) else {
+            return false;
+        };
+        let minute = now.hour() * 60 + now.minute();
+        let today = now.weekday();
+        let starts_on = |day: chrono::Weekday| self.days.is_empty() || self.days.contains(&Weekday::from_chrono(day));
+        
+        if start < end {
+            (start..end).contains(&minute) && starts_on(today)
+        } else if start > end {
+            (minute >= start && starts_on(today)) || (minute < end && starts_on(today.pred()))
+        } else {
+            starts_on(today)
+        }
+    }
+}
+
+fn parse_time(value: &str) -> Option<u32> {
+    let (hours, minutes) = value.split_once(':')?;
+    if hours.len() != 2 || minutes.len() != 2 {
+        return None;
+    }
+    let hours: u32 = hours.parse().ok()?;
+    let minutes: u32 = minutes.parse().ok()?;
+    (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
 }
 
 impl MatchCriteria {
@@ -231,9 +604,38 @@ impl MatchCriteria {
             }
         }
         
+        self.domain_patterns()?;
+        
+        if let Some(ref window) = self.active_hours {
+            window.validate()?;
+        }
+        
         Ok(())
     }
     
+    pub fn domain_patterns(&self) -> Result<Vec<String>> {
+        let mut patterns = self.domains.clone().unwrap_or_default();
+        for domain in &patterns {
+            validate_domain_pattern("domains", domain)?;
+        }
+        
+        if let Some(ref path) = self.domains_file {
+            let content = std::fs::read_to_string(path).map_err(|e| {
+                EngineError::validation("domains_file", format!("cannot read {}: {}", path.display(), e))
+            })?;
+            for line in content.lines() {
+                let domain = line.split('#').next().unwrap_or("").trim();
+                if domain.is_empty() {
+                    continue;
+                }
+                validate_domain_pattern("domains_file", domain)?;
+                patterns.push(domain.to_string());
+            }
+        }
+        
+        Ok(patterns)
+    }
+    
     pub fn is_catch_all(&self) -> bool {
         self.dst_ip.is_none()
             && self.src_ip.is_none()
@@ -241,10 +643,20 @@ impl MatchCriteria {
             && self.src_ports.is_none()
             && self.protocols.is_none()
             && self.domains.is_none()
+            && self.domains_file.is_none()
             && self.process.is_none()
+            && self.active_hours.is_none()
     }
 }
 
+fn validate_domain_pattern(field: &str, domain: &str) -> Result<()> {
+    let name = domain.strip_prefix("*.").unwrap_or(domain);
+    if name.is_empty() || name.contains('*') || name.contains(char::is_whitespace) {
+        return Err(EngineError::validation(field, format!("invalid domain pattern: {}", domain)));
+    }
+    Ok(())
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Protocol {
@@ -269,9 +681,11 @@ pub enum TransformType {
     Decoy,
     
     Reorder,
+    
+    Fake,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(default)]
 pub struct TransformParams {
     pub fragment: FragmentParams,
@@ -285,18 +699,100 @@ pub struct TransformParams {
     pub header: HeaderParams,
     
     pub decoy: DecoyParams,
+    
+    pub reorder: ReorderParams,
+    
+    pub fake: FakeParams,
 }
 
-impl Default for TransformParams {
-    fn default() -> Self {
-        Self {
-            fragment: FragmentParams::default(),
-            resegment: ResegmentParams::default(),
-            padding: PaddingParams::default(),
-            jitter: JitterParams::default(),
-            header: HeaderParams::default(),
-            decoy: DecoyParams::default(),
+impl TransformParams {
+    pub fn validate(&self, limits: &Limits) -> Result<()> {
+        if self.fragment.min_size == 0 {
+            return Err(EngineError::validation(
+                "transforms.fragment.min_size",
+                "must be > 0",
+            ));
+        }
+        
+        if self.fragment.max_size < self.fragment.min_size {
+            return Err(EngineError::validation(
+                "transforms.fragment.max_size",
+                "must be >= min_size",
+            ));
+        }
+        
+        if self.jitter.max_ms < self.jitter.min_ms {
+            return Err(EngineError::validation(
+                "transforms.jitter.max_ms",
+                "must be >= min_ms",
+            ));
+        }
+        
+        if self.jitter.max_ms > limits.max_jitter_ms {
+            return Err(EngineError::validation(
+                "transforms.jitter.max_ms",
+                format!(
+                    "exceeds safety limit of {}ms",
+                    limits.max_jitter_ms
+                ),
+            ));
+        }
+        
+        if self.padding.max_bytes < self.padding.min_bytes {
+            return Err(EngineError::validation(
+                "transforms.padding.max_bytes",
+                "must be >= min_bytes",
+            ));
+        }
+        
+        if self.padding.max_bytes > 1500 {
+            return Err(EngineError::validation(
+                "transforms.padding.max_bytes",
+                "exceeds MTU (1500 bytes)",
+            ));
+        }
+        
+        if self.resegment.segment_size == 0 {
+            return Err(EngineError::validation(
+                "transforms.resegment.segment_size",
+                "must be > 0",
+            ));
+        }
+        
+        if self.resegment.max_segments == 0 && self.resegment.tail_behavior != TailBehavior::ContinueSegmenting {
+            return Err(EngineError::validation(
+                "transforms.resegment.max_segments",
+                "must be > 0",
+            ));
+        }
+        
+        Ok(())
+    }
+    
+    /// Applies rule overrides keyed as `section.field` (e.g. `fragment.max_size`)
+    /// on top of a copy of these params.
+    pub fn with_overrides(&self, overrides: &HashMap<String, serde_json::Value>) -> Result<Self> {
+        if overrides.is_empty() {
+            return Ok(self.clone());
+        }
+        
+        let mut params = serde_json::to_value(self)?;
+        let mut keys: Vec<&String> = overrides.keys().collect();
+        keys.sort();
+        
+        for key in keys {
+            let field = format!("overrides.{}", key);
+            let slot = key
+                .split_once('.')
+                .and_then(|(section, name)| params.get_mut(section)?.as_object_mut()?.get_mut(name))
+                .ok_or_else(|| EngineError::validation(&field, format!("unknown transform parameter `{}`", field)))?;
+            *slot = overrides[key].clone();
+            
+            serde_json::from_value::<Self>(params.clone())
+                .map_err(|e| EngineError::validation(&field, format!("invalid value for `{}`: {}", field, e)))?;
         }
+        
+        Ok(serde_json::from_value(params)?)
     }
 }
 
@@ -310,6 +806,12 @@ pub struct FragmentParams {
     pub split_at_offset: Option<usize>,
     
     pub randomize: bool,
+    
+    /// Split TLS ClientHellos around the SNI hostname instead of by size.
+    pub split_at_sni: bool,
+    
+    /// Fixed RNG seed for reproducible fragment sizes; per-flow random when unset.
+    pub seed: Option<u64>,
 }
 
 impl Default for FragmentParams {
@@ -319,6 +821,8 @@ impl Default for FragmentParams {
             max_size: 40,
             split_at_offset: None,
             randomize: true,
+            split_at_sni: false,
+            seed: None,
         }
     }
 }
@@ -329,6 +833,8 @@ pub struct ResegmentParams {
     pub segment_size: usize,
     
     pub max_segments: usize,
+    
+    pub tail_behavior: TailBehavior,
 }
 
 impl Default for ResegmentParams {
@@ -336,10 +842,26 @@ impl Default for ResegmentParams {
         Self {
             segment_size: 16,
             max_segments: 8,
+            tail_behavior: TailBehavior::default(),
         }
     }
 }
 
+/// What happens to data left over once `max_segments` is reached.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TailBehavior {
+    /// The remainder goes out as one extra segment past `max_segments`.
+    OneBigTail,
+    
+    /// The last allowed segment absorbs the remainder.
+    #[default]
+    Strict,
+    
+    /// `max_segments` is ignored; all data is cut to `segment_size`.
+    ContinueSegmenting,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct PaddingParams {
@@ -348,6 +870,10 @@ pub struct PaddingParams {
     pub max_bytes: usize,
     
     pub fill_byte: Option<u8>,
+    
+    pub seed: Option<u64>,
+    
+    pub mode: PaddingMode,
 }
 
 impl Default for PaddingParams {
@@ -356,16 +882,32 @@ impl Default for PaddingParams {
             min_bytes: 0,
             max_bytes: 64,
             fill_byte: None,
+            seed: None,
+            mode: PaddingMode::default(),
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaddingMode {
+    /// Random bytes after the data; only safe where the data is a whole datagram.
+    #[default]
+    Append,
+    
+    /// A ChangeCipherSpec record after a complete ClientHello, which TLS 1.3
+    /// servers discard. Other data is left alone.
+    TlsRecord,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct JitterParams {
     pub min_ms: u64,
     
     pub max_ms: u64,
+    
+    pub seed: Option<u64>,
 }
 
 impl Default for JitterParams {
@@ -373,6 +915,7 @@ impl Default for JitterParams {
         Self {
             min_ms: 0,
             max_ms: 50,
+            seed: None,
         }
     }
 }
@@ -416,8 +959,14 @@ pub struct DecoyParams {
     pub ttl: u8,
     
     pub probability: f32,
-}
-
+    
+    pub corruption: DecoyCorruption,
+    
+    pub count: u8,
+    
+    pub seed: Option<u64>,
+}
+
 impl Default for DecoyParams {
     fn default() -> Self {
         Self {
@@ -425,10 +974,265 @@ impl Default for DecoyParams {
             send_after: false,
             ttl: 1,
             probability: 0.0,
+            corruption: DecoyCorruption::default(),
+            count: 1,
+            seed: None,
+        }
+    }
+}
+
+/// How a decoy is made to differ from the packet it imitates, so that the
+/// DPI parses it but the server never accepts it. Checksums stay valid except
+/// where breaking them is the point.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DecoyCorruption {
+    /// TTL lowered to `ttl` so the decoy expires before reaching the server.
+    #[default]
+    LowTtl,
+    
+    /// TCP checksum broken so the server drops the decoy.
+    BadChecksum,
+    
+    /// TCP sequence number moved outside the receive window.
+    BadSeq,
+    
+    /// TCP payload replaced by `template`, with the TTL lowered as for `LowTtl`.
+    FakePayload { template: Vec<u8> },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReorderStrategy {
+    #[default]
+    Swap,
+    
+    Shuffle,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReorderParams {
+    pub strategy: ReorderStrategy,
+    
+    /// Fixed shuffle seed; drawn from the flow's RNG when unset.
+    pub seed: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FakeParams {
+    pub payload: FakePayload,
+    
+    pub repeat: u8,
+}
+
+impl Default for FakeParams {
+    fn default() -> Self {
+        Self {
+            payload: FakePayload::default(),
+            repeat: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FakePayload {
+    Http { host: String },
+    
+    TlsClientHello { sni: String },
+    
+    Raw(Vec<u8>),
+}
+
+impl Default for FakePayload {
+    fn default() -> Self {
+        FakePayload::Http {
+            host: "example.com".to_string(),
         }
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DnsConfig {
+    pub providers: Vec<DohProvider>,
+    
+    pub min_ttl_secs: u64,
+    
+    pub max_ttl_secs: u64,
+    
+    pub negative_ttl_secs: u64,
+    
+    pub max_cache_entries: usize,
+    
+    pub family_preference: FamilyPreference,
+    
+    pub provider_stagger_ms: u64,
+    
+    pub lookup_timeout_ms: u64,
+    
+    pub hosts_file: Option<PathBuf>,
+    
+    /// Split the ClientHello of DoH/DoT connections like any other bypassed flow.
+    pub fragment_doh: bool,
+}
+
+impl Default for DnsConfig {
+    fn default() -> Self {
+        Self {
+            providers: DohProvider::defaults(),
+            min_ttl_secs: 10,
+            max_ttl_secs: 3600,
+            negative_ttl_secs: 30,
+            max_cache_entries: 4096,
+            family_preference: FamilyPreference::default(),
+            provider_stagger_ms: 300,
+            lookup_timeout_ms: 8000,
+            hosts_file: None,
+            fragment_doh: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FamilyPreference {
+    #[default]
+    V4First,
+    V6First,
+    V4Only,
+    V6Only,
+}
+
+impl std::str::FromStr for FamilyPreference {
+    type Err = EngineError;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().replace('-', "_").as_str() {
+            "v4_first" => Ok(Self::V4First),
+            "v6_first" => Ok(Self::V6First),
+            "v4_only" => Ok(Self::V4Only),
+            "v6_only" => Ok(Self::V6Only),
+            _ => Err(EngineError::Config(format!(
+                "unknown address family preference {} (expected v4-first, v6-first, v4-only or v6-only)", s
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DohProvider {
+    pub host: String,
+    
+    #[serde(default)]
+    pub ip: Option<IpAddr>,
+    
+    #[serde(default = "default_doh_path")]
+    pub path: String,
+    
+    #[serde(default)]
+    pub format: DohFormat,
+    
+    #[serde(default = "default_transports")]
+    pub transports: Vec<Transport>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DohFormat {
+    #[default]
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    Doh,
+    Dot,
+}
+
+fn default_doh_path() -> String {
+    "/dns-query".to_string()
+}
+
+fn default_transports() -> Vec<Transport> {
+    vec![Transport::Doh]
+}
+
+impl DohProvider {
+    pub fn new(ip: IpAddr, path: impl Into<String>) -> Self {
+        Self {
+            host: ip.to_string(),
+            ip: Some(ip),
+            path: path.into(),
+            format: DohFormat::Json,
+            transports: vec![Transport::Doh, Transport::Dot],
+        }
+    }
+    
+    pub fn defaults() -> Vec<Self> {
+        vec![
+            Self::new(IpAddr::from([1, 1, 1, 1]), "/dns-query"),
+            Self::new(IpAddr::from([8, 8, 8, 8]), "/resolve"),
+            Self::new(IpAddr::from([9, 9, 9, 9]), "/dns-query"),
+        ]
+    }
+    
+    pub fn validate(&self) -> Result<()> {
+        if self.ip.is_none() {
+            return Err(EngineError::validation(
+                "ip",
+                format!("provider {} needs a literal IP address to bootstrap without the system resolver", self.host),
+            ));
+        }
+        
+        if self.host.is_empty() {
+            return Err(EngineError::validation("host", "must not be empty"));
+        }
+        
+        if !self.path.starts_with('/') {
+            return Err(EngineError::validation("path", "must start with '/'"));
+        }
+        
+        if self.transports.is_empty() {
+            return Err(EngineError::validation("transports", "must not be empty"));
+        }
+        
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for DohProvider {
+    type Err = EngineError;
+    
+    fn from_str(url: &str) -> Result<Self> {
+        let (transport, rest) = if let Some(rest) = url.strip_prefix("https://") {
+            (Transport::Doh, rest)
+        } else if let Some(rest) = url.strip_prefix("tls://") {
+            (Transport::Dot, rest)
+        } else {
+            return Err(EngineError::Config(format!("DNS URL must start with https:// or tls://: {}", url)));
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(idx) => (&rest[..idx], &rest[idx..]),
+            None => (rest, "/dns-query"),
+        };
+        let host = authority.trim_start_matches('[').trim_end_matches(']');
+        
+        let provider = Self {
+            host: host.to_string(),
+            ip: host.parse().ok(),
+            path: path.to_string(),
+            format: DohFormat::Json,
+            transports: vec![transport],
+        };
+        provider.validate()?;
+        Ok(provider)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct Limits {
@@ -442,6 +1246,8 @@ pub struct Limits {
     
     pub flow_timeout_secs: u64,
     
+    pub cleanup_interval_secs: u64,
+    
     pub log_rate_limit: u32,
 }
 
@@ -453,11 +1259,135 @@ impl Default for Limits {
             max_memory_mb: 128,
             max_jitter_ms: 500,
             flow_timeout_secs: 120,
+            cleanup_interval_secs: 30,
             log_rate_limit: 100,
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendKind {
+    #[default]
+    Socks5,
+    HttpConnect,
+    BypassHttp,
+    Tun,
+}
+
+impl BackendKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            BackendKind::Socks5 => "socks5",
+            BackendKind::HttpConnect => "http_connect",
+            BackendKind::BypassHttp => "bypass_http",
+            BackendKind::Tun => "tun",
+        }
+    }
+}
+
+impl std::fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+pub const MIN_TUN_MTU: u16 = 576;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackendSection {
+    pub kind: BackendKind,
+    
+    /// Ignored by the TUN backend.
+    pub listen: Vec<SocketAddr>,
+    
+    pub max_connections: usize,
+    
+    pub timeout_secs: u64,
+    
+    pub tun: TunConfig,
+    
+    pub bypass: BypassConfig,
+}
+
+impl Default for BackendSection {
+    fn default() -> Self {
+        Self {
+            kind: BackendKind::default(),
+            listen: vec![SocketAddr::from(([127, 0, 0, 1], 1080))],
+            max_connections: 1000,
+            timeout_secs: 300,
+            tun: TunConfig::default(),
+            bypass: BypassConfig::default(),
+        }
+    }
+}
+
+impl BackendSection {
+    pub fn validate(&self) -> Result<()> {
+        if self.kind != BackendKind::Tun && self.listen.is_empty() {
+            return Err(EngineError::validation("listen", "must not be empty"));
+        }
+        
+        if self.max_connections == 0 {
+            return Err(EngineError::validation("max_connections", "must be > 0"));
+        }
+        
+        if self.timeout_secs == 0 {
+            return Err(EngineError::validation("timeout_secs", "must be > 0"));
+        }
+        
+        self.tun.validate().map_err(|e| e.in_section("tun"))?;
+        self.bypass.validate().map_err(|e| e.in_section("bypass"))?;
+        
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TunConfig {
+    pub device_name: Option<String>,
+    
+    pub mtu: u16,
+    
+    pub address: String,
+    
+    pub netmask: String,
+}
+
+impl Default for TunConfig {
+    fn default() -> Self {
+        Self {
+            device_name: None,
+            mtu: 1500,
+            address: "10.0.85.1".to_string(),
+            netmask: "255.255.255.0".to_string(),
+        }
+    }
+}
+
+impl TunConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.device_name.as_deref().is_some_and(|name| name.trim().is_empty()) {
+            return Err(EngineError::validation("device_name", "must not be empty"));
+        }
+        
+        if self.mtu < MIN_TUN_MTU {
+            return Err(EngineError::validation("mtu", format!("must be >= {}", MIN_TUN_MTU)));
+        }
+        
+        for (field, value) in [("address", &self.address), ("netmask", &self.netmask)] {
+            if value.parse::<Ipv4Addr>().is_err() {
+                return Err(EngineError::validation(field, format!("{} is not an IPv4 address", value)));
+            }
+        }
+        
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -490,6 +1420,144 @@ mod tests {
         config.limits.max_jitter_ms = 500;
         assert!(config.validate().is_err());
     }
+    
+    #[test]
+    fn test_jitter_min_exceeds_max() {
+        let mut config = Config::default();
+        config.transforms.jitter.min_ms = 100;
+        config.transforms.jitter.max_ms = 50;
+        assert!(matches!(
+            config.validate(),
+            Err(EngineError::ConfigValidation { ref field, .. }) if field == "transforms.jitter.max_ms"
+        ));
+    }
+    
+    #[test]
+    fn test_padding_min_exceeds_max() {
+        let mut config = Config::default();
+        config.transforms.padding.min_bytes = 64;
+        config.transforms.padding.max_bytes = 16;
+        assert!(matches!(
+            config.validate(),
+            Err(EngineError::ConfigValidation { ref field, .. }) if field == "transforms.padding.max_bytes"
+        ));
+    }
+
+    #[test]
+    fn test_doh_provider_requires_literal_ip() {
+        let mut config = Config::default();
+        assert_eq!(config.dns.providers.len(), 3);
+        
+        config.dns.providers.push(DohProvider {
+            host: "dns.example.com".to_string(),
+            ip: None,
+            path: "/dns-query".to_string(),
+            format: DohFormat::Json,
+            transports: vec![Transport::Doh],
+        });
+        assert!(config.validate().is_err());
+        
+        config.dns.providers.clear();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_parse_doh_url() {
+        let provider: DohProvider = "https://9.9.9.9/dns-query".parse().unwrap();
+        assert_eq!(provider.ip, Some("9.9.9.9".parse().unwrap()));
+        assert_eq!(provider.host, "9.9.9.9");
+        assert_eq!(provider.path, "/dns-query");
+        
+        let provider: DohProvider = "https://[2620:fe::fe]".parse().unwrap();
+        assert_eq!(provider.ip, Some("2620:fe::fe".parse().unwrap()));
+        assert_eq!(provider.path, "/dns-query");
+        
+        let provider: DohProvider = "tls://1.1.1.1".parse().unwrap();
+        assert_eq!(provider.transports, vec![Transport::Dot]);
+        
+        assert!("https://dns.quad9.net/dns-query".parse::<DohProvider>().is_err());
+        assert!("http://9.9.9.9/dns-query".parse::<DohProvider>().is_err());
+    }
+
+    #[test]
+    fn test_parse_dns_section() {
+        let toml_str = r#"
+        [dns]
+        hosts_file = "/etc/turkeydpi/hosts"
+        
+        [[dns.providers]]
+        host = "cloudflare-dns.com"
+        ip = "1.0.0.1"
+        "#;
+        
+        let config = Config::from_toml(toml_str).unwrap();
+        assert_eq!(config.dns.providers.len(), 1);
+        assert_eq!(config.dns.providers[0].path, "/dns-query");
+        assert_eq!(config.dns.providers[0].format, DohFormat::Json);
+        assert_eq!(config.dns.providers[0].transports, vec![Transport::Doh]);
+        assert_eq!(config.dns.hosts_file, Some(PathBuf::from("/etc/turkeydpi/hosts")));
+    }
+    
+    #[test]
+    fn test_parse_backend_section() {
+        let toml_str = r#"
+        [backend]
+        kind = "bypass_http"
+        listen = ["127.0.0.1:8844", "[::1]:8844"]
+        timeout_secs = 60
+        
+        [backend.bypass]
+        split_strategy = "sni_start"
+        max_segment_size = 10
+        
+        [[backend.bypass.host_overrides]]
+        pattern = "*.discord.com"
+        config = { send_fake_packets = true, fake_packet_ttl = 3 }
+        "#;
+        
+        let config = Config::from_toml(toml_str).unwrap();
+        assert_eq!(config.backend.kind, BackendKind::BypassHttp);
+        assert_eq!(config.backend.listen.len(), 2);
+        assert_eq!(config.backend.max_connections, 1000);
+        assert_eq!(config.backend.bypass.split_strategy, Some(crate::SplitStrategy::SniStart));
+        assert_eq!(config.backend.bypass.host_overrides[0].config.fake_packet_ttl, 3);
+        assert_eq!(config.backend.tun.mtu, 1500);
+        
+        let toml = toml::to_string_pretty(&config).unwrap();
+        let reparsed = Config::from_toml(&toml).unwrap();
+        assert_eq!(reparsed.backend.listen, config.backend.listen);
+        assert_eq!(reparsed.backend.bypass.max_segment_size, 10);
+        assert!(reparsed.backend.bypass.host_overrides[0].config.send_fake_packets);
+        
+        let json = serde_json::to_string(&config).unwrap();
+        let reparsed = Config::from_json(&json).unwrap();
+        assert_eq!(reparsed.backend.kind, BackendKind::BypassHttp);
+        assert_eq!(reparsed.backend.timeout_secs, 60);
+        
+        let defaults = Config::from_toml("").unwrap();
+        assert_eq!(defaults.backend.kind, BackendKind::Socks5);
+        assert_eq!(defaults.backend.listen, vec!["127.0.0.1:1080".parse().unwrap()]);
+    }
+    
+    #[test]
+    fn test_invalid_backend_section() {
+        for (toml_str, expected) in [
+            ("[backend]\nlisten = []", "backend.listen"),
+            ("[backend]\nmax_connections = 0", "backend.max_connections"),
+            ("[backend.tun]\nmtu = 100", "backend.tun.mtu"),
+            ("[backend.tun]\nnetmask = \"255.255.0\"", "backend.tun.netmask"),
+            ("[backend.bypass]\nmin_segment_size = 50\nmax_segment_size = 10", "backend.bypass.max_segment_size"),
+            ("[backend.bypass]\nfake_packet_ttl = 0", "backend.bypass.fake_packet_ttl"),
+        ] {
+            match Config::from_toml(toml_str) {
+                Err(EngineError::ConfigValidation { field, .. }) => assert_eq!(field, expected, "{}", toml_str),
+                other => panic!("{}: {:?}", toml_str, other),
+            }
+        }
+        
+        let tun = Config::from_toml("[backend]\nkind = \"tun\"\nlisten = []").unwrap();
+        assert_eq!(tun.backend.kind, BackendKind::Tun);
+    }
 
     #[test]
     fn test_valid_rule() {
@@ -502,11 +1570,204 @@ mod tests {
                 protocols: Some(vec![Protocol::Tcp]),
                 ..Default::default()
             },
+            action: RuleAction::Transform,
             transforms: vec![TransformType::Fragment, TransformType::Padding],
             overrides: HashMap::new(),
         };
         assert!(rule.validate().is_ok());
     }
+    
+    #[test]
+    fn test_rule_overrides_validation() {
+        let mut rule = Rule {
+            name: "test-rule".to_string(),
+            enabled: true,
+            priority: 10,
+            match_criteria: MatchCriteria::default(),
+            action: RuleAction::Transform,
+            transforms: vec![TransformType::Fragment],
+            overrides: HashMap::new(),
+        };
+        rule.overrides.insert("fragment.max_size".to_string(), serde_json::json!(2));
+        rule.overrides.insert("padding.fill_byte".to_string(), serde_json::json!(255));
+        assert!(rule.validate().is_ok());
+        
+        let params = TransformParams::default().with_overrides(&rule.overrides).unwrap();
+        assert_eq!(params.fragment.max_size, 2);
+        assert_eq!(params.padding.fill_byte, Some(255));
+        assert_eq!(params.fragment.min_size, FragmentParams::default().min_size);
+        
+        rule.overrides.insert("fragment.max_sise".to_string(), serde_json::json!(2));
+        let err = rule.validate().unwrap_err().to_string();
+        assert!(err.contains("overrides.fragment.max_sise"), "{}", err);
+        
+        rule.overrides.remove("fragment.max_sise");
+        rule.overrides.insert("jitter.max_ms".to_string(), serde_json::json!("fast"));
+        let err = rule.validate().unwrap_err().to_string();
+        assert!(err.contains("overrides.jitter.max_ms"), "{}", err);
+        
+        rule.overrides.insert("jitter.max_ms".to_string(), serde_json::json!(100_000));
+        let mut config = Config::default();
+        config.rules.push(rule);
+        assert!(config.validate().is_err());
+    }
+    
+    #[test]
+    fn test_append_padding_on_tcp_warns() {
+        let mut config = Config::from_toml(
+            r#"
+[[rules]]
+name = "https"
+transforms = ["padding"]
+
+[rules.match_criteria]
+dst_ports = [443]
+protocols = ["tcp"]
+
+[[rules]]
+name = "dns"
+transforms = ["padding"]
+
+[rules.match_criteria]
+dst_ports = [53]
+protocols = ["udp"]
+"#,
+        ).unwrap();
+        let warnings = config.warnings();
+        assert_eq!(warnings.len(), 1, "{:?}", warnings);
+        assert!(warnings[0].contains("`https`"), "{:?}", warnings);
+        
+        config.rules[0].overrides.insert("padding.mode".to_string(), serde_json::json!("tls_record"));
+        assert!(config.warnings().is_empty());
+        
+        config.rules[0].overrides.clear();
+        config.transforms.padding.mode = PaddingMode::TlsRecord;
+        assert!(config.warnings().is_empty());
+        
+        config.transforms.padding.mode = PaddingMode::Append;
+        config.rules[0].match_criteria.protocols = None;
+        assert_eq!(config.warnings().len(), 1);
+    }
+    
+    #[test]
+    fn test_short_resegment_coverage_warns() {
+        let mut config = Config::from_toml(r#"
+[[rules]]
+name = "http"
+transforms = ["resegment"]
+
+[rules.match_criteria]
+dst_ports = [80]
+"#,
+        ).unwrap();
+        let warnings = config.warnings();
+        assert_eq!(warnings.len(), 1, "{:?}", warnings);
+        assert!(warnings[0].contains("`http`"), "{:?}", warnings);
+        
+        config.rules[0].overrides.insert("resegment.max_segments".to_string(), serde_json::json!(100));
+        assert!(config.warnings().is_empty());
+        
+        config.rules[0].overrides.clear();
+        config.transforms.resegment.tail_behavior = TailBehavior::ContinueSegmenting;
+        assert!(config.warnings().is_empty());
+        
+        config.transforms.resegment.max_segments = 0;
+        assert!(config.validate().is_ok());
+        config.transforms.resegment.tail_behavior = TailBehavior::Strict;
+        assert!(config.validate().is_err());
+        config.transforms.resegment.max_segments = 8;
+        config.transforms.resegment.segment_size = 0;
+        assert!(config.validate().is_err());
+    }
+    
+    #[test]
+    fn test_rule_editing() {
+        let rule = |name: &str, port: u16| Rule {
+            name: name.to_string(),
+            enabled: true,
+            priority: 10,
+            match_criteria: MatchCriteria {
+                dst_ports: Some(vec![port]),
+                ..Default::default()
+            },
+            action: RuleAction::Transform,
+            transforms: vec![TransformType::Fragment],
+            overrides: HashMap::new(),
+        };
+        let mut config = Config::default();
+        
+        config.upsert_rule(rule("https", 443));
+        config.upsert_rule(rule("http", 80));
+        config.upsert_rule(rule("https", 8443));
+        assert_eq!(config.rules.len(), 2);
+        assert_eq!(config.rules[0].match_criteria.dst_ports, Some(vec![8443]));
+        
+        assert!(config.set_rule_enabled("http", false));
+        assert!(!config.rules[1].enabled);
+        assert!(!config.set_rule_enabled("missing", false));
+        
+        assert!(config.remove_rule("http"));
+        assert!(!config.remove_rule("http"));
+        assert_eq!(config.rules.len(), 1);
+    }
+    
+    #[test]
+    fn test_time_window() {
+        use chrono::NaiveDate;
+        
+        let at = |day: u32, hour: u32, minute: u32| {
+            NaiveDate::from_ymd_opt(2024, 3, day).unwrap().and_hms_opt(hour, minute, 0).unwrap()
+        };
+        let evening = TimeWindow {
+            start: "19:00".to_string(),
+            end: "23:30".to_string(),
+            days: Vec::new(),
+        };
+        assert!(evening.is_active_at(&at(4, 19, 0)));
+        assert!(evening.is_active_at(&at(4, 23, 29)));
+        assert!(!evening.is_active_at(&at(4, 23, 30)));
+        assert!(!evening.is_active_at(&at(4, 12, 0)));
+        
+        // 2024-03-08 is a Friday.
+        let friday_night = TimeWindow {
+            start: "22:00".to_string(),
+            end: "02:00".to_string(),
+            days: vec![Weekday::Fri],
+        };
+        assert!(friday_night.is_active_at(&at(8, 23, 0)));
+        assert!(friday_night.is_active_at(&at(9, 1, 59)));
+        assert!(!friday_night.is_active_at(&at(9, 2, 0)));
+        assert!(!friday_night.is_active_at(&at(9, 23, 0)));
+        assert!(!friday_night.is_active_at(&at(8, 1, 0)));
+        
+        let all_day = TimeWindow {
+            start: "00:00".to_string(),
+            end: "00:00".to_string(),
+            days: vec![Weekday::Sat],
+        };
+        assert!(all_day.is_active_at(&at(9, 12, 0)));
+        assert!(!all_day.is_active_at(&at(10, 12, 0)));
+    }
+    
+    #[test]
+    fn test_time_window_validation() {
+        let json = r#"{"start": "19:00", "end": "01:00", "days": ["mon", "sun"]}"#;
+        let window: TimeWindow = serde_json::from_str(json).unwrap();
+        assert_eq!(window.days, vec![Weekday::Mon, Weekday::Sun]);
+        assert!(window.validate().is_ok());
+        
+        for (start, end) in [("7:00", "08:00"), ("24:00", "01:00"), ("19:00", "19:60"), ("evening", "01:00")] {
+            let criteria = MatchCriteria {
+                active_hours: Some(TimeWindow {
+                    start: start.to_string(),
+                    end: end.to_string(),
+                    days: Vec::new(),
+                }),
+                ..Default::default()
+            };
+            assert!(criteria.validate().is_err(), "{}-{}", start, end);
+        }
+    }
 
     #[test]
     fn test_parse_json_config() {
@@ -561,4 +1822,134 @@ mod tests {
         assert!(config.global.enabled);
         assert_eq!(config.rules.len(), 1);
     }
+    
+    // Held by tests that set process environment variables.
+    static ENV_LOCK: parking_lot::Mutex<()> = parking_lot::Mutex::new(());
+    
+    #[test]
+    fn test_load_with_env() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, r#"
+        [[rules]]
+        name = "https"
+        transforms = ["fragment"]
+        
+        [rules.match_criteria]
+        dst_ports = [443]
+        "#).unwrap();
+        
+        let vars = [
+            ("TURKEYDPI_GLOBAL__ENABLE_JITTER", "true"),
+            ("TURKEYDPI_LIMITS__MAX_FLOWS", "50000"),
+            ("TURKEYDPI_TRANSFORMS__FRAGMENT__MAX_SIZE", "8"),
+            ("TURKEYDPI_RULES__0__MATCH_CRITERIA__DST_PORTS", "443, 8443"),
+            ("TURKEYDPI_RULES__0__MATCH_CRITERIA__SRC_PORTS", "5000"),
+        ];
+        let result = {
+            let _env = ENV_LOCK.lock();
+            for (name, value) in vars {
+                std::env::set_var(name, value);
+            }
+            let result = Config::load_with_env_report(Some(&path));
+            for (name, _) in vars {
+                std::env::remove_var(name);
+            }
+            result
+        };
+        
+        let (config, applied) = result.unwrap();
+        assert!(config.global.enable_jitter);
+        assert_eq!(config.limits.max_flows, 50000);
+        assert_eq!(config.transforms.fragment.max_size, 8);
+        assert_eq!(config.rules[0].match_criteria.dst_ports, Some(vec![443, 8443]));
+        assert_eq!(config.rules[0].match_criteria.src_ports, Some(vec![5000]));
+        assert_eq!(applied.len(), vars.len());
+    }
+    
+    #[test]
+    fn test_env_override_errors() {
+        let var = |name: &str, value: &str| vec![(name.to_string(), value.to_string())];
+        
+        let mut config = Config::default();
+        assert!(config.apply_env_overrides(var("TURKEYDPI_CONFIG", "/etc/turkeydpi.toml")).unwrap().is_empty());
+        
+        let err = config.apply_env_overrides(var("TURKEYDPI_LIMITS__MAX_FLOWZ", "1")).unwrap_err().to_string();
+        assert!(err.contains("TURKEYDPI_LIMITS__MAX_FLOWZ"), "{}", err);
+        
+        let err = config.apply_env_overrides(var("TURKEYDPI_LIMITS__MAX_FLOWS", "lots")).unwrap_err().to_string();
+        assert!(err.contains("invalid value for `TURKEYDPI_LIMITS__MAX_FLOWS`"), "{}", err);
+        assert_eq!(config.limits.max_flows, Limits::default().max_flows);
+    }
+    
+    #[test]
+    fn test_save_to_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::preset(crate::presets::ConfigPreset::Vodafone);
+        config.limits.max_flows = 4321;
+        
+        for (name, format) in [("config.toml", ConfigFormat::Toml), ("config.json", ConfigFormat::Json)] {
+            let path = dir.path().join(name);
+            config.save_to_file(&path, format).unwrap();
+            let loaded = Config::load_from_file(&path).unwrap();
+            assert_eq!(serde_json::to_value(&loaded).unwrap(), serde_json::to_value(&config).unwrap());
+        }
+        
+        let path = dir.path().join("config.toml");
+        let saved = std::fs::read(&path).unwrap();
+        
+        // TOML has no null, so this rule cannot be serialized.
+        let mut broken = config.clone();
+        broken.rules[0].overrides.insert("fragment.min_size".to_string(), serde_json::Value::Null);
+        assert!(broken.save_to_file(&path, ConfigFormat::Toml).is_err());
+        
+        let err = config.save_to_file(&path, ConfigFormat::Json).unwrap_err().to_string();
+        assert!(err.contains("would be loaded as toml, not json"), "{}", err);
+        
+        assert_eq!(std::fs::read(&path).unwrap(), saved);
+        let mut files: Vec<_> = std::fs::read_dir(dir.path()).unwrap().map(|e| e.unwrap().file_name()).collect();
+        files.sort();
+        assert_eq!(files, ["config.json", "config.toml"]);
+        
+        let bare = dir.path().join("turkeydpi");
+        config.save_to_file(&bare, ConfigFormat::of_path(&bare)).unwrap();
+        assert!(std::fs::read_to_string(&bare).unwrap().contains("\"limits\""));
+        assert!(Config::load_from_file(&bare).is_ok());
+        std::fs::remove_file(&bare).unwrap();
+        
+        assert_eq!("JSON".parse::<ConfigFormat>().unwrap(), ConfigFormat::Json);
+        assert!("yaml".parse::<ConfigFormat>().is_err());
+    }
+    
+    #[test]
+    fn test_rule_load_from_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let toml_path = dir.path().join("rule.toml");
+        std::fs::write(&toml_path, r#"
+        name = "https"
+        transforms = ["fragment"]
+        
+        [match_criteria]
+        dst_ports = [443]
+        "#).unwrap();
+        let json_path = dir.path().join("rule.json");
+        std::fs::write(&json_path, r#"{"name": "dns", "transforms": ["padding"], "match_criteria": {"dst_ports": [53]}}"#).unwrap();
+        
+        let rule = Rule::load_from_file(&toml_path).unwrap();
+        assert_eq!(rule.name, "https");
+        assert_eq!(rule.transforms, vec![TransformType::Fragment]);
+        assert_eq!(rule.match_criteria.dst_ports, Some(vec![443]));
+        
+        let rule = Rule::load_from_file(&json_path).unwrap();
+        assert_eq!(rule.name, "dns");
+        assert_eq!(rule.match_criteria.dst_ports, Some(vec![53]));
+        
+        // Anything that is not .toml is parsed as JSON.
+        let misnamed = dir.path().join("rule.conf");
+        std::fs::copy(&toml_path, &misnamed).unwrap();
+        assert!(Rule::load_from_file(&misnamed).is_err());
+        
+        std::fs::write(&json_path, r#"{"name": "", "transforms": ["padding"], "match_criteria": {}}"#).unwrap();
+        assert!(Rule::load_from_file(&json_path).is_err());
+    }
 }
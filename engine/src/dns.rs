@@ -1,11 +1,141 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::io::ErrorKind;
+use std::future::Future;
 use std::net::{IpAddr, SocketAddr};
-use std::sync::RwLock;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
 use std::time::{Duration, Instant};
 
+use bytes::{Buf, Bytes};
+use lru::LruCache;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, ReadBuf};
+use tokio::net::TcpStream;
+
+use crate::bypass::{BypassConfig, BypassEngine};
+use crate::config::{DnsConfig, DohFormat, DohProvider, FamilyPreference, Transport};
+
+const DNS_TYPE_A: u16 = 1;
+const DNS_TYPE_CNAME: u16 = 5;
+const DNS_TYPE_AAAA: u16 = 28;
+const DNS_CLASS_IN: u16 = 1;
+const DNS_STATUS_NXDOMAIN: u32 = 3;
+const MAX_CNAME_CHAIN: usize = 8;
+const MAX_NAME_POINTERS: usize = 128;
+const DOH_PORT: u16 = 443;
+const DOT_PORT: u16 = 853;
+const POOL_MAX_PER_PROVIDER: usize = 4;
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+type TlsConn = BufReader<tokio_native_tls::TlsStream<FragmentingStream<TcpStream>>>;
+type PoolKey = (String, SocketAddr);
+type ProviderQuery<'a> = Pin<Box<dyn Future<Output = std::io::Result<(Vec<IpAddr>, u32)>> + Send + 'a>>;
+
+#[derive(Debug, Error)]
+pub enum DohError {
+    #[error("DoH server returned HTTP {0}")]
+    HttpStatus(u16),
+    
+    #[error("DNS query failed with status {0}")]
+    DnsStatus(u32),
+    
+    #[error("Malformed DoH response: {0}")]
+    Malformed(String),
+}
+
+impl From<DohError> for std::io::Error {
+    fn from(err: DohError) -> Self {
+        let kind = match err {
+            DohError::DnsStatus(DNS_STATUS_NXDOMAIN) => ErrorKind::NotFound,
+            DohError::HttpStatus(_) | DohError::DnsStatus(_) => ErrorKind::Other,
+            DohError::Malformed(_) => ErrorKind::InvalidData,
+        };
+        std::io::Error::new(kind, err)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DnsJsonResponse {
+    status: u32,
+    #[serde(default)]
+    answer: Vec<DnsAnswer>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DnsAnswer {
+    name: String,
+    #[serde(rename = "type")]
+    record_type: u16,
+    #[serde(rename = "TTL", default)]
+    ttl: u32,
+    data: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub negative_hits: u64,
+    pub lookups: u64,
+    pub lookup_time_us: u64,
+}
+
+impl DnsCacheStats {
+    pub fn average_lookup_ms(&self) -> f64 {
+        if self.lookups == 0 {
+            0.0
+        } else {
+            self.lookup_time_us as f64 / self.lookups as f64 / 1000.0
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PooledConn {
+    stream: TlsConn,
+    idle_since: Instant,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsTransportStats {
+    pub doh_ok: u64,
+    pub doh_failed: u64,
+    pub dot_ok: u64,
+    pub dot_failed: u64,
+}
+
+#[derive(Debug)]
 pub struct DohResolver {
-    cache: RwLock<HashMap<String, (Vec<IpAddr>, Instant)>>,
-    ttl: Duration,
+    cache: Mutex<LruCache<String, (Vec<IpAddr>, Instant)>>,
+    negative_cache: RwLock<HashMap<String, Instant>>,
+    overrides: RwLock<HashMap<String, Vec<IpAddr>>>,
+    min_ttl: Duration,
+    max_ttl: Duration,
+    negative_ttl: Duration,
+    bind_addr: Option<IpAddr>,
+    providers: Vec<DohProvider>,
+    family: FamilyPreference,
+    bypass: Option<Arc<BypassEngine>>,
+    stagger: Duration,
+    deadline: Duration,
+    pool: Mutex<HashMap<PoolKey, Vec<PooledConn>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    negative_hits: AtomicU64,
+    lookups: AtomicU64,
+    lookup_time_us: AtomicU64,
+    doh_ok: AtomicU64,
+    doh_failed: AtomicU64,
+    dot_ok: AtomicU64,
+    dot_failed: AtomicU64,
 }
 
 impl Default for DohResolver {
@@ -16,42 +146,229 @@ impl Default for DohResolver {
 
 impl DohResolver {
     pub fn new() -> Self {
+        let defaults = DnsConfig::default();
+        Self {
+            cache: Mutex::new(LruCache::new(cache_capacity(defaults.max_cache_entries))),
+            negative_cache: RwLock::new(HashMap::new()),
+            overrides: RwLock::new(HashMap::new()),
+            min_ttl: Duration::from_secs(defaults.min_ttl_secs),
+            max_ttl: Duration::from_secs(defaults.max_ttl_secs),
+            negative_ttl: Duration::from_secs(defaults.negative_ttl_secs),
+            bind_addr: None,
+            providers: defaults.providers,
+            family: defaults.family_preference,
+            bypass: None,
+            stagger: Duration::from_millis(defaults.provider_stagger_ms),
+            deadline: Duration::from_millis(defaults.lookup_timeout_ms),
+            pool: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            negative_hits: AtomicU64::new(0),
+            lookups: AtomicU64::new(0),
+            lookup_time_us: AtomicU64::new(0),
+            doh_ok: AtomicU64::new(0),
+            doh_failed: AtomicU64::new(0),
+            dot_ok: AtomicU64::new(0),
+            dot_failed: AtomicU64::new(0),
+        }
+    }
+    
+    pub fn from_config(config: &DnsConfig) -> Self {
+        let resolver = Self::with_providers(config.providers.clone())
+            .with_cache_ttl(
+                Duration::from_secs(config.min_ttl_secs),
+                Duration::from_secs(config.max_ttl_secs),
+                Duration::from_secs(config.negative_ttl_secs),
+            )
+            .with_family_preference(config.family_preference)
+            .with_cache_capacity(config.max_cache_entries)
+            .with_race_timing(
+                Duration::from_millis(config.provider_stagger_ms),
+                Duration::from_millis(config.lookup_timeout_ms),
+            );
+        
+        if config.fragment_doh {
+            resolver.with_bypass_engine(Arc::new(BypassEngine::new(BypassConfig::default())))
+        } else {
+            resolver
+        }
+    }
+
+    pub fn with_providers(providers: Vec<DohProvider>) -> Self {
+        Self {
+            providers,
+            ..Self::new()
+        }
+    }
+
+    pub fn with_bind_addr(self, bind_addr: Option<IpAddr>) -> Self {
+        Self {
+            bind_addr,
+            ..self
+        }
+    }
+
+    pub fn with_cache_ttl(self, min_ttl: Duration, max_ttl: Duration, negative_ttl: Duration) -> Self {
+        Self {
+            min_ttl,
+            max_ttl: max_ttl.max(min_ttl),
+            negative_ttl,
+            ..self
+        }
+    }
+    
+    pub fn with_cache_capacity(self, max_entries: usize) -> Self {
+        Self {
+            cache: Mutex::new(LruCache::new(cache_capacity(max_entries))),
+            ..self
+        }
+    }
+    
+    pub fn with_bypass_engine(self, engine: Arc<BypassEngine>) -> Self {
+        Self {
+            bypass: Some(engine),
+            ..self
+        }
+    }
+    
+    pub fn with_race_timing(self, stagger: Duration, deadline: Duration) -> Self {
+        Self {
+            stagger,
+            deadline,
+            ..self
+        }
+    }
+    
+    pub fn with_family_preference(self, family: FamilyPreference) -> Self {
         Self {
-            cache: RwLock::new(HashMap::new()),
-            ttl: Duration::from_secs(300), 
+            family,
+            ..self
+        }
+    }
+    
+    pub fn family_preference(&self) -> FamilyPreference {
+        self.family
+    }
+    
+    pub fn providers(&self) -> &[DohProvider] {
+        &self.providers
+    }
+    
+    pub fn cache_stats(&self) -> DnsCacheStats {
+        DnsCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            negative_hits: self.negative_hits.load(Ordering::Relaxed),
+            lookups: self.lookups.load(Ordering::Relaxed),
+            lookup_time_us: self.lookup_time_us.load(Ordering::Relaxed),
         }
     }
+    
+    pub fn transport_stats(&self) -> DnsTransportStats {
+        DnsTransportStats {
+            doh_ok: self.doh_ok.load(Ordering::Relaxed),
+            doh_failed: self.doh_failed.load(Ordering::Relaxed),
+            dot_ok: self.dot_ok.load(Ordering::Relaxed),
+            dot_failed: self.dot_failed.load(Ordering::Relaxed),
+        }
+    }
+    
+    pub fn add_override(&self, host: &str, ips: Vec<IpAddr>) {
+        if let Ok(mut overrides) = self.overrides.write() {
+            overrides.entry(normalize_name(host)).or_default().extend(ips);
+        }
+    }
+    
+    pub fn load_hosts_file(&self, path: impl AsRef<Path>) -> std::io::Result<usize> {
+        let content = std::fs::read_to_string(path)?;
+        let entries = parse_hosts(&content);
+        let count = entries.len();
+        for (host, ip) in entries {
+            self.add_override(&host, vec![ip]);
+        }
+        Ok(count)
+    }
+    
+    fn get_override(&self, hostname: &str) -> Option<Vec<IpAddr>> {
+        self.overrides.read().ok()
+            .and_then(|overrides| overrides.get(&normalize_name(hostname)).cloned())
+    }
 
     pub async fn resolve(&self, hostname: &str) -> std::io::Result<Vec<IpAddr>> {
+        if let Some(ips) = self.get_override(hostname) {
+            return self.order_by_family(hostname, ips);
+        }
         
-        if let Some(ips) = self.get_cached(hostname) {
-            return Ok(ips);
+        let now = Instant::now();
+        if let Some(ips) = self.get_cached(hostname, now) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return self.order_by_family(hostname, ips);
         }
-
+        if self.is_negative(hostname, now) {
+            self.negative_hits.fetch_add(1, Ordering::Relaxed);
+            return Err(std::io::Error::new(
+                ErrorKind::NotFound,
+                format!("{} recently failed to resolve", hostname),
+            ));
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
         
-        let providers = [
-            ("1.1.1.1", "/dns-query"),           
-            ("8.8.8.8", "/resolve"),              
-            ("9.9.9.9", "/dns-query"),            
-        ];
-
-        for (server, path) in providers {
-            match self.doh_query(server, path, hostname).await {
-                Ok(ips) if !ips.is_empty() => {
-                    self.cache_result(hostname, &ips);
-                    return Ok(ips);
-                }
-                _ => continue,
+        let started = Instant::now();
+        let result = self.lookup(hostname).await;
+        self.lookups.fetch_add(1, Ordering::Relaxed);
+        self.lookup_time_us.fetch_add(started.elapsed().as_micros() as u64, Ordering::Relaxed);
+        result
+    }
+    
+    async fn lookup(&self, hostname: &str) -> std::io::Result<Vec<IpAddr>> {
+        let queries = self.providers.iter()
+            .map(|provider| Box::pin(self.query_families(provider, hostname)) as ProviderQuery<'_>)
+            .collect();
+        
+        let result = tokio::time::timeout(self.deadline, race_staggered(queries, self.stagger)).await
+            .map_err(|_| std::io::Error::new(
+                ErrorKind::TimedOut,
+                format!("DoH lookup for {} timed out", hostname),
+            ))?;
+        
+        match result {
+            Ok((ips, ttl)) if !ips.is_empty() => {
+                self.cache_result(hostname, &ips, ttl, Instant::now());
+                self.order_by_family(hostname, ips)
             }
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                self.cache_negative(hostname, Instant::now());
+                Err(e)
+            }
+            Ok(_) => {
+                self.cache_negative(hostname, Instant::now());
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("Failed to resolve {} via DoH", hostname),
+                ))
+            }
+            Err(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("Failed to resolve {} via DoH", hostname),
+            )),
         }
+    }
+    
+    pub async fn resolve_with_ttl(&self, hostname: &str) -> std::io::Result<(Vec<IpAddr>, u32)> {
+        let ips = self.resolve(hostname).await?;
+        let ttl = self.remaining_ttl(hostname, Instant::now()).unwrap_or(self.min_ttl);
+        Ok((ips, ttl.as_secs().clamp(1, u32::MAX as u64) as u32))
+    }
 
-        Err(std::io::Error::new(
+    pub async fn resolve_host_port(&self, host_port: &str) -> std::io::Result<SocketAddr> {
+        let addrs = self.resolve_host_port_all(host_port).await?;
+        addrs.first().copied().ok_or_else(|| std::io::Error::new(
             std::io::ErrorKind::NotFound,
-            format!("Failed to resolve {} via DoH", hostname),
+            "No IP addresses returned",
         ))
     }
 
-    pub async fn resolve_host_port(&self, host_port: &str) -> std::io::Result<SocketAddr> {
+    pub async fn resolve_host_port_all(&self, host_port: &str) -> std::io::Result<Vec<SocketAddr>> {
         let (host, port) = if let Some(idx) = host_port.rfind(':') {
             let port: u16 = host_port[idx + 1..].parse().map_err(|_| {
                 std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid port")
@@ -63,115 +380,741 @@ impl DohResolver {
 
         
         if let Ok(ip) = host.parse::<IpAddr>() {
-            return Ok(SocketAddr::new(ip, port));
+            return Ok(vec![SocketAddr::new(ip, port)]);
         }
 
         
         let ips = self.resolve(host).await?;
-        
-        
-        let ip = ips.iter()
-            .find(|ip| ip.is_ipv4())
-            .or(ips.first())
-            .ok_or_else(|| std::io::Error::new(
+        if ips.is_empty() {
+            return Err(std::io::Error::new(
                 std::io::ErrorKind::NotFound,
                 "No IP addresses returned",
-            ))?;
+            ));
+        }
 
-        Ok(SocketAddr::new(*ip, port))
+        Ok(ips.into_iter().map(|ip| SocketAddr::new(ip, port)).collect())
+    }
+    
+    pub fn flush(&self) -> usize {
+        let mut cache = self.cache.lock();
+        let flushed = cache.len();
+        cache.clear();
+        if let Ok(mut negative) = self.negative_cache.write() {
+            negative.clear();
+        }
+        flushed
+    }
+    
+    pub fn dump(&self) -> Vec<(String, Vec<IpAddr>, Duration)> {
+        let now = Instant::now();
+        let cache = self.cache.lock();
+        let mut entries: Vec<_> = cache.iter()
+            .filter_map(|(host, (ips, expiry))| {
+                expiry.checked_duration_since(now).map(|remaining| (host.clone(), ips.clone(), remaining))
+            })
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        entries
     }
 
-    fn get_cached(&self, hostname: &str) -> Option<Vec<IpAddr>> {
-        let cache = self.cache.read().ok()?;
+    fn get_cached(&self, hostname: &str, now: Instant) -> Option<Vec<IpAddr>> {
+        let mut cache = self.cache.lock();
         let (ips, expiry) = cache.get(hostname)?;
-        if Instant::now() < *expiry {
+        if now < *expiry {
             Some(ips.clone())
         } else {
+            cache.pop(hostname);
             None
         }
     }
+    
+    fn remaining_ttl(&self, hostname: &str, now: Instant) -> Option<Duration> {
+        let cache = self.cache.lock();
+        let (_, expiry) = cache.peek(hostname)?;
+        expiry.checked_duration_since(now)
+    }
 
-    fn cache_result(&self, hostname: &str, ips: &[IpAddr]) {
-        if let Ok(mut cache) = self.cache.write() {
-            cache.insert(
-                hostname.to_string(),
-                (ips.to_vec(), Instant::now() + self.ttl),
-            );
+    fn is_negative(&self, hostname: &str, now: Instant) -> bool {
+        self.negative_cache.read().ok()
+            .and_then(|cache| cache.get(hostname).copied())
+            .is_some_and(|expiry| now < expiry)
+    }
+
+    fn cache_result(&self, hostname: &str, ips: &[IpAddr], ttl: u32, now: Instant) {
+        let ttl = Duration::from_secs(ttl as u64).clamp(self.min_ttl, self.max_ttl);
+        self.cache.lock().put(hostname.to_string(), (ips.to_vec(), now + ttl));
+        if let Ok(mut negative) = self.negative_cache.write() {
+            negative.remove(hostname);
+        }
+    }
+
+    fn cache_negative(&self, hostname: &str, now: Instant) {
+        if self.negative_ttl.is_zero() {
+            return;
+        }
+        if let Ok(mut negative) = self.negative_cache.write() {
+            negative.retain(|_, expiry| now < *expiry);
+            negative.insert(hostname.to_string(), now + self.negative_ttl);
         }
     }
 
-    async fn doh_query(&self, server: &str, path: &str, hostname: &str) -> std::io::Result<Vec<IpAddr>> {
-        use tokio::io::{AsyncReadExt, AsyncWriteExt};
-        use tokio::net::TcpStream;
+    fn order_by_family(&self, hostname: &str, ips: Vec<IpAddr>) -> std::io::Result<Vec<IpAddr>> {
+        let (v4, v6): (Vec<IpAddr>, Vec<IpAddr>) = ips.into_iter().partition(|ip| ip.is_ipv4());
+        let ordered = match self.family {
+            FamilyPreference::V4First => v4.into_iter().chain(v6).collect(),
+            FamilyPreference::V6First => v6.into_iter().chain(v4).collect(),
+            FamilyPreference::V4Only => v4,
+            FamilyPreference::V6Only => v6,
+        };
+        
+        if ordered.is_empty() {
+            return Err(std::io::Error::new(
+                ErrorKind::NotFound,
+                format!("{} has no {:?} addresses", hostname, self.family),
+            ));
+        }
+        Ok(ordered)
+    }
 
+    async fn query_families(&self, provider: &DohProvider, hostname: &str) -> std::io::Result<(Vec<IpAddr>, u32)> {
+        let (v4, v6) = match self.family {
+            FamilyPreference::V4Only => (self.query_provider(provider, hostname, DNS_TYPE_A).await, Ok((Vec::new(), 0))),
+            FamilyPreference::V6Only => (Ok((Vec::new(), 0)), self.query_provider(provider, hostname, DNS_TYPE_AAAA).await),
+            FamilyPreference::V4First | FamilyPreference::V6First => tokio::join!(
+                self.query_provider(provider, hostname, DNS_TYPE_A),
+                self.query_provider(provider, hostname, DNS_TYPE_AAAA),
+            ),
+        };
+        
+        match (v4, v6) {
+            (Err(e), _) | (_, Err(e)) if e.kind() == ErrorKind::NotFound => Err(e),
+            (Ok(v4), Ok(v6)) => Ok(merge_answers(v4, v6)),
+            (Ok(answer), Err(e)) | (Err(e), Ok(answer)) => {
+                if answer.0.is_empty() {
+                    Err(e)
+                } else {
+                    Ok(answer)
+                }
+            }
+            (Err(e), Err(_)) => Err(e),
+        }
+    }
+
+    async fn query_provider(&self, provider: &DohProvider, hostname: &str, record_type: u16) -> std::io::Result<(Vec<IpAddr>, u32)> {
+        let mut last_error = std::io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("DNS provider {} has no transports", provider.host),
+        );
+        
+        for &transport in &provider.transports {
+            let result = match transport {
+                Transport::Doh => self.doh_query(provider, hostname, record_type).await,
+                Transport::Dot => self.dot_query(provider, hostname, record_type).await,
+            };
+            let (ok, failed) = match transport {
+                Transport::Doh => (&self.doh_ok, &self.doh_failed),
+                Transport::Dot => (&self.dot_ok, &self.dot_failed),
+            };
+            
+            match result {
+                Err(e) if e.kind() != ErrorKind::NotFound => {
+                    failed.fetch_add(1, Ordering::Relaxed);
+                    last_error = e;
+                }
+                answered => {
+                    ok.fetch_add(1, Ordering::Relaxed);
+                    return answered;
+                }
+            }
+        }
+        
+        Err(last_error)
+    }
+    
+    async fn checkout(&self, provider: &DohProvider, port: u16) -> std::io::Result<(TlsConn, bool)> {
+        let ip = provider.ip.ok_or_else(|| std::io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("DoH provider {} has no IP address", provider.host),
+        ))?;
+        let key = (provider.host.clone(), SocketAddr::new(ip, port));
+        
+        let pooled = {
+            let mut pool = self.pool.lock();
+            let conns = pool.entry(key.clone()).or_default();
+            conns.retain(|conn| conn.idle_since.elapsed() < POOL_IDLE_TIMEOUT);
+            conns.pop()
+        };
+        if let Some(conn) = pooled {
+            return Ok((conn.stream, true));
+        }
         
-        let addr: SocketAddr = format!("{}:443", server).parse().unwrap();
+        Ok((BufReader::new(self.connect_tls(&provider.host, key.1).await?), false))
+    }
+    
+    fn checkin(&self, provider: &DohProvider, port: u16, stream: TlsConn) {
+        let Some(ip) = provider.ip else {
+            return;
+        };
+        let mut pool = self.pool.lock();
+        let conns = pool.entry((provider.host.clone(), SocketAddr::new(ip, port))).or_default();
+        if conns.len() < POOL_MAX_PER_PROVIDER {
+            conns.push(PooledConn {
+                stream,
+                idle_since: Instant::now(),
+            });
+        }
+    }
+    
+    async fn connect_tls(
+        &self,
+        host: &str,
+        addr: SocketAddr,
+    ) -> std::io::Result<tokio_native_tls::TlsStream<FragmentingStream<TcpStream>>> {
+        use tokio::net::TcpSocket;
+        
+        let connect = async {
+            match self.bind_addr {
+                Some(bind_addr) => {
+                    if bind_addr.is_ipv4() != addr.is_ipv4() {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            format!("bind address {} cannot reach DoH server {}", bind_addr, addr),
+                        ));
+                    }
+                    let socket = if addr.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
+                    socket.bind(SocketAddr::new(bind_addr, 0))?;
+                    socket.connect(addr).await
+                }
+                None => TcpStream::connect(addr).await,
+            }
+        };
         
         let stream = tokio::time::timeout(
             Duration::from_secs(5),
-            TcpStream::connect(addr)
+            connect
         ).await
             .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "DoH connect timeout"))?
             .map_err(|e| std::io::Error::new(std::io::ErrorKind::ConnectionRefused, e))?;
-
+        if self.bypass.is_some() {
+            stream.set_nodelay(true)?;
+        }
+        let stream = FragmentingStream::new(stream, self.bypass.clone());
         
         let connector = tokio_native_tls::TlsConnector::from(
             native_tls::TlsConnector::new()
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+                .map_err(std::io::Error::other)?
         );
-
-        let mut tls_stream = tokio::time::timeout(
+        
+        tokio::time::timeout(
             Duration::from_secs(5),
-            connector.connect(server, stream)
+            connector.connect(host, stream)
         ).await
             .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "TLS timeout"))?
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-
+            .map_err(std::io::Error::other)
+    }
+    
+    async fn doh_query(&self, provider: &DohProvider, hostname: &str, record_type: u16) -> std::io::Result<(Vec<IpAddr>, u32)> {
+        let request = match provider.format {
+            DohFormat::Json => format!(
+                "GET {}?name={}&type={} HTTP/1.1\r\n\
+                 Host: {}\r\n\
+                 Accept: application/dns-json\r\n\
+                 Connection: keep-alive\r\n\r\n",
+                provider.path, hostname, if record_type == DNS_TYPE_AAAA { "AAAA" } else { "A" }, provider.host
+            ),
+        };
         
-        let request = format!(
-            "GET {}?name={}&type=A HTTP/1.1\r\n\
-             Host: {}\r\n\
-             Accept: application/dns-json\r\n\
-             Connection: close\r\n\r\n",
-            path, hostname, server
-        );
+        loop {
+            let (mut stream, reused) = self.checkout(provider, DOH_PORT).await?;
+            let exchange = async {
+                stream.write_all(request.as_bytes()).await?;
+                stream.flush().await?;
+                read_http_response(&mut stream).await
+            };
+            
+            match tokio::time::timeout(QUERY_TIMEOUT, exchange).await {
+                Ok(Ok((response, keep_alive))) => {
+                    if keep_alive {
+                        self.checkin(provider, DOH_PORT, stream);
+                    }
+                    return self.parse_doh_response(hostname, &response);
+                }
+                Ok(Err(_)) if reused => continue,
+                Ok(Err(e)) => return Err(e),
+                Err(_) => return Err(std::io::Error::new(ErrorKind::TimedOut, "DoH response timeout")),
+            }
+        }
+    }
+    
+    async fn dot_query(&self, provider: &DohProvider, hostname: &str, record_type: u16) -> std::io::Result<(Vec<IpAddr>, u32)> {
+        let id = query_id();
+        let query = build_wire_query(id, hostname, record_type)?;
+        let mut framed = Vec::with_capacity(query.len() + 2);
+        framed.extend_from_slice(&(query.len() as u16).to_be_bytes());
+        framed.extend_from_slice(&query);
+        
+        loop {
+            let (mut stream, reused) = self.checkout(provider, DOT_PORT).await?;
+            let exchange = async {
+                stream.write_all(&framed).await?;
+                stream.flush().await?;
+                let mut len = [0u8; 2];
+                stream.read_exact(&mut len).await?;
+                let mut response = vec![0u8; u16::from_be_bytes(len) as usize];
+                stream.read_exact(&mut response).await?;
+                Ok::<_, std::io::Error>(response)
+            };
+            
+            match tokio::time::timeout(QUERY_TIMEOUT, exchange).await {
+                Ok(Ok(response)) => {
+                    self.checkin(provider, DOT_PORT, stream);
+                    return parse_wire_response(id, hostname, &response);
+                }
+                Ok(Err(_)) if reused => continue,
+                Ok(Err(e)) => return Err(e),
+                Err(_) => return Err(std::io::Error::new(ErrorKind::TimedOut, "DoT response timeout")),
+            }
+        }
+    }
+    
+    fn parse_doh_response(&self, hostname: &str, response: &[u8]) -> std::io::Result<(Vec<IpAddr>, u32)> {
+        let (status, headers, body) = split_http_response(response)?;
+        if status != 200 {
+            return Err(DohError::HttpStatus(status).into());
+        }
+        
+        let chunked = header_value(&headers, "transfer-encoding")
+            .is_some_and(|v| v.to_ascii_lowercase().contains("chunked"));
+        let body = if chunked {
+            decode_chunked(body)?
+        } else if let Some(length) = header_value(&headers, "content-length") {
+            let length: usize = length.trim().parse()
+                .map_err(|_| DohError::Malformed(format!("invalid Content-Length {}", length)))?;
+            body.get(..length)
+                .ok_or_else(|| DohError::Malformed("body shorter than Content-Length".to_string()))?
+                .to_vec()
+        } else {
+            body.to_vec()
+        };
+        
+        let dns: DnsJsonResponse = serde_json::from_slice(&body)
+            .map_err(|e| DohError::Malformed(e.to_string()))?;
+        if dns.status != 0 {
+            return Err(DohError::DnsStatus(dns.status).into());
+        }
+        
+        Ok(follow_answers(hostname, &dns.answer))
+    }
+}
 
-        tls_stream.write_all(request.as_bytes()).await?;
-        tls_stream.flush().await?;
+fn query_id() -> u16 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+    (nanos as u64 ^ COUNTER.fetch_add(1, Ordering::Relaxed)) as u16
+}
 
+fn build_wire_query(id: u16, hostname: &str, record_type: u16) -> std::io::Result<Vec<u8>> {
+    let mut query = Vec::with_capacity(hostname.len() + 18);
+    query.extend_from_slice(&id.to_be_bytes());
+    query.extend_from_slice(&[0x01, 0x00]);
+    query.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    
+    for label in hostname.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid hostname {}", hostname),
+            ));
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&record_type.to_be_bytes());
+    query.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+fn parse_wire_response(id: u16, hostname: &str, message: &[u8]) -> std::io::Result<(Vec<IpAddr>, u32)> {
+    let malformed = |what: &str| -> std::io::Error { DohError::Malformed(what.to_string()).into() };
+    
+    if message.len() < 12 {
+        return Err(malformed("DNS message shorter than header"));
+    }
+    if u16::from_be_bytes([message[0], message[1]]) != id {
+        return Err(malformed("DNS response id does not match query"));
+    }
+    if message[2] & 0x80 == 0 {
+        return Err(malformed("DNS message is not a response"));
+    }
+    let rcode = (message[3] & 0x0F) as u32;
+    if rcode != 0 {
+        return Err(DohError::DnsStatus(rcode).into());
+    }
+    
+    let questions = u16::from_be_bytes([message[4], message[5]]);
+    let answer_count = u16::from_be_bytes([message[6], message[7]]);
+    let mut offset = 12;
+    
+    for _ in 0..questions {
+        let (_, next) = read_wire_name(message, offset)?;
+        offset = next + 4;
+    }
+    
+    let mut answers = Vec::with_capacity(answer_count as usize);
+    for _ in 0..answer_count {
+        let (name, next) = read_wire_name(message, offset)?;
+        let fixed = message.get(next..next + 10).ok_or_else(|| malformed("truncated resource record"))?;
+        let record_type = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let ttl = u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]);
+        let rdlen = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+        let rdata_start = next + 10;
+        let rdata = message.get(rdata_start..rdata_start + rdlen).ok_or_else(|| malformed("truncated record data"))?;
         
-        let mut response = Vec::new();
-        tls_stream.read_to_end(&mut response).await?;
+        let data = match (record_type, rdlen) {
+            (DNS_TYPE_A, 4) => Some(IpAddr::from([rdata[0], rdata[1], rdata[2], rdata[3]]).to_string()),
+            (DNS_TYPE_AAAA, 16) => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(rdata);
+                Some(IpAddr::from(octets).to_string())
+            }
+            (DNS_TYPE_CNAME, _) => Some(read_wire_name(message, rdata_start)?.0),
+            _ => None,
+        };
+        if let Some(data) = data {
+            answers.push(DnsAnswer { name, record_type, ttl, data });
+        }
+        offset = rdata_start + rdlen;
+    }
+    
+    Ok(follow_answers(hostname, &answers))
+}
+
+fn read_wire_name(message: &[u8], mut offset: usize) -> std::io::Result<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    
+    for _ in 0..MAX_NAME_POINTERS {
+        let len = *message.get(offset).ok_or_else(|| DohError::Malformed("truncated name".to_string()))? as usize;
+        if len & 0xC0 == 0xC0 {
+            let low = *message.get(offset + 1).ok_or_else(|| DohError::Malformed("truncated name pointer".to_string()))?;
+            end.get_or_insert(offset + 2);
+            offset = ((len & 0x3F) << 8) | low as usize;
+            continue;
+        }
+        if len == 0 {
+            return Ok((labels.join("."), end.unwrap_or(offset + 1)));
+        }
+        let label = message.get(offset + 1..offset + 1 + len)
+            .ok_or_else(|| DohError::Malformed("truncated label".to_string()))?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        offset += 1 + len;
+    }
+    
+    Err(DohError::Malformed("too many name compression pointers".to_string()).into())
+}
+
+/// Passes the first write (the ClientHello) through a [`BypassEngine`] and sends
+/// each resulting fragment as its own write.
+#[derive(Debug)]
+struct FragmentingStream<S> {
+    inner: S,
+    engine: Option<Arc<BypassEngine>>,
+    pending: VecDeque<Bytes>,
+}
+
+impl<S: AsyncWrite + Unpin> FragmentingStream<S> {
+    fn new(inner: S, engine: Option<Arc<BypassEngine>>) -> Self {
+        Self {
+            inner,
+            engine,
+            pending: VecDeque::new(),
+        }
+    }
+    
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        while let Some(fragment) = self.pending.front_mut() {
+            let n = std::task::ready!(Pin::new(&mut self.inner).poll_write(cx, fragment))?;
+            if n == 0 {
+                return Poll::Ready(Err(std::io::Error::from(ErrorKind::WriteZero)));
+            }
+            fragment.advance(n);
+            if fragment.is_empty() {
+                self.pending.pop_front();
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for FragmentingStream<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
 
+impl<S: AsyncWrite + Unpin> AsyncWrite for FragmentingStream<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        std::task::ready!(self.poll_drain(cx))?;
         
-        let response_str = String::from_utf8_lossy(&response);
-        self.parse_doh_response(&response_str)
+        let Some(engine) = self.engine.take() else {
+            return Pin::new(&mut self.inner).poll_write(cx, buf);
+        };
+        let result = engine.process_outgoing(buf);
+        if result.drop || result.fragments.is_empty() {
+            self.pending.push_back(Bytes::copy_from_slice(buf));
+        } else {
+            self.pending.extend(result.fragments);
+        }
+        
+        if let Poll::Ready(Err(e)) = self.poll_drain(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+    
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        std::task::ready!(self.poll_drain(cx))?;
+        Pin::new(&mut self.inner).poll_flush(cx)
     }
+    
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        std::task::ready!(self.poll_drain(cx))?;
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
 
-    fn parse_doh_response(&self, response: &str) -> std::io::Result<Vec<IpAddr>> {
+async fn race_staggered(queries: Vec<ProviderQuery<'_>>, stagger: Duration) -> std::io::Result<(Vec<IpAddr>, u32)> {
+    let mut pending: Vec<ProviderQuery<'_>> = queries.into_iter()
+        .enumerate()
+        .map(|(i, query)| Box::pin(async move {
+            if i > 0 {
+                tokio::time::sleep(stagger * i as u32).await;
+            }
+            query.await
+        }) as ProviderQuery<'_>)
+        .collect();
+    
+    let mut empty = false;
+    let mut last_error = None;
+    while !pending.is_empty() {
+        let (index, result) = std::future::poll_fn(|cx| {
+            for (i, query) in pending.iter_mut().enumerate() {
+                if let Poll::Ready(result) = query.as_mut().poll(cx) {
+                    return Poll::Ready((i, result));
+                }
+            }
+            Poll::Pending
+        }).await;
+        drop(pending.remove(index));
         
-        let body = response.split("\r\n\r\n").nth(1).unwrap_or("");
+        match result {
+            Ok((ips, ttl)) if !ips.is_empty() => return Ok((ips, ttl)),
+            Ok(_) => empty = true,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Err(e),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    
+    if empty {
+        return Ok((Vec::new(), 0));
+    }
+    Err(last_error.unwrap_or_else(|| std::io::Error::new(ErrorKind::NotFound, "no DoH providers configured")))
+}
+
+fn merge_answers(first: (Vec<IpAddr>, u32), second: (Vec<IpAddr>, u32)) -> (Vec<IpAddr>, u32) {
+    if first.0.is_empty() {
+        return second;
+    }
+    if second.0.is_empty() {
+        return first;
+    }
+    
+    let (mut ips, ttl) = first;
+    ips.extend(second.0);
+    (ips, ttl.min(second.1))
+}
+
+fn cache_capacity(max_entries: usize) -> NonZeroUsize {
+    NonZeroUsize::new(max_entries).unwrap_or(NonZeroUsize::MIN)
+}
+
+fn parse_hosts(content: &str) -> Vec<(String, IpAddr)> {
+    let mut entries = Vec::new();
+    for line in content.lines() {
+        let line = line.split('#').next().unwrap_or("");
+        let mut fields = line.split_whitespace();
+        let Some(Ok(ip)) = fields.next().map(|field| field.parse::<IpAddr>()) else {
+            continue;
+        };
+        entries.extend(fields.map(|host| (normalize_name(host), ip)));
+    }
+    entries
+}
+
+fn normalize_name(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn follow_answers(hostname: &str, answers: &[DnsAnswer]) -> (Vec<IpAddr>, u32) {
+    let mut current = normalize_name(hostname);
+    let mut ttl = u32::MAX;
+    
+    for _ in 0..MAX_CNAME_CHAIN {
+        let records: Vec<&DnsAnswer> = answers.iter()
+            .filter(|a| matches!(a.record_type, DNS_TYPE_A | DNS_TYPE_AAAA))
+            .filter(|a| normalize_name(&a.name) == current)
+            .collect();
+        let ips: Vec<IpAddr> = records.iter().filter_map(|a| a.data.parse().ok()).collect();
+        if !ips.is_empty() {
+            let min_ttl = records.iter().map(|a| a.ttl).min().unwrap_or(0);
+            return (ips, ttl.min(min_ttl));
+        }
         
-        let mut ips = Vec::new();
+        match answers.iter().find(|a| a.record_type == DNS_TYPE_CNAME && normalize_name(&a.name) == current) {
+            Some(cname) => {
+                ttl = ttl.min(cname.ttl);
+                current = normalize_name(&cname.data);
+            }
+            None => break,
+        }
+    }
+    
+    (Vec::new(), 0)
+}
+
+type HttpResponse<'a> = (u16, Vec<(String, String)>, &'a [u8]);
+
+fn split_http_response(response: &[u8]) -> std::io::Result<HttpResponse<'_>> {
+    let Some(head_end) = find_subsequence(response, b"\r\n\r\n") else {
+        return Err(DohError::Malformed("incomplete HTTP response".to_string()).into());
+    };
+    let body_start = head_end + 4;
+    
+    let head = String::from_utf8_lossy(&response[..head_end]);
+    let mut lines = head.lines();
+    let status = lines.next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| DohError::Malformed("invalid HTTP status line".to_string()))?;
+    
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        .collect();
+    
+    Ok((status, headers, &response[body_start..]))
+}
+
+async fn read_http_response<S: AsyncBufRead + Unpin>(stream: &mut S) -> std::io::Result<(Vec<u8>, bool)> {
+    let mut response = Vec::new();
+    
+    loop {
+        let available = stream.fill_buf().await?;
+        if available.is_empty() {
+            if response.is_empty() {
+                return Err(std::io::Error::new(ErrorKind::UnexpectedEof, "DoH server closed the connection"));
+            }
+            return Ok((response, false));
+        }
         
+        let read = available.len();
+        let previous = response.len();
+        response.extend_from_slice(available);
         
+        if let Some((length, keep_alive)) = framed_length(&response)? {
+            stream.consume(length.saturating_sub(previous));
+            response.truncate(length);
+            return Ok((response, keep_alive));
+        }
+        stream.consume(read);
+    }
+}
+
+fn framed_length(response: &[u8]) -> std::io::Result<Option<(usize, bool)>> {
+    if find_subsequence(response, b"\r\n\r\n").is_none() {
+        return Ok(None);
+    }
+    
+    let (_, headers, body) = split_http_response(response)?;
+    let body_start = response.len() - body.len();
+    let keep_alive = !header_value(&headers, "connection")
+        .is_some_and(|v| v.eq_ignore_ascii_case("close"));
+    
+    let chunked = header_value(&headers, "transfer-encoding")
+        .is_some_and(|v| v.to_ascii_lowercase().contains("chunked"));
+    if chunked {
+        return Ok(chunked_length(body).map(|length| (body_start + length, keep_alive)));
+    }
+    
+    match header_value(&headers, "content-length").and_then(|v| v.trim().parse::<usize>().ok()) {
+        Some(length) if body.len() >= length => Ok(Some((body_start + length, keep_alive))),
+        _ => Ok(None),
+    }
+}
+
+fn chunked_length(body: &[u8]) -> Option<usize> {
+    let mut offset = 0;
+    
+    loop {
+        let line_end = offset + find_subsequence(&body[offset..], b"\r\n")?;
+        let size_line = String::from_utf8_lossy(&body[offset..line_end]);
+        let size = usize::from_str_radix(size_line.split(';').next()?.trim(), 16).ok()?;
+        offset = line_end + 2;
         
-        for part in body.split("\"data\"") {
-            if let Some(start) = part.find(":\"") {
-                let rest = &part[start + 2..];
-                if let Some(end) = rest.find('"') {
-                    let ip_str = &rest[..end];
-                    if let Ok(ip) = ip_str.parse::<IpAddr>() {
-                        ips.push(ip);
-                    }
+        if size == 0 {
+            loop {
+                let trailer_end = offset + find_subsequence(&body[offset..], b"\r\n")?;
+                let last = trailer_end == offset;
+                offset = trailer_end + 2;
+                if last {
+                    return Some(offset);
                 }
             }
         }
+        
+        offset += size + 2;
+        if offset > body.len() {
+            return None;
+        }
+    }
+}
+
+fn header_value<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers.iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, v)| v.as_str())
+}
 
-        Ok(ips)
+fn decode_chunked(mut body: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    
+    loop {
+        let line_end = find_subsequence(body, b"\r\n")
+            .ok_or_else(|| DohError::Malformed("truncated chunk size".to_string()))?;
+        let size_line = String::from_utf8_lossy(&body[..line_end]);
+        let size_hex = size_line.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size_hex, 16)
+            .map_err(|_| DohError::Malformed(format!("invalid chunk size {:?}", size_hex)))?;
+        body = &body[line_end + 2..];
+        
+        if size == 0 {
+            return Ok(decoded);
+        }
+        
+        let chunk = body.get(..size)
+            .ok_or_else(|| DohError::Malformed("truncated chunk".to_string()))?;
+        decoded.extend_from_slice(chunk);
+        body = body.get(size + 2..).unwrap_or(&[]);
     }
 }
 
+fn find_subsequence(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -182,21 +1125,446 @@ mod tests {
         let response = r#"HTTP/1.1 200 OK
 Content-Type: application/dns-json
 
-{"Status":0,"Answer":[{"name":"discord.com","type":1,"TTL":300,"data":"162.159.130.234"},{"name":"discord.com","type":1,"TTL":300,"data":"162.159.129.234"}]}"#;
+{"Status":0,"Answer":[{"name":"discord.com","type":1,"TTL":300,"data":"162.159.130.234"},{"name":"discord.com","type":1,"TTL":300,"data":"162.159.129.234"}]}"#.replace('\n', "\r\n");
         
-        let ips = resolver.parse_doh_response(response).unwrap();
-        assert!(!ips.is_empty());
+        let (ips, _) = resolver.parse_doh_response("discord.com", response.as_bytes()).unwrap();
+        assert_eq!(ips.len(), 2);
         assert!(ips.iter().any(|ip| ip.to_string().starts_with("162.159")));
     }
 
+    #[tokio::test]
+    async fn test_resolve_host_port_all_literal() {
+        let resolver = DohResolver::new();
+        let addrs = resolver.resolve_host_port_all("192.0.2.1:8443").await.unwrap();
+        assert_eq!(addrs, vec!["192.0.2.1:8443".parse::<SocketAddr>().unwrap()]);
+        
+        let addr = resolver.resolve_host_port("192.0.2.1").await.unwrap();
+        assert_eq!(addr.port(), 443);
+    }
+
     #[test]
     fn test_parse_google_response() {
         let resolver = DohResolver::new();
         let response = r#"HTTP/1.1 200 OK
 
-{"Status":0,"Answer":[{"name":"discord.com.","type":1,"TTL":60,"data":"162.159.130.234"}]}"#;
+{"Status":0,"Answer":[{"name":"discord.com.","type":1,"TTL":60,"data":"162.159.130.234"}]}"#.replace('\n', "\r\n");
         
-        let ips = resolver.parse_doh_response(response).unwrap();
+        let (ips, _) = resolver.parse_doh_response("discord.com", response.as_bytes()).unwrap();
         assert!(!ips.is_empty());
     }
+
+    #[test]
+    fn test_parse_chunked_response() {
+        let resolver = DohResolver::new();
+        let body = r#"{"Status":0,"Answer":[{"name":"discord.com.","type":1,"TTL":60,"data":"162.159.130.234"}]}"#;
+        let (head, tail) = body.split_at(20);
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n{}\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+            head.len(), head, tail.len(), tail
+        );
+        
+        let (ips, _) = resolver.parse_doh_response("discord.com", response.as_bytes()).unwrap();
+        assert_eq!(ips, vec!["162.159.130.234".parse::<IpAddr>().unwrap()]);
+    }
+
+    #[test]
+    fn test_parse_content_length_response() {
+        let resolver = DohResolver::new();
+        let body = r#"{"Status":0,"Answer":[{"name":"discord.com","type":1,"TTL":60,"data":"162.159.130.234"}]}"#;
+        let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}trailing", body.len(), body);
+        
+        let (ips, _) = resolver.parse_doh_response("discord.com", response.as_bytes()).unwrap();
+        assert_eq!(ips.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_follows_cname_chain() {
+        let resolver = DohResolver::new();
+        let response = "HTTP/1.1 200 OK\r\n\r\n{\"Status\":0,\"Answer\":[\
+            {\"name\":\"www.example.com.\",\"type\":5,\"TTL\":60,\"data\":\"cdn.example.net.\"},\
+            {\"name\":\"cdn.example.net.\",\"type\":5,\"TTL\":45,\"data\":\"edge.example.net.\"},\
+            {\"name\":\"edge.example.net.\",\"type\":1,\"TTL\":60,\"data\":\"192.0.2.10\"},\
+            {\"name\":\"edge.example.net.\",\"type\":28,\"TTL\":60,\"data\":\"2001:db8::10\"},\
+            {\"name\":\"other.example.org.\",\"type\":1,\"TTL\":60,\"data\":\"198.51.100.1\"}]}";
+        
+        let (ips, ttl) = resolver.parse_doh_response("www.example.com", response.as_bytes()).unwrap();
+        assert_eq!(ips, vec![
+            "192.0.2.10".parse::<IpAddr>().unwrap(),
+            "2001:db8::10".parse::<IpAddr>().unwrap(),
+        ]);
+        assert_eq!(ttl, 45);
+    }
+
+    #[test]
+    fn test_cache_honors_clamped_ttl() {
+        let resolver = DohResolver::new().with_cache_ttl(
+            Duration::from_secs(10),
+            Duration::from_secs(100),
+            Duration::from_secs(30),
+        );
+        let ips = vec!["192.0.2.1".parse::<IpAddr>().unwrap()];
+        let now = Instant::now();
+        
+        resolver.cache_result("short.example", &ips, 60, now);
+        assert!(resolver.get_cached("short.example", now + Duration::from_secs(59)).is_some());
+        assert!(resolver.get_cached("short.example", now + Duration::from_secs(61)).is_none());
+        
+        resolver.cache_result("tiny.example", &ips, 1, now);
+        assert!(resolver.get_cached("tiny.example", now + Duration::from_secs(9)).is_some());
+        assert!(resolver.get_cached("tiny.example", now + Duration::from_secs(11)).is_none());
+        
+        resolver.cache_result("long.example", &ips, 86400, now);
+        assert!(resolver.get_cached("long.example", now + Duration::from_secs(99)).is_some());
+        assert!(resolver.get_cached("long.example", now + Duration::from_secs(101)).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_family_preference_orders_cached_addresses() {
+        let v4: IpAddr = "192.0.2.1".parse().unwrap();
+        let v6: IpAddr = "2001:db8::1".parse().unwrap();
+        let v4b: IpAddr = "192.0.2.2".parse().unwrap();
+        
+        let cases = [
+            (FamilyPreference::V4First, vec![v4, v4b, v6]),
+            (FamilyPreference::V6First, vec![v6, v4, v4b]),
+            (FamilyPreference::V4Only, vec![v4, v4b]),
+            (FamilyPreference::V6Only, vec![v6]),
+        ];
+        for (family, expected) in cases {
+            let resolver = DohResolver::new().with_family_preference(family);
+            resolver.cache_result("dual.example", &[v4, v6, v4b], 60, Instant::now());
+            assert_eq!(resolver.resolve("dual.example").await.unwrap(), expected, "{:?}", family);
+            
+            let first = resolver.resolve_host_port("dual.example:443").await.unwrap();
+            assert_eq!(first, SocketAddr::new(expected[0], 443));
+        }
+        
+        let resolver = DohResolver::new().with_family_preference(FamilyPreference::V6Only);
+        resolver.cache_result("v4only.example", &[v4], 60, Instant::now());
+        let err = resolver.resolve("v4only.example").await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_merge_answers_keeps_nonempty_ttl() {
+        let v4: IpAddr = "192.0.2.1".parse().unwrap();
+        let v6: IpAddr = "2001:db8::1".parse().unwrap();
+        
+        assert_eq!(merge_answers((vec![v4], 300), (Vec::new(), 0)), (vec![v4], 300));
+        assert_eq!(merge_answers((Vec::new(), 0), (vec![v6], 60)), (vec![v6], 60));
+        assert_eq!(merge_answers((vec![v4], 300), (vec![v6], 60)), (vec![v4, v6], 60));
+    }
+
+    #[test]
+    fn test_negative_cache_expires() {
+        let resolver = DohResolver::new().with_cache_ttl(
+            Duration::from_secs(10),
+            Duration::from_secs(100),
+            Duration::from_secs(30),
+        );
+        let now = Instant::now();
+        
+        resolver.cache_negative("missing.example", now);
+        assert!(resolver.is_negative("missing.example", now + Duration::from_secs(29)));
+        assert!(!resolver.is_negative("missing.example", now + Duration::from_secs(31)));
+        
+        resolver.cache_result("missing.example", &["192.0.2.1".parse().unwrap()], 60, now);
+        assert!(!resolver.is_negative("missing.example", now));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_short_circuits_from_cache() {
+        let resolver = DohResolver::new();
+        resolver.cache_negative("missing.example", Instant::now());
+        resolver.cache_result("cached.example", &["192.0.2.1".parse().unwrap()], 60, Instant::now());
+        
+        let err = resolver.resolve("missing.example").await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert_eq!(resolver.resolve("cached.example").await.unwrap().len(), 1);
+        
+        assert_eq!(resolver.cache_stats(), DnsCacheStats { hits: 1, misses: 0, negative_hits: 1, lookups: 0, lookup_time_us: 0 });
+    }
+    
+    #[tokio::test]
+    async fn test_resolve_with_ttl_reports_remaining_ttl() {
+        let resolver = DohResolver::with_providers(Vec::new());
+        resolver.cache_result("cached.example", &["192.0.2.1".parse().unwrap()], 60, Instant::now());
+        let (ips, ttl) = resolver.resolve_with_ttl("cached.example").await.unwrap();
+        assert_eq!(ips.len(), 1);
+        assert!((59..=60).contains(&ttl), "{}", ttl);
+    }
+    
+    fn wire_response(id: u16, rcode: u8, answers: &[u8], answer_count: u16) -> Vec<u8> {
+        let query = build_wire_query(id, "www.example.com", DNS_TYPE_A).unwrap();
+        let mut message = query.clone();
+        message[2] = 0x81;
+        message[3] = 0x80 | rcode;
+        message[6..8].copy_from_slice(&answer_count.to_be_bytes());
+        message.extend_from_slice(answers);
+        message
+    }
+
+    #[test]
+    fn test_build_wire_query() {
+        let query = build_wire_query(0x1234, "discord.com.", DNS_TYPE_A).unwrap();
+        assert_eq!(&query[..4], &[0x12, 0x34, 0x01, 0x00]);
+        assert_eq!(&query[12..], b"\x07discord\x03com\x00\x00\x01\x00\x01");
+        
+        assert!(build_wire_query(1, "bad..name", DNS_TYPE_A).is_err());
+    }
+
+    #[test]
+    fn test_parse_wire_response_follows_cname() {
+        let mut answers = Vec::new();
+        answers.extend_from_slice(&[0xC0, 12, 0, 5, 0, 1, 0, 0, 0, 120, 0, 18]);
+        answers.extend_from_slice(b"\x04edge\x07example\x03net\x00");
+        let edge = 12 + 21 + 12;
+        answers.extend_from_slice(&[0xC0, edge as u8, 0, 1, 0, 1, 0, 0, 0, 30, 0, 4, 192, 0, 2, 10]);
+        answers.extend_from_slice(&[0xC0, edge as u8, 0, 28, 0, 1, 0, 0, 0, 60, 0, 16]);
+        answers.extend_from_slice(&"2001:db8::10".parse::<std::net::Ipv6Addr>().unwrap().octets());
+        
+        let message = wire_response(0xBEEF, 0, &answers, 3);
+        let (ips, ttl) = parse_wire_response(0xBEEF, "www.example.com", &message).unwrap();
+        assert_eq!(ips, vec![
+            "192.0.2.10".parse::<IpAddr>().unwrap(),
+            "2001:db8::10".parse::<IpAddr>().unwrap(),
+        ]);
+        assert_eq!(ttl, 30);
+    }
+
+    #[test]
+    fn test_parse_wire_response_errors() {
+        let nxdomain = wire_response(7, 3, &[], 0);
+        let err = parse_wire_response(7, "www.example.com", &nxdomain).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        
+        let err = parse_wire_response(8, "www.example.com", &nxdomain).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        
+        let truncated = wire_response(7, 0, &[0xC0, 12, 0, 1], 1);
+        let err = parse_wire_response(7, "www.example.com", &truncated).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_parse_nxdomain_response() {
+        let resolver = DohResolver::new();
+        let response = r#"HTTP/1.1 200 OK
+Content-Type: application/dns-json
+
+{"Status":3,"Question":[{"name":"nonexistent.invalid.","type":1}],"Authority":[{"name":"invalid.","type":6,"TTL":900,"data":"a.root-servers.net."}]}"#.replace('\n', "\r\n");
+        
+        let err = resolver.parse_doh_response("nonexistent.invalid", response.as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert!(matches!(
+            err.get_ref().and_then(|e| e.downcast_ref::<DohError>()),
+            Some(DohError::DnsStatus(3))
+        ));
+    }
+
+    #[test]
+    fn test_parse_http_error_response() {
+        let resolver = DohResolver::new();
+        let response = "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n";
+        
+        let err = resolver.parse_doh_response("discord.com", response.as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Other);
+        assert!(matches!(
+            err.get_ref().and_then(|e| e.downcast_ref::<DohError>()),
+            Some(DohError::HttpStatus(503))
+        ));
+    }
+    
+    #[tokio::test]
+    async fn test_read_http_response_stops_at_content_length() {
+        let first = "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello";
+        let second = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nhi";
+        let mut stream = std::io::Cursor::new(format!("{}{}", first, second).into_bytes());
+        
+        let (response, keep_alive) = read_http_response(&mut stream).await.unwrap();
+        assert_eq!(response, first.as_bytes());
+        assert!(keep_alive);
+        
+        let (response, keep_alive) = read_http_response(&mut stream).await.unwrap();
+        assert_eq!(response, second.as_bytes());
+        assert!(!keep_alive);
+        
+        let err = read_http_response(&mut stream).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+    
+    #[tokio::test]
+    async fn test_read_http_response_chunked_and_unframed() {
+        let chunked = "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n";
+        let mut stream = std::io::Cursor::new(format!("{}trailing", chunked).into_bytes());
+        let (response, keep_alive) = read_http_response(&mut stream).await.unwrap();
+        assert_eq!(response, chunked.as_bytes());
+        assert!(keep_alive);
+        
+        let unframed = "HTTP/1.1 200 OK\r\n\r\nbody until close";
+        let mut stream = std::io::Cursor::new(unframed.as_bytes().to_vec());
+        let (response, keep_alive) = read_http_response(&mut stream).await.unwrap();
+        assert_eq!(response, unframed.as_bytes());
+        assert!(!keep_alive);
+    }
+    
+    #[test]
+    fn test_chunked_length_incomplete() {
+        assert_eq!(chunked_length(b"5\r\nhel"), None);
+        assert_eq!(chunked_length(b"3\r\nabc\r\n0\r\n"), None);
+        assert_eq!(chunked_length(b"3\r\nabc\r\n0\r\n\r\n"), Some(13));
+    }
+    
+    fn answer_after(delay_ms: u64, ip: &str) -> ProviderQuery<'static> {
+        let ip: IpAddr = ip.parse().unwrap();
+        Box::pin(async move {
+            tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+            Ok((vec![ip], 60))
+        })
+    }
+    
+    #[tokio::test]
+    async fn test_race_slow_first_provider() {
+        let started = Instant::now();
+        let queries = vec![answer_after(2000, "1.1.1.1"), answer_after(20, "8.8.8.8")];
+        
+        let (ips, _) = race_staggered(queries, Duration::from_millis(100)).await.unwrap();
+        assert_eq!(ips, vec!["8.8.8.8".parse::<IpAddr>().unwrap()]);
+        assert!(started.elapsed() < Duration::from_millis(1000));
+    }
+    
+    #[tokio::test]
+    async fn test_race_prefers_first_provider_and_skips_failures() {
+        let queries = vec![answer_after(20, "1.1.1.1"), answer_after(0, "8.8.8.8")];
+        let (ips, _) = race_staggered(queries, Duration::from_millis(100)).await.unwrap();
+        assert_eq!(ips, vec!["1.1.1.1".parse::<IpAddr>().unwrap()]);
+        
+        let failing: ProviderQuery<'static> = Box::pin(async {
+            Err(std::io::Error::new(ErrorKind::ConnectionRefused, "refused"))
+        });
+        let (ips, _) = race_staggered(vec![failing, answer_after(0, "9.9.9.9")], Duration::from_millis(10)).await.unwrap();
+        assert_eq!(ips, vec!["9.9.9.9".parse::<IpAddr>().unwrap()]);
+        
+        let empty: ProviderQuery<'static> = Box::pin(async { Ok((Vec::new(), 0)) });
+        let failing: ProviderQuery<'static> = Box::pin(async {
+            Err(std::io::Error::new(ErrorKind::ConnectionRefused, "refused"))
+        });
+        let (ips, _) = race_staggered(vec![empty, failing], Duration::from_millis(10)).await.unwrap();
+        assert!(ips.is_empty());
+    }
+    
+    #[test]
+    fn test_parse_hosts() {
+        let content = "# static entries\n\
+                       127.0.0.1\tlocalhost\n\
+                       \n\
+                       10.0.0.5   nas.home  media.home   # self-hosted\n\
+                       ::1 localhost ip6-localhost\n\
+                       not-an-ip example.com\n";
+        
+        let entries = parse_hosts(content);
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert_eq!(entries, vec![
+            ("localhost".to_string(), ip("127.0.0.1")),
+            ("nas.home".to_string(), ip("10.0.0.5")),
+            ("media.home".to_string(), ip("10.0.0.5")),
+            ("localhost".to_string(), ip("::1")),
+            ("ip6-localhost".to_string(), ip("::1")),
+        ]);
+    }
+    
+    #[tokio::test]
+    async fn test_overrides_bypass_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hosts");
+        std::fs::write(&path, "203.0.113.7 Blocked.Example.\n2001:db8::7 blocked.example\n").unwrap();
+        
+        let resolver = DohResolver::new().with_family_preference(FamilyPreference::V6First);
+        assert_eq!(resolver.load_hosts_file(&path).unwrap(), 2);
+        
+        resolver.cache_negative("blocked.example", Instant::now());
+        let ips = resolver.resolve("blocked.example").await.unwrap();
+        assert_eq!(ips, vec![
+            "2001:db8::7".parse::<IpAddr>().unwrap(),
+            "203.0.113.7".parse::<IpAddr>().unwrap(),
+        ]);
+        
+        resolver.add_override("pinned.example", vec!["198.51.100.1".parse().unwrap()]);
+        let addrs = resolver.resolve_host_port_all("pinned.example:443").await.unwrap();
+        assert_eq!(addrs, vec!["198.51.100.1:443".parse::<SocketAddr>().unwrap()]);
+        assert_eq!(resolver.cache_stats().negative_hits, 0);
+    }
+    
+    #[derive(Default)]
+    struct RecordingStream {
+        writes: Arc<Mutex<Vec<Vec<u8>>>>,
+    }
+    
+    impl AsyncRead for RecordingStream {
+        fn poll_read(self: Pin<&mut Self>, _cx: &mut Context<'_>, _buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Err(std::io::Error::from(ErrorKind::ConnectionReset)))
+        }
+    }
+    
+    impl AsyncWrite for RecordingStream {
+        fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+            self.writes.lock().push(buf.to_vec());
+            Poll::Ready(Ok(buf.len()))
+        }
+        
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+        
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+    
+    async fn record_handshake(engine: Option<Arc<BypassEngine>>) -> Vec<Vec<u8>> {
+        let recording = RecordingStream::default();
+        let writes = recording.writes.clone();
+        let connector = tokio_native_tls::TlsConnector::from(native_tls::TlsConnector::new().unwrap());
+        
+        let _ = connector.connect("cloudflare-dns.com", FragmentingStream::new(recording, engine)).await;
+        let writes = writes.lock().clone();
+        writes
+    }
+    
+    #[tokio::test]
+    async fn test_fragment_doh_splits_client_hello() {
+        let plain = record_handshake(None).await;
+        assert_eq!(plain.len(), 1);
+        assert_eq!(plain[0][0], 0x16);
+        
+        let engine = Arc::new(BypassEngine::new(BypassConfig::default()));
+        let fragmented = record_handshake(Some(engine)).await;
+        assert!(fragmented.len() > 1);
+        assert!(fragmented[0].len() < plain[0].len());
+        assert_eq!(fragmented.concat().len(), plain[0].len());
+        assert_eq!(fragmented[0][0], 0x16);
+    }
+
+    #[test]
+    fn test_cache_lru_flush_and_dump() {
+        let resolver = DohResolver::new().with_cache_capacity(2);
+        let now = Instant::now();
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        
+        resolver.cache_result("a.example", &[ip("192.0.2.1")], 60, now);
+        resolver.cache_result("b.example", &[ip("192.0.2.2")], 120, now);
+        assert!(resolver.get_cached("a.example", now).is_some());
+        resolver.cache_result("c.example", &[ip("192.0.2.3")], 60, now);
+        
+        let dump = resolver.dump();
+        let hosts: Vec<&str> = dump.iter().map(|(host, _, _)| host.as_str()).collect();
+        assert_eq!(hosts, vec!["a.example", "c.example"]);
+        assert_eq!(dump[0].1, vec![ip("192.0.2.1")]);
+        assert!(dump[0].2 <= Duration::from_secs(60));
+        
+        resolver.cache_negative("missing.example", now);
+        assert_eq!(resolver.flush(), 2);
+        assert!(resolver.dump().is_empty());
+        assert!(!resolver.is_negative("missing.example", now));
+    }
 }
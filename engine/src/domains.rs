@@ -0,0 +1,123 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+/// Domain patterns compiled for lookups that do not depend on the number of
+/// patterns: exact names and `*.suffix` patterns are kept in separate sets and
+/// a host is checked label by label against the suffix set.
+#[derive(Debug, Default, Clone)]
+pub struct DomainSet {
+    exact: HashSet<String>,
+    suffixes: HashSet<String>,
+}
+
+impl DomainSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    pub fn insert(&mut self, pattern: &str) {
+        let pattern = pattern.trim_end_matches('.').to_ascii_lowercase();
+        match pattern.strip_prefix("*.").or_else(|| pattern.strip_prefix('.')) {
+            Some(suffix) => {
+                self.suffixes.insert(suffix.to_string());
+            }
+            None => {
+                self.exact.insert(pattern);
+            }
+        }
+    }
+    
+    pub fn len(&self) -> usize {
+        self.exact.len() + self.suffixes.len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.exact.is_empty() && self.suffixes.is_empty()
+    }
+    
+    pub fn matches(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.');
+        let host = if host.bytes().any(|b| b.is_ascii_uppercase()) {
+            Cow::Owned(host.to_ascii_lowercase())
+        } else {
+            Cow::Borrowed(host)
+        };
+        
+        if self.exact.contains(host.as_ref()) {
+            return true;
+        }
+        if self.suffixes.is_empty() {
+            return false;
+        }
+        
+        let mut rest = host.as_ref();
+        loop {
+            if self.suffixes.contains(rest) {
+                return true;
+            }
+            match rest.find('.') {
+                Some(dot) => rest = &rest[dot + 1..],
+                None => return false,
+            }
+        }
+    }
+}
+
+impl<'a> FromIterator<&'a str> for DomainSet {
+    fn from_iter<I: IntoIterator<Item = &'a str>>(iter: I) -> Self {
+        let mut set = Self::new();
+        for pattern in iter {
+            set.insert(pattern);
+        }
+        set
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bypass::host_matches;
+    
+    #[test]
+    fn test_domain_set_matching() {
+        let set: DomainSet = ["discord.com", "*.youtube.com", ".googlevideo.com.", "Example.ORG"].into_iter().collect();
+        assert_eq!(set.len(), 4);
+        
+        assert!(set.matches("discord.com"));
+        assert!(set.matches("DISCORD.com."));
+        assert!(!set.matches("cdn.discord.com"));
+        assert!(set.matches("youtube.com"));
+        assert!(set.matches("www.youtube.com"));
+        assert!(!set.matches("notyoutube.com"));
+        assert!(set.matches("rr1.sn-abc.googlevideo.com"));
+        assert!(set.matches("example.org"));
+        assert!(!set.matches("com"));
+        assert!(!DomainSet::new().matches("discord.com"));
+    }
+    
+    #[test]
+    fn test_domain_set_agrees_with_host_matches() {
+        let patterns = ["discord.com", "*.youtube.com", "*.co.uk"];
+        let set: DomainSet = patterns.into_iter().collect();
+        
+        for host in ["discord.com", "a.discord.com", "youtube.com", "m.youtube.com", "bbc.co.uk", "co.uk", "uk"] {
+            let expected = patterns.iter().any(|p| host_matches(p, host));
+            assert_eq!(set.matches(host), expected, "{}", host);
+        }
+    }
+    
+    #[test]
+    fn test_domain_set_many_patterns() {
+        let patterns: Vec<String> = (0..10_000)
+            .map(|i| if i % 2 == 0 { format!("mirror{}.example.com", i) } else { format!("*.mirror{}.example.net", i) })
+            .collect();
+        let set: DomainSet = patterns.iter().map(String::as_str).collect();
+        assert_eq!(set.len(), 10_000);
+        
+        assert!(set.matches("mirror5000.example.com"));
+        assert!(set.matches("cdn.mirror9999.example.net"));
+        assert!(!set.matches("mirror5001.example.com"));
+        assert!(!set.matches("cdn.mirror5000.example.com"));
+        assert!(!set.matches("unlisted.example.org"));
+    }
+}
@@ -56,6 +56,13 @@ impl EngineError {
         }
     }
 
+    pub fn in_section(self, section: &str) -> Self {
+        match self {
+            Self::ConfigValidation { message, field } => Self::validation(format!("{}.{}", section, field), message),
+            other => other,
+        }
+    }
+    
     pub fn transform(transform: impl Into<String>, message: impl Into<String>) -> Self {
         Self::Transform {
             transform: transform.into(),
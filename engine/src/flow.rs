@@ -1,12 +1,17 @@
-use std::hash::Hash;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::net::IpAddr;
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 
 use bytes::BytesMut;
 use lru::LruCache;
 use parking_lot::RwLock;
+use rand::rngs::SmallRng;
+use rand::{RngCore, SeedableRng};
 use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
 
 use crate::config::{Limits, Protocol, Rule};
 
@@ -67,7 +72,9 @@ pub struct FlowState {
     
     pub byte_count: u64,
     
-    pub matched_rule: Option<String>,
+    pub matched_rule: Option<Arc<str>>,
+    
+    pub hostname: Option<String>,
     
     pub direction: FlowDirection,
     
@@ -86,13 +93,14 @@ impl FlowState {
             packet_count: 0,
             byte_count: 0,
             matched_rule: None,
+            hostname: None,
             direction: FlowDirection::Outbound,
             tcp_state: if key.is_tcp() {
                 Some(TcpFlowState::default())
             } else {
                 None
             },
-            transform_state: TransformState::default(),
+            transform_state: TransformState::new(&key),
         }
     }
 
@@ -113,11 +121,67 @@ impl FlowState {
     pub fn idle_time(&self) -> Duration {
         self.last_seen.elapsed()
     }
+    
+    /// The RNG a transform should draw from. A pinned `seed` gives a generator
+    /// derived from it and the packet number, so output is reproducible.
+    pub fn rng(&mut self, seed: Option<u64>) -> TransformRng<'_> {
+        match seed {
+            Some(seed) => TransformRng::Seeded(SmallRng::seed_from_u64(
+                seed ^ self.packet_count.wrapping_mul(0x9E37_79B9_7F4A_7C15),
+            )),
+            None => TransformRng::Flow(&mut self.transform_state.rng),
+        }
+    }
+}
+
+pub enum TransformRng<'a> {
+    Flow(&'a mut SmallRng),
+    Seeded(SmallRng),
+}
+
+impl TransformRng<'_> {
+    fn inner(&mut self) -> &mut SmallRng {
+        match self {
+            TransformRng::Flow(rng) => rng,
+            TransformRng::Seeded(rng) => rng,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+impl RngCore for TransformRng<'_> {
+    fn next_u32(&mut self) -> u32 {
+        self.inner().next_u32()
+    }
+    
+    fn next_u64(&mut self) -> u64 {
+        self.inner().next_u64()
+    }
+    
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.inner().fill_bytes(dest)
+    }
+    
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.inner().try_fill_bytes(dest)
+    }
+}
+
+/// Seeds a flow's RNG from a process-wide secret and the flow key, so flows
+/// carrying identical traffic still get different fragment sizes, padding
+/// and timing, and the pattern does not repeat across restarts.
+fn flow_rng(key: &FlowKey) -> SmallRng {
+    static SECRET: OnceLock<u64> = OnceLock::new();
+    let mut hasher = DefaultHasher::new();
+    SECRET.get_or_init(rand::random).hash(&mut hasher);
+    key.hash(&mut hasher);
+    SmallRng::seed_from_u64(hasher.finish())
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum FlowDirection {
     Inbound,
+    #[default]
     Outbound,
 }
 
@@ -142,13 +206,32 @@ pub struct TcpFlowState {
     pub reassembly_bytes: usize,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct TransformState {
     pub fragment: FragmentState,
     
     pub jitter: JitterState,
     
     pub resegment: ResegmentState,
+    
+    pub rate_limit: RateLimitState,
+    
+    pub fake: FakeState,
+    
+    pub rng: SmallRng,
+}
+
+impl TransformState {
+    pub fn new(key: &FlowKey) -> Self {
+        Self {
+            fragment: FragmentState::default(),
+            jitter: JitterState::default(),
+            resegment: ResegmentState::default(),
+            rate_limit: RateLimitState::default(),
+            fake: FakeState::default(),
+            rng: flow_rng(key),
+        }
+    }
 }
 
 #[derive(Debug, Default)]
@@ -165,6 +248,36 @@ pub struct JitterState {
     pub total_jitter_ms: u64,
 }
 
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RateLimitState {
+    pub tokens: f64,
+    
+    pub last_refill: Option<Instant>,
+}
+
+impl RateLimitState {
+    pub fn admit(&mut self, pps: u32, now: Instant) -> bool {
+        let capacity = f64::from(pps);
+        self.tokens = match self.last_refill {
+            Some(last) => (self.tokens + now.duration_since(last).as_secs_f64() * capacity).min(capacity),
+            None => capacity,
+        };
+        self.last_refill = Some(now);
+        
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FakeState {
+    pub sent: bool,
+}
+
 #[derive(Debug, Default)]
 pub struct ResegmentState {
     pub buffer: BytesMut,
@@ -172,6 +285,48 @@ pub struct ResegmentState {
     pub segments_generated: u32,
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmitKind {
+    #[default]
+    Real,
+    
+    Fake,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmittedPacket {
+    pub data: BytesMut,
+    
+    pub kind: EmitKind,
+    
+    pub delay_before: Option<Duration>,
+    
+    pub ttl_override: Option<u8>,
+}
+
+impl EmittedPacket {
+    pub fn new(data: BytesMut, kind: EmitKind) -> Self {
+        Self {
+            data,
+            kind,
+            delay_before: None,
+            ttl_override: None,
+        }
+    }
+    
+    pub fn with_ttl(mut self, ttl: u8) -> Self {
+        self.ttl_override = Some(ttl);
+        self
+    }
+}
+
+impl From<BytesMut> for EmittedPacket {
+    fn from(data: BytesMut) -> Self {
+        Self::new(data, EmitKind::Real)
+    }
+}
+
 #[derive(Debug)]
 pub struct FlowContext<'a> {
     pub key: &'a FlowKey,
@@ -186,11 +341,22 @@ pub struct FlowContext<'a> {
     
     pub is_first_packet: bool,
     
-    pub output_packets: Vec<BytesMut>,
+    /// The data is a chunk of a byte stream (e.g. a proxied TCP connection)
+    /// rather than a whole packet, so nothing may be appended blindly.
+    pub is_stream: bool,
+    
+    pub output_packets: Vec<EmittedPacket>,
+    
+    pub data_kind: EmitKind,
+    
+    pub data_ttl: Option<u8>,
     
     pub delay: Option<Duration>,
     
     pub drop: bool,
+    
+    /// Filler bytes transforms appended to the data, for overhead accounting.
+    pub padding_added: usize,
 }
 
 impl<'a> FlowContext<'a> {
@@ -207,15 +373,48 @@ impl<'a> FlowContext<'a> {
             timestamp: Instant::now(),
             direction: FlowDirection::Outbound,
             is_first_packet,
+            is_stream: false,
             output_packets: Vec::new(),
+            data_kind: EmitKind::Real,
+            data_ttl: None,
             delay: None,
             drop: false,
+            padding_added: 0,
         }
     }
+    
+    pub fn with_direction(mut self, direction: FlowDirection) -> Self {
+        self.direction = direction;
+        self
+    }
+    
+    pub fn with_stream(mut self, is_stream: bool) -> Self {
+        self.is_stream = is_stream;
+        self
+    }
 
-    pub fn emit(&mut self, packet: BytesMut) {
+    pub fn emit(&mut self, packet: EmittedPacket) {
         self.output_packets.push(packet);
     }
+    
+    pub fn emit_continuation(&mut self, packet: BytesMut) {
+        self.output_packets.push(EmittedPacket {
+            data: packet,
+            kind: self.data_kind,
+            delay_before: None,
+            ttl_override: self.data_ttl,
+        });
+    }
+    
+    pub fn send_before(&mut self, data: &mut BytesMut, packet: EmittedPacket) {
+        let previous = EmittedPacket {
+            data: std::mem::replace(data, packet.data),
+            kind: std::mem::replace(&mut self.data_kind, packet.kind),
+            delay_before: None,
+            ttl_override: std::mem::replace(&mut self.data_ttl, packet.ttl_override),
+        };
+        self.output_packets.insert(0, previous);
+    }
 
     pub fn request_delay(&mut self, delay: Duration) {
         self.delay = Some(delay);
@@ -237,6 +436,17 @@ pub struct FlowCache {
     eviction_count: AtomicU64,
     hit_count: AtomicU64,
     miss_count: AtomicU64,
+    memory_budget: usize,
+    memory_used: AtomicUsize,
+    memory_evictions: AtomicU64,
+    pending_memory_evictions: AtomicU64,
+}
+
+fn entry_size(state: &FlowState) -> usize {
+    std::mem::size_of::<FlowKey>()
+        + std::mem::size_of::<FlowState>()
+        + state.hostname.as_ref().map_or(0, |h| h.capacity())
+        + state.matched_rule.as_ref().map_or(0, |r| r.len())
 }
 
 impl FlowCache {
@@ -250,6 +460,28 @@ impl FlowCache {
             eviction_count: AtomicU64::new(0),
             hit_count: AtomicU64::new(0),
             miss_count: AtomicU64::new(0),
+            memory_budget: limits.max_memory_mb.saturating_mul(1024 * 1024),
+            memory_used: AtomicUsize::new(0),
+            memory_evictions: AtomicU64::new(0),
+            pending_memory_evictions: AtomicU64::new(0),
+        }
+    }
+
+    fn insert(&self, cache: &mut LruCache<FlowKey, FlowState>, state: FlowState) {
+        self.memory_used.fetch_add(entry_size(&state), Ordering::Relaxed);
+        if let Some((_, old)) = cache.push(state.key, state) {
+            self.memory_used.fetch_sub(entry_size(&old), Ordering::Relaxed);
+        }
+    }
+
+    fn make_room(&self, cache: &mut LruCache<FlowKey, FlowState>, needed: usize) {
+        while self.memory_used.load(Ordering::Relaxed) + needed > self.memory_budget {
+            let Some((_, evicted)) = cache.pop_lru() else {
+                break;
+            };
+            self.memory_used.fetch_sub(entry_size(&evicted), Ordering::Relaxed);
+            self.memory_evictions.fetch_add(1, Ordering::Relaxed);
+            self.pending_memory_evictions.fetch_add(1, Ordering::Relaxed);
         }
     }
 
@@ -266,9 +498,17 @@ impl FlowCache {
                 packet_count: state.packet_count,
                 byte_count: state.byte_count,
                 matched_rule: state.matched_rule.clone(),
+                hostname: state.hostname.clone(),
                 direction: state.direction,
                 tcp_state: None, 
-                transform_state: TransformState::default(),
+                transform_state: TransformState {
+                    fragment: FragmentState::default(),
+                    jitter: JitterState::default(),
+                    resegment: ResegmentState::default(),
+                    rate_limit: state.transform_state.rate_limit,
+                    fake: state.transform_state.fake,
+                    rng: state.transform_state.rng.clone(),
+                },
             }
         } else {
             self.miss_count.fetch_add(1, Ordering::Relaxed);
@@ -280,22 +520,76 @@ impl FlowCache {
             
             let state = FlowState::new(key);
             let result = FlowState::new(key);
-            cache.put(key, state);
+            self.make_room(&mut cache, entry_size(&state));
+            self.insert(&mut cache, state);
             result
         }
     }
 
     pub fn update(&self, state: FlowState) {
         let mut cache = self.cache.write();
-        cache.put(state.key, state);
+        self.insert(&mut cache, state);
     }
 
-    pub fn cleanup(&self) -> usize {
+    pub fn set_hostname(&self, key: FlowKey, hostname: String) {
         let mut cache = self.cache.write();
-        let timeout = self.timeout;
         
-        let before = cache.len();
+        if let Some(state) = cache.get_mut(&key) {
+            self.memory_used.fetch_add(hostname.capacity(), Ordering::Relaxed);
+            if let Some(old) = state.hostname.replace(hostname) {
+                self.memory_used.fetch_sub(old.capacity(), Ordering::Relaxed);
+            }
+        } else {
+            let mut state = FlowState::new(key);
+            state.hostname = Some(hostname);
+            self.make_room(&mut cache, entry_size(&state));
+            self.insert(&mut cache, state);
+        }
+    }
+
+    pub fn hostname(&self, key: &FlowKey) -> Option<String> {
+        self.cache.read().peek(key).and_then(|state| state.hostname.clone())
+    }
+    
+    /// Summaries of the tracked flows. Only the cheap parts of each flow are
+    /// copied under the lock; the summaries are built after it is released.
+    pub fn iter_snapshot(&self) -> Vec<FlowSummary> {
+        let entries: Vec<_> = self.cache
+            .read()
+            .iter()
+            .map(|(_, state)| {
+                (
+                    state.key,
+                    state.hostname.clone(),
+                    state.matched_rule.clone(),
+                    state.packet_count,
+                    state.byte_count,
+                    state.created_at,
+                    state.last_seen,
+                )
+            })
+            .collect();
         
+        let now = Instant::now();
+        entries
+            .into_iter()
+            .map(|(key, hostname, matched_rule, packet_count, byte_count, created_at, last_seen)| FlowSummary {
+                key,
+                hostname,
+                matched_rule: matched_rule.as_deref().map(str::to_string),
+                packet_count,
+                byte_count,
+                age_secs: now.saturating_duration_since(created_at).as_secs(),
+                idle_secs: now.saturating_duration_since(last_seen).as_secs(),
+            })
+            .collect()
+    }
+
+    /// Removes expired flows and hands them back, so their final state can
+    /// still be inspected once the cache lock is released.
+    pub fn cleanup(&self) -> Vec<(FlowKey, FlowState)> {
+        let mut cache = self.cache.write();
+        let timeout = self.timeout;
         
         let expired: Vec<FlowKey> = cache
             .iter()
@@ -303,11 +597,15 @@ impl FlowCache {
             .map(|(key, _)| *key)
             .collect();
         
-        for key in &expired {
-            cache.pop(key);
+        let mut evicted = Vec::with_capacity(expired.len());
+        for key in expired {
+            if let Some(state) = cache.pop(&key) {
+                self.memory_used.fetch_sub(entry_size(&state), Ordering::Relaxed);
+                evicted.push((key, state));
+            }
         }
         
-        before - cache.len()
+        evicted
     }
 
     pub fn stats(&self) -> FlowCacheStats {
@@ -318,9 +616,20 @@ impl FlowCache {
             hit_count: self.hit_count.load(Ordering::Relaxed),
             miss_count: self.miss_count.load(Ordering::Relaxed),
             eviction_count: self.eviction_count.load(Ordering::Relaxed),
+            memory_bytes: self.memory_used.load(Ordering::Relaxed),
+            memory_budget: self.memory_budget,
+            memory_evictions: self.memory_evictions.load(Ordering::Relaxed),
         }
     }
 
+    pub fn memory_used(&self) -> usize {
+        self.memory_used.load(Ordering::Relaxed)
+    }
+
+    pub fn take_memory_evictions(&self) -> u64 {
+        self.pending_memory_evictions.swap(0, Ordering::Relaxed)
+    }
+
     pub fn len(&self) -> usize {
         self.cache.read().len()
     }
@@ -330,10 +639,23 @@ impl FlowCache {
     }
 
     pub fn clear(&self) {
-        self.cache.write().clear();
+        let mut cache = self.cache.write();
+        cache.clear();
+        self.memory_used.store(0, Ordering::Relaxed);
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlowSummary {
+    pub key: FlowKey,
+    pub hostname: Option<String>,
+    pub matched_rule: Option<String>,
+    pub packet_count: u64,
+    pub byte_count: u64,
+    pub age_secs: u64,
+    pub idle_secs: u64,
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct FlowCacheStats {
     pub size: usize,
@@ -341,6 +663,9 @@ pub struct FlowCacheStats {
     pub hit_count: u64,
     pub miss_count: u64,
     pub eviction_count: u64,
+    pub memory_bytes: usize,
+    pub memory_budget: usize,
+    pub memory_evictions: u64,
 }
 
 impl FlowCacheStats {
@@ -391,6 +716,22 @@ mod tests {
         assert_eq!(state.byte_count, 100);
     }
 
+    #[test]
+    fn test_rate_limit_token_bucket() {
+        let mut bucket = RateLimitState::default();
+        let start = Instant::now();
+        
+        assert!((0..3).all(|_| bucket.admit(3, start)));
+        assert!(!bucket.admit(3, start));
+        assert!(!bucket.admit(3, start + Duration::from_millis(100)));
+        assert!(bucket.admit(3, start + Duration::from_millis(400)));
+        assert!(!bucket.admit(3, start + Duration::from_millis(400)));
+        
+        let later = start + Duration::from_secs(10);
+        assert!((0..3).all(|_| bucket.admit(3, later)));
+        assert!(!bucket.admit(3, later));
+    }
+
     #[test]
     fn test_flow_cache_get_or_create() {
         let limits = Limits::default();
@@ -410,10 +751,84 @@ mod tests {
         assert_eq!(stats.hit_count, 1);
     }
 
+    #[test]
+    fn test_flow_cache_hostname() {
+        let cache = FlowCache::new(&Limits::default());
+        let key = test_key();
+        
+        cache.set_hostname(key, "discord.com".to_string());
+        assert_eq!(cache.hostname(&key).as_deref(), Some("discord.com"));
+        
+        let state = cache.get_or_create(key);
+        assert_eq!(state.hostname.as_deref(), Some("discord.com"));
+        cache.update(state);
+        assert_eq!(cache.hostname(&key).as_deref(), Some("discord.com"));
+        assert_eq!(cache.hostname(&key.reverse()), None);
+    }
+
+    #[test]
+    fn test_flow_cache_snapshot() {
+        let cache = FlowCache::new(&Limits::default());
+        let key = test_key();
+        
+        let mut state = cache.get_or_create(key);
+        state.created_at = Instant::now() - Duration::from_secs(30);
+        state.update(100);
+        state.matched_rule = Some("test".into());
+        cache.update(state);
+        cache.set_hostname(key.reverse(), "discord.com".to_string());
+        
+        let flows = cache.iter_snapshot();
+        assert_eq!(flows.len(), 2);
+        let flow = flows.iter().find(|flow| flow.key == key).unwrap();
+        assert_eq!(flow.matched_rule.as_deref(), Some("test"));
+        assert_eq!(flow.byte_count, 100);
+        assert_eq!((flow.age_secs, flow.idle_secs), (30, 0));
+        assert_eq!(flow.hostname, None);
+        let reverse = flows.iter().find(|flow| flow.key == key.reverse()).unwrap();
+        assert_eq!(reverse.hostname.as_deref(), Some("discord.com"));
+    }
+
+    #[test]
+    fn test_flow_cache_memory_budget() {
+        let limits = Limits {
+            max_flows: 100_000,
+            max_memory_mb: 1,
+            ..Default::default()
+        };
+        let cache = FlowCache::new(&limits);
+        let budget = 1024 * 1024;
+        
+        for port in 0..20_000u16 {
+            let mut key = test_key();
+            key.src_port = port;
+            let _ = cache.get_or_create(key);
+        }
+        
+        let stats = cache.stats();
+        assert!(stats.memory_evictions > 0);
+        assert!(cache.memory_used() <= budget);
+        assert_eq!(cache.memory_used(), cache.len() * entry_size(&FlowState::new(test_key())));
+        assert_eq!(cache.take_memory_evictions(), stats.memory_evictions);
+        assert_eq!(cache.take_memory_evictions(), 0);
+        
+        let mut newest = test_key();
+        newest.src_port = 19_999;
+        assert!(cache.iter_snapshot().iter().any(|flow| flow.key == newest));
+        let mut oldest = test_key();
+        oldest.src_port = 0;
+        assert!(cache.iter_snapshot().iter().all(|flow| flow.key != oldest));
+        
+        cache.clear();
+        assert_eq!(cache.memory_used(), 0);
+    }
+
     #[test]
     fn test_flow_cache_lru_eviction() {
-        let mut limits = Limits::default();
-        limits.max_flows = 2;
+        let limits = Limits {
+            max_flows: 2,
+            ..Default::default()
+        };
         let cache = FlowCache::new(&limits);
         
         let key1 = FlowKey::new(
@@ -1,18 +1,26 @@
 pub mod bypass;
+pub mod checksum;
 pub mod config;
 pub mod dns;
+pub mod domains;
 pub mod error;
 pub mod flow;
+pub mod log_limit;
 pub mod pipeline;
+pub mod presets;
+pub mod quic;
 pub mod stats;
 pub mod tls;
 pub mod transform;
 
-pub use bypass::{BypassConfig, BypassEngine, BypassResult, DetectedProtocol};
-pub use config::Config;
-pub use dns::DohResolver;
+pub use bypass::{host_matches, BypassConfig, BypassEngine, BypassResult, DetectedProtocol, HostOverride, IncomingVerdict, SplitStrategy};
+pub use config::{Config, ConfigFormat, DohProvider, FamilyPreference};
+pub use dns::{DnsCacheStats, DnsTransportStats, DohError, DohResolver};
 pub use error::{EngineError, Result};
-pub use flow::{FlowContext, FlowKey, FlowState};
-pub use pipeline::Pipeline;
-pub use stats::Stats;
+pub use flow::{FlowContext, FlowKey, FlowState, FlowSummary};
+pub use log_limit::LogRateLimiter;
+pub use pipeline::{Clock, Explanation, LocalClock, PacketMeta, Pipeline, RuleStats, TransformPlan};
+pub use presets::ConfigPreset;
+pub use quic::{parse_quic_initial, QuicInitialInfo};
+pub use stats::{HostCounters, HostStats, HostStatsEntry, PersistedStats, Rates, Stats, StatsHistory};
 pub use tls::{parse_client_hello, ClientHelloInfo};
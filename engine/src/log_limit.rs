@@ -0,0 +1,117 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use tracing::warn;
+
+/// Caps how many messages per second a noisy log site may emit. The budget is
+/// refilled once per second; anything over it is counted and reported as a
+/// single "suppressed" line when the next second starts or on [`flush`].
+///
+/// [`flush`]: LogRateLimiter::flush
+#[derive(Debug)]
+pub struct LogRateLimiter {
+    per_sec: AtomicU32,
+    epoch: Instant,
+    window: AtomicU64,
+    used: AtomicU32,
+    suppressed: AtomicU64,
+}
+
+impl LogRateLimiter {
+    /// A rate of 0 disables the limit.
+    pub fn new(per_sec: u32) -> Self {
+        Self {
+            per_sec: AtomicU32::new(per_sec),
+            epoch: Instant::now(),
+            window: AtomicU64::new(0),
+            used: AtomicU32::new(0),
+            suppressed: AtomicU64::new(0),
+        }
+    }
+    
+    pub fn set_rate(&self, per_sec: u32) {
+        self.per_sec.store(per_sec, Ordering::Relaxed);
+    }
+    
+    pub fn allow(&self) -> bool {
+        self.allow_at(self.epoch.elapsed())
+    }
+    
+    fn allow_at(&self, elapsed: Duration) -> bool {
+        let per_sec = self.per_sec.load(Ordering::Relaxed);
+        if per_sec == 0 {
+            return true;
+        }
+        
+        let second = elapsed.as_secs();
+        let window = self.window.load(Ordering::Relaxed);
+        if second > window
+            && self.window.compare_exchange(window, second, Ordering::Relaxed, Ordering::Relaxed).is_ok()
+        {
+            self.used.store(0, Ordering::Relaxed);
+            self.flush();
+        }
+        
+        if self.used.fetch_add(1, Ordering::Relaxed) < per_sec {
+            true
+        } else {
+            self.suppressed.fetch_add(1, Ordering::Relaxed);
+            false
+        }
+    }
+    
+    /// Logs and resets the number of messages suppressed so far.
+    pub fn flush(&self) -> u64 {
+        let suppressed = self.suppressed.swap(0, Ordering::Relaxed);
+        if suppressed > 0 {
+            warn!(suppressed, "suppressed {} similar log messages", suppressed);
+        }
+        suppressed
+    }
+}
+
+impl Default for LogRateLimiter {
+    fn default() -> Self {
+        Self::new(100)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn allowed(limiter: &LogRateLimiter, attempts: usize, at: Duration) -> usize {
+        (0..attempts).filter(|_| limiter.allow_at(at)).count()
+    }
+    
+    #[test]
+    fn test_burst_is_capped() {
+        let limiter = LogRateLimiter::new(10);
+        assert_eq!(allowed(&limiter, 1000, Duration::from_millis(100)), 10);
+        assert_eq!(allowed(&limiter, 1000, Duration::from_millis(900)), 0);
+        assert_eq!(limiter.flush(), 1990);
+        assert_eq!(limiter.flush(), 0);
+    }
+    
+    #[test]
+    fn test_steady_state() {
+        let limiter = LogRateLimiter::new(5);
+        let mut total = 0;
+        for second in 0..10 {
+            total += allowed(&limiter, 20, Duration::from_millis(second * 1000 + 500));
+        }
+        assert_eq!(total, 50);
+        assert_eq!(limiter.flush(), 15);
+        
+        assert_eq!(allowed(&limiter, 3, Duration::from_secs(11)), 3);
+    }
+    
+    #[test]
+    fn test_zero_rate_is_unlimited() {
+        let limiter = LogRateLimiter::new(0);
+        assert_eq!(allowed(&limiter, 1000, Duration::ZERO), 1000);
+        
+        limiter.set_rate(2);
+        assert_eq!(allowed(&limiter, 10, Duration::ZERO), 2);
+    }
+}
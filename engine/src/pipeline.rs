@@ -1,29 +1,90 @@
 use std::collections::HashMap;
 use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use bytes::BytesMut;
+use chrono::NaiveDateTime;
 use ipnet::IpNet;
-use parking_lot::RwLock;
+use parking_lot::{RwLock, RwLockUpgradableReadGuard};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
 use tracing::{debug, trace, warn};
 
-use crate::config::{Config, Rule, TransformType};
+use crate::config::{Config, Rule, RuleAction, TransformParams, TransformType};
+use crate::domains::DomainSet;
 use crate::error::{EngineError, Result};
-use crate::flow::{FlowCache, FlowContext, FlowKey};
+use crate::flow::{EmitKind, EmittedPacket, FlowCache, FlowContext, FlowDirection, FlowKey, FlowState};
+use crate::log_limit::LogRateLimiter;
 use crate::stats::Stats;
 use crate::transform::{
     BoxedTransform, TransformResult,
     FragmentTransform, JitterTransform, PaddingTransform,
-    HeaderNormalizationTransform, ResegmentTransform, DecoyTransform,
+    HeaderNormalizationTransform, ResegmentTransform, DecoyTransform, ReorderTransform, FakeTransform,
 };
 
 #[derive(Debug)]
 pub struct PipelineOutput {
     pub primary: Option<BytesMut>,
-    pub additional: Vec<BytesMut>,    
+    pub additional: Vec<EmittedPacket>,    
     pub delay: Option<std::time::Duration>,    
     pub dropped: bool,    
+    pub matched_rule: Option<Arc<str>>,
+    pub primary_kind: EmitKind,
+    pub primary_ttl: Option<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Explanation {
+    pub pipeline_enabled: bool,
     pub matched_rule: Option<String>,
+    pub hostname: Option<String>,
+    pub transforms: Vec<TransformPlan>,
+    /// Transforms listed by the rule but turned off by the global enable flags.
+    pub skipped: Vec<TransformType>,
+    /// Sizes of the packets a sample of the requested length would be emitted as.
+    pub output_sizes: Vec<usize>,
+    pub delay_ms: Option<u64>,
+    pub dropped: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransformPlan {
+    pub transform: TransformType,
+    pub params: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PacketMeta {
+    pub hostname: Option<String>,
+    pub direction: FlowDirection,
+    pub stream: bool,
+}
+
+impl PacketMeta {
+    pub fn outbound() -> Self {
+        Self::default()
+    }
+    
+    pub fn inbound() -> Self {
+        Self {
+            hostname: None,
+            direction: FlowDirection::Inbound,
+            stream: false,
+        }
+    }
+    
+    pub fn with_hostname(mut self, hostname: Option<String>) -> Self {
+        self.hostname = hostname;
+        self
+    }
+    
+    /// Marks the data as a chunk of a TCP byte stream rather than a packet.
+    pub fn in_stream(mut self) -> Self {
+        self.stream = true;
+        self
+    }
 }
 
 impl PipelineOutput {
@@ -34,6 +95,8 @@ impl PipelineOutput {
             delay: None,
             dropped: true,
             matched_rule: None,
+            primary_kind: EmitKind::Real,
+            primary_ttl: None,
         }
     }
 
@@ -44,6 +107,8 @@ impl PipelineOutput {
             delay: None,
             dropped: false,
             matched_rule: None,
+            primary_kind: EmitKind::Real,
+            primary_ttl: None,
         }
     }
 
@@ -52,23 +117,182 @@ impl PipelineOutput {
         if let Some(primary) = self.primary {
             packets.push(primary);
         }
+        packets.extend(self.additional.into_iter().map(|p| p.data));
+        packets
+    }
+    
+    /// Every packet with how it should be sent; `delay` becomes the first
+    /// packet's `delay_before`.
+    pub fn emitted(self) -> Vec<EmittedPacket> {
+        let mut packets = Vec::with_capacity(self.additional.len() + 1);
+        if let Some(primary) = self.primary {
+            packets.push(EmittedPacket {
+                data: primary,
+                kind: self.primary_kind,
+                delay_before: self.delay,
+                ttl_override: self.primary_ttl,
+            });
+        }
         packets.extend(self.additional);
         packets
     }
 }
 
+pub const SCHEDULE_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> NaiveDateTime;
+}
+
+pub struct LocalClock;
+
+impl Clock for LocalClock {
+    fn now(&self) -> NaiveDateTime {
+        chrono::Local::now().naive_local()
+    }
+}
+
+type RuleTransforms = HashMap<String, HashMap<TransformType, BoxedTransform>>;
+
+// Everything a reload replaces sits behind one lock, so a packet never sees
+// the rules of one config paired with the transforms of another.
+struct ActiveConfig {
+    config: Arc<Config>,
+    transforms: HashMap<TransformType, BoxedTransform>,
+    rule_transforms: RuleTransforms,
+    rules: RuleIndex,
+}
+
+impl ActiveConfig {
+    fn find_rule(&self, key: &FlowKey, direction: FlowDirection, hostname: Option<&str>) -> Option<Arc<CompiledRule>> {
+        let rule = self.rules.find(key, direction, hostname)?;
+        trace!(
+            flow = ?key,
+            rule = %rule.name,
+            "matched rule"
+        );
+        Some(rule.clone())
+    }
+    
+    fn transforms_for(&self, rule: &str) -> &HashMap<TransformType, BoxedTransform> {
+        self.rule_transforms.get(rule).unwrap_or(&self.transforms)
+    }
+    
+    fn all_transforms(&self) -> impl Iterator<Item = &BoxedTransform> {
+        self.transforms.values().chain(self.rule_transforms.values().flat_map(HashMap::values))
+    }
+}
+
 pub struct Pipeline {
-    config: RwLock<Arc<Config>>,
+    active: RwLock<ActiveConfig>,
     flow_cache: FlowCache,
     stats: Arc<Stats>,    
-    transforms: RwLock<HashMap<TransformType, BoxedTransform>>,    
-    compiled_rules: RwLock<Vec<CompiledRule>>,
+    clock: Arc<dyn Clock>,
+    log_limiter: LogRateLimiter,
+    paused: Arc<AtomicBool>,
 }
 
 struct CompiledRule {
+    name: Arc<str>,
     rule: Rule,    
     dst_nets: Vec<IpNet>,    
     src_nets: Vec<IpNet>,
+    domains: DomainSet,
+    counters: Arc<RuleCounters>,
+    schedule_active: AtomicBool,
+}
+
+/// Compiled rules in priority order, plus the indices of the rules that can
+/// match each destination port. Rules without `dst_ports` are candidates for
+/// every port; both lists are ascending, so merging them keeps priority order.
+#[derive(Default)]
+struct RuleIndex {
+    rules: Vec<Arc<CompiledRule>>,
+    by_dst_port: HashMap<u16, Vec<usize>>,
+    any_port: Vec<usize>,
+}
+
+impl RuleIndex {
+    fn new(rules: Vec<Arc<CompiledRule>>) -> Self {
+        let mut by_dst_port: HashMap<u16, Vec<usize>> = HashMap::new();
+        let mut any_port = Vec::new();
+        
+        for (idx, rule) in rules.iter().enumerate() {
+            match rule.rule.match_criteria.dst_ports {
+                Some(ref ports) => {
+                    for port in ports {
+                        let candidates = by_dst_port.entry(*port).or_default();
+                        if candidates.last() != Some(&idx) {
+                            candidates.push(idx);
+                        }
+                    }
+                }
+                None => any_port.push(idx),
+            }
+        }
+        
+        Self {
+            rules,
+            by_dst_port,
+            any_port,
+        }
+    }
+    
+    fn find(&self, key: &FlowKey, direction: FlowDirection, hostname: Option<&str>) -> Option<&Arc<CompiledRule>> {
+        let by_port = self.by_dst_port.get(&key.dst_port).map_or(&[][..], Vec::as_slice);
+        let (mut i, mut j) = (0, 0);
+        
+        loop {
+            let idx = match (by_port.get(i), self.any_port.get(j)) {
+                (Some(&a), Some(&b)) if a < b => {
+                    i += 1;
+                    a
+                }
+                (_, Some(&b)) => {
+                    j += 1;
+                    b
+                }
+                (Some(&a), None) => {
+                    i += 1;
+                    a
+                }
+                (None, None) => return None,
+            };
+            
+            let rule = &self.rules[idx];
+            if rule.matches(key, direction, hostname) {
+                return Some(rule);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct RuleCounters {
+    hits: AtomicU64,
+    bytes: AtomicU64,
+    last_hit_ms: AtomicU64,
+}
+
+impl RuleCounters {
+    fn record(&self, bytes: usize) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        self.last_hit_ms.store(now_ms, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuleStats {
+    pub name: String,
+    pub hits: u64,
+    pub bytes: u64,
+    /// Unix time in milliseconds of the most recent match.
+    pub last_hit: Option<u64>,
 }
 
 impl CompiledRule {
@@ -97,16 +321,38 @@ impl CompiledRule {
             None => Vec::new(),
         };
         
+        let domains = rule.match_criteria
+            .domain_patterns()?
+            .iter()
+            .map(String::as_str)
+            .collect();
+        
         Ok(Self {
+            name: rule.name.as_str().into(),
             rule,
             dst_nets,
             src_nets,
+            domains,
+            counters: Arc::new(RuleCounters::default()),
+            schedule_active: AtomicBool::new(true),
         })
     }
-
-    fn matches(&self, key: &FlowKey) -> bool {
+    
+    fn refresh_schedule(&self, now: &NaiveDateTime) {
+        let active = match self.rule.match_criteria.active_hours {
+            Some(ref window) => window.is_active_at(now),
+            None => true,
+        };
+        self.schedule_active.store(active, Ordering::Relaxed);
+    }
+    
+    fn matches(&self, key: &FlowKey, direction: FlowDirection, hostname: Option<&str>) -> bool {
         let criteria = &self.rule.match_criteria;
         
+        if criteria.direction.is_some_and(|d| d != direction) || !self.schedule_active.load(Ordering::Relaxed) {
+            return false;
+        }
+        
         if let Some(ref protocols) = criteria.protocols {
             if !protocols.contains(&key.protocol) {
                 return false;
@@ -139,6 +385,14 @@ impl CompiledRule {
             }
         }
         
+        if !self.domains.is_empty() {
+            match hostname {
+                Some(host) if !self.domains.matches(host) => return false,
+                None if criteria.require_hostname => return false,
+                _ => {}
+            }
+        }
+        
         true
     }
 }
@@ -148,20 +402,62 @@ impl Pipeline {
         config.validate()?;
         
         let flow_cache = FlowCache::new(&config.limits);
-        let transforms = Self::create_transforms(&config);
-        let compiled_rules = Self::compile_rules(&config.rules)?;
+        let clock: Arc<dyn Clock> = Arc::new(LocalClock);
+        let log_limiter = LogRateLimiter::new(config.limits.log_rate_limit);
+        let active = ActiveConfig {
+            transforms: Self::create_transforms(&config.transforms),
+            rule_transforms: Self::create_rule_transforms(&config)?,
+            rules: Self::compile_rules(&config.rules, &RuleIndex::default(), &clock.now())?,
+            config: Arc::new(config),
+        };
         
         Ok(Self {
-            config: RwLock::new(Arc::new(config)),
+            active: RwLock::new(active),
             flow_cache,
             stats,
-            transforms: RwLock::new(transforms),
-            compiled_rules: RwLock::new(compiled_rules),
+            clock,
+            log_limiter,
+            paused: Arc::new(AtomicBool::new(false)),
         })
     }
-
-    fn create_transforms(config: &Config) -> HashMap<TransformType, BoxedTransform> {
-        let params = &config.transforms;
+    
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self.refresh_schedules();
+        self
+    }
+    
+    pub fn with_pause_flag(mut self, paused: Arc<AtomicBool>) -> Self {
+        self.paused = paused;
+        self
+    }
+    
+    /// Passes every packet through as if `global.enabled` were false, until
+    /// [`Pipeline::resume`]. Flows and their state are kept.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+    
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::Relaxed);
+    }
+    
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+    
+    pub fn pause_flag(&self) -> Arc<AtomicBool> {
+        self.paused.clone()
+    }
+    
+    pub fn refresh_schedules(&self) {
+        let now = self.clock.now();
+        for rule in &self.active.read().rules.rules {
+            rule.refresh_schedule(&now);
+        }
+    }
+    
+    fn create_transforms(params: &TransformParams) -> HashMap<TransformType, BoxedTransform> {
         let mut transforms: HashMap<TransformType, BoxedTransform> = HashMap::new();
         
         transforms.insert(
@@ -188,11 +484,30 @@ impl Pipeline {
             TransformType::Decoy,
             Box::new(DecoyTransform::new(&params.decoy)),
         );
+        transforms.insert(
+            TransformType::Reorder,
+            Box::new(ReorderTransform::new(&params.reorder)),
+        );
+        transforms.insert(
+            TransformType::Fake,
+            Box::new(FakeTransform::new(&params.fake)),
+        );
         
         transforms
     }
 
-    fn compile_rules(rules: &[Rule]) -> Result<Vec<CompiledRule>> {
+    fn create_rule_transforms(config: &Config) -> Result<HashMap<String, HashMap<TransformType, BoxedTransform>>> {
+        let mut rule_transforms = HashMap::new();
+        
+        for rule in config.rules.iter().filter(|r| r.enabled && !r.overrides.is_empty()) {
+            let params = config.transforms.with_overrides(&rule.overrides)?;
+            rule_transforms.insert(rule.name.clone(), Self::create_transforms(&params));
+        }
+        
+        Ok(rule_transforms)
+    }
+    
+    fn compile_rules(rules: &[Rule], previous: &RuleIndex, now: &NaiveDateTime) -> Result<RuleIndex> {
         let mut compiled: Vec<CompiledRule> = rules
             .iter()
             .filter(|r| r.enabled)
@@ -200,108 +515,212 @@ impl Pipeline {
             .map(CompiledRule::compile)
             .collect::<Result<Vec<_>>>()?;
         
-        compiled.sort_by(|a, b| b.rule.priority.cmp(&a.rule.priority));
+        for rule in &mut compiled {
+            if let Some(old) = previous.rules.iter().find(|old| old.name == rule.name) {
+                rule.counters = old.counters.clone();
+            }
+            rule.refresh_schedule(now);
+        }
         
-        Ok(compiled)
+        compiled.sort_by_key(|c| std::cmp::Reverse(c.rule.priority));
+        
+        Ok(RuleIndex::new(compiled.into_iter().map(Arc::new).collect()))
     }
-
+    
+    /// Builds the per-rule transforms and the rule index for `config`,
+    /// counting a failure in [`Stats::rule_compile_errors`].
+    fn prepare_rules(&self, config: &Config, previous: &RuleIndex) -> Result<(RuleTransforms, RuleIndex)> {
+        Self::create_rule_transforms(config)
+            .and_then(|transforms| {
+                let compiled = Self::compile_rules(&config.rules, previous, &self.clock.now())?;
+                Ok((transforms, compiled))
+            })
+            .inspect_err(|_| self.stats.record_rule_compile_error())
+    }
+    
     pub fn reload_config(&self, new_config: Config) -> Result<()> {
         new_config.validate()?;
         
-        let new_transforms = Self::create_transforms(&new_config);
-        let new_compiled = Self::compile_rules(&new_config.rules)?;
+        // The upgradable guard keeps other writers out while the new rules
+        // are built, without blocking packets until the swap itself.
+        let active = self.active.upgradable_read();
+        let new_transforms = Self::create_transforms(&new_config.transforms);
+        let (new_rule_transforms, new_rules) = self.prepare_rules(&new_config, &active.rules)?;
         
-        {
-            let mut transforms = self.transforms.write();
-            *transforms = new_transforms;
-        }
-        {
-            let mut compiled = self.compiled_rules.write();
-            *compiled = new_compiled;
-        }
-        {
-            let mut config = self.config.write();
-            *config = Arc::new(new_config);
-        }
+        let mut active = RwLockUpgradableReadGuard::upgrade(active);
+        active.all_transforms().for_each(|t| t.reset());
+        self.log_limiter.set_rate(new_config.limits.log_rate_limit);
+        *active = ActiveConfig {
+            config: Arc::new(new_config),
+            transforms: new_transforms,
+            rule_transforms: new_rule_transforms,
+            rules: new_rules,
+        };
         
         debug!("Configuration reloaded successfully");
         Ok(())
     }
 
+    pub fn update_rules(&self, rules: Vec<Rule>) -> Result<()> {
+        let active = self.active.upgradable_read();
+        let mut new_config = Config::clone(&active.config);
+        new_config.rules = rules;
+        new_config.validate().inspect_err(|_| self.stats.record_rule_compile_error())?;
+        
+        let (new_rule_transforms, new_rules) = self.prepare_rules(&new_config, &active.rules)?;
+        
+        let mut active = RwLockUpgradableReadGuard::upgrade(active);
+        active.rule_transforms.values().flat_map(HashMap::values).for_each(|t| t.reset());
+        active.rule_transforms = new_rule_transforms;
+        active.rules = new_rules;
+        active.config = Arc::new(new_config);
+        
+        debug!("Rules updated successfully");
+        Ok(())
+    }
+
     pub fn config(&self) -> Arc<Config> {
-        self.config.read().clone()
+        self.active.read().config.clone()
     }
 
-    fn find_matching_rule(&self, key: &FlowKey) -> Option<Rule> {
-        let compiled = self.compiled_rules.read();
-        
-        for compiled_rule in compiled.iter() {
-            if compiled_rule.matches(key) {
-                trace!(
-                    flow = ?key,
-                    rule = %compiled_rule.rule.name,
-                    "matched rule"
-                );
-                return Some(compiled_rule.rule.clone());
-            }
-        }
-        
-        None
+    pub fn rule_stats(&self) -> Vec<RuleStats> {
+        self.active
+            .read()
+            .rules
+            .rules
+            .iter()
+            .map(|compiled| {
+                let last_hit = compiled.counters.last_hit_ms.load(Ordering::Relaxed);
+                RuleStats {
+                    name: compiled.name.to_string(),
+                    hits: compiled.counters.hits.load(Ordering::Relaxed),
+                    bytes: compiled.counters.bytes.load(Ordering::Relaxed),
+                    last_hit: (last_hit > 0).then_some(last_hit),
+                }
+            })
+            .collect()
+    }
+    
+    #[cfg(test)]
+    fn find_matching_rule(
+        &self,
+        key: &FlowKey,
+        direction: FlowDirection,
+        hostname: Option<&str>,
+    ) -> Option<Arc<CompiledRule>> {
+        self.active.read().find_rule(key, direction, hostname)
+    }
+    
+    /// Name of the rule a flow to `hostname` would match, if any.
+    pub fn matching_rule(&self, key: &FlowKey, direction: FlowDirection, hostname: Option<&str>) -> Option<Arc<str>> {
+        self.active.read().find_rule(key, direction, hostname).map(|rule| rule.name.clone())
+    }
+
+    pub fn process(&self, key: FlowKey, data: BytesMut) -> Result<PipelineOutput> {
+        self.process_with_meta(key, data, PacketMeta::outbound())
+    }
+
+    pub fn process_inbound(&self, key: FlowKey, data: BytesMut) -> Result<PipelineOutput> {
+        self.process_with_meta(key, data, PacketMeta::inbound())
     }
 
-    pub fn process(&self, key: FlowKey, mut data: BytesMut) -> Result<PipelineOutput> {
-        let config = self.config.read().clone();
+    pub fn process_with_meta(&self, key: FlowKey, mut data: BytesMut, meta: PacketMeta) -> Result<PipelineOutput> {
+        let direction = meta.direction;
+        let active = self.active.read();
+        let config = active.config.clone();
         
-        if !config.global.enabled {
+        if !config.global.enabled || self.is_paused() {
             return Ok(PipelineOutput::passthrough(data));
         }
         
         self.stats.record_packet_in(data.len());
         
+        if direction == FlowDirection::Inbound {
+            let hostname = meta.hostname.clone().or_else(|| self.flow_cache.hostname(&key));
+            if active.find_rule(&key, direction, hostname.as_deref()).is_none() {
+                if let Some(ref host) = hostname {
+                    self.stats.hosts.record(host, |c| c.bytes_down += data.len() as u64);
+                }
+                return Ok(PipelineOutput::passthrough(data));
+            }
+        }
+        
         let mut flow_state = self.flow_cache.get_or_create(key);
         let is_new_flow = flow_state.packet_count == 0;
         
+        let host_learned = is_new_flow || flow_state.hostname.is_none();
+        if flow_state.hostname.is_none() {
+            flow_state.hostname = meta.hostname;
+        }
+        if let Some(ref host) = flow_state.hostname {
+            let len = data.len() as u64;
+            self.stats.hosts.record(host, |c| match direction {
+                FlowDirection::Outbound => {
+                    c.connections += host_learned as u64;
+                    c.bytes_up += len;
+                }
+                FlowDirection::Inbound => c.bytes_down += len,
+            });
+        }
+        
         if is_new_flow {
             self.stats.record_flow_created();
+            self.sync_flow_memory();
         }
         
-        let matched_rule = self.find_matching_rule(&key);
+        let matched_rule = active.find_rule(&key, direction, flow_state.hostname.as_deref());
         
-        if matched_rule.is_some() {
-            self.stats.record_match();
-        }
-        
-        let rule = match matched_rule {
-            Some(r) => r,
+        let compiled = match matched_rule {
+            Some(compiled) => {
+                self.stats.record_match();
+                compiled.counters.record(data.len());
+                compiled
+            }
             None => {
                 flow_state.update(data.len());
                 self.flow_cache.update(flow_state);
+                if direction == FlowDirection::Outbound {
+                    self.stats.record_packet_out(data.len());
+                }
                 return Ok(PipelineOutput::passthrough(data));
             }
         };
         
-        let rule_ref = &rule;
-        let mut ctx = FlowContext::new(&key, &mut flow_state, Some(rule_ref));
+        let rule = &compiled.rule;
+        let admitted = match rule.action {
+            RuleAction::Transform => true,
+            RuleAction::Drop => false,
+            RuleAction::RateLimit { pps } => flow_state.transform_state.rate_limit.admit(pps, Instant::now()),
+        };
+        if !admitted {
+            flow_state.update(data.len());
+            flow_state.matched_rule = Some(compiled.name.clone());
+            self.flow_cache.update(flow_state);
+            self.stats.record_drop();
+            return Ok(PipelineOutput {
+                matched_rule: Some(compiled.name.clone()),
+                ..PipelineOutput::dropped()
+            });
+        }
+        
+        let mut ctx = FlowContext::new(&key, &mut flow_state, Some(rule))
+            .with_direction(direction)
+            .with_stream(meta.stream);
         
-        let transforms = self.transforms.read();
+        let transforms = active.transforms_for(&compiled.name);
+        let mut failures = 0u64;
         
         for transform_type in &rule.transforms {
-            let enabled = match transform_type {
-                TransformType::Fragment => config.global.enable_fragmentation,
-                TransformType::Jitter => config.global.enable_jitter,
-                TransformType::Padding => config.global.enable_padding,
-                TransformType::HeaderNormalization => config.global.enable_header_normalization,
-                _ => true,
-            };
-            
-            if !enabled {
+            if !transform_enabled(&config, *transform_type) {
                 continue;
             }
             
             let transform = match transforms.get(transform_type) {
                 Some(t) => t,
                 None => {
-                    warn!(transform = ?transform_type, "transform not found");
+                    if self.log_limiter.allow() {
+                        warn!(transform = ?transform_type, "transform not found");
+                    }
                     continue;
                 }
             };
@@ -316,11 +735,14 @@ impl Pipeline {
                 Ok(r) => r,
                 Err(e) => {
                     self.stats.record_transform_error();
-                    warn!(
-                        transform = transform.name(),
-                        error = %e,
-                        "transform error"
-                    );
+                    failures += 1;
+                    if self.log_limiter.allow() {
+                        warn!(
+                            transform = transform.name(),
+                            error = %e,
+                            "transform error"
+                        );
+                    }
                     continue;
                 }
             };
@@ -334,7 +756,11 @@ impl Pipeline {
                 }
                 TransformResult::Delay => {
                     self.stats.record_transform();
-                    if let Some(delay) = ctx.delay {
+                    let delay: Duration = ctx.delay
+                        .into_iter()
+                        .chain(ctx.output_packets.iter().filter_map(|p| p.delay_before))
+                        .sum();
+                    if !delay.is_zero() {
                         self.stats.record_jitter(delay.as_millis() as u64);
                     }
                 }
@@ -347,20 +773,34 @@ impl Pipeline {
                 }
                 TransformResult::Error(msg) => {
                     self.stats.record_transform_error();
-                    warn!(transform = transform.name(), error = %msg, "transform error");
+                    failures += 1;
+                    if self.log_limiter.allow() {
+                        warn!(transform = transform.name(), error = %msg, "transform error");
+                    }
                 }
             }
         }
         
         ctx.state.update(data.len());
-        ctx.state.matched_rule = Some(rule.name.clone());
+        let first_transform = ctx.state.matched_rule.is_none();
+        ctx.state.matched_rule = Some(compiled.name.clone());
+        if let Some(ref host) = ctx.state.hostname {
+            if first_transform || failures > 0 {
+                self.stats.hosts.record(host, |c| {
+                    c.bypass_applied += first_transform as u64;
+                    c.failures += failures;
+                });
+            }
+        }
         
         let should_drop = ctx.drop;
         let output_packets = std::mem::take(&mut ctx.output_packets);
+        let (primary_kind, primary_ttl) = (ctx.data_kind, ctx.data_ttl);
         let delay = ctx.delay;
+        let padding_added = ctx.padding_added;
         
-        drop(transforms);
         drop(ctx);
+        drop(active);
         
         self.flow_cache.update(flow_state);
         
@@ -369,9 +809,23 @@ impl Pipeline {
             return Ok(PipelineOutput::dropped());
         }
         
-        self.stats.record_packet_out(data.len());
-        for packet in &output_packets {
-            self.stats.record_packet_out(packet.len());
+        if padding_added > 0 {
+            self.stats.record_padding(padding_added);
+        }
+        
+        if direction == FlowDirection::Outbound {
+            self.stats.record_packet_out(data.len());
+            for packet in &output_packets {
+                self.stats.record_packet_out(packet.data.len());
+            }
+            let (fakes, fake_bytes) = std::iter::once((primary_kind, data.len()))
+                .chain(output_packets.iter().map(|p| (p.kind, p.data.len())))
+                .filter(|&(kind, _)| kind == EmitKind::Fake)
+                .fold((0, 0), |(count, bytes), (_, len)| (count + 1, bytes + len));
+            if fakes > 0 {
+                self.stats.record_decoys(fakes);
+                self.stats.record_decoy_bytes(fake_bytes);
+            }
         }
         
         Ok(PipelineOutput {
@@ -379,9 +833,137 @@ impl Pipeline {
             additional: output_packets,
             delay,
             dropped: false,
-            matched_rule: Some(rule.name),
+            matched_rule: Some(compiled.name.clone()),
+            primary_kind,
+            primary_ttl,
         })
     }
+    
+    /// Records a connection relayed by a backend that shapes traffic itself as
+    /// a flow and matches it against the rules without running any transforms.
+    /// Returns false if a `Drop` rule matched.
+    pub fn admit_connection(&self, key: FlowKey, meta: PacketMeta, len: usize) -> bool {
+        let active = self.active.read();
+        
+        let mut flow_state = self.flow_cache.get_or_create(key);
+        if flow_state.packet_count == 0 {
+            self.stats.record_flow_created();
+            self.sync_flow_memory();
+        }
+        if flow_state.hostname.is_none() {
+            flow_state.hostname = meta.hostname;
+        }
+        flow_state.update(len);
+        
+        let matched_rule = if active.config.global.enabled && !self.is_paused() {
+            active.find_rule(&key, meta.direction, flow_state.hostname.as_deref())
+        } else {
+            None
+        };
+        let admitted = match matched_rule {
+            Some(compiled) => {
+                self.stats.record_match();
+                compiled.counters.record(len);
+                flow_state.matched_rule = Some(compiled.name.clone());
+                compiled.rule.action != RuleAction::Drop
+            }
+            None => true,
+        };
+        if !admitted {
+            self.stats.record_drop();
+        }
+        
+        self.flow_cache.update(flow_state);
+        admitted
+    }
+
+    pub fn explain(&self, key: FlowKey, sample_len: usize) -> Explanation {
+        self.explain_with_meta(key, &PacketMeta::outbound(), sample_len)
+    }
+
+    pub fn explain_with_meta(&self, key: FlowKey, meta: &PacketMeta, sample_len: usize) -> Explanation {
+        let active = self.active.read();
+        let config = &active.config;
+        let hostname = meta.hostname.clone().or_else(|| self.flow_cache.hostname(&key));
+        
+        let mut explanation = Explanation {
+            pipeline_enabled: config.global.enabled && !self.is_paused(),
+            matched_rule: None,
+            hostname: hostname.clone(),
+            transforms: Vec::new(),
+            skipped: Vec::new(),
+            output_sizes: vec![sample_len],
+            delay_ms: None,
+            dropped: false,
+        };
+        
+        if !explanation.pipeline_enabled {
+            return explanation;
+        }
+        
+        let compiled = match active.find_rule(&key, meta.direction, hostname.as_deref()) {
+            Some(compiled) => compiled,
+            None => return explanation,
+        };
+        let rule = &compiled.rule;
+        explanation.matched_rule = Some(rule.name.clone());
+        if rule.action == RuleAction::Drop {
+            explanation.dropped = true;
+            explanation.output_sizes = Vec::new();
+            return explanation;
+        }
+        let params = config.transforms
+            .with_overrides(&rule.overrides)
+            .unwrap_or_else(|_| config.transforms.clone());
+        
+        let mut state = FlowState::new(key);
+        state.hostname = hostname;
+        let mut data = BytesMut::zeroed(sample_len);
+        let mut ctx = FlowContext::new(&key, &mut state, Some(rule))
+            .with_direction(meta.direction)
+            .with_stream(meta.stream);
+        
+        let transforms = active.transforms_for(&compiled.name);
+        
+        for transform_type in &rule.transforms {
+            let transform = match transforms.get(transform_type) {
+                Some(t) if transform_enabled(config, *transform_type) => t,
+                _ => {
+                    explanation.skipped.push(*transform_type);
+                    continue;
+                }
+            };
+            
+            explanation.transforms.push(TransformPlan {
+                transform: *transform_type,
+                params: transform_params(&params, *transform_type),
+            });
+            
+            match transform.apply(&mut ctx, &mut data) {
+                Ok(TransformResult::Drop) => {
+                    ctx.mark_drop();
+                    break;
+                }
+                Ok(TransformResult::Skip) => break,
+                _ => {}
+            }
+        }
+        
+        explanation.dropped = ctx.drop;
+        explanation.delay_ms = ctx.delay.map(|d| d.as_millis() as u64);
+        explanation.output_sizes = if ctx.drop {
+            Vec::new()
+        } else {
+            std::iter::once(data.len())
+                .chain(ctx.output_packets.iter().map(|p| p.data.len()))
+                .collect()
+        };
+        explanation
+    }
+
+    pub fn set_flow_hostname(&self, key: FlowKey, hostname: String) {
+        self.flow_cache.set_hostname(key, hostname);
+    }
 
     pub fn flow_cache(&self) -> &FlowCache {
         &self.flow_cache
@@ -390,22 +972,68 @@ impl Pipeline {
     pub fn stats(&self) -> &Arc<Stats> {
         &self.stats
     }
+    
+    pub fn log_limiter(&self) -> &LogRateLimiter {
+        &self.log_limiter
+    }
 
     pub fn cleanup(&self) -> usize {
         let evicted = self.flow_cache.cleanup();
-        for _ in 0..evicted {
-            self.stats.record_flow_evicted();
+        if !evicted.is_empty() {
+            let active = self.active.read();
+            for (key, state) in &evicted {
+                self.stats.record_flow_evicted();
+                for transform in active.all_transforms() {
+                    transform.on_flow_evicted(key, state);
+                }
+            }
         }
-        evicted
+        self.sync_flow_memory();
+        self.log_limiter.flush();
+        evicted.len()
+    }
+
+    fn sync_flow_memory(&self) {
+        let evicted = self.flow_cache.take_memory_evictions();
+        if evicted > 0 {
+            debug!(evicted, "evicted flows to stay within the memory budget");
+            self.stats.record_memory_evictions(evicted);
+        }
+        self.stats.set_flow_memory(self.flow_cache.memory_used());
+    }
+}
+
+fn transform_enabled(config: &Config, transform_type: TransformType) -> bool {
+    match transform_type {
+        TransformType::Fragment => config.global.enable_fragmentation,
+        TransformType::Jitter => config.global.enable_jitter,
+        TransformType::Padding => config.global.enable_padding,
+        TransformType::HeaderNormalization => config.global.enable_header_normalization,
+        _ => true,
     }
 }
 
+fn transform_params(params: &TransformParams, transform_type: TransformType) -> serde_json::Value {
+    let section = match transform_type {
+        TransformType::Fragment => serde_json::to_value(&params.fragment),
+        TransformType::Resegment => serde_json::to_value(&params.resegment),
+        TransformType::Padding => serde_json::to_value(&params.padding),
+        TransformType::Jitter => serde_json::to_value(&params.jitter),
+        TransformType::HeaderNormalization => serde_json::to_value(&params.header),
+        TransformType::Decoy => serde_json::to_value(&params.decoy),
+        TransformType::Reorder => serde_json::to_value(&params.reorder),
+        TransformType::Fake => serde_json::to_value(&params.fake),
+    };
+    section.unwrap_or(serde_json::Value::Null)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::net::Ipv4Addr;
     use crate::config::{MatchCriteria, Protocol};
-
+    use crate::transform::Transform;
+    
     fn test_config() -> Config {
         let mut config = Config::default();
         config.rules.push(Rule {
@@ -417,6 +1045,7 @@ mod tests {
                 protocols: Some(vec![Protocol::Tcp]),
                 ..Default::default()
             },
+            action: RuleAction::Transform,
             transforms: vec![TransformType::Fragment, TransformType::Padding],
             overrides: HashMap::new(),
         });
@@ -448,12 +1077,12 @@ mod tests {
         let pipeline = Pipeline::new(config, stats).unwrap();
         
         let key_443 = test_flow_key(443);
-        let rule = pipeline.find_matching_rule(&key_443);
+        let rule = pipeline.find_matching_rule(&key_443, FlowDirection::Outbound, None);
         assert!(rule.is_some());
-        assert_eq!(rule.unwrap().name, "test-https");
+        assert_eq!(&*rule.unwrap().name, "test-https");
         
         let key_80 = test_flow_key(80);
-        let rule = pipeline.find_matching_rule(&key_80);
+        let rule = pipeline.find_matching_rule(&key_80, FlowDirection::Outbound, None);
         assert!(rule.is_none());
     }
 
@@ -474,6 +1103,11 @@ mod tests {
         assert!(output.primary.is_some());
         assert_eq!(output.primary.unwrap(), data);
         assert!(output.additional.is_empty());
+        
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.packets_in, 1);
+        assert_eq!(snapshot.packets_out, 1);
+        assert_eq!(snapshot.bytes_out, data.len() as u64);
     }
 
     #[test]
@@ -493,6 +1127,30 @@ mod tests {
         assert!(output.primary.is_some());
         assert_eq!(output.primary.unwrap(), data);
     }
+    
+    #[test]
+    fn test_pipeline_pause_resume() {
+        let pipeline = Pipeline::new(test_config(), Arc::new(Stats::new())).unwrap();
+        let key = test_flow_key(443);
+        let data = BytesMut::from(&b"This is a longer test message for fragmentation testing"[..]);
+        
+        let output = pipeline.process(key, data.clone()).unwrap();
+        assert!(output.matched_rule.is_some());
+        assert!(!output.additional.is_empty());
+        
+        pipeline.pause();
+        assert!(pipeline.is_paused());
+        let output = pipeline.process(key, data.clone()).unwrap();
+        assert!(output.matched_rule.is_none());
+        assert_eq!(output.all_packets(), vec![data.clone()]);
+        assert!(!pipeline.explain(key, data.len()).pipeline_enabled);
+        assert_eq!(pipeline.flow_cache().len(), 1);
+        
+        pipeline.resume();
+        let output = pipeline.process(key, data).unwrap();
+        assert!(output.matched_rule.is_some());
+        assert!(!output.additional.is_empty());
+    }
 
     #[test]
     fn test_pipeline_transform_application() {
@@ -530,6 +1188,84 @@ mod tests {
         assert_eq!(snapshot.packets_matched, 1);
     }
 
+    #[test]
+    fn test_pipeline_inbound_direction() {
+        let mut config = test_config();
+        config.rules.push(Rule {
+            name: "inbound-https".to_string(),
+            enabled: true,
+            priority: 5,
+            match_criteria: MatchCriteria {
+                src_ports: Some(vec![443]),
+                direction: Some(FlowDirection::Inbound),
+                ..Default::default()
+            },
+            action: RuleAction::Transform,
+            transforms: vec![TransformType::Padding],
+            overrides: HashMap::new(),
+        });
+        
+        let stats = Arc::new(Stats::new());
+        let pipeline = Pipeline::new(config, stats.clone()).unwrap();
+        
+        // Rules without a direction match both ways.
+        let key = test_flow_key(443);
+        assert_eq!(pipeline.find_matching_rule(&key, FlowDirection::Inbound, None).map(|r| r.name.clone()).as_deref(), Some("test-https"));
+        
+        let output = pipeline.process_inbound(key.reverse(), BytesMut::from(&b"response"[..])).unwrap();
+        assert_eq!(output.matched_rule.as_deref(), Some("inbound-https"));
+        
+        let output = pipeline.process(key.reverse(), BytesMut::from(&b"response"[..])).unwrap();
+        assert!(output.matched_rule.is_none());
+        
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.packets_in, 2);
+        assert_eq!(snapshot.packets_out, 1);
+    }
+    
+    #[test]
+    fn test_pipeline_direction_limits_transforms() {
+        let mut config = Config::default();
+        config.transforms.header.normalize_ttl = true;
+        config.transforms.header.ttl_value = 128;
+        config.transforms.decoy.send_after = true;
+        config.transforms.decoy.probability = 1.0;
+        for (name, direction, match_criteria) in [
+            ("outbound", FlowDirection::Outbound, MatchCriteria { dst_ports: Some(vec![443]), ..Default::default() }),
+            ("inbound", FlowDirection::Inbound, MatchCriteria { src_ports: Some(vec![443]), ..Default::default() }),
+        ] {
+            config.rules.push(Rule {
+                name: name.to_string(),
+                enabled: true,
+                priority: 10,
+                match_criteria: MatchCriteria { direction: Some(direction), ..match_criteria },
+                action: RuleAction::Transform,
+                transforms: vec![TransformType::HeaderNormalization, TransformType::Decoy],
+                overrides: HashMap::new(),
+            });
+        }
+        
+        let pipeline = Pipeline::new(config, Arc::new(Stats::new())).unwrap();
+        let key = test_flow_key(443);
+        let packet = BytesMut::from(&[
+            0x45, 0x00, 0x00, 0x28, 0x12, 0x34, 0x00, 0x00,
+            0x40, 0x06, 0x00, 0x00, 192, 168, 1, 1,
+            8, 8, 8, 8, 0x30, 0x39, 0x01, 0xBB,
+            0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+            0x50, 0x02, 0x72, 0x10, 0x00, 0x00, 0x00, 0x00,
+        ][..]);
+        
+        let output = pipeline.process(key, packet.clone()).unwrap();
+        assert_eq!(output.matched_rule.as_deref(), Some("outbound"));
+        assert_eq!(output.primary.unwrap()[8], 128);
+        assert_eq!(output.additional.len(), 1);
+        
+        let output = pipeline.process_inbound(key.reverse(), packet.clone()).unwrap();
+        assert_eq!(output.matched_rule.as_deref(), Some("inbound"));
+        assert_eq!(output.primary.unwrap(), packet);
+        assert!(output.additional.is_empty());
+    }
+
     #[test]
     fn test_pipeline_config_reload() {
         let config = test_config();
@@ -545,6 +1281,7 @@ mod tests {
                 dst_ports: Some(vec![8080]),
                 ..Default::default()
             },
+            action: RuleAction::Transform,
             transforms: vec![TransformType::Padding],
             overrides: HashMap::new(),
         });
@@ -558,9 +1295,9 @@ mod tests {
             8080,
             Protocol::Tcp,
         );
-        let rule = pipeline.find_matching_rule(&key);
+        let rule = pipeline.find_matching_rule(&key, FlowDirection::Outbound, None);
         assert!(rule.is_some());
-        assert_eq!(rule.unwrap().name, "new-rule");
+        assert_eq!(&*rule.unwrap().name, "new-rule");
     }
 
     #[test]
@@ -572,6 +1309,7 @@ mod tests {
             enabled: true,
             priority: 0,
             match_criteria: MatchCriteria::default(),
+            action: RuleAction::Transform,
             transforms: vec![TransformType::Padding],
             overrides: HashMap::new(),
         });
@@ -584,6 +1322,7 @@ mod tests {
                 dst_ports: Some(vec![443]),
                 ..Default::default()
             },
+            action: RuleAction::Transform,
             transforms: vec![TransformType::Fragment],
             overrides: HashMap::new(),
         });
@@ -592,9 +1331,9 @@ mod tests {
         let pipeline = Pipeline::new(config, stats).unwrap();
         
         let key = test_flow_key(443);
-        let rule = pipeline.find_matching_rule(&key);
+        let rule = pipeline.find_matching_rule(&key, FlowDirection::Outbound, None);
         assert!(rule.is_some());
-        assert_eq!(rule.unwrap().name, "specific");
+        assert_eq!(&*rule.unwrap().name, "specific");
     }
 
     #[test]
@@ -608,6 +1347,7 @@ mod tests {
                 dst_ip: Some(vec!["8.8.8.0/24".to_string()]),
                 ..Default::default()
             },
+            action: RuleAction::Transform,
             transforms: vec![TransformType::Padding],
             overrides: HashMap::new(),
         });
@@ -622,7 +1362,7 @@ mod tests {
             53,
             Protocol::Udp,
         );
-        assert!(pipeline.find_matching_rule(&key1).is_some());
+        assert!(pipeline.find_matching_rule(&key1, FlowDirection::Outbound, None).is_some());
         
         let key2 = FlowKey::new(
             IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)),
@@ -631,6 +1371,618 @@ mod tests {
             53,
             Protocol::Udp,
         );
-        assert!(pipeline.find_matching_rule(&key2).is_none());
+        assert!(pipeline.find_matching_rule(&key2, FlowDirection::Outbound, None).is_none());
+    }
+
+    fn domain_config(require_hostname: bool) -> Config {
+        let mut config = test_config();
+        config.rules.push(Rule {
+            name: "blocked-sites".to_string(),
+            enabled: true,
+            priority: 20,
+            match_criteria: MatchCriteria {
+                dst_ports: Some(vec![443]),
+                domains: Some(vec!["discord.com".to_string(), "*.youtube.com".to_string()]),
+                require_hostname,
+                ..Default::default()
+            },
+            action: RuleAction::Transform,
+            transforms: vec![TransformType::Fragment],
+            overrides: HashMap::new(),
+        });
+        config
+    }
+
+    #[test]
+    fn test_domain_rule_matching() {
+        let pipeline = Pipeline::new(domain_config(true), Arc::new(Stats::new())).unwrap();
+        
+        let unknown = test_flow_key(443);
+        let output = pipeline.process(unknown, BytesMut::from(&b"hello"[..])).unwrap();
+        assert_eq!(output.matched_rule.as_deref(), Some("test-https"));
+        
+        let mut discord = test_flow_key(443);
+        discord.src_port = 40001;
+        pipeline.set_flow_hostname(discord, "Discord.com".to_string());
+        let output = pipeline.process(discord, BytesMut::from(&b"hello"[..])).unwrap();
+        assert_eq!(output.matched_rule.as_deref(), Some("blocked-sites"));
+        
+        let mut youtube = test_flow_key(443);
+        youtube.src_port = 40002;
+        pipeline.set_flow_hostname(youtube, "www.youtube.com".to_string());
+        let output = pipeline.process(youtube, BytesMut::from(&b"hello"[..])).unwrap();
+        assert_eq!(output.matched_rule.as_deref(), Some("blocked-sites"));
+        
+        let mut other = test_flow_key(443);
+        other.src_port = 40003;
+        pipeline.set_flow_hostname(other, "example.com".to_string());
+        let output = pipeline.process(other, BytesMut::from(&b"hello"[..])).unwrap();
+        assert_eq!(output.matched_rule.as_deref(), Some("test-https"));
+    }
+
+    #[test]
+    fn test_rule_overrides_fragment_size() {
+        let mut config = Config::default();
+        config.transforms.fragment.randomize = false;
+        for (name, port, max_size) in [("small", 443, 2), ("large", 8443, 16)] {
+            config.rules.push(Rule {
+                name: name.to_string(),
+                enabled: true,
+                priority: 10,
+                match_criteria: MatchCriteria {
+                    dst_ports: Some(vec![port]),
+                    ..Default::default()
+                },
+                action: RuleAction::Transform,
+                transforms: vec![TransformType::Fragment],
+                overrides: HashMap::from([("fragment.max_size".to_string(), serde_json::json!(max_size))]),
+            });
+        }
+        let pipeline = Pipeline::new(config.clone(), Arc::new(Stats::new())).unwrap();
+        let payload = [0x42u8; 32];
+        
+        let output = pipeline.process(test_flow_key(443), BytesMut::from(&payload[..])).unwrap();
+        let packets = output.all_packets();
+        assert_eq!(packets.len(), 16);
+        assert!(packets.iter().all(|p| p.len() == 2));
+        
+        let output = pipeline.process(test_flow_key(8443), BytesMut::from(&payload[..])).unwrap();
+        let packets = output.all_packets();
+        assert_eq!(packets.len(), 2);
+        assert!(packets.iter().all(|p| p.len() == 16));
+        
+        config.rules[0].overrides.insert("fragment.max_size".to_string(), serde_json::json!(8));
+        pipeline.reload_config(config).unwrap();
+        let output = pipeline.process(test_flow_key(443), BytesMut::from(&payload[..])).unwrap();
+        assert_eq!(output.all_packets().len(), 4);
+    }
+
+    #[test]
+    fn test_rule_stats_survive_reload() {
+        let config = test_config();
+        let pipeline = Pipeline::new(config.clone(), Arc::new(Stats::new())).unwrap();
+        
+        pipeline.process(test_flow_key(443), BytesMut::from(&b"hello"[..])).unwrap();
+        pipeline.process(test_flow_key(443), BytesMut::from(&b"world!"[..])).unwrap();
+        pipeline.process(test_flow_key(80), BytesMut::from(&b"ignored"[..])).unwrap();
+        
+        let stats = pipeline.rule_stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].name, "test-https");
+        assert_eq!(stats[0].hits, 2);
+        assert_eq!(stats[0].bytes, 11);
+        assert!(stats[0].last_hit.is_some());
+        
+        let mut reloaded = config.clone();
+        reloaded.rules[0].priority = 50;
+        let mut renamed = reloaded.rules[0].clone();
+        renamed.name = "test-https-renamed".to_string();
+        renamed.match_criteria.dst_ports = Some(vec![8443]);
+        reloaded.rules.push(renamed);
+        pipeline.reload_config(reloaded).unwrap();
+        
+        let stats = pipeline.rule_stats();
+        let kept = stats.iter().find(|s| s.name == "test-https").unwrap();
+        assert_eq!(kept.hits, 2);
+        let fresh = stats.iter().find(|s| s.name == "test-https-renamed").unwrap();
+        assert_eq!(fresh.hits, 0);
+        assert_eq!(fresh.last_hit, None);
+    }
+
+    #[test]
+    fn test_update_rules() {
+        let pipeline = Pipeline::new(test_config(), Arc::new(Stats::new())).unwrap();
+        pipeline.process(test_flow_key(443), BytesMut::from(&b"hello"[..])).unwrap();
+        
+        let mut rules = pipeline.config().rules.clone();
+        let mut http = rules[0].clone();
+        http.name = "test-http".to_string();
+        http.match_criteria.dst_ports = Some(vec![80]);
+        rules.push(http);
+        pipeline.update_rules(rules.clone()).unwrap();
+        
+        let output = pipeline.process(test_flow_key(80), BytesMut::from(&b"hello"[..])).unwrap();
+        assert_eq!(output.matched_rule.as_deref(), Some("test-http"));
+        assert_eq!(pipeline.rule_stats().iter().find(|r| r.name == "test-https").unwrap().hits, 1);
+        
+        rules[1].transforms.clear();
+        assert!(pipeline.update_rules(rules.clone()).is_err());
+        assert_eq!(pipeline.config().rules[1].transforms.len(), 2);
+        
+        rules[1].transforms = vec![TransformType::Padding];
+        rules[1].enabled = false;
+        pipeline.update_rules(rules).unwrap();
+        let output = pipeline.process(test_flow_key(80), BytesMut::from(&b"hello"[..])).unwrap();
+        assert_eq!(output.matched_rule, None);
+    }
+
+    #[test]
+    fn test_memory_evictions_reported() {
+        let mut config = Config::default();
+        config.limits.max_memory_mb = 1;
+        config.limits.max_flows = 100_000;
+        let stats = Arc::new(Stats::new());
+        let pipeline = Pipeline::new(config, stats.clone()).unwrap();
+        
+        for port in 0..20_000u16 {
+            let mut key = test_flow_key(443);
+            key.src_port = port;
+            pipeline.process(key, BytesMut::from(&b"x"[..])).unwrap();
+        }
+        
+        let snapshot = stats.snapshot();
+        assert!(snapshot.memory_evictions > 0);
+        assert_eq!(snapshot.active_flows, pipeline.flow_cache().len() as u64);
+        assert!(snapshot.flow_memory_bytes <= 1024 * 1024);
+        assert!(snapshot.flow_memory_bytes > 0);
+    }
+
+    #[test]
+    fn test_explain_matches_process() {
+        let mut config = test_config();
+        config.transforms.fragment.randomize = false;
+        config.transforms.fragment.max_size = 8;
+        config.transforms.padding.max_bytes = 0;
+        config.rules[0].overrides.insert("fragment.max_size".to_string(), serde_json::json!(4));
+        let stats = Arc::new(Stats::new());
+        let pipeline = Pipeline::new(config, stats.clone()).unwrap();
+        
+        let key = test_flow_key(443);
+        let explanation = pipeline.explain(key, 10);
+        assert!(pipeline.flow_cache().is_empty());
+        assert_eq!(stats.snapshot().packets_in, 0);
+        assert_eq!(pipeline.rule_stats()[0].hits, 0);
+        
+        assert_eq!(explanation.matched_rule.as_deref(), Some("test-https"));
+        let planned: Vec<TransformType> = explanation.transforms.iter().map(|t| t.transform).collect();
+        assert_eq!(planned, vec![TransformType::Fragment, TransformType::Padding]);
+        assert_eq!(explanation.transforms[0].params["max_size"], 4);
+        
+        let output = pipeline.process(key, BytesMut::zeroed(10)).unwrap();
+        assert_eq!(output.matched_rule.as_deref(), explanation.matched_rule.as_deref());
+        let sizes: Vec<usize> = output.all_packets().iter().map(|p| p.len()).collect();
+        assert_eq!(sizes, explanation.output_sizes);
+        assert_eq!(sizes, vec![4, 4, 2]);
+        
+        let unmatched = pipeline.explain(test_flow_key(80), 10);
+        assert_eq!(unmatched.matched_rule, None);
+        assert_eq!(unmatched.output_sizes, vec![10]);
+    }
+
+    #[test]
+    fn test_explain_reports_disabled_transforms() {
+        let mut config = domain_config(true);
+        config.global.enable_fragmentation = false;
+        let pipeline = Pipeline::new(config, Arc::new(Stats::new())).unwrap();
+        
+        let meta = PacketMeta::outbound().with_hostname(Some("discord.com".to_string()));
+        let explanation = pipeline.explain_with_meta(test_flow_key(443), &meta, 100);
+        assert_eq!(explanation.matched_rule.as_deref(), Some("blocked-sites"));
+        assert!(explanation.transforms.is_empty());
+        assert_eq!(explanation.skipped, vec![TransformType::Fragment]);
+        assert_eq!(explanation.output_sizes, vec![100]);
+    }
+    
+    #[test]
+    fn test_admit_connection() {
+        let mut config = domain_config(true);
+        config.rules[1].action = RuleAction::Drop;
+        config.rules[1].transforms.clear();
+        let stats = Arc::new(Stats::new());
+        let pipeline = Pipeline::new(config, stats.clone()).unwrap();
+        
+        let meta = |host: &str| PacketMeta::outbound().with_hostname(Some(host.to_string())).in_stream();
+        let mut blocked = test_flow_key(443);
+        blocked.src_port = 40001;
+        assert!(!pipeline.admit_connection(blocked, meta("discord.com"), 100));
+        assert!(pipeline.admit_connection(test_flow_key(443), meta("example.com"), 100));
+        
+        assert_eq!(pipeline.flow_cache().len(), 2);
+        assert_eq!(pipeline.flow_cache().hostname(&blocked).as_deref(), Some("discord.com"));
+        let snapshot = stats.snapshot();
+        assert_eq!((snapshot.flows_created, snapshot.packets_dropped, snapshot.packets_in), (2, 1, 0));
+        let hits: Vec<u64> = pipeline.rule_stats().iter().map(|rule| rule.hits).collect();
+        assert_eq!(hits, vec![1, 1]);
+    }
+    
+    #[test]
+    fn test_emitted_keeps_packet_metadata() {
+        let output = PipelineOutput {
+            primary: Some(BytesMut::from(&b"decoy"[..])),
+            additional: vec![
+                BytesMut::from(&b"real"[..]).into(),
+                EmittedPacket {
+                    delay_before: Some(Duration::from_millis(5)),
+                    ..BytesMut::from(&b"rest"[..]).into()
+                },
+            ],
+            delay: Some(Duration::from_millis(20)),
+            dropped: false,
+            matched_rule: None,
+            primary_kind: EmitKind::Fake,
+            primary_ttl: Some(2),
+        };
+        
+        let emitted = output.emitted();
+        assert_eq!(emitted.len(), 3);
+        assert_eq!(emitted[0].kind, EmitKind::Fake);
+        assert_eq!(emitted[0].ttl_override, Some(2));
+        assert_eq!(emitted[0].delay_before, Some(Duration::from_millis(20)));
+        assert_eq!(emitted[1], BytesMut::from(&b"real"[..]).into());
+        assert_eq!(emitted[2].delay_before, Some(Duration::from_millis(5)));
+    }
+    
+    #[test]
+    fn test_fake_goes_out_before_fragments() {
+        let mut config = test_config();
+        config.rules[0].transforms = vec![TransformType::Fragment, TransformType::Fake];
+        let stats = Arc::new(Stats::new());
+        let pipeline = Pipeline::new(config, stats.clone()).unwrap();
+        let key = test_flow_key(443);
+        
+        let real: Vec<u8> = (0..200u8).collect();
+        let meta = PacketMeta::outbound().in_stream();
+        let emitted = pipeline.process_with_meta(key, BytesMut::from(&real[..]), meta.clone()).unwrap().emitted();
+        assert!(emitted.len() > 2);
+        assert_eq!(emitted[0].data, &b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n"[..]);
+        assert_eq!(emitted[0].kind, EmitKind::Fake);
+        assert!(emitted[1..].iter().all(|p| p.kind == EmitKind::Real));
+        let rest: Vec<u8> = emitted[1..].iter().flat_map(|p| p.data.to_vec()).collect();
+        assert_eq!(rest, real);
+        assert_eq!(stats.snapshot().decoys_sent, 1);
+        
+        let emitted = pipeline.process_with_meta(key, BytesMut::from(&real[..]), meta).unwrap().emitted();
+        assert!(emitted.iter().all(|p| p.kind == EmitKind::Real));
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.decoys_sent, 1);
+        assert_eq!(snapshot.decoy_bytes_sent, b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n".len() as u64);
+    }
+    
+    #[derive(Default)]
+    struct RecordingTransform {
+        evicted: parking_lot::Mutex<Vec<FlowKey>>,
+        resets: AtomicU64,
+    }
+    
+    impl Transform for Arc<RecordingTransform> {
+        fn name(&self) -> &'static str {
+            "recording"
+        }
+        
+        fn apply(&self, _ctx: &mut FlowContext<'_>, _data: &mut BytesMut) -> Result<TransformResult> {
+            Ok(TransformResult::Continue)
+        }
+        
+        fn on_flow_evicted(&self, key: &FlowKey, _state: &FlowState) {
+            self.evicted.lock().push(*key);
+        }
+        
+        fn reset(&self) {
+            self.resets.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    
+    #[test]
+    fn test_transform_hooks() {
+        let mut config = test_config();
+        config.limits.flow_timeout_secs = 0;
+        let pipeline = Pipeline::new(config.clone(), Arc::new(Stats::new())).unwrap();
+        let global = Arc::new(RecordingTransform::default());
+        let per_rule = Arc::new(RecordingTransform::default());
+        let mut active = pipeline.active.write();
+        active.transforms.insert(TransformType::Jitter, Box::new(global.clone()));
+        active.rule_transforms
+            .entry("test-https".to_string())
+            .or_default()
+            .insert(TransformType::Jitter, Box::new(per_rule.clone()));
+        drop(active);
+        
+        let key = test_flow_key(443);
+        pipeline.process(key, BytesMut::from(&b"data"[..])).unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(pipeline.cleanup(), 1);
+        assert_eq!(*global.evicted.lock(), vec![key]);
+        assert_eq!(*per_rule.evicted.lock(), vec![key]);
+        assert_eq!(pipeline.cleanup(), 0);
+        assert_eq!(global.evicted.lock().len(), 1);
+        
+        assert_eq!(global.resets.load(Ordering::Relaxed), 0);
+        pipeline.reload_config(config).unwrap();
+        assert_eq!(global.resets.load(Ordering::Relaxed), 1);
+        assert_eq!(per_rule.resets.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_process_with_meta_records_hostname() {
+        let pipeline = Pipeline::new(domain_config(true), Arc::new(Stats::new())).unwrap();
+        let key = test_flow_key(443);
+        
+        let meta = PacketMeta::outbound().with_hostname(Some("discord.com".to_string()));
+        let output = pipeline.process_with_meta(key, BytesMut::from(&b"hello"[..]), meta).unwrap();
+        assert_eq!(output.matched_rule.as_deref(), Some("blocked-sites"));
+        
+        let meta = PacketMeta::outbound().with_hostname(Some("example.com".to_string()));
+        let output = pipeline.process_with_meta(key, BytesMut::from(&b"hello"[..]), meta).unwrap();
+        assert_eq!(output.matched_rule.as_deref(), Some("blocked-sites"));
+        
+        let output = pipeline.process(key, BytesMut::from(&b"hello"[..])).unwrap();
+        assert_eq!(output.matched_rule.as_deref(), Some("blocked-sites"));
+        
+        let flows = pipeline.flow_cache().iter_snapshot();
+        assert_eq!(flows.len(), 1);
+        assert_eq!(flows[0].hostname.as_deref(), Some("discord.com"));
+        assert_eq!(flows[0].matched_rule.as_deref(), Some("blocked-sites"));
+        assert_eq!(flows[0].packet_count, 3);
+    }
+    
+    #[test]
+    fn test_rejected_rules_counted() {
+        let stats = Arc::new(Stats::new());
+        let pipeline = Pipeline::new(test_config(), stats.clone()).unwrap();
+        
+        let mut rules = test_config().rules;
+        rules[0].match_criteria.dst_ip = Some(vec!["not-an-ip".to_string()]);
+        assert!(pipeline.update_rules(rules).is_err());
+        assert_eq!(stats.snapshot().rule_compile_errors, 1);
+        
+        assert!(pipeline.update_rules(test_config().rules).is_ok());
+        assert_eq!(stats.snapshot().rule_compile_errors, 1);
+    }
+    
+    #[test]
+    fn test_host_stats_per_flow() {
+        let stats = Arc::new(Stats::new());
+        let pipeline = Pipeline::new(domain_config(false), stats.clone()).unwrap();
+        
+        for (port, host) in [(40001, "discord.com"), (40002, "discord.com"), (40003, "example.org")] {
+            let mut key = test_flow_key(443);
+            key.src_port = port;
+            let meta = PacketMeta::outbound().with_hostname(Some(host.to_string()));
+            pipeline.process_with_meta(key, BytesMut::from(&b"hello"[..]), meta.clone()).unwrap();
+            pipeline.process_with_meta(key, BytesMut::from(&b"world!"[..]), meta).unwrap();
+            
+            let meta = PacketMeta::inbound().with_hostname(Some(host.to_string()));
+            pipeline.process_with_meta(key.reverse(), BytesMut::from(&b"response"[..]), meta).unwrap();
+        }
+        
+        let mut unnamed = test_flow_key(443);
+        unnamed.src_port = 40004;
+        pipeline.process(unnamed, BytesMut::from(&b"hello"[..])).unwrap();
+        
+        let discord = stats.hosts.get("discord.com").unwrap();
+        assert_eq!(discord.connections, 2);
+        assert_eq!(discord.bytes_up, 22);
+        assert_eq!(discord.bytes_down, 16);
+        assert_eq!(discord.bypass_applied, 2);
+        assert_eq!(discord.failures, 0);
+        
+        let other = stats.hosts.get("example.org").unwrap();
+        assert_eq!((other.connections, other.bytes_up, other.bypass_applied), (1, 11, 1));
+        assert_eq!(stats.hosts.len(), 2);
+        assert_eq!(stats.hosts.top(1)[0].host, "discord.com");
+    }
+
+    #[test]
+    fn test_domain_rule_deferred_without_hostname() {
+        let pipeline = Pipeline::new(domain_config(false), Arc::new(Stats::new())).unwrap();
+        
+        let key = test_flow_key(443);
+        assert_eq!(pipeline.find_matching_rule(&key, FlowDirection::Outbound, None).map(|r| r.name.clone()).as_deref(), Some("blocked-sites"));
+        assert_eq!(
+            pipeline.find_matching_rule(&key, FlowDirection::Outbound, Some("example.com")).map(|r| r.name.clone()).as_deref(),
+            Some("test-https")
+        );
+    }
+
+    #[test]
+    fn test_domains_file_reread_on_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("blocklist.txt");
+        std::fs::write(&path, "# mirrors\n*.mirror.example\n\nstatic.example  # cdn\n").unwrap();
+        
+        let mut config = domain_config(true);
+        config.rules[1].match_criteria.domains = None;
+        config.rules[1].match_criteria.domains_file = Some(path.clone());
+        let pipeline = Pipeline::new(config.clone(), Arc::new(Stats::new())).unwrap();
+        
+        let key = test_flow_key(443);
+        let rule = |host: &str| pipeline.find_matching_rule(&key, FlowDirection::Outbound, Some(host)).map(|r| r.name.clone());
+        assert_eq!(rule("a.mirror.example").as_deref(), Some("blocked-sites"));
+        assert_eq!(rule("static.example").as_deref(), Some("blocked-sites"));
+        assert_eq!(rule("discord.com").as_deref(), Some("test-https"));
+        
+        std::fs::write(&path, "discord.com\n").unwrap();
+        pipeline.reload_config(config.clone()).unwrap();
+        assert_eq!(rule("discord.com").as_deref(), Some("blocked-sites"));
+        assert_eq!(rule("a.mirror.example").as_deref(), Some("test-https"));
+        
+        std::fs::write(&path, "bad pattern\n").unwrap();
+        assert!(pipeline.reload_config(config.clone()).is_err());
+        
+        dir.close().unwrap();
+        let err = Pipeline::new(config, Arc::new(Stats::new())).err().unwrap().to_string();
+        assert!(err.contains("cannot read"), "{}", err);
+    }
+    
+    #[test]
+    fn test_invalid_domain_pattern() {
+        let mut config = domain_config(true);
+        config.rules[1].match_criteria.domains = Some(vec!["disc*rd.com".to_string()]);
+        assert!(Pipeline::new(config, Arc::new(Stats::new())).is_err());
+    }
+    
+    struct TestClock(parking_lot::Mutex<NaiveDateTime>);
+    
+    impl TestClock {
+        fn at(hour: u32, minute: u32) -> NaiveDateTime {
+            chrono::NaiveDate::from_ymd_opt(2024, 1, 5).unwrap().and_hms_opt(hour, minute, 0).unwrap()
+        }
+    }
+    
+    impl Clock for TestClock {
+        fn now(&self) -> NaiveDateTime {
+            *self.0.lock()
+        }
+    }
+    
+    #[test]
+    fn test_active_hours_wrap_midnight() {
+        let mut config = test_config();
+        config.rules.push(Rule {
+            name: "night".to_string(),
+            enabled: true,
+            priority: 20,
+            match_criteria: MatchCriteria {
+                dst_ports: Some(vec![443]),
+                active_hours: Some(crate::config::TimeWindow {
+                    start: "22:00".to_string(),
+                    end: "02:00".to_string(),
+                    days: Vec::new(),
+                }),
+                ..Default::default()
+            },
+            action: RuleAction::Transform,
+            transforms: vec![TransformType::Padding],
+            overrides: HashMap::new(),
+        });
+        
+        let clock = Arc::new(TestClock(parking_lot::Mutex::new(TestClock::at(12, 0))));
+        let pipeline = Pipeline::new(config, Arc::new(Stats::new())).unwrap().with_clock(clock.clone());
+        let rule = |pipeline: &Pipeline| {
+            pipeline.find_matching_rule(&test_flow_key(443), FlowDirection::Outbound, None).map(|r| r.name.clone())
+        };
+        assert_eq!(rule(&pipeline).as_deref(), Some("test-https"));
+        
+        *clock.0.lock() = TestClock::at(23, 15);
+        assert_eq!(rule(&pipeline).as_deref(), Some("test-https"));
+        pipeline.refresh_schedules();
+        assert_eq!(rule(&pipeline).as_deref(), Some("night"));
+        
+        *clock.0.lock() = TestClock::at(1, 59);
+        pipeline.refresh_schedules();
+        assert_eq!(rule(&pipeline).as_deref(), Some("night"));
+        
+        *clock.0.lock() = TestClock::at(2, 0);
+        pipeline.refresh_schedules();
+        assert_eq!(rule(&pipeline).as_deref(), Some("test-https"));
+    }
+    
+    #[test]
+    fn test_matched_rule_outlives_update() {
+        let pipeline = Pipeline::new(domain_config(false), Arc::new(Stats::new())).unwrap();
+        let key = test_flow_key(443);
+        
+        let matched = pipeline.find_matching_rule(&key, FlowDirection::Outbound, Some("www.youtube.com")).unwrap();
+        assert_eq!(&*matched.name, "blocked-sites");
+        assert_eq!(pipeline.matching_rule(&key, FlowDirection::Outbound, Some("example.com")).as_deref(), Some("test-https"));
+        
+        pipeline.update_rules(Vec::new()).unwrap();
+        assert!(pipeline.find_matching_rule(&key, FlowDirection::Outbound, None).is_none());
+        assert_eq!(matched.rule.transforms, vec![TransformType::Fragment]);
+    }
+    
+    struct XorShift(u64);
+    
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+        
+        fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+        
+        fn pick<T: Clone>(&mut self, items: &[T]) -> T {
+            items[self.below(items.len() as u64) as usize].clone()
+        }
+        
+        fn maybe<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> Option<T> {
+            (self.below(2) == 0).then(|| f(self))
+        }
+    }
+    
+    fn random_rule(rng: &mut XorShift, idx: usize) -> Rule {
+        const PORTS: [u16; 5] = [53, 80, 443, 8080, 8443];
+        let ports = |rng: &mut XorShift| (0..rng.below(3)).map(|_| rng.pick(&PORTS)).collect::<Vec<_>>();
+        
+        Rule {
+            name: format!("rule-{}", idx),
+            enabled: true,
+            priority: rng.below(4) as i32,
+            match_criteria: MatchCriteria {
+                dst_ip: rng.maybe(|rng| vec![rng.pick(&["8.8.8.0/24", "1.1.1.1", "10.0.0.0/8"]).to_string()]),
+                dst_ports: rng.maybe(ports),
+                src_ports: rng.maybe(ports),
+                protocols: rng.maybe(|rng| vec![rng.pick(&[Protocol::Tcp, Protocol::Udp])]),
+                domains: rng.maybe(|rng| vec![rng.pick(&["discord.com", "*.youtube.com"]).to_string()]),
+                require_hostname: rng.below(2) == 0,
+                direction: rng.maybe(|rng| rng.pick(&[FlowDirection::Outbound, FlowDirection::Inbound])),
+                ..Default::default()
+            },
+            action: RuleAction::Transform,
+            transforms: vec![TransformType::Padding],
+            overrides: HashMap::new(),
+        }
+    }
+    
+    #[test]
+    fn test_rule_index_matches_linear_scan() {
+        let mut rng = XorShift(0x9E37_79B9_7F4A_7C15);
+        let now = chrono::NaiveDate::from_ymd_opt(2024, 1, 5).unwrap().and_hms_opt(12, 0, 0).unwrap();
+        let mut matched = 0;
+        
+        for _ in 0..200 {
+            let rules: Vec<Rule> = (0..rng.below(20) as usize).map(|i| random_rule(&mut rng, i)).collect();
+            let index = Pipeline::compile_rules(&rules, &RuleIndex::default(), &now).unwrap();
+            
+            for _ in 0..50 {
+                let key = FlowKey::new(
+                    IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)),
+                    rng.pick(&["8.8.8.8", "1.1.1.1", "10.1.2.3", "9.9.9.9"]).parse().unwrap(),
+                    rng.pick(&[53, 80, 443, 8080, 8443, 12345]),
+                    rng.pick(&[53, 80, 443, 8080, 8443, 12345]),
+                    rng.pick(&[Protocol::Tcp, Protocol::Udp]),
+                );
+                let direction = rng.pick(&[FlowDirection::Outbound, FlowDirection::Inbound]);
+                let hostname = rng.pick(&[None, Some("discord.com"), Some("www.youtube.com"), Some("example.com")]);
+                
+                let indexed = index.find(&key, direction, hostname);
+                let linear = index.rules.iter().find(|r| r.matches(&key, direction, hostname));
+                assert_eq!(
+                    indexed.map(|r| &*r.name),
+                    linear.map(|r| &*r.name),
+                    "{:?} {:?} {:?} in {:?}",
+                    key,
+                    direction,
+                    hostname,
+                    rules,
+                );
+                matched += usize::from(indexed.is_some());
+            }
+        }
+        assert!(matched > 100, "{}", matched);
     }
 }
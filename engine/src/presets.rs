@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::bypass::BypassConfig;
+use crate::config::{Config, MatchCriteria, Protocol, Rule, RuleAction, TransformType};
+use crate::error::{EngineError, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConfigPreset {
+    TurkTelekom,
+    Vodafone,
+    Superonline,
+    Aggressive,
+    Gaming,
+    Streaming,
+}
+
+impl ConfigPreset {
+    pub const ALL: [ConfigPreset; 6] = [
+        ConfigPreset::TurkTelekom,
+        ConfigPreset::Vodafone,
+        ConfigPreset::Superonline,
+        ConfigPreset::Aggressive,
+        ConfigPreset::Gaming,
+        ConfigPreset::Streaming,
+    ];
+    
+    pub fn name(self) -> &'static str {
+        match self {
+            ConfigPreset::TurkTelekom => "turk-telekom",
+            ConfigPreset::Vodafone => "vodafone",
+            ConfigPreset::Superonline => "superonline",
+            ConfigPreset::Aggressive => "aggressive",
+            ConfigPreset::Gaming => "gaming",
+            ConfigPreset::Streaming => "streaming",
+        }
+    }
+    
+    pub fn description(self) -> &'static str {
+        match self {
+            ConfigPreset::TurkTelekom => "Türk Telekom: SNI fragments of up to 20 bytes, Host split at 2",
+            ConfigPreset::Vodafone => "Vodafone TR: SNI fragments of up to 30 bytes with a short delay, Host split at 3",
+            ConfigPreset::Superonline => "Superonline: SNI fragments of up to 15 bytes, Host split at 1",
+            ConfigPreset::Aggressive => "Tiny fragments, jitter and QUIC blocking for stubborn DPI",
+            ConfigPreset::Gaming => "Fragments HTTPS only, with no added delay and long-lived flows",
+            ConfigPreset::Streaming => "Blocks QUIC so video falls back to fragmented TCP",
+        }
+    }
+    
+    /// The bypass settings the preset's transform parameters are derived from.
+    pub fn bypass(self) -> BypassConfig {
+        match self {
+            ConfigPreset::TurkTelekom => BypassConfig::turk_telekom(),
+            ConfigPreset::Vodafone => BypassConfig::vodafone_tr(),
+            ConfigPreset::Superonline => BypassConfig::superonline(),
+            ConfigPreset::Aggressive => BypassConfig {
+                block_quic: true,
+                ..BypassConfig::aggressive()
+            },
+            ConfigPreset::Gaming => BypassConfig {
+                max_segment_size: 40,
+                ..BypassConfig::turk_telekom()
+            },
+            ConfigPreset::Streaming => BypassConfig {
+                block_quic: true,
+                ..BypassConfig::turk_telekom()
+            },
+        }
+    }
+    
+    /// Serializes the preset as TOML with a comment above each section.
+    pub fn to_commented_toml(self) -> Result<String> {
+        let body = toml::to_string_pretty(&Config::preset(self))
+            .map_err(|e| EngineError::Config(e.to_string()))?;
+        
+        let mut output = format!(
+            "# TurkeyDPI configuration generated from the `{}` preset.\n# {}\n\n",
+            self.name(),
+            self.description(),
+        );
+        let mut rules_seen = false;
+        for line in body.lines() {
+            let comment = match line {
+                "[global]" => Some("# Master switches for each family of transforms"),
+                "[[rules]]" if !rules_seen => {
+                    rules_seen = true;
+                    Some("# Rules are tried from the highest priority down; the first match handles the flow")
+                }
+                "[limits]" => Some("# Resource limits"),
+                "[dns]" => Some("# DNS-over-HTTPS resolution"),
+                "[backend]" => Some("# Backend to run: socks5, http_connect, bypass_http or tun"),
+                "[backend.bypass]" => Some("# Bypass settings used by the proxy backends"),
+                "[transforms.fragment]" => Some("# Segment sizes taken from the preset's bypass settings"),
+                "[transforms.jitter]" => Some("# Delay between fragments, in milliseconds"),
+                _ => None,
+            };
+            if let Some(comment) = comment {
+                output.push_str(comment);
+                output.push('\n');
+            }
+            output.push_str(line);
+            output.push('\n');
+        }
+        
+        Ok(output)
+    }
+}
+
+impl fmt::Display for ConfigPreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for ConfigPreset {
+    type Err = EngineError;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        let name = s.to_ascii_lowercase().replace('_', "-");
+        Self::ALL
+            .into_iter()
+            .find(|preset| preset.name() == name)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Self::ALL.iter().map(|p| p.name()).collect();
+                EngineError::Config(format!("unknown preset {} (expected one of: {})", s, names.join(", ")))
+            })
+    }
+}
+
+fn tcp_rule(name: &str, priority: i32, port: u16, transforms: Vec<TransformType>) -> Rule {
+    Rule {
+        name: name.to_string(),
+        enabled: true,
+        priority,
+        match_criteria: MatchCriteria {
+            dst_ports: Some(vec![port]),
+            protocols: Some(vec![Protocol::Tcp]),
+            ..Default::default()
+        },
+        action: RuleAction::Transform,
+        transforms,
+        overrides: HashMap::new(),
+    }
+}
+
+impl Config {
+    pub fn preset(preset: ConfigPreset) -> Self {
+        let bypass = preset.bypass();
+        let mut config = Config::default();
+        
+        let fragment = &mut config.transforms.fragment;
+        fragment.min_size = bypass.min_segment_size.max(1);
+        fragment.max_size = bypass.max_segment_size.max(fragment.min_size);
+        fragment.split_at_offset = (bypass.tls_split_pos > 0).then_some(bypass.tls_split_pos);
+        fragment.randomize = bypass.split_strategy.is_some();
+        
+        let delay_ms = bypass.fragment_delay_us.div_ceil(1000);
+        config.global.enable_jitter = delay_ms > 0;
+        config.transforms.jitter.min_ms = 0;
+        config.transforms.jitter.max_ms = delay_ms.min(config.limits.max_jitter_ms);
+        
+        config.global.enable_padding = false;
+        
+        let mut https = vec![TransformType::Fragment];
+        if delay_ms > 0 {
+            https.push(TransformType::Jitter);
+        }
+        config.rules.push(tcp_rule("https", 100, 443, https));
+        
+        if bypass.fragment_http_host {
+            let mut http = tcp_rule("http", 90, 80, vec![TransformType::Fragment]);
+            if bypass.http_split_pos > 0 {
+                http.overrides.insert("fragment.split_at_offset".to_string(), bypass.http_split_pos.into());
+            }
+            config.rules.push(http);
+        }
+        
+        if bypass.block_quic {
+            config.rules.push(Rule {
+                name: "block-quic".to_string(),
+                enabled: true,
+                priority: 150,
+                match_criteria: MatchCriteria {
+                    dst_ports: Some(vec![443]),
+                    protocols: Some(vec![Protocol::Udp]),
+                    ..Default::default()
+                },
+                action: RuleAction::Drop,
+                transforms: Vec::new(),
+                overrides: HashMap::new(),
+            });
+        }
+        
+        config.backend.bypass = bypass;
+        
+        match preset {
+            ConfigPreset::Gaming => config.limits.flow_timeout_secs = 600,
+            ConfigPreset::Streaming => config.limits.max_flows = 50_000,
+            _ => {}
+        }
+        
+        config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_presets_validate_and_round_trip() {
+        for preset in ConfigPreset::ALL {
+            let config = Config::preset(preset);
+            config.validate().unwrap_or_else(|e| panic!("{}: {}", preset, e));
+            assert!(config.warnings().is_empty(), "{}: {:?}", preset, config.warnings());
+            assert!(config.rules.iter().all(|r| !r.transforms.contains(&TransformType::Padding)), "{}", preset);
+            assert!(!config.rules.is_empty(), "{}", preset);
+            
+            let toml = preset.to_commented_toml().unwrap();
+            assert!(toml.starts_with("# TurkeyDPI configuration"));
+            let parsed = Config::from_toml(&toml).unwrap_or_else(|e| panic!("{}: {}", preset, e));
+            assert_eq!(parsed.rules.len(), config.rules.len());
+            
+            let json = serde_json::to_string(&config).unwrap();
+            Config::from_json(&json).unwrap_or_else(|e| panic!("{}: {}", preset, e));
+        }
+    }
+    
+    #[test]
+    fn test_preset_values() {
+        let telekom = Config::preset(ConfigPreset::TurkTelekom);
+        assert_eq!(telekom.transforms.fragment.max_size, BypassConfig::turk_telekom().max_segment_size);
+        assert_eq!(telekom.backend.bypass.http_split_pos, 2);
+        assert!(!telekom.global.enable_jitter);
+        let http = telekom.rules.iter().find(|r| r.name == "http").unwrap();
+        assert_eq!(http.overrides["fragment.split_at_offset"], serde_json::json!(2));
+        
+        let vodafone = Config::preset(ConfigPreset::Vodafone);
+        assert!(vodafone.global.enable_jitter);
+        assert_eq!(vodafone.transforms.jitter.max_ms, 1);
+        
+        let streaming = Config::preset(ConfigPreset::Streaming);
+        let quic = streaming.rules.iter().find(|r| r.name == "block-quic").unwrap();
+        assert_eq!(quic.action, RuleAction::Drop);
+    }
+    
+    #[test]
+    fn test_preset_from_str() {
+        for preset in ConfigPreset::ALL {
+            assert_eq!(preset.name().parse::<ConfigPreset>().unwrap(), preset);
+        }
+        assert_eq!("TURK_TELEKOM".parse::<ConfigPreset>().unwrap(), ConfigPreset::TurkTelekom);
+        let err = "ttnet".parse::<ConfigPreset>().unwrap_err().to_string();
+        assert!(err.contains("turk-telekom"), "{}", err);
+    }
+}
@@ -0,0 +1,144 @@
+pub const QUIC_VERSION_1: u32 = 0x0000_0001;
+pub const QUIC_VERSION_2: u32 = 0x6b33_43cf;
+
+pub const LONG_HEADER_FORM: u8 = 0x80;
+pub const FIXED_BIT: u8 = 0x40;
+
+pub const MAX_CONNECTION_ID_LEN: usize = 20;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QuicInitialInfo {
+    pub version: u32,
+    pub dcid: Vec<u8>,
+    pub scid: Vec<u8>,
+    pub token_length: usize,
+    pub payload_length: usize,
+    pub header_length: usize,
+}
+
+pub fn is_quic_initial(data: &[u8]) -> bool {
+    parse_quic_initial(data).is_some()
+}
+
+pub fn parse_quic_initial(data: &[u8]) -> Option<QuicInitialInfo> {
+    let first = *data.first()?;
+    if first & (LONG_HEADER_FORM | FIXED_BIT) != (LONG_HEADER_FORM | FIXED_BIT) {
+        return None;
+    }
+    
+    let version = u32::from_be_bytes(data.get(1..5)?.try_into().ok()?);
+    let packet_type = (first & 0x30) >> 4;
+    let initial_type = match version {
+        QUIC_VERSION_1 => 0b00,
+        QUIC_VERSION_2 => 0b01,
+        _ => return None,
+    };
+    if packet_type != initial_type {
+        return None;
+    }
+    
+    let mut pos = 5;
+    
+    let dcid_len = *data.get(pos)? as usize;
+    pos += 1;
+    if dcid_len > MAX_CONNECTION_ID_LEN {
+        return None;
+    }
+    let dcid = data.get(pos..pos + dcid_len)?.to_vec();
+    pos += dcid_len;
+    
+    let scid_len = *data.get(pos)? as usize;
+    pos += 1;
+    if scid_len > MAX_CONNECTION_ID_LEN {
+        return None;
+    }
+    let scid = data.get(pos..pos + scid_len)?.to_vec();
+    pos += scid_len;
+    
+    let (token_length, n) = read_varint(data.get(pos..)?)?;
+    pos += n;
+    let token_length = usize::try_from(token_length).ok()?;
+    if data.len() < pos + token_length {
+        return None;
+    }
+    pos += token_length;
+    
+    let (payload_length, n) = read_varint(data.get(pos..)?)?;
+    pos += n;
+    
+    Some(QuicInitialInfo {
+        version,
+        dcid,
+        scid,
+        token_length,
+        payload_length: usize::try_from(payload_length).ok()?,
+        header_length: pos,
+    })
+}
+
+pub fn read_varint(data: &[u8]) -> Option<(u64, usize)> {
+    let first = *data.first()?;
+    let len = 1 << (first >> 6);
+    let bytes = data.get(..len)?;
+    
+    let mut value = (first & 0x3f) as u64;
+    for &b in &bytes[1..] {
+        value = (value << 8) | b as u64;
+    }
+    
+    Some((value, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    // Client Initial from RFC 9001 Appendix A.2, header plus the start of the payload.
+    fn rfc9001_client_initial() -> Vec<u8> {
+        vec![
+            0xc0, 0x00, 0x00, 0x00, 0x01, 0x08, 0x83, 0x94,
+            0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08, 0x00, 0x00,
+            0x44, 0x9e, 0x7b, 0x9a, 0xec, 0x34, 0xd1, 0xb1,
+            0xc9, 0x8d, 0xd7, 0x68, 0x9f, 0xb8, 0xec, 0x11,
+        ]
+    }
+    
+    #[test]
+    fn test_parse_quic_v1_initial() {
+        let data = rfc9001_client_initial();
+        let info = parse_quic_initial(&data).unwrap();
+        
+        assert_eq!(info.version, QUIC_VERSION_1);
+        assert_eq!(info.dcid, vec![0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08]);
+        assert!(info.scid.is_empty());
+        assert_eq!(info.token_length, 0);
+        assert_eq!(info.payload_length, 1182);
+        assert_eq!(info.header_length, 18);
+    }
+    
+    #[test]
+    fn test_rejects_non_initial() {
+        let mut data = rfc9001_client_initial();
+        
+        data[0] = 0xe0;
+        assert!(!is_quic_initial(&data));
+        
+        data[0] = 0x40;
+        assert!(!is_quic_initial(&data));
+        
+        let mut unknown_version = rfc9001_client_initial();
+        unknown_version[4] = 0x02;
+        assert!(!is_quic_initial(&unknown_version));
+        
+        assert!(!is_quic_initial(&rfc9001_client_initial()[..10]));
+        assert!(!is_quic_initial(b"\x16\x03\x01\x00\x05hello"));
+    }
+    
+    #[test]
+    fn test_read_varint() {
+        assert_eq!(read_varint(&[0x25]), Some((37, 1)));
+        assert_eq!(read_varint(&[0x7b, 0xbd]), Some((15293, 2)));
+        assert_eq!(read_varint(&[0x9d, 0x7f, 0x3e, 0x7d]), Some((494878333, 4)));
+        assert_eq!(read_varint(&[0x7b]), None);
+    }
+}
@@ -1,6 +1,20 @@
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::Path;
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use dashmap::DashMap;
+use parking_lot::Mutex;
 use serde::{Serialize, Deserialize};
 
+use crate::error::Result;
+
+pub const DEFAULT_MAX_HOSTS: usize = 1024;
+pub const HISTORY_SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+/// Enough samples at [`HISTORY_SAMPLE_INTERVAL`] to cover five minutes.
+pub const DEFAULT_HISTORY_LEN: usize = 31;
+
 #[derive(Debug, Default)]
 pub struct Stats {
     pub packets_in: AtomicU64,
@@ -11,6 +25,8 @@ pub struct Stats {
     pub packets_matched: AtomicU64,    
     pub packets_transformed: AtomicU64,    
     pub transform_errors: AtomicU64,    
+    pub rule_compile_errors: AtomicU64,
+    pub invalid_packets: AtomicU64,
     pub active_flows: AtomicU64,    
     pub flows_created: AtomicU64,    
     pub flows_evicted: AtomicU64,    
@@ -18,6 +34,35 @@ pub struct Stats {
     pub fragments_generated: AtomicU64,
     pub total_jitter_ms: AtomicU64,
     pub decoys_sent: AtomicU64,
+    pub padding_bytes_added: AtomicU64,
+    pub decoy_bytes_sent: AtomicU64,
+    pub fragment_overhead_writes: AtomicU64,
+    pub socks_doh_resolved: AtomicU64,
+    pub dns_queries: AtomicU64,
+    pub connect_fallbacks: AtomicU64,
+    pub memory_evictions: AtomicU64,
+    pub connection_errors: AtomicU64,
+    pub dns_failures: AtomicU64,
+    pub connect_timeouts: AtomicU64,
+    pub connect_refused: AtomicU64,
+    pub relay_errors: AtomicU64,
+    pub tls_blocked_suspected: AtomicU64,
+    pub flow_memory_bytes: AtomicU64,
+    pub hosts: HostStats,
+    started_at: StartedAt,
+    /// Traffic counters carried over from a previous run, which the rates
+    /// since start leave out.
+    baseline: Mutex<StatsSnapshot>,
+    epoch: AtomicU64,
+}
+
+#[derive(Debug)]
+struct StartedAt(Mutex<Instant>);
+
+impl Default for StartedAt {
+    fn default() -> Self {
+        Self(Mutex::new(Instant::now()))
+    }
 }
 
 impl Stats {
@@ -50,6 +95,14 @@ impl Stats {
     pub fn record_transform_error(&self) {
         self.transform_errors.fetch_add(1, Ordering::Relaxed);
     }
+    
+    pub fn record_rule_compile_error(&self) {
+        self.rule_compile_errors.fetch_add(1, Ordering::Relaxed);
+    }
+    
+    pub fn record_invalid_packet(&self) {
+        self.invalid_packets.fetch_add(1, Ordering::Relaxed);
+    }
 
     pub fn record_flow_created(&self) {
         self.flows_created.fetch_add(1, Ordering::Relaxed);
@@ -58,7 +111,7 @@ impl Stats {
 
     pub fn record_flow_evicted(&self) {
         self.flows_evicted.fetch_add(1, Ordering::Relaxed);
-        self.active_flows.fetch_sub(1, Ordering::Relaxed);
+        self.release_flows(1);
     }
 
     pub fn record_queue_overflow(&self) {
@@ -77,12 +130,55 @@ impl Stats {
         self.decoys_sent.fetch_add(count as u64, Ordering::Relaxed);
     }
 
+    pub fn record_padding(&self, bytes: usize) {
+        self.padding_bytes_added.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+    
+    pub fn record_decoy_bytes(&self, bytes: usize) {
+        self.decoy_bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+    
+    /// Writes beyond the first that a backend needed to send split data.
+    pub fn record_fragment_overhead_writes(&self, count: u64) {
+        self.fragment_overhead_writes.fetch_add(count, Ordering::Relaxed);
+    }
+    
+    pub fn record_socks_doh_resolved(&self) {
+        self.socks_doh_resolved.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_connect_fallback(&self) {
+        self.connect_fallbacks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_memory_evictions(&self, count: u64) {
+        self.memory_evictions.fetch_add(count, Ordering::Relaxed);
+        self.flows_evicted.fetch_add(count, Ordering::Relaxed);
+        self.release_flows(count);
+    }
+    
+    // A reset can zero the gauge while flows are still alive, so their later
+    // eviction must not wrap it around.
+    fn release_flows(&self, count: u64) {
+        let _ = self.active_flows.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |active| Some(active.saturating_sub(count)));
+    }
+
+    pub fn set_flow_memory(&self, bytes: usize) {
+        self.flow_memory_bytes.store(bytes as u64, Ordering::Relaxed);
+    }
+
     pub fn set_active_flows(&self, count: usize) {
         self.active_flows.store(count as u64, Ordering::Relaxed);
     }
 
+    /// When counting started; [`Stats::reset`] restarts the clock.
+    pub fn started_at(&self) -> Instant {
+        *self.started_at.0.lock()
+    }
+    
     pub fn snapshot(&self) -> StatsSnapshot {
-        StatsSnapshot {
+        let elapsed = self.started_at().elapsed();
+        let mut snapshot = StatsSnapshot {
             packets_in: self.packets_in.load(Ordering::Relaxed),
             packets_out: self.packets_out.load(Ordering::Relaxed),
             bytes_in: self.bytes_in.load(Ordering::Relaxed),
@@ -91,6 +187,8 @@ impl Stats {
             packets_matched: self.packets_matched.load(Ordering::Relaxed),
             packets_transformed: self.packets_transformed.load(Ordering::Relaxed),
             transform_errors: self.transform_errors.load(Ordering::Relaxed),
+            rule_compile_errors: self.rule_compile_errors.load(Ordering::Relaxed),
+            invalid_packets: self.invalid_packets.load(Ordering::Relaxed),
             active_flows: self.active_flows.load(Ordering::Relaxed),
             flows_created: self.flows_created.load(Ordering::Relaxed),
             flows_evicted: self.flows_evicted.load(Ordering::Relaxed),
@@ -98,7 +196,34 @@ impl Stats {
             fragments_generated: self.fragments_generated.load(Ordering::Relaxed),
             total_jitter_ms: self.total_jitter_ms.load(Ordering::Relaxed),
             decoys_sent: self.decoys_sent.load(Ordering::Relaxed),
-        }
+            padding_bytes_added: self.padding_bytes_added.load(Ordering::Relaxed),
+            decoy_bytes_sent: self.decoy_bytes_sent.load(Ordering::Relaxed),
+            fragment_overhead_writes: self.fragment_overhead_writes.load(Ordering::Relaxed),
+            socks_doh_resolved: self.socks_doh_resolved.load(Ordering::Relaxed),
+            dns_queries: self.dns_queries.load(Ordering::Relaxed),
+            connect_fallbacks: self.connect_fallbacks.load(Ordering::Relaxed),
+            memory_evictions: self.memory_evictions.load(Ordering::Relaxed),
+            connection_errors: self.connection_errors.load(Ordering::Relaxed),
+            dns_failures: self.dns_failures.load(Ordering::Relaxed),
+            connect_timeouts: self.connect_timeouts.load(Ordering::Relaxed),
+            connect_refused: self.connect_refused.load(Ordering::Relaxed),
+            relay_errors: self.relay_errors.load(Ordering::Relaxed),
+            tls_blocked_suspected: self.tls_blocked_suspected.load(Ordering::Relaxed),
+            flow_memory_bytes: self.flow_memory_bytes.load(Ordering::Relaxed),
+            uptime_secs: elapsed.as_secs(),
+            pps_in: 0.0,
+            pps_out: 0.0,
+            bps_in: 0.0,
+            bps_out: 0.0,
+            rate_1m: None,
+            rate_5m: None,
+        };
+        let rates = Rates::over(&self.baseline.lock(), &snapshot, elapsed);
+        snapshot.pps_in = rates.pps_in;
+        snapshot.pps_out = rates.pps_out;
+        snapshot.bps_in = rates.bps_in;
+        snapshot.bps_out = rates.bps_out;
+        snapshot
     }
 
     pub fn reset(&self) {
@@ -110,6 +235,8 @@ impl Stats {
         self.packets_matched.store(0, Ordering::Relaxed);
         self.packets_transformed.store(0, Ordering::Relaxed);
         self.transform_errors.store(0, Ordering::Relaxed);
+        self.rule_compile_errors.store(0, Ordering::Relaxed);
+        self.invalid_packets.store(0, Ordering::Relaxed);
         self.active_flows.store(0, Ordering::Relaxed);
         self.flows_created.store(0, Ordering::Relaxed);
         self.flows_evicted.store(0, Ordering::Relaxed);
@@ -117,10 +244,236 @@ impl Stats {
         self.fragments_generated.store(0, Ordering::Relaxed);
         self.total_jitter_ms.store(0, Ordering::Relaxed);
         self.decoys_sent.store(0, Ordering::Relaxed);
+        self.padding_bytes_added.store(0, Ordering::Relaxed);
+        self.decoy_bytes_sent.store(0, Ordering::Relaxed);
+        self.fragment_overhead_writes.store(0, Ordering::Relaxed);
+        self.socks_doh_resolved.store(0, Ordering::Relaxed);
+        self.dns_queries.store(0, Ordering::Relaxed);
+        self.connect_fallbacks.store(0, Ordering::Relaxed);
+        self.memory_evictions.store(0, Ordering::Relaxed);
+        self.connection_errors.store(0, Ordering::Relaxed);
+        self.dns_failures.store(0, Ordering::Relaxed);
+        self.connect_timeouts.store(0, Ordering::Relaxed);
+        self.connect_refused.store(0, Ordering::Relaxed);
+        self.relay_errors.store(0, Ordering::Relaxed);
+        self.tls_blocked_suspected.store(0, Ordering::Relaxed);
+        self.hosts.clear();
+        *self.baseline.lock() = StatsSnapshot::default();
+        *self.started_at.0.lock() = Instant::now();
+    }
+    
+    pub fn save_to(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let persisted = PersistedStats {
+            epoch: self.epoch.fetch_add(1, Ordering::Relaxed) + 1,
+            snapshot: self.snapshot(),
+        };
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let mut tmp = tempfile::NamedTempFile::new_in(dir)?;
+        tmp.write_all(&serde_json::to_vec_pretty(&persisted)?)?;
+        tmp.as_file().sync_all()?;
+        tmp.persist(path).map_err(|e| e.error)?;
+        Ok(())
+    }
+    
+    /// Adds the counters saved at `path` to these ones, so totals resume
+    /// where the previous run left off. Gauges such as the active flows are
+    /// not carried over.
+    pub fn load_from(&self, path: impl AsRef<Path>) -> Result<()> {
+        let persisted: PersistedStats = serde_json::from_slice(&std::fs::read(path)?)?;
+        self.add_baseline(&persisted.snapshot);
+        self.epoch.fetch_max(persisted.epoch, Ordering::Relaxed);
+        Ok(())
+    }
+    
+    pub fn epoch(&self) -> u64 {
+        self.epoch.load(Ordering::Relaxed)
+    }
+    
+    fn add_baseline(&self, base: &StatsSnapshot) {
+        self.packets_in.fetch_add(base.packets_in, Ordering::Relaxed);
+        self.packets_out.fetch_add(base.packets_out, Ordering::Relaxed);
+        self.bytes_in.fetch_add(base.bytes_in, Ordering::Relaxed);
+        self.bytes_out.fetch_add(base.bytes_out, Ordering::Relaxed);
+        self.packets_dropped.fetch_add(base.packets_dropped, Ordering::Relaxed);
+        self.packets_matched.fetch_add(base.packets_matched, Ordering::Relaxed);
+        self.packets_transformed.fetch_add(base.packets_transformed, Ordering::Relaxed);
+        self.transform_errors.fetch_add(base.transform_errors, Ordering::Relaxed);
+        self.rule_compile_errors.fetch_add(base.rule_compile_errors, Ordering::Relaxed);
+        self.invalid_packets.fetch_add(base.invalid_packets, Ordering::Relaxed);
+        self.flows_created.fetch_add(base.flows_created, Ordering::Relaxed);
+        self.flows_evicted.fetch_add(base.flows_evicted, Ordering::Relaxed);
+        self.queue_overflows.fetch_add(base.queue_overflows, Ordering::Relaxed);
+        self.fragments_generated.fetch_add(base.fragments_generated, Ordering::Relaxed);
+        self.total_jitter_ms.fetch_add(base.total_jitter_ms, Ordering::Relaxed);
+        self.decoys_sent.fetch_add(base.decoys_sent, Ordering::Relaxed);
+        self.padding_bytes_added.fetch_add(base.padding_bytes_added, Ordering::Relaxed);
+        self.decoy_bytes_sent.fetch_add(base.decoy_bytes_sent, Ordering::Relaxed);
+        self.fragment_overhead_writes.fetch_add(base.fragment_overhead_writes, Ordering::Relaxed);
+        self.socks_doh_resolved.fetch_add(base.socks_doh_resolved, Ordering::Relaxed);
+        self.dns_queries.fetch_add(base.dns_queries, Ordering::Relaxed);
+        self.connect_fallbacks.fetch_add(base.connect_fallbacks, Ordering::Relaxed);
+        self.memory_evictions.fetch_add(base.memory_evictions, Ordering::Relaxed);
+        self.connection_errors.fetch_add(base.connection_errors, Ordering::Relaxed);
+        self.dns_failures.fetch_add(base.dns_failures, Ordering::Relaxed);
+        self.connect_timeouts.fetch_add(base.connect_timeouts, Ordering::Relaxed);
+        self.connect_refused.fetch_add(base.connect_refused, Ordering::Relaxed);
+        self.relay_errors.fetch_add(base.relay_errors, Ordering::Relaxed);
+        self.tls_blocked_suspected.fetch_add(base.tls_blocked_suspected, Ordering::Relaxed);
+        
+        let mut baseline = self.baseline.lock();
+        baseline.packets_in += base.packets_in;
+        baseline.packets_out += base.packets_out;
+        baseline.bytes_in += base.bytes_in;
+        baseline.bytes_out += base.bytes_out;
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PersistedStats {
+    pub epoch: u64,
+    pub snapshot: StatsSnapshot,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostCounters {
+    pub connections: u64,
+    pub bytes_up: u64,
+    pub bytes_down: u64,
+    pub bypass_applied: u64,
+    pub failures: u64,
+}
+
+impl HostCounters {
+    pub fn total_bytes(&self) -> u64 {
+        self.bytes_up + self.bytes_down
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostStatsEntry {
+    pub host: String,
+    #[serde(flatten)]
+    pub counters: HostCounters,
+}
+
+/// Traffic per hostname, for at most `capacity` hosts. Every update marks a
+/// host as used, so the one dropped to make room is the least recently active.
+#[derive(Debug)]
+pub struct HostStats {
+    hosts: DashMap<String, HostEntry>,
+    capacity: usize,
+    clock: AtomicU64,
+}
+
+#[derive(Debug)]
+struct HostEntry {
+    counters: HostCounters,
+    last_active: u64,
+}
+
+impl HostStats {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            hosts: DashMap::new(),
+            capacity: capacity.max(1),
+            clock: AtomicU64::new(0),
+        }
+    }
+    
+    pub fn record(&self, host: &str, update: impl FnOnce(&mut HostCounters)) {
+        let host = if host.bytes().any(|b| b.is_ascii_uppercase()) {
+            Cow::Owned(host.to_ascii_lowercase())
+        } else {
+            Cow::Borrowed(host)
+        };
+        let now = self.clock.fetch_add(1, Ordering::Relaxed);
+        
+        if let Some(mut entry) = self.hosts.get_mut(host.as_ref()) {
+            entry.last_active = now;
+            update(&mut entry.counters);
+            return;
+        }
+        
+        // The shard lock must be released before scanning for a host to evict.
+        {
+            let mut entry = self.hosts.entry(host.into_owned()).or_insert(HostEntry {
+                counters: HostCounters::default(),
+                last_active: now,
+            });
+            entry.last_active = now;
+            update(&mut entry.counters);
+        }
+        
+        while self.hosts.len() > self.capacity {
+            let oldest = self.hosts
+                .iter()
+                .min_by_key(|entry| entry.last_active)
+                .map(|entry| entry.key().clone());
+            match oldest {
+                Some(host) => self.hosts.remove(&host),
+                None => break,
+            };
+        }
+    }
+    
+    /// Records a finished connection in one go.
+    pub fn record_connection(&self, host: &str, bytes_up: u64, bytes_down: u64, bypass_applied: bool, failed: bool) {
+        self.record(host, |counters| {
+            counters.connections += 1;
+            counters.bytes_up += bytes_up;
+            counters.bytes_down += bytes_down;
+            counters.bypass_applied += bypass_applied as u64;
+            counters.failures += failed as u64;
+        });
+    }
+    
+    pub fn get(&self, host: &str) -> Option<HostCounters> {
+        self.hosts.get(&host.to_ascii_lowercase()).map(|entry| entry.counters)
+    }
+    
+    /// The `n` hosts with the most traffic, busiest first.
+    pub fn top(&self, n: usize) -> Vec<HostStatsEntry> {
+        let mut entries: Vec<HostStatsEntry> = self.hosts
+            .iter()
+            .map(|entry| HostStatsEntry {
+                host: entry.key().clone(),
+                counters: entry.counters,
+            })
+            .collect();
+        entries.sort_by(|a, b| {
+            b.counters.total_bytes()
+                .cmp(&a.counters.total_bytes())
+                .then(b.counters.connections.cmp(&a.counters.connections))
+                .then_with(|| a.host.cmp(&b.host))
+        });
+        entries.truncate(n);
+        entries
+    }
+    
+    pub fn len(&self) -> usize {
+        self.hosts.len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    
+    pub fn clear(&self) {
+        self.hosts.clear();
+    }
+}
+
+impl Default for HostStats {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_HOSTS)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct StatsSnapshot {
     pub packets_in: u64,
     pub packets_out: u64,
@@ -137,6 +490,137 @@ pub struct StatsSnapshot {
     pub fragments_generated: u64,
     pub total_jitter_ms: u64,
     pub decoys_sent: u64,
+    #[serde(default)]
+    pub socks_doh_resolved: u64,
+    #[serde(default)]
+    pub connect_fallbacks: u64,
+    #[serde(default)]
+    pub memory_evictions: u64,
+    #[serde(default)]
+    pub connection_errors: u64,
+    #[serde(default)]
+    pub dns_failures: u64,
+    #[serde(default)]
+    pub connect_timeouts: u64,
+    #[serde(default)]
+    pub connect_refused: u64,
+    #[serde(default)]
+    pub relay_errors: u64,
+    #[serde(default)]
+    pub tls_blocked_suspected: u64,
+    #[serde(default)]
+    pub flow_memory_bytes: u64,
+    #[serde(default)]
+    pub rule_compile_errors: u64,
+    #[serde(default)]
+    pub invalid_packets: u64,
+    #[serde(default)]
+    pub padding_bytes_added: u64,
+    #[serde(default)]
+    pub decoy_bytes_sent: u64,
+    #[serde(default)]
+    pub fragment_overhead_writes: u64,
+    #[serde(default)]
+    pub dns_queries: u64,
+    #[serde(default)]
+    pub uptime_secs: u64,
+    #[serde(default)]
+    pub pps_in: f64,
+    #[serde(default)]
+    pub pps_out: f64,
+    #[serde(default)]
+    pub bps_in: f64,
+    #[serde(default)]
+    pub bps_out: f64,
+    /// Filled in from a [`StatsHistory`] by whoever samples the stats.
+    #[serde(default)]
+    pub rate_1m: Option<Rates>,
+    #[serde(default)]
+    pub rate_5m: Option<Rates>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Rates {
+    pub pps_in: f64,
+    pub pps_out: f64,
+    pub bps_in: f64,
+    pub bps_out: f64,
+}
+
+impl Rates {
+    fn over(earlier: &StatsSnapshot, later: &StatsSnapshot, elapsed: Duration) -> Self {
+        let secs = elapsed.as_secs_f64();
+        if secs <= 0.0 {
+            return Self::default();
+        }
+        let rate = |later: u64, earlier: u64| later.saturating_sub(earlier) as f64 / secs;
+        Self {
+            pps_in: rate(later.packets_in, earlier.packets_in),
+            pps_out: rate(later.packets_out, earlier.packets_out),
+            bps_in: rate(later.bytes_in, earlier.bytes_in),
+            bps_out: rate(later.bytes_out, earlier.bytes_out),
+        }
+    }
+}
+
+/// The last few snapshots of a [`Stats`], taken by a background sampler so
+/// that recent rates can be told apart from the average since startup.
+#[derive(Debug)]
+pub struct StatsHistory {
+    samples: Mutex<VecDeque<(Instant, StatsSnapshot)>>,
+    capacity: usize,
+}
+
+impl StatsHistory {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(2);
+        Self {
+            samples: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+        }
+    }
+    
+    pub fn sample(&self, stats: &Stats) {
+        self.record(Instant::now(), stats.snapshot());
+    }
+    
+    pub fn record(&self, at: Instant, snapshot: StatsSnapshot) {
+        let mut samples = self.samples.lock();
+        if samples.len() == self.capacity {
+            samples.pop_front();
+        }
+        samples.push_back((at, snapshot));
+    }
+    
+    /// Rates between the newest sample and the oldest one no more than
+    /// `window` before it. Until the history covers the window, the rate is
+    /// over whatever it does cover.
+    pub fn rate(&self, window: Duration) -> Option<Rates> {
+        let samples = self.samples.lock();
+        let (newest_at, newest) = samples.back()?;
+        let (oldest_at, oldest) = samples
+            .iter()
+            .find(|(at, _)| newest_at.duration_since(*at) <= window + HISTORY_SAMPLE_INTERVAL / 2)?;
+        if oldest_at == newest_at {
+            return None;
+        }
+        Some(Rates::over(oldest, newest, newest_at.duration_since(*oldest_at)))
+    }
+    
+    pub fn fill_rates(&self, snapshot: &mut StatsSnapshot) {
+        snapshot.rate_1m = self.rate(Duration::from_secs(60));
+        snapshot.rate_5m = self.rate(Duration::from_secs(300));
+    }
+    
+    pub fn clear(&self) {
+        self.samples.lock().clear();
+    }
+}
+
+impl Default for StatsHistory {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY_LEN)
+    }
 }
 
 impl StatsSnapshot {
@@ -179,6 +663,15 @@ impl StatsSnapshot {
             self.packets_out as f64 / self.packets_in as f64
         }
     }
+    
+    /// Padding and decoy bytes sent per byte of original data.
+    pub fn overhead_ratio(&self) -> f64 {
+        if self.bytes_in == 0 {
+            0.0
+        } else {
+            (self.padding_bytes_added + self.decoy_bytes_sent) as f64 / self.bytes_in as f64
+        }
+    }
 }
 
 #[cfg(test)]
@@ -215,6 +708,11 @@ mod tests {
         assert_eq!(snapshot.flows_created, 3);
         assert_eq!(snapshot.active_flows, 2);
         assert_eq!(snapshot.flows_evicted, 1);
+        
+        stats.reset();
+        stats.record_flow_evicted();
+        stats.record_memory_evictions(3);
+        assert_eq!(stats.snapshot().active_flows, 0);
     }
 
     #[test]
@@ -224,11 +722,16 @@ mod tests {
         stats.record_packet_in(100);
         stats.record_flow_created();
         stats.record_fragments(10);
+        stats.record_invalid_packet();
+        stats.record_rule_compile_error();
+        assert_eq!(stats.snapshot().invalid_packets, 1);
+        assert_eq!(stats.snapshot().rule_compile_errors, 1);
         
         stats.reset();
         
         let snapshot = stats.snapshot();
         assert_eq!(snapshot.packets_in, 0);
+        assert_eq!(snapshot.invalid_packets, 0);
         assert_eq!(snapshot.flows_created, 0);
         assert_eq!(snapshot.fragments_generated, 0);
     }
@@ -251,6 +754,11 @@ mod tests {
             fragments_generated: 50,
             total_jitter_ms: 1000,
             decoys_sent: 20,
+            socks_doh_resolved: 0,
+            connect_fallbacks: 0,
+            memory_evictions: 0,
+            flow_memory_bytes: 0,
+            ..Default::default()
         };
         
         assert_eq!(snapshot.expansion_ratio(), 1.5);
@@ -278,6 +786,11 @@ mod tests {
             fragments_generated: 0,
             total_jitter_ms: 0,
             decoys_sent: 0,
+            socks_doh_resolved: 0,
+            connect_fallbacks: 0,
+            memory_evictions: 0,
+            flow_memory_bytes: 0,
+            ..Default::default()
         };
         
         assert_eq!(empty.expansion_ratio(), 0.0);
@@ -285,4 +798,182 @@ mod tests {
         assert_eq!(empty.drop_ratio(), 0.0);
         assert_eq!(empty.packets_per_second(0.0), 0.0);
     }
+    
+    #[test]
+    fn test_snapshot_rates_since_start() {
+        let stats = Stats::new();
+        *stats.started_at.0.lock() = Instant::now() - Duration::from_secs(10);
+        for _ in 0..50 {
+            stats.record_packet_in(100);
+        }
+        stats.record_packet_out(1000);
+        
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.uptime_secs, 10);
+        assert!((4.9..=5.0).contains(&snapshot.pps_in), "{}", snapshot.pps_in);
+        assert!((490.0..=500.0).contains(&snapshot.bps_in), "{}", snapshot.bps_in);
+        assert!(snapshot.pps_out > 0.09 && snapshot.bps_out <= 100.0);
+        assert!(snapshot.rate_1m.is_none());
+        
+        stats.reset();
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.uptime_secs, 0);
+        assert_eq!(snapshot.pps_in, 0.0);
+    }
+    
+    #[test]
+    fn test_persisted_baseline() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stats.json");
+        
+        let stats = Stats::new();
+        stats.record_packet_in(100);
+        stats.record_packet_out(120);
+        stats.record_flow_created();
+        stats.record_padding(20);
+        stats.save_to(&path).unwrap();
+        stats.save_to(&path).unwrap();
+        assert_eq!(stats.epoch(), 2);
+        
+        let restarted = Stats::new();
+        *restarted.started_at.0.lock() = Instant::now() - Duration::from_secs(10);
+        restarted.load_from(&path).unwrap();
+        assert_eq!(restarted.epoch(), 2);
+        restarted.record_packet_in(50);
+        
+        let snapshot = restarted.snapshot();
+        assert_eq!((snapshot.packets_in, snapshot.bytes_in), (2, 150));
+        assert_eq!((snapshot.packets_out, snapshot.bytes_out), (1, 120));
+        assert_eq!(snapshot.flows_created, 1);
+        assert_eq!(snapshot.active_flows, 0);
+        assert_eq!(snapshot.padding_bytes_added, 20);
+        assert!((4.9..=5.0).contains(&snapshot.bps_in), "{}", snapshot.bps_in);
+        assert_eq!(snapshot.pps_out, 0.0);
+        
+        restarted.save_to(&path).unwrap();
+        assert_eq!(restarted.epoch(), 3);
+        let files: Vec<_> = std::fs::read_dir(dir.path()).unwrap().map(|e| e.unwrap().file_name()).collect();
+        assert_eq!(files, ["stats.json"]);
+        
+        std::fs::write(&path, b"{ not json").unwrap();
+        assert!(Stats::new().load_from(&path).is_err());
+        assert!(Stats::new().load_from(dir.path().join("missing.json")).is_err());
+    }
+    
+    #[test]
+    fn test_history_windows() {
+        let history = StatsHistory::new(4);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let counts = |packets_in, bytes_in| StatsSnapshot {
+            packets_in,
+            bytes_in,
+            ..Default::default()
+        };
+        
+        assert!(history.rate(Duration::from_secs(60)).is_none());
+        history.record(at(0), counts(0, 0));
+        assert!(history.rate(Duration::from_secs(60)).is_none());
+        
+        history.record(at(30), counts(300, 3000));
+        history.record(at(60), counts(600, 6000));
+        history.record(at(90), counts(1800, 18000));
+        let rate = history.rate(Duration::from_secs(60)).unwrap();
+        assert_eq!(rate.pps_in, 25.0);
+        assert_eq!(rate.bps_in, 250.0);
+        
+        // The first sample falls out of the ring, so the 5-minute rate covers
+        // the 90 seconds that are left.
+        history.record(at(120), counts(3000, 30000));
+        let rate = history.rate(Duration::from_secs(300)).unwrap();
+        assert_eq!(rate.pps_in, 30.0);
+        
+        let mut snapshot = StatsSnapshot::default();
+        history.fill_rates(&mut snapshot);
+        assert_eq!(snapshot.rate_1m.unwrap().pps_in, 40.0);
+        assert_eq!(snapshot.rate_5m.unwrap().pps_in, 30.0);
+        
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let parsed: StatsSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.rate_1m, snapshot.rate_1m);
+        
+        let legacy: StatsSnapshot = serde_json::from_str(r#"{
+            "packets_in": 1, "packets_out": 1, "bytes_in": 1, "bytes_out": 1,
+            "packets_dropped": 0, "packets_matched": 0, "packets_transformed": 0,
+            "transform_errors": 0, "active_flows": 0, "flows_created": 0,
+            "flows_evicted": 0, "queue_overflows": 0, "fragments_generated": 0,
+            "total_jitter_ms": 0, "decoys_sent": 0
+        }"#).unwrap();
+        assert_eq!(legacy.socks_doh_resolved, 0);
+        assert_eq!(legacy.uptime_secs, 0);
+        assert!(legacy.rate_5m.is_none());
+        
+        history.clear();
+        assert!(history.rate(Duration::from_secs(60)).is_none());
+    }
+    
+    #[test]
+    fn test_host_stats_aggregation() {
+        let hosts = HostStats::new(16);
+        
+        hosts.record_connection("discord.com", 500, 20_000, true, false);
+        hosts.record_connection("Discord.com", 300, 10_000, true, false);
+        hosts.record_connection("example.com", 100, 100, false, true);
+        hosts.record("www.youtube.com", |c| c.bytes_down += 50_000);
+        
+        let discord = hosts.get("discord.com").unwrap();
+        assert_eq!(discord, HostCounters {
+            connections: 2,
+            bytes_up: 800,
+            bytes_down: 30_000,
+            bypass_applied: 2,
+            failures: 0,
+        });
+        assert_eq!(hosts.get("example.com").unwrap().failures, 1);
+        
+        let top: Vec<String> = hosts.top(2).into_iter().map(|e| e.host).collect();
+        assert_eq!(top, vec!["www.youtube.com", "discord.com"]);
+        assert_eq!(hosts.top(10).len(), 3);
+    }
+    
+    #[test]
+    fn test_host_stats_evicts_least_active() {
+        let hosts = HostStats::new(2);
+        
+        hosts.record_connection("a.example", 1, 1, false, false);
+        hosts.record_connection("b.example", 1, 1, false, false);
+        hosts.record("a.example", |c| c.bytes_up += 1);
+        hosts.record_connection("c.example", 1, 1, false, false);
+        
+        assert_eq!(hosts.len(), 2);
+        assert!(hosts.get("a.example").is_some());
+        assert!(hosts.get("b.example").is_none());
+        
+        let stats = Stats::new();
+        stats.hosts.record_connection("a.example", 1, 1, false, false);
+        stats.reset();
+        assert!(stats.hosts.is_empty());
+    }
+    
+    #[test]
+    fn test_host_stats_concurrent_updates() {
+        let hosts = std::sync::Arc::new(HostStats::new(4));
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let hosts = hosts.clone();
+                std::thread::spawn(move || {
+                    for n in 0..1000 {
+                        hosts.record_connection(&format!("host{}.example", (i + n) % 6), 1, 2, false, false);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        
+        assert_eq!(hosts.len(), 4);
+        let connections: u64 = hosts.top(usize::MAX).iter().map(|e| e.counters.connections).sum();
+        assert!(connections > 0 && connections <= 8000);
+    }
 }
@@ -13,10 +13,16 @@ pub const EXT_STATUS_REQUEST: u16 = 0x0005;
 pub const EXT_SUPPORTED_GROUPS: u16 = 0x000a;
 pub const EXT_EC_POINT_FORMATS: u16 = 0x000b;
 pub const EXT_SIGNATURE_ALGORITHMS: u16 = 0x000d;
+pub const EXT_ALPN: u16 = 0x0010;
+pub const EXT_SUPPORTED_VERSIONS: u16 = 0x002b;
+pub const EXT_KEY_SHARE: u16 = 0x0033;
+pub const EXT_PADDING: u16 = 0x0015;
+
+const GROUP_X25519: u16 = 0x001d;
 
 pub const SNI_HOST_NAME: u8 = 0x00;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct ClientHelloInfo {
     pub record_offset: usize,
     pub record_length: usize,
@@ -25,24 +31,15 @@ pub struct ClientHelloInfo {
     pub sni_hostname: Option<String>,    
     pub record_version: (u8, u8),    
     pub client_version: (u8, u8),    
+    pub alpn: Vec<String>,
+    pub cipher_suites: Vec<u16>,
+    /// Extension types in the order the client sent them.
+    pub extensions: Vec<u16>,
+    pub supported_groups: Vec<u16>,
+    pub ec_point_formats: Vec<u8>,
     pub is_valid: bool,
 }
 
-impl Default for ClientHelloInfo {
-    fn default() -> Self {
-        Self {
-            record_offset: 0,
-            record_length: 0,
-            sni_offset: None,
-            sni_length: None,
-            sni_hostname: None,
-            record_version: (0, 0),
-            client_version: (0, 0),
-            is_valid: false,
-        }
-    }
-}
-
 impl ClientHelloInfo {
     pub fn get_split_points(&self) -> Vec<usize> {
         let mut points = Vec::new();
@@ -69,130 +66,212 @@ impl ClientHelloInfo {
             None
         }
     }
-}
-
-pub fn parse_client_hello(data: &[u8]) -> Option<ClientHelloInfo> {
-    let mut info = ClientHelloInfo::default();
     
-    if data.len() < 6 {
-        return None;
+    /// The JA3 fingerprint string: version, ciphers, extensions, groups and
+    /// point formats, with GREASE values left out.
+    pub fn ja3_string(&self) -> String {
+        fn join<T: Copy + Into<u16>>(values: &[T]) -> String {
+            values.iter()
+                .map(|&v| v.into())
+                .filter(|&v| !is_grease(v))
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+                .join("-")
+        }
+        
+        format!(
+            "{},{},{},{},{}",
+            u16::from_be_bytes([self.client_version.0, self.client_version.1]),
+            join(&self.cipher_suites),
+            join(&self.extensions),
+            join(&self.supported_groups),
+            join(&self.ec_point_formats),
+        )
     }
     
-    let mut pos = 0;
-    let content_type = data[pos];
+    /// MD5 of [`Self::ja3_string`], in lowercase hex.
+    pub fn ja3(&self) -> String {
+        format!("{:x}", md5::compute(self.ja3_string()))
+    }
+}
 
-    if content_type != TLS_HANDSHAKE {
-        return None;
+/// GREASE values (RFC 8701) are random per connection, so JA3 skips them.
+fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+/// Bounds-checked reader; every read past the end of `data` returns `None`.
+/// Positions are offsets into the original buffer.
+#[derive(Clone, Copy)]
+struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
     }
-    pos += 1;
     
-    info.record_version = (data[pos], data[pos + 1]);
-    pos += 2;
-    
-    let record_length = u16::from_be_bytes([data[pos], data[pos + 1]]) as usize;
-    pos += 2;
-    info.record_length = record_length + 5;
-    
-    if data.len() < pos + record_length {
-      
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
     }
     
-    if pos >= data.len() {
-        return None;
+    fn rest(&self) -> &'a [u8] {
+        &self.data[self.pos..]
     }
     
-    let handshake_type = data[pos];
-    if handshake_type != HANDSHAKE_CLIENT_HELLO {
-        return None;
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let end = self.pos.checked_add(len).filter(|&end| end <= self.data.len())?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Some(bytes)
     }
-    pos += 1;
-    
-    info.is_valid = true;
     
-    if pos + 3 > data.len() {
-        return Some(info);
+    fn skip(&mut self, len: usize) -> Option<()> {
+        self.bytes(len).map(|_| ())
     }
-    let _handshake_length = u32::from_be_bytes([0, data[pos], data[pos + 1], data[pos + 2]]) as usize;
-    pos += 3;
     
-    if pos + 2 > data.len() {
-        return Some(info);
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
     }
-    info.client_version = (data[pos], data[pos + 1]);
-    pos += 2;
     
-    pos += 32;
-    if pos > data.len() {
-        return Some(info);
+    fn u16(&mut self) -> Option<u16> {
+        self.bytes(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
     }
     
-    if pos >= data.len() {
-        return Some(info);
-    }
-    let session_id_len = data[pos] as usize;
-    pos += 1 + session_id_len;
-    if pos > data.len() {
-        return Some(info);
+    fn u24(&mut self) -> Option<u32> {
+        self.bytes(3).map(|b| u32::from_be_bytes([0, b[0], b[1], b[2]]))
     }
     
-    if pos + 2 > data.len() {
-        return Some(info);
+    /// Splits off the next `len` bytes, or as many as there are, as a
+    /// cursor of their own.
+    fn take(&mut self, len: usize) -> Cursor<'a> {
+        let end = self.pos + len.min(self.remaining());
+        let sub = Cursor { data: &self.data[..end], pos: self.pos };
+        self.pos = end;
+        sub
     }
-    let cipher_suites_len = u16::from_be_bytes([data[pos], data[pos + 1]]) as usize;
-    pos += 2 + cipher_suites_len;
-    if pos > data.len() {
-        return Some(info);
+}
+
+pub fn parse_client_hello(data: &[u8]) -> Option<ClientHelloInfo> {
+    let mut cursor = Cursor::new(data);
+    let header = cursor.bytes(5)?;
+    let record_length = u16::from_be_bytes([header[3], header[4]]) as usize;
+    // Whatever follows the first record, such as the rest of a ClientHello
+    // split across records, is not part of this one.
+    let mut record = cursor.take(record_length);
+    if header[0] != TLS_HANDSHAKE || record.u8()? != HANDSHAKE_CLIENT_HELLO {
+        return None;
     }
     
-    if pos >= data.len() {
-        return Some(info);
-    }
-    let compression_len = data[pos] as usize;
-    pos += 1 + compression_len;
-    if pos > data.len() {
-        return Some(info);
+    let mut info = ClientHelloInfo {
+        record_version: (header[1], header[2]),
+        record_length: record_length + 5,
+        is_valid: true,
+        ..Default::default()
+    };
+
+    // A ClientHello cut short (e.g. by a TCP segment boundary) keeps the
+    // fields that were complete.
+    let _ = parse_client_hello_body(&mut record, &mut info);
+    Some(info)
+}
+    
+fn parse_client_hello_body(record: &mut Cursor<'_>, info: &mut ClientHelloInfo) -> Option<()> {
+    let handshake_length = record.u24()? as usize;
+    let cursor = &mut record.take(handshake_length);
+    
+    let version = cursor.bytes(2)?;
+    info.client_version = (version[0], version[1]);
+    
+    cursor.skip(32)?;
+    
+    let session_id_len = cursor.u8()? as usize;
+    cursor.skip(session_id_len)?;
+    
+    let cipher_suites_len = cursor.u16()? as usize;
+    info.cipher_suites = u16_list(cursor.bytes(cipher_suites_len)?);
+    
+    let compression_len = cursor.u8()? as usize;
+    cursor.skip(compression_len)?;
+    
+    let extensions_len = cursor.u16()? as usize;
+    let mut extensions = cursor.take(extensions_len);
+    
+    while let (Some(ext_type), Some(ext_len)) = (extensions.u16(), extensions.u16()) {
+        let mut ext = extensions.take(ext_len as usize);
+        info.extensions.push(ext_type);
+        match ext_type {
+            EXT_SERVER_NAME => {
+                if let Some((offset, name)) = parse_server_name(&mut ext) {
+                    info.sni_offset = Some(offset);
+                    info.sni_length = Some(name.len());
+                    info.sni_hostname = std::str::from_utf8(name).ok().map(str::to_string);
+                }
+            }
+            EXT_ALPN => {
+                info.alpn = parse_alpn(ext.rest());
+            }
+            EXT_SUPPORTED_GROUPS => {
+                if let Some(len) = ext.u16() {
+                    info.supported_groups = u16_list(ext.take(len as usize).rest());
+                }
+            }
+            EXT_EC_POINT_FORMATS => {
+                if let Some(len) = ext.u8() {
+                    info.ec_point_formats = ext.take(len as usize).rest().to_vec();
+                }
+            }
+            _ => {}
+        }
     }
     
-    if pos + 2 > data.len() {
-        return Some(info);
+    Some(())
+}
+
+/// Big-endian u16s; a trailing odd byte is ignored.
+fn u16_list(bytes: &[u8]) -> Vec<u16> {
+    bytes.chunks_exact(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect()
+}
+
+/// Offset and bytes of the first host_name entry, if it lies wholly within
+/// the extension.
+fn parse_server_name<'a>(ext: &mut Cursor<'a>) -> Option<(usize, &'a [u8])> {
+    let _list_len = ext.u16()?;
+    let name_type = ext.u8()?;
+    let name_len = ext.u16()? as usize;
+    let offset = ext.pos;
+    let name = ext.bytes(name_len)?;
+    (name_type == SNI_HOST_NAME).then_some((offset, name))
+}
+
+fn parse_alpn(ext: &[u8]) -> Vec<String> {
+    let mut protocols = Vec::new();
+    
+    if ext.len() < 2 {
+        return protocols;
     }
-    let extensions_len = u16::from_be_bytes([data[pos], data[pos + 1]]) as usize;
-    pos += 2;
     
-    let extensions_end = pos + extensions_len;
+    let list_len = u16::from_be_bytes([ext[0], ext[1]]) as usize;
+    let list_end = (2 + list_len).min(ext.len());
+    let mut pos = 2;
     
-    while pos + 4 <= data.len() && pos < extensions_end {
-        let ext_type = u16::from_be_bytes([data[pos], data[pos + 1]]);
-        pos += 2;
-        
-        let ext_len = u16::from_be_bytes([data[pos], data[pos + 1]]) as usize;
-        pos += 2;
+    while pos < list_end {
+        let name_len = ext[pos] as usize;
+        pos += 1;
         
-        if ext_type == EXT_SERVER_NAME {
-            if pos + 5 <= data.len() && pos + ext_len <= data.len() {
-                let _sni_list_len = u16::from_be_bytes([data[pos], data[pos + 1]]) as usize;
-                let name_type = data[pos + 2];
-                let name_len = u16::from_be_bytes([data[pos + 3], data[pos + 4]]) as usize;
-                
-                if name_type == SNI_HOST_NAME {
-                    let name_offset = pos + 5;
-                    info.sni_offset = Some(name_offset);
-                    info.sni_length = Some(name_len);
-                    
-                    if name_offset + name_len <= data.len() {
-                        if let Ok(hostname) = std::str::from_utf8(&data[name_offset..name_offset + name_len]) {
-                            info.sni_hostname = Some(hostname.to_string());
-                        }
-                    }
-                }
-            }
+        if name_len == 0 || pos + name_len > list_end {
             break;
         }
         
-        pos += ext_len;
+        if let Ok(name) = std::str::from_utf8(&ext[pos..pos + name_len]) {
+            protocols.push(name.to_string());
+        }
+        pos += name_len;
     }
     
-    Some(info)
+    protocols
 }
 
 pub fn is_client_hello(data: &[u8]) -> bool {
@@ -215,6 +294,77 @@ pub fn is_client_hello(data: &[u8]) -> bool {
     true
 }
 
+/// Content type of the last record, if `data` is a whole number of TLS records.
+pub fn last_record_type(data: &[u8]) -> Option<u8> {
+    let mut pos = 0;
+    let mut last = None;
+    while pos < data.len() {
+        let header = data.get(pos..pos + 5)?;
+        if !(TLS_CHANGE_CIPHER_SPEC..=TLS_APPLICATION_DATA).contains(&header[0]) || header[1] != 0x03 {
+            return None;
+        }
+        pos += 5 + u16::from_be_bytes([header[3], header[4]]) as usize;
+        last = Some(header[0]);
+    }
+    (pos == data.len()).then_some(last?)
+}
+
+pub fn build_client_hello(sni: &str, random: &[u8; 32]) -> Vec<u8> {
+    build_client_hello_padded(sni, random, 0)
+}
+
+/// Like [`build_client_hello`], padded to `len` bytes when `len` leaves room
+/// for a padding extension.
+pub fn build_client_hello_padded(sni: &str, random: &[u8; 32], len: usize) -> Vec<u8> {
+    fn extension(out: &mut Vec<u8>, ext_type: u16, body: &[u8]) {
+        out.extend_from_slice(&ext_type.to_be_bytes());
+        out.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        out.extend_from_slice(body);
+    }
+    
+    let name = sni.as_bytes();
+    let mut server_name = Vec::with_capacity(name.len() + 5);
+    server_name.extend_from_slice(&(name.len() as u16 + 3).to_be_bytes());
+    server_name.push(SNI_HOST_NAME);
+    server_name.extend_from_slice(&(name.len() as u16).to_be_bytes());
+    server_name.extend_from_slice(name);
+    
+    let mut key_share = Vec::with_capacity(38);
+    key_share.extend_from_slice(&36u16.to_be_bytes());
+    key_share.extend_from_slice(&GROUP_X25519.to_be_bytes());
+    key_share.extend_from_slice(&32u16.to_be_bytes());
+    key_share.extend_from_slice(random);
+    
+    let mut extensions = Vec::new();
+    extension(&mut extensions, EXT_SERVER_NAME, &server_name);
+    extension(&mut extensions, EXT_SUPPORTED_GROUPS, &[0x00, 0x02, 0x00, 0x1d]);
+    extension(&mut extensions, EXT_SIGNATURE_ALGORITHMS, &[0x00, 0x06, 0x04, 0x03, 0x08, 0x04, 0x04, 0x01]);
+    extension(&mut extensions, EXT_SUPPORTED_VERSIONS, &[0x02, 0x03, 0x04]);
+    extension(&mut extensions, EXT_KEY_SHARE, &key_share);
+    
+    // Record and handshake headers, fixed hello fields, and the extension
+    // header of the padding itself.
+    let unpadded = 5 + 4 + 2 + 32 + 1 + 8 + 2 + 2 + extensions.len();
+    if let Some(padding) = len.checked_sub(unpadded + 4).filter(|&n| n <= u16::MAX as usize) {
+        extension(&mut extensions, EXT_PADDING, &vec![0; padding]);
+    }
+    
+    let mut hello = vec![0x03, 0x03];
+    hello.extend_from_slice(random);
+    hello.push(0x00);
+    hello.extend_from_slice(&[0x00, 0x06, 0x13, 0x01, 0x13, 0x02, 0x13, 0x03]);
+    hello.extend_from_slice(&[0x01, 0x00]);
+    hello.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+    hello.extend_from_slice(&extensions);
+    
+    let mut record = vec![TLS_HANDSHAKE, 0x03, 0x01];
+    record.extend_from_slice(&(hello.len() as u16 + 4).to_be_bytes());
+    record.push(HANDSHAKE_CLIENT_HELLO);
+    record.extend_from_slice(&(hello.len() as u32).to_be_bytes()[1..]);
+    record.extend_from_slice(&hello);
+    record
+}
+
 pub fn is_http_request(data: &[u8]) -> bool {
     if data.len() < 4 {
         return false;
@@ -231,27 +381,46 @@ pub fn is_http_request(data: &[u8]) -> bool {
 }
 
 pub fn find_http_host(data: &[u8]) -> Option<(usize, usize)> {
-    let text = std::str::from_utf8(data).ok()?;
-    
-    let lower = text.to_lowercase();
-    let host_pos = lower.find("\nhost:")?;
+    http_host_line(data).map(|(_, value, len)| (value, len))
+}
     
-    let value_start = host_pos + 6;
+pub fn find_http_host_header(data: &[u8]) -> Option<usize> {
+    http_host_line(data).map(|(name, _, _)| name)
+}
     
-    let mut start = value_start;
-    while start < text.len() && (text.as_bytes()[start] == b' ' || text.as_bytes()[start] == b'\t') {
-        start += 1;
+/// Stops at the blank line that ends the headers, so a body can never be
+/// mistaken for a header.
+fn http_host_line(data: &[u8]) -> Option<(usize, usize, usize)> {
+    let mut line_start = data.iter().position(|&b| b == b'\n')? + 1;
+    
+    while line_start < data.len() {
+        let line_end = data[line_start..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(data.len(), |p| line_start + p);
+        let line = &data[line_start..line_end];
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() {
+            return None;
+        }
+        
+        if let Some(colon) = line.iter().position(|&b| b == b':') {
+            let name = &line[..colon];
+            if name.trim_ascii().eq_ignore_ascii_case(b"host") {
+                let indent = name.len() - name.trim_ascii_start().len();
+                let value = &line[colon + 1..];
+                let lead = value.len() - value.trim_ascii_start().len();
+                return Some((line_start + indent, line_start + colon + 1 + lead, value.trim_ascii().len()));
+            }
+        }
+        
+        line_start = line_end + 1;
     }
     
-    let end = text[start..].find('\r')
-        .or_else(|| text[start..].find('\n'))
-        .map(|p| start + p)
-        .unwrap_or(text.len());
-    
-    Some((start, end - start))
+    None
 }
 
-pub fn fragment_at_offsets(data: &[u8], offsets: &[usize]) -> Vec<BytesMut> {
+pub fn fragment_at_offsets(mut data: BytesMut, offsets: &[usize]) -> Vec<BytesMut> {
     let mut fragments = Vec::new();
     let mut prev = 0;
     
@@ -263,24 +432,61 @@ pub fn fragment_at_offsets(data: &[u8], offsets: &[usize]) -> Vec<BytesMut> {
     sorted_offsets.dedup();
     
     for offset in sorted_offsets {
-        if offset > prev && offset <= data.len() {
-            fragments.push(BytesMut::from(&data[prev..offset]));
-            prev = offset;
-        }
+        fragments.push(data.split_to(offset - prev));
+        prev = offset;
     }
     
-    if prev < data.len() {
-        fragments.push(BytesMut::from(&data[prev..]));
+    if !data.is_empty() {
+        fragments.push(data);
     }
     
     fragments
 }
 
+pub fn split_into_records(data: &[u8], record_sizes: &[usize]) -> Option<Vec<BytesMut>> {
+    if data.len() < 5 {
+        return None;
+    }
+    
+    let record_length = u16::from_be_bytes([data[3], data[4]]) as usize;
+    if data.len() != 5 + record_length {
+        return None;
+    }
+    
+    let header = &data[..3];
+    let mut body = &data[5..];
+    let mut payloads = Vec::new();
+    
+    for &size in record_sizes.iter().filter(|&&s| s > 0) {
+        if body.is_empty() {
+            break;
+        }
+        let (chunk, rest) = body.split_at(size.min(body.len()));
+        payloads.push(chunk);
+        body = rest;
+    }
+    
+    if !body.is_empty() {
+        payloads.push(body);
+    }
+    
+    // All records are written to one buffer and split off it, so they share
+    // a single allocation.
+    let mut buf = BytesMut::with_capacity(5 * payloads.len() + record_length);
+    for payload in &payloads {
+        buf.extend_from_slice(header);
+        buf.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        buf.extend_from_slice(payload);
+    }
+
+    Some(payloads.iter().map(|payload| buf.split_to(5 + payload.len())).collect())
+}
+
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
     
-    fn sample_client_hello() -> Vec<u8> {
+    pub(crate) fn sample_client_hello() -> Vec<u8> {
         vec![
             0x16, 
             0x03, 0x01, 
@@ -328,6 +534,76 @@ mod tests {
         ]
     }
     
+    pub(crate) fn client_hello_with_alpn(alpn_ext: &[u8]) -> Vec<u8> {
+        let mut extensions = vec![
+            0x00, 0x00, 
+            0x00, 0x10, 
+            0x00, 0x0e, 
+            0x00, 
+            0x00, 0x0b, 
+            0x64, 0x69, 0x73, 0x63, 0x6f, 0x72, 0x64, 0x2e, 0x63, 0x6f, 0x6d,
+            0x00, 0x10,
+        ];
+        extensions.extend_from_slice(&(alpn_ext.len() as u16).to_be_bytes());
+        extensions.extend_from_slice(alpn_ext);
+        
+        let mut hello = vec![0x03, 0x03];
+        hello.extend_from_slice(&[0x00; 32]);
+        hello.push(0x00);
+        hello.extend_from_slice(&[0x00, 0x02, 0x13, 0x01]);
+        hello.extend_from_slice(&[0x01, 0x00]);
+        hello.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+        hello.extend_from_slice(&extensions);
+        
+        let mut handshake = vec![HANDSHAKE_CLIENT_HELLO];
+        handshake.extend_from_slice(&(hello.len() as u32).to_be_bytes()[1..]);
+        handshake.extend_from_slice(&hello);
+        
+        let mut record = vec![TLS_HANDSHAKE, 0x03, 0x01];
+        record.extend_from_slice(&(handshake.len() as u16).to_be_bytes());
+        record.extend_from_slice(&handshake);
+        record
+    }
+    
+    #[test]
+    fn test_last_record_type() {
+        let hello = client_hello_with_alpn(&[]);
+        assert_eq!(last_record_type(&hello), Some(TLS_HANDSHAKE));
+        assert_eq!(last_record_type(&hello[..hello.len() - 1]), None);
+        
+        let mut flight = hello.clone();
+        flight.extend_from_slice(&[TLS_CHANGE_CIPHER_SPEC, 0x03, 0x03, 0x00, 0x01, 0x01]);
+        flight.extend_from_slice(&[TLS_APPLICATION_DATA, 0x03, 0x03, 0x00, 0x02, 0xAA, 0xBB]);
+        assert_eq!(last_record_type(&flight), Some(TLS_APPLICATION_DATA));
+        
+        assert_eq!(last_record_type(b"GET / HTTP/1.1\r\n\r\n"), None);
+        assert_eq!(last_record_type(&[]), None);
+    }
+    
+    #[test]
+    fn test_build_client_hello() {
+        let hello = build_client_hello("www.example.org", &[0x5A; 32]);
+        assert!(is_client_hello(&hello));
+        assert_eq!(last_record_type(&hello), Some(TLS_HANDSHAKE));
+        
+        let info = parse_client_hello(&hello).unwrap();
+        assert!(info.is_valid);
+        assert_eq!(info.sni_hostname.as_deref(), Some("www.example.org"));
+        let offset = info.sni_offset.unwrap();
+        assert_eq!(&hello[offset..offset + info.sni_length.unwrap()], b"www.example.org");
+        assert_eq!(&hello[11..43], &[0x5A; 32]);
+        
+        for len in [0, 100, hello.len() + 3, hello.len() + 4, 517] {
+            let padded = build_client_hello_padded("www.example.org", &[0x5A; 32], len);
+            if len >= hello.len() + 4 {
+                assert_eq!(padded.len(), len);
+            } else {
+                assert_eq!(padded, hello);
+            }
+            assert_eq!(parse_client_hello(&padded).unwrap().sni_hostname.as_deref(), Some("www.example.org"));
+        }
+    }
+    
     #[test]
     fn test_is_client_hello() {
         let data = sample_client_hello();
@@ -349,6 +625,196 @@ mod tests {
         assert_eq!(info.sni_hostname.as_deref(), Some("discord.com"));
     }
     
+    #[test]
+    fn test_ja3() {
+        let info = parse_client_hello(&sample_client_hello()).unwrap();
+        assert_eq!(info.cipher_suites, vec![0x1301, 0x1302]);
+        assert_eq!(info.extensions, vec![EXT_SERVER_NAME, 0x0015]);
+        assert_eq!(info.ja3_string(), "771,4865-4866,0-21,,");
+        assert_eq!(info.ja3(), "b3d8493f626285d259ec53ef1fe2a94c");
+        
+        let info = parse_client_hello(&build_client_hello("example.org", &[0; 32])).unwrap();
+        assert_eq!(info.supported_groups, vec![GROUP_X25519]);
+        assert_eq!(info.ja3_string(), "771,4865-4866-4867,0-10-13-43-51,29,");
+    }
+    
+    #[test]
+    fn test_ja3_skips_grease() {
+        let mut info = parse_client_hello(&sample_client_hello()).unwrap();
+        info.cipher_suites.insert(0, 0x0a0a);
+        info.extensions.push(0xfafa);
+        info.supported_groups = vec![0x2a2a, 0x001d, 0x0017];
+        info.ec_point_formats = vec![0];
+        assert_eq!(info.ja3_string(), "771,4865-4866,0-21,29-23,0");
+        assert!(!is_grease(0x0a1a));
+    }
+    
+    #[test]
+    fn test_parse_alpn() {
+        let alpn = [
+            0x00, 0x0c,
+            0x02, b'h', b'2',
+            0x08, b'h', b't', b't', b'p', b'/', b'1', b'.', b'1',
+        ];
+        let data = client_hello_with_alpn(&alpn);
+        let info = parse_client_hello(&data).unwrap();
+        
+        assert_eq!(info.sni_hostname.as_deref(), Some("discord.com"));
+        assert_eq!(info.alpn, vec!["h2".to_string(), "http/1.1".to_string()]);
+    }
+    
+    #[test]
+    fn test_parse_alpn_truncated() {
+        let alpn = [
+            0x00, 0x0c,
+            0x02, b'h', b'2',
+            0x08, b'h', b't', b't',
+        ];
+        let data = client_hello_with_alpn(&alpn);
+        let info = parse_client_hello(&data).unwrap();
+        
+        assert_eq!(info.sni_hostname.as_deref(), Some("discord.com"));
+        assert_eq!(info.alpn, vec!["h2".to_string()]);
+        
+        let truncated = &data[..data.len() - 6];
+        let info = parse_client_hello(truncated).unwrap();
+        assert_eq!(info.sni_hostname.as_deref(), Some("discord.com"));
+        assert!(info.alpn.len() <= 1);
+    }
+    
+    #[test]
+    fn test_parse_client_hello_without_alpn() {
+        let data = sample_client_hello();
+        let info = parse_client_hello(&data).unwrap();
+        assert!(info.alpn.is_empty());
+    }
+    
+    /// Any SNI the parser reports must lie inside the input and match it.
+    fn assert_sni_in_bounds(data: &[u8], info: &ClientHelloInfo) {
+        assert_eq!(info.sni_offset.is_some(), info.sni_length.is_some());
+        if let (Some(offset), Some(len)) = (info.sni_offset, info.sni_length) {
+            assert!(offset + len <= data.len(), "SNI {}+{} past {} bytes", offset, len, data.len());
+            if let Some(hostname) = &info.sni_hostname {
+                assert_eq!(hostname.as_bytes(), &data[offset..offset + len]);
+            }
+        }
+    }
+    
+    #[test]
+    fn test_parse_client_hello_every_truncation() {
+        let hello = build_client_hello("discord.com", &[0x11; 32]);
+        let sni_end = 65 + "discord.com".len();
+        
+        for len in 0..=hello.len() {
+            let data = &hello[..len];
+            let Some(info) = parse_client_hello(data) else {
+                assert!(len < 6, "len {}", len);
+                continue;
+            };
+            assert!(info.is_valid);
+            assert_sni_in_bounds(data, &info);
+            assert_eq!(info.sni_hostname.is_some(), len >= sni_end, "len {}", len);
+        }
+    }
+    
+    #[test]
+    fn test_parse_client_hello_malformed() {
+        fn set_u16(data: &mut [u8], offset: usize, value: u16) {
+            data[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+        }
+        
+        fn insert_extension(data: &mut Vec<u8>, ext: &[u8]) {
+            data.splice(56..56, ext.iter().copied());
+            let grow = |data: &mut Vec<u8>, offset: usize| {
+                let value = u16::from_be_bytes([data[offset], data[offset + 1]]) + ext.len() as u16;
+                set_u16(data, offset, value);
+            };
+            grow(data, 3);
+            grow(data, 7);
+            grow(data, 54);
+        }
+        
+        // Offsets into build_client_hello: session id length at 43, cipher
+        // suites length at 44, extensions length at 54, the SNI extension
+        // at 56 with its name length at 63.
+        type Case = (&'static str, fn(&mut Vec<u8>), Option<&'static str>);
+        let cases: &[Case] = &[
+            ("intact", |_| {}, Some("discord.com")),
+            ("oversized session id", |d| d[43] = 0xFF, None),
+            ("oversized cipher suites", |d| set_u16(d, 44, 0xFFFF), None),
+            ("oversized compression methods", |d| d[52] = 0xFF, None),
+            ("extensions length past buffer", |d| set_u16(d, 54, 0xFFFF), Some("discord.com")),
+            ("SNI past extensions end", |d| set_u16(d, 54, 8), None),
+            ("no extensions", |d| set_u16(d, 54, 0), None),
+            ("oversized SNI extension", |d| set_u16(d, 58, 0xFFFF), Some("discord.com")),
+            ("SNI name past extension", |d| set_u16(d, 58, 6), None),
+            ("oversized SNI name", |d| set_u16(d, 63, 0xFFFF), None),
+            ("zero-length SNI name", |d| set_u16(d, 63, 0), Some("")),
+            ("zero-length extension first", |d| insert_extension(d, &[0x00, 0x17, 0x00, 0x00]), Some("discord.com")),
+            ("non-hostname SNI entry", |d| d[62] = 0x01, None),
+            ("invalid UTF-8 hostname", |d| d[65] = 0xFF, None),
+            ("record ends before SNI", |d| set_u16(d, 3, 60), None),
+            ("handshake ends before SNI", |d| set_u16(d, 7, 56), None),
+        ];
+        
+        for (name, mutate, expected) in cases {
+            let mut data = build_client_hello("discord.com", &[0x11; 32]);
+            mutate(&mut data);
+            let info = parse_client_hello(&data).unwrap_or_else(|| panic!("{}: rejected", name));
+            assert!(info.is_valid, "{}", name);
+            assert_sni_in_bounds(&data, &info);
+            assert_eq!(info.sni_hostname.as_deref(), *expected, "{}", name);
+        }
+        
+        let mut data = build_client_hello("discord.com", &[0x11; 32]);
+        data[65] = 0xFF;
+        let info = parse_client_hello(&data).unwrap();
+        assert_eq!((info.sni_offset, info.sni_length), (Some(65), Some(11)));
+    }
+    
+    #[test]
+    fn test_parse_client_hello_split_across_records() {
+        let hello = build_client_hello("discord.com", &[0x11; 32]);
+        
+        // The first record ends inside the SNI extension; the second
+        // record's header must not be read as hostname bytes.
+        let records = split_into_records(&hello, &[60]).unwrap();
+        let stream: Vec<u8> = records.iter().flat_map(|r| r.iter().copied()).collect();
+        let info = parse_client_hello(&stream).unwrap();
+        assert_eq!(info.record_length, 65);
+        assert_eq!(info.sni_hostname, None);
+        assert_sni_in_bounds(&stream[..65], &info);
+        
+        let records = split_into_records(&hello, &[90]).unwrap();
+        let stream: Vec<u8> = records.iter().flat_map(|r| r.iter().copied()).collect();
+        let info = parse_client_hello(&stream).unwrap();
+        assert_eq!(info.sni_hostname.as_deref(), Some("discord.com"));
+    }
+    
+    proptest::proptest! {
+        #[test]
+        fn prop_parse_client_hello_stays_in_bounds(data in proptest::collection::vec(proptest::num::u8::ANY, 0..300)) {
+            if let Some(info) = parse_client_hello(&data) {
+                assert_sni_in_bounds(&data, &info);
+            }
+        }
+        
+        #[test]
+        fn prop_parse_corrupted_client_hello(
+            edits in proptest::collection::vec((0..90usize, proptest::num::u8::ANY), 1..6),
+            cut in 0..90usize,
+        ) {
+            let mut data = build_client_hello("discord.com", &[0x11; 32]);
+            for (offset, byte) in edits {
+                data[offset] = byte;
+            }
+            data.truncate(data.len() - cut);
+            if let Some(info) = parse_client_hello(&data) {
+                assert_sni_in_bounds(&data, &info);
+            }
+        }
+    }
+    
     #[test]
     fn test_get_split_points() {
         let data = sample_client_hello();
@@ -389,14 +855,80 @@ mod tests {
         assert_eq!(host, "discord.com");
     }
     
+    #[test]
+    fn test_find_http_host_unusual_layouts() {
+        let host = |request: &[u8]| find_http_host(request).map(|(offset, len)| request[offset..offset + len].to_vec());
+        
+        let reordered = b"POST /upload HTTP/1.1\r\nContent-Length: 4\r\nX-Forwarded-Host: proxy\r\nhOsT:\t  discord.com \r\n\r\nbody";
+        assert_eq!(host(reordered).as_deref(), Some(&b"discord.com"[..]));
+        assert_eq!(&reordered[find_http_host_header(reordered).unwrap()..][..4], b"hOsT");
+        
+        let indented = b"GET / HTTP/1.1\n  Host : discord.com\n\n";
+        assert_eq!(host(indented).as_deref(), Some(&b"discord.com"[..]));
+        assert_eq!(find_http_host_header(indented), Some(17));
+        
+        assert_eq!(host(b"GET / HTTP/1.1\r\nHost: last.example"), Some(b"last.example".to_vec()));
+        assert_eq!(host(b"GET / HTTP/1.1\r\nAccept: */*\r\n\r\nHost: in.body"), None);
+        assert_eq!(host(b"GET / HTTP/1.1\r\nX-Bin: \xff\xfe\r\nHost: a.b\r\n\r\n"), Some(b"a.b".to_vec()));
+    }
+    
     #[test]
     fn test_fragment_at_offsets() {
         let data = b"Hello, World!";
         
-        let fragments = fragment_at_offsets(data, &[5, 7]);
+        let fragments = fragment_at_offsets(BytesMut::from(&data[..]), &[5, 7, 7, 40]);
         assert_eq!(fragments.len(), 3);
         assert_eq!(&fragments[0][..], b"Hello");
         assert_eq!(&fragments[1][..], b", ");
         assert_eq!(&fragments[2][..], b"World!");
     }
+    
+    #[test]
+    fn test_fragments_share_allocation() {
+        let data = BytesMut::from(&client_hello_with_alpn(&[])[..]);
+        let base = data.as_ptr();
+        let len = data.len();
+        
+        let fragments = fragment_at_offsets(data, &[5, 40, 41]);
+        assert_eq!(fragments.len(), 4);
+        assert_eq!(fragments[0].as_ptr(), base);
+        assert_eq!(fragments[3].as_ptr(), base.wrapping_add(41));
+        assert_eq!(fragments.iter().map(|f| f.len()).sum::<usize>(), len);
+        
+        let data = client_hello_with_alpn(&[]);
+        let records = split_into_records(&data, &[10, 20]).unwrap();
+        for pair in records.windows(2) {
+            assert_eq!(pair[1].as_ptr(), pair[0].as_ptr().wrapping_add(pair[0].len()));
+        }
+    }
+    
+    #[test]
+    fn test_split_into_records() {
+        let data = client_hello_with_alpn(&[]);
+        let records = split_into_records(&data, &[10, 0, 20]).unwrap();
+        assert_eq!(records.len(), 3);
+        
+        let stream: Vec<u8> = records.iter().flat_map(|r| r.iter().copied()).collect();
+        let mut pos = 0;
+        let mut payload = Vec::new();
+        let mut lengths = Vec::new();
+        while pos < stream.len() {
+            assert_eq!(&stream[pos..pos + 3], &data[..3]);
+            let len = u16::from_be_bytes([stream[pos + 3], stream[pos + 4]]) as usize;
+            payload.extend_from_slice(&stream[pos + 5..pos + 5 + len]);
+            lengths.push(len);
+            pos += 5 + len;
+        }
+        
+        assert_eq!(pos, stream.len());
+        assert_eq!(lengths, vec![10, 20, data.len() - 35]);
+        assert_eq!(payload, &data[5..]);
+    }
+    
+    #[test]
+    fn test_split_into_records_invalid() {
+        let data = client_hello_with_alpn(&[]);
+        assert!(split_into_records(&data[..data.len() - 1], &[10]).is_none());
+        assert!(split_into_records(&data[..3], &[1]).is_none());
+    }
 }
@@ -1,9 +1,11 @@
 use bytes::BytesMut;
+use rand::Rng;
 use tracing::trace;
 
-use crate::config::{DecoyParams, TransformParams};
+use crate::checksum;
+use crate::config::{DecoyCorruption, DecoyParams, TransformParams};
 use crate::error::Result;
-use crate::flow::FlowContext;
+use crate::flow::{EmitKind, EmittedPacket, FlowContext, FlowDirection};
 use super::{Transform, TransformResult};
 
 pub struct DecoyTransform {
@@ -18,28 +20,45 @@ impl DecoyTransform {
     }
 
     fn create_decoy(&self, original: &[u8]) -> Option<BytesMut> {
-        if original.len() < 20 {
-            return None;
-        }
-
-        let version = (original[0] >> 4) & 0x0F;
-        if version != 4 {
-            return None;
-        }
-
+        let ihl = checksum::ipv4_header_len(original)?;
         let mut decoy = BytesMut::from(original);
         
-        decoy[8] = self.params.ttl;
-        
-        if decoy.len() > 5 {
-            decoy[4] ^= 0xFF;
-            decoy[5] ^= 0xFF;
+        match &self.params.corruption {
+            DecoyCorruption::LowTtl => {
+                decoy[8] = self.params.ttl;
+                checksum::set_ipv4_header_checksum(&mut decoy);
+            }
+            DecoyCorruption::BadChecksum => {
+                let tcp = tcp_header(&decoy, ihl)?;
+                let correct = checksum::tcp_ipv4_checksum(src_addr(&decoy), dst_addr(&decoy), &decoy[ihl..]);
+                // Flipping only the low byte never swaps 0x0000 and 0xFFFF,
+                // the one pair of values a receiver treats as equal.
+                let broken = correct ^ 0x00FF;
+                decoy[tcp.checksum..tcp.checksum + 2].copy_from_slice(&broken.to_be_bytes());
+            }
+            DecoyCorruption::BadSeq => {
+                let tcp = tcp_header(&decoy, ihl)?;
+                let seq = tcp.start + 4;
+                let old = u32::from_be_bytes([decoy[seq], decoy[seq + 1], decoy[seq + 2], decoy[seq + 3]]);
+                let new = old.wrapping_add(1 << 31).to_be_bytes();
+                if decoy[tcp.checksum..tcp.checksum + 2] == [0, 0] {
+                    decoy[seq..seq + 4].copy_from_slice(&new);
+                } else {
+                    checksum::replace_u16(&mut decoy, seq, u16::from_be_bytes([new[0], new[1]]), tcp.checksum);
+                    checksum::replace_u16(&mut decoy, seq + 2, u16::from_be_bytes([new[2], new[3]]), tcp.checksum);
+                }
+            }
+            DecoyCorruption::FakePayload { template } => {
+                decoy = with_tcp_payload(original, template)?;
+                decoy[8] = self.params.ttl;
+                checksum::set_ipv4_header_checksum(&mut decoy);
+            }
         }
 
         Some(decoy)
     }
 
-    fn should_send_decoy(&self, seed: u64) -> bool {
+    fn should_send_decoy(&self, rng: &mut impl Rng) -> bool {
         if self.params.probability <= 0.0 {
             return false;
         }
@@ -47,8 +66,7 @@ impl DecoyTransform {
             return true;
         }
         
-        let threshold = (self.params.probability * 1000.0) as u64;
-        (seed % 1000) < threshold
+        rng.gen_bool(self.params.probability as f64)
     }
 }
 
@@ -58,15 +76,15 @@ impl Transform for DecoyTransform {
     }
 
     fn apply(&self, ctx: &mut FlowContext<'_>, data: &mut BytesMut) -> Result<TransformResult> {
-        if !self.params.send_before && !self.params.send_after {
+        // Decoys only make sense towards the censor, never back to the client.
+        if ctx.direction == FlowDirection::Inbound
+            || self.params.count == 0
+            || (!self.params.send_before && !self.params.send_after)
+        {
             return Ok(TransformResult::Continue);
         }
 
-        let seed = ctx.state.packet_count
-            .wrapping_mul(0x1337CAFE)
-            .wrapping_add(data.len() as u64);
-
-        if !self.should_send_decoy(seed) {
+        if !self.should_send_decoy(&mut ctx.state.rng(self.params.seed)) {
             return Ok(TransformResult::Continue);
         }
 
@@ -81,15 +99,21 @@ impl Transform for DecoyTransform {
             "generating decoy packet"
         );
 
+        let mut decoy = EmittedPacket::new(decoy, EmitKind::Fake);
+        if matches!(self.params.corruption, DecoyCorruption::LowTtl | DecoyCorruption::FakePayload { .. }) {
+            decoy = decoy.with_ttl(self.params.ttl);
+        }
+        
         if self.params.send_before {
-            let real = data.clone();
-            data.clear();
-            data.extend_from_slice(&decoy);
-            ctx.emit(real);
+            for _ in 0..self.params.count {
+                ctx.send_before(data, decoy.clone());
+            }
         }
 
         if self.params.send_after {
-            ctx.emit(decoy);
+            for _ in 0..self.params.count {
+                ctx.emit(decoy.clone());
+            }
         }
 
         Ok(TransformResult::Fragmented)
@@ -97,10 +121,55 @@ impl Transform for DecoyTransform {
 
     fn is_enabled(&self, params: &TransformParams) -> bool {
         params.decoy.probability > 0.0 
+            && params.decoy.count > 0
             && (params.decoy.send_before || params.decoy.send_after)
     }
 }
 
+pub(super) struct TcpHeader {
+    pub(super) start: usize,
+    pub(super) checksum: usize,
+    pub(super) payload: usize,
+}
+
+pub(super) fn tcp_header(packet: &[u8], ihl: usize) -> Option<TcpHeader> {
+    if packet[9] != 6 || packet.len() < ihl + 20 {
+        return None;
+    }
+    let payload = ihl + (packet[ihl + 12] >> 4) as usize * 4;
+    (payload >= ihl + 20 && payload <= packet.len()).then_some(TcpHeader {
+        start: ihl,
+        checksum: ihl + 16,
+        payload,
+    })
+}
+
+pub(super) fn with_tcp_payload(packet: &[u8], payload: &[u8]) -> Option<BytesMut> {
+    let ihl = checksum::ipv4_header_len(packet)?;
+    let tcp = tcp_header(packet, ihl)?;
+    let offloaded = packet[tcp.checksum..tcp.checksum + 2] == [0, 0];
+    
+    let mut out = BytesMut::with_capacity(tcp.payload + payload.len());
+    out.extend_from_slice(&packet[..tcp.payload]);
+    out.extend_from_slice(payload);
+    let total_len = u16::try_from(out.len()).ok()?;
+    out[2..4].copy_from_slice(&total_len.to_be_bytes());
+    checksum::set_ipv4_header_checksum(&mut out);
+    if !offloaded {
+        let sum = checksum::tcp_ipv4_checksum(src_addr(&out), dst_addr(&out), &out[ihl..]);
+        out[tcp.checksum..tcp.checksum + 2].copy_from_slice(&sum.to_be_bytes());
+    }
+    Some(out)
+}
+
+fn src_addr(packet: &[u8]) -> [u8; 4] {
+    [packet[12], packet[13], packet[14], packet[15]]
+}
+
+fn dst_addr(packet: &[u8]) -> [u8; 4] {
+    [packet[16], packet[17], packet[18], packet[19]]
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -142,6 +211,9 @@ mod tests {
             send_after: false,
             ttl: 1,
             probability: 1.0,
+            corruption: DecoyCorruption::LowTtl,
+            count: 1,
+            seed: None,
         };
         let transform = DecoyTransform::new(&params);
         
@@ -162,6 +234,9 @@ mod tests {
             send_after: true,
             ttl: 1,
             probability: 0.0,
+            corruption: DecoyCorruption::LowTtl,
+            count: 1,
+            seed: None,
         };
         let transform = DecoyTransform::new(&params);
         
@@ -182,6 +257,9 @@ mod tests {
             send_after: true,
             ttl: 3,
             probability: 1.0,
+            corruption: DecoyCorruption::LowTtl,
+            count: 1,
+            seed: None,
         };
         let transform = DecoyTransform::new(&params);
         
@@ -199,7 +277,9 @@ mod tests {
         assert_eq!(data[8], 0x40);
         
         
-        assert_eq!(ctx.output_packets[0][8], 3);
+        assert_eq!(ctx.output_packets[0].data[8], 3);
+        assert_eq!(ctx.output_packets[0].kind, EmitKind::Fake);
+        assert_eq!(ctx.output_packets[0].ttl_override, Some(3));
     }
 
     #[test]
@@ -209,6 +289,9 @@ mod tests {
             send_after: false,
             ttl: 2,
             probability: 1.0,
+            corruption: DecoyCorruption::LowTtl,
+            count: 1,
+            seed: None,
         };
         let transform = DecoyTransform::new(&params);
         
@@ -225,7 +308,9 @@ mod tests {
         assert_eq!(data[8], 2);
         
         
-        assert_eq!(ctx.output_packets[0][8], 0x40);
+        assert_eq!(ctx.output_packets[0].data[8], 0x40);
+        assert_eq!((ctx.data_kind, ctx.data_ttl), (EmitKind::Fake, Some(2)));
+        assert_eq!((ctx.output_packets[0].kind, ctx.output_packets[0].ttl_override), (EmitKind::Real, None));
     }
 
     #[test]
@@ -235,6 +320,9 @@ mod tests {
             send_after: true,
             ttl: 1,
             probability: 1.0,
+            corruption: DecoyCorruption::LowTtl,
+            count: 1,
+            seed: None,
         };
         let transform = DecoyTransform::new(&params);
 
@@ -245,10 +333,232 @@ mod tests {
         assert_eq!(decoy[8], 1);
         
         
-        assert_ne!(decoy[4], original[4]);
-        assert_ne!(decoy[5], original[5]);
+        assert_eq!(decoy[4..6], original[4..6]);
     }
 
+    #[test]
+    fn test_decoy_header_checksum() {
+        let params = DecoyParams {
+            send_before: false,
+            send_after: true,
+            ttl: 1,
+            probability: 1.0,
+            corruption: DecoyCorruption::LowTtl,
+            count: 1,
+            seed: None,
+        };
+        let transform = DecoyTransform::new(&params);
+        
+        let mut original = create_ipv4_packet();
+        original[10] = 0x96;
+        original[11] = 0xE3;
+        let decoy = transform.create_decoy(&original).unwrap();
+        
+        assert_eq!(&decoy[10..12], &[0xD5, 0xE3]);
+        assert_eq!(&decoy[20..], &original[20..]);
+    }
+    
+    fn decoy_params(corruption: DecoyCorruption) -> DecoyParams {
+        DecoyParams {
+            send_before: false,
+            send_after: true,
+            ttl: 1,
+            probability: 1.0,
+            corruption,
+            count: 1,
+            seed: None,
+        }
+    }
+    
+    fn tcp_packet() -> BytesMut {
+        let mut packet = create_ipv4_packet();
+        packet.extend_from_slice(b"\x16\x03\x01\x00\x05hello");
+        let total_len = (packet.len() as u16).to_be_bytes();
+        packet[2..4].copy_from_slice(&total_len);
+        checksum::set_ipv4_header_checksum(&mut packet);
+        let tcp = checksum::tcp_ipv4_checksum(src_addr(&packet), dst_addr(&packet), &packet[20..]);
+        packet[36..38].copy_from_slice(&tcp.to_be_bytes());
+        packet
+    }
+    
+    fn ip_valid(packet: &[u8]) -> bool {
+        checksum::checksum(&packet[..20]) == 0
+    }
+    
+    fn tcp_valid(packet: &[u8]) -> bool {
+        let segment_len = (packet.len() as u16 - 20).to_be_bytes();
+        let pseudo = [&packet[12..20], &[0, 6], &segment_len[..], &packet[20..]].concat();
+        checksum::checksum(&pseudo) == 0
+    }
+    
+    fn changed(a: &[u8], b: &[u8]) -> Vec<usize> {
+        a.iter().zip(b).enumerate().filter(|(_, (x, y))| x != y).map(|(i, _)| i).collect()
+    }
+    
+    fn assert_only_changed(original: &[u8], decoy: &[u8], allowed: &[usize]) {
+        let diff = changed(original, decoy);
+        assert!(!diff.is_empty());
+        assert!(diff.iter().all(|i| allowed.contains(i)), "changed bytes {:?}, allowed {:?}", diff, allowed);
+    }
+    
+    #[test]
+    fn test_corruption_low_ttl() {
+        let transform = DecoyTransform::new(&decoy_params(DecoyCorruption::LowTtl));
+        let original = tcp_packet();
+        let decoy = transform.create_decoy(&original).unwrap();
+        
+        assert_eq!(decoy.len(), original.len());
+        assert_eq!(decoy[8], 1);
+        assert_only_changed(&original, &decoy, &[8, 10, 11]);
+        assert!(ip_valid(&decoy));
+        assert!(tcp_valid(&decoy));
+    }
+    
+    #[test]
+    fn test_corruption_bad_checksum() {
+        let transform = DecoyTransform::new(&decoy_params(DecoyCorruption::BadChecksum));
+        let original = tcp_packet();
+        let decoy = transform.create_decoy(&original).unwrap();
+        
+        assert_eq!(decoy.len(), original.len());
+        assert_only_changed(&original, &decoy, &[36, 37]);
+        assert!(ip_valid(&decoy));
+        assert!(!tcp_valid(&decoy));
+        
+        let mut offloaded = tcp_packet();
+        offloaded[36..38].fill(0);
+        let decoy = transform.create_decoy(&offloaded).unwrap();
+        assert!(!tcp_valid(&decoy));
+        assert_ne!(&decoy[36..38], &[0xFF, 0xFF]);
+    }
+    
+    #[test]
+    fn test_corruption_bad_seq() {
+        let transform = DecoyTransform::new(&decoy_params(DecoyCorruption::BadSeq));
+        let original = tcp_packet();
+        let decoy = transform.create_decoy(&original).unwrap();
+        
+        assert_eq!(decoy.len(), original.len());
+        assert_only_changed(&original, &decoy, &[24, 25, 26, 27, 36, 37]);
+        let seq = |p: &[u8]| u32::from_be_bytes([p[24], p[25], p[26], p[27]]);
+        assert_eq!(seq(&decoy), seq(&original).wrapping_add(1 << 31));
+        assert_eq!(decoy[8], original[8]);
+        assert!(ip_valid(&decoy));
+        assert!(tcp_valid(&decoy));
+        
+        let mut offloaded = tcp_packet();
+        offloaded[36..38].fill(0);
+        let decoy = transform.create_decoy(&offloaded).unwrap();
+        assert_only_changed(&offloaded, &decoy, &[24, 25, 26, 27]);
+    }
+    
+    #[test]
+    fn test_corruption_fake_payload() {
+        let template = b"GET / HTTP/1.1\r\nHost: www.example.com\r\n\r\n".to_vec();
+        let transform = DecoyTransform::new(&decoy_params(DecoyCorruption::FakePayload { template: template.clone() }));
+        let original = tcp_packet();
+        let decoy = transform.create_decoy(&original).unwrap();
+        
+        assert_eq!(decoy.len(), 40 + template.len());
+        assert_eq!(&decoy[40..], &template[..]);
+        assert_only_changed(&original[..40], &decoy[..40], &[2, 3, 8, 10, 11, 36, 37]);
+        assert_eq!(u16::from_be_bytes([decoy[2], decoy[3]]) as usize, decoy.len());
+        assert_eq!(decoy[8], 1);
+        assert!(ip_valid(&decoy));
+        assert!(tcp_valid(&decoy));
+    }
+    
+    #[test]
+    fn test_tcp_corruptions_skip_udp() {
+        let mut udp = tcp_packet();
+        udp[9] = 17;
+        for corruption in [DecoyCorruption::BadChecksum, DecoyCorruption::BadSeq] {
+            assert!(DecoyTransform::new(&decoy_params(corruption)).create_decoy(&udp).is_none());
+        }
+        assert!(DecoyTransform::new(&decoy_params(DecoyCorruption::LowTtl)).create_decoy(&udp).is_some());
+    }
+    
+    #[test]
+    fn test_decoy_count() {
+        let key = test_flow_key();
+        let mut state = FlowState::new(key);
+        let original = tcp_packet();
+        
+        let before = DecoyTransform::new(&DecoyParams {
+            send_before: true,
+            send_after: false,
+            count: 3,
+            ..decoy_params(DecoyCorruption::BadSeq)
+        });
+        let decoy = before.create_decoy(&original).unwrap();
+        let mut ctx = FlowContext::new(&key, &mut state, None);
+        let mut data = original.clone();
+        assert_eq!(before.apply(&mut ctx, &mut data).unwrap(), TransformResult::Fragmented);
+        assert_eq!(data, decoy);
+        let fake = EmittedPacket::new(decoy.clone(), EmitKind::Fake);
+        assert_eq!(ctx.output_packets, vec![fake.clone(), fake.clone(), original.clone().into()]);
+        assert_eq!((ctx.data_kind, ctx.data_ttl), (EmitKind::Fake, None));
+        
+        let after = DecoyTransform::new(&DecoyParams {
+            count: 2,
+            ..decoy_params(DecoyCorruption::BadSeq)
+        });
+        let mut ctx = FlowContext::new(&key, &mut state, None);
+        let mut data = original.clone();
+        after.apply(&mut ctx, &mut data).unwrap();
+        assert_eq!(data, original);
+        assert_eq!(ctx.output_packets, vec![fake.clone(), fake]);
+        
+        let none = DecoyTransform::new(&DecoyParams {
+            count: 0,
+            ..decoy_params(DecoyCorruption::LowTtl)
+        });
+        let mut ctx = FlowContext::new(&key, &mut state, None);
+        assert_eq!(none.apply(&mut ctx, &mut data).unwrap(), TransformResult::Continue);
+        assert!(ctx.output_packets.is_empty());
+    }
+    
+    #[test]
+    fn test_decoy_probability() {
+        let transform = DecoyTransform::new(&DecoyParams {
+            probability: 0.3,
+            ..decoy_params(DecoyCorruption::LowTtl)
+        });
+        
+        let key = test_flow_key();
+        let mut state = FlowState::new(key);
+        let mut rng = state.rng(None);
+        let sent = (0..10_000).filter(|_| transform.should_send_decoy(&mut rng)).count();
+        assert!((2_500..3_500).contains(&sent), "{}", sent);
+        
+        let seeded = |mut state: FlowState| {
+            (0..64)
+                .map(|i| {
+                    state.packet_count = i;
+                    transform.should_send_decoy(&mut state.rng(Some(9)))
+                })
+                .collect::<Vec<_>>()
+        };
+        let draws = seeded(FlowState::new(key));
+        assert_eq!(draws, seeded(FlowState::new(key.reverse())));
+        assert!(draws.contains(&true) && draws.contains(&false));
+    }
+    
+    #[test]
+    fn test_corruption_from_config() {
+        let config = crate::Config::from_toml(
+            "[transforms.decoy]\ncorruption = \"bad_seq\"\ncount = 2\n",
+        ).unwrap();
+        assert_eq!(config.transforms.decoy.corruption, DecoyCorruption::BadSeq);
+        assert_eq!(config.transforms.decoy.count, 2);
+        
+        let config = crate::Config::from_toml(
+            "[transforms.decoy.corruption.fake_payload]\ntemplate = [1, 2, 3]\n",
+        ).unwrap();
+        assert_eq!(config.transforms.decoy.corruption, DecoyCorruption::FakePayload { template: vec![1, 2, 3] });
+        assert_eq!(config.transforms.decoy.count, 1);
+    }
+    
     #[test]
     fn test_small_packet_no_decoy() {
         let params = DecoyParams {
@@ -256,6 +566,9 @@ mod tests {
             send_after: true,
             ttl: 1,
             probability: 1.0,
+            corruption: DecoyCorruption::LowTtl,
+            count: 1,
+            seed: None,
         };
         let transform = DecoyTransform::new(&params);
         
@@ -268,4 +581,29 @@ mod tests {
         assert_eq!(result, TransformResult::Continue);
         assert!(ctx.output_packets.is_empty());
     }
+    
+    #[test]
+    fn test_inbound_no_decoy() {
+        let params = DecoyParams {
+            send_before: true,
+            send_after: true,
+            ttl: 1,
+            probability: 1.0,
+            corruption: DecoyCorruption::LowTtl,
+            count: 1,
+            seed: None,
+        };
+        let transform = DecoyTransform::new(&params);
+        
+        let key = test_flow_key();
+        let mut state = FlowState::new(key);
+        let mut ctx = FlowContext::new(&key, &mut state, None).with_direction(FlowDirection::Inbound);
+        let original = create_ipv4_packet();
+        let mut data = original.clone();
+        
+        let result = transform.apply(&mut ctx, &mut data).unwrap();
+        assert_eq!(result, TransformResult::Continue);
+        assert!(ctx.output_packets.is_empty());
+        assert_eq!(data, original);
+    }
 }
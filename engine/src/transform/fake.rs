@@ -0,0 +1,212 @@
+use bytes::BytesMut;
+use rand::Rng;
+use tracing::trace;
+
+use crate::config::{FakeParams, FakePayload, TransformParams};
+use crate::error::Result;
+use crate::flow::{EmitKind, EmittedPacket, FlowContext, FlowDirection};
+use crate::tls::build_client_hello;
+use super::decoy::{tcp_header, with_tcp_payload};
+use super::{Transform, TransformResult};
+
+pub struct FakeTransform {
+    params: FakeParams,
+}
+
+impl FakeTransform {
+    pub fn new(params: &FakeParams) -> Self {
+        Self {
+            params: params.clone(),
+        }
+    }
+    
+    fn payload(&self, rng: &mut impl Rng) -> Vec<u8> {
+        match &self.params.payload {
+            FakePayload::Http { host } => format!("GET / HTTP/1.1\r\nHost: {}\r\n\r\n", host).into_bytes(),
+            FakePayload::TlsClientHello { sni } => build_client_hello(sni, &rng.gen()),
+            FakePayload::Raw(bytes) => bytes.clone(),
+        }
+    }
+}
+
+impl Transform for FakeTransform {
+    fn name(&self) -> &'static str {
+        "fake"
+    }
+    
+    fn apply(&self, ctx: &mut FlowContext<'_>, data: &mut BytesMut) -> Result<TransformResult> {
+        if ctx.direction == FlowDirection::Inbound
+            || self.params.repeat == 0
+            || ctx.state.transform_state.fake.sent
+        {
+            return Ok(TransformResult::Continue);
+        }
+        
+        let payload = self.payload(&mut ctx.state.rng(None));
+        let fake = if ctx.is_stream {
+            if data.is_empty() {
+                return Ok(TransformResult::Continue);
+            }
+            BytesMut::from(&payload[..])
+        } else {
+            // Packets without a TCP payload (handshake, bare ACKs) are not
+            // what the DPI classifies the flow by.
+            let has_payload = crate::checksum::ipv4_header_len(data)
+                .and_then(|ihl| tcp_header(data, ihl))
+                .is_some_and(|tcp| tcp.payload < data.len());
+            match with_tcp_payload(data, &payload) {
+                Some(fake) if has_payload => fake,
+                _ => return Ok(TransformResult::Continue),
+            }
+        };
+        
+        ctx.state.transform_state.fake.sent = true;
+        for _ in 0..self.params.repeat {
+            ctx.send_before(data, EmittedPacket::new(fake.clone(), EmitKind::Fake));
+        }
+        
+        trace!(
+            flow = ?ctx.key,
+            bytes = fake.len(),
+            repeat = self.params.repeat,
+            "sending fake before first data"
+        );
+        
+        Ok(TransformResult::Fragmented)
+    }
+    
+    fn is_enabled(&self, params: &TransformParams) -> bool {
+        params.fake.repeat > 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+    use crate::checksum;
+    use crate::config::Protocol;
+    use crate::flow::{FlowKey, FlowState};
+    use crate::tls::parse_client_hello;
+    
+    fn test_flow_key() -> FlowKey {
+        FlowKey::new(
+            IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)),
+            IpAddr::V4(Ipv4Addr::new(93, 184, 216, 34)),
+            40000,
+            80,
+            Protocol::Tcp,
+        )
+    }
+    
+    fn fake(payload: FakePayload, repeat: u8) -> FakeTransform {
+        FakeTransform::new(&FakeParams { payload, repeat })
+    }
+    
+    fn tcp_packet(payload: &[u8]) -> BytesMut {
+        let mut packet = BytesMut::from(&[
+            0x45, 0x00, 0x00, 0x00,
+            0x12, 0x34, 0x40, 0x00,
+            0x40, 0x06, 0x00, 0x00,
+            192, 168, 1, 1,
+            93, 184, 216, 34,
+            0x9C, 0x40, 0x00, 0x50,
+            0x00, 0x00, 0x10, 0x00,
+            0x00, 0x00, 0x20, 0x00,
+            0x50, 0x18, 0x72, 0x10,
+            0x00, 0x00, 0x00, 0x00,
+        ][..]);
+        packet.extend_from_slice(payload);
+        let total_len = (packet.len() as u16).to_be_bytes();
+        packet[2..4].copy_from_slice(&total_len);
+        checksum::set_ipv4_header_checksum(&mut packet);
+        let sum = checksum::tcp_ipv4_checksum([192, 168, 1, 1], [93, 184, 216, 34], &packet[20..]);
+        packet[36..38].copy_from_slice(&sum.to_be_bytes());
+        packet
+    }
+    
+    fn sent(ctx: &FlowContext<'_>, data: BytesMut) -> Vec<(BytesMut, EmitKind)> {
+        std::iter::once((data, ctx.data_kind))
+            .chain(ctx.output_packets.iter().map(|p| (p.data.clone(), p.kind)))
+            .collect()
+    }
+    
+    #[test]
+    fn test_fake_precedes_stream_data() {
+        let transform = fake(FakePayload::default(), 2);
+        let key = test_flow_key();
+        let mut state = FlowState::new(key);
+        let mut ctx = FlowContext::new(&key, &mut state, None).with_stream(true);
+        let real = BytesMut::from(&b"GET /blocked HTTP/1.1\r\nHost: blocked.example\r\n\r\n"[..]);
+        let mut data = real.clone();
+        
+        assert_eq!(transform.apply(&mut ctx, &mut data).unwrap(), TransformResult::Fragmented);
+        let fake = BytesMut::from(&b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n"[..]);
+        assert_eq!(sent(&ctx, data), vec![
+            (fake.clone(), EmitKind::Fake),
+            (fake, EmitKind::Fake),
+            (real, EmitKind::Real),
+        ]);
+    }
+    
+    #[test]
+    fn test_fake_sent_once_per_flow() {
+        let transform = fake(FakePayload::Raw(vec![1, 2, 3]), 1);
+        let key = test_flow_key();
+        let mut state = FlowState::new(key);
+        
+        let mut ctx = FlowContext::new(&key, &mut state, None).with_stream(true);
+        let mut data = BytesMut::from(&b"first"[..]);
+        transform.apply(&mut ctx, &mut data).unwrap();
+        assert_eq!(&data[..], &[1, 2, 3]);
+        assert_eq!(&ctx.output_packets[0].data[..], b"first");
+        
+        let mut ctx = FlowContext::new(&key, &mut state, None).with_stream(true);
+        let mut data = BytesMut::from(&b"second"[..]);
+        assert_eq!(transform.apply(&mut ctx, &mut data).unwrap(), TransformResult::Continue);
+        assert_eq!(&data[..], b"second");
+        assert!(ctx.output_packets.is_empty());
+        
+        let mut fresh = FlowState::new(key);
+        let mut ctx = FlowContext::new(&key, &mut fresh, None)
+            .with_stream(true)
+            .with_direction(FlowDirection::Inbound);
+        assert_eq!(transform.apply(&mut ctx, &mut data).unwrap(), TransformResult::Continue);
+        assert!(ctx.output_packets.is_empty());
+    }
+    
+    #[test]
+    fn test_fake_client_hello() {
+        let transform = fake(FakePayload::TlsClientHello { sni: "www.w3.org".to_string() }, 1);
+        let key = test_flow_key();
+        let mut state = FlowState::new(key);
+        let mut ctx = FlowContext::new(&key, &mut state, None).with_stream(true);
+        let mut data = BytesMut::from(&b"\x16\x03\x01real"[..]);
+        
+        transform.apply(&mut ctx, &mut data).unwrap();
+        assert_eq!(ctx.data_kind, EmitKind::Fake);
+        let info = parse_client_hello(&data).unwrap();
+        assert_eq!(info.sni_hostname.as_deref(), Some("www.w3.org"));
+        assert_eq!(&ctx.output_packets[0].data[..], b"\x16\x03\x01real");
+    }
+    
+    #[test]
+    fn test_fake_packet_keeps_headers() {
+        let transform = fake(FakePayload::Raw(b"fake!".to_vec()), 1);
+        let key = test_flow_key();
+        let mut state = FlowState::new(key);
+        
+        let syn = tcp_packet(b"");
+        let mut ctx = FlowContext::new(&key, &mut state, None);
+        let mut data = syn.clone();
+        assert_eq!(transform.apply(&mut ctx, &mut data).unwrap(), TransformResult::Continue);
+        assert_eq!(data, syn);
+        
+        let real = tcp_packet(b"GET /blocked HTTP/1.1\r\n\r\n");
+        let mut ctx = FlowContext::new(&key, &mut state, None);
+        let mut data = real.clone();
+        assert_eq!(transform.apply(&mut ctx, &mut data).unwrap(), TransformResult::Fragmented);
+        assert_eq!(data, tcp_packet(b"fake!"));
+        assert_eq!(ctx.output_packets, vec![real.into()]);
+    }
+}
@@ -1,9 +1,11 @@
 use bytes::BytesMut;
+use rand::Rng;
 use tracing::{debug, trace};
 
 use crate::config::{FragmentParams, TransformParams};
 use crate::error::Result;
 use crate::flow::FlowContext;
+use crate::tls::{fragment_at_offsets, is_client_hello, parse_client_hello};
 use super::{Transform, TransformResult};
 
 pub struct FragmentTransform {
@@ -17,40 +19,36 @@ impl FragmentTransform {
         }
     }
 
-    fn calculate_fragment_size(&self, remaining: usize) -> usize {
+    fn calculate_fragment_size(&self, rng: &mut impl Rng) -> usize {
         if self.params.randomize {
-            let range = self.params.max_size - self.params.min_size;
-            if range == 0 {
-                self.params.min_size
-            } else {
-                let pseudo_random = (remaining * 31337) % (range + 1);
-                self.params.min_size + pseudo_random
-            }
+            rng.gen_range(self.params.min_size..=self.params.max_size)
         } else {
             self.params.max_size
         }
     }
 
-    pub fn fragment_data(&self, data: &[u8]) -> Vec<BytesMut> {
+    /// Splits `data` in place; every fragment shares the original allocation.
+    pub fn fragment_data(&self, mut data: BytesMut, rng: &mut impl Rng) -> Vec<BytesMut> {
         let mut fragments = Vec::new();
-        let mut offset = 0;
+        
+        if self.params.split_at_sni && is_client_hello(&data) {
+            let points = parse_client_hello(&data).map(|info| info.get_split_points()).unwrap_or_default();
+            if !points.is_empty() {
+                return fragment_at_offsets(data, &points);
+            }
+        }
         
         if let Some(split_at) = self.params.split_at_offset {
             if split_at > 0 && split_at < data.len() {
-                let first = BytesMut::from(&data[..split_at]);
-                let second = BytesMut::from(&data[split_at..]);
-                fragments.push(first);
-                fragments.push(second);
+                fragments.push(data.split_to(split_at));
+                fragments.push(data);
                 return fragments;
             }
         }     
-        while offset < data.len() {
-            let remaining = data.len() - offset;
-            let size = self.calculate_fragment_size(remaining).min(remaining);
+        while !data.is_empty() {
+            let size = self.calculate_fragment_size(rng).min(data.len());
             
-            let fragment = BytesMut::from(&data[offset..offset + size]);
-            fragments.push(fragment);
-            offset += size;
+            fragments.push(data.split_to(size));
         }
 
         fragments
@@ -73,31 +71,28 @@ impl Transform for FragmentTransform {
             return Ok(TransformResult::Continue);
         }
 
-        let fragments = self.fragment_data(data);
+        let original_size = data.len();
+        let mut rng = ctx.state.rng(self.params.seed);
+        let mut fragments = self.fragment_data(data.split(), &mut rng).into_iter();
+        *data = fragments.next().unwrap_or_default();
         
-        if fragments.len() <= 1 {
+        if fragments.len() == 0 {
             return Ok(TransformResult::Continue);
         }
 
         debug!(
             flow = ?ctx.key,
-            original_size = data.len(),
-            fragments = fragments.len(),
+            original_size,
+            fragments = fragments.len() + 1,
             "fragmented packet"
         );
 
         
-        ctx.state.transform_state.fragment.fragments_generated += fragments.len() as u32;
+        ctx.state.transform_state.fragment.fragments_generated += fragments.len() as u32 + 1;
 
         
-        for (i, fragment) in fragments.into_iter().enumerate() {
-            if i == 0 {
-                
-                data.clear();
-                data.extend_from_slice(&fragment);
-            } else {
-                ctx.emit(fragment);
-            }
+        for fragment in fragments {
+            ctx.emit_continuation(fragment);
         }
 
         Ok(TransformResult::Fragmented)
@@ -112,8 +107,14 @@ impl Transform for FragmentTransform {
 mod tests {
     use super::*;
     use std::net::{IpAddr, Ipv4Addr};
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
     use crate::config::Protocol;
     use crate::flow::{FlowKey, FlowState};
+    
+    fn test_rng() -> SmallRng {
+        SmallRng::seed_from_u64(7)
+    }
 
     fn test_context<'a>(key: &'a FlowKey, state: &'a mut FlowState) -> FlowContext<'a> {
         FlowContext::new(key, state, None)
@@ -136,11 +137,13 @@ mod tests {
             max_size: 10,
             split_at_offset: None,
             randomize: false,
+            split_at_sni: false,
+            seed: None,
         };
         let transform = FragmentTransform::new(&params);
 
         let data = b"Hello, this is a test message that should be fragmented";
-        let fragments = transform.fragment_data(data);
+        let fragments = transform.fragment_data(BytesMut::from(&data[..]), &mut test_rng());
 
         assert!(fragments.len() > 1);
         
@@ -156,6 +159,8 @@ mod tests {
             max_size: 20,
             split_at_offset: None,
             randomize: false,
+            split_at_sni: false,
+            seed: None,
         };
         let transform = FragmentTransform::new(&params);
         
@@ -176,11 +181,13 @@ mod tests {
             max_size: 100,
             split_at_offset: Some(5),
             randomize: false,
+            split_at_sni: false,
+            seed: None,
         };
         let transform = FragmentTransform::new(&params);
 
         let data = b"Hello, World!";
-        let fragments = transform.fragment_data(data);
+        let fragments = transform.fragment_data(BytesMut::from(&data[..]), &mut test_rng());
 
         assert_eq!(fragments.len(), 2);
         assert_eq!(&fragments[0][..], b"Hello");
@@ -194,6 +201,8 @@ mod tests {
             max_size: 5,
             split_at_offset: None,
             randomize: false,
+            split_at_sni: false,
+            seed: None,
         };
         let transform = FragmentTransform::new(&params);
 
@@ -217,6 +226,8 @@ mod tests {
             max_size: 7,
             split_at_offset: None,
             randomize: false,
+            split_at_sni: false,
+            seed: None,
         };
         let transform = FragmentTransform::new(&params);
 
@@ -231,9 +242,150 @@ mod tests {
         
         let mut all_data = data.to_vec();
         for packet in &ctx.output_packets {
-            all_data.extend_from_slice(packet);
+            all_data.extend_from_slice(&packet.data);
         }
 
         assert_eq!(all_data.as_slice(), original);
     }
+    
+    #[test]
+    fn test_fragments_alias_original_buffer() {
+        let params = FragmentParams {
+            min_size: 40,
+            max_size: 40,
+            split_at_offset: None,
+            randomize: false,
+            split_at_sni: false,
+            seed: None,
+        };
+        let transform = FragmentTransform::new(&params);
+        
+        let key = test_flow_key();
+        let mut state = FlowState::new(key);
+        let mut ctx = test_context(&key, &mut state);
+        let mut data = BytesMut::from(&[0x16; 2048][..]);
+        let base = data.as_ptr();
+        
+        let result = transform.apply(&mut ctx, &mut data).unwrap();
+        assert_eq!(result, TransformResult::Fragmented);
+        assert_eq!(ctx.output_packets.len(), 51);
+        
+        
+        let mut offset = 0;
+        for fragment in std::iter::once(&data).chain(ctx.output_packets.iter().map(|p| &p.data)) {
+            assert_eq!(fragment.as_ptr(), base.wrapping_add(offset));
+            offset += fragment.len();
+        }
+        assert_eq!(offset, 2048);
+    }
+    
+    #[test]
+    fn test_random_sizes_within_bounds() {
+        let params = FragmentParams {
+            min_size: 3,
+            max_size: 9,
+            split_at_offset: None,
+            randomize: true,
+            split_at_sni: false,
+            seed: None,
+        };
+        let transform = FragmentTransform::new(&params);
+        
+        let key = test_flow_key();
+        let mut state = FlowState::new(key);
+        let fragments = transform.fragment_data(BytesMut::from(&[0u8; 60_000][..]), &mut state.rng(None));
+        let (last, full) = fragments.split_last().unwrap();
+        assert!(!last.is_empty() && last.len() <= 9);
+        assert!(full.iter().all(|f| (3..=9).contains(&f.len())));
+        for size in 3..=9 {
+            assert!(full.iter().any(|f| f.len() == size), "size {} never drawn", size);
+        }
+    }
+    
+    #[test]
+    fn test_seed_makes_sizes_reproducible() {
+        let sizes = |seed: Option<u64>, src_port: u16| {
+            let params = FragmentParams {
+                min_size: 1,
+                max_size: 40,
+                split_at_offset: None,
+                randomize: true,
+                split_at_sni: false,
+                seed,
+            };
+            let transform = FragmentTransform::new(&params);
+            let key = FlowKey::new(
+                IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)),
+                IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)),
+                src_port,
+                443,
+                Protocol::Tcp,
+            );
+            let mut state = FlowState::new(key);
+            let mut ctx = test_context(&key, &mut state);
+            let mut data = BytesMut::from(&[0x16; 4096][..]);
+            transform.apply(&mut ctx, &mut data).unwrap();
+            std::iter::once(&data).chain(ctx.output_packets.iter().map(|p| &p.data)).map(|f| f.len()).collect::<Vec<_>>()
+        };
+        
+        assert_eq!(sizes(Some(42), 1000), sizes(Some(42), 2000));
+        assert_ne!(sizes(Some(42), 1000), sizes(Some(43), 1000));
+        assert_ne!(sizes(None, 1000), sizes(None, 2000));
+        assert_eq!(sizes(None, 1000), sizes(None, 1000));
+    }
+    
+    #[test]
+    fn test_split_at_sni() {
+        let params = FragmentParams {
+            min_size: 40,
+            max_size: 40,
+            split_at_offset: Some(3),
+            randomize: false,
+            split_at_sni: true,
+            seed: None,
+        };
+        let transform = FragmentTransform::new(&params);
+        
+        let key = test_flow_key();
+        let mut state = FlowState::new(key);
+        let mut ctx = test_context(&key, &mut state);
+        let hello = crate::tls::tests::sample_client_hello();
+        let mut data = BytesMut::from(&hello[..]);
+        
+        let result = transform.apply(&mut ctx, &mut data).unwrap();
+        assert_eq!(result, TransformResult::Fragmented);
+        
+        let fragments: Vec<&BytesMut> = std::iter::once(&data).chain(ctx.output_packets.iter().map(|p| &p.data)).collect();
+        assert_eq!(fragments.len(), 3);
+        assert_eq!(fragments.iter().map(|f| f.to_vec()).collect::<Vec<_>>().concat(), hello);
+        
+        let host = b"discord.com";
+        let host_start = hello.windows(host.len()).position(|w| w == host).unwrap();
+        let first_end = fragments[0].len();
+        let second_end = first_end + fragments[1].len();
+        assert!(first_end <= host_start);
+        assert!(second_end > host_start && second_end < host_start + host.len());
+        assert!(!fragments.iter().any(|f| f.windows(host.len()).any(|w| w == host)));
+    }
+    
+    #[test]
+    fn test_split_at_sni_falls_back_to_size() {
+        let params = FragmentParams {
+            min_size: 10,
+            max_size: 10,
+            split_at_offset: None,
+            randomize: false,
+            split_at_sni: true,
+            seed: None,
+        };
+        let transform = FragmentTransform::new(&params);
+        
+        let fragments = transform.fragment_data(BytesMut::from(&b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n"[..]), &mut test_rng());
+        assert_eq!(fragments.len(), 4);
+        assert!(fragments[..3].iter().all(|f| f.len() == 10));
+        
+        let hello = crate::tls::tests::sample_client_hello();
+        let fragments = transform.fragment_data(BytesMut::from(&hello[..40]), &mut test_rng());
+        assert_eq!(fragments.len(), 4);
+    }
 }
@@ -1,9 +1,10 @@
 use bytes::BytesMut;
 use tracing::trace;
 
+use crate::checksum;
 use crate::config::{HeaderParams, TransformParams};
 use crate::error::Result;
-use crate::flow::FlowContext;
+use crate::flow::{FlowContext, FlowDirection};
 use super::{Transform, TransformResult};
 
 pub struct HeaderNormalizationTransform {
@@ -39,6 +40,10 @@ impl HeaderNormalizationTransform {
             data[4] = new_id[0];
             data[5] = new_id[1];
         }
+        
+        if self.params.normalize_ttl || self.params.randomize_ip_id {
+            checksum::set_ipv4_header_checksum(data);
+        }
     }
 
     fn tcp_offset(&self, data: &[u8]) -> Option<usize> {
@@ -72,10 +77,15 @@ impl HeaderNormalizationTransform {
         };
 
         if self.params.normalize_window {
-            
-            let window = 65535u16.to_be_bytes();
-            data[tcp_offset + 14] = window[0];
-            data[tcp_offset + 15] = window[1];
+            let window = tcp_offset + 14;
+            let tcp_checksum = tcp_offset + 16;
+            // A zero checksum is left for the NIC to fill in (offload), so
+            // patching it here would produce a bogus value.
+            if data[tcp_checksum..tcp_checksum + 2] == [0, 0] {
+                data[window..window + 2].copy_from_slice(&65535u16.to_be_bytes());
+            } else {
+                checksum::replace_u16(data, window, 65535, tcp_checksum);
+            }
         }
     }
 }
@@ -86,6 +96,10 @@ impl Transform for HeaderNormalizationTransform {
     }
 
     fn apply(&self, ctx: &mut FlowContext<'_>, data: &mut BytesMut) -> Result<TransformResult> {
+        // Rewriting headers on replies would corrupt traffic we only relay.
+        if ctx.direction == FlowDirection::Inbound {
+            return Ok(TransformResult::Continue);
+        }
         
         let seed = ctx.state.packet_count.wrapping_mul(0xDEADBEEF);
 
@@ -275,4 +289,108 @@ mod tests {
         
         assert_eq!(data[..], original[..]);
     }
+    
+    fn naive_checksum(parts: &[&[u8]]) -> u16 {
+        let bytes: Vec<u8> = parts.concat();
+        let mut total: u64 = 0;
+        for pair in bytes.chunks(2) {
+            total += ((pair[0] as u64) << 8) | pair.get(1).copied().unwrap_or(0) as u64;
+        }
+        while total > 0xFFFF {
+            total = (total & 0xFFFF) + (total >> 16);
+        }
+        !(total as u16)
+    }
+    
+    fn tcp_pseudo_checksum(packet: &[u8]) -> u16 {
+        let segment = &packet[20..];
+        let pseudo = [&packet[12..20], &[0, 6], &(segment.len() as u16).to_be_bytes()[..]].concat();
+        naive_checksum(&[&pseudo, &segment[..16], &segment[18..]])
+    }
+    
+    fn checksummed_packet() -> BytesMut {
+        let mut packet = create_ipv4_header();
+        packet.extend_from_slice(b"GET / HTTP/1.1\r\n\r\n");
+        let total_len = (packet.len() as u16).to_be_bytes();
+        packet[2..4].copy_from_slice(&total_len);
+        
+        let ip = naive_checksum(&[&packet[..10], &packet[12..20]]);
+        packet[10..12].copy_from_slice(&ip.to_be_bytes());
+        let tcp = tcp_pseudo_checksum(&packet);
+        packet[36..38].copy_from_slice(&tcp.to_be_bytes());
+        packet
+    }
+    
+    #[test]
+    fn test_checksums_follow_rewrites() {
+        let params = HeaderParams {
+            normalize_ttl: true,
+            ttl_value: 128,
+            normalize_window: true,
+            randomize_ip_id: true,
+        };
+        let transform = HeaderNormalizationTransform::new(&params);
+        
+        let key = test_flow_key();
+        let mut state = FlowState::new(key);
+        for packet_count in [0, 1, 7, 1000] {
+            state.packet_count = packet_count;
+            let mut ctx = FlowContext::new(&key, &mut state, None);
+            let mut data = checksummed_packet();
+            assert_eq!(naive_checksum(&[&data[..20]]), 0);
+            
+            transform.apply(&mut ctx, &mut data).unwrap();
+            
+            assert_eq!(data[8], 128);
+            assert_eq!(&data[34..36], &[0xFF, 0xFF]);
+            assert_eq!(naive_checksum(&[&data[..20]]), 0, "packet {}", packet_count);
+            assert_eq!(&data[10..12], &naive_checksum(&[&data[..10], &data[12..20]]).to_be_bytes());
+            assert_eq!(&data[36..38], &tcp_pseudo_checksum(&data).to_be_bytes(), "packet {}", packet_count);
+        }
+    }
+    
+    #[test]
+    fn test_offloaded_tcp_checksum_stays_zero() {
+        let params = HeaderParams {
+            normalize_ttl: true,
+            ttl_value: 128,
+            normalize_window: true,
+            randomize_ip_id: false,
+        };
+        let transform = HeaderNormalizationTransform::new(&params);
+        
+        let key = test_flow_key();
+        let mut state = FlowState::new(key);
+        let mut ctx = FlowContext::new(&key, &mut state, None);
+        let mut data = checksummed_packet();
+        data[36] = 0;
+        data[37] = 0;
+        
+        transform.apply(&mut ctx, &mut data).unwrap();
+        
+        assert_eq!(&data[34..36], &[0xFF, 0xFF]);
+        assert_eq!(&data[36..38], &[0, 0]);
+        assert_eq!(naive_checksum(&[&data[..20]]), 0);
+    }
+    
+    #[test]
+    fn test_inbound_is_untouched() {
+        let params = HeaderParams {
+            normalize_ttl: true,
+            ttl_value: 128,
+            normalize_window: true,
+            randomize_ip_id: true,
+        };
+        let transform = HeaderNormalizationTransform::new(&params);
+        
+        let key = test_flow_key();
+        let mut state = FlowState::new(key);
+        let mut ctx = FlowContext::new(&key, &mut state, None).with_direction(FlowDirection::Inbound);
+        let original = create_ipv4_header();
+        let mut data = original.clone();
+        
+        let result = transform.apply(&mut ctx, &mut data).unwrap();
+        assert_eq!(result, TransformResult::Continue);
+        assert_eq!(data, original);
+    }
 }
@@ -1,11 +1,12 @@
 use std::time::Duration;
 
 use bytes::BytesMut;
-use tracing::trace;
+use rand::Rng;
+use tracing::{debug, trace};
 
 use crate::config::{JitterParams, TransformParams};
 use crate::error::Result;
-use crate::flow::FlowContext;
+use crate::flow::{FlowContext, FlowKey, FlowState};
 use super::{Transform, TransformResult};
 
 pub struct JitterTransform {
@@ -19,19 +20,12 @@ impl JitterTransform {
         }
     }
     
-    fn calculate_jitter(&self, seed: u64) -> Duration {
+    fn calculate_jitter(&self, rng: &mut impl Rng) -> Duration {
         if self.params.max_ms == 0 {
             return Duration::ZERO;
         }
 
-        let range = self.params.max_ms - self.params.min_ms;
-        if range == 0 {
-            return Duration::from_millis(self.params.min_ms);
-        }
-
-        
-        let jitter_ms = self.params.min_ms + (seed % (range + 1));
-        Duration::from_millis(jitter_ms)
+        Duration::from_millis(rng.gen_range(self.params.min_ms..=self.params.max_ms))
     }
 }
 
@@ -40,18 +34,27 @@ impl Transform for JitterTransform {
         "jitter"
     }
 
-    fn apply(&self, ctx: &mut FlowContext<'_>, data: &mut BytesMut) -> Result<TransformResult> {
+    fn apply(&self, ctx: &mut FlowContext<'_>, _data: &mut BytesMut) -> Result<TransformResult> {
         
         if self.params.max_ms == 0 {
             return Ok(TransformResult::Continue);
         }
 
-        
-        let seed = ctx.state.packet_count
-            .wrapping_mul(31337)
-            .wrapping_add(data.len() as u64);
-        
-        let jitter = self.calculate_jitter(seed);
+        let mut rng = ctx.state.rng(self.params.seed);
+        let jitter = if ctx.output_packets.is_empty() {
+            self.calculate_jitter(&mut rng)
+        } else {
+            // Space fragments out rather than holding the whole batch back.
+            let mut total = Duration::ZERO;
+            for packet in &mut ctx.output_packets {
+                let delay = self.calculate_jitter(&mut rng);
+                if !delay.is_zero() {
+                    packet.delay_before = Some(delay);
+                    total += delay;
+                }
+            }
+            total
+        };
 
         if jitter.is_zero() {
             return Ok(TransformResult::Continue);
@@ -67,10 +70,19 @@ impl Transform for JitterTransform {
         ctx.state.transform_state.jitter.last_jitter_ms = jitter.as_millis() as u64;
         ctx.state.transform_state.jitter.total_jitter_ms += jitter.as_millis() as u64;
 
-        ctx.request_delay(jitter);
+        if ctx.output_packets.is_empty() {
+            ctx.request_delay(jitter);
+        }
         Ok(TransformResult::Delay)
     }
 
+    fn on_flow_evicted(&self, key: &FlowKey, state: &FlowState) {
+        let jitter = &state.transform_state.jitter;
+        if jitter.total_jitter_ms > 0 {
+            debug!(flow = ?key, total_jitter_ms = jitter.total_jitter_ms, "flow evicted");
+        }
+    }
+
     fn is_enabled(&self, params: &TransformParams) -> bool {
         params.jitter.max_ms > 0
     }
@@ -81,7 +93,6 @@ mod tests {
     use super::*;
     use std::net::{IpAddr, Ipv4Addr};
     use crate::config::Protocol;
-    use crate::flow::{FlowKey, FlowState};
 
     fn test_flow_key() -> FlowKey {
         FlowKey::new(
@@ -98,6 +109,7 @@ mod tests {
         let params = JitterParams {
             min_ms: 0,
             max_ms: 0,
+            seed: None,
         };
         let transform = JitterTransform::new(&params);
         
@@ -116,6 +128,7 @@ mod tests {
         let params = JitterParams {
             min_ms: 10,
             max_ms: 50,
+            seed: None,
         };
         let transform = JitterTransform::new(&params);
         
@@ -137,6 +150,7 @@ mod tests {
         let params = JitterParams {
             min_ms: 25,
             max_ms: 25,
+            seed: None,
         };
         let transform = JitterTransform::new(&params);
         
@@ -150,18 +164,42 @@ mod tests {
         assert_eq!(ctx.delay.unwrap(), Duration::from_millis(25));
     }
 
+    #[test]
+    fn test_jitter_spaces_fragments() {
+        let transform = JitterTransform::new(&JitterParams {
+            min_ms: 25,
+            max_ms: 25,
+            seed: None,
+        });
+        
+        let key = test_flow_key();
+        let mut state = FlowState::new(key);
+        let mut ctx = FlowContext::new(&key, &mut state, None);
+        ctx.emit_continuation(BytesMut::from(&b"second"[..]));
+        ctx.emit_continuation(BytesMut::from(&b"third"[..]));
+        let mut data = BytesMut::from(&b"first"[..]);
+        
+        assert_eq!(transform.apply(&mut ctx, &mut data).unwrap(), TransformResult::Delay);
+        assert!(ctx.delay.is_none());
+        assert!(ctx.output_packets.iter().all(|p| p.delay_before == Some(Duration::from_millis(25))));
+        assert_eq!(ctx.state.transform_state.jitter.total_jitter_ms, 50);
+    }
+    
     #[test]
     fn test_jitter_bounds() {
         let params = JitterParams {
             min_ms: 0,
             max_ms: 100,
+            seed: None,
         };
         let transform = JitterTransform::new(&params);
         
-        
-        for seed in 0..100 {
-            let jitter = transform.calculate_jitter(seed);
-            assert!(jitter <= Duration::from_millis(100));
-        }
+        let key = test_flow_key();
+        let mut state = FlowState::new(key);
+        let mut rng = state.rng(None);
+        let draws: Vec<Duration> = (0..10_000).map(|_| transform.calculate_jitter(&mut rng)).collect();
+        assert!(draws.iter().all(|&jitter| jitter <= Duration::from_millis(100)));
+        assert!(draws.contains(&Duration::ZERO));
+        assert!(draws.contains(&Duration::from_millis(100)));
     }
 }
@@ -4,13 +4,15 @@ pub mod padding;
 pub mod header;
 pub mod resegment;
 pub mod decoy;
+pub mod reorder;
+pub mod fake;
 
 use bytes::BytesMut;
 use serde::{Deserialize, Serialize};
 
 use crate::config::TransformParams;
 use crate::error::Result;
-use crate::flow::FlowContext;
+use crate::flow::{FlowContext, FlowKey, FlowState};
 
 pub use fragment::FragmentTransform;
 pub use jitter::JitterTransform;
@@ -18,6 +20,8 @@ pub use padding::PaddingTransform;
 pub use header::HeaderNormalizationTransform;
 pub use resegment::ResegmentTransform;
 pub use decoy::DecoyTransform;
+pub use reorder::ReorderTransform;
+pub use fake::FakeTransform;
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TransformResult {
@@ -37,6 +41,12 @@ pub trait Transform: Send + Sync {
         true
     }
     
+    /// Called for each flow that times out of the flow cache.
+    fn on_flow_evicted(&self, key: &FlowKey, state: &FlowState) {
+        let _ = (key, state);
+    }
+    
+    /// Called on the outgoing transform set before a config reload replaces it.
     fn reset(&self) {}
 }
 
@@ -50,6 +60,8 @@ pub fn create_all_transforms(params: &TransformParams) -> Vec<BoxedTransform> {
         Box::new(JitterTransform::new(&params.jitter)),
         Box::new(HeaderNormalizationTransform::new(&params.header)),
         Box::new(DecoyTransform::new(&params.decoy)),
+        Box::new(ReorderTransform::new(&params.reorder)),
+        Box::new(FakeTransform::new(&params.fake)),
     ]
 }
 
@@ -62,7 +74,7 @@ mod tests {
         let params = TransformParams::default();
         let transforms = create_all_transforms(&params);
         
-        assert_eq!(transforms.len(), 6);
+        assert_eq!(transforms.len(), 8);
         
         let names: Vec<&str> = transforms.iter().map(|t| t.name()).collect();
         assert!(names.contains(&"fragment"));
@@ -71,5 +83,7 @@ mod tests {
         assert!(names.contains(&"jitter"));
         assert!(names.contains(&"header_normalization"));
         assert!(names.contains(&"decoy"));
+        assert!(names.contains(&"reorder"));
+        assert!(names.contains(&"fake"));
     }
 }
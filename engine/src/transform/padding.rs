@@ -1,47 +1,45 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use bytes::BytesMut;
-use tracing::trace;
+use rand::Rng;
+use tracing::{trace, warn};
 
-use crate::config::{PaddingParams, TransformParams};
+use crate::config::{PaddingMode, PaddingParams, TransformParams};
 use crate::error::Result;
 use crate::flow::FlowContext;
+use crate::tls::{is_client_hello, last_record_type, TLS_CHANGE_CIPHER_SPEC, TLS_HANDSHAKE};
 use super::{Transform, TransformResult};
 
+/// What a TLS 1.3 peer sends for middlebox compatibility and must ignore.
+const CHANGE_CIPHER_SPEC_RECORD: [u8; 6] = [TLS_CHANGE_CIPHER_SPEC, 0x03, 0x03, 0x00, 0x01, 0x01];
+
 pub struct PaddingTransform {
     params: PaddingParams,
+    warned_stream: AtomicBool,
 }
 
 impl PaddingTransform {
     pub fn new(params: &PaddingParams) -> Self {
         Self {
             params: params.clone(),
+            warned_stream: AtomicBool::new(false),
         }
     }
 
-    fn calculate_padding_size(&self, seed: u64) -> usize {
+    fn calculate_padding_size(&self, rng: &mut impl Rng) -> usize {
         if self.params.max_bytes == 0 {
             return 0;
         }
 
-        let range = self.params.max_bytes - self.params.min_bytes;
-        if range == 0 {
-            return self.params.min_bytes;
-        }
-
-        
-        self.params.min_bytes + ((seed as usize) % (range + 1))
+        rng.gen_range(self.params.min_bytes..=self.params.max_bytes)
     }
 
-    fn generate_padding(&self, size: usize, seed: u64) -> Vec<u8> {
+    fn generate_padding(&self, size: usize, rng: &mut impl Rng) -> Vec<u8> {
         match self.params.fill_byte {
             Some(byte) => vec![byte; size],
             None => {
-                
-                let mut padding = Vec::with_capacity(size);
-                let mut value = seed;
-                for _ in 0..size {
-                    value = value.wrapping_mul(1103515245).wrapping_add(12345);
-                    padding.push((value >> 16) as u8);
-                }
+                let mut padding = vec![0u8; size];
+                rng.fill(&mut padding[..]);
                 padding
             }
         }
@@ -57,19 +55,38 @@ impl Transform for PaddingTransform {
         if self.params.max_bytes == 0 {
             return Ok(TransformResult::Continue);
         }
-
         
-        let seed = ctx.state.packet_count
-            .wrapping_mul(48271)
-            .wrapping_add(data.len() as u64);
+        match self.params.mode {
+            PaddingMode::Append if ctx.is_stream => {
+                if !self.warned_stream.swap(true, Ordering::Relaxed) {
+                    warn!(
+                        flow = ?ctx.key,
+                        "append padding would corrupt stream data and is skipped; use padding.mode = \"tls_record\""
+                    );
+                }
+                return Ok(TransformResult::Continue);
+            }
+            PaddingMode::Append => {}
+            PaddingMode::TlsRecord => {
+                // Only after the first flight: in TLS 1.2 the client's encrypted
+                // Finished is also a handshake record, and a CCS after it is fatal.
+                if is_client_hello(data) && last_record_type(data) == Some(TLS_HANDSHAKE) {
+                    trace!(flow = ?ctx.key, "appending change cipher spec record");
+                    data.extend_from_slice(&CHANGE_CIPHER_SPEC_RECORD);
+                    ctx.padding_added += CHANGE_CIPHER_SPEC_RECORD.len();
+                }
+                return Ok(TransformResult::Continue);
+            }
+        }
 
-        let padding_size = self.calculate_padding_size(seed);
+        let mut rng = ctx.state.rng(self.params.seed);
+        let padding_size = self.calculate_padding_size(&mut rng);
         
         if padding_size == 0 {
             return Ok(TransformResult::Continue);
         }
 
-        let padding = self.generate_padding(padding_size, seed);
+        let padding = self.generate_padding(padding_size, &mut rng);
         
         trace!(
             flow = ?ctx.key,
@@ -79,6 +96,7 @@ impl Transform for PaddingTransform {
         );
 
         data.extend_from_slice(&padding);
+        ctx.padding_added += padding_size;
 
         Ok(TransformResult::Continue)
     }
@@ -111,6 +129,8 @@ mod tests {
             min_bytes: 0,
             max_bytes: 0,
             fill_byte: None,
+            seed: None,
+            mode: PaddingMode::Append,
         };
         let transform = PaddingTransform::new(&params);
         
@@ -131,6 +151,8 @@ mod tests {
             min_bytes: 10,
             max_bytes: 10,
             fill_byte: Some(0xAB),
+            seed: None,
+            mode: PaddingMode::Append,
         };
         let transform = PaddingTransform::new(&params);
         
@@ -143,6 +165,7 @@ mod tests {
         let result = transform.apply(&mut ctx, &mut data).unwrap();
         assert_eq!(result, TransformResult::Continue);
         assert_eq!(data.len(), original.len() + 10);
+        assert_eq!(ctx.padding_added, 10);
         
         
         for i in original.len()..data.len() {
@@ -156,6 +179,8 @@ mod tests {
             min_bytes: 5,
             max_bytes: 5,
             fill_byte: None,
+            seed: None,
+            mode: PaddingMode::Append,
         };
         let transform = PaddingTransform::new(&params);
         
@@ -176,6 +201,8 @@ mod tests {
             min_bytes: 20,
             max_bytes: 20,
             fill_byte: Some(0x00),
+            seed: None,
+            mode: PaddingMode::Append,
         };
         let transform = PaddingTransform::new(&params);
         
@@ -197,14 +224,67 @@ mod tests {
             min_bytes: 5,
             max_bytes: 15,
             fill_byte: None,
+            seed: None,
+            mode: PaddingMode::Append,
         };
         let transform = PaddingTransform::new(&params);
         
+        let key = test_flow_key();
+        let mut state = FlowState::new(key);
+        let mut rng = state.rng(None);
+        let sizes: Vec<usize> = (0..10_000).map(|_| transform.calculate_padding_size(&mut rng)).collect();
+        assert!(sizes.iter().all(|size| (5..=15).contains(size)));
+        assert!(sizes.contains(&5));
+        assert!(sizes.contains(&15));
+    }
+    
+    fn stream_params(mode: PaddingMode) -> PaddingParams {
+        PaddingParams {
+            min_bytes: 8,
+            max_bytes: 8,
+            fill_byte: None,
+            seed: None,
+            mode,
+        }
+    }
+    
+    fn pad(transform: &PaddingTransform, data: &[u8], is_stream: bool) -> BytesMut {
+        let key = test_flow_key();
+        let mut state = FlowState::new(key);
+        let mut ctx = FlowContext::new(&key, &mut state, None).with_stream(is_stream);
+        let original_len = data.len();
+        let mut data = BytesMut::from(data);
+        assert_eq!(transform.apply(&mut ctx, &mut data).unwrap(), TransformResult::Continue);
+        assert_eq!(ctx.padding_added, data.len() - original_len);
+        data
+    }
+    
+    #[test]
+    fn test_append_refused_for_streams() {
+        let transform = PaddingTransform::new(&stream_params(PaddingMode::Append));
+        let hello = crate::tls::tests::client_hello_with_alpn(&[]);
+        
+        assert_eq!(pad(&transform, &hello, true), hello);
+        assert_eq!(pad(&transform, &hello, false).len(), hello.len() + 8);
+    }
+    
+    #[test]
+    fn test_tls_record_mode() {
+        let transform = PaddingTransform::new(&stream_params(PaddingMode::TlsRecord));
+        let hello = crate::tls::tests::client_hello_with_alpn(&[]);
         
-        for seed in 0..100u64 {
-            let size = transform.calculate_padding_size(seed);
-            assert!(size >= 5);
-            assert!(size <= 15);
+        for is_stream in [true, false] {
+            let padded = pad(&transform, &hello, is_stream);
+            assert_eq!(&padded[..hello.len()], &hello[..]);
+            assert_eq!(&padded[hello.len()..], &CHANGE_CIPHER_SPEC_RECORD);
+            assert_eq!(last_record_type(&padded), Some(TLS_CHANGE_CIPHER_SPEC));
         }
+        
+        let partial = &hello[..hello.len() - 10];
+        assert_eq!(pad(&transform, partial, true), partial);
+        
+        let app_data = [0x17, 0x03, 0x03, 0x00, 0x03, 1, 2, 3];
+        assert_eq!(pad(&transform, &app_data, true), &app_data[..]);
+        assert_eq!(pad(&transform, b"GET / HTTP/1.1\r\n\r\n", false), &b"GET / HTTP/1.1\r\n\r\n"[..]);
     }
 }
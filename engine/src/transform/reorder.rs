@@ -0,0 +1,181 @@
+use bytes::BytesMut;
+use rand::Rng;
+use tracing::trace;
+
+use crate::config::{ReorderParams, ReorderStrategy, TransformParams};
+use crate::error::Result;
+use crate::flow::FlowContext;
+use super::{Transform, TransformResult};
+
+pub struct ReorderTransform {
+    params: ReorderParams,
+}
+
+impl ReorderTransform {
+    pub fn new(params: &ReorderParams) -> Self {
+        Self {
+            params: params.clone(),
+        }
+    }
+    
+    fn shuffle<T>(packets: &mut [T], seed: u64) {
+        let mut state = seed | 1;
+        for i in (1..packets.len()).rev() {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let j = (state % (i as u64 + 1)) as usize;
+            packets.swap(i, j);
+        }
+    }
+}
+
+impl Transform for ReorderTransform {
+    fn name(&self) -> &'static str {
+        "reorder"
+    }
+    
+    fn apply(&self, ctx: &mut FlowContext<'_>, data: &mut BytesMut) -> Result<TransformResult> {
+        if ctx.output_packets.is_empty() {
+            return Ok(TransformResult::Continue);
+        }
+        
+        // Packets change places; the delays between them stay where they are.
+        match self.params.strategy {
+            ReorderStrategy::Swap => {
+                let first = &mut ctx.output_packets[0];
+                std::mem::swap(data, &mut first.data);
+                std::mem::swap(&mut ctx.data_kind, &mut first.kind);
+                std::mem::swap(&mut ctx.data_ttl, &mut first.ttl_override);
+            }
+            ReorderStrategy::Shuffle => {
+                let seed = self.params.seed.unwrap_or_else(|| ctx.state.rng(None).gen());
+                
+                let mut packets = Vec::with_capacity(ctx.output_packets.len() + 1);
+                packets.push((std::mem::take(data), ctx.data_kind, ctx.data_ttl));
+                packets.extend(
+                    ctx.output_packets
+                        .iter_mut()
+                        .map(|p| (std::mem::take(&mut p.data), p.kind, p.ttl_override)),
+                );
+                Self::shuffle(&mut packets, seed);
+                
+                let mut packets = packets.into_iter();
+                if let Some(first) = packets.next() {
+                    (*data, ctx.data_kind, ctx.data_ttl) = first;
+                }
+                for (slot, (data, kind, ttl)) in ctx.output_packets.iter_mut().zip(packets) {
+                    slot.data = data;
+                    slot.kind = kind;
+                    slot.ttl_override = ttl;
+                }
+            }
+        }
+        
+        trace!(
+            flow = ?ctx.key,
+            strategy = ?self.params.strategy,
+            packets = ctx.output_packets.len() + 1,
+            "reordered packets"
+        );
+        
+        Ok(TransformResult::Continue)
+    }
+    
+    fn is_enabled(&self, _params: &TransformParams) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+    use crate::config::{FragmentParams, Protocol};
+    use crate::flow::{FlowKey, FlowState};
+    use crate::transform::FragmentTransform;
+    
+    fn test_flow_key() -> FlowKey {
+        FlowKey::new(
+            IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)),
+            IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)),
+            12345,
+            443,
+            Protocol::Tcp,
+        )
+    }
+    
+    fn fragment_then_reorder(params: ReorderParams, input: &[u8]) -> Vec<BytesMut> {
+        let fragment = FragmentTransform::new(&FragmentParams {
+            min_size: 1,
+            max_size: 4,
+            split_at_offset: None,
+            randomize: false,
+            split_at_sni: false,
+            seed: None,
+        });
+        let reorder = ReorderTransform::new(&params);
+        
+        let key = test_flow_key();
+        let mut state = FlowState::new(key);
+        let mut ctx = FlowContext::new(&key, &mut state, None);
+        let mut data = BytesMut::from(input);
+        
+        assert_eq!(fragment.apply(&mut ctx, &mut data).unwrap(), TransformResult::Fragmented);
+        assert_eq!(reorder.apply(&mut ctx, &mut data).unwrap(), TransformResult::Continue);
+        
+        let mut packets = vec![data];
+        packets.extend(ctx.output_packets.drain(..).map(|p| p.data));
+        packets
+    }
+    
+    fn restore_order(mut packets: Vec<BytesMut>) -> Vec<u8> {
+        packets.sort_by_key(|p| p[0]);
+        packets.concat()
+    }
+    
+    #[test]
+    fn test_reorder_without_fragments() {
+        let transform = ReorderTransform::new(&ReorderParams::default());
+        
+        let key = test_flow_key();
+        let mut state = FlowState::new(key);
+        let mut ctx = FlowContext::new(&key, &mut state, None);
+        let mut data = BytesMut::from(&b"test data"[..]);
+        
+        let result = transform.apply(&mut ctx, &mut data).unwrap();
+        assert_eq!(result, TransformResult::Continue);
+        assert_eq!(&data[..], b"test data");
+        assert!(ctx.output_packets.is_empty());
+    }
+    
+    #[test]
+    fn test_reorder_swap() {
+        let input: Vec<u8> = (0..16).collect();
+        let packets = fragment_then_reorder(ReorderParams::default(), &input);
+        
+        assert_eq!(packets.len(), 4);
+        assert_eq!(&packets[0][..], &[4, 5, 6, 7]);
+        assert_eq!(&packets[1][..], &[0, 1, 2, 3]);
+        assert_eq!(&packets[2][..], &[8, 9, 10, 11]);
+        assert_eq!(restore_order(packets), input);
+    }
+    
+    #[test]
+    fn test_reorder_shuffle() {
+        let input: Vec<u8> = (0..64).collect();
+        let params = ReorderParams {
+            strategy: ReorderStrategy::Shuffle,
+            seed: Some(42),
+        };
+        
+        let packets = fragment_then_reorder(params.clone(), &input);
+        assert_eq!(packets.len(), 16);
+        let starts: Vec<u8> = packets.iter().map(|p| p[0]).collect();
+        assert_ne!(starts, (0..64).step_by(4).collect::<Vec<u8>>());
+        
+        let again = fragment_then_reorder(params, &input);
+        assert_eq!(again, packets);
+        assert_eq!(restore_order(packets), input);
+    }
+}
@@ -1,7 +1,7 @@
 use bytes::BytesMut;
 use tracing::trace;
 
-use crate::config::{ResegmentParams, TransformParams};
+use crate::config::{ResegmentParams, TailBehavior, TransformParams};
 use crate::error::Result;
 use crate::flow::FlowContext;
 use super::{Transform, TransformResult};
@@ -17,25 +17,22 @@ impl ResegmentTransform {
         }
     }
 
-    pub fn segment_data(&self, data: &[u8]) -> Vec<BytesMut> {
+    /// Splits `data` in place; every segment shares the original allocation.
+    pub fn segment_data(&self, mut data: BytesMut) -> Vec<BytesMut> {
+        let size = self.params.segment_size.max(1);
+        let limit = match self.params.tail_behavior {
+            TailBehavior::OneBigTail => self.params.max_segments + 1,
+            TailBehavior::Strict => self.params.max_segments.max(1),
+            TailBehavior::ContinueSegmenting => usize::MAX,
+        };
         let mut segments = Vec::new();
-        let mut offset = 0;
-        let mut count = 0;
-
-        while offset < data.len() && count < self.params.max_segments {
-            let remaining = data.len() - offset;
-            let size = self.params.segment_size.min(remaining);
-            
-            let segment = BytesMut::from(&data[offset..offset + size]);
-            segments.push(segment);
-            
-            offset += size;
-            count += 1;
+
+        while data.len() > size && segments.len() + 1 < limit {
+            segments.push(data.split_to(size));
         }
 
-        
-        if offset < data.len() {
-            segments.push(BytesMut::from(&data[offset..]));
+        if !data.is_empty() {
+            segments.push(data);
         }
 
         segments
@@ -53,30 +50,29 @@ impl Transform for ResegmentTransform {
             return Ok(TransformResult::Continue);
         }
 
-        let segments = self.segment_data(data);
+        let original_size = data.len();
+        let segments = self.segment_data(data.split());
+        let count = segments.len();
+        let mut segments = segments.into_iter();
+        *data = segments.next().unwrap_or_default();
         
-        if segments.len() <= 1 {
+        if count < 2 {
             return Ok(TransformResult::Continue);
         }
 
         trace!(
             flow = ?ctx.key,
-            original_size = data.len(),
-            segments = segments.len(),
+            original_size,
+            segments = count,
             "resegmented packet"
         );
 
         
-        ctx.state.transform_state.resegment.segments_generated += segments.len() as u32;
+        ctx.state.transform_state.resegment.segments_generated += count as u32;
 
         
-        for (i, segment) in segments.into_iter().enumerate() {
-            if i == 0 {
-                data.clear();
-                data.extend_from_slice(&segment);
-            } else {
-                ctx.emit(segment);
-            }
+        for segment in segments {
+            ctx.emit_continuation(segment);
         }
 
         Ok(TransformResult::Fragmented)
@@ -109,11 +105,12 @@ mod tests {
         let params = ResegmentParams {
             segment_size: 10,
             max_segments: 100,
+            ..Default::default()
         };
         let transform = ResegmentTransform::new(&params);
 
         let data = b"This is a test message for resegmentation";
-        let segments = transform.segment_data(data);
+        let segments = transform.segment_data(BytesMut::from(&data[..]));
 
         
         for (i, segment) in segments.iter().enumerate() {
@@ -127,23 +124,59 @@ mod tests {
         assert_eq!(reassembled.as_slice(), data);
     }
 
+    fn segment_lengths(tail_behavior: TailBehavior, max_segments: usize, data: &[u8]) -> Vec<usize> {
+        let transform = ResegmentTransform::new(&ResegmentParams {
+            segment_size: 5,
+            max_segments,
+            tail_behavior,
+        });
+        let segments = transform.segment_data(BytesMut::from(data));
+        let reassembled: Vec<u8> = segments.iter().flat_map(|s| s.iter().copied()).collect();
+        assert_eq!(reassembled.as_slice(), data);
+        segments.iter().map(|s| s.len()).collect()
+    }
+
+    #[test]
+    fn test_resegment_strict_max_segments() {
+        let data = b"12345678901234567890";
+        assert_eq!(segment_lengths(TailBehavior::Strict, 3, data), vec![5, 5, 10]);
+        assert_eq!(segment_lengths(TailBehavior::Strict, 4, data), vec![5, 5, 5, 5]);
+        assert_eq!(segment_lengths(TailBehavior::Strict, 10, data), vec![5, 5, 5, 5]);
+        assert_eq!(segment_lengths(TailBehavior::Strict, 1, data), vec![20]);
+    }
+
+    #[test]
+    fn test_resegment_one_big_tail() {
+        let data = b"12345678901234567890";
+        assert_eq!(segment_lengths(TailBehavior::OneBigTail, 3, data), vec![5, 5, 5, 5]);
+        assert_eq!(segment_lengths(TailBehavior::OneBigTail, 2, data), vec![5, 5, 10]);
+        assert_eq!(segment_lengths(TailBehavior::OneBigTail, 0, data), vec![20]);
+    }
+
+    #[test]
+    fn test_resegment_continue_segmenting() {
+        let data = b"1234567890123456789012";
+        assert_eq!(segment_lengths(TailBehavior::ContinueSegmenting, 2, data), vec![5, 5, 5, 5, 2]);
+        assert_eq!(segment_lengths(TailBehavior::ContinueSegmenting, 0, data), vec![5, 5, 5, 5, 2]);
+    }
+
     #[test]
-    fn test_resegment_max_segments() {
+    fn test_resegment_counts_segments() {
         let params = ResegmentParams {
             segment_size: 5,
             max_segments: 3,
+            ..Default::default()
         };
         let transform = ResegmentTransform::new(&params);
-
-        let data = b"12345678901234567890"; 
-        let segments = transform.segment_data(data);
-
-        
-        assert_eq!(segments.len(), 4);
         
+        let key = test_flow_key();
+        let mut state = FlowState::new(key);
+        let mut ctx = FlowContext::new(&key, &mut state, None);
+        let mut data = BytesMut::from(&b"12345678901234567890"[..]);
         
-        let reassembled: Vec<u8> = segments.iter().flat_map(|s| s.iter().copied()).collect();
-        assert_eq!(reassembled.as_slice(), data);
+        assert_eq!(transform.apply(&mut ctx, &mut data).unwrap(), TransformResult::Fragmented);
+        assert_eq!(ctx.output_packets.len(), 2);
+        assert_eq!(ctx.state.transform_state.resegment.segments_generated, 3);
     }
 
     #[test]
@@ -151,6 +184,7 @@ mod tests {
         let params = ResegmentParams {
             segment_size: 20,
             max_segments: 10,
+            ..Default::default()
         };
         let transform = ResegmentTransform::new(&params);
         
@@ -169,6 +203,7 @@ mod tests {
         let params = ResegmentParams {
             segment_size: 8,
             max_segments: 100,
+            ..Default::default()
         };
         let transform = ResegmentTransform::new(&params);
         
@@ -188,8 +223,31 @@ mod tests {
         
         let mut all_data = data.to_vec();
         for packet in &ctx.output_packets {
-            all_data.extend_from_slice(packet);
+            all_data.extend_from_slice(&packet.data);
         }
         assert_eq!(all_data.as_slice(), original);
     }
+    
+    #[test]
+    fn test_segments_alias_original_buffer() {
+        let params = ResegmentParams {
+            segment_size: 100,
+            max_segments: 5,
+            ..Default::default()
+        };
+        let transform = ResegmentTransform::new(&params);
+        
+        let data = BytesMut::from(&[0xAB; 1000][..]);
+        let base = data.as_ptr();
+        let segments = transform.segment_data(data);
+        assert_eq!(segments.len(), 5);
+        assert_eq!(segments[4].len(), 600);
+        
+        let mut offset = 0;
+        for segment in &segments {
+            assert_eq!(segment.as_ptr(), base.wrapping_add(offset));
+            offset += segment.len();
+        }
+        assert_eq!(offset, 1000);
+    }
 }
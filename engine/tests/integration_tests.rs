@@ -30,16 +30,21 @@ fn test_config_with_fragmentation() -> Config {
                 protocols: Some(vec![Protocol::Tcp]),
                 ..Default::default()
             },
+            action: RuleAction::Transform,
             transforms: vec![TransformType::Fragment],
             overrides: HashMap::new(),
         }],
         limits: Limits::default(),
+        dns: DnsConfig::default(),
+        backend: BackendSection::default(),
         transforms: TransformParams {
             fragment: FragmentParams {
                 min_size: 1,
                 max_size: 10,
                 split_at_offset: None,
                 randomize: false,
+                split_at_sni: false,
+                seed: None,
             },
             ..Default::default()
         },
@@ -66,21 +71,28 @@ fn test_config_multi_transform() -> Config {
                 protocols: Some(vec![Protocol::Tcp]),
                 ..Default::default()
             },
+            action: RuleAction::Transform,
             transforms: vec![TransformType::Fragment, TransformType::Padding],
             overrides: HashMap::new(),
         }],
         limits: Limits::default(),
+        dns: DnsConfig::default(),
+        backend: BackendSection::default(),
         transforms: TransformParams {
             fragment: FragmentParams {
                 min_size: 5,
                 max_size: 20,
                 split_at_offset: None,
                 randomize: false,
+                split_at_sni: false,
+                seed: None,
             },
             padding: PaddingParams {
                 min_bytes: 10,
                 max_bytes: 10,
                 fill_byte: Some(0xAA),
+                seed: None,
+                mode: PaddingMode::Append,
             },
             ..Default::default()
         },
@@ -120,7 +132,7 @@ fn test_pipeline_processes_matching_traffic() {
     let output = pipeline.process(key, data).unwrap();
 
     assert!(output.matched_rule.is_some());
-    assert_eq!(output.matched_rule.as_ref().unwrap(), "test-fragment");
+    assert_eq!(output.matched_rule.as_deref(), Some("test-fragment"));
 
     let all_packets = output.all_packets();
     assert!(all_packets.len() > 1, "Expected multiple fragments");
@@ -198,6 +210,30 @@ fn test_pipeline_multi_transform() {
     );
 }
 
+#[test]
+fn test_padding_overhead_accounting() {
+    let mut config = test_config_multi_transform();
+    config.global.enable_fragmentation = false;
+    config.rules[0].transforms = vec![TransformType::Padding];
+    let stats = Arc::new(Stats::new());
+    let pipeline = Pipeline::new(config, stats.clone()).unwrap();
+    
+    let key = https_flow_key();
+    for _ in 0..5 {
+        let data = BytesMut::from(&[0x42u8; 40][..]);
+        let output = pipeline.process(key, data).unwrap();
+        assert_eq!(output.all_packets().len(), 1);
+    }
+    
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.bytes_in, 200);
+    assert_eq!(snapshot.bytes_out, 250);
+    assert_eq!(snapshot.padding_bytes_added, 50);
+    assert_eq!(snapshot.decoy_bytes_sent, 0);
+    assert_eq!(snapshot.fragment_overhead_writes, 0);
+    assert!((snapshot.overhead_ratio() - 0.25).abs() < f64::EPSILON);
+}
+
 #[test]
 fn test_pipeline_config_reload() {
     let config = test_config_with_fragmentation();
@@ -286,6 +322,7 @@ fn test_multiple_rules_priority() {
                 enabled: true,
                 priority: 0,
                 match_criteria: MatchCriteria::default(),
+                action: RuleAction::Transform,
                 transforms: vec![TransformType::Padding],
                 overrides: HashMap::new(),
             },
@@ -298,11 +335,14 @@ fn test_multiple_rules_priority() {
                     protocols: Some(vec![Protocol::Tcp]),
                     ..Default::default()
                 },
+                action: RuleAction::Transform,
                 transforms: vec![TransformType::Fragment],
                 overrides: HashMap::new(),
             },
         ],
         limits: Limits::default(),
+        dns: DnsConfig::default(),
+        backend: BackendSection::default(),
         transforms: TransformParams::default(),
     };
 
@@ -312,12 +352,12 @@ fn test_multiple_rules_priority() {
     let https_key = https_flow_key();
     let data = BytesMut::from(&b"test"[..]);
     let output = pipeline.process(https_key, data).unwrap();
-    assert_eq!(output.matched_rule.unwrap(), "https-specific");
+    assert_eq!(output.matched_rule.as_deref(), Some("https-specific"));
 
     let http_key = http_flow_key();
     let data = BytesMut::from(&b"test"[..]);
     let output = pipeline.process(http_key, data).unwrap();
-    assert_eq!(output.matched_rule.unwrap(), "catch-all");
+    assert_eq!(output.matched_rule.as_deref(), Some("catch-all"));
 }
 
 #[test]
@@ -344,10 +384,13 @@ fn test_ip_cidr_matching() {
                 ]),
                 ..Default::default()
             },
+            action: RuleAction::Transform,
             transforms: vec![TransformType::Padding],
             overrides: HashMap::new(),
         }],
         limits: Limits::default(),
+        dns: DnsConfig::default(),
+        backend: BackendSection::default(),
         transforms: TransformParams::default(),
     };
 
@@ -376,3 +419,118 @@ fn test_ip_cidr_matching() {
     let output = pipeline.process(public_key, data).unwrap();
     assert!(output.matched_rule.is_none());
 }
+
+fn quic_flow_key(src_port: u16) -> FlowKey {
+    FlowKey::new(
+        IpAddr::V4(Ipv4Addr::new(192, 168, 1, 100)),
+        IpAddr::V4(Ipv4Addr::new(142, 250, 185, 78)),
+        src_port,
+        443,
+        Protocol::Udp,
+    )
+}
+
+#[test]
+fn test_drop_rule_blocks_quic() {
+    let mut config = test_config_with_fragmentation();
+    config.rules.push(Rule {
+        name: "block-quic".to_string(),
+        enabled: true,
+        priority: 200,
+        match_criteria: MatchCriteria {
+            dst_ports: Some(vec![443]),
+            protocols: Some(vec![Protocol::Udp]),
+            ..Default::default()
+        },
+        action: RuleAction::Drop,
+        transforms: Vec::new(),
+        overrides: HashMap::new(),
+    });
+
+    let stats = Arc::new(Stats::new());
+    let pipeline = Pipeline::new(config, stats.clone()).unwrap();
+
+    let output = pipeline.process(quic_flow_key(50000), BytesMut::from(&[0xC3u8; 1200][..])).unwrap();
+    assert!(output.dropped);
+    assert_eq!(output.matched_rule.as_deref(), Some("block-quic"));
+    assert!(output.all_packets().is_empty());
+
+    let output = pipeline.process(https_flow_key(), BytesMut::from(&b"\x16\x03\x01 hello"[..])).unwrap();
+    assert!(!output.dropped);
+    assert_eq!(output.matched_rule.as_deref(), Some("test-fragment"));
+    assert!(!output.all_packets().is_empty());
+
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.packets_dropped, 1);
+    assert_eq!(snapshot.packets_matched, 2);
+}
+
+#[test]
+fn test_rate_limit_rule() {
+    let mut config = test_config_with_fragmentation();
+    config.rules.push(Rule {
+        name: "limit-quic".to_string(),
+        enabled: true,
+        priority: 200,
+        match_criteria: MatchCriteria {
+            protocols: Some(vec![Protocol::Udp]),
+            ..Default::default()
+        },
+        action: RuleAction::RateLimit { pps: 5 },
+        transforms: Vec::new(),
+        overrides: HashMap::new(),
+    });
+
+    let stats = Arc::new(Stats::new());
+    let pipeline = Pipeline::new(config, stats.clone()).unwrap();
+
+    let passed = (0..20)
+        .filter(|_| !pipeline.process(quic_flow_key(50001), BytesMut::from(&b"quic"[..])).unwrap().dropped)
+        .count();
+    assert!((5..=6).contains(&passed), "{}", passed);
+
+    let output = pipeline.process(quic_flow_key(50002), BytesMut::from(&b"quic"[..])).unwrap();
+    assert!(!output.dropped);
+    assert_eq!(stats.snapshot().packets_dropped as usize, 20 - passed);
+}
+
+#[test]
+fn test_drop_rule_with_transforms_is_invalid() {
+    let rule = Rule {
+        name: "bad-drop".to_string(),
+        enabled: true,
+        priority: 0,
+        match_criteria: MatchCriteria::default(),
+        action: RuleAction::Drop,
+        transforms: vec![TransformType::Padding],
+        overrides: HashMap::new(),
+    };
+    assert!(rule.validate().is_err());
+
+    let rule = Rule {
+        action: RuleAction::RateLimit { pps: 0 },
+        transforms: Vec::new(),
+        ..rule
+    };
+    assert!(rule.validate().is_err());
+
+    let config = Config::from_toml(r#"
+        [[rules]]
+        name = "block-quic"
+        action = "drop"
+
+        [rules.match_criteria]
+        dst_ports = [443]
+        protocols = ["udp"]
+
+        [[rules]]
+        name = "limit"
+        action = { rate_limit = { pps = 50 } }
+        transforms = ["padding"]
+
+        [rules.match_criteria]
+        protocols = ["udp"]
+    "#).unwrap();
+    assert_eq!(config.rules[0].action, RuleAction::Drop);
+    assert_eq!(config.rules[1].action, RuleAction::RateLimit { pps: 50 });
+}
@@ -0,0 +1,83 @@
+//! Runs in its own binary because it installs a counting global allocator.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
+
+use engine::config::*;
+use engine::flow::{FlowDirection, FlowKey};
+use engine::pipeline::Pipeline;
+use engine::stats::Stats;
+use engine::Config;
+
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+    
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations<R>(f: impl FnOnce() -> R) -> (R, usize) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let result = f();
+    (result, ALLOCATIONS.with(Cell::get) - before)
+}
+
+fn rule(name: &str, priority: i32, domains: Option<Vec<String>>) -> Rule {
+    Rule {
+        name: name.to_string(),
+        enabled: true,
+        priority,
+        match_criteria: MatchCriteria {
+            dst_ports: Some(vec![443]),
+            protocols: Some(vec![Protocol::Tcp]),
+            domains,
+            ..Default::default()
+        },
+        action: RuleAction::Transform,
+        transforms: vec![TransformType::Fragment],
+        overrides: HashMap::new(),
+    }
+}
+
+#[test]
+fn test_rule_matching_does_not_allocate() {
+    let mut config = Config::default();
+    config.rules.push(rule("test-https", 10, None));
+    config.rules.push(rule("blocked-sites", 20, Some(vec!["discord.com".to_string(), "*.youtube.com".to_string()])));
+    let pipeline = Pipeline::new(config, Arc::new(Stats::new())).unwrap();
+    let key = FlowKey::new(
+        IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)),
+        IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)),
+        12345,
+        443,
+        Protocol::Tcp,
+    );
+    
+    let (matched, count) = allocations(|| {
+        pipeline.matching_rule(&key, FlowDirection::Outbound, Some("www.youtube.com"))
+    });
+    assert_eq!(count, 0);
+    assert_eq!(matched.as_deref(), Some("blocked-sites"));
+    
+    let (fallback, count) = allocations(|| {
+        pipeline.matching_rule(&key, FlowDirection::Outbound, Some("example.com"))
+    });
+    assert_eq!(count, 0);
+    assert_eq!(fallback.as_deref(), Some("test-https"));
+}